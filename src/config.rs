@@ -1,5 +1,6 @@
 use crate::cli::{Cli, StorageBackend as CliStorageBackend};
 use crate::error::ConfigError;
+use crate::server::notify;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -19,6 +20,14 @@ pub struct ServerConfig {
     pub host: String,
     #[serde(default = "default_port")]
     pub port: u16,
+    /// Classes of keyspace events to publish, as Redis'
+    /// `notify-keyspace-events` (empty for none).
+    #[serde(default)]
+    pub notify_keyspace_events: String,
+    /// Password clients must AUTH with before running commands, as Redis'
+    /// `requirepass` (none if unset).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requirepass: Option<String>,
 }
 
 fn default_host() -> String {
@@ -29,9 +38,10 @@ fn default_port() -> u16 {
     6379
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(tag = "backend", rename_all = "lowercase")]
 pub enum StorageConfig {
+    #[default]
     Memory,
     Lmdb {
         path: PathBuf,
@@ -44,18 +54,14 @@ pub enum StorageConfig {
     },
 }
 
-impl Default for StorageConfig {
-    fn default() -> Self {
-        Self::Memory
-    }
-}
-
 impl Default for Config {
     fn default() -> Self {
         Self {
             server: ServerConfig {
                 host: default_host(),
                 port: default_port(),
+                notify_keyspace_events: String::new(),
+                requirepass: None,
             },
             storage: StorageConfig::Memory,
         }
@@ -77,6 +83,11 @@ impl Config {
             }
         }
 
+        // An empty password leaves authentication off, as in Redis
+        if let Ok(password) = std::env::var("REDIS_PASSWORD") {
+            config.server.requirepass = Some(password).filter(|p| !p.is_empty());
+        }
+
         match std::env::var("STORAGE_BACKEND").as_deref() {
             Ok("memory") => config.storage = StorageConfig::Memory,
             Ok("lmdb") => {
@@ -122,7 +133,22 @@ impl Config {
                 .port
                 .or_else(|| file_config.as_ref().map(|c| c.server.port))
                 .unwrap_or(env_config.server.port),
+            notify_keyspace_events: file_config
+                .as_ref()
+                .map(|c| c.server.notify_keyspace_events.clone())
+                .unwrap_or_default(),
+            requirepass: file_config
+                .as_ref()
+                .and_then(|c| c.server.requirepass.clone())
+                .or_else(|| env_config.server.requirepass.clone())
+                .filter(|p| !p.is_empty()),
         };
+        if notify::parse_flags(&server.notify_keyspace_events).is_none() {
+            return Err(ConfigError::Validation(format!(
+                "invalid notify_keyspace_events '{}'",
+                server.notify_keyspace_events
+            )));
+        }
 
         let storage = Self::resolve_storage(cli, file_config.as_ref(), &env_config)?;
 
@@ -208,3 +234,52 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    /// Resolve the password given the `REDIS_PASSWORD` environment variable
+    /// and a config file with `requirepass`, either of them possibly unset.
+    fn requirepass(env: Option<&str>, file: Option<Option<&str>>) -> Option<String> {
+        match env {
+            Some(password) => std::env::set_var("REDIS_PASSWORD", password),
+            None => std::env::remove_var("REDIS_PASSWORD"),
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let mut args = vec!["coral-redis".to_string()];
+        if let Some(password) = file {
+            let path = dir.path().join("config.json");
+            let mut config = Config::default();
+            config.server.requirepass = password.map(str::to_string);
+            config.save_to_file(&path).unwrap();
+            args.extend(["--config".to_string(), path.display().to_string()]);
+        }
+        let cli = Cli::parse_from(args);
+        Config::from_sources(&cli).unwrap().server.requirepass
+    }
+
+    // The environment is shared by the whole process, so every case of
+    // REDIS_PASSWORD runs in this one test
+    #[test]
+    fn test_requirepass_sources() {
+        assert_eq!(requirepass(None, None), None);
+        assert_eq!(requirepass(Some("env"), None).as_deref(), Some("env"));
+        assert_eq!(requirepass(Some("env"), Some(None)).as_deref(), Some("env"));
+        assert_eq!(
+            requirepass(None, Some(Some("file"))).as_deref(),
+            Some("file")
+        );
+        // The file wins over the environment
+        assert_eq!(
+            requirepass(Some("env"), Some(Some("file"))).as_deref(),
+            Some("file")
+        );
+        // An empty password turns authentication off, even over a set one
+        assert_eq!(requirepass(Some(""), None), None);
+        assert_eq!(requirepass(None, Some(Some(""))), None);
+        assert_eq!(requirepass(Some("env"), Some(Some(""))), None);
+        std::env::remove_var("REDIS_PASSWORD");
+    }
+}
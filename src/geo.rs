@@ -0,0 +1,398 @@
+//! Redis-compatible geohash encoding and distance computations.
+//!
+//! Geo indexes are sorted sets whose scores are 52-bit geohashes: 26 bits of
+//! latitude and 26 of longitude, interleaved with the longitude in the odd
+//! bits. Latitudes are limited to the Web Mercator range, ±85.05112878
+//! degrees, and distances use the haversine formula on Redis's Earth radius,
+//! so scores and distances match Redis's exactly.
+//!
+//! Searches scan the geohash cell holding the search center and its eight
+//! neighbours, at a precision picked so that these cells cover the search
+//! area, then filter the members they hold by exact distance.
+
+use std::ops::Range;
+
+pub const LONGITUDE_MIN: f64 = -180.0;
+pub const LONGITUDE_MAX: f64 = 180.0;
+pub const LATITUDE_MIN: f64 = -85.05112878;
+pub const LATITUDE_MAX: f64 = 85.05112878;
+/// Bits per coordinate of a score.
+const STEP_MAX: u32 = 26;
+/// Earth's quadratic mean radius for WGS-84, as used by Redis.
+const EARTH_RADIUS: f64 = 6372797.560856;
+/// Half the Web Mercator circumference, in meters.
+const MERCATOR_MAX: f64 = 20037726.37;
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Latitude and longitude ranges of the Web Mercator projection.
+const MERCATOR_RANGES: (Range<f64>, Range<f64>) =
+    (LONGITUDE_MIN..LONGITUDE_MAX, LATITUDE_MIN..LATITUDE_MAX);
+/// Latitude and longitude ranges of standard geohash strings.
+const STANDARD_RANGES: (Range<f64>, Range<f64>) = (-180.0..180.0, -90.0..90.0);
+
+/// A geohash cell: `step` bits per coordinate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    bits: u64,
+    step: u32,
+}
+
+/// Bounds of a geohash cell, in degrees.
+struct Area {
+    longitude: Range<f64>,
+    latitude: Range<f64>,
+}
+
+/// Spread the bits of `value` to the even bits of the result.
+fn spread(value: u32) -> u64 {
+    (0..32).fold(0, |bits, i| bits | ((value as u64 >> i) & 1) << (2 * i))
+}
+
+/// Gather the even bits of `bits`.
+fn gather(bits: u64) -> u32 {
+    (0..32).fold(0, |value, i| value | (((bits >> (2 * i)) & 1) as u32) << i)
+}
+
+fn encode_cell(
+    (longitude_range, latitude_range): &(Range<f64>, Range<f64>),
+    longitude: f64,
+    latitude: f64,
+    step: u32,
+) -> Option<Cell> {
+    if !valid_coordinates(longitude, latitude)
+        || !(longitude_range.start..=longitude_range.end).contains(&longitude)
+        || !(latitude_range.start..=latitude_range.end).contains(&latitude)
+    {
+        return None;
+    }
+    let scale = (1u64 << step) as f64;
+    let latitude_offset =
+        (latitude - latitude_range.start) / (latitude_range.end - latitude_range.start) * scale;
+    let longitude_offset =
+        (longitude - longitude_range.start) / (longitude_range.end - longitude_range.start) * scale;
+    Some(Cell {
+        bits: spread(latitude_offset as u32) | spread(longitude_offset as u32) << 1,
+        step,
+    })
+}
+
+fn decode_cell(cell: Cell) -> Area {
+    let (longitude_range, latitude_range) = MERCATOR_RANGES;
+    let scale = (1u64 << cell.step) as f64;
+    let latitude = gather(cell.bits) as f64;
+    let longitude = gather(cell.bits >> 1) as f64;
+    let latitude_span = latitude_range.end - latitude_range.start;
+    let longitude_span = longitude_range.end - longitude_range.start;
+    Area {
+        latitude: latitude_range.start + (latitude / scale) * latitude_span
+            ..latitude_range.start + ((latitude + 1.0) / scale) * latitude_span,
+        longitude: longitude_range.start + (longitude / scale) * longitude_span
+            ..longitude_range.start + ((longitude + 1.0) / scale) * longitude_span,
+    }
+}
+
+/// Whether a longitude/latitude pair can be indexed.
+pub fn valid_coordinates(longitude: f64, latitude: f64) -> bool {
+    (LONGITUDE_MIN..=LONGITUDE_MAX).contains(&longitude)
+        && (LATITUDE_MIN..=LATITUDE_MAX).contains(&latitude)
+}
+
+/// Score of a position, or `None` if it is out of range.
+pub fn encode(longitude: f64, latitude: f64) -> Option<u64> {
+    encode_cell(&MERCATOR_RANGES, longitude, latitude, STEP_MAX).map(|cell| cell.bits)
+}
+
+/// Position at the center of a score's cell, as `(longitude, latitude)`.
+pub fn decode(score: u64) -> (f64, f64) {
+    let area = decode_cell(Cell {
+        bits: score,
+        step: STEP_MAX,
+    });
+    let longitude = (area.longitude.start + area.longitude.end) / 2.0;
+    let latitude = (area.latitude.start + area.latitude.end) / 2.0;
+    (
+        longitude.clamp(LONGITUDE_MIN, LONGITUDE_MAX),
+        latitude.clamp(LATITUDE_MIN, LATITUDE_MAX),
+    )
+}
+
+/// Standard 11 character geohash of a score. Scores use the Mercator
+/// latitude range, so the position is re-encoded over ±90 degrees; the last
+/// character carries no information.
+pub fn geohash(score: u64) -> String {
+    let (longitude, latitude) = decode(score);
+    let bits =
+        encode_cell(&STANDARD_RANGES, longitude, latitude, STEP_MAX).map_or(0, |cell| cell.bits);
+    (0..11)
+        .map(|i| {
+            let index = if i == 10 {
+                0
+            } else {
+                (bits >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            GEOHASH_ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+fn latitude_distance(latitude1: f64, latitude2: f64) -> f64 {
+    EARTH_RADIUS * (latitude2.to_radians() - latitude1.to_radians()).abs()
+}
+
+/// Haversine distance in meters between two `(longitude, latitude)` points.
+pub fn distance((longitude1, latitude1): (f64, f64), (longitude2, latitude2): (f64, f64)) -> f64 {
+    let v = ((longitude2.to_radians() - longitude1.to_radians()) / 2.0).sin();
+    // Same longitude: the distance is along the meridian
+    if v == 0.0 {
+        return latitude_distance(latitude1, latitude2);
+    }
+    let (latitude1, latitude2) = (latitude1.to_radians(), latitude2.to_radians());
+    let u = ((latitude2 - latitude1) / 2.0).sin();
+    let a = u * u + latitude1.cos() * latitude2.cos() * v * v;
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Area searched around a center, in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+impl Shape {
+    /// Distance from `center` to `point` if the point lies within the shape.
+    pub fn distance_within(&self, center: (f64, f64), point: (f64, f64)) -> Option<f64> {
+        match *self {
+            Shape::Radius(radius) => Some(distance(center, point)).filter(|&d| d <= radius),
+            Shape::Box { width, height } => {
+                if latitude_distance(point.1, center.1) > height / 2.0 {
+                    return None;
+                }
+                // The width is measured along the point's parallel
+                if distance((point.0, point.1), (center.0, point.1)) > width / 2.0 {
+                    return None;
+                }
+                Some(distance(center, point))
+            }
+        }
+    }
+
+    /// Radius of the circle enclosing the shape.
+    fn radius(&self) -> f64 {
+        match *self {
+            Shape::Radius(radius) => radius,
+            Shape::Box { width, height } => (width / 2.0).hypot(height / 2.0),
+        }
+    }
+
+    /// Bounding box around `center`, as `(longitudes, latitudes)`.
+    fn bounds(&self, (longitude, latitude): (f64, f64)) -> (Range<f64>, Range<f64>) {
+        let (half_width, half_height) = match *self {
+            Shape::Radius(radius) => (radius, radius),
+            Shape::Box { width, height } => (width / 2.0, height / 2.0),
+        };
+        let latitude_delta = (half_height / EARTH_RADIUS).to_degrees();
+        let longitude_delta =
+            |latitude: f64| (half_width / EARTH_RADIUS / latitude.to_radians().cos()).to_degrees();
+        // A degree of longitude is shortest on the parallel furthest from
+        // the equator, which so bounds the box
+        let longitude_delta = if latitude < 0.0 {
+            longitude_delta(latitude - latitude_delta)
+        } else {
+            longitude_delta(latitude + latitude_delta)
+        };
+        (
+            longitude - longitude_delta..longitude + longitude_delta,
+            latitude - latitude_delta..latitude + latitude_delta,
+        )
+    }
+}
+
+/// Cell precision whose cells are about as large as a search radius.
+fn estimate_step(radius: f64, latitude: f64) -> u32 {
+    if radius == 0.0 {
+        return STEP_MAX;
+    }
+    let mut radius = radius;
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    // Make sure the radius is covered in most cases
+    step -= 2;
+    // Cells narrow towards the poles
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP_MAX as i32) as u32
+}
+
+/// Move a cell by one along the longitude (`x`) or latitude axis, wrapping
+/// around.
+fn move_cell(cell: Cell, x: bool, forward: bool) -> Cell {
+    const ODD: u64 = 0xaaaa_aaaa_aaaa_aaaa;
+    const EVEN: u64 = 0x5555_5555_5555_5555;
+    let (moving, fixed) = if x { (ODD, EVEN) } else { (EVEN, ODD) };
+    let shift = 64 - cell.step * 2;
+    // Bits that are not part of the moving coordinate carry through it
+    let filler = fixed >> shift;
+    let mut value = cell.bits & moving;
+    if forward {
+        value = value.wrapping_add(filler + 1);
+    } else {
+        value = (value | filler).wrapping_sub(filler + 1);
+    }
+    Cell {
+        bits: (value & (moving >> shift)) | (cell.bits & fixed),
+        step: cell.step,
+    }
+}
+
+/// Score ranges holding every position within `shape` of `center`; some
+/// positions in them may lie outside it.
+pub fn search_ranges(center: (f64, f64), shape: &Shape) -> Vec<Range<u64>> {
+    let (longitude_bounds, latitude_bounds) = shape.bounds(center);
+    let mut step = estimate_step(shape.radius(), center.1);
+    let neighbours = |cell: Cell| {
+        let east = move_cell(cell, true, true);
+        let west = move_cell(cell, true, false);
+        [
+            move_cell(cell, false, true),
+            move_cell(cell, false, false),
+            east,
+            west,
+            move_cell(east, false, true),
+            move_cell(west, false, true),
+            move_cell(east, false, false),
+            move_cell(west, false, false),
+        ]
+    };
+    let Some(mut cell) = encode_cell(&MERCATOR_RANGES, center.0, center.1, step) else {
+        return Vec::new();
+    };
+
+    // The estimate can fall short at the edges of the covered area
+    let [north, south, east, west, ..] = neighbours(cell).map(decode_cell);
+    if step > 1
+        && (north.latitude.end < latitude_bounds.end
+            || south.latitude.start > latitude_bounds.start
+            || east.longitude.end < longitude_bounds.end
+            || west.longitude.start > longitude_bounds.start)
+    {
+        step -= 1;
+        cell = encode_cell(&MERCATOR_RANGES, center.0, center.1, step).expect("valid center");
+    }
+
+    // [north, south, east, west, north east, north west, south east, south west]
+    let mut useful = [true; 8];
+    if step >= 2 {
+        let area = decode_cell(cell);
+        if area.latitude.start < latitude_bounds.start {
+            for i in [1, 6, 7] {
+                useful[i] = false;
+            }
+        }
+        if area.latitude.end > latitude_bounds.end {
+            for i in [0, 4, 5] {
+                useful[i] = false;
+            }
+        }
+        if area.longitude.start < longitude_bounds.start {
+            for i in [3, 5, 7] {
+                useful[i] = false;
+            }
+        }
+        if area.longitude.end > longitude_bounds.end {
+            for i in [2, 4, 6] {
+                useful[i] = false;
+            }
+        }
+    }
+
+    let mut cells = vec![cell];
+    for (neighbour, useful) in neighbours(cell).into_iter().zip(useful) {
+        // Large cells wrap around into each other
+        if useful && !cells.contains(&neighbour) {
+            cells.push(neighbour);
+        }
+    }
+    let shift = 2 * (STEP_MAX - step);
+    cells
+        .into_iter()
+        .map(|cell| cell.bits << shift..(cell.bits + 1) << shift)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALERMO: (f64, f64) = (13.361389, 38.115556);
+    const CATANIA: (f64, f64) = (15.087269, 37.502669);
+
+    #[test]
+    fn test_scores_match_redis() {
+        let palermo = encode(PALERMO.0, PALERMO.1).unwrap();
+        let catania = encode(CATANIA.0, CATANIA.1).unwrap();
+        assert_eq!((palermo, catania), (3479099956230698, 3479447370796909));
+
+        let (longitude, latitude) = decode(palermo);
+        assert_eq!(format!("{:.17}", longitude), "13.36138933897018433");
+        assert_eq!(format!("{:.17}", latitude), "38.11555639549629859");
+
+        assert_eq!(geohash(palermo), "sqc8b49rny0");
+        assert_eq!(geohash(catania), "sqdtr74hyu0");
+
+        assert_eq!(encode(0.0, 86.0), None);
+        assert_eq!(encode(-181.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_distance_matches_redis() {
+        let palermo = decode(encode(PALERMO.0, PALERMO.1).unwrap());
+        let catania = decode(encode(CATANIA.0, CATANIA.1).unwrap());
+        assert_eq!(format!("{:.4}", distance(palermo, catania)), "166274.1516");
+        assert_eq!(
+            format!("{:.4}", distance((15.0, 37.0), catania) / 1000.0),
+            "56.4413"
+        );
+        assert_eq!(distance(palermo, palermo), 0.0);
+    }
+
+    #[test]
+    fn test_search_ranges_cover_shape() {
+        // Every point within the shape falls in one of the ranges
+        let center = (15.0, 37.0);
+        for shape in [
+            Shape::Radius(200_000.0),
+            Shape::Radius(50.0),
+            Shape::Box {
+                width: 400_000.0,
+                height: 100_000.0,
+            },
+        ] {
+            let ranges = search_ranges(center, &shape);
+            assert!(!ranges.is_empty() && ranges.len() <= 9);
+            for i in 0..200 {
+                for j in 0..200 {
+                    let longitude = 10.0 + i as f64 * 0.05;
+                    let latitude = 33.0 + j as f64 * 0.04;
+                    let score = encode(longitude, latitude).unwrap();
+                    if shape.distance_within(center, decode(score)).is_some() {
+                        assert!(
+                            ranges.iter().any(|range| range.contains(&score)),
+                            "{:?} misses {},{}",
+                            shape,
+                            longitude,
+                            latitude
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Redis-compatible glob-style pattern matching.
+//!
+//! Supports `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` escapes, matching
+//! the semantics of Redis's `stringmatchlen` used by KEYS, SCAN MATCH and
+//! PSUBSCRIBE. Patterns and subjects are raw bytes.
+
+/// Check whether `string` matches the glob `pattern`.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    match_from(pattern, string, false)
+}
+
+/// Case-insensitive variant of [`glob_match`] (ASCII only).
+pub fn glob_match_nocase(pattern: &[u8], string: &[u8]) -> bool {
+    match_from(pattern, string, true)
+}
+
+fn match_from(mut pattern: &[u8], mut string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    while let Some(&p) = pattern.first() {
+        match p {
+            b'*' => {
+                // Collapse consecutive stars
+                while pattern.len() > 1 && pattern[1] == b'*' {
+                    pattern = &pattern[1..];
+                }
+                if pattern.len() == 1 {
+                    return true;
+                }
+                for start in 0..=string.len() {
+                    if match_from(&pattern[1..], &string[start..], nocase) {
+                        return true;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if string.is_empty() {
+                    return false;
+                }
+                string = &string[1..];
+            }
+            b'[' => {
+                let Some(&c) = string.first() else {
+                    return false;
+                };
+                pattern = &pattern[1..];
+                let negate = pattern.first() == Some(&b'^');
+                if negate {
+                    pattern = &pattern[1..];
+                }
+
+                let mut matched = false;
+                loop {
+                    match pattern {
+                        [] => break,
+                        [b']', ..] => break,
+                        [b'\\', escaped, ..] => {
+                            if eq(*escaped, c) {
+                                matched = true;
+                            }
+                            pattern = &pattern[2..];
+                        }
+                        [start, b'-', end, ..] if *end != b']' => {
+                            let (mut lo, mut hi) = (*start, *end);
+                            if lo > hi {
+                                std::mem::swap(&mut lo, &mut hi);
+                            }
+                            let in_range = if nocase {
+                                let c = c.to_ascii_lowercase();
+                                (lo.to_ascii_lowercase()..=hi.to_ascii_lowercase()).contains(&c)
+                            } else {
+                                (lo..=hi).contains(&c)
+                            };
+                            if in_range {
+                                matched = true;
+                            }
+                            pattern = &pattern[3..];
+                        }
+                        [single, ..] => {
+                            if eq(*single, c) {
+                                matched = true;
+                            }
+                            pattern = &pattern[1..];
+                        }
+                    }
+                }
+
+                if matched == negate {
+                    return false;
+                }
+                string = &string[1..];
+                if pattern.is_empty() {
+                    // Unterminated class: treat the end of pattern as the end of the class
+                    return string.is_empty();
+                }
+            }
+            b'\\' if pattern.len() >= 2 => {
+                pattern = &pattern[1..];
+                match string.first() {
+                    Some(&c) if eq(pattern[0], c) => string = &string[1..],
+                    _ => return false,
+                }
+            }
+            _ => match string.first() {
+                Some(&c) if eq(p, c) => string = &string[1..],
+                _ => return false,
+            },
+        }
+        pattern = &pattern[1..];
+    }
+
+    string.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal_and_wildcards() {
+        assert!(glob_match(b"hello", b"hello"));
+        assert!(!glob_match(b"hello", b"hell"));
+        assert!(glob_match(b"h*o", b"hello"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"h?llo", b"hallo"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(glob_match(b"user:*:name", b"user:42:name"));
+        assert!(!glob_match(b"user:*:name", b"user:42:age"));
+    }
+
+    #[test]
+    fn test_character_classes() {
+        assert!(glob_match(b"h[ae]llo", b"hello"));
+        assert!(!glob_match(b"h[ae]llo", b"hillo"));
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo"));
+        assert!(glob_match(b"h[b-a]llo", b"hallo"));
+        assert!(!glob_match(b"h[a-b]llo", b"hcllo"));
+    }
+
+    #[test]
+    fn test_escapes_and_nocase() {
+        assert!(glob_match(b"a\\*b", b"a*b"));
+        assert!(!glob_match(b"a\\*b", b"axb"));
+        assert!(glob_match_nocase(b"HeLLo*", b"hello world"));
+        assert!(glob_match(b"\xff*", b"\xff\x00"));
+    }
+}
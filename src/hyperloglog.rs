@@ -0,0 +1,399 @@
+//! Redis-compatible HyperLogLog encoding and cardinality estimation.
+//!
+//! A HyperLogLog is stored as a string, byte-for-byte in Redis's format, so
+//! values dumped from Redis can be read back and the other way round:
+//!
+//! - a 16 byte header: the `HYLL` magic, the encoding (0 dense, 1 sparse),
+//!   three unused bytes and the cached cardinality as a little-endian u64
+//!   whose most significant bit flags the cache as stale;
+//! - 16384 registers of 6 bits, either packed (dense, least significant bits
+//!   first) or run-length encoded (sparse) with the `ZERO`, `XZERO` and `VAL`
+//!   opcodes.
+//!
+//! Elements are hashed with MurmurHash64A, and the cardinality is estimated
+//! with Otmar Ertl's improved estimator, exactly as Redis does.
+
+/// Number of bits of the hash addressing a register.
+const P: u32 = 14;
+/// Number of bits of the hash counted for the run of zeros.
+const Q: u32 = 64 - P;
+pub const REGISTERS: usize = 1 << P;
+/// Largest register value.
+const REGISTER_MAX: u8 = 63;
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + REGISTERS * 6 / 8;
+const MAGIC: &[u8; 4] = b"HYLL";
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+/// Largest register value a sparse `VAL` opcode can hold.
+const SPARSE_VAL_MAX: u8 = 32;
+/// Longest run of a sparse `VAL` opcode.
+const SPARSE_VAL_MAX_LEN: usize = 4;
+/// Longest run of a sparse `ZERO` opcode.
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+/// Size above which a sparse HyperLogLog is promoted to the dense encoding,
+/// Redis's default `hll-sparse-max-bytes`.
+const SPARSE_MAX_BYTES: usize = 3000;
+/// Seed of the element hash.
+const HASH_SEED: u64 = 0xadc83b19;
+
+/// MurmurHash64A, as used by Redis's HyperLogLog.
+fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("8 byte chunk"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Register index of an element and the length of its run of zeros plus one.
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(element, HASH_SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // The sentinel bit bounds the count to Q + 1
+    let rest = (hash >> P) | (1 << Q);
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+/// A decoded HyperLogLog.
+#[derive(Clone)]
+pub struct HyperLogLog {
+    registers: Box<[u8; REGISTERS]>,
+    dense: bool,
+    /// Raw cached cardinality bytes of the header.
+    cache: [u8; 8],
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HyperLogLog {
+    /// An empty HyperLogLog, sparse like a fresh Redis one.
+    pub fn new() -> Self {
+        Self {
+            registers: Box::new([0; REGISTERS]),
+            dense: false,
+            cache: [0; 8],
+        }
+    }
+
+    /// Decode a stored value; `None` if it is not a valid HyperLogLog.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            return None;
+        }
+        let mut hll = Self::new();
+        hll.cache.copy_from_slice(&data[8..HEADER_LEN]);
+        let body = &data[HEADER_LEN..];
+
+        match data[4] {
+            DENSE => {
+                if data.len() != DENSE_LEN {
+                    return None;
+                }
+                hll.dense = true;
+                for (index, register) in hll.registers.iter_mut().enumerate() {
+                    *register = dense_get(body, index);
+                }
+            }
+            SPARSE => {
+                let mut index = 0;
+                let mut i = 0;
+                while i < body.len() {
+                    let opcode = body[i];
+                    let (value, run) = match opcode >> 6 {
+                        0b00 => (0, (opcode & 0x3f) as usize + 1),
+                        0b01 => {
+                            let low = *body.get(i + 1)?;
+                            i += 1;
+                            (0, (((opcode & 0x3f) as usize) << 8 | low as usize) + 1)
+                        }
+                        _ => (((opcode >> 2) & 0x1f) + 1, (opcode & 0x03) as usize + 1),
+                    };
+                    let registers = hll.registers.get_mut(index..index + run)?;
+                    registers.fill(value);
+                    index += run;
+                    i += 1;
+                }
+                if index != REGISTERS {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+        Some(hll)
+    }
+
+    /// Encode in Redis's format, sparse while the registers allow it.
+    pub fn encode(&self) -> Vec<u8> {
+        let sparse = if self.dense {
+            None
+        } else {
+            self.encode_sparse()
+        };
+
+        let mut data = Vec::with_capacity(sparse.as_ref().map_or(DENSE_LEN, |s| s.len()));
+        data.extend_from_slice(MAGIC);
+        data.push(if sparse.is_some() { SPARSE } else { DENSE });
+        data.extend_from_slice(&[0; 3]);
+        data.extend_from_slice(&self.cache);
+        match sparse {
+            Some(body) => data.extend_from_slice(&body),
+            None => {
+                let mut body = vec![0u8; DENSE_LEN - HEADER_LEN];
+                for (index, &register) in self.registers.iter().enumerate() {
+                    dense_set(&mut body, index, register);
+                }
+                data.extend_from_slice(&body);
+            }
+        }
+        data
+    }
+
+    /// Sparse body, or `None` if the registers need the dense encoding.
+    fn encode_sparse(&self) -> Option<Vec<u8>> {
+        let mut body = Vec::new();
+        let mut index = 0;
+        while index < REGISTERS {
+            let value = self.registers[index];
+            let run = self.registers[index..]
+                .iter()
+                .take_while(|&&register| register == value)
+                .count();
+            if value == 0 {
+                let mut left = run;
+                while left > 0 {
+                    if left <= SPARSE_ZERO_MAX_LEN {
+                        body.push((left - 1) as u8);
+                        left = 0;
+                    } else {
+                        let len = left.min(REGISTERS);
+                        body.push(0x40 | ((len - 1) >> 8) as u8);
+                        body.push(((len - 1) & 0xff) as u8);
+                        left -= len;
+                    }
+                }
+            } else {
+                if value > SPARSE_VAL_MAX {
+                    return None;
+                }
+                let mut left = run;
+                while left > 0 {
+                    let len = left.min(SPARSE_VAL_MAX_LEN);
+                    body.push(0x80 | (value - 1) << 2 | (len - 1) as u8);
+                    left -= len;
+                }
+            }
+            index += run;
+        }
+        (HEADER_LEN + body.len() <= SPARSE_MAX_BYTES).then_some(body)
+    }
+
+    /// Add an element, returning whether a register changed.
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let (index, count) = pattern_len(element);
+        if self.registers[index] >= count {
+            return false;
+        }
+        self.registers[index] = count;
+        self.invalidate_cache();
+        true
+    }
+
+    /// Merge another HyperLogLog into this one, register by register.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, &value) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(value);
+        }
+        // A union with a dense HyperLogLog is dense, as in Redis
+        self.dense |= other.dense;
+        self.invalidate_cache();
+    }
+
+    /// Cached cardinality, if it is still valid.
+    pub fn cached_count(&self) -> Option<u64> {
+        (self.cache[7] & 0x80 == 0).then(|| u64::from_le_bytes(self.cache))
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.cache[7] |= 0x80;
+    }
+
+    /// Estimated cardinality, computed from the registers.
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let mut histogram = [0u32; 64];
+        for &register in self.registers.iter() {
+            histogram[(register & REGISTER_MAX) as usize] += 1;
+        }
+
+        let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+        for j in (1..=Q as usize).rev() {
+            z += histogram[j] as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+        (ALPHA_INF * m * m / z).round() as u64
+    }
+
+    /// Copy of a stored value with `count` cached in its header, leaving the
+    /// registers' encoding untouched.
+    pub fn with_cached_count(data: &[u8], count: u64) -> Vec<u8> {
+        let mut data = data.to_vec();
+        data[8..HEADER_LEN].copy_from_slice(&count.to_le_bytes());
+        data
+    }
+}
+
+/// 0.5 / ln(2), the asymptotic bias correction.
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+fn dense_get(body: &[u8], index: usize) -> u8 {
+    let bit = index * 6;
+    let (byte, shift) = (bit / 8, bit % 8);
+    // A register may straddle two bytes; the last one never does
+    let low = body[byte] as u16;
+    let high = body.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((low | high << 8) >> shift) as u8) & REGISTER_MAX
+}
+
+fn dense_set(body: &mut [u8], index: usize, value: u8) {
+    let bit = index * 6;
+    let (byte, shift) = (bit / 8, bit % 8);
+    body[byte] &= !(REGISTER_MAX << shift);
+    body[byte] |= value << shift;
+    if shift > 2 {
+        body[byte + 1] &= !(REGISTER_MAX >> (8 - shift));
+        body[byte + 1] |= value >> (8 - shift);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        let mut hll = HyperLogLog::new();
+        let empty = hll.encode();
+        // A fresh HyperLogLog is a single XZERO run, as in Redis
+        assert_eq!(&empty[..5], b"HYLL\x01");
+        assert_eq!(&empty[HEADER_LEN..], &[0x7f, 0xff]);
+        assert_eq!(HyperLogLog::decode(&empty).unwrap().count(), 0);
+
+        for i in 0..200 {
+            hll.add(format!("element:{}", i).as_bytes());
+        }
+        let sparse = hll.encode();
+        assert_eq!(sparse[4], SPARSE);
+        let decoded = HyperLogLog::decode(&sparse).unwrap();
+        assert_eq!(decoded.registers, hll.registers);
+        assert_eq!(decoded.cached_count(), None);
+
+        for i in 0..20_000 {
+            hll.add(format!("element:{}", i).as_bytes());
+        }
+        let dense = hll.encode();
+        assert_eq!((dense[4], dense.len()), (DENSE, DENSE_LEN));
+        assert_eq!(
+            HyperLogLog::decode(&dense).unwrap().registers,
+            hll.registers
+        );
+
+        assert!(HyperLogLog::decode(b"HYLL").is_none());
+        assert!(HyperLogLog::decode(&dense[..DENSE_LEN - 1]).is_none());
+        assert!(HyperLogLog::decode(&sparse[..sparse.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_dense_registers_pack_six_bits() {
+        let mut body = vec![0u8; DENSE_LEN - HEADER_LEN];
+        for index in 0..REGISTERS {
+            dense_set(&mut body, index, (index % 64) as u8);
+        }
+        for index in 0..REGISTERS {
+            assert_eq!(dense_get(&body, index), (index % 64) as u8);
+        }
+        // Register 0 sits in the low bits of the first byte
+        assert_eq!(body[0] & 0x3f, 0);
+        assert_eq!(body[0] >> 6 | (body[1] & 0x0f) << 2, 1);
+    }
+
+    #[test]
+    fn test_count_accuracy() {
+        let mut hll = HyperLogLog::new();
+        for i in 1..=5 {
+            hll.add(format!("{}", i).as_bytes());
+        }
+        assert_eq!(hll.count(), 5);
+
+        let mut added = 5;
+        for cardinality in [1_000u64, 10_000, 100_000] {
+            while added < cardinality {
+                added += 1;
+                hll.add(format!("{}", added).as_bytes());
+            }
+            let error = (hll.count() as f64 - cardinality as f64).abs() / cardinality as f64;
+            // Standard error is 0.81%, allow five of them
+            assert!(error < 0.04, "{} estimated as {}", cardinality, hll.count());
+        }
+    }
+}
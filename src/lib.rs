@@ -6,9 +6,14 @@
 pub mod cli;
 pub mod config;
 pub mod error;
+pub mod geo;
+pub mod glob;
+pub mod hyperloglog;
+pub mod lua;
 pub mod metrics;
 pub mod protocol;
 pub mod server;
+pub mod slot;
 pub mod storage;
 pub mod telemetry;
 
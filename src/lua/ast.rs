@@ -0,0 +1,148 @@
+//! Compiled form of Lua source: a syntax tree whose variables are already
+//! resolved to local slots, upvalues or globals.
+
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// A compiled function body.
+pub struct FunctionProto {
+    /// Number of named parameters, which take the first slots.
+    pub params: usize,
+    pub is_vararg: bool,
+    /// Number of local slots; every local declaration has its own.
+    pub slots: usize,
+    /// Slots captured by inner functions, which live in shared cells.
+    pub captured: Vec<bool>,
+    /// Where each upvalue comes from when a closure is created.
+    pub upvalues: Vec<UpvalueSource>,
+    pub body: Block,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UpvalueSource {
+    /// A local slot of the enclosing function.
+    Local(usize),
+    /// An upvalue of the enclosing function.
+    Upvalue(usize),
+}
+
+pub type Block = Vec<Stat>;
+
+pub struct Stat {
+    pub line: u32,
+    pub kind: StatKind,
+}
+
+pub enum StatKind {
+    Expr(Expr),
+    Local {
+        slots: Vec<usize>,
+        values: Vec<Expr>,
+    },
+    Assign {
+        targets: Vec<Expr>,
+        values: Vec<Expr>,
+    },
+    LocalFunction {
+        slot: usize,
+        function: Arc<FunctionProto>,
+    },
+    Do(Block),
+    While {
+        condition: Expr,
+        body: Block,
+    },
+    Repeat {
+        body: Block,
+        condition: Expr,
+    },
+    If {
+        branches: Vec<(Expr, Block)>,
+        otherwise: Option<Block>,
+    },
+    NumericFor {
+        slot: usize,
+        start: Expr,
+        limit: Expr,
+        step: Option<Expr>,
+        body: Block,
+    },
+    GenericFor {
+        slots: Vec<usize>,
+        values: Vec<Expr>,
+        body: Block,
+    },
+    Return(Vec<Expr>),
+    Break,
+}
+
+pub enum Expr {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    String(Bytes),
+    Vararg,
+    Function(Arc<FunctionProto>),
+    Local(usize),
+    Upvalue(usize),
+    Global(Bytes),
+    Index(Box<Expr>, Box<Expr>),
+    Call {
+        function: Box<Expr>,
+        args: Vec<Expr>,
+        line: u32,
+    },
+    Method {
+        object: Box<Expr>,
+        name: Bytes,
+        args: Vec<Expr>,
+        line: u32,
+    },
+    /// A parenthesized expression, truncated to one value.
+    Paren(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Table(Vec<Field>),
+}
+
+/// A field of a table constructor.
+pub enum Field {
+    /// `value`, stored at the next integer key.
+    Positional(Expr),
+    /// `[key] = value` or `name = value`.
+    Named(Expr, Expr),
+}
+
+impl Expr {
+    /// Whether the expression can produce several values.
+    pub fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call { .. } | Expr::Method { .. } | Expr::Vararg)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
@@ -0,0 +1,891 @@
+//! Tree-walking interpreter for compiled chunks.
+
+use super::ast::{BinOp, Block, Expr, Field, FunctionProto, StatKind, UnOp, UpvalueSource};
+use super::value::{format_number, Closure, Function, TableRef, Value};
+use super::{stdlib, Chunk, LuaError};
+use bytes::Bytes;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Limit of nested calls, Lua's and native ones alike.
+const MAX_CALL_DEPTH: usize = 200;
+
+/// Limit of the native stack scripts may use. Each nested call takes a few
+/// frames of the tree walker, whose size depends much on the build.
+const MAX_STACK_USAGE: usize = 1 << 20;
+
+/// Limit of `__index` and `__newindex` chains.
+const MAX_META_CHAIN: usize = 100;
+
+/// Calls and loop iterations between two checks of [`Host::interrupted`].
+const INTERRUPT_INTERVAL: u32 = 1024;
+
+/// The embedding application: functions exposed to scripts by name, and a
+/// way to stop runaway scripts.
+pub trait Host {
+    /// Run the host function `name`, registered with [`Interp::host_function`].
+    fn call(&mut self, name: &'static str, args: Vec<Value>) -> Result<Vec<Value>, LuaError>;
+
+    /// Whether the script should stop, checked regularly while it runs.
+    fn interrupted(&mut self) -> bool {
+        false
+    }
+}
+
+pub struct Interp<'h> {
+    host: &'h mut dyn Host,
+    pub(super) globals: TableRef,
+    /// Library whose functions strings have as methods (`s:upper()`).
+    pub(super) string_lib: TableRef,
+    /// Tables scripts may not modify, once frozen.
+    readonly: Vec<TableRef>,
+    frozen: bool,
+    depth: usize,
+    /// Address on the native stack where the script started.
+    stack_base: usize,
+    line: u32,
+    steps: u32,
+    /// State of `math.random`, which starts over with every script.
+    pub(super) random: u64,
+    pub(super) started: Instant,
+}
+
+/// Where the native stack is at, to measure how much a script uses.
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+#[derive(Clone)]
+enum Slot {
+    Value(Value),
+    /// A local captured by a closure.
+    Cell(Rc<RefCell<Value>>),
+}
+
+struct Frame<'c> {
+    slots: Vec<Slot>,
+    varargs: Vec<Value>,
+    closure: &'c Closure,
+}
+
+impl Frame<'_> {
+    fn get(&self, slot: usize) -> Value {
+        match &self.slots[slot] {
+            Slot::Value(value) => value.clone(),
+            Slot::Cell(cell) => cell.borrow().clone(),
+        }
+    }
+
+    fn set(&mut self, slot: usize, value: Value) {
+        match &mut self.slots[slot] {
+            Slot::Value(v) => *v = value,
+            Slot::Cell(cell) => *cell.borrow_mut() = value,
+        }
+    }
+
+    /// Start a new instance of a local; closures made earlier keep the old
+    /// one.
+    fn declare(&mut self, slot: usize, value: Value) {
+        self.slots[slot] = if self.closure.proto.captured[slot] {
+            Slot::Cell(Rc::new(RefCell::new(value)))
+        } else {
+            Slot::Value(value)
+        };
+    }
+
+    fn cell(&mut self, slot: usize) -> Rc<RefCell<Value>> {
+        match &self.slots[slot] {
+            Slot::Cell(cell) => Rc::clone(cell),
+            Slot::Value(value) => {
+                let cell = Rc::new(RefCell::new(value.clone()));
+                self.slots[slot] = Slot::Cell(Rc::clone(&cell));
+                cell
+            }
+        }
+    }
+}
+
+/// A place an assignment stores to.
+enum Place {
+    Local(usize),
+    Upvalue(usize),
+    Global(Bytes),
+    Index(Value, Value),
+}
+
+impl<'h> Interp<'h> {
+    /// An interpreter with the standard libraries loaded.
+    pub fn new(host: &'h mut dyn Host) -> Self {
+        let mut interp = Interp {
+            host,
+            globals: TableRef::new(),
+            string_lib: TableRef::new(),
+            readonly: Vec::new(),
+            frozen: false,
+            depth: 0,
+            line: 0,
+            steps: 0,
+            random: stdlib::random_seed(0),
+            stack_base: 0,
+            started: Instant::now(),
+        };
+        stdlib::install(&mut interp);
+        interp
+    }
+
+    pub fn set_global(&self, name: &str, value: Value) {
+        self.globals.set_str(name, value);
+    }
+
+    /// A function that forwards its calls to [`Host::call`].
+    pub fn host_function(name: &'static str) -> Value {
+        Function::native(move |interp, args| interp.host.call(name, args))
+    }
+
+    /// Make the globals and the libraries read-only: from now on, assigning
+    /// a global or a library field fails, and so does reading a global that
+    /// does not exist.
+    pub fn freeze(&mut self) {
+        let mut readonly = vec![self.globals.clone()];
+        let mut key = Value::Nil;
+        while let Ok(Some((k, value))) = self.globals.next(&key) {
+            if let Value::Table(table) = &value {
+                readonly.push(table.clone());
+            }
+            key = k;
+        }
+        self.readonly = readonly;
+        self.frozen = true;
+    }
+
+    /// Run a chunk, returning the values of its `return` statement.
+    pub fn run(&mut self, chunk: &Chunk) -> Result<Vec<Value>, LuaError> {
+        self.stack_base = stack_position();
+        let main = Closure {
+            proto: Arc::clone(&chunk.0),
+            upvalues: Vec::new(),
+        };
+        self.call(&Value::Function(Function::Lua(Rc::new(main))), Vec::new())
+    }
+
+    /// Line of the statement running, or of the last error.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// A runtime error with the current position.
+    pub fn error(&self, message: impl std::fmt::Display) -> LuaError {
+        LuaError::Error(Value::string(format!(
+            "user_script:{}: {}",
+            self.line, message
+        )))
+    }
+
+    pub fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        self.check_interrupt()?;
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(self.error("stack overflow"));
+        }
+        self.check_stack()?;
+        self.depth += 1;
+        let line = self.line;
+        let result = match function {
+            Value::Function(Function::Lua(closure)) => self.call_closure(closure, args),
+            Value::Function(Function::Native(f)) => f(self, args),
+            other => match self.metamethod(other, "__call") {
+                Value::Nil => {
+                    Err(self.error(format!("attempt to call a {} value", other.type_name())))
+                }
+                handler => {
+                    let mut call_args = vec![other.clone()];
+                    call_args.extend(args);
+                    self.call(&handler, call_args)
+                }
+            },
+        };
+        self.depth -= 1;
+        // On errors, keep the line where it was raised
+        if result.is_ok() {
+            self.line = line;
+        }
+        result
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: &Closure,
+        mut args: Vec<Value>,
+    ) -> Result<Vec<Value>, LuaError> {
+        let proto = &closure.proto;
+        let varargs = if proto.is_vararg && args.len() > proto.params {
+            args.split_off(proto.params)
+        } else {
+            Vec::new()
+        };
+        args.resize(proto.params, Value::Nil);
+        let mut frame = Frame {
+            slots: vec![Slot::Value(Value::Nil); proto.slots],
+            varargs,
+            closure,
+        };
+        for (slot, value) in args.into_iter().enumerate() {
+            frame.declare(slot, value);
+        }
+        match self.exec_block(&mut frame, &proto.body)? {
+            Flow::Return(values) => Ok(values),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn check_interrupt(&mut self) -> Result<(), LuaError> {
+        self.steps = self.steps.wrapping_add(1);
+        if self.steps.is_multiple_of(INTERRUPT_INTERVAL) && self.host.interrupted() {
+            return Err(LuaError::Interrupted);
+        }
+        Ok(())
+    }
+
+    fn check_stack(&self) -> Result<(), LuaError> {
+        if stack_position().abs_diff(self.stack_base) > MAX_STACK_USAGE {
+            return Err(self.error("stack overflow"));
+        }
+        Ok(())
+    }
+
+    fn exec_block(&mut self, frame: &mut Frame, block: &Block) -> Result<Flow, LuaError> {
+        self.check_stack()?;
+        for stat in block {
+            self.line = stat.line;
+            match self.exec(frame, &stat.kind)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Run a loop body, telling whether the loop goes on.
+    fn exec_loop_body(
+        &mut self,
+        frame: &mut Frame,
+        body: &Block,
+    ) -> Result<Option<Flow>, LuaError> {
+        self.check_interrupt()?;
+        Ok(match self.exec_block(frame, body)? {
+            Flow::Normal => None,
+            Flow::Break => Some(Flow::Normal),
+            flow => Some(flow),
+        })
+    }
+
+    fn exec(&mut self, frame: &mut Frame, stat: &StatKind) -> Result<Flow, LuaError> {
+        match stat {
+            StatKind::Expr(expr) => {
+                self.eval_multi(frame, expr)?;
+            }
+            StatKind::Local { slots, values } => {
+                let values = self.eval_list_adjusted(frame, values, slots.len())?;
+                for (slot, value) in slots.iter().zip(values) {
+                    frame.declare(*slot, value);
+                }
+            }
+            StatKind::Assign { targets, values } => {
+                let mut places = Vec::with_capacity(targets.len());
+                for target in targets {
+                    places.push(match target {
+                        Expr::Local(slot) => Place::Local(*slot),
+                        Expr::Upvalue(i) => Place::Upvalue(*i),
+                        Expr::Global(name) => Place::Global(name.clone()),
+                        Expr::Index(object, key) => {
+                            let object_value = self.eval(frame, object)?;
+                            if !matches!(object_value, Value::Table(_)) {
+                                return Err(self.operand_error("index", object, &object_value));
+                            }
+                            Place::Index(object_value, self.eval(frame, key)?)
+                        }
+                        _ => unreachable!("the parser only accepts assignable targets"),
+                    });
+                }
+                let values = self.eval_list_adjusted(frame, values, places.len())?;
+                for (place, value) in places.into_iter().zip(values) {
+                    match place {
+                        Place::Local(slot) => frame.set(slot, value),
+                        Place::Upvalue(i) => *frame.closure.upvalues[i].borrow_mut() = value,
+                        Place::Global(name) => {
+                            if self.frozen {
+                                return Err(self.error("Attempt to modify a readonly table"));
+                            }
+                            self.globals
+                                .set(Value::String(name), value)
+                                .map_err(|e| self.error(e))?;
+                        }
+                        Place::Index(object, key) => self.set_index(&object, key, value)?,
+                    }
+                }
+            }
+            StatKind::LocalFunction { slot, function } => {
+                frame.declare(*slot, Value::Nil);
+                let closure = self.closure(frame, function);
+                frame.set(*slot, closure);
+            }
+            StatKind::Do(body) => return self.exec_block(frame, body),
+            StatKind::While { condition, body } => {
+                while self.eval(frame, condition)?.truthy() {
+                    if let Some(flow) = self.exec_loop_body(frame, body)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            StatKind::Repeat { body, condition } => loop {
+                if let Some(flow) = self.exec_loop_body(frame, body)? {
+                    return Ok(flow);
+                }
+                if self.eval(frame, condition)?.truthy() {
+                    break;
+                }
+            },
+            StatKind::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    if self.eval(frame, condition)?.truthy() {
+                        return self.exec_block(frame, body);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.exec_block(frame, body);
+                }
+            }
+            StatKind::NumericFor {
+                slot,
+                start,
+                limit,
+                step,
+                body,
+            } => {
+                let start = self.for_number(frame, start, "initial value")?;
+                let limit = self.for_number(frame, limit, "limit")?;
+                let step = match step {
+                    Some(step) => self.for_number(frame, step, "step")?,
+                    None => 1.0,
+                };
+                let mut i = start;
+                while (step > 0.0 && i <= limit) || (step <= 0.0 && i >= limit) {
+                    frame.declare(*slot, Value::Number(i));
+                    if let Some(flow) = self.exec_loop_body(frame, body)? {
+                        return Ok(flow);
+                    }
+                    i += step;
+                }
+            }
+            StatKind::GenericFor {
+                slots,
+                values,
+                body,
+            } => {
+                let mut values = self.eval_list_adjusted(frame, values, 3)?.into_iter();
+                let function = values.next().unwrap_or_default();
+                let state = values.next().unwrap_or_default();
+                let mut control = values.next().unwrap_or_default();
+                loop {
+                    let mut results = self.call(&function, vec![state.clone(), control.clone()])?;
+                    results.resize(slots.len(), Value::Nil);
+                    if matches!(results[0], Value::Nil) {
+                        break;
+                    }
+                    control = results[0].clone();
+                    for (slot, value) in slots.iter().zip(results) {
+                        frame.declare(*slot, value);
+                    }
+                    if let Some(flow) = self.exec_loop_body(frame, body)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            StatKind::Return(values) => return Ok(Flow::Return(self.eval_list(frame, values)?)),
+            StatKind::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn for_number(&mut self, frame: &mut Frame, expr: &Expr, what: &str) -> Result<f64, LuaError> {
+        self.eval(frame, expr)?
+            .as_number()
+            .ok_or_else(|| self.error(format!("'for' {} must be a number", what)))
+    }
+
+    fn eval(&mut self, frame: &mut Frame, expr: &Expr) -> Result<Value, LuaError> {
+        self.check_stack()?;
+        Ok(match expr {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Boolean(true),
+            Expr::False => Value::Boolean(false),
+            Expr::Number(n) => Value::Number(*n),
+            Expr::String(s) => Value::String(s.clone()),
+            Expr::Vararg => frame.varargs.first().cloned().unwrap_or_default(),
+            Expr::Function(proto) => self.closure(frame, proto),
+            Expr::Local(slot) => frame.get(*slot),
+            Expr::Upvalue(i) => frame.closure.upvalues[*i].borrow().clone(),
+            Expr::Global(name) => {
+                let value = self.globals.get(&Value::String(name.clone()));
+                if self.frozen && matches!(value, Value::Nil) {
+                    return Err(self.error(format!(
+                        "Script attempted to access nonexistent global variable '{}'",
+                        String::from_utf8_lossy(name)
+                    )));
+                }
+                value
+            }
+            Expr::Index(object, key) => {
+                let object_value = self.eval(frame, object)?;
+                let key = self.eval(frame, key)?;
+                if !matches!(object_value, Value::Table(_) | Value::String(_)) {
+                    return Err(self.operand_error("index", object, &object_value));
+                }
+                self.index(&object_value, &key)?
+            }
+            Expr::Call { .. } | Expr::Method { .. } => self
+                .eval_multi(frame, expr)?
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Expr::Paren(expr) => self.eval(frame, expr)?,
+            Expr::And(left, right) => {
+                let left = self.eval(frame, left)?;
+                if !left.truthy() {
+                    return Ok(left);
+                }
+                self.eval(frame, right)?
+            }
+            Expr::Or(left, right) => {
+                let left = self.eval(frame, left)?;
+                if left.truthy() {
+                    return Ok(left);
+                }
+                self.eval(frame, right)?
+            }
+            Expr::Binary(op, left, right) => {
+                let a = self.eval(frame, left)?;
+                let b = self.eval(frame, right)?;
+                self.binary(*op, &a, &b, left, right)?
+            }
+            Expr::Unary(op, operand) => {
+                let value = self.eval(frame, operand)?;
+                match op {
+                    UnOp::Not => Value::Boolean(!value.truthy()),
+                    UnOp::Neg => match value.as_number() {
+                        Some(n) => Value::Number(-n),
+                        None => match self.metamethod(&value, "__unm") {
+                            Value::Nil => {
+                                return Err(self.operand_error(
+                                    "perform arithmetic on",
+                                    operand,
+                                    &value,
+                                ))
+                            }
+                            handler => first(self.call(&handler, vec![value.clone(), value])?),
+                        },
+                    },
+                    UnOp::Len => match &value {
+                        Value::String(s) => Value::Number(s.len() as f64),
+                        Value::Table(t) => Value::Number(t.len() as f64),
+                        _ => return Err(self.operand_error("get length of", operand, &value)),
+                    },
+                }
+            }
+            Expr::Table(fields) => {
+                let table = TableRef::new();
+                let mut n = 0;
+                for (i, field) in fields.iter().enumerate() {
+                    match field {
+                        Field::Positional(expr) if i == fields.len() - 1 && expr.is_multi() => {
+                            for value in self.eval_multi(frame, expr)? {
+                                n += 1;
+                                table.set_index(n, value);
+                            }
+                        }
+                        Field::Positional(expr) => {
+                            n += 1;
+                            let value = self.eval(frame, expr)?;
+                            table.set_index(n, value);
+                        }
+                        Field::Named(key, value) => {
+                            let key = self.eval(frame, key)?;
+                            let value = self.eval(frame, value)?;
+                            table.set(key, value).map_err(|e| self.error(e))?;
+                        }
+                    }
+                }
+                Value::Table(table)
+            }
+        })
+    }
+
+    /// All values of an expression that may produce several.
+    fn eval_multi(&mut self, frame: &mut Frame, expr: &Expr) -> Result<Vec<Value>, LuaError> {
+        match expr {
+            Expr::Call {
+                function,
+                args,
+                line,
+            } => {
+                let function_value = self.eval(frame, function)?;
+                let args = self.eval_list(frame, args)?;
+                self.line = *line;
+                if !matches!(function_value, Value::Function(_))
+                    && matches!(self.metamethod(&function_value, "__call"), Value::Nil)
+                {
+                    return Err(self.operand_error("call", function, &function_value));
+                }
+                self.call(&function_value, args)
+            }
+            Expr::Method {
+                object,
+                name,
+                args,
+                line,
+            } => {
+                let object_value = self.eval(frame, object)?;
+                if !matches!(object_value, Value::Table(_) | Value::String(_)) {
+                    return Err(self.operand_error("index", object, &object_value));
+                }
+                let method = self.index(&object_value, &Value::String(name.clone()))?;
+                let mut call_args = vec![object_value];
+                call_args.extend(self.eval_list(frame, args)?);
+                self.line = *line;
+                if !matches!(method, Value::Function(_))
+                    && matches!(self.metamethod(&method, "__call"), Value::Nil)
+                {
+                    return Err(self.error(format!(
+                        "attempt to call method '{}' (a {} value)",
+                        String::from_utf8_lossy(name),
+                        method.type_name()
+                    )));
+                }
+                self.call(&method, call_args)
+            }
+            Expr::Vararg => Ok(frame.varargs.clone()),
+            expr => Ok(vec![self.eval(frame, expr)?]),
+        }
+    }
+
+    /// Values of an expression list, the last expression expanded.
+    fn eval_list(&mut self, frame: &mut Frame, exprs: &[Expr]) -> Result<Vec<Value>, LuaError> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for (i, expr) in exprs.iter().enumerate() {
+            if i == exprs.len() - 1 && expr.is_multi() {
+                values.extend(self.eval_multi(frame, expr)?);
+            } else {
+                values.push(self.eval(frame, expr)?);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Values of an expression list, truncated or padded with nils to `n`.
+    fn eval_list_adjusted(
+        &mut self,
+        frame: &mut Frame,
+        exprs: &[Expr],
+        n: usize,
+    ) -> Result<Vec<Value>, LuaError> {
+        let mut values = self.eval_list(frame, exprs)?;
+        values.resize(n, Value::Nil);
+        Ok(values)
+    }
+
+    fn closure(&mut self, frame: &mut Frame, proto: &Arc<FunctionProto>) -> Value {
+        let upvalues = proto
+            .upvalues
+            .iter()
+            .map(|source| match source {
+                UpvalueSource::Local(slot) => frame.cell(*slot),
+                UpvalueSource::Upvalue(i) => Rc::clone(&frame.closure.upvalues[*i]),
+            })
+            .collect();
+        Value::Function(Function::Lua(Rc::new(Closure {
+            proto: Arc::clone(proto),
+            upvalues,
+        })))
+    }
+
+    /// An error about a value of the wrong type, naming where it came from
+    /// when the expression tells, like `attempt to index global 'x' (a nil
+    /// value)`.
+    fn operand_error(&self, action: &str, expr: &Expr, value: &Value) -> LuaError {
+        let name = match expr {
+            Expr::Global(name) => Some(format!("global '{}'", String::from_utf8_lossy(name))),
+            Expr::Index(_, key) => match key.as_ref() {
+                Expr::String(key) => Some(format!("field '{}'", String::from_utf8_lossy(key))),
+                _ => None,
+            },
+            _ => None,
+        };
+        match name {
+            Some(name) => self.error(format!(
+                "attempt to {} {} (a {} value)",
+                action,
+                name,
+                value.type_name()
+            )),
+            None => self.error(format!(
+                "attempt to {} a {} value",
+                action,
+                value.type_name()
+            )),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: BinOp,
+        a: &Value,
+        b: &Value,
+        left: &Expr,
+        right: &Expr,
+    ) -> Result<Value, LuaError> {
+        let offending = |a: &Value| if a.as_number().is_none() { left } else { right };
+        Ok(match op {
+            BinOp::Eq => Value::Boolean(self.equals(a, b)?),
+            BinOp::Ne => Value::Boolean(!self.equals(a, b)?),
+            BinOp::Lt => Value::Boolean(self.less_than(a, b)?),
+            BinOp::Le => Value::Boolean(self.less_equal(a, b)?),
+            BinOp::Gt => Value::Boolean(self.less_than(b, a)?),
+            BinOp::Ge => Value::Boolean(self.less_equal(b, a)?),
+            BinOp::Concat => match self.concat(a, b)? {
+                Some(value) => value,
+                None => {
+                    let (expr, value) = if a.as_bytes().is_none() {
+                        (left, a)
+                    } else {
+                        (right, b)
+                    };
+                    return Err(self.operand_error("concatenate", expr, value));
+                }
+            },
+            op => match self.arith(op, a, b)? {
+                Some(value) => value,
+                None => {
+                    let value = if a.as_number().is_none() { a } else { b };
+                    return Err(self.operand_error("perform arithmetic on", offending(a), value));
+                }
+            },
+        })
+    }
+
+    /// Arithmetic on numbers or through metamethods; `None` if neither
+    /// applies.
+    fn arith(&mut self, op: BinOp, a: &Value, b: &Value) -> Result<Option<Value>, LuaError> {
+        if let (Some(x), Some(y)) = (a.as_number(), b.as_number()) {
+            return Ok(Some(Value::Number(match op {
+                BinOp::Add => x + y,
+                BinOp::Sub => x - y,
+                BinOp::Mul => x * y,
+                BinOp::Div => x / y,
+                BinOp::Mod => x - (x / y).floor() * y,
+                BinOp::Pow => x.powf(y),
+                _ => unreachable!("not an arithmetic operator"),
+            })));
+        }
+        let event = match op {
+            BinOp::Add => "__add",
+            BinOp::Sub => "__sub",
+            BinOp::Mul => "__mul",
+            BinOp::Div => "__div",
+            BinOp::Mod => "__mod",
+            BinOp::Pow => "__pow",
+            _ => unreachable!("not an arithmetic operator"),
+        };
+        self.binary_metamethod(a, b, event)
+    }
+
+    fn concat(&mut self, a: &Value, b: &Value) -> Result<Option<Value>, LuaError> {
+        if let (Some(x), Some(y)) = (a.as_bytes(), b.as_bytes()) {
+            let mut s = Vec::with_capacity(x.len() + y.len());
+            s.extend_from_slice(&x);
+            s.extend_from_slice(&y);
+            return Ok(Some(Value::string(s)));
+        }
+        self.binary_metamethod(a, b, "__concat")
+    }
+
+    fn binary_metamethod(
+        &mut self,
+        a: &Value,
+        b: &Value,
+        event: &'static str,
+    ) -> Result<Option<Value>, LuaError> {
+        let handler = match self.metamethod(a, event) {
+            Value::Nil => self.metamethod(b, event),
+            handler => handler,
+        };
+        if matches!(handler, Value::Nil) {
+            return Ok(None);
+        }
+        Ok(Some(first(
+            self.call(&handler, vec![a.clone(), b.clone()])?,
+        )))
+    }
+
+    pub fn equals(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        if a.raw_equals(b) {
+            return Ok(true);
+        }
+        if let (Value::Table(_), Value::Table(_)) = (a, b) {
+            let handler = self.metamethod(a, "__eq");
+            if !matches!(handler, Value::Nil) && handler.raw_equals(&self.metamethod(b, "__eq")) {
+                return Ok(first(self.call(&handler, vec![a.clone(), b.clone()])?).truthy());
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn less_than(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::Number(x), Value::Number(y)) => Ok(x < y),
+            (Value::String(x), Value::String(y)) => Ok(x < y),
+            _ => match self.binary_metamethod(a, b, "__lt")? {
+                Some(result) => Ok(result.truthy()),
+                None => Err(self.compare_error(a, b)),
+            },
+        }
+    }
+
+    fn less_equal(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::Number(x), Value::Number(y)) => Ok(x <= y),
+            (Value::String(x), Value::String(y)) => Ok(x <= y),
+            _ => match self.binary_metamethod(a, b, "__le")? {
+                Some(result) => Ok(result.truthy()),
+                // a <= b is not b < a
+                None => match self.binary_metamethod(b, a, "__lt")? {
+                    Some(result) => Ok(!result.truthy()),
+                    None => Err(self.compare_error(a, b)),
+                },
+            },
+        }
+    }
+
+    fn compare_error(&self, a: &Value, b: &Value) -> LuaError {
+        if a.type_name() == b.type_name() {
+            self.error(format!("attempt to compare two {} values", a.type_name()))
+        } else {
+            self.error(format!(
+                "attempt to compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            ))
+        }
+    }
+
+    pub fn metamethod(&self, value: &Value, event: &'static str) -> Value {
+        match value {
+            Value::Table(table) => table
+                .metatable()
+                .map(|metatable| {
+                    metatable.get(&Value::String(Bytes::from_static(event.as_bytes())))
+                })
+                .unwrap_or_default(),
+            _ => Value::Nil,
+        }
+    }
+
+    /// `object[key]`, following `__index`.
+    pub fn index(&mut self, object: &Value, key: &Value) -> Result<Value, LuaError> {
+        let mut object = object.clone();
+        for _ in 0..MAX_META_CHAIN {
+            let handler = match &object {
+                Value::Table(table) => {
+                    let value = table.get(key);
+                    if !matches!(value, Value::Nil) {
+                        return Ok(value);
+                    }
+                    self.metamethod(&object, "__index")
+                }
+                Value::String(_) => Value::Table(self.string_lib.clone()),
+                other => {
+                    return Err(
+                        self.error(format!("attempt to index a {} value", other.type_name()))
+                    )
+                }
+            };
+            match handler {
+                Value::Nil => return Ok(Value::Nil),
+                Value::Function(_) => {
+                    return Ok(first(self.call(&handler, vec![object, key.clone()])?));
+                }
+                handler => object = handler,
+            }
+        }
+        Err(self.error("loop in gettable"))
+    }
+
+    /// `object[key] = value`, following `__newindex`.
+    pub fn set_index(&mut self, object: &Value, key: Value, value: Value) -> Result<(), LuaError> {
+        let mut object = object.clone();
+        for _ in 0..MAX_META_CHAIN {
+            let Value::Table(table) = &object else {
+                return Err(self.error(format!("attempt to index a {} value", object.type_name())));
+            };
+            let handler = match table.get(&key) {
+                Value::Nil => self.metamethod(&object, "__newindex"),
+                _ => Value::Nil,
+            };
+            match handler {
+                Value::Nil => return self.raw_set(table, key, value),
+                Value::Function(_) => {
+                    self.call(&handler, vec![object.clone(), key, value])?;
+                    return Ok(());
+                }
+                handler => object = handler,
+            }
+        }
+        Err(self.error("loop in settable"))
+    }
+
+    /// Set a field without metamethods, unless the table is read-only.
+    pub fn raw_set(&self, table: &TableRef, key: Value, value: Value) -> Result<(), LuaError> {
+        self.check_writable(table)?;
+        table.set(key, value).map_err(|e| self.error(e))
+    }
+
+    pub fn check_writable(&self, table: &TableRef) -> Result<(), LuaError> {
+        if self.readonly.iter().any(|readonly| readonly.ptr_eq(table)) {
+            return Err(self.error("Attempt to modify a readonly table"));
+        }
+        Ok(())
+    }
+
+    /// `tostring(value)`, honoring `__tostring`.
+    pub fn tostring(&mut self, value: &Value) -> Result<Bytes, LuaError> {
+        let handler = self.metamethod(value, "__tostring");
+        if !matches!(handler, Value::Nil) {
+            return first(self.call(&handler, vec![value.clone()])?)
+                .as_bytes()
+                .ok_or_else(|| self.error("'__tostring' must return a string"));
+        }
+        Ok(match value {
+            Value::Nil => Bytes::from_static(b"nil"),
+            Value::Boolean(true) => Bytes::from_static(b"true"),
+            Value::Boolean(false) => Bytes::from_static(b"false"),
+            Value::Number(n) => Bytes::from(format_number(*n)),
+            Value::String(s) => s.clone(),
+            other => Bytes::from(format!("{}: {:#x}", other.type_name(), other.addr())),
+        })
+    }
+}
+
+/// The first of several values, nil if there are none.
+pub(super) fn first(values: Vec<Value>) -> Value {
+    values.into_iter().next().unwrap_or_default()
+}
@@ -0,0 +1,358 @@
+//! The `cjson` library: JSON encoding and decoding of Lua values.
+
+use super::value::{format_number, TableRef, Value};
+
+/// Nesting limit of encoded tables and decoded documents.
+const MAX_DEPTH: usize = 1000;
+
+/// Encode a value. `null` is the sentinel standing for JSON null.
+pub(super) fn encode(value: &Value, null: &TableRef) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    encode_value(value, null, 0, &mut out)?;
+    Ok(out)
+}
+
+fn encode_value(
+    value: &Value,
+    null: &TableRef,
+    depth: usize,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    match value {
+        Value::Nil => out.extend_from_slice(b"null"),
+        Value::Boolean(b) => out.extend_from_slice(if *b { b"true" } else { b"false" }),
+        Value::Number(n) => {
+            if !n.is_finite() {
+                return Err("Cannot serialise number: must not be NaN or Inf".to_string());
+            }
+            out.extend_from_slice(format_number(*n).as_bytes());
+        }
+        Value::String(s) => encode_string(s, out),
+        Value::Table(table) if table.ptr_eq(null) => out.extend_from_slice(b"null"),
+        Value::Table(table) => {
+            if depth >= MAX_DEPTH {
+                return Err(format!(
+                    "Cannot serialise, excessive nesting ({})",
+                    depth + 1
+                ));
+            }
+            let mut entries = Vec::new();
+            let mut key = Value::Nil;
+            while let Ok(Some((k, v))) = table.next(&key) {
+                entries.push((k.clone(), v));
+                key = k;
+            }
+            match array_length(&entries)? {
+                Some(len) => {
+                    out.push(b'[');
+                    for i in 1..=len {
+                        if i > 1 {
+                            out.push(b',');
+                        }
+                        encode_value(&table.get_index(i), null, depth + 1, out)?;
+                    }
+                    out.push(b']');
+                }
+                None => {
+                    out.push(b'{');
+                    for (i, (key, value)) in entries.iter().enumerate() {
+                        if i > 0 {
+                            out.push(b',');
+                        }
+                        let key =
+                            match key {
+                                Value::String(_) | Value::Number(_) => {
+                                    key.as_bytes().unwrap_or_default()
+                                }
+                                _ => return Err(
+                                    "Cannot serialise table: table key must be a number or string"
+                                        .to_string(),
+                                ),
+                            };
+                        encode_string(&key, out);
+                        out.push(b':');
+                        encode_value(value, null, depth + 1, out)?;
+                    }
+                    out.push(b'}');
+                }
+            }
+        }
+        Value::Function(_) => {
+            return Err("Cannot serialise function: type not supported".to_string())
+        }
+    }
+    Ok(())
+}
+
+/// The length of a table to encode as an array: one whose keys are all
+/// positive integers, without too many holes. Empty tables are objects.
+fn array_length(entries: &[(Value, Value)]) -> Result<Option<usize>, String> {
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    let mut max = 0;
+    for (key, _) in entries {
+        match key {
+            Value::Number(n) if *n >= 1.0 && n.fract() == 0.0 => max = max.max(*n as usize),
+            _ => return Ok(None),
+        }
+    }
+    if max > 10 && max > entries.len() * 2 {
+        return Err("Cannot serialise table: excessively sparse array".to_string());
+    }
+    Ok(Some(max))
+}
+
+fn encode_string(s: &[u8], out: &mut Vec<u8>) {
+    // Other bytes pass through as they are, as Lua strings have no encoding
+    out.push(b'"');
+    for &c in s {
+        match c {
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'/' => out.extend_from_slice(b"\\/"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            0x08 => out.extend_from_slice(b"\\b"),
+            0x0c => out.extend_from_slice(b"\\f"),
+            c if c < 0x20 || c == 0x7f => out.extend_from_slice(format!("\\u{:04x}", c).as_bytes()),
+            c => out.push(c),
+        }
+    }
+    out.push(b'"');
+}
+
+/// Decode a JSON document. `null` is the sentinel standing for JSON null.
+pub(super) fn decode(s: &[u8], null: &TableRef) -> Result<Value, String> {
+    let mut decoder = Decoder { s, pos: 0, null };
+    let value = decoder.value(0)?;
+    decoder.skip_whitespace();
+    if decoder.pos < s.len() {
+        return Err(decoder.error("the end"));
+    }
+    Ok(value)
+}
+
+struct Decoder<'a> {
+    s: &'a [u8],
+    pos: usize,
+    null: &'a TableRef,
+}
+
+impl Decoder<'_> {
+    fn error(&self, expected: &str) -> String {
+        format!(
+            "Expected {} but found invalid token at character {}",
+            expected,
+            self.pos + 1
+        )
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .s
+            .get(self.pos)
+            .is_some_and(|c| matches!(c, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth >= MAX_DEPTH {
+            return Err(format!(
+                "Found too many nested data structures ({}) at character {}",
+                depth + 1,
+                self.pos + 1
+            ));
+        }
+        self.skip_whitespace();
+        let Some(&c) = self.s.get(self.pos) else {
+            return Err(self.error("value"));
+        };
+        match c {
+            b'{' => {
+                self.pos += 1;
+                let table = TableRef::new();
+                self.skip_whitespace();
+                if self.s.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Table(table));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.s.get(self.pos) != Some(&b'"') {
+                        return Err(self.error("object key string"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if self.s.get(self.pos) != Some(&b':') {
+                        return Err(self.error("colon"));
+                    }
+                    self.pos += 1;
+                    let value = self.value(depth + 1)?;
+                    let _ = table.set(key, value);
+                    self.skip_whitespace();
+                    match self.s.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Table(table));
+                        }
+                        _ => return Err(self.error("comma or object end")),
+                    }
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.s.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Value::Table(TableRef::from_array(items)));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.s.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Table(TableRef::from_array(items)));
+                        }
+                        _ => return Err(self.error("comma or array end")),
+                    }
+                }
+            }
+            b'"' => self.string(),
+            b't' => self.literal("true", Value::Boolean(true)),
+            b'f' => self.literal("false", Value::Boolean(false)),
+            b'n' => self.literal("null", Value::Table(self.null.clone())),
+            b'-' | b'0'..=b'9' => {
+                let start = self.pos;
+                while self.s.get(self.pos).is_some_and(|c| {
+                    c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.' | b'e' | b'E')
+                }) {
+                    self.pos += 1;
+                }
+                std::str::from_utf8(&self.s[start..self.pos])
+                    .ok()
+                    .and_then(|n| n.parse().ok())
+                    .map(Value::Number)
+                    .ok_or_else(|| {
+                        self.pos = start;
+                        self.error("value")
+                    })
+            }
+            _ => Err(self.error("value")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        if !self.s[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("value"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            let Some(&c) = self.s.get(self.pos) else {
+                self.pos = start;
+                return Err(self.error("string end"));
+            };
+            self.pos += 1;
+            match c {
+                b'"' => return Ok(Value::string(out)),
+                b'\\' => {
+                    let escape = self.s.get(self.pos).copied();
+                    self.pos += 1;
+                    match escape {
+                        Some(b'"') => out.push(b'"'),
+                        Some(b'\\') => out.push(b'\\'),
+                        Some(b'/') => out.push(b'/'),
+                        Some(b'b') => out.push(0x08),
+                        Some(b'f') => out.push(0x0c),
+                        Some(b'n') => out.push(b'\n'),
+                        Some(b'r') => out.push(b'\r'),
+                        Some(b't') => out.push(b'\t'),
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.s[self.pos..].starts_with(b"\\u")
+                            {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if (0xdc00..0xe000).contains(&low) {
+                                    code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                                }
+                            }
+                            let c =
+                                char::from_u32(code).ok_or_else(|| self.error("unicode escape"))?;
+                            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        _ => {
+                            self.pos -= 2;
+                            return Err(self.error("escape sequence"));
+                        }
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .s
+            .get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| self.error("unicode escape"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let null = TableRef::new();
+        let doc = r#"{"a":[1,2.5,"x\/é😀"],"b":{"c":true,"d":null},"e":{}}"#.as_bytes();
+        let value = decode(doc, &null).unwrap();
+        let Value::Table(table) = &value else {
+            panic!("Expected table");
+        };
+        assert!(
+            matches!(&table.get_str("b"), Value::Table(b) if matches!(b.get_str("d"), Value::Table(d) if d.ptr_eq(&null)))
+        );
+        assert_eq!(
+            String::from_utf8(encode(&value, &null).unwrap()).unwrap(),
+            "{\"a\":[1,2.5,\"x\\/é😀\"],\"b\":{\"c\":true,\"d\":null},\"e\":{}}"
+        );
+
+        assert!(decode(b"[1,", &null).is_err());
+        assert!(decode(b"{\"a\" 1}", &null).is_err());
+        assert!(decode(b"1 2", &null).is_err());
+
+        let sparse = TableRef::new();
+        sparse.set_index(100, Value::Boolean(true));
+        assert!(encode(&Value::Table(sparse), &null)
+            .unwrap_err()
+            .contains("sparse"));
+        let nested = TableRef::new();
+        nested.set_str("self", Value::Table(nested.clone()));
+        assert!(encode(&Value::Table(nested.clone()), &null)
+            .unwrap_err()
+            .contains("nesting"));
+        nested.set_str("self", Value::Nil);
+    }
+}
@@ -0,0 +1,402 @@
+//! Tokenizer for Lua 5.1 source.
+
+use super::value::str_to_number;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Token {
+    Name(String),
+    String(Vec<u8>),
+    Number(f64),
+    // Keywords
+    And,
+    Break,
+    Do,
+    Else,
+    ElseIf,
+    End,
+    False,
+    For,
+    Function,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    // Symbols
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Hash,
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+    Assign,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Semicolon,
+    Colon,
+    Comma,
+    Dot,
+    Concat,
+    Dots,
+    Eof,
+}
+
+const KEYWORDS: &[(&str, Token)] = &[
+    ("and", Token::And),
+    ("break", Token::Break),
+    ("do", Token::Do),
+    ("else", Token::Else),
+    ("elseif", Token::ElseIf),
+    ("end", Token::End),
+    ("false", Token::False),
+    ("for", Token::For),
+    ("function", Token::Function),
+    ("if", Token::If),
+    ("in", Token::In),
+    ("local", Token::Local),
+    ("nil", Token::Nil),
+    ("not", Token::Not),
+    ("or", Token::Or),
+    ("repeat", Token::Repeat),
+    ("return", Token::Return),
+    ("then", Token::Then),
+    ("true", Token::True),
+    ("until", Token::Until),
+    ("while", Token::While),
+];
+
+impl Token {
+    /// The token as it appears in error messages (`near '...'`).
+    pub(super) fn describe(&self) -> String {
+        let symbol = match self {
+            Token::Name(name) => return name.clone(),
+            Token::String(s) => return String::from_utf8_lossy(s).into_owned(),
+            Token::Number(n) => return super::value::format_number(*n),
+            Token::Eof => return "<eof>".to_string(),
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Caret => "^",
+            Token::Hash => "#",
+            Token::Eq => "==",
+            Token::Ne => "~=",
+            Token::Le => "<=",
+            Token::Ge => ">=",
+            Token::Lt => "<",
+            Token::Gt => ">",
+            Token::Assign => "=",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Concat => "..",
+            Token::Dots => "...",
+            keyword => {
+                return KEYWORDS
+                    .iter()
+                    .find(|(_, token)| token == keyword)
+                    .map_or_else(String::new, |(name, _)| name.to_string())
+            }
+        };
+        symbol.to_string()
+    }
+}
+
+pub(super) struct Lexer<'a> {
+    src: &'a [u8],
+    pos: usize,
+    /// Line of the next character.
+    pub(super) line: u32,
+}
+
+impl<'a> Lexer<'a> {
+    pub(super) fn new(src: &'a [u8]) -> Self {
+        Lexer {
+            src,
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.src.get(self.pos + offset).copied()
+    }
+
+    /// Next token and the line it starts on. Errors are messages without
+    /// position, which the parser adds.
+    pub(super) fn next_token(&mut self) -> Result<(Token, u32), String> {
+        self.skip_whitespace()?;
+        let line = self.line;
+        let Some(c) = self.peek(0) else {
+            return Ok((Token::Eof, line));
+        };
+
+        let token = match c {
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                let start = self.pos;
+                while self
+                    .peek(0)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_')
+                {
+                    self.pos += 1;
+                }
+                let word = std::str::from_utf8(&self.src[start..self.pos]).unwrap_or_default();
+                KEYWORDS
+                    .iter()
+                    .find(|(name, _)| *name == word)
+                    .map_or_else(|| Token::Name(word.to_string()), |(_, t)| t.clone())
+            }
+            b'0'..=b'9' => self.number()?,
+            b'.' if self.peek(1).is_some_and(|c| c.is_ascii_digit()) => self.number()?,
+            b'"' | b'\'' => self.short_string(c)?,
+            b'[' if matches!(self.peek(1), Some(b'[' | b'=')) => match self.long_bracket()? {
+                Some(s) => Token::String(s),
+                None => return Err("invalid long string delimiter near '['".to_string()),
+            },
+            _ => {
+                let (token, len) = match (c, self.peek(1), self.peek(2)) {
+                    (b'.', Some(b'.'), Some(b'.')) => (Token::Dots, 3),
+                    (b'.', Some(b'.'), _) => (Token::Concat, 2),
+                    (b'=', Some(b'='), _) => (Token::Eq, 2),
+                    (b'~', Some(b'='), _) => (Token::Ne, 2),
+                    (b'<', Some(b'='), _) => (Token::Le, 2),
+                    (b'>', Some(b'='), _) => (Token::Ge, 2),
+                    (b'.', ..) => (Token::Dot, 1),
+                    (b'=', ..) => (Token::Assign, 1),
+                    (b'<', ..) => (Token::Lt, 1),
+                    (b'>', ..) => (Token::Gt, 1),
+                    (b'+', ..) => (Token::Plus, 1),
+                    (b'-', ..) => (Token::Minus, 1),
+                    (b'*', ..) => (Token::Star, 1),
+                    (b'/', ..) => (Token::Slash, 1),
+                    (b'%', ..) => (Token::Percent, 1),
+                    (b'^', ..) => (Token::Caret, 1),
+                    (b'#', ..) => (Token::Hash, 1),
+                    (b'(', ..) => (Token::LParen, 1),
+                    (b')', ..) => (Token::RParen, 1),
+                    (b'{', ..) => (Token::LBrace, 1),
+                    (b'}', ..) => (Token::RBrace, 1),
+                    (b'[', ..) => (Token::LBracket, 1),
+                    (b']', ..) => (Token::RBracket, 1),
+                    (b';', ..) => (Token::Semicolon, 1),
+                    (b':', ..) => (Token::Colon, 1),
+                    (b',', ..) => (Token::Comma, 1),
+                    _ => {
+                        return Err(format!(
+                            "unexpected symbol near '{}'",
+                            String::from_utf8_lossy(&[c])
+                        ))
+                    }
+                };
+                self.pos += len;
+                token
+            }
+        };
+        Ok((token, line))
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), String> {
+        while let Some(c) = self.peek(0) {
+            match c {
+                b'\n' => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                b' ' | b'\t' | b'\r' | 0x0b | 0x0c => self.pos += 1,
+                b'-' if self.peek(1) == Some(b'-') => {
+                    self.pos += 2;
+                    if self.peek(0) == Some(b'[') && self.long_bracket()?.is_some() {
+                        continue;
+                    }
+                    while self.peek(0).is_some_and(|c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<Token, String> {
+        let start = self.pos;
+        while let Some(c) = self.peek(0) {
+            if matches!(c, b'e' | b'E') && matches!(self.peek(1), Some(b'+' | b'-')) {
+                self.pos += 2;
+            } else if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        let text = &self.src[start..self.pos];
+        str_to_number(text)
+            .map(Token::Number)
+            .ok_or_else(|| format!("malformed number near '{}'", String::from_utf8_lossy(text)))
+    }
+
+    fn short_string(&mut self, quote: u8) -> Result<Token, String> {
+        self.pos += 1;
+        let mut s = Vec::new();
+        loop {
+            let Some(c) = self.peek(0) else {
+                return Err("unfinished string near '<eof>'".to_string());
+            };
+            self.pos += 1;
+            match c {
+                b'\n' => return Err("unfinished string".to_string()),
+                c if c == quote => return Ok(Token::String(s)),
+                b'\\' => {
+                    let Some(escape) = self.peek(0) else {
+                        return Err("unfinished string near '<eof>'".to_string());
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'a' => s.push(0x07),
+                        b'b' => s.push(0x08),
+                        b'f' => s.push(0x0c),
+                        b'n' => s.push(b'\n'),
+                        b'r' => s.push(b'\r'),
+                        b't' => s.push(b'\t'),
+                        b'v' => s.push(0x0b),
+                        b'\n' => {
+                            self.line += 1;
+                            s.push(b'\n');
+                        }
+                        b'0'..=b'9' => {
+                            let mut value = u32::from(escape - b'0');
+                            for _ in 0..2 {
+                                match self.peek(0) {
+                                    Some(digit @ b'0'..=b'9') => {
+                                        value = value * 10 + u32::from(digit - b'0');
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            let byte = u8::try_from(value)
+                                .map_err(|_| "escape sequence too large".to_string())?;
+                            s.push(byte);
+                        }
+                        // Any other escaped character stands for itself
+                        other => s.push(other),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// Read a long bracket (`[[...]]`, `[==[...]==]`) starting at `[`.
+    /// Returns `None`, consuming nothing, if it is not one.
+    fn long_bracket(&mut self) -> Result<Option<Vec<u8>>, String> {
+        let mut level = 0;
+        while self.peek(1 + level) == Some(b'=') {
+            level += 1;
+        }
+        if self.peek(1 + level) != Some(b'[') {
+            return Ok(None);
+        }
+        self.pos += level + 2;
+
+        // A newline right after the opening bracket is skipped
+        if self.peek(0) == Some(b'\r') {
+            self.pos += 1;
+        }
+        if self.peek(0) == Some(b'\n') {
+            self.line += 1;
+            self.pos += 1;
+        }
+
+        let start = self.pos;
+        loop {
+            match self.peek(0) {
+                None => return Err("unfinished long string near '<eof>'".to_string()),
+                Some(b']')
+                    if (1..=level).all(|i| self.peek(i) == Some(b'='))
+                        && self.peek(level + 1) == Some(b']') =>
+                {
+                    let s = self.src[start..self.pos].to_vec();
+                    self.pos += level + 2;
+                    return Ok(Some(s));
+                }
+                Some(b'\n') => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(src: &str) -> Vec<Token> {
+        let mut lexer = Lexer::new(src.as_bytes());
+        let mut tokens = Vec::new();
+        loop {
+            match lexer.next_token().unwrap() {
+                (Token::Eof, _) => return tokens,
+                (token, _) => tokens.push(token),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            tokens("local x = 0x1F + 1.5e2 -- comment\n..'a\\tb\\065' [==[\nlong]]]==] ~= ..."),
+            vec![
+                Token::Local,
+                Token::Name("x".to_string()),
+                Token::Assign,
+                Token::Number(31.0),
+                Token::Plus,
+                Token::Number(150.0),
+                Token::Concat,
+                Token::String(b"a\tbA".to_vec()),
+                Token::String(b"long]]".to_vec()),
+                Token::Ne,
+                Token::Dots,
+            ]
+        );
+        assert_eq!(
+            tokens("--[[ block\ncomment ]] a"),
+            vec![Token::Name("a".into())]
+        );
+
+        let mut lexer = Lexer::new(b"'unfinished");
+        assert!(lexer.next_token().is_err());
+        let mut lexer = Lexer::new(b"3x");
+        assert!(lexer.next_token().is_err());
+    }
+}
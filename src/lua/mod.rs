@@ -0,0 +1,404 @@
+//! A Lua 5.1 interpreter for server-side scripts (EVAL).
+//!
+//! Scripts are compiled once by [`compile`] into a [`Chunk`], which is
+//! `Send` so it can be cached and shared by all connections, and each call
+//! runs it in a fresh [`Interp`]. The interpreter only offers the libraries
+//! Redis scripts may use, and reaches the server through the functions of
+//! its [`Host`].
+
+mod ast;
+mod interp;
+mod json;
+mod lexer;
+mod parser;
+mod pattern;
+mod stdlib;
+mod value;
+
+pub use interp::{Host, Interp};
+pub use value::{format_number, Function, TableRef, Value};
+
+use std::sync::Arc;
+
+/// A compiled script.
+#[derive(Clone)]
+pub struct Chunk(Arc<ast::FunctionProto>);
+
+/// Compile a script. The error is a message like Lua's, such as
+/// `user_script:1: '=' expected near 'x'`.
+pub fn compile(source: &[u8]) -> Result<Chunk, String> {
+    parser::compile(source).map(Chunk)
+}
+
+#[derive(Debug)]
+pub enum LuaError {
+    /// An error raised by the script or a function it called, with its value.
+    Error(Value),
+    /// The host asked the script to stop. Scripts can not catch this one.
+    Interrupted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestHost {
+        calls: usize,
+        interrupt_after: Option<usize>,
+    }
+
+    impl Host for TestHost {
+        fn call(&mut self, name: &'static str, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+            self.calls += 1;
+            let mut results = vec![Value::string(name)];
+            results.extend(args);
+            Ok(results)
+        }
+
+        fn interrupted(&mut self) -> bool {
+            self.interrupt_after
+                .is_some_and(|after| self.calls >= after)
+        }
+    }
+
+    fn run_with(host: &mut TestHost, source: &str) -> Result<Vec<Value>, LuaError> {
+        let chunk = compile(source.as_bytes()).map_err(|e| LuaError::Error(Value::string(e)))?;
+        let mut interp = Interp::new(host);
+        interp.set_global("host", Interp::host_function("echo"));
+        interp.freeze();
+        interp.run(&chunk)
+    }
+
+    /// The values returned by a script, as debug strings.
+    fn run(source: &str) -> Vec<String> {
+        let mut host = TestHost {
+            calls: 0,
+            interrupt_after: None,
+        };
+        match run_with(&mut host, source) {
+            Ok(values) => values.iter().map(|v| format!("{:?}", v)).collect(),
+            Err(e) => panic!("{} failed: {:?}", source, e),
+        }
+    }
+
+    fn run_err(source: &str) -> String {
+        let mut host = TestHost {
+            calls: 0,
+            interrupt_after: None,
+        };
+        match run_with(&mut host, source) {
+            Err(LuaError::Error(Value::String(e))) => String::from_utf8_lossy(&e).into_owned(),
+            other => panic!("{} did not fail: {:?}", source, other),
+        }
+    }
+
+    #[test]
+    fn test_language() {
+        assert_eq!(
+            run("return 1 + 2 * 3 ^ 2, 7 % 3, -7 % 3, 10 / 4, '10' + 1"),
+            ["19", "1", "2", "2.5", "11"]
+        );
+        assert_eq!(
+            run("return 1 .. 2, 'a' < 'b', 2 ^ 3 ^ 2, not nil == true"),
+            ["b\"12\"", "true", "512", "true"]
+        );
+        assert_eq!(
+            run("return nil and 1 or 2, false or nil, 1 and 2"),
+            ["2", "nil", "2"]
+        );
+        assert_eq!(
+            run("local t = {1, 2, 3, x = 'y', [10] = 'z'}\nreturn #t, t.x, t[10], #'abc'"),
+            ["3", "b\"y\"", "b\"z\"", "3"]
+        );
+        assert_eq!(
+            run("local s = 0\n\
+                 for i = 10, 1, -2 do s = s + i end\n\
+                 local t = {}\n\
+                 for k, v in pairs({a = 1, b = 2}) do t[#t + 1] = k .. v end\n\
+                 local n = 0\n\
+                 while true do n = n + 1 if n == 5 then break end end\n\
+                 repeat local m = n; n = n - 1 until m < 3\n\
+                 return s, table.concat(t, ','), n"),
+            ["30", "b\"a1,b2\"", "1"]
+        );
+        // Each iteration has its own local for closures to capture
+        assert_eq!(
+            run("local fs = {}\n\
+                 for i = 1, 3 do fs[i] = function() return i end end\n\
+                 local function counter()\n\
+                   local c = 0\n\
+                   return function() c = c + 1; return c end\n\
+                 end\n\
+                 local next_value = counter(); next_value()\n\
+                 return fs[1]() + fs[3](), next_value()"),
+            ["4", "2"]
+        );
+        assert_eq!(
+            run("local function f(...) return select('#', ...), ... end\n\
+                 local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end\n\
+                 return fib(15), f(1, nil, 3)"),
+            ["610", "3", "1", "nil", "3"]
+        );
+        assert_eq!(
+            run("local v = setmetatable({}, {\n\
+                   __index = function(t, k) return k .. '!' end,\n\
+                   __add = function(a, b) return 42 end,\n\
+                   __call = function(self, x) return x * 2 end,\n\
+                   __tostring = function() return 'vec' end})\n\
+                 local obj = {n = 1}\n\
+                 function obj:inc(by) self.n = self.n + by; return self end\n\
+                 return v.hi, v + 1, v(21), tostring(v), obj:inc(2):inc(3).n"),
+            ["b\"hi!\"", "42", "42", "b\"vec\"", "6"]
+        );
+    }
+
+    #[test]
+    fn test_libraries() {
+        assert_eq!(
+            run("return string.format('%5.2f|%-3d|%x|%s|%q|%g', 3.14159, 7, 255, 'hi', 'a\"b', 1e20)"),
+            ["b\" 3.14|7  |ff|hi|\\\"a\\\\\\\"b\\\"|1e+20\""]
+        );
+        assert_eq!(
+            run("local s = 'hello world from lua'\n\
+                 local words = {}\n\
+                 for w in s:gmatch('%a+') do words[#words + 1] = w:upper() end\n\
+                 return table.concat(words, ' '), (s:gsub('o', '0', 2)), s:find('wor'), s:match('(%a+) (%a+)$')"),
+            ["b\"HELLO WORLD FROM LUA\"", "b\"hell0 w0rld from lua\"", "7", "b\"from\"", "b\"lua\""]
+        );
+        assert_eq!(
+            run("local t = {5, 2, 8, 1}\n\
+                 table.sort(t)\n\
+                 table.insert(t, 1, 0)\n\
+                 local last = table.remove(t)\n\
+                 table.sort(t, function(a, b) return a > b end)\n\
+                 return table.concat(t, ','), last, unpack({1, 2})"),
+            ["b\"5,2,1,0\"", "8", "1", "2"]
+        );
+        assert_eq!(
+            run("return math.max(1, 5, 3), math.floor(-2.5), tonumber('0x10'), tonumber('z', 36), tonumber('x')"),
+            ["5", "-3", "16", "35", "nil"]
+        );
+        assert_eq!(
+            run("return bit.band(0xff, 0x0f), bit.bor(1, 2, 4), bit.lshift(1, 31), bit.tohex(255), bit.tobit(2^32 + 1)"),
+            ["15", "7", "-2147483648", "b\"000000ff\"", "1"]
+        );
+        assert_eq!(
+            run("local t = cjson.decode('{\"a\":[1,2,{\"b\":null}]}')\n\
+                 return t.a[2], t.a[3].b == cjson.null, cjson.encode({1, 'x', true})"),
+            ["2", "true", "b\"[1,\\\"x\\\",true]\""]
+        );
+        // The generator starts over with every script
+        assert_eq!(
+            run("return math.random(100)"),
+            run("return math.random(100)")
+        );
+        assert_eq!(run("return host(1, 'a')"), ["b\"echo\"", "1", "b\"a\""]);
+    }
+
+    /// The values of `expr` as `tostring` shows them, separated by spaces.
+    fn show(expr: &str) -> String {
+        let source = format!(
+            "local function show(...)\n\
+               local out = {{}}\n\
+               for i = 1, select('#', ...) do out[i] = tostring((select(i, ...))) end\n\
+               return table.concat(out, ' ')\n\
+             end\n\
+             return show({})",
+            expr
+        );
+        let mut host = TestHost {
+            calls: 0,
+            interrupt_after: None,
+        };
+        match run_with(&mut host, &source) {
+            Ok(values) => match values.as_slice() {
+                [Value::String(s)] => String::from_utf8_lossy(s).into_owned(),
+                other => panic!("{} returned {:?}", expr, other),
+            },
+            Err(e) => panic!("{} failed: {:?}", expr, e),
+        }
+    }
+
+    /// Every function of the libraries scripts get, against the results of
+    /// the reference Lua 5.1.
+    #[test]
+    fn test_standard_library_conformance() {
+        let cases = [
+            // Base functions
+            ("_VERSION", "Lua 5.1"),
+            ("type(nil), type(1), type('s'), type({}), type(type)", "nil number string table function"),
+            ("tostring(1e15), tostring(2^53), tostring(0.1), tostring(-1.5)", "1e+15 9.007199254741e+15 0.1 -1.5"),
+            ("tostring(nil), tostring(true), 1/0, -1/0", "nil true inf -inf"),
+            ("tonumber('  10  '), tonumber('1e2'), tonumber('0x1F'), tonumber(''), tonumber('1 2')", "10 100 31 nil nil"),
+            ("tonumber('10', 2), tonumber('ff', 16), tonumber('Zz', 36), tonumber('2', 2)", "2 255 1295 nil"),
+            ("select(2, 'a', 'b', 'c'), select(-1, 'x', 'y'), select('#', nil, nil)", "b y 2"),
+            ("unpack({1, 2, 3}, 2, 2), unpack({}), unpack({1, 2, 3}, 2)", "2 nil 2 3"),
+            ("rawequal({}, {}), rawequal('a', 'a'), rawget(setmetatable({}, {__index = function() return 1 end}), 'k')", "false true nil"),
+            ("rawset({}, 'k', 'v').k, next({}), next({10})", "v nil 1 10"),
+            ("(function() local n = 0 for _ in ipairs({1, 2, nil, 4}) do n = n + 1 end return n end)()", "2"),
+            ("(function() local n = 0 for _ in pairs({1, 2, nil, 4, a = 5}) do n = n + 1 end return n end)()", "4"),
+            ("select('#', pcall(error)), select(2, pcall(error, 'm', 0))", "2 m"),
+            ("pcall(function(...) return ... end, 1, 2)", "true 1 2"),
+            ("xpcall(function() error('x', 0) end, function(e) return 'handled ' .. e end)", "false handled x"),
+            ("select(2, pcall(function() assert(false) end)), assert(1, 'm')", "user_script:6: assertion failed! 1 m"),
+            ("pcall(function() assert(nil, 'why') end)", "false user_script:6: why"),
+            ("getmetatable(setmetatable({}, {__metatable = 'locked'})), getmetatable({})", "locked nil"),
+            // string
+            ("string.len('abc'), ('abc'):len(), #''", "3 3 0"),
+            ("('hello'):sub(2, -2), ('hello'):sub(-3), ('hello'):sub(0), ('hello'):sub(10), ('hello'):sub(3, 2)", "ell llo hello  "),
+            ("('MiXeD'):upper(), ('MiXeD'):lower(), ('ab'):rep(3), ('x'):rep(0), ('abc'):reverse()", "MIXED mixed ababab  cba"),
+            ("string.char(72, 105), string.char(), ('ABC'):byte(), ('ABC'):byte(1, -1)", "Hi  65 65 66 67"),
+            ("select('#', ('ABC'):byte(10))", "0"),
+            ("string.format('%d|%i|%+d|% d|%5d|%-5d|%05d', 3.7, 42, 5, 5, 42, 42, 42)", "3|42|+5| 5|   42|42   |00042"),
+            ("string.format('%5s|%-5s|%.3s|%s', 'ab', 'ab', 'abcdef', 12)", "   ab|ab   |abc|12"),
+            ("string.format('%05.1f|%.2f|%e|%.0e|%E', 3.14159, 2.675, 12345.678, 5e10, 0.5)", "003.1|2.67|1.234568e+04|5e+10|5.000000E-01"),
+            ("string.format('%g|%g|%g|%g|%.3g', 0.0001, 1e-5, 100000, 1e6, 2/3)", "0.0001|1e-05|100000|1e+06|0.667"),
+            ("string.format('%o|%x|%X|%#x|%#o|%c|%%', 8, 255, 255, 255, 8, 65)", "10|ff|FF|0xff|010|A|%"),
+            ("string.format('%q', 'a\\nb\\0c\\r\"')", "\"a\\\nb\\000c\\r\\\"\""),
+            ("('a.b'):find('.', 1, true), ('abc'):find('x'), ('hello'):find('l', -2), ('hello'):find('l+')", "2 nil 4 3 4"),
+            ("('key=val'):find('(%w+)=(%w+)')", "1 7 key val"),
+            ("('abc'):find(''), ('abc'):find('', 10)", "1 4 3"),
+            ("('  trim  '):match('^%s*(.-)%s*$'), ('2024-01-15'):match('(%d+)-(%d+)-(%d+)')", "trim 2024 01 15"),
+            ("('[test]'):match('%[(.-)%]'), ('f(a(b)c)d'):match('%b()'), ('x'):match('y'), ('abc'):match('()b()')", "test (a(b)c) nil 2 3"),
+            ("('a1 b2'):match('%a%d$'), ('A-z'):match('[%u%-]+'), ('THE (quick) fox'):gsub('%f[%a]%a+', 'W')", "b2 A- W (W) W 3"),
+            ("(function() local t = {} for k, v in ('a=1, b=2'):gmatch('(%w+)=(%w+)') do t[#t + 1] = k .. v end return table.concat(t, ',') end)()", "a1,b2"),
+            ("(function() local n = 0 for _ in ('abc'):gmatch('') do n = n + 1 end return n end)()", "4"),
+            ("('hello world'):gsub('o', '0')", "hell0 w0rld 2"),
+            ("('abc'):gsub('%w', '%0%0')", "aabbcc 3"),
+            ("('hello'):gsub('(l)(l)', '%2%1')", "hello 1"),
+            ("('$name is $age'):gsub('%$(%w+)', {name = 'Bob', age = 42})", "Bob is 42 2"),
+            ("('abc'):gsub('', '-')", "-a-b-c- 4"),
+            ("('x y z'):gsub('%s', '', 1)", "xy z 1"),
+            ("('abc'):gsub('.', function(c) if c ~= 'b' then return c:byte() .. ' ' end end)", "97 b99  3"),
+            ("pcall(function() return string.rep() end)", "false user_script:6: bad argument #1 to 'rep' (string expected, got no value)"),
+            ("pcall(function() return ('x'):find('%') end)", "false user_script:6: malformed pattern (ends with '%')"),
+            ("pcall(function() return ('x'):find('(') end)", "false user_script:6: unfinished capture"),
+            // table
+            ("(function() local t = {1, 2} table.insert(t, 3) table.insert(t, 1, 0) return table.concat(t, ',') end)()", "0,1,2,3"),
+            ("(function() local t = {1, 2, 3} return table.remove(t, 1), table.remove(t), table.concat(t), table.remove({}) end)()", "1 3 2"),
+            ("table.concat({1, 2, 3}, ', ', 2, 3), table.concat({}), table.concat({'a', 'b'}, '', 3)", "2, 3  "),
+            ("(function() local t = {'pear', 'apple', 'fig'} table.sort(t) return table.concat(t, ' ') end)()", "apple fig pear"),
+            ("(function() local t = {3, 1, 2} table.sort(t, function(a, b) return a > b end) return table.concat(t) end)()", "321"),
+            ("table.maxn({1, [10] = 2, [2.5] = 3}), table.getn({1, 2}), #{1, 2, 3}", "10 2 3"),
+            // math
+            ("math.floor(3.7), math.floor(-3.7), math.ceil(3.2), math.ceil(-3.2), math.abs(-2)", "3 -4 4 -3 2"),
+            ("math.sqrt(16), math.max(1, 5, 3), math.min(4, -1), math.fmod(7, 3), math.fmod(-7, 3)", "4 5 -1 1 -1"),
+            ("math.pow(2, 10), math.exp(0), math.log(1), math.log10(1000), math.modf(3.75)", "1024 1 0 3 3 0.75"),
+            ("math.modf(-3.75)", "-3 -0.75"),
+            ("math.huge, -math.huge, math.pi, math.ldexp(0.5, 4), math.frexp(8)", "inf -inf 3.1415926535898 8 0.5 4"),
+            ("math.deg(math.pi), math.rad(180), math.sin(0), math.cos(0), math.tan(0), math.atan2(1, 1) * 4", "180 3.1415926535898 0 1 0 3.1415926535898"),
+            ("math.asin(1) * 2, math.acos(1), math.atan(0), math.sinh(0), math.cosh(0), math.tanh(0)", "3.1415926535898 0 0 0 1 0"),
+            ("(function() math.randomseed(7) local r = math.random(3, 5) return r >= 3 and r <= 5, r == math.floor(r) end)()", "true true"),
+            ("(function() local r = math.random() return r >= 0 and r < 1 end)(), pcall(function() return math.random(0) end)", "true false user_script:6: bad argument #1 to 'random' (interval is empty)"),
+            // bit
+            ("bit.bnot(0), bit.bxor(5, 3), bit.rshift(-1, 28), bit.arshift(-256, 4), bit.rol(1, 33), bit.ror(1, 1)", "-1 6 15 -16 2 -2147483648"),
+            ("bit.bswap(0x12345678), bit.tohex(255, -4), bit.tohex(0x12345678, 2), bit.tobit(0xffffffff), bit.band(-1, 2^32 + 3)", "2018915346 00FF 78 -1 3"),
+            // cjson
+            ("cjson.encode({a = 1}), cjson.encode('x\"y'), cjson.encode(1.5), cjson.encode({}), cjson.encode(cjson.null)", "{\"a\":1} \"x\\\"y\" 1.5 {} null"),
+            ("cjson.encode({1, 2, nil, 4}), cjson.encode({true, false, {}}), cjson.encode('a/b\\n')", "[1,2,null,4] [true,false,{}] \"a\\/b\\n\""),
+            ("(function() local t = cjson.decode('[1,\"a\",true,null,{\"n\":1e2}]') return t[1], t[2], t[3], t[4] == cjson.null, t[5].n end)()", "1 a true true 100"),
+            ("cjson.decode('\"\\\\u00e9\"') == '\\195\\169', pcall(function() return cjson.decode('invalid') end)", "true false user_script:6: Expected value but found invalid token at character 1"),
+            // os
+            ("type(os.clock()), os.clock() >= 0", "number true"),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(show(expr), expected, "{}", expr);
+        }
+    }
+
+    /// Libraries and functions of Lua 5.1 or Redis that are not offered, as
+    /// listed in the README.
+    #[test]
+    fn test_unsupported_libraries() {
+        for name in [
+            "struct",
+            "cmsgpack",
+            "coroutine",
+            "loadstring",
+            "load",
+            "dofile",
+            "getfenv",
+            "setfenv",
+            "collectgarbage",
+            "gcinfo",
+            "newproxy",
+            "print",
+            "require",
+            "module",
+            "io",
+            "debug",
+        ] {
+            assert_eq!(show(&format!("rawget(_G, '{}')", name)), "nil", "{}", name);
+        }
+        assert_eq!(
+            show("string.dump, string.gfind, math.mod, table.foreach, table.foreachi, table.setn"),
+            "nil nil nil nil nil nil"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            run_err("local x = nil\nreturn x.y"),
+            "user_script:2: attempt to index a nil value"
+        );
+        assert_eq!(
+            run_err("local t = {}\nreturn t.a.b"),
+            "user_script:2: attempt to index field 'a' (a nil value)"
+        );
+        assert_eq!(
+            run_err("return 1 + {}"),
+            "user_script:1: attempt to perform arithmetic on a table value"
+        );
+        assert_eq!(
+            run_err("return {} < {}"),
+            "user_script:1: attempt to compare two table values"
+        );
+        assert_eq!(run_err("error('boom')"), "user_script:1: boom");
+        assert_eq!(
+            run_err("x = 1"),
+            "user_script:1: Attempt to modify a readonly table"
+        );
+        assert_eq!(
+            run_err("return undefined_name"),
+            "user_script:1: Script attempted to access nonexistent global variable 'undefined_name'"
+        );
+        assert_eq!(
+            run_err("string.len = nil"),
+            "user_script:1: Attempt to modify a readonly table"
+        );
+        assert_eq!(
+            run_err("local function f() return f() + 1 end\nreturn f()"),
+            "user_script:1: stack overflow"
+        );
+        // Deep nesting fails cleanly, short of the native stack limit
+        let nested = format!("return {}1{}", "{".repeat(190), "}".repeat(190));
+        let mut host = TestHost {
+            calls: 0,
+            interrupt_after: None,
+        };
+        assert!(!matches!(
+            run_with(&mut host, &nested),
+            Err(LuaError::Interrupted)
+        ));
+        assert!(run_err("return string.rep('x', 2^40)").contains("too large"));
+        assert!(run_err("local x = ").contains("unexpected symbol near '<eof>'"));
+
+        assert_eq!(run("return pcall(error, {code = 1})")[0], "false");
+        assert_eq!(
+            run("local ok, e = pcall(function() error('inner', 0) end)\nreturn ok, e"),
+            ["false", "b\"inner\""]
+        );
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut host = TestHost {
+            calls: 0,
+            interrupt_after: Some(0),
+        };
+        // Not even pcall stops an interruption
+        let result = run_with(&mut host, "while true do pcall(function() end) end");
+        assert!(matches!(result, Err(LuaError::Interrupted)));
+    }
+}
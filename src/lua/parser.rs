@@ -0,0 +1,768 @@
+//! Recursive descent parser from Lua source to the tree of [`ast`].
+//!
+//! Variables are resolved while parsing, as the reference compiler does:
+//! every local declaration gets its own slot in the function's frame,
+//! variables of enclosing functions become upvalues, and everything else is
+//! a global.
+//!
+//! [`ast`]: super::ast
+
+use super::ast::{BinOp, Block, Expr, Field, FunctionProto, Stat, StatKind, UnOp, UpvalueSource};
+use super::lexer::{Lexer, Token};
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// Nesting limit for statements and expressions, which both the parser and
+/// the interpreter recurse through.
+const MAX_NESTING: usize = 200;
+
+/// Priority of unary operators: binds tighter than all binary operators but
+/// `^`.
+const UNARY_PRIORITY: u8 = 8;
+
+/// Compile a chunk into the prototype of its main function. Errors carry the
+/// line, like `user_script:1: '=' expected near 'x'`.
+pub fn compile(source: &[u8]) -> Result<Arc<FunctionProto>, String> {
+    let mut parser = Parser {
+        lexer: Lexer::new(source),
+        token: Token::Eof,
+        line: 1,
+        ahead: None,
+        functions: vec![FunctionState {
+            is_vararg: true,
+            ..Default::default()
+        }],
+        depth: 0,
+    };
+    parser.advance()?;
+    let body = parser.statements()?;
+    if parser.token != Token::Eof {
+        return Err(parser.error("'<eof>' expected"));
+    }
+    let state = parser.functions.pop().expect("main function");
+    Ok(Arc::new(state.finish(0, body)))
+}
+
+/// Variables of the function being parsed.
+#[derive(Default)]
+struct FunctionState {
+    /// Locals in scope, innermost last.
+    actives: Vec<(String, usize)>,
+    slots: usize,
+    captured: Vec<bool>,
+    upvalues: Vec<(String, UpvalueSource)>,
+    is_vararg: bool,
+    /// Number of enclosing loops, for `break`.
+    loops: usize,
+}
+
+impl FunctionState {
+    fn finish(self, params: usize, body: Block) -> FunctionProto {
+        FunctionProto {
+            params,
+            is_vararg: self.is_vararg,
+            slots: self.slots,
+            captured: self.captured,
+            upvalues: self
+                .upvalues
+                .into_iter()
+                .map(|(_, source)| source)
+                .collect(),
+            body,
+        }
+    }
+}
+
+enum Variable {
+    Local(usize),
+    Upvalue(usize),
+}
+
+enum Binary {
+    And,
+    Or,
+    Op(BinOp),
+}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    token: Token,
+    /// Line of the current token.
+    line: u32,
+    /// The token after the current one, when it was looked at.
+    ahead: Option<(Token, u32)>,
+    functions: Vec<FunctionState>,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    /// Move to the next token, returning the current one.
+    fn advance(&mut self) -> Result<Token, String> {
+        let (token, line) = match self.ahead.take() {
+            Some(next) => next,
+            None => self.next_token()?,
+        };
+        self.line = line;
+        Ok(std::mem::replace(&mut self.token, token))
+    }
+
+    fn peek(&mut self) -> Result<&Token, String> {
+        if self.ahead.is_none() {
+            self.ahead = Some(self.next_token()?);
+        }
+        Ok(&self.ahead.as_ref().expect("token was read").0)
+    }
+
+    fn next_token(&mut self) -> Result<(Token, u32), String> {
+        self.lexer
+            .next_token()
+            .map_err(|e| format!("user_script:{}: {}", self.lexer.line, e))
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!(
+            "user_script:{}: {} near '{}'",
+            self.line,
+            message,
+            self.token.describe()
+        )
+    }
+
+    /// Consume `token` if it is the current one.
+    fn test(&mut self, token: &Token) -> Result<bool, String> {
+        if self.token == *token {
+            self.advance()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        if self.test(&token)? {
+            return Ok(());
+        }
+        Err(self.error(&format!("'{}' expected", token.describe())))
+    }
+
+    /// Expect the token closing a construct opened by `opener` on `line`.
+    fn expect_match(&mut self, token: Token, opener: Token, line: u32) -> Result<(), String> {
+        if self.test(&token)? {
+            return Ok(());
+        }
+        if line == self.line {
+            return Err(self.error(&format!("'{}' expected", token.describe())));
+        }
+        Err(self.error(&format!(
+            "'{}' expected (to close '{}' at line {})",
+            token.describe(),
+            opener.describe(),
+            line
+        )))
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match &self.token {
+            Token::Name(_) => match self.advance()? {
+                Token::Name(name) => Ok(name),
+                _ => unreachable!("current token is a name"),
+            },
+            _ => Err(self.error("<name> expected")),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(self.error("chunk has too many syntax levels"));
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn function(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("inside a function")
+    }
+
+    fn declare(&mut self, name: String) -> usize {
+        let function = self.function();
+        let slot = function.slots;
+        function.slots += 1;
+        function.captured.push(false);
+        function.actives.push((name, slot));
+        slot
+    }
+
+    fn open_scope(&mut self) -> usize {
+        self.function().actives.len()
+    }
+
+    fn close_scope(&mut self, mark: usize) {
+        self.function().actives.truncate(mark);
+    }
+
+    fn resolve(&mut self, name: String) -> Expr {
+        match self.resolve_in(self.functions.len() - 1, &name) {
+            Some(Variable::Local(slot)) => Expr::Local(slot),
+            Some(Variable::Upvalue(i)) => Expr::Upvalue(i),
+            None => Expr::Global(Bytes::from(name)),
+        }
+    }
+
+    fn resolve_in(&mut self, level: usize, name: &str) -> Option<Variable> {
+        let function = &self.functions[level];
+        if let Some((_, slot)) = function.actives.iter().rev().find(|(n, _)| n == name) {
+            return Some(Variable::Local(*slot));
+        }
+        if let Some(i) = function.upvalues.iter().position(|(n, _)| n == name) {
+            return Some(Variable::Upvalue(i));
+        }
+        if level == 0 {
+            return None;
+        }
+        let source = match self.resolve_in(level - 1, name)? {
+            Variable::Local(slot) => {
+                self.functions[level - 1].captured[slot] = true;
+                UpvalueSource::Local(slot)
+            }
+            Variable::Upvalue(i) => UpvalueSource::Upvalue(i),
+        };
+        let upvalues = &mut self.functions[level].upvalues;
+        upvalues.push((name.to_string(), source));
+        Some(Variable::Upvalue(upvalues.len() - 1))
+    }
+
+    fn block_follows(&self) -> bool {
+        matches!(
+            self.token,
+            Token::Else | Token::ElseIf | Token::End | Token::Until | Token::Eof
+        )
+    }
+
+    /// A block with its own scope.
+    fn block(&mut self) -> Result<Block, String> {
+        let mark = self.open_scope();
+        let block = self.statements()?;
+        self.close_scope(mark);
+        Ok(block)
+    }
+
+    fn loop_block(&mut self) -> Result<Block, String> {
+        self.function().loops += 1;
+        let block = self.block();
+        self.function().loops -= 1;
+        block
+    }
+
+    /// Statements up to the end of the block, in the current scope.
+    fn statements(&mut self) -> Result<Block, String> {
+        let mut block = Vec::new();
+        while !self.block_follows() {
+            let line = self.line;
+            // `return` and `break` must end their block
+            let last = matches!(self.token, Token::Return | Token::Break);
+            let kind = self.statement()?;
+            block.push(Stat { line, kind });
+            self.test(&Token::Semicolon)?;
+            if last {
+                break;
+            }
+        }
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<StatKind, String> {
+        self.enter()?;
+        let line = self.line;
+        let stat = match self.token {
+            Token::If => self.if_statement(line),
+            Token::While => {
+                self.advance()?;
+                let condition = self.expr()?;
+                self.expect(Token::Do)?;
+                let body = self.loop_block()?;
+                self.expect_match(Token::End, Token::While, line)?;
+                Ok(StatKind::While { condition, body })
+            }
+            Token::Do => {
+                self.advance()?;
+                let body = self.block()?;
+                self.expect_match(Token::End, Token::Do, line)?;
+                Ok(StatKind::Do(body))
+            }
+            Token::For => self.for_statement(line),
+            Token::Repeat => {
+                self.advance()?;
+                // The condition sees the locals of the body
+                self.function().loops += 1;
+                let mark = self.open_scope();
+                let body = self.statements()?;
+                self.expect_match(Token::Until, Token::Repeat, line)?;
+                let condition = self.expr()?;
+                self.close_scope(mark);
+                self.function().loops -= 1;
+                Ok(StatKind::Repeat { body, condition })
+            }
+            Token::Function => {
+                self.advance()?;
+                let name = self.name()?;
+                let mut target = self.resolve(name);
+                while self.test(&Token::Dot)? {
+                    let key = Expr::String(Bytes::from(self.name()?));
+                    target = Expr::Index(Box::new(target), Box::new(key));
+                }
+                let is_method = self.test(&Token::Colon)?;
+                if is_method {
+                    let key = Expr::String(Bytes::from(self.name()?));
+                    target = Expr::Index(Box::new(target), Box::new(key));
+                }
+                let function = self.function_body(is_method, line)?;
+                Ok(StatKind::Assign {
+                    targets: vec![target],
+                    values: vec![Expr::Function(function)],
+                })
+            }
+            Token::Local => {
+                self.advance()?;
+                if self.test(&Token::Function)? {
+                    // Declared first so the function can call itself
+                    let name = self.name()?;
+                    let slot = self.declare(name);
+                    let function = self.function_body(false, line)?;
+                    Ok(StatKind::LocalFunction { slot, function })
+                } else {
+                    let mut names = vec![self.name()?];
+                    while self.test(&Token::Comma)? {
+                        names.push(self.name()?);
+                    }
+                    let values = if self.test(&Token::Assign)? {
+                        self.expr_list()?
+                    } else {
+                        Vec::new()
+                    };
+                    let slots = names.into_iter().map(|name| self.declare(name)).collect();
+                    Ok(StatKind::Local { slots, values })
+                }
+            }
+            Token::Return => {
+                self.advance()?;
+                let values = if self.block_follows() || self.token == Token::Semicolon {
+                    Vec::new()
+                } else {
+                    self.expr_list()?
+                };
+                Ok(StatKind::Return(values))
+            }
+            Token::Break => {
+                self.advance()?;
+                if self.function().loops == 0 {
+                    return Err(self.error("no loop to break"));
+                }
+                Ok(StatKind::Break)
+            }
+            _ => self.expr_statement(),
+        };
+        self.leave();
+        stat
+    }
+
+    fn if_statement(&mut self, line: u32) -> Result<StatKind, String> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        // Skip `if`, then each `elseif`
+        self.advance()?;
+        loop {
+            let condition = self.expr()?;
+            self.expect(Token::Then)?;
+            branches.push((condition, self.block()?));
+            match self.token {
+                Token::ElseIf => {
+                    self.advance()?;
+                }
+                Token::Else => {
+                    self.advance()?;
+                    otherwise = Some(self.block()?);
+                    self.expect_match(Token::End, Token::If, line)?;
+                    break;
+                }
+                _ => {
+                    self.expect_match(Token::End, Token::If, line)?;
+                    break;
+                }
+            }
+        }
+        Ok(StatKind::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn for_statement(&mut self, line: u32) -> Result<StatKind, String> {
+        self.advance()?;
+        let first = self.name()?;
+        let stat = match self.token {
+            Token::Assign => {
+                self.advance()?;
+                let start = self.expr()?;
+                self.expect(Token::Comma)?;
+                let limit = self.expr()?;
+                let step = if self.test(&Token::Comma)? {
+                    Some(self.expr()?)
+                } else {
+                    None
+                };
+                self.expect(Token::Do)?;
+                let mark = self.open_scope();
+                let slot = self.declare(first);
+                let body = self.loop_block()?;
+                self.close_scope(mark);
+                StatKind::NumericFor {
+                    slot,
+                    start,
+                    limit,
+                    step,
+                    body,
+                }
+            }
+            Token::Comma | Token::In => {
+                let mut names = vec![first];
+                while self.test(&Token::Comma)? {
+                    names.push(self.name()?);
+                }
+                self.expect(Token::In)?;
+                let values = self.expr_list()?;
+                self.expect(Token::Do)?;
+                let mark = self.open_scope();
+                let slots = names.into_iter().map(|name| self.declare(name)).collect();
+                let body = self.loop_block()?;
+                self.close_scope(mark);
+                StatKind::GenericFor {
+                    slots,
+                    values,
+                    body,
+                }
+            }
+            _ => return Err(self.error("'=' or 'in' expected")),
+        };
+        self.expect_match(Token::End, Token::For, line)?;
+        Ok(stat)
+    }
+
+    fn function_body(&mut self, is_method: bool, line: u32) -> Result<Arc<FunctionProto>, String> {
+        self.functions.push(FunctionState::default());
+        let mut params = 0;
+        if is_method {
+            self.declare("self".to_string());
+            params += 1;
+        }
+        self.expect(Token::LParen)?;
+        if self.token != Token::RParen {
+            loop {
+                if self.test(&Token::Dots)? {
+                    self.function().is_vararg = true;
+                    break;
+                }
+                let name = self.name()?;
+                self.declare(name);
+                params += 1;
+                if !self.test(&Token::Comma)? {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::RParen)?;
+        let body = self.statements()?;
+        self.expect_match(Token::End, Token::Function, line)?;
+        let state = self.functions.pop().expect("function being parsed");
+        Ok(Arc::new(state.finish(params, body)))
+    }
+
+    fn expr_statement(&mut self) -> Result<StatKind, String> {
+        let expr = self.suffixed_expr()?;
+        if !matches!(self.token, Token::Assign | Token::Comma) {
+            return match expr {
+                Expr::Call { .. } | Expr::Method { .. } => Ok(StatKind::Expr(expr)),
+                _ => Err(self.error("'=' expected")),
+            };
+        }
+        let mut targets = vec![expr];
+        while self.test(&Token::Comma)? {
+            targets.push(self.suffixed_expr()?);
+        }
+        if !targets.iter().all(|target| {
+            matches!(
+                target,
+                Expr::Local(_) | Expr::Upvalue(_) | Expr::Global(_) | Expr::Index(..)
+            )
+        }) {
+            return Err(self.error("syntax error"));
+        }
+        self.expect(Token::Assign)?;
+        let values = self.expr_list()?;
+        Ok(StatKind::Assign { targets, values })
+    }
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>, String> {
+        let mut exprs = vec![self.expr()?];
+        while self.test(&Token::Comma)? {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.subexpr(0)
+    }
+
+    /// An expression whose binary operators bind tighter than `limit`.
+    fn subexpr(&mut self, limit: u8) -> Result<Expr, String> {
+        self.enter()?;
+        let unary = match self.token {
+            Token::Not => Some(UnOp::Not),
+            Token::Minus => Some(UnOp::Neg),
+            Token::Hash => Some(UnOp::Len),
+            _ => None,
+        };
+        let mut left = match unary {
+            Some(op) => {
+                self.advance()?;
+                match (op, self.subexpr(UNARY_PRIORITY)?) {
+                    (UnOp::Neg, Expr::Number(n)) => Expr::Number(-n),
+                    (op, operand) => Expr::Unary(op, Box::new(operand)),
+                }
+            }
+            None => self.simple_expr()?,
+        };
+        while let Some((op, left_priority, right_priority)) = binary_operator(&self.token) {
+            if left_priority <= limit {
+                break;
+            }
+            self.advance()?;
+            let right = Box::new(self.subexpr(right_priority)?);
+            let operand = Box::new(left);
+            left = match op {
+                Binary::And => Expr::And(operand, right),
+                Binary::Or => Expr::Or(operand, right),
+                Binary::Op(op) => Expr::Binary(op, operand, right),
+            };
+        }
+        self.leave();
+        Ok(left)
+    }
+
+    fn simple_expr(&mut self) -> Result<Expr, String> {
+        let expr = match self.token {
+            Token::Number(n) => Expr::Number(n),
+            Token::String(_) => match self.advance()? {
+                Token::String(s) => return Ok(Expr::String(Bytes::from(s))),
+                _ => unreachable!("current token is a string"),
+            },
+            Token::Nil => Expr::Nil,
+            Token::True => Expr::True,
+            Token::False => Expr::False,
+            Token::Dots => {
+                if !self.function().is_vararg {
+                    return Err(self.error("cannot use '...' outside a vararg function"));
+                }
+                Expr::Vararg
+            }
+            Token::LBrace => return self.table(),
+            Token::Function => {
+                let line = self.line;
+                self.advance()?;
+                return Ok(Expr::Function(self.function_body(false, line)?));
+            }
+            _ => return self.suffixed_expr(),
+        };
+        self.advance()?;
+        Ok(expr)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, String> {
+        match self.token {
+            Token::Name(_) => {
+                let name = self.name()?;
+                Ok(self.resolve(name))
+            }
+            Token::LParen => {
+                let line = self.line;
+                self.advance()?;
+                let expr = self.expr()?;
+                self.expect_match(Token::RParen, Token::LParen, line)?;
+                Ok(Expr::Paren(Box::new(expr)))
+            }
+            _ => Err(self.error("unexpected symbol")),
+        }
+    }
+
+    fn suffixed_expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary_expr()?;
+        loop {
+            expr = match self.token {
+                Token::Dot => {
+                    self.advance()?;
+                    let key = Expr::String(Bytes::from(self.name()?));
+                    Expr::Index(Box::new(expr), Box::new(key))
+                }
+                Token::LBracket => {
+                    self.advance()?;
+                    let key = self.expr()?;
+                    self.expect(Token::RBracket)?;
+                    Expr::Index(Box::new(expr), Box::new(key))
+                }
+                Token::Colon => {
+                    self.advance()?;
+                    let name = Bytes::from(self.name()?);
+                    let line = self.line;
+                    let args = self.call_args()?;
+                    Expr::Method {
+                        object: Box::new(expr),
+                        name,
+                        args,
+                        line,
+                    }
+                }
+                Token::LParen | Token::String(_) | Token::LBrace => {
+                    let line = self.line;
+                    let args = self.call_args()?;
+                    Expr::Call {
+                        function: Box::new(expr),
+                        args,
+                        line,
+                    }
+                }
+                _ => return Ok(expr),
+            };
+        }
+    }
+
+    fn call_args(&mut self) -> Result<Vec<Expr>, String> {
+        match self.token {
+            Token::String(_) => Ok(vec![self.simple_expr()?]),
+            Token::LBrace => Ok(vec![self.table()?]),
+            Token::LParen => {
+                let line = self.line;
+                self.advance()?;
+                let args = if self.token == Token::RParen {
+                    Vec::new()
+                } else {
+                    self.expr_list()?
+                };
+                self.expect_match(Token::RParen, Token::LParen, line)?;
+                Ok(args)
+            }
+            _ => Err(self.error("function arguments expected")),
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, String> {
+        let line = self.line;
+        self.expect(Token::LBrace)?;
+        let mut fields = Vec::new();
+        while self.token != Token::RBrace {
+            let named = matches!(self.token, Token::Name(_)) && *self.peek()? == Token::Assign;
+            let field = match self.token {
+                Token::LBracket => {
+                    self.advance()?;
+                    let key = self.expr()?;
+                    self.expect(Token::RBracket)?;
+                    self.expect(Token::Assign)?;
+                    Field::Named(key, self.expr()?)
+                }
+                Token::Name(_) if named => {
+                    let key = Expr::String(Bytes::from(self.name()?));
+                    self.advance()?;
+                    Field::Named(key, self.expr()?)
+                }
+                _ => Field::Positional(self.expr()?),
+            };
+            fields.push(field);
+            if !self.test(&Token::Comma)? && !self.test(&Token::Semicolon)? {
+                break;
+            }
+        }
+        self.expect_match(Token::RBrace, Token::LBrace, line)?;
+        Ok(Expr::Table(fields))
+    }
+}
+
+/// A binary operator with its left and right priorities.
+fn binary_operator(token: &Token) -> Option<(Binary, u8, u8)> {
+    let op = match token {
+        Token::Or => return Some((Binary::Or, 1, 1)),
+        Token::And => return Some((Binary::And, 2, 2)),
+        Token::Lt => (BinOp::Lt, 3, 3),
+        Token::Gt => (BinOp::Gt, 3, 3),
+        Token::Le => (BinOp::Le, 3, 3),
+        Token::Ge => (BinOp::Ge, 3, 3),
+        Token::Ne => (BinOp::Ne, 3, 3),
+        Token::Eq => (BinOp::Eq, 3, 3),
+        // Right associative
+        Token::Concat => (BinOp::Concat, 5, 4),
+        Token::Plus => (BinOp::Add, 6, 6),
+        Token::Minus => (BinOp::Sub, 6, 6),
+        Token::Star => (BinOp::Mul, 7, 7),
+        Token::Slash => (BinOp::Div, 7, 7),
+        Token::Percent => (BinOp::Mod, 7, 7),
+        Token::Caret => (BinOp::Pow, 10, 9),
+        _ => return None,
+    };
+    Some((Binary::Op(op.0), op.1, op.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolves_variables() {
+        let main = compile(
+            b"local a = 1\n\
+              local function f(x) return a + x + g end\n\
+              do local a = 2 end\n\
+              return f(a)",
+        )
+        .unwrap();
+        assert_eq!(main.slots, 3);
+        // Only the outer `a` is used by `f`
+        assert_eq!(main.captured, vec![true, false, false]);
+        let StatKind::LocalFunction { function, .. } = &main.body[1].kind else {
+            panic!("Expected local function");
+        };
+        assert_eq!(function.params, 1);
+        assert!(matches!(function.upvalues[..], [UpvalueSource::Local(0)]));
+        assert_eq!(main.body[3].line, 4);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        for (source, error) in [
+            ("x = = 1", "user_script:1: unexpected symbol near '='"),
+            ("local x\nx", "user_script:2: '=' expected near '<eof>'"),
+            (
+                "if x then\n",
+                "user_script:2: 'end' expected (to close 'if' at line 1) near '<eof>'",
+            ),
+            ("break", "user_script:1: no loop to break near '<eof>'"),
+            ("return 1 x = 2", "user_script:1: '<eof>' expected near 'x'"),
+            (
+                "function f() return ... end",
+                "user_script:1: cannot use '...' outside a vararg function near '...'",
+            ),
+        ] {
+            assert_eq!(
+                compile(source.as_bytes()).err().as_deref(),
+                Some(error),
+                "{}",
+                source
+            );
+        }
+        let nested = format!("return {}1{}", "(".repeat(300), ")".repeat(300));
+        assert!(compile(nested.as_bytes())
+            .err()
+            .unwrap()
+            .contains("too many syntax levels"));
+    }
+}
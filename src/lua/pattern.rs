@@ -0,0 +1,423 @@
+//! Lua pattern matching, as used by `string.find`, `match`, `gmatch` and
+//! `gsub`; a port of the matcher of the reference implementation.
+
+use super::value::Value;
+
+const MAX_CAPTURES: usize = 32;
+
+/// Limit of nested pattern items being matched.
+const MAX_RECURSION: usize = 200;
+
+#[derive(Clone, Copy)]
+enum CaptureLen {
+    /// `()`: captures the position.
+    Position,
+    Unclosed,
+    Closed(usize),
+}
+
+pub(super) struct Matcher<'a> {
+    src: &'a [u8],
+    pattern: &'a [u8],
+    level: usize,
+    captures: [(usize, CaptureLen); MAX_CAPTURES],
+    depth: usize,
+}
+
+impl<'a> Matcher<'a> {
+    pub(super) fn new(src: &'a [u8], pattern: &'a [u8]) -> Self {
+        Matcher {
+            src,
+            pattern,
+            level: 0,
+            captures: [(0, CaptureLen::Unclosed); MAX_CAPTURES],
+            depth: 0,
+        }
+    }
+
+    /// Match the pattern from `p` against the subject at `s`, returning where
+    /// the match ends.
+    pub(super) fn find_at(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        self.level = 0;
+        self.depth = 0;
+        self.do_match(s, p)
+    }
+
+    /// The captures of the last match from `start` to `end`, or the whole
+    /// match if the pattern has none.
+    pub(super) fn captures(
+        &self,
+        start: usize,
+        end: usize,
+        whole: bool,
+    ) -> Result<Vec<Value>, String> {
+        let count = if self.level == 0 && whole {
+            1
+        } else {
+            self.level
+        };
+        (0..count).map(|i| self.capture(i, start, end)).collect()
+    }
+
+    pub(super) fn capture(&self, i: usize, start: usize, end: usize) -> Result<Value, String> {
+        if i >= self.level {
+            if i == 0 {
+                return Ok(Value::string(self.src[start..end].to_vec()));
+            }
+            return Err("invalid capture index".to_string());
+        }
+        let (init, len) = self.captures[i];
+        match len {
+            CaptureLen::Position => Ok(Value::Number((init + 1) as f64)),
+            CaptureLen::Unclosed => Err("unfinished capture".to_string()),
+            CaptureLen::Closed(len) => Ok(Value::string(self.src[init..init + len].to_vec())),
+        }
+    }
+
+    /// The text matched from `start` to `end`.
+    pub(super) fn matched(&self, start: usize, end: usize) -> &[u8] {
+        &self.src[start..end]
+    }
+
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        self.depth += 1;
+        if self.depth > MAX_RECURSION {
+            return Err("pattern too complex".to_string());
+        }
+        let result = loop {
+            let Some(&c) = self.pattern.get(p) else {
+                break Some(s);
+            };
+            match c {
+                b'(' => {
+                    break if self.pattern.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CaptureLen::Position)?
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLen::Unclosed)?
+                    };
+                }
+                b')' => break self.end_capture(s, p + 1)?,
+                b'$' if p + 1 == self.pattern.len() => {
+                    break (s == self.src.len()).then_some(s);
+                }
+                b'%' if self.pattern.get(p + 1) == Some(&b'b') => {
+                    match self.match_balance(s, p + 2)? {
+                        Some(end) => {
+                            s = end;
+                            p += 4;
+                        }
+                        None => break None,
+                    }
+                }
+                b'%' if self.pattern.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if self.pattern.get(p) != Some(&b'[') {
+                        return Err("missing '[' after '%f' in pattern".to_string());
+                    }
+                    let end = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if self.match_bracket_class(previous, p, end - 1)
+                        || !self.match_bracket_class(current, p, end - 1)
+                    {
+                        break None;
+                    }
+                    p = end;
+                }
+                b'%' if self.pattern.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                    match self.match_capture(s, self.pattern[p + 1])? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                        }
+                        None => break None,
+                    }
+                }
+                _ => {
+                    let end = self.class_end(p)?;
+                    let matches = s < self.src.len() && self.single_match(self.src[s], p, end);
+                    match self.pattern.get(end) {
+                        Some(b'?') => {
+                            if matches {
+                                if let Some(res) = self.do_match(s + 1, end + 1)? {
+                                    break Some(res);
+                                }
+                            }
+                            p = end + 1;
+                        }
+                        Some(b'*') => break self.max_expand(s, p, end)?,
+                        Some(b'+') => {
+                            break if matches {
+                                self.max_expand(s + 1, p, end)?
+                            } else {
+                                None
+                            };
+                        }
+                        Some(b'-') => break self.min_expand(s, p, end)?,
+                        _ => {
+                            if !matches {
+                                break None;
+                            }
+                            s += 1;
+                            p = end;
+                        }
+                    }
+                }
+            }
+        };
+        self.depth -= 1;
+        Ok(result)
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        len: CaptureLen,
+    ) -> Result<Option<usize>, String> {
+        if self.level >= MAX_CAPTURES {
+            return Err("too many captures".to_string());
+        }
+        self.captures[self.level] = (s, len);
+        self.level += 1;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let l = (0..self.level)
+            .rev()
+            .find(|&i| matches!(self.captures[i].1, CaptureLen::Unclosed))
+            .ok_or("invalid pattern capture")?;
+        self.captures[l].1 = CaptureLen::Closed(s - self.captures[l].0);
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[l].1 = CaptureLen::Unclosed;
+        }
+        Ok(result)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let (Some(&open), Some(&close)) = (self.pattern.get(p), self.pattern.get(p + 1)) else {
+            return Err("missing arguments to '%b'".to_string());
+        };
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    fn match_capture(&self, s: usize, digit: u8) -> Result<Option<usize>, String> {
+        let l = usize::from(digit).wrapping_sub(usize::from(b'1'));
+        let (init, len) = match self.captures.get(l) {
+            Some(&(init, CaptureLen::Closed(len))) if l < self.level => (init, len),
+            _ => return Err("invalid capture index".to_string()),
+        };
+        let captured = &self.src[init..init + len];
+        Ok(self.src[s..].starts_with(captured).then_some(s + len))
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        let mut i = 0;
+        while s + i < self.src.len() && self.single_match(self.src[s + i], p, end) {
+            i += 1;
+        }
+        loop {
+            if let Some(res) = self.do_match(s + i, end + 1)? {
+                return Ok(Some(res));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(res) = self.do_match(s, end + 1)? {
+                return Ok(Some(res));
+            }
+            if s < self.src.len() && self.single_match(self.src[s], p, end) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// End of the single-character class starting at `p`.
+    fn class_end(&self, p: usize) -> Result<usize, String> {
+        let pattern = self.pattern;
+        match pattern[p] {
+            b'%' => {
+                if p + 1 >= pattern.len() {
+                    return Err("malformed pattern (ends with '%')".to_string());
+                }
+                Ok(p + 2)
+            }
+            b'[' => {
+                let mut q = p + 1;
+                if pattern.get(q) == Some(&b'^') {
+                    q += 1;
+                }
+                // The first character of a set may be `]`
+                loop {
+                    let Some(&c) = pattern.get(q) else {
+                        return Err("malformed pattern (missing ']')".to_string());
+                    };
+                    q += 1;
+                    if c == b'%' && q < pattern.len() {
+                        q += 1;
+                    }
+                    if pattern.get(q) == Some(&b']') {
+                        return Ok(q + 1);
+                    }
+                }
+            }
+            _ => Ok(p + 1),
+        }
+    }
+
+    fn single_match(&self, c: u8, p: usize, end: usize) -> bool {
+        match self.pattern[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(c, p, end - 1),
+            literal => literal == c,
+        }
+    }
+
+    /// Whether `c` is in the set from `[` at `p` to `]` at `end`.
+    fn match_bracket_class(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let pattern = self.pattern;
+        let mut found = true;
+        if pattern[p + 1] == b'^' {
+            found = false;
+            p += 1;
+        }
+        p += 1;
+        while p < end {
+            if pattern[p] == b'%' {
+                p += 1;
+                if match_class(c, pattern[p]) {
+                    return found;
+                }
+            } else if pattern.get(p + 1) == Some(&b'-') && p + 2 < end {
+                if pattern[p] <= c && c <= pattern[p + 2] {
+                    return found;
+                }
+                p += 2;
+            } else if pattern[p] == c {
+                return found;
+            }
+            p += 1;
+        }
+        !found
+    }
+}
+
+fn match_class(c: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => matches!(c, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !matches
+    } else {
+        matches
+    }
+}
+
+/// Whether a pattern has no special characters, so `find` can search for
+/// it as is.
+pub(super) fn is_plain(pattern: &[u8]) -> bool {
+    !pattern.iter().any(|c| b"^$*+?.([%-".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(src: &str, pattern: &str) -> Option<(usize, usize, Vec<String>)> {
+        let mut matcher = Matcher::new(src.as_bytes(), pattern.as_bytes());
+        let (anchor, p) = match pattern.strip_prefix('^') {
+            Some(_) => (true, 1),
+            None => (false, 0),
+        };
+        for start in 0..=src.len() {
+            if let Some(end) = matcher.find_at(start, p).unwrap() {
+                let captures = matcher
+                    .captures(start, end, false)
+                    .unwrap()
+                    .iter()
+                    .map(|c| format!("{:?}", c))
+                    .collect();
+                return Some((start, end, captures));
+            }
+            if anchor {
+                break;
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(find("hello world", "o w"), Some((4, 7, vec![])));
+        assert_eq!(
+            find("key:123:x", "%a+:(%d+)"),
+            Some((0, 7, vec!["b\"123\"".to_string()]))
+        );
+        assert_eq!(
+            find("  trim  ", "^%s*(.-)%s*$").unwrap().2,
+            vec!["b\"trim\""]
+        );
+        assert_eq!(find("f(a(b)c)d", "%b()"), Some((1, 8, vec![])));
+        assert_eq!(
+            find("THE (quick) fox", "%f[%a]%a+%f[%A]"),
+            Some((0, 3, vec![]))
+        );
+        assert_eq!(
+            find("abcabc", "(a)(b)()"),
+            Some((0, 2, vec!["b\"a\"".into(), "b\"b\"".into(), "3".into()]))
+        );
+        assert_eq!(
+            find("xyyx", "(y)%1"),
+            Some((1, 3, vec!["b\"y\"".to_string()]))
+        );
+        assert_eq!(find("a-b", "[%-]"), Some((1, 2, vec![])));
+        assert_eq!(find("abc", "^b"), None);
+        assert_eq!(find("ab]", "[]]"), Some((2, 3, vec![])));
+
+        let mut matcher = Matcher::new(b"abc", b"[a");
+        assert!(matcher.find_at(0, 0).is_err());
+        let mut matcher = Matcher::new(b"abc", b"%");
+        assert!(matcher.find_at(0, 0).is_err());
+        assert!(is_plain(b"a:b"));
+        assert!(!is_plain(b"a.b"));
+    }
+}
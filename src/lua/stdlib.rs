@@ -0,0 +1,1081 @@
+//! Standard libraries available to scripts: the base functions, `string`,
+//! `table`, `math`, `bit`, `cjson` and `os.clock`. Functions that reach
+//! outside the sandbox (`io`, `load*`, `require`, most of `os`) are left
+//! out, as are coroutines, the `struct` and `cmsgpack` libraries and the
+//! settings of `cjson`; the README lists everything missing.
+
+use super::interp::{first, Interp};
+use super::pattern::{is_plain, Matcher};
+use super::value::{format_e, format_g, Function, TableRef, Value};
+use super::{json, LuaError};
+use bytes::Bytes;
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Size limit of strings built by `string.rep`, as for Redis values.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// Limit of values returned by `unpack`.
+const MAX_RESULTS: i64 = 8000;
+
+pub(super) fn install(interp: &mut Interp) {
+    let globals = interp.globals.clone();
+    install_base(&globals);
+    globals.set_str("_G", Value::Table(globals.clone()));
+    globals.set_str("_VERSION", Value::string("Lua 5.1"));
+
+    let string = string_lib();
+    globals.set_str("string", Value::Table(string.clone()));
+    interp.string_lib = string;
+    globals.set_str("table", Value::Table(table_lib()));
+    globals.set_str("math", Value::Table(math_lib()));
+    globals.set_str("bit", Value::Table(bit_lib()));
+    globals.set_str("cjson", Value::Table(cjson_lib()));
+
+    let os = TableRef::new();
+    register(&os, "clock", |interp, _| {
+        Ok(vec![Value::Number(interp.started.elapsed().as_secs_f64())])
+    });
+    globals.set_str("os", Value::Table(os));
+}
+
+fn register(
+    table: &TableRef,
+    name: &str,
+    f: impl Fn(&mut Interp<'_>, Vec<Value>) -> Result<Vec<Value>, LuaError> + 'static,
+) {
+    table.set_str(name, Function::native(f));
+}
+
+fn arg(args: &[Value], i: usize) -> Value {
+    args.get(i).cloned().unwrap_or_default()
+}
+
+fn type_of(args: &[Value], i: usize) -> &'static str {
+    args.get(i).map_or("no value", Value::type_name)
+}
+
+fn bad_argument(interp: &Interp, i: usize, function: &str, message: &str) -> LuaError {
+    interp.error(format!(
+        "bad argument #{} to '{}' ({})",
+        i + 1,
+        function,
+        message
+    ))
+}
+
+fn check_number(
+    interp: &Interp,
+    args: &[Value],
+    i: usize,
+    function: &str,
+) -> Result<f64, LuaError> {
+    args.get(i).and_then(Value::as_number).ok_or_else(|| {
+        let message = format!("number expected, got {}", type_of(args, i));
+        bad_argument(interp, i, function, &message)
+    })
+}
+
+fn check_int(interp: &Interp, args: &[Value], i: usize, function: &str) -> Result<i64, LuaError> {
+    check_number(interp, args, i, function).map(|n| n as i64)
+}
+
+fn opt_int(
+    interp: &Interp,
+    args: &[Value],
+    i: usize,
+    function: &str,
+    default: i64,
+) -> Result<i64, LuaError> {
+    match args.get(i) {
+        None | Some(Value::Nil) => Ok(default),
+        Some(_) => check_int(interp, args, i, function),
+    }
+}
+
+fn check_string(
+    interp: &Interp,
+    args: &[Value],
+    i: usize,
+    function: &str,
+) -> Result<Bytes, LuaError> {
+    args.get(i).and_then(Value::as_bytes).ok_or_else(|| {
+        let message = format!("string expected, got {}", type_of(args, i));
+        bad_argument(interp, i, function, &message)
+    })
+}
+
+fn check_table(
+    interp: &Interp,
+    args: &[Value],
+    i: usize,
+    function: &str,
+) -> Result<TableRef, LuaError> {
+    match args.get(i) {
+        Some(Value::Table(table)) => Ok(table.clone()),
+        _ => {
+            let message = format!("table expected, got {}", type_of(args, i));
+            Err(bad_argument(interp, i, function, &message))
+        }
+    }
+}
+
+fn check_any(interp: &Interp, args: &[Value], i: usize, function: &str) -> Result<Value, LuaError> {
+    args.get(i)
+        .cloned()
+        .ok_or_else(|| bad_argument(interp, i, function, "value expected"))
+}
+
+fn install_base(globals: &TableRef) {
+    register(globals, "assert", |interp, args| {
+        if arg(&args, 0).truthy() {
+            return Ok(args);
+        }
+        match args.get(1).and_then(Value::as_bytes) {
+            Some(message) => Err(interp.error(String::from_utf8_lossy(&message))),
+            None => Err(interp.error("assertion failed!")),
+        }
+    });
+    register(globals, "error", |interp, args| {
+        let level = opt_int(interp, &args, 1, "error", 1)?;
+        match arg(&args, 0) {
+            Value::String(message) if level > 0 => {
+                let mut s = format!("user_script:{}: ", interp.line()).into_bytes();
+                s.extend_from_slice(&message);
+                Err(LuaError::Error(Value::string(s)))
+            }
+            value => Err(LuaError::Error(value)),
+        }
+    });
+    register(globals, "pcall", |interp, mut args| {
+        let function = check_any(interp, &args, 0, "pcall")?;
+        args.remove(0);
+        match interp.call(&function, args) {
+            Ok(mut results) => {
+                results.insert(0, Value::Boolean(true));
+                Ok(results)
+            }
+            Err(LuaError::Error(error)) => Ok(vec![Value::Boolean(false), error]),
+            Err(LuaError::Interrupted) => Err(LuaError::Interrupted),
+        }
+    });
+    register(globals, "xpcall", |interp, args| {
+        let function = arg(&args, 0);
+        let handler = arg(&args, 1);
+        match interp.call(&function, Vec::new()) {
+            Ok(mut results) => {
+                results.insert(0, Value::Boolean(true));
+                Ok(results)
+            }
+            Err(LuaError::Error(error)) => {
+                let handled = first(interp.call(&handler, vec![error])?);
+                Ok(vec![Value::Boolean(false), handled])
+            }
+            Err(LuaError::Interrupted) => Err(LuaError::Interrupted),
+        }
+    });
+
+    let next = Function::native(|interp, args| {
+        let table = check_table(interp, &args, 0, "next")?;
+        match table.next(&arg(&args, 1)) {
+            Ok(Some((key, value))) => Ok(vec![key, value]),
+            Ok(None) => Ok(vec![Value::Nil]),
+            Err(e) => Err(interp.error(e)),
+        }
+    });
+    globals.set_str("next", next.clone());
+    register(globals, "pairs", move |interp, args| {
+        let table = check_table(interp, &args, 0, "pairs")?;
+        Ok(vec![next.clone(), Value::Table(table), Value::Nil])
+    });
+    let ipairs_step = Function::native(|interp, args| {
+        let table = check_table(interp, &args, 0, "ipairs")?;
+        let i = check_int(interp, &args, 1, "ipairs")? + 1;
+        match table.get(&Value::Number(i as f64)) {
+            Value::Nil => Ok(vec![Value::Nil]),
+            value => Ok(vec![Value::Number(i as f64), value]),
+        }
+    });
+    register(globals, "ipairs", move |interp, args| {
+        let table = check_table(interp, &args, 0, "ipairs")?;
+        Ok(vec![
+            ipairs_step.clone(),
+            Value::Table(table),
+            Value::Number(0.0),
+        ])
+    });
+
+    register(globals, "rawequal", |interp, args| {
+        let a = check_any(interp, &args, 0, "rawequal")?;
+        let b = check_any(interp, &args, 1, "rawequal")?;
+        Ok(vec![Value::Boolean(a.raw_equals(&b))])
+    });
+    register(globals, "rawget", |interp, args| {
+        let table = check_table(interp, &args, 0, "rawget")?;
+        Ok(vec![table.get(&arg(&args, 1))])
+    });
+    register(globals, "rawset", |interp, args| {
+        let table = check_table(interp, &args, 0, "rawset")?;
+        interp.raw_set(&table, arg(&args, 1), arg(&args, 2))?;
+        Ok(vec![Value::Table(table)])
+    });
+    register(globals, "select", |interp, args| {
+        if let Some(Value::String(s)) = args.first() {
+            if s.first() == Some(&b'#') {
+                return Ok(vec![Value::Number((args.len() - 1) as f64)]);
+            }
+        }
+        let n = check_int(interp, &args, 0, "select")?;
+        let top = args.len() as i64;
+        let n = if n < 0 { top + n } else { n.min(top) };
+        if n < 1 {
+            return Err(bad_argument(interp, 0, "select", "index out of range"));
+        }
+        Ok(args[n as usize..].to_vec())
+    });
+    register(globals, "setmetatable", |interp, args| {
+        let table = check_table(interp, &args, 0, "setmetatable")?;
+        let metatable = match arg(&args, 1) {
+            Value::Nil => None,
+            Value::Table(metatable) => Some(metatable),
+            _ => {
+                return Err(bad_argument(
+                    interp,
+                    1,
+                    "setmetatable",
+                    "nil or table expected",
+                ))
+            }
+        };
+        interp.check_writable(&table)?;
+        if let Some(current) = table.metatable() {
+            if !matches!(current.get_str("__metatable"), Value::Nil) {
+                return Err(interp.error("cannot change a protected metatable"));
+            }
+        }
+        table.set_metatable(metatable);
+        Ok(vec![Value::Table(table)])
+    });
+    register(globals, "getmetatable", |_, args| {
+        let Some(metatable) = (match arg(&args, 0) {
+            Value::Table(table) => table.metatable(),
+            _ => None,
+        }) else {
+            return Ok(vec![Value::Nil]);
+        };
+        match metatable.get_str("__metatable") {
+            Value::Nil => Ok(vec![Value::Table(metatable)]),
+            protected => Ok(vec![protected]),
+        }
+    });
+    register(globals, "tonumber", |interp, args| {
+        let value = check_any(interp, &args, 0, "tonumber")?;
+        let base = opt_int(interp, &args, 1, "tonumber", 10)?;
+        if base == 10 {
+            return Ok(vec![value.as_number().map_or(Value::Nil, Value::Number)]);
+        }
+        if !(2..=36).contains(&base) {
+            return Err(bad_argument(interp, 1, "tonumber", "base out of range"));
+        }
+        let digits = check_string(interp, &args, 0, "tonumber")?;
+        let digits = String::from_utf8_lossy(digits.trim_ascii()).into_owned();
+        Ok(vec![i64::from_str_radix(&digits, base as u32)
+            .map_or(Value::Nil, |n| Value::Number(n as f64))])
+    });
+    register(globals, "tostring", |interp, args| {
+        let value = check_any(interp, &args, 0, "tostring")?;
+        Ok(vec![Value::String(interp.tostring(&value)?)])
+    });
+    register(globals, "type", |interp, args| {
+        let value = check_any(interp, &args, 0, "type")?;
+        Ok(vec![Value::string(value.type_name())])
+    });
+    register(globals, "unpack", |interp, args| {
+        let table = check_table(interp, &args, 0, "unpack")?;
+        let start = opt_int(interp, &args, 1, "unpack", 1)?;
+        let end = opt_int(interp, &args, 2, "unpack", table.len() as i64)?;
+        if start > end {
+            return Ok(Vec::new());
+        }
+        if end.saturating_sub(start) >= MAX_RESULTS {
+            return Err(interp.error("too many results to unpack"));
+        }
+        Ok((start..=end)
+            .map(|i| table.get(&Value::Number(i as f64)))
+            .collect())
+    });
+}
+
+/// Convert a position that may count from the end (negative) to one
+/// counting from 1; 0 if it is before the start.
+fn relative_position(pos: i64, len: usize) -> i64 {
+    let pos = if pos < 0 { len as i64 + pos + 1 } else { pos };
+    pos.max(0)
+}
+
+fn string_lib() -> TableRef {
+    let string = TableRef::new();
+    register(&string, "len", |interp, args| {
+        let s = check_string(interp, &args, 0, "len")?;
+        Ok(vec![Value::Number(s.len() as f64)])
+    });
+    register(&string, "sub", |interp, args| {
+        let s = check_string(interp, &args, 0, "sub")?;
+        let start = relative_position(opt_int(interp, &args, 1, "sub", 1)?, s.len()).max(1);
+        let end =
+            relative_position(opt_int(interp, &args, 2, "sub", -1)?, s.len()).min(s.len() as i64);
+        if start > end {
+            return Ok(vec![Value::string("")]);
+        }
+        Ok(vec![Value::String(
+            s.slice(start as usize - 1..end as usize),
+        )])
+    });
+    register(&string, "upper", |interp, args| {
+        let s = check_string(interp, &args, 0, "upper")?;
+        Ok(vec![Value::string(s.to_ascii_uppercase())])
+    });
+    register(&string, "lower", |interp, args| {
+        let s = check_string(interp, &args, 0, "lower")?;
+        Ok(vec![Value::string(s.to_ascii_lowercase())])
+    });
+    register(&string, "rep", |interp, args| {
+        let s = check_string(interp, &args, 0, "rep")?;
+        let n = check_int(interp, &args, 1, "rep")?;
+        if n <= 0 || s.is_empty() {
+            return Ok(vec![Value::string("")]);
+        }
+        if (n as u64).saturating_mul(s.len() as u64) > MAX_STRING_LEN as u64 {
+            return Err(interp.error("resulting string too large"));
+        }
+        Ok(vec![Value::string(s.repeat(n as usize))])
+    });
+    register(&string, "reverse", |interp, args| {
+        let mut s = check_string(interp, &args, 0, "reverse")?.to_vec();
+        s.reverse();
+        Ok(vec![Value::string(s)])
+    });
+    register(&string, "byte", |interp, args| {
+        let s = check_string(interp, &args, 0, "byte")?;
+        let start = relative_position(opt_int(interp, &args, 1, "byte", 1)?, s.len());
+        let end = relative_position(opt_int(interp, &args, 2, "byte", start)?, s.len());
+        let (start, end) = (start.max(1), end.min(s.len() as i64));
+        if start > end {
+            return Ok(Vec::new());
+        }
+        Ok(s[start as usize - 1..end as usize]
+            .iter()
+            .map(|&b| Value::Number(f64::from(b)))
+            .collect())
+    });
+    register(&string, "char", |interp, args| {
+        let mut s = Vec::with_capacity(args.len());
+        for i in 0..args.len() {
+            let c = check_int(interp, &args, i, "char")?;
+            let c =
+                u8::try_from(c).map_err(|_| bad_argument(interp, i, "char", "invalid value"))?;
+            s.push(c);
+        }
+        Ok(vec![Value::string(s)])
+    });
+    register(&string, "format", format);
+    register(&string, "find", |interp, args| find(interp, &args, true));
+    register(&string, "match", |interp, args| find(interp, &args, false));
+    register(&string, "gmatch", |interp, args| {
+        let s = check_string(interp, &args, 0, "gmatch")?;
+        let pattern = check_string(interp, &args, 1, "gmatch")?;
+        let position = Rc::new(Cell::new(0));
+        Ok(vec![Function::native(move |interp, _| {
+            let mut matcher = Matcher::new(&s, &pattern);
+            for start in position.get()..=s.len() {
+                if let Some(end) = matcher.find_at(start, 0).map_err(|e| interp.error(e))? {
+                    // An empty match moves on by one character
+                    position.set(if end == start { end + 1 } else { end });
+                    return matcher
+                        .captures(start, end, true)
+                        .map_err(|e| interp.error(e));
+                }
+            }
+            position.set(s.len() + 1);
+            Ok(vec![Value::Nil])
+        })])
+    });
+    register(&string, "gsub", gsub);
+    string
+}
+
+/// `string.find` and `string.match`.
+fn find(interp: &mut Interp, args: &[Value], is_find: bool) -> Result<Vec<Value>, LuaError> {
+    let name = if is_find { "find" } else { "match" };
+    let s = check_string(interp, args, 0, name)?;
+    let pattern = check_string(interp, args, 1, name)?;
+    let init = relative_position(opt_int(interp, args, 2, name, 1)?, s.len());
+    let init = (init.max(1) as usize - 1).min(s.len());
+
+    if is_find && (arg(args, 3).truthy() || is_plain(&pattern)) {
+        let found = if pattern.is_empty() {
+            Some(0)
+        } else {
+            s[init..]
+                .windows(pattern.len())
+                .position(|window| window == &pattern[..])
+        };
+        return Ok(match found {
+            Some(i) => vec![
+                Value::Number((init + i + 1) as f64),
+                Value::Number((init + i + pattern.len()) as f64),
+            ],
+            None => vec![Value::Nil],
+        });
+    }
+
+    let anchored = pattern.first() == Some(&b'^');
+    let mut matcher = Matcher::new(&s, &pattern);
+    for start in init..=s.len() {
+        if let Some(end) = matcher
+            .find_at(start, usize::from(anchored))
+            .map_err(|e| interp.error(e))?
+        {
+            if !is_find {
+                return matcher
+                    .captures(start, end, true)
+                    .map_err(|e| interp.error(e));
+            }
+            let mut results = vec![Value::Number((start + 1) as f64), Value::Number(end as f64)];
+            results.extend(
+                matcher
+                    .captures(start, end, false)
+                    .map_err(|e| interp.error(e))?,
+            );
+            return Ok(results);
+        }
+        if anchored {
+            break;
+        }
+    }
+    Ok(vec![Value::Nil])
+}
+
+fn gsub(interp: &mut Interp, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let src = check_string(interp, &args, 0, "gsub")?;
+    let pattern = check_string(interp, &args, 1, "gsub")?;
+    let replacement = arg(&args, 2);
+    if !matches!(
+        replacement,
+        Value::Number(_) | Value::String(_) | Value::Table(_) | Value::Function(_)
+    ) {
+        return Err(bad_argument(
+            interp,
+            2,
+            "gsub",
+            "string/function/table expected",
+        ));
+    }
+    let max = opt_int(interp, &args, 3, "gsub", src.len() as i64 + 1)?;
+
+    let anchored = pattern.first() == Some(&b'^');
+    let mut matcher = Matcher::new(&src, &pattern);
+    let mut out = Vec::with_capacity(src.len());
+    let mut s = 0;
+    let mut count = 0;
+    while count < max {
+        let end = matcher
+            .find_at(s, usize::from(anchored))
+            .map_err(|e| interp.error(e))?;
+        if let Some(end) = end {
+            count += 1;
+            let value = match &replacement {
+                Value::Table(_) => {
+                    let key = matcher.capture(0, s, end).map_err(|e| interp.error(e))?;
+                    interp.index(&replacement, &key)?
+                }
+                Value::Function(_) => {
+                    let captures = matcher
+                        .captures(s, end, true)
+                        .map_err(|e| interp.error(e))?;
+                    first(interp.call(&replacement, captures)?)
+                }
+                _ => Value::string(
+                    expand_replacement(&matcher, &replacement, s, end)
+                        .map_err(|e| interp.error(e))?,
+                ),
+            };
+            match value {
+                // False or nil keeps the original text
+                Value::Nil | Value::Boolean(false) => out.extend_from_slice(&src[s..end]),
+                value => match value.as_bytes() {
+                    Some(bytes) => out.extend_from_slice(&bytes),
+                    None => {
+                        return Err(interp.error(format!(
+                            "invalid replacement value (a {})",
+                            value.type_name()
+                        )))
+                    }
+                },
+            }
+        }
+        match end {
+            Some(end) if end > s => s = end,
+            _ if s < src.len() => {
+                out.push(src[s]);
+                s += 1;
+            }
+            _ => break,
+        }
+        if anchored {
+            break;
+        }
+    }
+    out.extend_from_slice(&src[s..]);
+    Ok(vec![Value::string(out), Value::Number(count as f64)])
+}
+
+/// A replacement string with `%0`-`%9` substituted by captures.
+fn expand_replacement(
+    matcher: &Matcher,
+    replacement: &Value,
+    start: usize,
+    end: usize,
+) -> Result<Vec<u8>, String> {
+    let replacement = replacement.as_bytes().unwrap_or_default();
+    let mut out = Vec::with_capacity(replacement.len());
+    let mut chars = replacement.iter();
+    while let Some(&c) = chars.next() {
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(b'0') => out.extend_from_slice(matcher.matched(start, end)),
+            Some(&d) if d.is_ascii_digit() => {
+                let capture = matcher.capture(usize::from(d - b'1'), start, end)?;
+                out.extend_from_slice(&capture.as_bytes().unwrap_or_default());
+            }
+            Some(&other) => out.push(other),
+            None => {}
+        }
+    }
+    Ok(out)
+}
+
+/// `string.format`, with the conversions of C's `printf` that Lua allows.
+fn format(interp: &mut Interp, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let spec = check_string(interp, &args, 0, "format")?;
+    let mut out = Vec::with_capacity(spec.len());
+    let mut arg_index = 0;
+    let mut i = 0;
+    while i < spec.len() {
+        let c = spec[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        if spec.get(i) == Some(&b'%') {
+            out.push(b'%');
+            i += 1;
+            continue;
+        }
+
+        let flags_start = i;
+        while spec.get(i).is_some_and(|c| b"-+ #0".contains(c)) {
+            i += 1;
+        }
+        let flags = &spec[flags_start..i];
+        if flags.len() > 5 {
+            return Err(interp.error("invalid format (repeated flags)"));
+        }
+        let read_number = |i: &mut usize| -> Result<usize, LuaError> {
+            let start = *i;
+            let mut n = 0;
+            while let Some(d) = spec.get(*i).filter(|c| c.is_ascii_digit()) {
+                n = n * 10 + usize::from(d - b'0');
+                *i += 1;
+            }
+            if *i - start > 2 {
+                return Err(interp.error("invalid format (width or precision too long)"));
+            }
+            Ok(n)
+        };
+        let width = read_number(&mut i)?;
+        let precision = if spec.get(i) == Some(&b'.') {
+            i += 1;
+            Some(read_number(&mut i)?)
+        } else {
+            None
+        };
+        let Some(&conversion) = spec.get(i) else {
+            return Err(interp.error("invalid option '%' to 'format'"));
+        };
+        i += 1;
+        arg_index += 1;
+
+        let has = |flag: u8| flags.contains(&flag);
+        let formatted = match conversion {
+            b'c' => {
+                let c = check_number(interp, &args, arg_index, "format")? as i64 as u8;
+                pad(vec![c], width, has(b'-'))
+            }
+            b'd' | b'i' | b'u' => {
+                let n = check_number(interp, &args, arg_index, "format")? as i64;
+                let mut digits = n.unsigned_abs().to_string();
+                if let Some(precision) = precision {
+                    digits = format!("{:0>1$}", digits, precision);
+                }
+                let sign = sign(n < 0, has(b'+'), has(b' '));
+                pad_number(sign, "", digits, width, flags, precision.is_none())
+            }
+            b'o' | b'x' | b'X' => {
+                let n = check_number(interp, &args, arg_index, "format")? as i64 as u64;
+                let (mut digits, prefix) = match conversion {
+                    b'o' => (
+                        format!("{:o}", n),
+                        if has(b'#') && n != 0 { "0" } else { "" },
+                    ),
+                    b'x' => (
+                        format!("{:x}", n),
+                        if has(b'#') && n != 0 { "0x" } else { "" },
+                    ),
+                    _ => (
+                        format!("{:X}", n),
+                        if has(b'#') && n != 0 { "0X" } else { "" },
+                    ),
+                };
+                if let Some(precision) = precision {
+                    digits = format!("{:0>1$}", digits, precision);
+                }
+                pad_number("", prefix, digits, width, flags, precision.is_none())
+            }
+            b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let n = check_number(interp, &args, arg_index, "format")?;
+                let precision = precision.unwrap_or(6);
+                let upper = conversion.is_ascii_uppercase();
+                let body = match conversion.to_ascii_lowercase() {
+                    b'e' => format_e(n.abs(), precision, upper),
+                    b'f' if n.is_finite() => format!("{:.*}", precision, n.abs()),
+                    b'f' => format_g(n.abs(), precision, upper, false),
+                    _ => format_g(n.abs(), precision, upper, has(b'#')),
+                };
+                let sign = sign(n.is_sign_negative() && !n.is_nan(), has(b'+'), has(b' '));
+                pad_number(sign, "", body, width, flags, n.is_finite())
+            }
+            b'q' => {
+                let s = check_string(interp, &args, arg_index, "format")?;
+                let mut quoted = vec![b'"'];
+                for &c in s.iter() {
+                    match c {
+                        b'"' | b'\\' | b'\n' => quoted.extend_from_slice(&[b'\\', c]),
+                        b'\r' => quoted.extend_from_slice(b"\\r"),
+                        0 => quoted.extend_from_slice(b"\\000"),
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push(b'"');
+                quoted
+            }
+            b's' => {
+                let s = check_string(interp, &args, arg_index, "format")?;
+                let s = match precision {
+                    Some(precision) => s.slice(..precision.min(s.len())),
+                    None => s,
+                };
+                pad(s.to_vec(), width, has(b'-'))
+            }
+            other => {
+                return Err(interp.error(format!(
+                    "invalid option '%{}' to 'format'",
+                    String::from_utf8_lossy(&[other])
+                )))
+            }
+        };
+        out.extend_from_slice(&formatted);
+    }
+    Ok(vec![Value::string(out)])
+}
+
+fn sign(negative: bool, plus: bool, space: bool) -> &'static str {
+    match (negative, plus, space) {
+        (true, ..) => "-",
+        (false, true, _) => "+",
+        (false, false, true) => " ",
+        _ => "",
+    }
+}
+
+/// A number padded to `width`, with zeros between the sign and the digits
+/// for the `0` flag.
+fn pad_number(
+    sign: &str,
+    prefix: &str,
+    digits: String,
+    width: usize,
+    flags: &[u8],
+    zero_allowed: bool,
+) -> Vec<u8> {
+    let len = sign.len() + prefix.len() + digits.len();
+    if flags.contains(&b'0') && !flags.contains(&b'-') && zero_allowed && len < width {
+        let zeros = "0".repeat(width - len);
+        return format!("{}{}{}{}", sign, prefix, zeros, digits).into_bytes();
+    }
+    pad(
+        format!("{}{}{}", sign, prefix, digits).into_bytes(),
+        width,
+        flags.contains(&b'-'),
+    )
+}
+
+/// `s` padded with spaces to `width`, on the right for the `-` flag.
+fn pad(mut s: Vec<u8>, width: usize, left: bool) -> Vec<u8> {
+    if s.len() >= width {
+        return s;
+    }
+    let fill = vec![b' '; width - s.len()];
+    if left {
+        s.extend_from_slice(&fill);
+        s
+    } else {
+        let mut padded = fill;
+        padded.extend_from_slice(&s);
+        padded
+    }
+}
+
+fn table_lib() -> TableRef {
+    let table = TableRef::new();
+    register(&table, "insert", |interp, args| {
+        let t = check_table(interp, &args, 0, "insert")?;
+        let end = t.len() as i64 + 1;
+        let (pos, value) = match args.len() {
+            2 => (end, arg(&args, 1)),
+            3 => (check_int(interp, &args, 1, "insert")?, arg(&args, 2)),
+            _ => return Err(interp.error("wrong number of arguments to 'insert'")),
+        };
+        let mut i = end.max(pos);
+        while i > pos {
+            let previous = t.get(&Value::Number((i - 1) as f64));
+            interp.raw_set(&t, Value::Number(i as f64), previous)?;
+            i -= 1;
+        }
+        interp.raw_set(&t, Value::Number(pos as f64), value)?;
+        Ok(Vec::new())
+    });
+    register(&table, "remove", |interp, args| {
+        let t = check_table(interp, &args, 0, "remove")?;
+        let end = t.len() as i64;
+        let pos = opt_int(interp, &args, 1, "remove", end)?;
+        if end == 0 || pos < 1 || pos > end {
+            return Ok(Vec::new());
+        }
+        let removed = t.get(&Value::Number(pos as f64));
+        for i in pos..end {
+            let next = t.get(&Value::Number((i + 1) as f64));
+            interp.raw_set(&t, Value::Number(i as f64), next)?;
+        }
+        interp.raw_set(&t, Value::Number(end as f64), Value::Nil)?;
+        Ok(vec![removed])
+    });
+    register(&table, "concat", |interp, args| {
+        let t = check_table(interp, &args, 0, "concat")?;
+        let separator = match args.get(1) {
+            None | Some(Value::Nil) => Bytes::new(),
+            Some(_) => check_string(interp, &args, 1, "concat")?,
+        };
+        let start = opt_int(interp, &args, 2, "concat", 1)?;
+        let end = opt_int(interp, &args, 3, "concat", t.len() as i64)?;
+        let mut out = Vec::new();
+        for i in start..=end {
+            let Some(item) = t.get(&Value::Number(i as f64)).as_bytes() else {
+                return Err(interp.error(format!(
+                    "invalid value (at index {}) in table for 'concat'",
+                    i
+                )));
+            };
+            out.extend_from_slice(&item);
+            if i < end {
+                out.extend_from_slice(&separator);
+            }
+        }
+        Ok(vec![Value::string(out)])
+    });
+    register(&table, "sort", |interp, args| {
+        let t = check_table(interp, &args, 0, "sort")?;
+        let compare = match arg(&args, 1) {
+            Value::Nil => None,
+            f @ Value::Function(_) => Some(f),
+            _ => return Err(bad_argument(interp, 1, "sort", "function expected")),
+        };
+        interp.check_writable(&t)?;
+        let values = (1..=t.len()).map(|i| t.get_index(i)).collect();
+        let mut less = |a: &Value, b: &Value| match &compare {
+            Some(f) => Ok(first(interp.call(f, vec![a.clone(), b.clone()])?).truthy()),
+            None => interp.less_than(a, b),
+        };
+        for (i, value) in merge_sort(values, &mut less)?.into_iter().enumerate() {
+            t.set_index(i + 1, value);
+        }
+        Ok(Vec::new())
+    });
+    register(&table, "maxn", |interp, args| {
+        let t = check_table(interp, &args, 0, "maxn")?;
+        let mut max = 0.0_f64;
+        let mut key = Value::Nil;
+        while let Ok(Some((k, _))) = t.next(&key) {
+            if let Value::Number(n) = k {
+                max = max.max(n);
+            }
+            key = k;
+        }
+        Ok(vec![Value::Number(max)])
+    });
+    register(&table, "getn", |interp, args| {
+        let t = check_table(interp, &args, 0, "getn")?;
+        Ok(vec![Value::Number(t.len() as f64)])
+    });
+    table
+}
+
+/// A stable sort whose comparison may fail; unlike the standard library's,
+/// it tolerates inconsistent comparisons.
+fn merge_sort(
+    mut values: Vec<Value>,
+    less: &mut dyn FnMut(&Value, &Value) -> Result<bool, LuaError>,
+) -> Result<Vec<Value>, LuaError> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, less)?;
+    let right = merge_sort(right, less)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if less(r, l)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+type MathFn = fn(f64) -> f64;
+type MathFn2 = fn(f64, f64) -> f64;
+
+fn math_lib() -> TableRef {
+    let math = TableRef::new();
+    math.set_str("pi", Value::Number(std::f64::consts::PI));
+    math.set_str("huge", Value::Number(f64::INFINITY));
+    let unary: [(&str, MathFn); 18] = [
+        ("abs", f64::abs),
+        ("ceil", f64::ceil),
+        ("floor", f64::floor),
+        ("sqrt", f64::sqrt),
+        ("exp", f64::exp),
+        ("log", f64::ln),
+        ("log10", f64::log10),
+        ("sin", f64::sin),
+        ("cos", f64::cos),
+        ("tan", f64::tan),
+        ("asin", f64::asin),
+        ("acos", f64::acos),
+        ("atan", f64::atan),
+        ("sinh", f64::sinh),
+        ("cosh", f64::cosh),
+        ("tanh", f64::tanh),
+        ("deg", f64::to_degrees),
+        ("rad", f64::to_radians),
+    ];
+    for (name, f) in unary {
+        register(&math, name, move |interp, args| {
+            Ok(vec![Value::Number(f(check_number(
+                interp, &args, 0, name,
+            )?))])
+        });
+    }
+    let binary: [(&str, MathFn2); 4] = [
+        ("pow", f64::powf),
+        ("atan2", f64::atan2),
+        ("fmod", |x, y| x % y),
+        ("ldexp", |x, e| x * 2f64.powi(e as i32)),
+    ];
+    for (name, f) in binary {
+        register(&math, name, move |interp, args| {
+            let x = check_number(interp, &args, 0, name)?;
+            let y = check_number(interp, &args, 1, name)?;
+            Ok(vec![Value::Number(f(x, y))])
+        });
+    }
+    register(&math, "modf", |interp, args| {
+        let x = check_number(interp, &args, 0, "modf")?;
+        Ok(vec![Value::Number(x.trunc()), Value::Number(x.fract())])
+    });
+    register(&math, "frexp", |interp, args| {
+        let x = check_number(interp, &args, 0, "frexp")?;
+        if x == 0.0 || !x.is_finite() {
+            return Ok(vec![Value::Number(x), Value::Number(0.0)]);
+        }
+        let exponent = x.abs().log2().floor() + 1.0;
+        Ok(vec![
+            Value::Number(x / 2f64.powf(exponent)),
+            Value::Number(exponent),
+        ])
+    });
+    for (name, pick_max) in [("max", true), ("min", false)] {
+        register(&math, name, move |interp, args| {
+            let mut best = check_number(interp, &args, 0, name)?;
+            for i in 1..args.len() {
+                let n = check_number(interp, &args, i, name)?;
+                if (pick_max && n > best) || (!pick_max && n < best) {
+                    best = n;
+                }
+            }
+            Ok(vec![Value::Number(best)])
+        });
+    }
+    register(&math, "random", |interp, args| {
+        // The 48-bit generator of drand48, for sequences that only depend
+        // on the seed
+        interp.random = interp.random.wrapping_mul(0x5DEECE66D).wrapping_add(0xB) & ((1 << 48) - 1);
+        let r = (interp.random >> 17) as f64 / (1u64 << 31) as f64;
+        let (low, high) = match args.len() {
+            0 => return Ok(vec![Value::Number(r)]),
+            1 => (1.0, check_int(interp, &args, 0, "random")? as f64),
+            2 => (
+                check_int(interp, &args, 0, "random")? as f64,
+                check_int(interp, &args, 1, "random")? as f64,
+            ),
+            _ => return Err(interp.error("wrong number of arguments")),
+        };
+        if low > high {
+            return Err(bad_argument(
+                interp,
+                args.len() - 1,
+                "random",
+                "interval is empty",
+            ));
+        }
+        Ok(vec![Value::Number((r * (high - low + 1.0)).floor() + low)])
+    });
+    register(&math, "randomseed", |interp, args| {
+        let seed = check_int(interp, &args, 0, "randomseed")?;
+        interp.random = random_seed(seed);
+        Ok(Vec::new())
+    });
+    math
+}
+
+/// State of `math.random` after seeding it with `seed`, as `srand48` does.
+pub(super) fn random_seed(seed: i64) -> u64 {
+    (u64::from(seed as u32) << 16) | 0x330e
+}
+
+type BitFn = fn(i32, i32) -> i32;
+type ShiftFn = fn(i32, u32) -> i32;
+
+fn bit_lib() -> TableRef {
+    /// Numbers wrap to 32-bit integers, rounding fractions.
+    fn tobit(x: f64) -> i32 {
+        (x + 6_755_399_441_055_744.0).to_bits() as u32 as i32
+    }
+    fn check_bit(interp: &Interp, args: &[Value], i: usize, name: &str) -> Result<i32, LuaError> {
+        Ok(tobit(check_number(interp, args, i, name)?))
+    }
+    fn result(n: i32) -> Result<Vec<Value>, LuaError> {
+        Ok(vec![Value::Number(f64::from(n))])
+    }
+
+    let bit = TableRef::new();
+    register(&bit, "tobit", |interp, args| {
+        result(check_bit(interp, &args, 0, "tobit")?)
+    });
+    register(&bit, "bnot", |interp, args| {
+        result(!check_bit(interp, &args, 0, "bnot")?)
+    });
+    register(&bit, "bswap", |interp, args| {
+        result(check_bit(interp, &args, 0, "bswap")?.swap_bytes())
+    });
+    let folds: [(&str, BitFn); 3] = [
+        ("band", |a, b| a & b),
+        ("bor", |a, b| a | b),
+        ("bxor", |a, b| a ^ b),
+    ];
+    for (name, f) in folds {
+        register(&bit, name, move |interp, args| {
+            let mut acc = check_bit(interp, &args, 0, name)?;
+            for i in 1..args.len() {
+                acc = f(acc, check_bit(interp, &args, i, name)?);
+            }
+            result(acc)
+        });
+    }
+    let shifts: [(&str, ShiftFn); 5] = [
+        ("lshift", |x, n| ((x as u32) << n) as i32),
+        ("rshift", |x, n| ((x as u32) >> n) as i32),
+        ("arshift", |x, n| x >> n),
+        ("rol", |x, n| x.rotate_left(n)),
+        ("ror", |x, n| x.rotate_right(n)),
+    ];
+    for (name, f) in shifts {
+        register(&bit, name, move |interp, args| {
+            let x = check_bit(interp, &args, 0, name)?;
+            let n = check_bit(interp, &args, 1, name)? as u32 & 31;
+            result(f(x, n))
+        });
+    }
+    register(&bit, "tohex", |interp, args| {
+        let x = check_bit(interp, &args, 0, "tohex")? as u32;
+        let n = match args.get(1) {
+            None | Some(Value::Nil) => 8,
+            Some(_) => check_bit(interp, &args, 1, "tohex")?,
+        };
+        let digits = n.unsigned_abs().min(8) as usize;
+        let hex = if n < 0 {
+            format!("{:08X}", x)
+        } else {
+            format!("{:08x}", x)
+        };
+        Ok(vec![Value::string(hex[8 - digits..].to_string())])
+    });
+    bit
+}
+
+fn cjson_lib() -> TableRef {
+    let cjson = TableRef::new();
+    let null = TableRef::new();
+    cjson.set_str("null", Value::Table(null.clone()));
+    let encode_null = null.clone();
+    register(&cjson, "encode", move |interp, args| {
+        let value = check_any(interp, &args, 0, "encode")?;
+        json::encode(&value, &encode_null)
+            .map(|s| vec![Value::string(s)])
+            .map_err(|e| interp.error(e))
+    });
+    register(&cjson, "decode", move |interp, args| {
+        let s = check_string(interp, &args, 0, "decode")?;
+        json::decode(&s, &null)
+            .map(|value| vec![value])
+            .map_err(|e| interp.error(e))
+    });
+    cjson
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::value::format_number;
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(3.0), "3");
+        assert_eq!(relative_position(-1, 5), 5);
+        assert_eq!(relative_position(-10, 5), 0);
+        assert_eq!(pad_number("-", "", "5".to_string(), 4, b"0", true), b"-005");
+        assert_eq!(
+            pad_number("", "0x", "ff".to_string(), 6, b"-#", true),
+            b"0xff  "
+        );
+    }
+}
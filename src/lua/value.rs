@@ -0,0 +1,543 @@
+//! Lua values and tables.
+
+use super::ast::FunctionProto;
+use super::interp::Interp;
+use super::LuaError;
+use bytes::Bytes;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::Arc;
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Bytes),
+    Table(TableRef),
+    Function(Function),
+}
+
+impl Value {
+    pub fn string(s: impl Into<Bytes>) -> Value {
+        Value::String(s.into())
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+        }
+    }
+
+    /// Everything but nil and false is true.
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    /// The value as a number, converting strings that hold one.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::String(s) => str_to_number(s),
+            _ => None,
+        }
+    }
+
+    /// The value as a string, converting numbers.
+    pub fn as_bytes(&self) -> Option<Bytes> {
+        match self {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(Bytes::from(format_number(*n))),
+            _ => None,
+        }
+    }
+
+    /// Equality without metamethods.
+    pub fn raw_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => a.ptr_eq(b),
+            (Value::Function(a), Value::Function(b)) => a.addr() == b.addr(),
+            _ => false,
+        }
+    }
+
+    /// Address shown by `tostring` for tables and functions.
+    pub(super) fn addr(&self) -> usize {
+        match self {
+            Value::Table(t) => Rc::as_ptr(&t.0) as *const () as usize,
+            Value::Function(f) => f.addr(),
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", format_number(*n)),
+            Value::String(s) => write!(f, "{:?}", s),
+            other => write!(f, "{}: {:#x}", other.type_name(), other.addr()),
+        }
+    }
+}
+
+/// Parse a number the way Lua converts strings: decimal or hexadecimal,
+/// surrounded by optional whitespace.
+pub fn str_to_number(s: &[u8]) -> Option<f64> {
+    let s = s.trim_ascii();
+    let (negative, digits) = match s.first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    if let Some(hex) = digits
+        .strip_prefix(b"0x")
+        .or_else(|| digits.strip_prefix(b"0X"))
+    {
+        let value = u64::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()? as f64;
+        return Some(if negative { -value } else { value });
+    }
+    // Rust also parses words like "inf" and "nan", which Lua does not
+    if s.is_empty()
+        || !s
+            .iter()
+            .all(|c| c.is_ascii_digit() || matches!(c, b'.' | b'e' | b'E' | b'+' | b'-'))
+    {
+        return None;
+    }
+    std::str::from_utf8(s).ok()?.parse().ok()
+}
+
+/// Format a number the way Lua does (`%.14g`).
+pub fn format_number(n: f64) -> String {
+    format_g(n, 14, false, false)
+}
+
+/// C's `%g`: the shorter of `%e` and `%f` for `precision` significant
+/// digits.
+pub(super) fn format_g(n: f64, precision: usize, upper: bool, alternate: bool) -> String {
+    if !n.is_finite() {
+        return format_special(n, upper);
+    }
+    let precision = precision.max(1);
+    let exponent = if n == 0.0 {
+        0
+    } else {
+        // The exponent after rounding to the requested precision
+        let e = format!("{:.*e}", precision - 1, n);
+        e[e.find('e').unwrap_or(0) + 1..]
+            .parse::<i32>()
+            .unwrap_or(0)
+    };
+    let mut s = if exponent < -4 || exponent >= precision as i32 {
+        format_e(n, precision - 1, upper)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, n)
+    };
+    if !alternate {
+        // Strip trailing zeros of the fraction, keeping any exponent
+        let (mantissa, exp) = match s.find(['e', 'E']) {
+            Some(i) => (s[..i].to_string(), s[i..].to_string()),
+            None => (s.clone(), String::new()),
+        };
+        if mantissa.contains('.') {
+            let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+            s = format!("{}{}", mantissa, exp);
+        }
+    }
+    s
+}
+
+/// C's `%e`: one digit before the point and a signed exponent of at least
+/// two digits.
+pub(super) fn format_e(n: f64, precision: usize, upper: bool) -> String {
+    if !n.is_finite() {
+        return format_special(n, upper);
+    }
+    let s = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = s.split_once('e').unwrap_or((&s, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    let e = if upper { 'E' } else { 'e' };
+    format!("{}{}{}{:02}", mantissa, e, sign, exponent.abs())
+}
+
+fn format_special(n: f64, upper: bool) -> String {
+    let s = if n.is_nan() {
+        if n.is_sign_negative() {
+            "-nan"
+        } else {
+            "nan"
+        }
+    } else if n < 0.0 {
+        "-inf"
+    } else {
+        "inf"
+    };
+    if upper {
+        s.to_uppercase()
+    } else {
+        s.to_string()
+    }
+}
+
+/// Shared, mutable reference to a table.
+#[derive(Clone, Default)]
+pub struct TableRef(Rc<RefCell<Table>>);
+
+/// A table: an array part for the keys 1..n and a hash part that keeps the
+/// insertion order, so iteration is deterministic.
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+    /// Entries whose value was set to nil.
+    removed: usize,
+    metatable: Option<TableRef>,
+}
+
+impl TableRef {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A table holding `values` at the keys 1..n.
+    pub fn from_array(values: Vec<Value>) -> Self {
+        let table = TableRef::new();
+        table.borrow_mut().array = values;
+        table
+    }
+
+    pub fn ptr_eq(&self, other: &TableRef) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn borrow(&self) -> Ref<'_, Table> {
+        self.0.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, Table> {
+        self.0.borrow_mut()
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        self.borrow().get(key)
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::string(key.to_string()))
+    }
+
+    pub fn get_index(&self, i: usize) -> Value {
+        self.get(&Value::Number(i as f64))
+    }
+
+    /// Set a field; fails for nil and NaN keys.
+    pub fn set(&self, key: Value, value: Value) -> Result<(), &'static str> {
+        self.borrow_mut().set(key, value)
+    }
+
+    pub fn set_str(&self, key: &str, value: Value) {
+        // A string key is always valid
+        let _ = self.set(Value::string(key.to_string()), value);
+    }
+
+    pub fn set_index(&self, i: usize, value: Value) {
+        let _ = self.set(Value::Number(i as f64), value);
+    }
+
+    /// The length operator (`#`): a border of the array.
+    pub fn len(&self) -> usize {
+        self.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next(&Value::Nil).ok().flatten().is_none()
+    }
+
+    pub fn metatable(&self) -> Option<TableRef> {
+        self.borrow().metatable.clone()
+    }
+
+    pub fn set_metatable(&self, metatable: Option<TableRef>) {
+        self.borrow_mut().metatable = metatable;
+    }
+
+    /// The entry after `key` in iteration order, `Err` if `key` is not in
+    /// the table.
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, &'static str> {
+        self.borrow().next(key)
+    }
+}
+
+impl Table {
+    pub fn get(&self, key: &Value) -> Value {
+        if let Some(i) = array_index(key) {
+            if i <= self.array.len() {
+                return self.array[i - 1].clone();
+            }
+        }
+        match Key::new(key).and_then(|key| self.index.get(&key)) {
+            Some(&i) => self.entries[i].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        match &key {
+            Value::Nil => return Err("table index is nil"),
+            Value::Number(n) if n.is_nan() => return Err("table index is NaN"),
+            _ => {}
+        }
+        if let Some(i) = array_index(&key) {
+            if i <= self.array.len() {
+                self.array[i - 1] = value;
+                return Ok(());
+            }
+            if i == self.array.len() + 1 && !matches!(value, Value::Nil) {
+                self.remove_entry(&key);
+                self.array.push(value);
+                self.migrate();
+                return Ok(());
+            }
+        }
+
+        let key = Key::new(&key).expect("valid keys were checked");
+        match self.index.get(&key) {
+            Some(&i) => {
+                let entry = &mut self.entries[i].1;
+                match (matches!(entry, Value::Nil), matches!(value, Value::Nil)) {
+                    (false, true) => self.removed += 1,
+                    (true, false) => self.removed -= 1,
+                    _ => {}
+                }
+                *entry = value;
+            }
+            None if matches!(value, Value::Nil) => {}
+            None => {
+                if self.removed > 16 && self.removed * 2 > self.entries.len() {
+                    self.compact();
+                }
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key.0, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        let mut len = self.array.len();
+        while len > 0 && matches!(self.array[len - 1], Value::Nil) {
+            len -= 1;
+        }
+        len
+    }
+
+    fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, &'static str> {
+        let mut array_start = 0;
+        let mut entry_start = 0;
+        match key {
+            Value::Nil => {}
+            key => match array_index(key) {
+                Some(i) if i <= self.array.len() => array_start = i,
+                _ => {
+                    let i = Key::new(key)
+                        .and_then(|key| self.index.get(&key).copied())
+                        .ok_or("invalid key to 'next'")?;
+                    array_start = self.array.len();
+                    entry_start = i + 1;
+                }
+            },
+        }
+        for i in array_start..self.array.len() {
+            if !matches!(self.array[i], Value::Nil) {
+                return Ok(Some((Value::Number((i + 1) as f64), self.array[i].clone())));
+            }
+        }
+        Ok(self.entries[entry_start..]
+            .iter()
+            .find(|(_, value)| !matches!(value, Value::Nil))
+            .cloned())
+    }
+
+    /// Mark the hash entry of `key`, if any, as removed.
+    fn remove_entry(&mut self, key: &Value) {
+        if let Some(&i) = Key::new(key).and_then(|key| self.index.get(&key)) {
+            if !matches!(self.entries[i].1, Value::Nil) {
+                self.entries[i].1 = Value::Nil;
+                self.removed += 1;
+            }
+        }
+    }
+
+    /// Move the keys following the array part out of the hash part.
+    fn migrate(&mut self) {
+        loop {
+            let key = Value::Number((self.array.len() + 1) as f64);
+            let value = self.get_entry(&key);
+            if matches!(value, Value::Nil) {
+                return;
+            }
+            self.remove_entry(&key);
+            self.array.push(value);
+        }
+    }
+
+    fn get_entry(&self, key: &Value) -> Value {
+        match Key::new(key).and_then(|key| self.index.get(&key)) {
+            Some(&i) => self.entries[i].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    /// Drop removed entries from the hash part.
+    fn compact(&mut self) {
+        self.entries
+            .retain(|(_, value)| !matches!(value, Value::Nil));
+        self.index = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| (Key(key.clone()), i))
+            .collect();
+        self.removed = 0;
+        let len = self.len();
+        self.array.truncate(len);
+    }
+}
+
+/// The position in the array part for a key, if it is a positive integer.
+fn array_index(key: &Value) -> Option<usize> {
+    match key {
+        Value::Number(n) if *n >= 1.0 && n.fract() == 0.0 && *n <= u32::MAX as f64 => {
+            Some(*n as usize)
+        }
+        _ => None,
+    }
+}
+
+/// A hashable table key: never nil or NaN.
+#[derive(Clone)]
+struct Key(Value);
+
+impl Key {
+    fn new(value: &Value) -> Option<Key> {
+        match value {
+            Value::Nil => None,
+            Value::Number(n) if n.is_nan() => None,
+            // 0 and -0 are the same key
+            Value::Number(n) if *n == 0.0 => Some(Key(Value::Number(0.0))),
+            value => Some(Key(value.clone())),
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.raw_equals(&other.0)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            Value::Nil => 0u8.hash(state),
+            Value::Boolean(b) => (1u8, b).hash(state),
+            Value::Number(n) => (2u8, n.to_bits()).hash(state),
+            Value::String(s) => (3u8, s).hash(state),
+            other => (4u8, other.addr()).hash(state),
+        }
+    }
+}
+
+/// Signature of functions implemented in Rust.
+pub type NativeFn = dyn Fn(&mut Interp<'_>, Vec<Value>) -> Result<Vec<Value>, LuaError>;
+
+#[derive(Clone)]
+pub enum Function {
+    Lua(Rc<Closure>),
+    Native(Rc<NativeFn>),
+}
+
+impl Function {
+    pub fn native(
+        f: impl Fn(&mut Interp<'_>, Vec<Value>) -> Result<Vec<Value>, LuaError> + 'static,
+    ) -> Value {
+        Value::Function(Function::Native(Rc::new(f)))
+    }
+
+    fn addr(&self) -> usize {
+        match self {
+            Function::Lua(closure) => Rc::as_ptr(closure) as *const () as usize,
+            Function::Native(f) => Rc::as_ptr(f) as *const () as usize,
+        }
+    }
+}
+
+/// A Lua function with the variables it captured from enclosing functions.
+pub struct Closure {
+    pub(super) proto: Arc<FunctionProto>,
+    pub(super) upvalues: Vec<Rc<RefCell<Value>>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_parts() {
+        let table = TableRef::new();
+        table.set_index(2, Value::Number(2.0));
+        table.set_str("name", Value::Boolean(true));
+        assert_eq!(table.len(), 0);
+        // Filling the hole moves the following keys into the array part
+        table.set_index(1, Value::Number(1.0));
+        assert_eq!(table.len(), 2);
+        table.set_index(2, Value::Nil);
+        assert_eq!(table.len(), 1);
+        assert!(table.set(Value::Nil, Value::Nil).is_err());
+        assert!(table.set(Value::Number(f64::NAN), Value::Nil).is_err());
+
+        let mut keys = Vec::new();
+        let mut key = Value::Nil;
+        while let Some((k, _)) = table.next(&key).unwrap() {
+            keys.push(format!("{:?}", k));
+            key = k;
+        }
+        assert_eq!(keys, vec!["1", "b\"name\""]);
+        assert!(table.next(&Value::string("missing")).is_err());
+    }
+
+    #[test]
+    fn test_number_conversions() {
+        assert_eq!(str_to_number(b" 0x10 "), Some(16.0));
+        assert_eq!(str_to_number(b"-1.5e3"), Some(-1500.0));
+        assert_eq!(str_to_number(b"inf"), None);
+        assert_eq!(str_to_number(b""), None);
+        assert_eq!(format_number(1e15), "1e+15");
+        assert_eq!(format_number(100.0), "100");
+        assert_eq!(format_number(0.1), "0.1");
+        assert_eq!(format_number(-0.5), "-0.5");
+        assert_eq!(format_number(1e100), "1e+100");
+        assert_eq!(format_g(0.0001, 6, false, false), "0.0001");
+        assert_eq!(format_e(12345.678, 2, true), "1.23E+04");
+    }
+}
@@ -6,7 +6,7 @@ use coral_redis::{
     cli::Cli,
     config::{Config, StorageConfig},
     error::AppError,
-    server::Handler,
+    server::{Handler, ServerContext},
     storage::StorageFactory,
     telemetry::{init_telemetry_with_config, TelemetryConfig},
 };
@@ -42,15 +42,20 @@ async fn main() -> Result<(), AppError> {
     info!("Redis server listening on {}", bind_addr);
 
     let config = Arc::new(config);
+    let context = Arc::new(ServerContext::with_config(&config));
+    storage.set_expiry_listener(context.expiry_listener());
 
     loop {
         let (socket, addr) = listener.accept().await?;
         let storage_clone = Arc::clone(&storage);
         let config_clone = Arc::clone(&config);
+        let context_clone = Arc::clone(&context);
 
         tokio::spawn(async move {
             info!("New connection from {}", addr);
-            if let Err(e) = handle_connection(socket, storage_clone, config_clone).await {
+            if let Err(e) =
+                handle_connection(socket, storage_clone, config_clone, context_clone).await
+            {
                 error!("Error handling connection: {}", e);
             }
         });
@@ -83,8 +88,9 @@ async fn handle_connection(
     mut socket: TcpStream,
     storage: Arc<dyn coral_redis::StorageBackend>,
     config: Arc<Config>,
+    context: Arc<ServerContext>,
 ) -> Result<(), AppError> {
-    let mut handler = Handler::new_with_config(storage, config);
+    let mut handler = Handler::new_with_context(storage, config, context);
     handler.handle_stream(&mut socket).await?;
     Ok(())
 }
@@ -22,7 +22,7 @@ pub fn detect_format(buffer: &[u8]) -> Option<ProtocolFormat> {
         // RESP2 type bytes
         b'+' | b'-' | b':' | b'$' | b'*' |
         // RESP3 type bytes
-        b'_' | b'#' | b',' | b'~' | b'%' => Some(ProtocolFormat::Resp),
+        b'_' | b'#' | b',' | b'~' | b'%' | b'>' => Some(ProtocolFormat::Resp),
         // Everything else is inline
         _ => Some(ProtocolFormat::Inline),
     }
@@ -51,6 +51,7 @@ mod tests {
         assert_eq!(detect_format(b",3.14\r\n"), Some(ProtocolFormat::Resp));
         assert_eq!(detect_format(b"~2\r\n"), Some(ProtocolFormat::Resp));
         assert_eq!(detect_format(b"%2\r\n"), Some(ProtocolFormat::Resp));
+        assert_eq!(detect_format(b">3\r\n"), Some(ProtocolFormat::Resp));
     }
 
     #[test]
@@ -3,11 +3,14 @@
 //! Supports telnet-style space-separated commands like "PING" or "SET key value".
 
 use super::RespValue;
+use bytes::Bytes;
 use std::io;
 
 /// Parser for inline (telnet-style) Redis commands.
 ///
 /// Converts space-separated text commands into RESP Array format.
+/// Arguments are treated as raw bytes; quoted arguments may use `\xHH`
+/// escapes to carry arbitrary binary data.
 pub struct InlineParser;
 
 impl InlineParser {
@@ -22,18 +25,8 @@ impl InlineParser {
             None => return Ok(None), // Incomplete command
         };
 
-        let line_data = &buffer[0..end_pos];
-
-        // Convert to string without allocating a vec
-        let line = std::str::from_utf8(line_data).map_err(|_| {
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid UTF-8 in inline command",
-            )
-        })?;
-
         // Parse the command line
-        let parts = Self::parse_command_line(line)?;
+        let parts = Self::parse_command_line(&buffer[0..end_pos])?;
 
         if parts.is_empty() {
             return Ok(None); // Empty command
@@ -42,50 +35,64 @@ impl InlineParser {
         // Convert to RESP Array of BulkStrings
         let resp_array: Vec<RespValue> = parts
             .into_iter()
-            .map(|s| RespValue::BulkString(Some(s)))
+            .map(|s| RespValue::BulkString(Some(Bytes::from(s))))
             .collect();
 
         Ok(Some(RespValue::Array(Some(resp_array))))
     }
 
     /// Parse command line handling quoted strings.
-    fn parse_command_line(line: &str) -> Result<Vec<String>, io::Error> {
+    fn parse_command_line(line: &[u8]) -> Result<Vec<Vec<u8>>, io::Error> {
         let mut parts = Vec::new();
-        let mut current = String::new();
+        let mut current = Vec::new();
         let mut in_quotes = false;
-        let mut chars = line.chars().peekable();
+        let mut bytes = line.iter().copied().peekable();
 
-        while let Some(ch) = chars.next() {
-            match ch {
-                '"' if !in_quotes => {
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'"' if !in_quotes => {
                     in_quotes = true;
                 }
-                '"' if in_quotes => {
+                b'"' if in_quotes => {
                     in_quotes = false;
                 }
-                ' ' | '\t' if !in_quotes => {
+                b' ' | b'\t' if !in_quotes => {
                     if !current.is_empty() {
                         parts.push(std::mem::take(&mut current));
                     }
                 }
-                '\\' if in_quotes => {
+                b'\\' if in_quotes => {
                     // Handle escape sequences in quotes
-                    if let Some(next_ch) = chars.next() {
-                        match next_ch {
-                            'n' => current.push('\n'),
-                            'r' => current.push('\r'),
-                            't' => current.push('\t'),
-                            '"' => current.push('"'),
-                            '\\' => current.push('\\'),
+                    if let Some(next) = bytes.next() {
+                        match next {
+                            b'n' => current.push(b'\n'),
+                            b'r' => current.push(b'\r'),
+                            b't' => current.push(b'\t'),
+                            b'"' => current.push(b'"'),
+                            b'\\' => current.push(b'\\'),
+                            b'x' => {
+                                // \xHH hex escape for binary data
+                                let hi = bytes.next();
+                                let lo = bytes.next();
+                                match (hi.and_then(hex_value), lo.and_then(hex_value)) {
+                                    (Some(h), Some(l)) => current.push((h << 4) | l),
+                                    _ => {
+                                        current.push(b'\\');
+                                        current.push(b'x');
+                                        current.extend(hi);
+                                        current.extend(lo);
+                                    }
+                                }
+                            }
                             _ => {
-                                current.push('\\');
-                                current.push(next_ch);
+                                current.push(b'\\');
+                                current.push(next);
                             }
                         }
                     }
                 }
                 _ => {
-                    current.push(ch);
+                    current.push(byte);
                 }
             }
         }
@@ -117,6 +124,10 @@ impl InlineParser {
     }
 }
 
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -192,6 +203,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_binary_arguments() {
+        let result = InlineParser::parse(b"SET key \"\\x00\\xff\\x7f\" \xc3\x28\r\n")
+            .unwrap()
+            .unwrap();
+        match result {
+            RespValue::Array(Some(parts)) => {
+                assert_eq!(parts.len(), 4);
+                match (&parts[2], &parts[3]) {
+                    (RespValue::BulkString(Some(a)), RespValue::BulkString(Some(b))) => {
+                        assert_eq!(a.as_ref(), b"\x00\xff\x7f");
+                        assert_eq!(b.as_ref(), b"\xc3\x28");
+                    }
+                    _ => panic!("Expected BulkStrings"),
+                }
+            }
+            _ => panic!("Expected Array"),
+        }
+    }
+
     #[test]
     fn test_incomplete_command() {
         let result = InlineParser::parse(b"PING").unwrap();
@@ -9,16 +9,11 @@ pub use inline::InlineParser;
 pub use resp::*;
 
 /// Protocol version for RESP communication.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum ProtocolVersion {
     /// RESP2 - Original Redis protocol
+    #[default]
     Resp2,
     /// RESP3 - Enhanced protocol with additional types
     Resp3,
 }
-
-impl Default for ProtocolVersion {
-    fn default() -> Self {
-        Self::Resp2
-    }
-}
@@ -1,5 +1,5 @@
 use super::{detect_format, InlineParser, ProtocolFormat};
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use std::io;
 
 /// RESP (Redis Serialization Protocol) value types.
@@ -11,7 +11,7 @@ pub enum RespValue {
     SimpleString(String),          // +
     Error(String),                 // -
     Integer(i64),                  // :
-    BulkString(Option<String>),    // $ (None = null in RESP2)
+    BulkString(Option<Bytes>),     // $ (None = null in RESP2, binary-safe)
     Array(Option<Vec<RespValue>>), // * (None = null in RESP2)
 
     // RESP3 types
@@ -20,6 +20,7 @@ pub enum RespValue {
     Double(f64),                      // , (floating point)
     Set(Vec<RespValue>),              // ~ (unordered collection)
     Map(Vec<(RespValue, RespValue)>), // % (key-value pairs)
+    Push(Vec<RespValue>),             // > (out-of-band data, e.g. pub/sub messages)
 }
 
 impl RespValue {
@@ -30,7 +31,12 @@ impl RespValue {
             RespValue::SimpleString(s) => format!("+{}\r\n", s).into_bytes(),
             RespValue::Error(e) => format!("-{}\r\n", e).into_bytes(),
             RespValue::Integer(i) => format!(":{}\r\n", i).into_bytes(),
-            RespValue::BulkString(Some(s)) => format!("${}\r\n{}\r\n", s.len(), s).into_bytes(),
+            RespValue::BulkString(Some(s)) => {
+                let mut result = format!("${}\r\n", s.len()).into_bytes();
+                result.extend_from_slice(s);
+                result.extend_from_slice(b"\r\n");
+                result
+            }
             RespValue::BulkString(None) => b"$-1\r\n".to_vec(),
             RespValue::Array(Some(arr)) => {
                 let mut result = format!("*{}\r\n", arr.len()).into_bytes();
@@ -61,6 +67,13 @@ impl RespValue {
                 }
                 result
             }
+            RespValue::Push(items) => {
+                let mut result = format!(">{}\r\n", items.len()).into_bytes();
+                for item in items {
+                    result.extend_from_slice(&item.to_bytes());
+                }
+                result
+            }
         }
     }
 }
@@ -90,6 +103,11 @@ impl RespParser {
         self.buffer.extend_from_slice(data);
     }
 
+    /// Number of bytes received and not parsed yet.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
     /// Reset the parser buffer to recover from errors.
     /// Clears the buffer to allow processing of subsequent messages.
     pub fn reset(&mut self) {
@@ -120,46 +138,46 @@ impl RespParser {
                 }
             }
             Some(ProtocolFormat::Resp) => {
-                // RESP parsing (existing logic)
-                let original_len = self.buffer.len();
-                match self.parse_value() {
-                    Ok(Some(value)) => Ok(Some(value)),
-                    Ok(None) => {
-                        // Reset buffer if we couldn't parse
-                        self.buffer.truncate(original_len);
-                        Ok(None)
+                // Parse against a cursor so that nothing is consumed until a
+                // complete value is available (payloads may span many reads)
+                let mut pos = 0;
+                match self.parse_value(&mut pos)? {
+                    Some(value) => {
+                        self.buffer.advance(pos);
+                        Ok(Some(value))
                     }
-                    Err(e) => Err(e),
+                    None => Ok(None),
                 }
             }
             None => Ok(None), // Empty buffer
         }
     }
 
-    fn parse_value(&mut self) -> Result<Option<RespValue>, io::Error> {
-        if self.buffer.is_empty() {
+    fn parse_value(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        if *pos >= self.buffer.len() {
             return Ok(None);
         }
 
-        tracing::trace!("Parsing buffer: {} bytes", self.buffer.len());
+        tracing::trace!("Parsing buffer: {} bytes", self.buffer.len() - *pos);
 
-        let type_byte = self.buffer[0];
-        self.buffer.advance(1);
+        let type_byte = self.buffer[*pos];
+        *pos += 1;
 
         match type_byte {
             // RESP2 types
-            b'+' => self.parse_simple_string(),
-            b'-' => self.parse_error(),
-            b':' => self.parse_integer(),
-            b'$' => self.parse_bulk_string(),
-            b'*' => self.parse_array(),
+            b'+' => self.parse_simple_string(pos),
+            b'-' => self.parse_error(pos),
+            b':' => self.parse_integer(pos),
+            b'$' => self.parse_bulk_string(pos),
+            b'*' => self.parse_array(pos),
 
             // RESP3 types
-            b'_' => self.parse_null(),
-            b'#' => self.parse_boolean(),
-            b',' => self.parse_double(),
-            b'~' => self.parse_set(),
-            b'%' => self.parse_map(),
+            b'_' => self.parse_null(pos),
+            b'#' => self.parse_boolean(pos),
+            b',' => self.parse_double(pos),
+            b'~' => self.parse_set(pos),
+            b'%' => self.parse_map(pos),
+            b'>' => self.parse_push(pos),
 
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
@@ -168,24 +186,24 @@ impl RespParser {
         }
     }
 
-    fn parse_simple_string(&mut self) -> Result<Option<RespValue>, io::Error> {
-        if let Some(line) = self.read_line()? {
+    fn parse_simple_string(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        if let Some(line) = self.read_line(pos)? {
             Ok(Some(RespValue::SimpleString(line)))
         } else {
             Ok(None)
         }
     }
 
-    fn parse_error(&mut self) -> Result<Option<RespValue>, io::Error> {
-        if let Some(line) = self.read_line()? {
+    fn parse_error(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        if let Some(line) = self.read_line(pos)? {
             Ok(Some(RespValue::Error(line)))
         } else {
             Ok(None)
         }
     }
 
-    fn parse_integer(&mut self) -> Result<Option<RespValue>, io::Error> {
-        if let Some(line) = self.read_line()? {
+    fn parse_integer(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        if let Some(line) = self.read_line(pos)? {
             let num = line
                 .parse::<i64>()
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid integer"))?;
@@ -195,8 +213,8 @@ impl RespParser {
         }
     }
 
-    fn parse_bulk_string(&mut self) -> Result<Option<RespValue>, io::Error> {
-        if let Some(length_str) = self.read_line()? {
+    fn parse_bulk_string(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        if let Some(length_str) = self.read_line(pos)? {
             let length = length_str.parse::<i64>().map_err(|_| {
                 io::Error::new(io::ErrorKind::InvalidData, "Invalid bulk string length")
             })?;
@@ -213,27 +231,27 @@ impl RespParser {
             }
 
             let length = length as usize;
-            if self.buffer.len() < length + 2 {
+            if self.buffer.len() - *pos < length + 2 {
                 return Ok(None); // Not enough data
             }
 
-            let data = self.buffer.split_to(length);
-            let string = String::from_utf8(data.to_vec())
-                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid UTF-8"))?;
+            // Bulk strings are binary-safe, so the payload is kept as raw bytes
+            let data = Bytes::copy_from_slice(&self.buffer[*pos..*pos + length]);
+            *pos += length;
 
             // Skip \r\n
-            if self.buffer.len() >= 2 && &self.buffer[0..2] == b"\r\n" {
-                self.buffer.advance(2);
+            if &self.buffer[*pos..*pos + 2] == b"\r\n" {
+                *pos += 2;
             }
 
-            Ok(Some(RespValue::BulkString(Some(string))))
+            Ok(Some(RespValue::BulkString(Some(data))))
         } else {
             Ok(None)
         }
     }
 
-    fn parse_array(&mut self) -> Result<Option<RespValue>, io::Error> {
-        if let Some(length_str) = self.read_line()? {
+    fn parse_array(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        if let Some(length_str) = self.read_line(pos)? {
             let length = length_str
                 .parse::<i64>()
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid array length"))?;
@@ -251,7 +269,7 @@ impl RespParser {
 
             let mut elements = Vec::with_capacity(length as usize);
             for _ in 0..length {
-                if let Some(element) = self.parse_value()? {
+                if let Some(element) = self.parse_value(pos)? {
                     elements.push(element);
                 } else {
                     return Ok(None); // Not enough data
@@ -264,13 +282,12 @@ impl RespParser {
         }
     }
 
-    fn read_line(&mut self) -> Result<Option<String>, io::Error> {
-        if let Some(pos) = self.buffer.windows(2).position(|w| w == b"\r\n") {
-            let line_data = self.buffer.split_to(pos);
-            self.buffer.advance(2); // Skip \r\n
-
-            let line = String::from_utf8(line_data.to_vec())
+    fn read_line(&self, pos: &mut usize) -> Result<Option<String>, io::Error> {
+        let remaining = &self.buffer[*pos..];
+        if let Some(end) = remaining.windows(2).position(|w| w == b"\r\n") {
+            let line = String::from_utf8(remaining[..end].to_vec())
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid UTF-8"))?;
+            *pos += end + 2; // Skip \r\n
 
             Ok(Some(line))
         } else {
@@ -279,20 +296,20 @@ impl RespParser {
     }
 
     // RESP3 parsers
-    fn parse_null(&mut self) -> Result<Option<RespValue>, io::Error> {
+    fn parse_null(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
         // Null is just _\r\n
-        if self.buffer.len() >= 2 && &self.buffer[0..2] == b"\r\n" {
-            self.buffer.advance(2);
+        if self.buffer.len() - *pos >= 2 && &self.buffer[*pos..*pos + 2] == b"\r\n" {
+            *pos += 2;
             Ok(Some(RespValue::Null))
         } else {
             Ok(None)
         }
     }
 
-    fn parse_boolean(&mut self) -> Result<Option<RespValue>, io::Error> {
+    fn parse_boolean(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
         // Boolean is #t\r\n or #f\r\n
-        if self.buffer.len() >= 3 {
-            let value = match self.buffer[0] {
+        if self.buffer.len() - *pos >= 3 {
+            let value = match self.buffer[*pos] {
                 b't' => true,
                 b'f' => false,
                 c => {
@@ -303,8 +320,8 @@ impl RespParser {
                 }
             };
 
-            if &self.buffer[1..3] == b"\r\n" {
-                self.buffer.advance(3);
+            if &self.buffer[*pos + 1..*pos + 3] == b"\r\n" {
+                *pos += 3;
                 Ok(Some(RespValue::Boolean(value)))
             } else {
                 Ok(None)
@@ -314,8 +331,8 @@ impl RespParser {
         }
     }
 
-    fn parse_double(&mut self) -> Result<Option<RespValue>, io::Error> {
-        if let Some(line) = self.read_line()? {
+    fn parse_double(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        if let Some(line) = self.read_line(pos)? {
             let num = line
                 .parse::<f64>()
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid double"))?;
@@ -325,8 +342,8 @@ impl RespParser {
         }
     }
 
-    fn parse_set(&mut self) -> Result<Option<RespValue>, io::Error> {
-        if let Some(length_str) = self.read_line()? {
+    fn parse_set(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        if let Some(length_str) = self.read_line(pos)? {
             let length = length_str
                 .parse::<i64>()
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid set length"))?;
@@ -340,7 +357,7 @@ impl RespParser {
 
             let mut elements = Vec::with_capacity(length as usize);
             for _ in 0..length {
-                if let Some(element) = self.parse_value()? {
+                if let Some(element) = self.parse_value(pos)? {
                     elements.push(element);
                 } else {
                     return Ok(None); // Not enough data
@@ -353,8 +370,16 @@ impl RespParser {
         }
     }
 
-    fn parse_map(&mut self) -> Result<Option<RespValue>, io::Error> {
-        if let Some(length_str) = self.read_line()? {
+    fn parse_push(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        // Same layout as a set
+        Ok(match self.parse_set(pos)? {
+            Some(RespValue::Set(items)) => Some(RespValue::Push(items)),
+            _ => None,
+        })
+    }
+
+    fn parse_map(&self, pos: &mut usize) -> Result<Option<RespValue>, io::Error> {
+        if let Some(length_str) = self.read_line(pos)? {
             let length = length_str
                 .parse::<i64>()
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid map length"))?;
@@ -368,13 +393,13 @@ impl RespParser {
 
             let mut pairs = Vec::with_capacity(length as usize);
             for _ in 0..length {
-                let key = if let Some(k) = self.parse_value()? {
+                let key = if let Some(k) = self.parse_value(pos)? {
                     k
                 } else {
                     return Ok(None); // Not enough data
                 };
 
-                let value = if let Some(v) = self.parse_value()? {
+                let value = if let Some(v) = self.parse_value(pos)? {
                     v
                 } else {
                     return Ok(None); // Not enough data
@@ -414,7 +439,7 @@ mod tests {
 
     #[test]
     fn test_bulk_string_serialization() {
-        let value = RespValue::BulkString(Some("hello".to_string()));
+        let value = RespValue::BulkString(Some("hello".into()));
         assert_eq!(value.to_bytes(), b"$5\r\nhello\r\n");
     }
 
@@ -560,8 +585,8 @@ mod tests {
 
     #[test]
     fn test_double_serialization() {
-        let value = RespValue::Double(3.14);
-        assert_eq!(value.to_bytes(), b",3.14\r\n");
+        let value = RespValue::Double(1.25);
+        assert_eq!(value.to_bytes(), b",1.25\r\n");
 
         let value_neg = RespValue::Double(-2.5);
         assert_eq!(value_neg.to_bytes(), b",-2.5\r\n");
@@ -591,6 +616,19 @@ mod tests {
         assert_eq!(value.to_bytes(), b"%2\r\n+key1\r\n:1\r\n+key2\r\n:2\r\n");
     }
 
+    #[test]
+    fn test_push_serialization() {
+        let value = RespValue::Push(vec![
+            RespValue::BulkString(Some("message".into())),
+            RespValue::BulkString(Some("news".into())),
+            RespValue::BulkString(Some("hi".into())),
+        ]);
+        assert_eq!(
+            value.to_bytes(),
+            b">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+    }
+
     // RESP3 parsing tests
     #[test]
     fn test_null_parsing() {
@@ -628,11 +666,11 @@ mod tests {
     #[test]
     fn test_double_parsing() {
         let mut parser = RespParser::new();
-        parser.add_data(b",3.14\r\n");
+        parser.add_data(b",1.25\r\n");
 
         let result = parser.parse().unwrap().unwrap();
         match result {
-            RespValue::Double(d) => assert_eq!(d, 3.14),
+            RespValue::Double(d) => assert_eq!(d, 1.25),
             _ => panic!("Expected Double"),
         }
     }
@@ -659,6 +697,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_push_parsing() {
+        let mut parser = RespParser::new();
+        parser.add_data(b">2\r\n+pong\r\n$0\r\n\r\n");
+
+        match parser.parse().unwrap().unwrap() {
+            RespValue::Push(items) => assert_eq!(items.len(), 2),
+            _ => panic!("Expected Push"),
+        }
+    }
+
     #[test]
     fn test_map_parsing() {
         let mut parser = RespParser::new();
@@ -789,4 +838,34 @@ mod tests {
         let result = parser.parse().unwrap();
         assert!(matches!(result, Some(RespValue::Array(_))));
     }
+
+    #[test]
+    fn test_binary_bulk_string_roundtrip() {
+        let payload: Vec<u8> = (0..=255u8).chain([b'\r', b'\n', 0]).collect();
+        let value = RespValue::BulkString(Some(Bytes::from(payload.clone())));
+        let encoded = value.to_bytes();
+
+        let mut parser = RespParser::new();
+        parser.add_data(&encoded);
+        match parser.parse().unwrap().unwrap() {
+            RespValue::BulkString(Some(data)) => assert_eq!(data.as_ref(), payload.as_slice()),
+            _ => panic!("Expected BulkString"),
+        }
+    }
+
+    #[test]
+    fn test_bulk_string_split_across_reads() {
+        let mut parser = RespParser::new();
+        parser.add_data(b"*2\r\n$3\r\nGET\r\n$6\r\nab");
+        assert!(parser.parse().unwrap().is_none());
+
+        parser.add_data(b"\x00\xffcd\r\n");
+        match parser.parse().unwrap().unwrap() {
+            RespValue::Array(Some(parts)) => match &parts[1] {
+                RespValue::BulkString(Some(data)) => assert_eq!(data.as_ref(), b"ab\x00\xffcd"),
+                _ => panic!("Expected BulkString"),
+            },
+            _ => panic!("Expected Array"),
+        }
+    }
 }
@@ -0,0 +1,175 @@
+//! Wake-up registry for clients blocked on keys (BLPOP, XREAD BLOCK, ...).
+//!
+//! A blocked client queues itself on every key it waits for. A push to a list
+//! wakes the first client queued on it; that client retries its pop and, when
+//! it leaves the queue (served, timed out or disconnected), passes the wake-up
+//! on to the next client. Clients are therefore served in the order they
+//! blocked, and a wake-up is never lost. Stream readers do not consume what
+//! they read, so an append wakes every client queued on the stream.
+
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::Notify;
+
+struct Waiter {
+    id: u64,
+    notify: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct Queues {
+    next_id: u64,
+    by_key: HashMap<Bytes, VecDeque<Waiter>>,
+}
+
+/// Per-key FIFO queues of blocked clients, shared by all connections.
+#[derive(Default)]
+pub struct BlockingRegistry {
+    queues: Mutex<Queues>,
+}
+
+impl BlockingRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a client on `keys`. It stays queued until the ticket is dropped.
+    pub fn register(&self, keys: &[&Bytes]) -> WaitTicket<'_> {
+        let mut queues = self.lock();
+        let id = queues.next_id;
+        queues.next_id += 1;
+
+        let notify = Arc::new(Notify::new());
+        for key in keys {
+            let queue = queues.by_key.entry((*key).clone()).or_default();
+            // A client waiting on the same key twice only queues once
+            if queue.iter().all(|waiter| waiter.id != id) {
+                queue.push_back(Waiter {
+                    id,
+                    notify: Arc::clone(&notify),
+                });
+            }
+        }
+
+        WaitTicket {
+            registry: self,
+            id,
+            keys: keys.iter().map(|key| (*key).clone()).collect(),
+            notify,
+        }
+    }
+
+    /// Signal that `key` may have become non-empty, waking its first waiter.
+    pub fn signal(&self, key: &[u8]) {
+        let queues = self.lock();
+        if let Some(waiter) = queues.by_key.get(key).and_then(|queue| queue.front()) {
+            waiter.notify.notify_one();
+        }
+    }
+
+    /// Wake every client waiting on `key`.
+    pub fn signal_all(&self, key: &[u8]) {
+        let queues = self.lock();
+        for waiter in queues.by_key.get(key).into_iter().flatten() {
+            waiter.notify.notify_one();
+        }
+    }
+
+    /// Number of clients currently blocked.
+    pub fn blocked_clients(&self) -> usize {
+        let queues = self.lock();
+        let mut ids: Vec<u64> = queues
+            .by_key
+            .values()
+            .flatten()
+            .map(|waiter| waiter.id)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.len()
+    }
+
+    fn unregister(&self, id: u64, keys: &[Bytes]) {
+        let mut queues = self.lock();
+        for key in keys {
+            let Some(queue) = queues.by_key.get_mut(key) else {
+                continue;
+            };
+            let Some(pos) = queue.iter().position(|waiter| waiter.id == id) else {
+                continue;
+            };
+            queue.remove(pos);
+
+            // The head may have been woken for an element it did not take
+            if pos == 0 {
+                if let Some(next) = queue.front() {
+                    next.notify.notify_one();
+                }
+            }
+            if queue.is_empty() {
+                queues.by_key.remove(key);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Queues> {
+        self.queues.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A client's place in the wait queues. Dropping it (including when the
+/// waiting future is cancelled because the client disconnected) leaves them.
+pub struct WaitTicket<'a> {
+    registry: &'a BlockingRegistry,
+    id: u64,
+    keys: Vec<Bytes>,
+    notify: Arc<Notify>,
+}
+
+impl WaitTicket<'_> {
+    /// Wait until one of the keys is signalled.
+    pub async fn notified(&self) {
+        self.notify.notified().await;
+    }
+}
+
+impl Drop for WaitTicket<'_> {
+    fn drop(&mut self) {
+        self.registry.unregister(self.id, &self.keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_signal_wakes_first_waiter_then_passes_on() {
+        let registry = BlockingRegistry::new();
+        let key = Bytes::from_static(b"q");
+
+        let first = registry.register(&[&key]);
+        let second = registry.register(&[&key]);
+        registry.signal(&key);
+
+        let timeout = Duration::from_millis(20);
+        assert!(tokio::time::timeout(timeout, first.notified())
+            .await
+            .is_ok());
+        assert!(tokio::time::timeout(timeout, second.notified())
+            .await
+            .is_err());
+
+        // Leaving the head of the queue hands the wake-up to the next client
+        drop(first);
+        assert!(tokio::time::timeout(timeout, second.notified())
+            .await
+            .is_ok());
+        assert_eq!(registry.blocked_clients(), 1);
+
+        drop(second);
+        assert_eq!(registry.blocked_clients(), 0);
+    }
+}
@@ -0,0 +1,334 @@
+//! Connected clients (CLIENT LIST, CLIENT KILL, CLIENT PAUSE).
+//!
+//! Every connection registers a [`Client`] under its client id and reports
+//! what it is doing after each command, so any connection can list the
+//! others. Killing a client raises its flag; the connection notices it
+//! between commands, or while blocked or paused, and closes.
+//!
+//! Pausing holds off all commands, or only writes, of every connection
+//! until a deadline or CLIENT UNPAUSE.
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// What a connection reported about itself after its last command.
+#[derive(Debug, Clone)]
+pub struct ClientState {
+    pub name: Option<Bytes>,
+    /// Name of the last command run, lowercase.
+    pub last_command: Option<String>,
+    pub last_interaction: Instant,
+    /// Channels, patterns and shard channels subscribed to.
+    pub subscriptions: (usize, usize, usize),
+    /// Commands queued, if a transaction is open.
+    pub multi: Option<usize>,
+    pub watched_keys: usize,
+    /// Whether a blocking command is waiting.
+    pub blocked: bool,
+    pub tracking: bool,
+    /// The client invalidations are redirected to, 0 for none and -1 when
+    /// not tracking.
+    pub redirect: i64,
+    pub resp: u8,
+    pub no_evict: bool,
+}
+
+impl Default for ClientState {
+    fn default() -> Self {
+        Self {
+            name: None,
+            last_command: None,
+            last_interaction: Instant::now(),
+            subscriptions: (0, 0, 0),
+            multi: None,
+            watched_keys: 0,
+            blocked: false,
+            tracking: false,
+            redirect: -1,
+            resp: 2,
+            no_evict: false,
+        }
+    }
+}
+
+/// A connected client.
+pub struct Client {
+    id: u64,
+    created: Instant,
+    /// Remote and local address, once the connection has a socket.
+    addrs: OnceLock<(SocketAddr, SocketAddr)>,
+    state: Mutex<ClientState>,
+    /// Bytes received but not parsed into a command yet.
+    query_buffer: AtomicUsize,
+    /// Bytes of a reply being written to the socket.
+    output_buffer: AtomicUsize,
+    killed: watch::Sender<bool>,
+}
+
+impl Client {
+    fn new(id: u64) -> Self {
+        Self {
+            id,
+            created: Instant::now(),
+            addrs: OnceLock::new(),
+            state: Mutex::default(),
+            query_buffer: AtomicUsize::new(0),
+            output_buffer: AtomicUsize::new(0),
+            killed: watch::Sender::new(false),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Record the addresses of the connection's socket.
+    pub fn set_addrs(&self, addr: SocketAddr, laddr: SocketAddr) {
+        let _ = self.addrs.set((addr, laddr));
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addrs.get().map(|(addr, _)| *addr)
+    }
+
+    pub fn laddr(&self) -> Option<SocketAddr> {
+        self.addrs.get().map(|(_, laddr)| *laddr)
+    }
+
+    /// A copy of the last reported state.
+    pub fn state(&self) -> ClientState {
+        self.lock().clone()
+    }
+
+    /// Change the reported state.
+    pub fn update<R>(&self, f: impl FnOnce(&mut ClientState) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    pub fn set_query_buffer(&self, len: usize) {
+        self.query_buffer.store(len, Ordering::Relaxed);
+    }
+
+    pub fn set_output_buffer(&self, len: usize) {
+        self.output_buffer.store(len, Ordering::Relaxed);
+    }
+
+    /// Ask the connection to close.
+    pub fn kill(&self) {
+        self.killed.send_replace(true);
+    }
+
+    pub fn is_killed(&self) -> bool {
+        *self.killed.borrow()
+    }
+
+    /// Wait until the client is killed. Cancel safe.
+    pub async fn killed(&self) {
+        let mut killed = self.killed.subscribe();
+        // The sender lives as long as `self`
+        let _ = killed.wait_for(|killed| *killed).await;
+    }
+
+    /// The client's line of CLIENT LIST.
+    pub fn describe(&self) -> String {
+        let state = self.state();
+        let now = Instant::now();
+        let mut flags = String::new();
+        for (set, flag) in [
+            (state.multi.is_some(), 'x'),
+            (state.blocked, 'b'),
+            (state.subscriptions != (0, 0, 0), 'P'),
+            (state.tracking, 't'),
+            (state.no_evict, 'e'),
+        ] {
+            if set {
+                flags.push(flag);
+            }
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        let address = |addr: Option<SocketAddr>| addr.map(|a| a.to_string()).unwrap_or_default();
+        let mut line = String::new();
+        let _ = write!(
+            line,
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db=0 sub={} psub={} \
+             ssub={} multi={} watch={} qbuf={} obl={} omem=0 cmd={} user=default redir={} \
+             resp={}",
+            self.id,
+            address(self.addr()),
+            address(self.laddr()),
+            state
+                .name
+                .as_deref()
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default(),
+            now.duration_since(self.created).as_secs(),
+            now.duration_since(state.last_interaction).as_secs(),
+            flags,
+            state.subscriptions.0,
+            state.subscriptions.1,
+            state.subscriptions.2,
+            state.multi.map_or(-1, |n| n as i64),
+            state.watched_keys,
+            self.query_buffer.load(Ordering::Relaxed),
+            self.output_buffer.load(Ordering::Relaxed),
+            state.last_command.as_deref().unwrap_or("NULL"),
+            state.redirect,
+            state.resp,
+        );
+        line
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ClientState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A pause set by CLIENT PAUSE.
+#[derive(Debug, Clone, Copy)]
+struct Pause {
+    until: Instant,
+    /// Whether all commands are paused, or only writes.
+    all: bool,
+}
+
+/// Clients connected to the server, by client id.
+pub struct ClientRegistry {
+    clients: Mutex<BTreeMap<u64, Arc<Client>>>,
+    pause: watch::Sender<Option<Pause>>,
+}
+
+impl Default for ClientRegistry {
+    fn default() -> Self {
+        Self {
+            clients: Mutex::default(),
+            pause: watch::Sender::new(None),
+        }
+    }
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the connection of client `id`.
+    pub fn register(&self, id: u64) -> Arc<Client> {
+        let client = Arc::new(Client::new(id));
+        self.lock().insert(id, Arc::clone(&client));
+        client
+    }
+
+    /// Forget a connection that closed.
+    pub fn unregister(&self, id: u64) {
+        self.lock().remove(&id);
+    }
+
+    /// All connected clients, by increasing id.
+    pub fn clients(&self) -> Vec<Arc<Client>> {
+        self.lock().values().cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Client>> {
+        self.lock().get(&id).cloned()
+    }
+
+    /// Pause all commands, or only writes, for `timeout`. A pause already
+    /// in effect is only ever extended, but its mode is replaced.
+    pub fn pause(&self, timeout: Duration, all: bool) {
+        let until = Instant::now() + timeout;
+        self.pause.send_modify(|pause| {
+            let until = pause
+                .filter(|pause| pause.until > until)
+                .map_or(until, |pause| pause.until);
+            *pause = Some(Pause { until, all });
+        });
+    }
+
+    /// End the pause, if any.
+    pub fn unpause(&self) {
+        self.pause.send_replace(None);
+    }
+
+    /// Whether commands are paused, all of them or only writes.
+    pub fn is_paused(&self) -> bool {
+        self.pause
+            .borrow()
+            .is_some_and(|pause| pause.until > Instant::now())
+    }
+
+    /// Wait for the pause to end, if it holds off this command. Cancel safe.
+    pub async fn wait_unpaused(&self, write: bool) {
+        let mut pause = self.pause.subscribe();
+        loop {
+            let until = match *pause.borrow_and_update() {
+                Some(p) if (p.all || write) && p.until > Instant::now() => p.until,
+                _ => return,
+            };
+            tokio::select! {
+                _ = tokio::time::sleep_until(until.into()) => {}
+                _ = pause.changed() => {}
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, Arc<Client>>> {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pause_holds_writes_until_unpaused() {
+        let registry = Arc::new(ClientRegistry::new());
+        registry.pause(Duration::from_secs(60), false);
+        assert!(registry.is_paused());
+        // Reads go through a write pause
+        registry.wait_unpaused(false).await;
+
+        let waiter = {
+            let registry = Arc::clone(&registry);
+            tokio::spawn(async move { registry.wait_unpaused(true).await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiter.is_finished());
+        registry.unpause();
+        waiter.await.unwrap();
+        assert!(!registry.is_paused());
+
+        registry.pause(Duration::from_millis(10), true);
+        tokio::time::timeout(Duration::from_secs(1), registry.wait_unpaused(false))
+            .await
+            .expect("pause did not expire");
+    }
+
+    #[test]
+    fn test_describe() {
+        let registry = ClientRegistry::new();
+        let client = registry.register(7);
+        client.update(|state| {
+            state.name = Some(Bytes::from_static(b"worker"));
+            state.multi = Some(2);
+            state.subscriptions = (1, 0, 0);
+        });
+        let line = client.describe();
+        assert!(line.starts_with("id=7 addr= laddr= name=worker age=0 idle=0 flags=xP "));
+        assert!(line.contains(" multi=2 "));
+        assert!(line.ends_with(" cmd=NULL user=default redir=-1 resp=2"));
+
+        client.kill();
+        assert!(client.is_killed());
+        registry.unregister(7);
+        assert!(registry.get(7).is_none());
+    }
+}
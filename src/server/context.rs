@@ -0,0 +1,101 @@
+//! State shared by all connections of a server.
+
+use super::blocking::BlockingRegistry;
+use super::clients::ClientRegistry;
+use super::cursors::CursorRegistry;
+use super::notify::{self, KeyspaceEvents, NOTIFY_EXPIRED, NOTIFY_KEYEVENT, NOTIFY_KEYSPACE};
+use super::pubsub::PubSubRegistry;
+use super::scripts::ScriptRegistry;
+use super::tracking::TrackingRegistry;
+use super::watch::WatchRegistry;
+use crate::config::Config;
+use crate::storage::ExpiryListener;
+use bytes::Bytes;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Registries shared across client connections.
+///
+/// Each connection's [`Handler`](super::Handler) holds an `Arc` to the same
+/// context; handlers created standalone get a private one.
+#[derive(Default)]
+pub struct ServerContext {
+    /// Connected clients.
+    pub clients: ClientRegistry,
+    /// Clients blocked on list keys.
+    pub blocking: BlockingRegistry,
+    /// Storage cursors of SCAN iterations in progress.
+    pub scan_cursors: CursorRegistry,
+    /// Keys watched by clients for optimistic transactions.
+    pub watches: WatchRegistry,
+    /// Pub/sub channels and their subscribers.
+    pub pubsub: PubSubRegistry,
+    /// Classes of keyspace events published (`notify-keyspace-events`).
+    pub keyspace_events: KeyspaceEvents,
+    /// Keys cached by clients with client-side caching enabled.
+    pub tracking: TrackingRegistry,
+    /// Lua scripts cached by EVAL and SCRIPT LOAD.
+    pub scripts: ScriptRegistry,
+    /// Number of writes since the server started.
+    pub dirty: AtomicU64,
+    /// Held exclusively while a transaction executes and shared by every
+    /// other command, so transactions and scripts never interleave with other clients.
+    pub exec_lock: RwLock<()>,
+}
+
+impl ServerContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A context with the runtime settings of `config`.
+    pub fn with_config(config: &Config) -> Self {
+        let context = Self::new();
+        let flags = notify::parse_flags(&config.server.notify_keyspace_events);
+        context.keyspace_events.set_flags(flags.unwrap_or_default());
+        context
+    }
+
+    /// Publish a keyspace event about `key`, if events of `class` are
+    /// enabled.
+    pub fn notify(&self, class: u32, event: &str, key: &[u8]) {
+        if !self.keyspace_events.enabled(class) {
+            return;
+        }
+        let flags = self.keyspace_events.flags();
+        if flags & NOTIFY_KEYSPACE != 0 {
+            let channel = [b"__keyspace@0__:", key].concat();
+            self.pubsub
+                .publish(&channel, &Bytes::copy_from_slice(event.as_bytes()));
+        }
+        if flags & NOTIFY_KEYEVENT != 0 {
+            let channel = format!("__keyevent@0__:{}", event);
+            self.pubsub
+                .publish(channel.as_bytes(), &Bytes::copy_from_slice(key));
+        }
+    }
+
+    /// Invalidate `key`, modified by client `writer` if any, in the caches
+    /// of the clients tracking it.
+    pub fn invalidate(&self, key: &[u8], writer: Option<u64>) {
+        self.tracking.invalidate(key, writer, &self.pubsub);
+    }
+
+    /// Invalidate every key cached by tracking clients.
+    pub fn invalidate_all(&self) {
+        self.tracking.invalidate_all(&self.pubsub);
+    }
+
+    /// Listener for the storage backend that publishes `expired` events and
+    /// invalidates the expired keys in client caches.
+    pub fn expiry_listener(self: &Arc<Self>) -> ExpiryListener {
+        let context = Arc::downgrade(self);
+        Arc::new(move |key| {
+            if let Some(context) = context.upgrade() {
+                context.notify(NOTIFY_EXPIRED, "expired", key);
+                context.invalidate(key, None);
+            }
+        })
+    }
+}
@@ -0,0 +1,82 @@
+//! Registry mapping SCAN cursor numbers to storage cursors.
+//!
+//! Storage backends resume an iteration from an opaque byte string (a key,
+//! a continuation token), while SCAN clients expect an unsigned integer. The
+//! registry hands out a random number for each storage cursor and remembers
+//! the most recent ones; older cursors are forgotten and reported as invalid.
+
+use bytes::Bytes;
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+
+/// Number of cursors remembered before the oldest ones are evicted.
+const MAX_CURSORS: usize = 4096;
+
+#[derive(Default)]
+struct Cursors {
+    by_id: HashMap<u64, Bytes>,
+    /// Ids in registration order, for eviction.
+    order: VecDeque<u64>,
+}
+
+/// SCAN cursors shared by all connections, so a cursor can be continued from
+/// any of them.
+#[derive(Default)]
+pub struct CursorRegistry {
+    cursors: Mutex<Cursors>,
+}
+
+impl CursorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember a storage cursor, returning its non-zero id.
+    pub fn register(&self, cursor: Bytes) -> u64 {
+        let mut cursors = self.lock();
+        let mut rng = rand::thread_rng();
+        let id = loop {
+            let id = rng.gen_range(1..=u64::MAX);
+            if !cursors.by_id.contains_key(&id) {
+                break id;
+            }
+        };
+
+        if cursors.order.len() == MAX_CURSORS {
+            if let Some(oldest) = cursors.order.pop_front() {
+                cursors.by_id.remove(&oldest);
+            }
+        }
+        cursors.by_id.insert(id, cursor);
+        cursors.order.push_back(id);
+        id
+    }
+
+    /// Storage cursor registered under `id`, if it is still remembered.
+    pub fn resolve(&self, id: u64) -> Option<Bytes> {
+        self.lock().by_id.get(&id).cloned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cursors> {
+        self.cursors.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_resolve_evict() {
+        let registry = CursorRegistry::new();
+        let first = registry.register(Bytes::from_static(b"a"));
+        assert_ne!(first, 0);
+        assert_eq!(registry.resolve(first), Some(Bytes::from_static(b"a")));
+
+        for _ in 0..MAX_CURSORS {
+            registry.register(Bytes::from_static(b"b"));
+        }
+        assert_eq!(registry.resolve(first), None);
+    }
+}
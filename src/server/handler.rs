@@ -1,12 +1,42 @@
+use super::clients::Client;
+use super::notify::{self, NOTIFY_GENERIC};
+use super::pubsub::Subscriber;
+use super::tracking::TrackingOptions;
+use super::watch::{WatchedKeys, WatchedStorage};
+use super::ServerContext;
 use crate::config::Config;
 use crate::metrics::{Metrics, Timer};
 use crate::protocol::{ProtocolVersion, RespParser, RespValue};
-use crate::storage::StorageBackend;
+use crate::storage::{ListEnd, StorageBackend, StorageError};
+use bytes::Bytes;
+use keys::TtlReply;
+use pubsub::{allowed_when_subscribed, Subscription};
+use set::SetOp;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use string::{SetExpiry, SetOptions};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tracing::{debug, warn};
+use transaction::Transaction;
+use zset::RangeBy;
+
+mod auth;
+mod bitmap;
+mod client;
+mod geo;
+mod hash;
+mod hyperloglog;
+mod keys;
+mod list;
+mod pubsub;
+mod scan;
+mod scripting;
+mod set;
+mod stream;
+mod string;
+mod transaction;
+mod zset;
 
 /// Supported Redis commands.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -21,36 +51,712 @@ enum Cmd {
     Command,
     Hello,
     Config,
+    // String commands
+    Incr,
+    Decr,
+    IncrBy,
+    DecrBy,
+    IncrByFloat,
+    MGet,
+    MSet,
+    MSetNx,
+    Append,
+    StrLen,
+    GetRange,
+    SubStr,
+    SetRange,
+    GetDel,
+    GetEx,
+    GetSet,
+    Lcs,
+    // Bitmap commands
+    SetBit,
+    GetBit,
+    BitCount,
+    BitPos,
+    BitOp,
+    BitField,
+    BitFieldRo,
+    // HyperLogLog commands
+    PfAdd,
+    PfCount,
+    PfMerge,
+    // Geo commands
+    GeoAdd,
+    GeoDist,
+    GeoPos,
+    GeoHash,
+    GeoSearch,
+    GeoSearchStore,
+    // Key commands
+    Expire,
+    PExpire,
+    ExpireAt,
+    PExpireAt,
+    Ttl,
+    PTtl,
+    ExpireTime,
+    PExpireTime,
+    Persist,
+    Scan,
+    Keys,
+    Rename,
+    RenameNx,
+    Copy,
+    Type,
+    Touch,
+    Unlink,
+    RandomKey,
+    // Hash commands
+    HSet,
+    HSetNx,
+    HMSet,
+    HGet,
+    HMGet,
+    HGetAll,
+    HDel,
+    HExists,
+    HLen,
+    HKeys,
+    HVals,
+    HIncrBy,
+    HIncrByFloat,
+    HStrLen,
+    HRandField,
+    HScan,
+    // List commands
+    LPush,
+    RPush,
+    LPushX,
+    RPushX,
+    LPop,
+    RPop,
+    LLen,
+    LRange,
+    LIndex,
+    LSet,
+    LInsert,
+    LRem,
+    LTrim,
+    LPos,
+    LMove,
+    RPopLPush,
+    LMPop,
+    BLPop,
+    BRPop,
+    BLMove,
+    BRPopLPush,
+    BLMPop,
+    // Set commands
+    SAdd,
+    SRem,
+    SMembers,
+    SIsMember,
+    SMIsMember,
+    SCard,
+    SPop,
+    SRandMember,
+    SMove,
+    SInter,
+    SInterCard,
+    SUnion,
+    SDiff,
+    SInterStore,
+    SUnionStore,
+    SDiffStore,
+    SScan,
+    // Sorted set commands
+    ZAdd,
+    ZIncrBy,
+    ZRem,
+    ZCard,
+    ZScore,
+    ZMScore,
+    ZRank,
+    ZRevRank,
+    ZCount,
+    ZLexCount,
+    ZRange,
+    ZRevRange,
+    ZRangeByScore,
+    ZRevRangeByScore,
+    ZRangeByLex,
+    ZRevRangeByLex,
+    ZRemRangeByRank,
+    ZRemRangeByScore,
+    ZRemRangeByLex,
+    ZPopMin,
+    ZPopMax,
+    ZUnionStore,
+    ZInterStore,
+    ZScan,
+    // Stream commands
+    XAdd,
+    XRange,
+    XRevRange,
+    XLen,
+    XRead,
+    XReadGroup,
+    XGroup,
+    XAck,
+    XPending,
+    XClaim,
+    XAutoClaim,
+    XTrim,
+    XDel,
+    XInfo,
+    // Transaction commands
+    Multi,
+    Exec,
+    Discard,
+    Watch,
+    Unwatch,
+    // Pub/Sub commands
+    Subscribe,
+    Unsubscribe,
+    PSubscribe,
+    PUnsubscribe,
+    Publish,
+    PubSub,
+    SSubscribe,
+    SUnsubscribe,
+    SPublish,
+    // Scripting commands
+    Eval,
+    EvalSha,
+    Script,
+    // Connection commands
+    Client,
+    Auth,
     Unknown,
 }
 
+/// Command names (lowercase) and the command they map to.
+const COMMAND_TABLE: &[(&str, Cmd)] = &[
+    ("ping", Cmd::Ping),
+    ("set", Cmd::Set),
+    ("get", Cmd::Get),
+    ("del", Cmd::Del),
+    ("exists", Cmd::Exists),
+    ("dbsize", Cmd::DbSize),
+    ("flushdb", Cmd::FlushDb),
+    ("command", Cmd::Command),
+    ("hello", Cmd::Hello),
+    ("config", Cmd::Config),
+    ("incr", Cmd::Incr),
+    ("decr", Cmd::Decr),
+    ("incrby", Cmd::IncrBy),
+    ("decrby", Cmd::DecrBy),
+    ("incrbyfloat", Cmd::IncrByFloat),
+    ("mget", Cmd::MGet),
+    ("mset", Cmd::MSet),
+    ("msetnx", Cmd::MSetNx),
+    ("append", Cmd::Append),
+    ("strlen", Cmd::StrLen),
+    ("getrange", Cmd::GetRange),
+    ("substr", Cmd::SubStr),
+    ("setrange", Cmd::SetRange),
+    ("getdel", Cmd::GetDel),
+    ("getex", Cmd::GetEx),
+    ("getset", Cmd::GetSet),
+    ("lcs", Cmd::Lcs),
+    ("setbit", Cmd::SetBit),
+    ("getbit", Cmd::GetBit),
+    ("bitcount", Cmd::BitCount),
+    ("bitpos", Cmd::BitPos),
+    ("bitop", Cmd::BitOp),
+    ("bitfield", Cmd::BitField),
+    ("bitfield_ro", Cmd::BitFieldRo),
+    ("pfadd", Cmd::PfAdd),
+    ("pfcount", Cmd::PfCount),
+    ("pfmerge", Cmd::PfMerge),
+    ("geoadd", Cmd::GeoAdd),
+    ("geodist", Cmd::GeoDist),
+    ("geopos", Cmd::GeoPos),
+    ("geohash", Cmd::GeoHash),
+    ("geosearch", Cmd::GeoSearch),
+    ("geosearchstore", Cmd::GeoSearchStore),
+    ("expire", Cmd::Expire),
+    ("pexpire", Cmd::PExpire),
+    ("expireat", Cmd::ExpireAt),
+    ("pexpireat", Cmd::PExpireAt),
+    ("ttl", Cmd::Ttl),
+    ("pttl", Cmd::PTtl),
+    ("expiretime", Cmd::ExpireTime),
+    ("pexpiretime", Cmd::PExpireTime),
+    ("persist", Cmd::Persist),
+    ("scan", Cmd::Scan),
+    ("keys", Cmd::Keys),
+    ("rename", Cmd::Rename),
+    ("renamenx", Cmd::RenameNx),
+    ("copy", Cmd::Copy),
+    ("type", Cmd::Type),
+    ("touch", Cmd::Touch),
+    ("unlink", Cmd::Unlink),
+    ("randomkey", Cmd::RandomKey),
+    ("hset", Cmd::HSet),
+    ("hsetnx", Cmd::HSetNx),
+    ("hmset", Cmd::HMSet),
+    ("hget", Cmd::HGet),
+    ("hmget", Cmd::HMGet),
+    ("hgetall", Cmd::HGetAll),
+    ("hdel", Cmd::HDel),
+    ("hexists", Cmd::HExists),
+    ("hlen", Cmd::HLen),
+    ("hkeys", Cmd::HKeys),
+    ("hvals", Cmd::HVals),
+    ("hincrby", Cmd::HIncrBy),
+    ("hincrbyfloat", Cmd::HIncrByFloat),
+    ("hstrlen", Cmd::HStrLen),
+    ("hrandfield", Cmd::HRandField),
+    ("hscan", Cmd::HScan),
+    ("lpush", Cmd::LPush),
+    ("rpush", Cmd::RPush),
+    ("lpushx", Cmd::LPushX),
+    ("rpushx", Cmd::RPushX),
+    ("lpop", Cmd::LPop),
+    ("rpop", Cmd::RPop),
+    ("llen", Cmd::LLen),
+    ("lrange", Cmd::LRange),
+    ("lindex", Cmd::LIndex),
+    ("lset", Cmd::LSet),
+    ("linsert", Cmd::LInsert),
+    ("lrem", Cmd::LRem),
+    ("ltrim", Cmd::LTrim),
+    ("lpos", Cmd::LPos),
+    ("lmove", Cmd::LMove),
+    ("rpoplpush", Cmd::RPopLPush),
+    ("lmpop", Cmd::LMPop),
+    ("blpop", Cmd::BLPop),
+    ("brpop", Cmd::BRPop),
+    ("blmove", Cmd::BLMove),
+    ("brpoplpush", Cmd::BRPopLPush),
+    ("blmpop", Cmd::BLMPop),
+    ("sadd", Cmd::SAdd),
+    ("srem", Cmd::SRem),
+    ("smembers", Cmd::SMembers),
+    ("sismember", Cmd::SIsMember),
+    ("smismember", Cmd::SMIsMember),
+    ("scard", Cmd::SCard),
+    ("spop", Cmd::SPop),
+    ("srandmember", Cmd::SRandMember),
+    ("smove", Cmd::SMove),
+    ("sinter", Cmd::SInter),
+    ("sintercard", Cmd::SInterCard),
+    ("sunion", Cmd::SUnion),
+    ("sdiff", Cmd::SDiff),
+    ("sinterstore", Cmd::SInterStore),
+    ("sunionstore", Cmd::SUnionStore),
+    ("sdiffstore", Cmd::SDiffStore),
+    ("sscan", Cmd::SScan),
+    ("zadd", Cmd::ZAdd),
+    ("zincrby", Cmd::ZIncrBy),
+    ("zrem", Cmd::ZRem),
+    ("zcard", Cmd::ZCard),
+    ("zscore", Cmd::ZScore),
+    ("zmscore", Cmd::ZMScore),
+    ("zrank", Cmd::ZRank),
+    ("zrevrank", Cmd::ZRevRank),
+    ("zcount", Cmd::ZCount),
+    ("zlexcount", Cmd::ZLexCount),
+    ("zrange", Cmd::ZRange),
+    ("zrevrange", Cmd::ZRevRange),
+    ("zrangebyscore", Cmd::ZRangeByScore),
+    ("zrevrangebyscore", Cmd::ZRevRangeByScore),
+    ("zrangebylex", Cmd::ZRangeByLex),
+    ("zrevrangebylex", Cmd::ZRevRangeByLex),
+    ("zremrangebyrank", Cmd::ZRemRangeByRank),
+    ("zremrangebyscore", Cmd::ZRemRangeByScore),
+    ("zremrangebylex", Cmd::ZRemRangeByLex),
+    ("zpopmin", Cmd::ZPopMin),
+    ("zpopmax", Cmd::ZPopMax),
+    ("zunionstore", Cmd::ZUnionStore),
+    ("zinterstore", Cmd::ZInterStore),
+    ("zscan", Cmd::ZScan),
+    ("xadd", Cmd::XAdd),
+    ("xrange", Cmd::XRange),
+    ("xrevrange", Cmd::XRevRange),
+    ("xlen", Cmd::XLen),
+    ("xread", Cmd::XRead),
+    ("xreadgroup", Cmd::XReadGroup),
+    ("xgroup", Cmd::XGroup),
+    ("xack", Cmd::XAck),
+    ("xpending", Cmd::XPending),
+    ("xclaim", Cmd::XClaim),
+    ("xautoclaim", Cmd::XAutoClaim),
+    ("xtrim", Cmd::XTrim),
+    ("xdel", Cmd::XDel),
+    ("xinfo", Cmd::XInfo),
+    ("multi", Cmd::Multi),
+    ("exec", Cmd::Exec),
+    ("discard", Cmd::Discard),
+    ("watch", Cmd::Watch),
+    ("unwatch", Cmd::Unwatch),
+    ("subscribe", Cmd::Subscribe),
+    ("unsubscribe", Cmd::Unsubscribe),
+    ("psubscribe", Cmd::PSubscribe),
+    ("punsubscribe", Cmd::PUnsubscribe),
+    ("publish", Cmd::Publish),
+    ("pubsub", Cmd::PubSub),
+    ("ssubscribe", Cmd::SSubscribe),
+    ("sunsubscribe", Cmd::SUnsubscribe),
+    ("spublish", Cmd::SPublish),
+    ("eval", Cmd::Eval),
+    ("evalsha", Cmd::EvalSha),
+    ("script", Cmd::Script),
+    ("client", Cmd::Client),
+    ("auth", Cmd::Auth),
+];
+
 impl Cmd {
-    /// Parse command string (case-insensitive).
-    fn parse(cmd: &str) -> Self {
-        if cmd.eq_ignore_ascii_case("ping") {
-            Self::Ping
-        } else if cmd.eq_ignore_ascii_case("set") {
-            Self::Set
-        } else if cmd.eq_ignore_ascii_case("get") {
-            Self::Get
-        } else if cmd.eq_ignore_ascii_case("del") {
-            Self::Del
-        } else if cmd.eq_ignore_ascii_case("exists") {
-            Self::Exists
-        } else if cmd.eq_ignore_ascii_case("dbsize") {
-            Self::DbSize
-        } else if cmd.eq_ignore_ascii_case("flushdb") {
-            Self::FlushDb
-        } else if cmd.eq_ignore_ascii_case("command") {
-            Self::Command
-        } else if cmd.eq_ignore_ascii_case("hello") {
-            Self::Hello
-        } else if cmd.eq_ignore_ascii_case("config") {
-            Self::Config
+    /// Parse command name (case-insensitive).
+    fn parse(cmd: &[u8]) -> Self {
+        COMMAND_TABLE
+            .iter()
+            .find(|(name, _)| name.as_bytes().eq_ignore_ascii_case(cmd))
+            .map(|&(_, cmd)| cmd)
+            .unwrap_or(Self::Unknown)
+    }
+
+    /// Lowercase name of the command.
+    fn name(self) -> &'static str {
+        COMMAND_TABLE
+            .iter()
+            .find(|&&(_, cmd)| cmd == self)
+            .map_or("unknown", |&(name, _)| name)
+    }
+
+    /// Number of arguments, command name included, as Redis reports it:
+    /// negative values are a minimum.
+    fn arity(self) -> i32 {
+        match self {
+            Cmd::DbSize | Cmd::RandomKey | Cmd::Multi | Cmd::Exec | Cmd::Discard | Cmd::Unwatch => {
+                1
+            }
+            Cmd::Ping
+            | Cmd::FlushDb
+            | Cmd::Command
+            | Cmd::Hello
+            | Cmd::Unsubscribe
+            | Cmd::PUnsubscribe
+            | Cmd::SUnsubscribe => -1,
+            Cmd::Get
+            | Cmd::Incr
+            | Cmd::Decr
+            | Cmd::StrLen
+            | Cmd::GetDel
+            | Cmd::Ttl
+            | Cmd::PTtl
+            | Cmd::ExpireTime
+            | Cmd::PExpireTime
+            | Cmd::Persist
+            | Cmd::Keys
+            | Cmd::Type
+            | Cmd::HGetAll
+            | Cmd::HLen
+            | Cmd::HKeys
+            | Cmd::HVals
+            | Cmd::LLen
+            | Cmd::SMembers
+            | Cmd::SCard
+            | Cmd::ZCard
+            | Cmd::XLen => 2,
+            Cmd::Del
+            | Cmd::Exists
+            | Cmd::Config
+            | Cmd::MGet
+            | Cmd::GetEx
+            | Cmd::BitCount
+            | Cmd::BitField
+            | Cmd::BitFieldRo
+            | Cmd::PfAdd
+            | Cmd::PfCount
+            | Cmd::PfMerge
+            | Cmd::GeoPos
+            | Cmd::GeoHash
+            | Cmd::Scan
+            | Cmd::Touch
+            | Cmd::Unlink
+            | Cmd::HRandField
+            | Cmd::LPop
+            | Cmd::RPop
+            | Cmd::SPop
+            | Cmd::SRandMember
+            | Cmd::SInter
+            | Cmd::SUnion
+            | Cmd::SDiff
+            | Cmd::ZPopMin
+            | Cmd::ZPopMax
+            | Cmd::XGroup
+            | Cmd::XInfo
+            | Cmd::Watch
+            | Cmd::Subscribe
+            | Cmd::PSubscribe
+            | Cmd::SSubscribe
+            | Cmd::PubSub
+            | Cmd::Script
+            | Cmd::Client
+            | Cmd::Auth => -2,
+            Cmd::IncrBy
+            | Cmd::DecrBy
+            | Cmd::IncrByFloat
+            | Cmd::Append
+            | Cmd::GetSet
+            | Cmd::GetBit
+            | Cmd::Rename
+            | Cmd::RenameNx
+            | Cmd::HGet
+            | Cmd::HExists
+            | Cmd::HStrLen
+            | Cmd::LIndex
+            | Cmd::RPopLPush
+            | Cmd::SIsMember
+            | Cmd::ZScore
+            | Cmd::Publish
+            | Cmd::SPublish => 3,
+            Cmd::Set
+            | Cmd::MSet
+            | Cmd::MSetNx
+            | Cmd::Lcs
+            | Cmd::BitPos
+            | Cmd::Expire
+            | Cmd::PExpire
+            | Cmd::ExpireAt
+            | Cmd::PExpireAt
+            | Cmd::Copy
+            | Cmd::HMGet
+            | Cmd::HDel
+            | Cmd::HScan
+            | Cmd::LPush
+            | Cmd::RPush
+            | Cmd::LPushX
+            | Cmd::RPushX
+            | Cmd::LPos
+            | Cmd::BLPop
+            | Cmd::BRPop
+            | Cmd::SAdd
+            | Cmd::SRem
+            | Cmd::SMIsMember
+            | Cmd::SInterCard
+            | Cmd::SInterStore
+            | Cmd::SUnionStore
+            | Cmd::SDiffStore
+            | Cmd::SScan
+            | Cmd::ZRem
+            | Cmd::ZMScore
+            | Cmd::ZRank
+            | Cmd::ZRevRank
+            | Cmd::ZScan
+            | Cmd::XPending
+            | Cmd::XDel
+            | Cmd::Eval
+            | Cmd::EvalSha => -3,
+            Cmd::GetRange
+            | Cmd::SubStr
+            | Cmd::SetRange
+            | Cmd::SetBit
+            | Cmd::HSetNx
+            | Cmd::HIncrBy
+            | Cmd::HIncrByFloat
+            | Cmd::LRange
+            | Cmd::LSet
+            | Cmd::LRem
+            | Cmd::LTrim
+            | Cmd::BRPopLPush
+            | Cmd::SMove
+            | Cmd::ZIncrBy
+            | Cmd::ZCount
+            | Cmd::ZLexCount
+            | Cmd::ZRemRangeByRank
+            | Cmd::ZRemRangeByScore
+            | Cmd::ZRemRangeByLex => 4,
+            Cmd::BitOp
+            | Cmd::GeoDist
+            | Cmd::HSet
+            | Cmd::HMSet
+            | Cmd::LMPop
+            | Cmd::ZAdd
+            | Cmd::ZRange
+            | Cmd::ZRevRange
+            | Cmd::ZRangeByScore
+            | Cmd::ZRevRangeByScore
+            | Cmd::ZRangeByLex
+            | Cmd::ZRevRangeByLex
+            | Cmd::ZUnionStore
+            | Cmd::ZInterStore
+            | Cmd::XRange
+            | Cmd::XRevRange
+            | Cmd::XRead
+            | Cmd::XAck
+            | Cmd::XTrim => -4,
+            Cmd::LInsert | Cmd::LMove => 5,
+            Cmd::GeoAdd | Cmd::BLMPop | Cmd::XAdd => -5,
+            Cmd::BLMove => 6,
+            Cmd::XClaim | Cmd::XAutoClaim => -6,
+            Cmd::GeoSearch | Cmd::XReadGroup => -7,
+            Cmd::GeoSearchStore => -8,
+            Cmd::Unknown => -1,
+        }
+    }
+
+    /// Whether `argc` arguments (command name included) fit the arity.
+    fn accepts(self, argc: usize) -> bool {
+        let arity = self.arity();
+        if arity < 0 {
+            argc >= arity.unsigned_abs() as usize
         } else {
-            Self::Unknown
+            argc == arity as usize
         }
     }
+
+    /// Commands that may suspend the client until data arrives.
+    fn is_blocking(self) -> bool {
+        matches!(
+            self,
+            Cmd::BLPop
+                | Cmd::BRPop
+                | Cmd::BLMove
+                | Cmd::BRPopLPush
+                | Cmd::BLMPop
+                | Cmd::XRead
+                | Cmd::XReadGroup
+        )
+    }
+
+    /// Commands that may modify data or reach other clients, which CLIENT
+    /// PAUSE WRITE holds off.
+    fn is_write(self) -> bool {
+        matches!(
+            self,
+            Cmd::Set
+                | Cmd::Del
+                | Cmd::FlushDb
+                | Cmd::Incr
+                | Cmd::Decr
+                | Cmd::IncrBy
+                | Cmd::DecrBy
+                | Cmd::IncrByFloat
+                | Cmd::MSet
+                | Cmd::MSetNx
+                | Cmd::Append
+                | Cmd::SetRange
+                | Cmd::GetDel
+                | Cmd::GetEx
+                | Cmd::GetSet
+                | Cmd::SetBit
+                | Cmd::BitOp
+                | Cmd::BitField
+                | Cmd::PfAdd
+                | Cmd::PfCount
+                | Cmd::PfMerge
+                | Cmd::GeoAdd
+                | Cmd::GeoSearchStore
+                | Cmd::Expire
+                | Cmd::PExpire
+                | Cmd::ExpireAt
+                | Cmd::PExpireAt
+                | Cmd::Persist
+                | Cmd::Rename
+                | Cmd::RenameNx
+                | Cmd::Copy
+                | Cmd::Unlink
+                | Cmd::HSet
+                | Cmd::HSetNx
+                | Cmd::HMSet
+                | Cmd::HDel
+                | Cmd::HIncrBy
+                | Cmd::HIncrByFloat
+                | Cmd::LPush
+                | Cmd::RPush
+                | Cmd::LPushX
+                | Cmd::RPushX
+                | Cmd::LPop
+                | Cmd::RPop
+                | Cmd::LSet
+                | Cmd::LInsert
+                | Cmd::LRem
+                | Cmd::LTrim
+                | Cmd::LMove
+                | Cmd::RPopLPush
+                | Cmd::LMPop
+                | Cmd::BLPop
+                | Cmd::BRPop
+                | Cmd::BLMove
+                | Cmd::BRPopLPush
+                | Cmd::BLMPop
+                | Cmd::SAdd
+                | Cmd::SRem
+                | Cmd::SPop
+                | Cmd::SMove
+                | Cmd::SInterStore
+                | Cmd::SUnionStore
+                | Cmd::SDiffStore
+                | Cmd::ZAdd
+                | Cmd::ZIncrBy
+                | Cmd::ZRem
+                | Cmd::ZRemRangeByRank
+                | Cmd::ZRemRangeByScore
+                | Cmd::ZRemRangeByLex
+                | Cmd::ZPopMin
+                | Cmd::ZPopMax
+                | Cmd::ZUnionStore
+                | Cmd::ZInterStore
+                | Cmd::XAdd
+                | Cmd::XReadGroup
+                | Cmd::XGroup
+                | Cmd::XAck
+                | Cmd::XClaim
+                | Cmd::XAutoClaim
+                | Cmd::XTrim
+                | Cmd::XDel
+                | Cmd::Publish
+                | Cmd::SPublish
+                | Cmd::Eval
+                | Cmd::EvalSha
+        )
+    }
+}
+
+/// Write `bytes` to the client and flush them. Returns false if the client
+/// was killed first, which is how a client that stopped reading its
+/// replies gets disconnected.
+async fn write_out(
+    stream: &mut TcpStream,
+    client: &Client,
+    bytes: &[u8],
+) -> Result<bool, std::io::Error> {
+    client.set_output_buffer(bytes.len());
+    let written = tokio::select! {
+        biased;
+        written = async {
+            stream.write_all(bytes).await?;
+            stream.flush().await
+        } => written.map(|()| true),
+        _ = client.killed() => Ok(false),
+    };
+    client.set_output_buffer(0);
+    written
+}
+
+fn is_blocking_command(value: &RespValue) -> bool {
+    match value {
+        RespValue::Array(Some(parts)) => match parts.first() {
+            Some(RespValue::BulkString(Some(cmd))) => Cmd::parse(cmd).is_blocking(),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Resolves once the peer has closed the connection. If the client has
+/// already sent more data it never resolves, as that data must not be
+/// consumed while the current command is still running.
+async fn peer_closed(stream: &TcpStream) {
+    let mut byte = [0u8; 1];
+    match stream.peek(&mut byte).await {
+        Ok(0) | Err(_) => {}
+        Ok(_) => std::future::pending().await,
+    }
 }
 
 /// Handles client connections and Redis command processing.
@@ -59,8 +765,32 @@ impl Cmd {
 /// Tracks protocol version per connection for RESP2/RESP3 support.
 pub struct Handler {
     storage: Arc<dyn StorageBackend>,
+    /// `storage` itself, for what the `StorageBackend` interface does not
+    /// offer.
+    watched_storage: Arc<WatchedStorage>,
     protocol_version: ProtocolVersion,
     config: Arc<Config>,
+    context: Arc<ServerContext>,
+    /// Commands queued since MULTI, if a transaction is open.
+    transaction: Option<Transaction>,
+    /// Keys watched for the next EXEC.
+    watched: WatchedKeys,
+    /// Whether EXEC is running the queued commands.
+    in_exec: bool,
+    /// Mailbox for pub/sub messages, and the subscriptions.
+    subscriber: Subscriber,
+    /// This connection's entry in the server's client registry.
+    client: Arc<Client>,
+    /// Replies of the current command sent ahead of the one it returns, for
+    /// commands replying more than once (SUBSCRIBE with several channels).
+    extra_replies: Vec<RespValue>,
+    /// How keys are tracked for client-side caching, if they are.
+    tracking: Option<TrackingOptions>,
+    /// CLIENT CACHING YES or NO, for the next command.
+    caching: Option<bool>,
+    /// Whether the connection may run commands: it authenticated, or no
+    /// password is required.
+    authenticated: bool,
 }
 
 impl Handler {
@@ -90,10 +820,49 @@ impl Handler {
         protocol_version: ProtocolVersion,
         config: Arc<Config>,
     ) -> Self {
-        Self {
+        let context = Arc::new(ServerContext::with_config(&config));
+        Self::build(storage, protocol_version, config, context)
+    }
+
+    /// Create a handler for one connection of a server, sharing `context`
+    /// with the server's other connections. Defaults to RESP2 protocol.
+    pub fn new_with_context(
+        storage: Arc<dyn StorageBackend>,
+        config: Arc<Config>,
+        context: Arc<ServerContext>,
+    ) -> Self {
+        Self::build(storage, ProtocolVersion::default(), config, context)
+    }
+
+    fn build(
+        storage: Arc<dyn StorageBackend>,
+        protocol_version: ProtocolVersion,
+        config: Arc<Config>,
+        context: Arc<ServerContext>,
+    ) -> Self {
+        let subscriber = context.pubsub.subscriber();
+        let client = context.clients.register(subscriber.id());
+        let authenticated = config.server.requirepass.is_none();
+        let watched_storage = Arc::new(WatchedStorage::new(
             storage,
+            Arc::clone(&context),
+            subscriber.id(),
+        ));
+        Self {
+            storage: Arc::clone(&watched_storage) as Arc<dyn StorageBackend>,
+            watched_storage,
             protocol_version,
             config,
+            subscriber,
+            client,
+            context,
+            transaction: None,
+            watched: WatchedKeys::default(),
+            in_exec: false,
+            extra_replies: Vec::new(),
+            tracking: None,
+            caching: None,
+            authenticated,
         }
     }
 
@@ -112,11 +881,28 @@ impl Handler {
         let metrics = Metrics::get();
         metrics.increment_connections();
 
+        if let (Ok(addr), Ok(laddr)) = (stream.peer_addr(), stream.local_addr()) {
+            self.client.set_addrs(addr, laddr);
+        }
+        let client = Arc::clone(&self.client);
+
         let mut parser = RespParser::new();
         let mut buffer = [0; 1024];
 
         loop {
-            let n = stream.read(&mut buffer).await?;
+            // Deliver pub/sub messages while waiting for commands
+            let n = tokio::select! {
+                n = stream.read(&mut buffer) => n?,
+                message = self.subscriber.receive() => {
+                    if let Some(frame) = self.message_frame(message) {
+                        if !write_out(stream, &client, &frame.to_bytes()).await? {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                _ = client.killed() => break,
+            };
             if n == 0 {
                 break; // Connection closed
             }
@@ -124,19 +910,38 @@ impl Handler {
             parser.add_data(&buffer[0..n]);
 
             loop {
+                client.set_query_buffer(parser.buffered());
                 match parser.parse() {
                     Ok(Some(value)) => {
                         debug!("Received command: {:?}", value);
 
                         let timer = Timer::new();
-                        let response = self.handle_command(value).await;
+                        let response =
+                            if is_blocking_command(&value) || self.context.clients.is_paused() {
+                                // Stop waiting (and leave the wait queues) if the
+                                // client goes away while blocked or paused.
+                                tokio::select! {
+                                    response = self.handle_command(value) => response,
+                                    _ = peer_closed(stream) => return Ok(()),
+                                    _ = client.killed() => return Ok(()),
+                                }
+                            } else {
+                                self.handle_command(value).await
+                            };
                         let duration = timer.elapsed_seconds();
 
                         metrics.record_request(duration);
 
-                        let response_bytes = response.to_bytes();
-                        stream.write_all(&response_bytes).await?;
-                        stream.flush().await?;
+                        let mut response_bytes = Vec::new();
+                        for reply in self.extra_replies.drain(..) {
+                            response_bytes.extend_from_slice(&reply.to_bytes());
+                        }
+                        response_bytes.extend_from_slice(&response.to_bytes());
+                        // A client killing itself still gets the reply
+                        if !write_out(stream, &client, &response_bytes).await? || client.is_killed()
+                        {
+                            return Ok(());
+                        }
                     }
                     Ok(None) => {
                         // Need more data
@@ -148,9 +953,9 @@ impl Handler {
                         metrics.record_error("protocol_error", None);
 
                         let error = RespValue::Error(format!("ERR Protocol error: {}", e));
-                        let error_bytes = error.to_bytes();
-                        stream.write_all(&error_bytes).await?;
-                        stream.flush().await?;
+                        if !write_out(stream, &client, &error.to_bytes()).await? {
+                            return Ok(());
+                        }
 
                         // Reset parser to recover from error
                         parser.reset();
@@ -177,26 +982,57 @@ impl Handler {
                     }
                 };
 
+                let cmd_name = String::from_utf8_lossy(cmd_str).into_owned();
                 let timer = Timer::new();
-                let response = match Cmd::parse(cmd_str) {
-                    Cmd::Ping => self.handle_ping(&parts[1..]).await,
-                    Cmd::Set => self.handle_set(&parts[1..]).await,
-                    Cmd::Get => self.handle_get(&parts[1..]).await,
-                    Cmd::Del => self.handle_del(&parts[1..]).await,
-                    Cmd::Exists => self.handle_exists(&parts[1..]).await,
-                    Cmd::DbSize => self.handle_dbsize().await,
-                    Cmd::FlushDb => self.handle_flushdb().await,
-                    Cmd::Command => self.handle_command_info().await,
-                    Cmd::Hello => self.handle_hello(&parts[1..]).await,
-                    Cmd::Config => self.handle_config(&parts[1..]).await,
-                    Cmd::Unknown => {
-                        metrics.record_error("unknown_command", Some(cmd_str));
-                        RespValue::Error(format!("Unknown command: {}", cmd_str))
-                    }
+                let cmd = Cmd::parse(cmd_str);
+                self.client.update(|state| {
+                    state.last_command = Some(cmd_name.to_lowercase());
+                    state.blocked = cmd.is_blocking();
+                });
+                let response = if !self.authenticated && !matches!(cmd, Cmd::Auth | Cmd::Hello) {
+                    RespValue::Error("NOAUTH Authentication required.".to_string())
+                } else if self.in_subscribed_mode() && !allowed_when_subscribed(cmd) {
+                    RespValue::Error(format!(
+                        "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING \
+                         / QUIT / RESET are allowed in this context",
+                        cmd_name.to_lowercase()
+                    ))
+                } else if self.transaction.is_some()
+                    && !matches!(cmd, Cmd::Multi | Cmd::Exec | Cmd::Discard | Cmd::Watch)
+                {
+                    self.queue_command(cmd, parts)
+                } else if cmd == Cmd::Exec {
+                    self.wait_unpaused(cmd).await;
+                    self.handle_exec(&parts[1..]).await
+                } else if cmd != Cmd::Script && self.context.scripts.busy() {
+                    RespValue::Error(
+                        "BUSY Redis is busy running a script. You can only call SCRIPT KILL \
+                         or SHUTDOWN NOSAVE."
+                            .to_string(),
+                    )
+                } else {
+                    // Commands that wait hold other clients' transactions off
+                    // only while they attempt, see `block_on`. Scripts lock
+                    // exclusively themselves, and SCRIPT must get through
+                    // while one runs.
+                    self.wait_unpaused(cmd).await;
+                    let context = Arc::clone(&self.context);
+                    let _shared = match cmd.is_blocking()
+                        || matches!(cmd, Cmd::Eval | Cmd::EvalSha | Cmd::Script)
+                    {
+                        true => None,
+                        false => Some(context.exec_lock.read().await),
+                    };
+                    self.execute(cmd, &parts).await
                 };
+                // CLIENT CACHING applies to the next command, or transaction
+                if cmd != Cmd::Client && self.transaction.is_none() {
+                    self.caching = None;
+                }
+                self.report_state();
 
                 let duration = timer.elapsed_seconds();
-                metrics.record_command(cmd_str, duration);
+                metrics.record_command(&cmd_name, duration);
 
                 response
             }
@@ -207,7 +1043,270 @@ impl Handler {
         }
     }
 
+    /// Run a command given as its name and arguments, on its own or as part
+    /// of a transaction, remembering the keys it read if they are tracked.
+    async fn execute(&mut self, cmd: Cmd, parts: &[RespValue]) -> RespValue {
+        if !self.tracks_reads() || !self.watched_storage.log_reads() {
+            return self.run_command(cmd, parts).await;
+        }
+        let response = self.run_command(cmd, parts).await;
+        if let Some(keys) = self.watched_storage.take_reads() {
+            self.context.tracking.remember(self.subscriber.id(), keys);
+        }
+        response
+    }
+
+    async fn run_command(&mut self, cmd: Cmd, parts: &[RespValue]) -> RespValue {
+        match cmd {
+            Cmd::Ping => self.handle_ping(&parts[1..]).await,
+            Cmd::Set => self.handle_set(&parts[1..]).await,
+            Cmd::Get => self.handle_get(&parts[1..]).await,
+            // UNLINK frees memory inline, so it is a plain DEL
+            Cmd::Del | Cmd::Unlink => self.handle_del(&parts[1..]).await,
+            Cmd::Exists => self.handle_exists(&parts[1..]).await,
+            Cmd::DbSize => self.handle_dbsize().await,
+            Cmd::FlushDb => self.handle_flushdb().await,
+            Cmd::Command => self.handle_command_info().await,
+            Cmd::Hello => self.handle_hello(&parts[1..]).await,
+            Cmd::Config => self.handle_config(&parts[1..]).await,
+            Cmd::Unknown => unknown_command(&parts[0]),
+            cmd => match bulk_args(&parts[1..]) {
+                Ok(args) => self.dispatch(cmd, &args).await,
+                Err(e) => e,
+            },
+        }
+    }
+
+    /// Dispatch data-type commands, whose arguments are all byte strings.
+    async fn dispatch(&mut self, cmd: Cmd, args: &[&Bytes]) -> RespValue {
+        match cmd {
+            Cmd::Incr => self.handle_incr(args, "incr", 1).await,
+            Cmd::Decr => self.handle_incr(args, "decr", -1).await,
+            Cmd::IncrBy => self.handle_incrby(args, "incrby", false).await,
+            Cmd::DecrBy => self.handle_incrby(args, "decrby", true).await,
+            Cmd::IncrByFloat => self.handle_incrbyfloat(args).await,
+            Cmd::MGet => self.handle_mget(args).await,
+            Cmd::MSet => self.handle_mset(args).await,
+            Cmd::MSetNx => self.handle_msetnx(args).await,
+            Cmd::Append => self.handle_append(args).await,
+            Cmd::StrLen => self.handle_strlen(args).await,
+            Cmd::GetRange => self.handle_getrange(args, "getrange").await,
+            Cmd::SubStr => self.handle_getrange(args, "substr").await,
+            Cmd::SetRange => self.handle_setrange(args).await,
+            Cmd::GetDel => self.handle_getdel(args).await,
+            Cmd::GetEx => self.handle_getex(args).await,
+            Cmd::GetSet => self.handle_getset(args).await,
+            Cmd::Lcs => self.handle_lcs(args).await,
+            Cmd::SetBit => self.handle_setbit(args).await,
+            Cmd::GetBit => self.handle_getbit(args).await,
+            Cmd::BitCount => self.handle_bitcount(args).await,
+            Cmd::BitPos => self.handle_bitpos(args).await,
+            Cmd::BitOp => self.handle_bitop(args).await,
+            Cmd::BitField => self.handle_bitfield(args, false).await,
+            Cmd::BitFieldRo => self.handle_bitfield(args, true).await,
+            Cmd::PfAdd => self.handle_pfadd(args).await,
+            Cmd::PfCount => self.handle_pfcount(args).await,
+            Cmd::PfMerge => self.handle_pfmerge(args).await,
+            Cmd::GeoAdd => self.handle_geoadd(args).await,
+            Cmd::GeoDist => self.handle_geodist(args).await,
+            Cmd::GeoPos => self.handle_geopos(args).await,
+            Cmd::GeoHash => self.handle_geohash(args).await,
+            Cmd::GeoSearch => self.handle_geosearch(args).await,
+            Cmd::GeoSearchStore => self.handle_geosearchstore(args).await,
+            Cmd::Expire => self.handle_expire(args, "expire", false, false).await,
+            Cmd::PExpire => self.handle_expire(args, "pexpire", true, false).await,
+            Cmd::ExpireAt => self.handle_expire(args, "expireat", false, true).await,
+            Cmd::PExpireAt => self.handle_expire(args, "pexpireat", true, true).await,
+            Cmd::Ttl => {
+                self.handle_ttl(args, "ttl", false, TtlReply::Remaining)
+                    .await
+            }
+            Cmd::PTtl => {
+                self.handle_ttl(args, "pttl", true, TtlReply::Remaining)
+                    .await
+            }
+            Cmd::ExpireTime => {
+                self.handle_ttl(args, "expiretime", false, TtlReply::Deadline)
+                    .await
+            }
+            Cmd::PExpireTime => {
+                self.handle_ttl(args, "pexpiretime", true, TtlReply::Deadline)
+                    .await
+            }
+            Cmd::Persist => self.handle_persist(args).await,
+            Cmd::Scan => self.handle_scan(args).await,
+            Cmd::Keys => self.handle_keys(args).await,
+            Cmd::Rename => self.handle_rename(args, false).await,
+            Cmd::RenameNx => self.handle_rename(args, true).await,
+            Cmd::Copy => self.handle_copy(args).await,
+            Cmd::Type => self.handle_type(args).await,
+            Cmd::Touch => self.handle_touch(args).await,
+            Cmd::RandomKey => self.handle_randomkey(args).await,
+            Cmd::HSet => self.handle_hset(args, "hset").await,
+            Cmd::HMSet => self.handle_hset(args, "hmset").await,
+            Cmd::HSetNx => self.handle_hsetnx(args).await,
+            Cmd::HGet => self.handle_hget(args).await,
+            Cmd::HMGet => self.handle_hmget(args).await,
+            Cmd::HGetAll => self.handle_hgetall(args).await,
+            Cmd::HDel => self.handle_hdel(args).await,
+            Cmd::HExists => self.handle_hexists(args).await,
+            Cmd::HLen => self.handle_hlen(args).await,
+            Cmd::HKeys => self.handle_hkeys(args).await,
+            Cmd::HVals => self.handle_hvals(args).await,
+            Cmd::HIncrBy => self.handle_hincrby(args).await,
+            Cmd::HIncrByFloat => self.handle_hincrbyfloat(args).await,
+            Cmd::HStrLen => self.handle_hstrlen(args).await,
+            Cmd::HRandField => self.handle_hrandfield(args).await,
+            Cmd::HScan => self.handle_hscan(args).await,
+            Cmd::LPush => self.handle_push(args, "lpush", ListEnd::Left, true).await,
+            Cmd::RPush => self.handle_push(args, "rpush", ListEnd::Right, true).await,
+            Cmd::LPushX => self.handle_push(args, "lpushx", ListEnd::Left, false).await,
+            Cmd::RPushX => {
+                self.handle_push(args, "rpushx", ListEnd::Right, false)
+                    .await
+            }
+            Cmd::LPop => self.handle_pop(args, "lpop", ListEnd::Left).await,
+            Cmd::RPop => self.handle_pop(args, "rpop", ListEnd::Right).await,
+            Cmd::LLen => self.handle_llen(args).await,
+            Cmd::LRange => self.handle_lrange(args).await,
+            Cmd::LIndex => self.handle_lindex(args).await,
+            Cmd::LSet => self.handle_lset(args).await,
+            Cmd::LInsert => self.handle_linsert(args).await,
+            Cmd::LRem => self.handle_lrem(args).await,
+            Cmd::LTrim => self.handle_ltrim(args).await,
+            Cmd::LPos => self.handle_lpos(args).await,
+            Cmd::LMove => self.handle_lmove(args).await,
+            Cmd::RPopLPush => self.handle_rpoplpush(args).await,
+            Cmd::LMPop => self.handle_lmpop(args).await,
+            Cmd::BLPop => self.handle_bpop(args, "blpop", ListEnd::Left).await,
+            Cmd::BRPop => self.handle_bpop(args, "brpop", ListEnd::Right).await,
+            Cmd::BLMove => self.handle_blmove(args).await,
+            Cmd::BRPopLPush => self.handle_brpoplpush(args).await,
+            Cmd::BLMPop => self.handle_blmpop(args).await,
+            Cmd::SAdd => self.handle_sadd(args).await,
+            Cmd::SRem => self.handle_srem(args).await,
+            Cmd::SMembers => self.handle_smembers(args).await,
+            Cmd::SIsMember => self.handle_sismember(args).await,
+            Cmd::SMIsMember => self.handle_smismember(args).await,
+            Cmd::SCard => self.handle_scard(args).await,
+            Cmd::SPop => self.handle_spop(args).await,
+            Cmd::SRandMember => self.handle_srandmember(args).await,
+            Cmd::SMove => self.handle_smove(args).await,
+            Cmd::SInter => self.handle_set_op(args, "sinter", SetOp::Inter).await,
+            Cmd::SInterCard => self.handle_sintercard(args).await,
+            Cmd::SUnion => self.handle_set_op(args, "sunion", SetOp::Union).await,
+            Cmd::SDiff => self.handle_set_op(args, "sdiff", SetOp::Diff).await,
+            Cmd::SInterStore => {
+                self.handle_set_op_store(args, "sinterstore", SetOp::Inter)
+                    .await
+            }
+            Cmd::SUnionStore => {
+                self.handle_set_op_store(args, "sunionstore", SetOp::Union)
+                    .await
+            }
+            Cmd::SDiffStore => {
+                self.handle_set_op_store(args, "sdiffstore", SetOp::Diff)
+                    .await
+            }
+            Cmd::SScan => self.handle_sscan(args).await,
+            Cmd::ZAdd => self.handle_zadd(args).await,
+            Cmd::ZIncrBy => self.handle_zincrby(args).await,
+            Cmd::ZRem => self.handle_zrem(args).await,
+            Cmd::ZCard => self.handle_zcard(args).await,
+            Cmd::ZScore => self.handle_zscore(args).await,
+            Cmd::ZMScore => self.handle_zmscore(args).await,
+            Cmd::ZRank => self.handle_zrank(args, "zrank", false).await,
+            Cmd::ZRevRank => self.handle_zrank(args, "zrevrank", true).await,
+            Cmd::ZCount => self.handle_zcount(args, "zcount", RangeBy::Score).await,
+            Cmd::ZLexCount => self.handle_zcount(args, "zlexcount", RangeBy::Lex).await,
+            Cmd::ZRange => self.handle_zrange(args, "zrange", None).await,
+            Cmd::ZRevRange => {
+                let fixed = Some((RangeBy::Rank, true));
+                self.handle_zrange(args, "zrevrange", fixed).await
+            }
+            Cmd::ZRangeByScore => {
+                let fixed = Some((RangeBy::Score, false));
+                self.handle_zrange(args, "zrangebyscore", fixed).await
+            }
+            Cmd::ZRevRangeByScore => {
+                let fixed = Some((RangeBy::Score, true));
+                self.handle_zrange(args, "zrevrangebyscore", fixed).await
+            }
+            Cmd::ZRangeByLex => {
+                let fixed = Some((RangeBy::Lex, false));
+                self.handle_zrange(args, "zrangebylex", fixed).await
+            }
+            Cmd::ZRevRangeByLex => {
+                let fixed = Some((RangeBy::Lex, true));
+                self.handle_zrange(args, "zrevrangebylex", fixed).await
+            }
+            Cmd::ZRemRangeByRank => {
+                self.handle_zremrange(args, "zremrangebyrank", RangeBy::Rank)
+                    .await
+            }
+            Cmd::ZRemRangeByScore => {
+                self.handle_zremrange(args, "zremrangebyscore", RangeBy::Score)
+                    .await
+            }
+            Cmd::ZRemRangeByLex => {
+                self.handle_zremrange(args, "zremrangebylex", RangeBy::Lex)
+                    .await
+            }
+            Cmd::ZPopMin => self.handle_zpop(args, "zpopmin", false).await,
+            Cmd::ZPopMax => self.handle_zpop(args, "zpopmax", true).await,
+            Cmd::ZUnionStore => self.handle_zstore(args, "zunionstore", false).await,
+            Cmd::ZInterStore => self.handle_zstore(args, "zinterstore", true).await,
+            Cmd::ZScan => self.handle_zscan(args).await,
+            Cmd::XAdd => self.handle_xadd(args).await,
+            Cmd::XRange => self.handle_xrange(args, "xrange", false).await,
+            Cmd::XRevRange => self.handle_xrange(args, "xrevrange", true).await,
+            Cmd::XLen => self.handle_xlen(args).await,
+            Cmd::XRead => self.handle_xread(args).await,
+            Cmd::XReadGroup => self.handle_xreadgroup(args).await,
+            Cmd::XGroup => self.handle_xgroup(args).await,
+            Cmd::XAck => self.handle_xack(args).await,
+            Cmd::XPending => self.handle_xpending(args).await,
+            Cmd::XClaim => self.handle_xclaim(args).await,
+            Cmd::XAutoClaim => self.handle_xautoclaim(args).await,
+            Cmd::XTrim => self.handle_xtrim(args).await,
+            Cmd::XDel => self.handle_xdel(args).await,
+            Cmd::XInfo => self.handle_xinfo(args).await,
+            Cmd::Multi => self.handle_multi(args),
+            Cmd::Discard => self.handle_discard(args),
+            Cmd::Watch => self.handle_watch(args),
+            Cmd::Unwatch => self.handle_unwatch(args),
+            Cmd::Subscribe => self.handle_subscribe(args, Subscription::Channel),
+            Cmd::Unsubscribe => self.handle_unsubscribe(args, Subscription::Channel),
+            Cmd::PSubscribe => self.handle_subscribe(args, Subscription::Pattern),
+            Cmd::PUnsubscribe => self.handle_unsubscribe(args, Subscription::Pattern),
+            Cmd::SSubscribe => self.handle_subscribe(args, Subscription::ShardChannel),
+            Cmd::SUnsubscribe => self.handle_unsubscribe(args, Subscription::ShardChannel),
+            Cmd::Publish => self.handle_publish(args),
+            Cmd::SPublish => self.handle_spublish(args),
+            Cmd::PubSub => self.handle_pubsub(args),
+            Cmd::Eval => self.handle_eval(args, false).await,
+            Cmd::EvalSha => self.handle_eval(args, true).await,
+            Cmd::Script => self.handle_script(args),
+            Cmd::Client => self.handle_client(args),
+            Cmd::Auth => self.handle_auth(args),
+            _ => unreachable!("{:?} is dispatched by handle_command", cmd),
+        }
+    }
+
     async fn handle_ping(&self, args: &[RespValue]) -> RespValue {
+        if self.in_subscribed_mode() {
+            return match args {
+                [] => self.push_frame(vec![
+                    RespValue::BulkString(Some("pong".into())),
+                    RespValue::BulkString(Some(Bytes::new())),
+                ]),
+                [message] => self.push_frame(vec![
+                    RespValue::BulkString(Some("pong".into())),
+                    message.clone(),
+                ]),
+                _ => RespValue::Error("Wrong number of arguments for PING".to_string()),
+            };
+        }
         match args.len() {
             0 => RespValue::SimpleString("PONG".to_string()),
             1 => match &args[0] {
@@ -220,6 +1319,7 @@ impl Handler {
         }
     }
 
+    /// SET key value [NX|XX] [GET] [EX s|PX ms|EXAT ts|PXAT ts-ms|KEEPTTL]
     async fn handle_set(&self, args: &[RespValue]) -> RespValue {
         let metrics = Metrics::get();
 
@@ -237,59 +1337,39 @@ impl Handler {
             _ => return RespValue::Error("Invalid value".to_string()),
         };
 
-        // Check for EX option (expiry in seconds)
-        if args.len() >= 4 {
-            if let (RespValue::BulkString(Some(option)), RespValue::BulkString(Some(ttl_str))) =
-                (&args[2], &args[3])
-            {
-                if option.eq_ignore_ascii_case("EX") {
-                    if let Ok(ttl_secs) = ttl_str.parse::<u64>() {
-                        let timer = Timer::new();
-                        let result = self
-                            .storage
-                            .set_with_expiry(key, value, Duration::from_secs(ttl_secs))
-                            .await;
-                        let duration = timer.elapsed_seconds();
+        let options = match bulk_args(&args[2..]).and_then(|options| SetOptions::parse(&options)) {
+            Ok(options) => options,
+            Err(e) => return e,
+        };
 
-                        match result {
-                            Ok(()) => {
-                                metrics.record_storage_operation(
-                                    "set_with_expiry",
-                                    "storage",
-                                    duration,
-                                );
-                                metrics.record_key_operation("set", 1);
-                                return RespValue::SimpleString("OK".to_string());
-                            }
-                            Err(e) => {
-                                metrics.record_storage_error(
-                                    "set_with_expiry",
-                                    "storage",
-                                    "operation_failed",
-                                );
-                                warn!("SET with expiry failed: {}", e);
-                                return RespValue::Error(format!("SET failed: {}", e));
-                            }
-                        }
-                    } else {
-                        return RespValue::Error("Invalid TTL".to_string());
-                    }
-                }
-            }
+        // Anything that depends on the current value is a read-modify-write
+        if options.condition.is_some() || options.get || options.expiry == SetExpiry::Keep {
+            return self.handle_set_conditional(key, value, &options).await;
         }
 
         let timer = Timer::new();
-        let result = self.storage.set(key, value).await;
+        let (operation, result) = match options.expiry {
+            SetExpiry::At(at) => match at.duration_since(SystemTime::now()) {
+                Ok(ttl) => (
+                    "set_with_expiry",
+                    self.storage.set_with_expiry(key, value, ttl).await,
+                ),
+                // A deadline in the past expires the key right away
+                Err(_) => ("set", self.storage.delete(key).await.map(|_| ())),
+            },
+            _ => ("set", self.storage.set(key, value).await),
+        };
         let duration = timer.elapsed_seconds();
 
         match result {
             Ok(()) => {
-                metrics.record_storage_operation("set", "storage", duration);
+                metrics.record_storage_operation(operation, "storage", duration);
                 metrics.record_key_operation("set", 1);
+                self.notify_set(key, options.expiry);
                 RespValue::SimpleString("OK".to_string())
             }
             Err(e) => {
-                metrics.record_storage_error("set", "storage", "operation_failed");
+                metrics.record_storage_error(operation, "storage", "operation_failed");
                 warn!("SET failed: {}", e);
                 RespValue::Error(format!("SET failed: {}", e))
             }
@@ -321,9 +1401,14 @@ impl Handler {
                 metrics.record_storage_operation("get", "storage", duration);
                 RespValue::BulkString(None)
             }
+            Err(e @ StorageError::WrongType) => RespValue::Error(e.to_string()),
             Err(e) => {
                 metrics.record_storage_error("get", "storage", "operation_failed");
-                warn!("GET failed for key '{}': {}", key, e);
+                warn!(
+                    "GET failed for key '{}': {}",
+                    String::from_utf8_lossy(key),
+                    e
+                );
                 RespValue::Error(format!("GET failed: {}", e))
             }
         }
@@ -337,10 +1422,10 @@ impl Handler {
         }
 
         // Extract all keys, filtering out invalid ones
-        let keys: Vec<&str> = args
+        let keys: Vec<&[u8]> = args
             .iter()
             .filter_map(|arg| match arg {
-                RespValue::BulkString(Some(k)) => Some(k.as_str()),
+                RespValue::BulkString(Some(k)) => Some(k.as_ref()),
                 _ => {
                     warn!("Invalid key in DEL command");
                     None
@@ -353,7 +1438,11 @@ impl Handler {
         }
 
         let timer = Timer::new();
-        let result = self.storage.delete_many(&keys).await;
+        let result = if self.context.keyspace_events.enabled(NOTIFY_GENERIC) {
+            self.delete_notifying(&keys).await
+        } else {
+            self.storage.delete_many(&keys).await
+        };
         let duration = timer.elapsed_seconds();
 
         match result {
@@ -369,6 +1458,18 @@ impl Handler {
         }
     }
 
+    /// Delete `keys` one at a time, to publish `del` for each that existed.
+    async fn delete_notifying(&self, keys: &[&[u8]]) -> Result<usize, StorageError> {
+        let mut deleted = 0;
+        for key in keys {
+            if self.storage.delete(key).await? {
+                self.notify(NOTIFY_GENERIC, "del", key);
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
     async fn handle_exists(&self, args: &[RespValue]) -> RespValue {
         if args.is_empty() {
             return RespValue::Error("Wrong number of arguments for EXISTS".to_string());
@@ -388,7 +1489,11 @@ impl Handler {
                 Ok(true) => exists_count += 1,
                 Ok(false) => {}
                 Err(e) => {
-                    warn!("Failed to check existence of key '{}': {}", key, e);
+                    warn!(
+                        "Failed to check existence of key '{}': {}",
+                        String::from_utf8_lossy(key),
+                        e
+                    );
                 }
             }
         }
@@ -423,7 +1528,7 @@ impl Handler {
 
     /// Handle CONFIG command for configuration management.
     /// Format: CONFIG GET parameter [parameter ...]
-    /// Currently supports GET subcommand only.
+    ///         CONFIG SET parameter value [parameter value ...]
     async fn handle_config(&self, args: &[RespValue]) -> RespValue {
         if args.is_empty() {
             return RespValue::Error("Wrong number of arguments for CONFIG".to_string());
@@ -434,11 +1539,13 @@ impl Handler {
             _ => return RespValue::Error("Invalid CONFIG subcommand".to_string()),
         };
 
-        // Only support CONFIG GET for now
-        if !subcommand.eq_ignore_ascii_case("GET") {
+        if subcommand.eq_ignore_ascii_case(b"SET") {
+            return self.handle_config_set(&args[1..]);
+        }
+        if !subcommand.eq_ignore_ascii_case(b"GET") {
             return RespValue::Error(format!(
-                "Unknown CONFIG subcommand: {}. Supported: GET",
-                subcommand
+                "Unknown CONFIG subcommand: {}. Supported: GET, SET",
+                String::from_utf8_lossy(subcommand)
             ));
         }
 
@@ -458,79 +1565,113 @@ impl Handler {
 
             // Match configuration parameters
             // Using lowercase comparison for case-insensitivity
-            let param_lower = param.to_lowercase();
+            let param_lower = String::from_utf8_lossy(param).to_lowercase();
             match param_lower.as_str() {
                 "port" => {
-                    results.push(RespValue::BulkString(Some("port".to_string())));
+                    results.push(RespValue::BulkString(Some("port".into())));
                     results.push(RespValue::BulkString(Some(
-                        self.config.server.port.to_string(),
+                        self.config.server.port.to_string().into(),
                     )));
                 }
                 "bind" | "host" => {
-                    results.push(RespValue::BulkString(Some("bind".to_string())));
-                    results.push(RespValue::BulkString(Some(self.config.server.host.clone())));
+                    results.push(RespValue::BulkString(Some("bind".into())));
+                    results.push(RespValue::BulkString(Some(
+                        self.config.server.host.clone().into(),
+                    )));
                 }
                 "storage" | "storage-backend" => {
-                    results.push(RespValue::BulkString(Some("storage-backend".to_string())));
+                    results.push(RespValue::BulkString(Some("storage-backend".into())));
                     let backend = match &self.config.storage {
                         crate::config::StorageConfig::Memory => "memory",
                         crate::config::StorageConfig::Lmdb { .. } => "lmdb",
                         #[cfg(feature = "s3-backend")]
                         crate::config::StorageConfig::S3 { .. } => "s3",
                     };
-                    results.push(RespValue::BulkString(Some(backend.to_string())));
+                    results.push(RespValue::BulkString(Some(backend.into())));
                 }
                 "maxmemory" => {
                     // Return 0 for unlimited (standard Redis behavior)
-                    results.push(RespValue::BulkString(Some("maxmemory".to_string())));
-                    results.push(RespValue::BulkString(Some("0".to_string())));
+                    results.push(RespValue::BulkString(Some("maxmemory".into())));
+                    results.push(RespValue::BulkString(Some("0".into())));
                 }
                 "maxmemory-policy" => {
                     // Default policy for Coral Redis
-                    results.push(RespValue::BulkString(Some("maxmemory-policy".to_string())));
-                    results.push(RespValue::BulkString(Some("noeviction".to_string())));
+                    results.push(RespValue::BulkString(Some("maxmemory-policy".into())));
+                    results.push(RespValue::BulkString(Some("noeviction".into())));
                 }
                 "save" => {
                     // No persistence snapshots in Coral Redis by default
-                    results.push(RespValue::BulkString(Some("save".to_string())));
-                    results.push(RespValue::BulkString(Some("".to_string())));
+                    results.push(RespValue::BulkString(Some("save".into())));
+                    results.push(RespValue::BulkString(Some("".into())));
                 }
                 "appendonly" => {
                     // AOF not supported
-                    results.push(RespValue::BulkString(Some("appendonly".to_string())));
-                    results.push(RespValue::BulkString(Some("no".to_string())));
+                    results.push(RespValue::BulkString(Some("appendonly".into())));
+                    results.push(RespValue::BulkString(Some("no".into())));
                 }
                 "databases" => {
                     // Single database in Coral Redis
-                    results.push(RespValue::BulkString(Some("databases".to_string())));
-                    results.push(RespValue::BulkString(Some("1".to_string())));
+                    results.push(RespValue::BulkString(Some("databases".into())));
+                    results.push(RespValue::BulkString(Some("1".into())));
+                }
+                "notify-keyspace-events" => {
+                    results.push(RespValue::BulkString(Some("notify-keyspace-events".into())));
+                    results.push(RespValue::BulkString(Some(
+                        notify::format_flags(self.context.keyspace_events.flags()).into(),
+                    )));
+                }
+                "requirepass" => {
+                    results.push(RespValue::BulkString(Some("requirepass".into())));
+                    results.push(RespValue::BulkString(Some(
+                        self.config
+                            .server
+                            .requirepass
+                            .clone()
+                            .unwrap_or_default()
+                            .into(),
+                    )));
                 }
                 "*" => {
                     // Wildcard - return all supported parameters
-                    results.push(RespValue::BulkString(Some("port".to_string())));
+                    results.push(RespValue::BulkString(Some("port".into())));
                     results.push(RespValue::BulkString(Some(
-                        self.config.server.port.to_string(),
+                        self.config.server.port.to_string().into(),
                     )));
-                    results.push(RespValue::BulkString(Some("bind".to_string())));
-                    results.push(RespValue::BulkString(Some(self.config.server.host.clone())));
-                    results.push(RespValue::BulkString(Some("storage-backend".to_string())));
+                    results.push(RespValue::BulkString(Some("bind".into())));
+                    results.push(RespValue::BulkString(Some(
+                        self.config.server.host.clone().into(),
+                    )));
+                    results.push(RespValue::BulkString(Some("storage-backend".into())));
                     let backend = match &self.config.storage {
                         crate::config::StorageConfig::Memory => "memory",
                         crate::config::StorageConfig::Lmdb { .. } => "lmdb",
                         #[cfg(feature = "s3-backend")]
                         crate::config::StorageConfig::S3 { .. } => "s3",
                     };
-                    results.push(RespValue::BulkString(Some(backend.to_string())));
-                    results.push(RespValue::BulkString(Some("maxmemory".to_string())));
-                    results.push(RespValue::BulkString(Some("0".to_string())));
-                    results.push(RespValue::BulkString(Some("maxmemory-policy".to_string())));
-                    results.push(RespValue::BulkString(Some("noeviction".to_string())));
-                    results.push(RespValue::BulkString(Some("save".to_string())));
-                    results.push(RespValue::BulkString(Some("".to_string())));
-                    results.push(RespValue::BulkString(Some("appendonly".to_string())));
-                    results.push(RespValue::BulkString(Some("no".to_string())));
-                    results.push(RespValue::BulkString(Some("databases".to_string())));
-                    results.push(RespValue::BulkString(Some("1".to_string())));
+                    results.push(RespValue::BulkString(Some(backend.into())));
+                    results.push(RespValue::BulkString(Some("maxmemory".into())));
+                    results.push(RespValue::BulkString(Some("0".into())));
+                    results.push(RespValue::BulkString(Some("maxmemory-policy".into())));
+                    results.push(RespValue::BulkString(Some("noeviction".into())));
+                    results.push(RespValue::BulkString(Some("save".into())));
+                    results.push(RespValue::BulkString(Some("".into())));
+                    results.push(RespValue::BulkString(Some("appendonly".into())));
+                    results.push(RespValue::BulkString(Some("no".into())));
+                    results.push(RespValue::BulkString(Some("databases".into())));
+                    results.push(RespValue::BulkString(Some("1".into())));
+                    results.push(RespValue::BulkString(Some("notify-keyspace-events".into())));
+                    results.push(RespValue::BulkString(Some(
+                        notify::format_flags(self.context.keyspace_events.flags()).into(),
+                    )));
+                    results.push(RespValue::BulkString(Some("requirepass".into())));
+                    results.push(RespValue::BulkString(Some(
+                        self.config
+                            .server
+                            .requirepass
+                            .clone()
+                            .unwrap_or_default()
+                            .into(),
+                    )));
                 }
                 _ => {
                     // Unknown parameter - Redis returns empty for unknown params
@@ -543,6 +1684,105 @@ impl Handler {
         RespValue::Array(Some(results))
     }
 
+    /// CONFIG SET parameter value [parameter value ...]
+    ///
+    /// Only runtime settings can be changed. Every value is checked before
+    /// any is applied.
+    fn handle_config_set(&self, args: &[RespValue]) -> RespValue {
+        if args.is_empty() || !args.len().is_multiple_of(2) {
+            return RespValue::Error(
+                "ERR wrong number of arguments for 'config|set' command".to_string(),
+            );
+        }
+
+        let mut keyspace_events = None;
+        for pair in args.chunks(2) {
+            let (RespValue::BulkString(Some(param)), RespValue::BulkString(Some(value))) =
+                (&pair[0], &pair[1])
+            else {
+                return RespValue::Error("ERR Invalid CONFIG SET argument".to_string());
+            };
+            let param = String::from_utf8_lossy(param).to_lowercase();
+            match param.as_str() {
+                "notify-keyspace-events" => {
+                    let flags = std::str::from_utf8(value)
+                        .ok()
+                        .and_then(notify::parse_flags);
+                    let Some(flags) = flags else {
+                        return RespValue::Error(format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - \
+                             Invalid event class character. Use 'Ag$lshzxeKEtmdn'.",
+                            param
+                        ));
+                    };
+                    keyspace_events = Some(flags);
+                }
+                _ => {
+                    return RespValue::Error(format!(
+                        "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                        param
+                    ))
+                }
+            }
+        }
+
+        if let Some(flags) = keyspace_events {
+            self.context.keyspace_events.set_flags(flags);
+        }
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    /// Null reply for commands that return an array: a null array under
+    /// RESP2, the dedicated null type under RESP3.
+    fn null_array(&self) -> RespValue {
+        match self.protocol_version {
+            ProtocolVersion::Resp3 => RespValue::Null,
+            ProtocolVersion::Resp2 => RespValue::Array(None),
+        }
+    }
+
+    /// Reply with unordered members: a set under RESP3, an array under RESP2.
+    fn set_reply(&self, items: Vec<RespValue>) -> RespValue {
+        match self.protocol_version {
+            ProtocolVersion::Resp3 => RespValue::Set(items),
+            ProtocolVersion::Resp2 => RespValue::Array(Some(items)),
+        }
+    }
+
+    /// Reply with field/value pairs: a map under RESP3, a flat array under RESP2.
+    fn map_reply(&self, pairs: Vec<(RespValue, RespValue)>) -> RespValue {
+        match self.protocol_version {
+            ProtocolVersion::Resp3 => RespValue::Map(pairs),
+            ProtocolVersion::Resp2 => {
+                RespValue::Array(Some(pairs.into_iter().flat_map(|(k, v)| [k, v]).collect()))
+            }
+        }
+    }
+
+    /// Publish a keyspace event of `class` about `key`.
+    fn notify(&self, class: u32, event: &str, key: &[u8]) {
+        self.context.notify(class, event, key);
+    }
+
+    /// Publish the event of a command storing its result at `key`: `event`
+    /// if the result has `len` elements, `del` if it removed the key.
+    fn notify_stored(&self, class: u32, event: &str, key: &[u8], len: usize, existed: bool) {
+        if len > 0 {
+            self.notify(class, event, key);
+        } else if existed {
+            self.notify(NOTIFY_GENERIC, "del", key);
+        }
+    }
+
+    /// Publish `del` for `key` if a write left it empty, which removes it.
+    async fn notify_if_removed(&self, key: &[u8]) {
+        if self.context.keyspace_events.enabled(NOTIFY_GENERIC)
+            && !self.watched_storage.contains(key).await.unwrap_or(true)
+        {
+            self.notify(NOTIFY_GENERIC, "del", key);
+        }
+    }
+
     /// Handle HELLO command for protocol negotiation.
     /// Format: HELLO [protover [AUTH username password] [SETNAME clientname]]
     async fn handle_hello(&mut self, args: &[RespValue]) -> RespValue {
@@ -551,13 +1791,13 @@ impl Handler {
             None
         } else {
             match &args[0] {
-                RespValue::BulkString(Some(ver_str)) => match ver_str.parse::<u8>() {
-                    Ok(2) => Some(ProtocolVersion::Resp2),
-                    Ok(3) => Some(ProtocolVersion::Resp3),
-                    Ok(v) => {
+                RespValue::BulkString(Some(ver_str)) => match parse_utf8::<u8>(ver_str) {
+                    Some(2) => Some(ProtocolVersion::Resp2),
+                    Some(3) => Some(ProtocolVersion::Resp3),
+                    Some(v) => {
                         return RespValue::Error(format!("ERR unsupported protocol version: {}", v))
                     }
-                    Err(_) => {
+                    None => {
                         return RespValue::Error(
                             "ERR protocol version must be a number".to_string(),
                         )
@@ -567,6 +1807,51 @@ impl Handler {
             }
         };
 
+        let options = match bulk_args(args.get(1..).unwrap_or_default()) {
+            Ok(options) => options,
+            Err(e) => return e,
+        };
+        let mut auth = None;
+        let mut name = None;
+        let mut i = 0;
+        while i < options.len() {
+            let remaining = options.len() - i - 1;
+            match options[i].to_ascii_uppercase().as_slice() {
+                b"AUTH" if remaining >= 2 => {
+                    auth = Some((options[i + 1], options[i + 2]));
+                    i += 3;
+                }
+                b"SETNAME" if remaining >= 1 => {
+                    name = Some(options[i + 1]);
+                    i += 2;
+                }
+                _ => {
+                    return RespValue::Error(format!(
+                        "ERR Syntax error in HELLO option '{}'",
+                        String::from_utf8_lossy(options[i])
+                    ))
+                }
+            }
+        }
+        if let Some((username, password)) = auth {
+            if let Err(e) = self.authenticate(Some(username), password) {
+                return e;
+            }
+        }
+        if !self.authenticated {
+            return RespValue::Error(
+                "NOAUTH HELLO must be called with the client already authenticated, \
+                 otherwise the HELLO <proto> AUTH <user> <pass> option can be used to \
+                 authenticate the client and select the RESP protocol version at the same time"
+                    .to_string(),
+            );
+        }
+        if let Some(name) = name {
+            if let Err(e) = self.set_client_name(name) {
+                return e;
+            }
+        }
+
         // Set protocol version if requested
         if let Some(version) = requested_version {
             self.set_protocol_version(version);
@@ -578,50 +1863,204 @@ impl Handler {
                 // RESP3: Return Map
                 RespValue::Map(vec![
                     (
-                        RespValue::BulkString(Some("server".to_string())),
-                        RespValue::BulkString(Some("coral-redis".to_string())),
+                        RespValue::BulkString(Some("server".into())),
+                        RespValue::BulkString(Some("coral-redis".into())),
                     ),
                     (
-                        RespValue::BulkString(Some("version".to_string())),
-                        RespValue::BulkString(Some("0.1.0".to_string())),
+                        RespValue::BulkString(Some("version".into())),
+                        RespValue::BulkString(Some("0.1.0".into())),
                     ),
                     (
-                        RespValue::BulkString(Some("proto".to_string())),
+                        RespValue::BulkString(Some("proto".into())),
                         RespValue::Integer(3),
                     ),
                     (
-                        RespValue::BulkString(Some("mode".to_string())),
-                        RespValue::BulkString(Some("standalone".to_string())),
+                        RespValue::BulkString(Some("mode".into())),
+                        RespValue::BulkString(Some("standalone".into())),
                     ),
                     (
-                        RespValue::BulkString(Some("role".to_string())),
-                        RespValue::BulkString(Some("master".to_string())),
+                        RespValue::BulkString(Some("role".into())),
+                        RespValue::BulkString(Some("master".into())),
                     ),
                 ])
             }
             ProtocolVersion::Resp2 => {
                 // RESP2: Return Array (key1, value1, key2, value2, ...)
                 RespValue::Array(Some(vec![
-                    RespValue::BulkString(Some("server".to_string())),
-                    RespValue::BulkString(Some("coral-redis".to_string())),
-                    RespValue::BulkString(Some("version".to_string())),
-                    RespValue::BulkString(Some("0.1.0".to_string())),
-                    RespValue::BulkString(Some("proto".to_string())),
+                    RespValue::BulkString(Some("server".into())),
+                    RespValue::BulkString(Some("coral-redis".into())),
+                    RespValue::BulkString(Some("version".into())),
+                    RespValue::BulkString(Some("0.1.0".into())),
+                    RespValue::BulkString(Some("proto".into())),
                     RespValue::Integer(2),
-                    RespValue::BulkString(Some("mode".to_string())),
-                    RespValue::BulkString(Some("standalone".to_string())),
-                    RespValue::BulkString(Some("role".to_string())),
-                    RespValue::BulkString(Some("master".to_string())),
+                    RespValue::BulkString(Some("mode".into())),
+                    RespValue::BulkString(Some("standalone".into())),
+                    RespValue::BulkString(Some("role".into())),
+                    RespValue::BulkString(Some("master".into())),
                 ]))
             }
         }
     }
 }
 
+/// Parse a numeric argument from raw bytes. Non-UTF-8 input never parses.
+fn parse_utf8<T: std::str::FromStr>(bytes: &[u8]) -> Option<T> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+/// Extract all arguments as byte strings. Fails if any argument is not a bulk string.
+fn bulk_args(args: &[RespValue]) -> Result<Vec<&Bytes>, RespValue> {
+    args.iter()
+        .map(|arg| match arg {
+            RespValue::BulkString(Some(data)) => Ok(data),
+            _ => Err(RespValue::Error("ERR invalid argument type".to_string())),
+        })
+        .collect()
+}
+
+/// Deduplicate keys for `modify_many`, returning the distinct keys and the
+/// slot index of each requested key.
+fn distinct_keys<'a>(keys: &[&'a Bytes]) -> (Vec<&'a [u8]>, Vec<usize>) {
+    let mut distinct: Vec<&[u8]> = Vec::new();
+    let indexes = keys
+        .iter()
+        .map(
+            |key| match distinct.iter().position(|k| *k == key.as_ref()) {
+                Some(index) => index,
+                None => {
+                    distinct.push(key);
+                    distinct.len() - 1
+                }
+            },
+        )
+        .collect();
+    (distinct, indexes)
+}
+
+fn unknown_command(name: &RespValue) -> RespValue {
+    let name = match name {
+        RespValue::BulkString(Some(name)) => String::from_utf8_lossy(name),
+        _ => "".into(),
+    };
+    Metrics::get().record_error("unknown_command", Some(&name));
+    RespValue::Error(format!("Unknown command: {}", name))
+}
+
+fn wrong_args(command: &str) -> RespValue {
+    RespValue::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        command
+    ))
+}
+
+fn syntax_error() -> RespValue {
+    RespValue::Error("ERR syntax error".to_string())
+}
+
+/// Parse a Redis integer argument.
+fn parse_int(arg: &[u8]) -> Result<i64, RespValue> {
+    parse_utf8::<i64>(arg)
+        .ok_or_else(|| RespValue::Error("ERR value is not an integer or out of range".to_string()))
+}
+
+/// Most picks a negative count of SRANDMEMBER or HRANDFIELD may ask for.
+/// Those picks may repeat, so the reply is not bounded by the key's size,
+/// and it is built in memory before it is sent.
+const MAX_RANDOM_PICKS: i64 = 10_000_000;
+
+/// Parse the count of SRANDMEMBER or HRANDFIELD. Redis rejects negative
+/// counts below `-(i64::MAX / 2)` as out of range; this rejects everything
+/// below `-MAX_RANDOM_PICKS` with the same error, before any memory is
+/// allocated for the reply.
+fn parse_random_count(arg: &[u8]) -> Result<i64, RespValue> {
+    let count = parse_int(arg)?;
+    if count < -MAX_RANDOM_PICKS {
+        return Err(RespValue::Error("ERR value is out of range".to_string()));
+    }
+    Ok(count)
+}
+
+/// Parse a Redis float argument (accepts `inf`/`-inf`, rejects NaN).
+fn parse_float(arg: &[u8]) -> Result<f64, RespValue> {
+    parse_utf8::<f64>(arg)
+        .filter(|f| !f.is_nan())
+        .ok_or_else(|| RespValue::Error("ERR value is not a valid float".to_string()))
+}
+
+/// Resolve an inclusive `start..=stop` range with negative (from the end)
+/// indices against a sequence of `len` elements, Redis style. Returns `None`
+/// if the range is empty.
+fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+/// Format a float the way Redis replies with it (`inf`, `-inf`, shortest repr).
+fn format_float(value: f64) -> String {
+    if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        format!("{}", value)
+    }
+}
+
+/// Resolve an expire amount to an absolute deadline: seconds or
+/// milliseconds, relative to now or since the Unix epoch. Deadlines before
+/// the epoch clamp to it; ones that overflow milliseconds are rejected.
+fn expire_deadline(
+    amount: i64,
+    millis: bool,
+    absolute: bool,
+    command: &str,
+) -> Result<SystemTime, RespValue> {
+    let ms = if millis {
+        Some(amount)
+    } else {
+        amount.checked_mul(1000)
+    };
+    let ms = if absolute {
+        ms
+    } else {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as i64);
+        ms.and_then(|ms| ms.checked_add(now_ms))
+    };
+    ms.map(|ms| UNIX_EPOCH + Duration::from_millis(ms.max(0) as u64))
+        .ok_or_else(|| invalid_expire_time(command))
+}
+
+fn invalid_expire_time(command: &str) -> RespValue {
+    RespValue::Error(format!("ERR invalid expire time in '{}' command", command))
+}
+
+/// Convert a storage failure into a client reply, recording metrics.
+fn storage_error(operation: &str, e: StorageError) -> RespValue {
+    match e {
+        StorageError::WrongType => RespValue::Error(e.to_string()),
+        e => {
+            Metrics::get().record_storage_error(operation, "storage", "operation_failed");
+            warn!("{} failed: {}", operation, e);
+            RespValue::Error(format!("ERR {} failed: {}", operation, e))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::storage::memory::MemoryStorage;
+    use bytes::Bytes;
     use std::sync::Arc;
 
     fn create_handler() -> Handler {
@@ -643,7 +2082,7 @@ mod tests {
     #[tokio::test]
     async fn test_ping_with_message() {
         let handler = create_handler();
-        let args = vec![RespValue::BulkString(Some("hello".to_string()))];
+        let args = vec![RespValue::BulkString(Some("hello".into()))];
         let result = handler.handle_ping(&args).await;
 
         match result {
@@ -658,8 +2097,8 @@ mod tests {
 
         // SET key value
         let set_args = vec![
-            RespValue::BulkString(Some("mykey".to_string())),
-            RespValue::BulkString(Some("myvalue".to_string())),
+            RespValue::BulkString(Some("mykey".into())),
+            RespValue::BulkString(Some("myvalue".into())),
         ];
         let set_result = handler.handle_set(&set_args).await;
 
@@ -669,7 +2108,7 @@ mod tests {
         }
 
         // GET key
-        let get_args = vec![RespValue::BulkString(Some("mykey".to_string()))];
+        let get_args = vec![RespValue::BulkString(Some("mykey".into()))];
         let get_result = handler.handle_get(&get_args).await;
 
         match get_result {
@@ -678,10 +2117,32 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_set_get_binary() {
+        let handler = create_handler();
+        let key = Bytes::from_static(b"\xff\x00key");
+        let value = Bytes::from_static(b"\x89PNG\r\n\x1a\n\x00\xfe");
+
+        let set_args = vec![
+            RespValue::BulkString(Some(key.clone())),
+            RespValue::BulkString(Some(value.clone())),
+        ];
+        assert!(matches!(
+            handler.handle_set(&set_args).await,
+            RespValue::SimpleString(_)
+        ));
+
+        let get_args = vec![RespValue::BulkString(Some(key))];
+        match handler.handle_get(&get_args).await {
+            RespValue::BulkString(Some(v)) => assert_eq!(v, value),
+            _ => panic!("Expected BulkString with value"),
+        }
+    }
+
     #[tokio::test]
     async fn test_get_nonexistent() {
         let handler = create_handler();
-        let args = vec![RespValue::BulkString(Some("nonexistent".to_string()))];
+        let args = vec![RespValue::BulkString(Some("nonexistent".into()))];
         let result = handler.handle_get(&args).await;
 
         match result {
@@ -696,13 +2157,13 @@ mod tests {
 
         // Set a key first
         let set_args = vec![
-            RespValue::BulkString(Some("key1".to_string())),
-            RespValue::BulkString(Some("value1".to_string())),
+            RespValue::BulkString(Some("key1".into())),
+            RespValue::BulkString(Some("value1".into())),
         ];
         handler.handle_set(&set_args).await;
 
         // Delete the key
-        let del_args = vec![RespValue::BulkString(Some("key1".to_string()))];
+        let del_args = vec![RespValue::BulkString(Some("key1".into()))];
         let result = handler.handle_del(&del_args).await;
 
         match result {
@@ -711,7 +2172,7 @@ mod tests {
         }
 
         // Try to delete non-existent key
-        let del_args = vec![RespValue::BulkString(Some("nonexistent".to_string()))];
+        let del_args = vec![RespValue::BulkString(Some("nonexistent".into()))];
         let result = handler.handle_del(&del_args).await;
 
         match result {
@@ -726,13 +2187,13 @@ mod tests {
 
         // Set a key
         let set_args = vec![
-            RespValue::BulkString(Some("key1".to_string())),
-            RespValue::BulkString(Some("value1".to_string())),
+            RespValue::BulkString(Some("key1".into())),
+            RespValue::BulkString(Some("value1".into())),
         ];
         handler.handle_set(&set_args).await;
 
         // Check if key exists
-        let exists_args = vec![RespValue::BulkString(Some("key1".to_string()))];
+        let exists_args = vec![RespValue::BulkString(Some("key1".into()))];
         let result = handler.handle_exists(&exists_args).await;
 
         match result {
@@ -741,7 +2202,7 @@ mod tests {
         }
 
         // Check non-existent key
-        let exists_args = vec![RespValue::BulkString(Some("nonexistent".to_string()))];
+        let exists_args = vec![RespValue::BulkString(Some("nonexistent".into()))];
         let result = handler.handle_exists(&exists_args).await;
 
         match result {
@@ -762,8 +2223,8 @@ mod tests {
 
         // Add some keys
         let set_args = vec![
-            RespValue::BulkString(Some("key1".to_string())),
-            RespValue::BulkString(Some("value1".to_string())),
+            RespValue::BulkString(Some("key1".into())),
+            RespValue::BulkString(Some("value1".into())),
         ];
         handler.handle_set(&set_args).await;
 
@@ -780,8 +2241,8 @@ mod tests {
 
         // Add some keys
         let set_args = vec![
-            RespValue::BulkString(Some("key1".to_string())),
-            RespValue::BulkString(Some("value1".to_string())),
+            RespValue::BulkString(Some("key1".into())),
+            RespValue::BulkString(Some("value1".into())),
         ];
         handler.handle_set(&set_args).await;
 
@@ -839,7 +2300,7 @@ mod tests {
         let mut handler = create_handler();
 
         // Request RESP2 protocol
-        let args = vec![RespValue::BulkString(Some("2".to_string()))];
+        let args = vec![RespValue::BulkString(Some("2".into()))];
         let result = handler.handle_hello(&args).await;
 
         // Should get Array response (RESP2 format)
@@ -859,7 +2320,7 @@ mod tests {
         let mut handler = create_handler();
 
         // Request RESP3 protocol
-        let args = vec![RespValue::BulkString(Some("3".to_string()))];
+        let args = vec![RespValue::BulkString(Some("3".into()))];
         let result = handler.handle_hello(&args).await;
 
         // Should get Map response (RESP3 format)
@@ -889,7 +2350,7 @@ mod tests {
         let mut handler = create_handler();
 
         // Request invalid protocol version
-        let args = vec![RespValue::BulkString(Some("99".to_string()))];
+        let args = vec![RespValue::BulkString(Some("99".into()))];
         let result = handler.handle_hello(&args).await;
 
         // Should get error
@@ -909,8 +2370,8 @@ mod tests {
         let handler = create_handler();
 
         let args = vec![
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("port".to_string())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("port".into())),
         ];
         let result = handler.handle_config(&args).await;
 
@@ -934,8 +2395,8 @@ mod tests {
         let handler = create_handler();
 
         let args = vec![
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("bind".to_string())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("bind".into())),
         ];
         let result = handler.handle_config(&args).await;
 
@@ -959,9 +2420,9 @@ mod tests {
         let handler = create_handler();
 
         let args = vec![
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("port".to_string())),
-            RespValue::BulkString(Some("bind".to_string())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("port".into())),
+            RespValue::BulkString(Some("bind".into())),
         ];
         let result = handler.handle_config(&args).await;
 
@@ -978,8 +2439,8 @@ mod tests {
         let handler = create_handler();
 
         let args = vec![
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("storage-backend".to_string())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("storage-backend".into())),
         ];
         let result = handler.handle_config(&args).await;
 
@@ -1003,8 +2464,8 @@ mod tests {
         let handler = create_handler();
 
         let args = vec![
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("unknown-param".to_string())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("unknown-param".into())),
         ];
         let result = handler.handle_config(&args).await;
 
@@ -1022,8 +2483,8 @@ mod tests {
         let handler = create_handler();
 
         let args = vec![
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("*".to_string())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("*".into())),
         ];
         let result = handler.handle_config(&args).await;
 
@@ -1052,11 +2513,7 @@ mod tests {
     async fn test_config_invalid_subcommand() {
         let handler = create_handler();
 
-        let args = vec![
-            RespValue::BulkString(Some("SET".to_string())),
-            RespValue::BulkString(Some("port".to_string())),
-            RespValue::BulkString(Some("8080".to_string())),
-        ];
+        let args = vec![RespValue::BulkString(Some("REWRITE".into()))];
         let result = handler.handle_config(&args).await;
 
         match result {
@@ -1072,8 +2529,8 @@ mod tests {
         let handler = create_handler();
 
         let args = vec![
-            RespValue::BulkString(Some("get".to_string())),
-            RespValue::BulkString(Some("PORT".to_string())),
+            RespValue::BulkString(Some("get".into())),
+            RespValue::BulkString(Some("PORT".into())),
         ];
         let result = handler.handle_config(&args).await;
 
@@ -1084,4 +2541,492 @@ mod tests {
             _ => panic!("Expected Array response"),
         }
     }
+
+    async fn run(handler: &mut Handler, args: &[&str]) -> RespValue {
+        let parts = args
+            .iter()
+            .map(|arg| RespValue::BulkString(Some(Bytes::from(arg.to_string()))))
+            .collect();
+        handler.handle_command(RespValue::Array(Some(parts))).await
+    }
+
+    /// Channel and payload of each message delivered to `handler`.
+    fn received(handler: &mut Handler) -> Vec<(String, String)> {
+        let mut messages = Vec::new();
+        while let Some(message) = handler.subscriber.try_receive() {
+            let RespValue::Array(Some(items)) = handler.push_frame(message) else {
+                panic!("Expected Array");
+            };
+            let text = |item: &RespValue| match item {
+                RespValue::BulkString(Some(b)) => String::from_utf8_lossy(b).into_owned(),
+                other => panic!("Expected BulkString, got {:?}", other),
+            };
+            let n = items.len();
+            messages.push((text(&items[n - 2]), text(&items[n - 1])));
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_config_set_notify_keyspace_events() {
+        let mut handler = create_handler();
+
+        assert!(matches!(
+            run(
+                &mut handler,
+                &["CONFIG", "SET", "notify-keyspace-events", "KEA"]
+            )
+            .await,
+            RespValue::SimpleString(_)
+        ));
+        let RespValue::Array(Some(items)) =
+            run(&mut handler, &["CONFIG", "GET", "notify-keyspace-events"]).await
+        else {
+            panic!("Expected Array");
+        };
+        assert!(matches!(&items[1], RespValue::BulkString(Some(v)) if v.as_ref() == b"AKE"));
+
+        // Nothing is applied if any value is invalid
+        assert!(matches!(
+            run(&mut handler, &["CONFIG", "SET", "notify-keyspace-events", "Ex", "notify-keyspace-events", "Eq"]).await,
+            RespValue::Error(e) if e.contains("Invalid event class character")
+        ));
+        assert!(matches!(
+            run(&mut handler, &["CONFIG", "SET", "port", "1"]).await,
+            RespValue::Error(e) if e.starts_with("ERR Unknown option")
+        ));
+        assert!(matches!(
+            run(&mut handler, &["CONFIG", "SET", "notify-keyspace-events"]).await,
+            RespValue::Error(e) if e.contains("'config|set'")
+        ));
+        assert_eq!(
+            handler.context.keyspace_events.flags(),
+            notify::parse_flags("AKE").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_notifications() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let context = Arc::new(ServerContext::new());
+        storage.set_expiry_listener(context.expiry_listener());
+        let config = Arc::new(Config::default());
+        let new_handler = || {
+            Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            )
+        };
+        let (mut client, mut listener) = (new_handler(), new_handler());
+        run(&mut listener, &["PSUBSCRIBE", "__key*__:*"]).await;
+
+        // Off by default
+        run(&mut client, &["SET", "k", "v"]).await;
+        assert!(received(&mut listener).is_empty());
+
+        run(
+            &mut client,
+            &["CONFIG", "SET", "notify-keyspace-events", "KEA"],
+        )
+        .await;
+        run(&mut client, &["SET", "k", "v", "EX", "100"]).await;
+        assert_eq!(
+            received(&mut listener),
+            [
+                ("__keyspace@0__:k".to_string(), "set".to_string()),
+                ("__keyevent@0__:set".to_string(), "k".to_string()),
+                ("__keyspace@0__:k".to_string(), "expire".to_string()),
+                ("__keyevent@0__:expire".to_string(), "k".to_string()),
+            ]
+        );
+
+        // Only the classes asked for, on the channels asked for
+        run(
+            &mut client,
+            &["CONFIG", "SET", "notify-keyspace-events", "El"],
+        )
+        .await;
+        run(&mut client, &["INCR", "n"]).await;
+        run(&mut client, &["RPUSH", "list", "a", "b"]).await;
+        run(&mut client, &["LPOP", "list", "2"]).await;
+        assert_eq!(
+            received(&mut listener),
+            [
+                ("__keyevent@0__:rpush".to_string(), "list".to_string()),
+                ("__keyevent@0__:lpop".to_string(), "list".to_string()),
+            ]
+        );
+
+        // Emptying a key deletes it; failed or no-op writes report nothing
+        run(
+            &mut client,
+            &["CONFIG", "SET", "notify-keyspace-events", "Egsh"],
+        )
+        .await;
+        run(&mut client, &["SADD", "set", "a"]).await;
+        run(&mut client, &["SREM", "set", "a", "b"]).await;
+        run(&mut client, &["SREM", "set", "a"]).await;
+        run(&mut client, &["HSET", "set", "f"]).await;
+        run(&mut client, &["DEL", "k", "missing"]).await;
+        assert_eq!(
+            received(&mut listener),
+            [
+                ("__keyevent@0__:sadd".to_string(), "set".to_string()),
+                ("__keyevent@0__:srem".to_string(), "set".to_string()),
+                ("__keyevent@0__:del".to_string(), "set".to_string()),
+                ("__keyevent@0__:del".to_string(), "k".to_string()),
+            ]
+        );
+
+        // Key misses and new keys are left out of A
+        run(
+            &mut client,
+            &["CONFIG", "SET", "notify-keyspace-events", "EAmn"],
+        )
+        .await;
+        run(&mut client, &["GET", "nothing"]).await;
+        run(&mut client, &["APPEND", "s", "x"]).await;
+        assert_eq!(
+            received(&mut listener),
+            [
+                ("__keyevent@0__:keymiss".to_string(), "nothing".to_string()),
+                ("__keyevent@0__:new".to_string(), "s".to_string()),
+                ("__keyevent@0__:append".to_string(), "s".to_string()),
+            ]
+        );
+
+        run(
+            &mut client,
+            &["CONFIG", "SET", "notify-keyspace-events", "Ex"],
+        )
+        .await;
+        run(&mut client, &["SET", "short", "v", "PX", "1"]).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        run(&mut client, &["GET", "short"]).await;
+        assert_eq!(
+            received(&mut listener),
+            [("__keyevent@0__:expired".to_string(), "short".to_string())]
+        );
+    }
+
+    /// Keys of each invalidation delivered to `handler`, None for all keys.
+    fn invalidations(handler: &mut Handler) -> Vec<Option<Vec<String>>> {
+        let mut invalidations = Vec::new();
+        while let Some(message) = handler.subscriber.try_receive() {
+            let items = match handler.message_frame(message) {
+                Some(RespValue::Push(items)) | Some(RespValue::Array(Some(items))) => items,
+                None => continue,
+                other => panic!("Expected Push or Array, got {:?}", other),
+            };
+            invalidations.push(match items.last() {
+                Some(RespValue::Array(Some(keys))) => Some(
+                    keys.iter()
+                        .map(|key| match key {
+                            RespValue::BulkString(Some(b)) => {
+                                String::from_utf8_lossy(b).into_owned()
+                            }
+                            other => panic!("Expected BulkString, got {:?}", other),
+                        })
+                        .collect(),
+                ),
+                Some(RespValue::Null) | Some(RespValue::Array(None)) => None,
+                other => panic!("Expected keys, got {:?}", other),
+            });
+        }
+        invalidations
+    }
+
+    fn keys(keys: &[&str]) -> Option<Vec<String>> {
+        Some(keys.iter().map(|key| key.to_string()).collect())
+    }
+
+    #[tokio::test]
+    async fn test_client_tracking() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let context = Arc::new(ServerContext::new());
+        storage.set_expiry_listener(context.expiry_listener());
+        let config = Arc::new(Config::default());
+        let new_handler = || {
+            Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            )
+        };
+        let (mut client, mut writer) = (new_handler(), new_handler());
+        run(&mut client, &["HELLO", "3"]).await;
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "GETREDIR"]).await,
+            RespValue::Integer(-1)
+        ));
+        run(&mut client, &["CLIENT", "TRACKING", "ON"]).await;
+
+        // Keys read are invalidated once, on their next modification
+        run(&mut writer, &["SET", "a", "1"]).await;
+        run(&mut client, &["GET", "a"]).await;
+        run(&mut client, &["EXISTS", "b"]).await;
+        run(&mut writer, &["SET", "a", "2"]).await;
+        run(&mut writer, &["SET", "a", "3"]).await;
+        run(&mut writer, &["SET", "c", "3"]).await;
+        run(&mut writer, &["SET", "b", "3"]).await;
+        assert_eq!(invalidations(&mut client), [keys(&["a"]), keys(&["b"])]);
+
+        // Including by the client itself, unless NOLOOP; writes cache nothing
+        run(&mut client, &["MGET", "a", "b"]).await;
+        run(&mut client, &["INCR", "n"]).await;
+        run(&mut client, &["DEL", "a"]).await;
+        run(&mut writer, &["SET", "n", "1"]).await;
+        assert_eq!(invalidations(&mut client), [keys(&["a"])]);
+        run(&mut client, &["CLIENT", "TRACKING", "ON", "NOLOOP"]).await;
+        run(&mut client, &["DEL", "b"]).await;
+        assert!(invalidations(&mut client).is_empty());
+
+        // Expiring and flushing invalidate too
+        run(&mut writer, &["SET", "short", "v", "PX", "50"]).await;
+        run(&mut client, &["GET", "short"]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        run(&mut writer, &["GET", "short"]).await;
+        run(&mut writer, &["FLUSHDB"]).await;
+        assert_eq!(invalidations(&mut client), [keys(&["short"]), None]);
+
+        // OPTIN caches only the command after CLIENT CACHING YES
+        run(&mut client, &["CLIENT", "TRACKING", "OFF"]).await;
+        run(&mut client, &["CLIENT", "TRACKING", "ON", "OPTIN"]).await;
+        run(&mut client, &["GET", "x"]).await;
+        run(&mut client, &["CLIENT", "CACHING", "YES"]).await;
+        run(&mut client, &["GET", "y"]).await;
+        run(&mut writer, &["MSET", "x", "1", "y", "1"]).await;
+        assert_eq!(invalidations(&mut client), [keys(&["y"])]);
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "CACHING", "NO"]).await,
+            RespValue::Error(e) if e.contains("OPTOUT mode")
+        ));
+
+        run(&mut client, &["CLIENT", "TRACKING", "OFF"]).await;
+        run(&mut client, &["GET", "x"]).await;
+        run(&mut writer, &["SET", "x", "2"]).await;
+        assert!(invalidations(&mut client).is_empty());
+        assert_eq!(context.tracking.tracking_clients(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_client_tracking_bcast() {
+        let context = Arc::new(ServerContext::new());
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let config = Arc::new(Config::default());
+        let mut client = Handler::new_with_context(
+            Arc::clone(&storage),
+            Arc::clone(&config),
+            Arc::clone(&context),
+        );
+        let mut writer = Handler::new_with_context(storage, config, context);
+        run(&mut client, &["HELLO", "3"]).await;
+
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "TRACKING", "ON", "PREFIX", "user:"]).await,
+            RespValue::Error(e) if e == "ERR PREFIX option requires BCAST mode to be enabled"
+        ));
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "TRACKING", "ON", "BCAST", "OPTIN"]).await,
+            RespValue::Error(e) if e == "ERR OPTIN and OPTOUT are not compatible with BCAST"
+        ));
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "TRACKING", "ON", "REDIRECT", "9999"]).await,
+            RespValue::Error(e) if e == "ERR The client ID you want redirect to does not exist"
+        ));
+        let on = [
+            "CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:", "PREFIX", "item:",
+        ];
+        assert!(matches!(
+            run(&mut client, &on).await,
+            RespValue::SimpleString(_)
+        ));
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:1"]).await,
+            RespValue::Error(e) if e.contains("overlaps with an existing prefix 'user:'")
+        ));
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "TRACKING", "ON"]).await,
+            RespValue::Error(e) if e.contains("switch BCAST mode")
+        ));
+
+        // Keys matching a prefix are invalidated without being read
+        run(&mut writer, &["SET", "user:1", "a"]).await;
+        run(&mut writer, &["SET", "order:1", "a"]).await;
+        run(&mut writer, &["SADD", "item:1", "a"]).await;
+        assert_eq!(
+            invalidations(&mut client),
+            [keys(&["user:1"]), keys(&["item:1"])]
+        );
+
+        let RespValue::Map(info) = run(&mut client, &["CLIENT", "TRACKINGINFO"]).await else {
+            panic!("Expected Map");
+        };
+        assert!(matches!(&info[0].1, RespValue::Set(flags) if flags.len() == 2));
+        assert!(matches!(&info[1].1, RespValue::Integer(0)));
+        assert!(matches!(&info[2].1, RespValue::Array(Some(prefixes)) if prefixes.len() == 2));
+    }
+
+    #[tokio::test]
+    async fn test_client_tracking_resp2_redirect() {
+        let context = Arc::new(ServerContext::new());
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let config = Arc::new(Config::default());
+        let new_handler = || {
+            Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            )
+        };
+        let (mut client, mut target) = (new_handler(), new_handler());
+        let RespValue::Integer(id) = run(&mut target, &["CLIENT", "ID"]).await else {
+            panic!("Expected Integer");
+        };
+        run(&mut target, &["SUBSCRIBE", "__redis__:invalidate"]).await;
+        let redirect = ["CLIENT", "TRACKING", "ON", "REDIRECT", &id.to_string()];
+        assert!(matches!(
+            run(&mut client, &redirect).await,
+            RespValue::SimpleString(_)
+        ));
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "GETREDIR"]).await,
+            RespValue::Integer(redirected) if redirected == id
+        ));
+
+        run(&mut client, &["GET", "k"]).await;
+        run(&mut client, &["SET", "k", "v"]).await;
+        let message = target.subscriber.try_receive().unwrap();
+        let Some(RespValue::Array(Some(items))) = target.message_frame(message) else {
+            panic!("Expected Array");
+        };
+        assert!(matches!(&items[0], RespValue::BulkString(Some(b)) if b == "message"));
+        assert!(matches!(&items[1], RespValue::BulkString(Some(b)) if b == "__redis__:invalidate"));
+        assert!(matches!(&items[2], RespValue::Array(Some(keys)) if keys.len() == 1));
+        assert!(invalidations(&mut client).is_empty());
+
+        // The client hears of it when the target goes away, under RESP3
+        drop(target);
+        run(&mut client, &["HELLO", "3"]).await;
+        run(&mut client, &["GET", "k"]).await;
+        run(&mut client, &["DEL", "k"]).await;
+        let message = client.subscriber.try_receive().unwrap();
+        assert!(matches!(
+            client.message_frame(message),
+            Some(RespValue::Push(items))
+                if matches!(&items[0], RespValue::BulkString(Some(b)) if b == "tracking-redir-broken")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_client_registry() {
+        let context = Arc::new(ServerContext::new());
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let config = Arc::new(Config::default());
+        let new_handler = || {
+            Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            )
+        };
+        let (mut client, mut other) = (new_handler(), new_handler());
+        let text = |value: RespValue| match value {
+            RespValue::BulkString(Some(b)) => String::from_utf8_lossy(&b).into_owned(),
+            other => panic!("Expected BulkString, got {:?}", other),
+        };
+
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "GETNAME"]).await,
+            RespValue::BulkString(None)
+        ));
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "SETNAME", "has space"]).await,
+            RespValue::Error(e) if e.contains("cannot contain spaces")
+        ));
+        run(&mut client, &["CLIENT", "SETNAME", "worker"]).await;
+        run(&mut other, &["HELLO", "3", "SETNAME", "watcher"]).await;
+        assert_eq!(
+            text(run(&mut other, &["CLIENT", "GETNAME"]).await),
+            "watcher"
+        );
+        assert!(matches!(
+            run(&mut other, &["HELLO", "3", "SETNAME"]).await,
+            RespValue::Error(e) if e == "ERR Syntax error in HELLO option 'SETNAME'"
+        ));
+
+        run(&mut client, &["MULTI"]).await;
+        run(&mut client, &["SET", "k", "v"]).await;
+        run(&mut other, &["SUBSCRIBE", "news"]).await;
+        let info = text(run(&mut other, &["CLIENT", "INFO"]).await);
+        assert!(info.starts_with(&format!("id={} ", other.subscriber.id())));
+        assert!(info.contains(" name=watcher ") && info.contains(" flags=P "));
+        assert!(info.contains(" sub=1 ") && info.contains(" cmd=client "));
+        assert!(info.ends_with(" resp=3\n"));
+
+        let list = text(run(&mut other, &["CLIENT", "LIST"]).await);
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(" name=worker ") && lines[0].contains(" multi=1 "));
+        assert!(lines[0].contains(" flags=x ") && lines[0].contains(" cmd=set "));
+        let pubsub = text(run(&mut other, &["CLIENT", "LIST", "TYPE", "pubsub"]).await);
+        assert_eq!(pubsub.lines().count(), 1);
+        let id = client.subscriber.id().to_string();
+        let by_id = text(run(&mut other, &["CLIENT", "LIST", "ID", &id, "999"]).await);
+        assert!(by_id.starts_with(&format!("id={} ", id)));
+        run(&mut client, &["DISCARD"]).await;
+
+        // SKIPME spares the killer by default
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "KILL", "USER", "default"]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(other.client.is_killed() && !client.client.is_killed());
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "KILL", "ID", &id, "SKIPME", "no"]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(client.client.is_killed());
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "KILL", "ID", "0"]).await,
+            RespValue::Error(e) if e == "ERR client-id should be greater than 0"
+        ));
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "KILL", "127.0.0.1:1"]).await,
+            RespValue::Error(e) if e == "ERR No such client"
+        ));
+
+        drop(other);
+        let list = text(run(&mut client, &["CLIENT", "LIST"]).await);
+        assert_eq!(list.lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_pause() {
+        let context = Arc::new(ServerContext::new());
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let config = Arc::new(Config::default());
+        let mut client = Handler::new_with_context(
+            Arc::clone(&storage),
+            Arc::clone(&config),
+            Arc::clone(&context),
+        );
+        let mut writer = Handler::new_with_context(storage, config, Arc::clone(&context));
+
+        run(&mut client, &["CLIENT", "PAUSE", "60000", "WRITE"]).await;
+        // Reads and CLIENT commands go through
+        run(&mut client, &["GET", "k"]).await;
+        let write = tokio::spawn(async move { run(&mut writer, &["SET", "k", "v"]).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!write.is_finished());
+
+        run(&mut client, &["CLIENT", "UNPAUSE"]).await;
+        assert!(matches!(write.await.unwrap(), RespValue::SimpleString(_)));
+        assert!(matches!(
+            run(&mut client, &["CLIENT", "PAUSE", "-1"]).await,
+            RespValue::Error(e) if e == "ERR timeout is negative"
+        ));
+        assert!(!context.clients.is_paused());
+    }
 }
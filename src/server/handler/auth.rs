@@ -0,0 +1,229 @@
+//! Authentication: AUTH, and the AUTH option of HELLO.
+//!
+//! With `requirepass` set, a connection runs nothing but AUTH and HELLO
+//! until it authenticates as the default user with that password; there
+//! are no other users. Passwords are compared through their digests, so the
+//! time a comparison takes tells nothing about how close a guess was.
+
+use super::{syntax_error, Handler};
+use crate::protocol::RespValue;
+use bytes::Bytes;
+use sha1::{Digest, Sha1};
+
+impl Handler {
+    /// Check `password` for `username`, the default user if none, and mark
+    /// the connection authenticated if it is right. A failed attempt leaves
+    /// the connection as it was.
+    pub(super) fn authenticate(
+        &mut self,
+        username: Option<&[u8]>,
+        password: &[u8],
+    ) -> Result<(), RespValue> {
+        let wrong_pass = || {
+            RespValue::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+            )
+        };
+        if username.is_some_and(|username| username != b"default") {
+            return Err(wrong_pass());
+        }
+        match &self.config.server.requirepass {
+            // Without a password the default user accepts any
+            None => Ok(()),
+            Some(required) if digest_eq(required.as_bytes(), password) => {
+                self.authenticated = true;
+                Ok(())
+            }
+            Some(_) => Err(wrong_pass()),
+        }
+    }
+
+    /// AUTH [username] password
+    pub(super) fn handle_auth(&mut self, args: &[&Bytes]) -> RespValue {
+        let (username, password) = match args {
+            [password] => {
+                if self.config.server.requirepass.is_none() {
+                    return RespValue::Error(
+                        "ERR AUTH <password> called without any password configured for the \
+                         default user. Are you sure your configuration is correct?"
+                            .to_string(),
+                    );
+                }
+                (None, password)
+            }
+            [username, password] => (Some(username.as_ref()), password),
+            _ => return syntax_error(),
+        };
+        match self.authenticate(username, password) {
+            Ok(()) => RespValue::SimpleString("OK".to_string()),
+            Err(e) => e,
+        }
+    }
+}
+
+/// Compare two passwords in a time independent of where they differ.
+fn digest_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (Sha1::digest(a), Sha1::digest(b));
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |diff, (x, y)| diff | (x ^ y))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::protocol::ProtocolVersion;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    async fn run(handler: &mut Handler, args: &[&str]) -> RespValue {
+        let parts = args
+            .iter()
+            .map(|arg| RespValue::BulkString(Some(Bytes::from(arg.to_string()))))
+            .collect();
+        handler.handle_command(RespValue::Array(Some(parts))).await
+    }
+
+    /// A client of a server whose `requirepass` is "secret".
+    fn protected(storage: &Arc<dyn StorageBackend>) -> Handler {
+        let mut config = Config::default();
+        config.server.requirepass = Some("secret".to_string());
+        Handler::new_with_config(Arc::clone(storage), Arc::new(config))
+    }
+
+    fn is_ok(value: &RespValue) -> bool {
+        matches!(value, RespValue::SimpleString(s) if s == "OK")
+    }
+
+    fn noauth(value: &RespValue) -> bool {
+        matches!(value, RespValue::Error(e) if e == "NOAUTH Authentication required.")
+    }
+
+    fn wrong_pass(value: &RespValue) -> bool {
+        matches!(
+            value,
+            RespValue::Error(e)
+                if e == "WRONGPASS invalid username-password pair or user is disabled."
+        )
+    }
+
+    #[tokio::test]
+    async fn test_auth() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let mut client = protected(&storage);
+
+        assert!(noauth(&run(&mut client, &["GET", "k"]).await));
+        assert!(noauth(&run(&mut client, &["MULTI"]).await));
+        assert!(matches!(
+            run(&mut client, &["HELLO", "3"]).await,
+            RespValue::Error(e) if e == "NOAUTH HELLO must be called with the client already \
+                authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used \
+                to authenticate the client and select the RESP protocol version at the same time"
+        ));
+        assert!(wrong_pass(&run(&mut client, &["AUTH", "guess"]).await));
+        assert!(wrong_pass(
+            &run(&mut client, &["AUTH", "admin", "secret"]).await
+        ));
+        assert!(noauth(&run(&mut client, &["GET", "k"]).await));
+        assert!(matches!(
+            run(&mut client, &["AUTH", "secret"]).await,
+            RespValue::SimpleString(_)
+        ));
+        assert!(matches!(
+            run(&mut client, &["GET", "k"]).await,
+            RespValue::BulkString(None)
+        ));
+        // A later failure does not log the connection out
+        assert!(wrong_pass(&run(&mut client, &["AUTH", "guess"]).await));
+        assert!(!noauth(&run(&mut client, &["GET", "k"]).await));
+
+        // HELLO authenticates and switches protocol at once, or does neither
+        let mut other = protected(&storage);
+        assert!(wrong_pass(
+            &run(&mut other, &["HELLO", "3", "AUTH", "default", "guess"]).await
+        ));
+        assert_eq!(other.protocol_version(), ProtocolVersion::Resp2);
+        assert!(matches!(
+            run(&mut other, &["HELLO", "3", "AUTH", "default", "secret"]).await,
+            RespValue::Map(_)
+        ));
+        assert!(!noauth(&run(&mut other, &["GET", "k"]).await));
+
+        // Without a password, only the default user's AUTH makes sense
+        let mut open = Handler::new(storage);
+        assert!(matches!(
+            run(&mut open, &["AUTH", "secret"]).await,
+            RespValue::Error(e) if e.contains("without any password configured")
+        ));
+        assert!(matches!(
+            run(&mut open, &["AUTH", "default", "anything"]).await,
+            RespValue::SimpleString(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_auth_as_default_user_or_with_password_only() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+
+        let mut named = protected(&storage);
+        assert!(is_ok(
+            &run(&mut named, &["AUTH", "default", "secret"]).await
+        ));
+        assert!(is_ok(&run(&mut named, &["SET", "k", "v"]).await));
+
+        let mut anonymous = protected(&storage);
+        assert!(is_ok(&run(&mut anonymous, &["AUTH", "secret"]).await));
+        assert!(matches!(
+            run(&mut anonymous, &["GET", "k"]).await,
+            RespValue::BulkString(Some(v)) if v == "v"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_auth_wrong_password() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let mut client = protected(&storage);
+
+        assert!(wrong_pass(&run(&mut client, &["AUTH", "guess"]).await));
+        assert!(wrong_pass(
+            &run(&mut client, &["AUTH", "default", "guess"]).await
+        ));
+        // Passwords are compared whole, not by prefix
+        assert!(wrong_pass(&run(&mut client, &["AUTH", "secre"]).await));
+        assert!(wrong_pass(&run(&mut client, &["AUTH", "secrets"]).await));
+        assert!(wrong_pass(&run(&mut client, &["AUTH", ""]).await));
+        assert!(noauth(&run(&mut client, &["PING"]).await));
+    }
+
+    #[tokio::test]
+    async fn test_noauth_gates_ordinary_commands() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let mut client = protected(&storage);
+
+        for command in [
+            &["PING"][..],
+            &["SET", "k", "v"],
+            &["DEL", "k"],
+            &["KEYS", "*"],
+            &["SUBSCRIBE", "channel"],
+            &["CLIENT", "SETNAME", "name"],
+            &["EVAL", "return 1", "0"],
+            &["EXEC"],
+        ] {
+            assert!(noauth(&run(&mut client, command).await), "{command:?}");
+        }
+        // None of them ran
+        assert!(storage.get(b"k").await.unwrap().is_none());
+        assert!(!client.in_subscribed_mode());
+
+        assert!(is_ok(&run(&mut client, &["AUTH", "secret"]).await));
+        assert!(is_ok(&run(&mut client, &["SET", "k", "v"]).await));
+        assert!(matches!(
+            run(&mut client, &["PING"]).await,
+            RespValue::SimpleString(s) if s == "PONG"
+        ));
+    }
+}
@@ -0,0 +1,822 @@
+//! Bitmap commands: SETBIT, GETBIT, BITCOUNT, BITPOS, BITOP and BITFIELD.
+//!
+//! Bitmaps are plain string values. Bit 0 is the most significant bit of the
+//! first byte, and strings grow with zero bytes as bits are set, as in Redis.
+
+use super::string::{store_string, string_value};
+use super::{distinct_keys, parse_int, storage_error, syntax_error, wrong_args, Handler};
+use crate::protocol::RespValue;
+use crate::server::notify::NOTIFY_STRING;
+use crate::storage::{StorageError, StorageValue, ValueSlot};
+use bytes::Bytes;
+
+/// Highest bit offset: bitmaps are limited to 512MB, as in Redis.
+const MAX_BIT_OFFSET: u64 = (1 << 32) - 1;
+
+fn bit_offset_error() -> RespValue {
+    RespValue::Error("ERR bit offset is not an integer or out of range".to_string())
+}
+
+/// Parse a SETBIT/GETBIT bit offset.
+fn parse_bit_offset(arg: &[u8]) -> Result<u64, RespValue> {
+    parse_int(arg)
+        .ok()
+        .filter(|offset| (0..=MAX_BIT_OFFSET as i64).contains(offset))
+        .map(|offset| offset as u64)
+        .ok_or_else(bit_offset_error)
+}
+
+fn get_bit(data: &[u8], offset: u64) -> bool {
+    data.get((offset / 8) as usize)
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+/// Set a bit; `data` must already cover `offset`.
+fn set_bit(data: &mut [u8], offset: u64, on: bool) {
+    let mask = 0x80 >> (offset % 8);
+    let byte = &mut data[(offset / 8) as usize];
+    if on {
+        *byte |= mask;
+    } else {
+        *byte &= !mask;
+    }
+}
+
+/// Read a string slot as a mutable byte vector at least `len` bytes long.
+fn grown_bytes(slot: &ValueSlot, len: usize) -> Result<Vec<u8>, StorageError> {
+    let mut data = string_value(slot)?.map_or_else(Vec::new, |data| data.to_vec());
+    if data.len() < len {
+        data.resize(len, 0);
+    }
+    Ok(data)
+}
+
+/// Unit of BITCOUNT/BITPOS ranges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeUnit {
+    Byte,
+    Bit,
+}
+
+/// Parse the optional `BYTE | BIT` argument after a range.
+fn parse_range_unit(args: &[&Bytes]) -> Result<RangeUnit, RespValue> {
+    match args {
+        [] => Ok(RangeUnit::Byte),
+        [unit] if unit.eq_ignore_ascii_case(b"BYTE") => Ok(RangeUnit::Byte),
+        [unit] if unit.eq_ignore_ascii_case(b"BIT") => Ok(RangeUnit::Bit),
+        _ => Err(syntax_error()),
+    }
+}
+
+/// Clamp a range with negative indexes to `0..len`, inclusive; `None` when
+/// it is empty.
+fn clamp_range(start: i64, end: i64, len: u64) -> Option<(u64, u64)> {
+    let len = len as i64;
+    let start = if start < 0 { start + len } else { start }.max(0);
+    let end = if end < 0 { end + len } else { end }.max(0).min(len - 1);
+    (start <= end).then_some((start as u64, end as u64))
+}
+
+/// Number of set bits between two bit offsets, inclusive.
+fn count_bits(data: &[u8], start: u64, end: u64) -> u64 {
+    let (first, last) = ((start / 8) as usize, (end / 8) as usize);
+    data[first..=last]
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| {
+            let mut byte = byte;
+            if i == 0 {
+                byte &= 0xFF >> (start % 8);
+            }
+            if first + i == last {
+                byte &= 0xFF << (7 - end % 8);
+            }
+            byte.count_ones() as u64
+        })
+        .sum()
+}
+
+/// Offset of the first bit equal to `bit` between two offsets, inclusive.
+fn find_bit(data: &[u8], start: u64, end: u64, bit: bool) -> Option<u64> {
+    let skip = if bit { 0x00 } else { 0xFF };
+    let mut offset = start;
+    while offset <= end {
+        // Skip whole bytes that cannot contain the bit
+        if offset.is_multiple_of(8) && offset + 7 <= end && data[(offset / 8) as usize] == skip {
+            offset += 8;
+            continue;
+        }
+        if get_bit(data, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+    None
+}
+
+/// BITOP operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl BitOp {
+    fn parse(arg: &[u8]) -> Option<Self> {
+        match arg.to_ascii_uppercase().as_slice() {
+            b"AND" => Some(BitOp::And),
+            b"OR" => Some(BitOp::Or),
+            b"XOR" => Some(BitOp::Xor),
+            b"NOT" => Some(BitOp::Not),
+            _ => None,
+        }
+    }
+
+    /// Combine the sources, shorter ones being padded with zero bytes.
+    fn apply(self, sources: &[&[u8]]) -> Vec<u8> {
+        let len = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+        let byte = |source: &[u8], i: usize| source.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| {
+                let mut bytes = sources.iter().map(|source| byte(source, i));
+                let first = bytes.next().unwrap_or(0);
+                match self {
+                    BitOp::And => bytes.fold(first, |acc, b| acc & b),
+                    BitOp::Or => bytes.fold(first, |acc, b| acc | b),
+                    BitOp::Xor => bytes.fold(first, |acc, b| acc ^ b),
+                    BitOp::Not => !first,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Integer type of a BITFIELD field, such as `i8` or `u16`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FieldType {
+    signed: bool,
+    bits: u32,
+}
+
+impl FieldType {
+    fn parse(arg: &[u8]) -> Result<Self, RespValue> {
+        let signed = match arg.first() {
+            Some(b'i' | b'I') => true,
+            Some(b'u' | b'U') => false,
+            _ => return Err(Self::error()),
+        };
+        let bits = std::str::from_utf8(&arg[1..])
+            .ok()
+            .and_then(|bits| bits.parse::<u32>().ok())
+            .filter(|&bits| bits >= 1 && bits <= if signed { 64 } else { 63 })
+            .ok_or_else(Self::error)?;
+        Ok(Self { signed, bits })
+    }
+
+    fn error() -> RespValue {
+        RespValue::Error(
+            "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+                .to_string(),
+        )
+    }
+
+    /// Smallest and largest values of the type.
+    fn limits(self) -> (i128, i128) {
+        if self.signed {
+            (-(1i128 << (self.bits - 1)), (1i128 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1i128 << self.bits) - 1)
+        }
+    }
+
+    /// Interpret raw field bits as a value of the type.
+    fn decode(self, raw: u64) -> i128 {
+        if self.signed && raw & (1 << (self.bits - 1)) != 0 {
+            raw as i128 - (1i128 << self.bits)
+        } else {
+            raw as i128
+        }
+    }
+
+    /// Apply an overflow policy to `value`; `None` means FAIL was triggered.
+    fn fit(self, value: i128, overflow: Overflow) -> Option<i128> {
+        let (min, max) = self.limits();
+        if (min..=max).contains(&value) {
+            return Some(value);
+        }
+        match overflow {
+            Overflow::Wrap => Some(self.decode(value.rem_euclid(1i128 << self.bits) as u64)),
+            Overflow::Sat => Some(value.clamp(min, max)),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+/// BITFIELD OVERFLOW policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldOp {
+    Get,
+    Set(i64),
+    IncrBy(i64),
+}
+
+/// One BITFIELD subcommand, with the overflow policy in effect for it.
+#[derive(Debug, Clone, Copy)]
+struct FieldCommand {
+    op: FieldOp,
+    ty: FieldType,
+    offset: u64,
+    overflow: Overflow,
+}
+
+/// Parse a field offset: bits, or `#N` for N times the field width.
+fn parse_field_offset(arg: &[u8], ty: FieldType) -> Result<u64, RespValue> {
+    let (digits, multiplier) = match arg.strip_prefix(b"#") {
+        Some(digits) => (digits, ty.bits as u64),
+        None => (arg, 1),
+    };
+    let offset = parse_int(digits)
+        .ok()
+        .and_then(|offset| u64::try_from(offset).ok())
+        .and_then(|offset| offset.checked_mul(multiplier))
+        .ok_or_else(bit_offset_error)?;
+    if offset + ty.bits as u64 - 1 > MAX_BIT_OFFSET {
+        return Err(bit_offset_error());
+    }
+    Ok(offset)
+}
+
+/// Parse BITFIELD subcommands; `read_only` restricts them to GET.
+fn parse_field_commands(args: &[&Bytes], read_only: bool) -> Result<Vec<FieldCommand>, RespValue> {
+    let mut commands = Vec::new();
+    let mut overflow = Overflow::Wrap;
+    let mut i = 0;
+    while i < args.len() {
+        let subcommand = args[i].to_ascii_uppercase();
+        let arity = match subcommand.as_slice() {
+            b"GET" => 3,
+            b"SET" | b"INCRBY" => 4,
+            b"OVERFLOW" => 2,
+            _ => return Err(syntax_error()),
+        };
+        if i + arity > args.len() {
+            return Err(syntax_error());
+        }
+        if read_only && subcommand != b"GET" {
+            return Err(RespValue::Error(
+                "ERR BITFIELD_RO only supports the GET subcommand".to_string(),
+            ));
+        }
+
+        if subcommand == b"OVERFLOW" {
+            overflow = match args[i + 1].to_ascii_uppercase().as_slice() {
+                b"WRAP" => Overflow::Wrap,
+                b"SAT" => Overflow::Sat,
+                b"FAIL" => Overflow::Fail,
+                _ => {
+                    return Err(RespValue::Error(
+                        "ERR Invalid OVERFLOW type specified".to_string(),
+                    ))
+                }
+            };
+        } else {
+            let ty = FieldType::parse(args[i + 1])?;
+            let offset = parse_field_offset(args[i + 2], ty)?;
+            let op = match subcommand.as_slice() {
+                b"GET" => FieldOp::Get,
+                b"SET" => FieldOp::Set(parse_int(args[i + 3])?),
+                _ => FieldOp::IncrBy(parse_int(args[i + 3])?),
+            };
+            commands.push(FieldCommand {
+                op,
+                ty,
+                offset,
+                overflow,
+            });
+        }
+        i += arity;
+    }
+    Ok(commands)
+}
+
+/// Read `bits` bits at a bit offset as an unsigned integer; missing bytes
+/// read as zero.
+fn read_field(data: &[u8], offset: u64, bits: u32) -> u64 {
+    (0..bits as u64).fold(0, |value, i| {
+        (value << 1) | get_bit(data, offset + i) as u64
+    })
+}
+
+/// Write the low `bits` bits of `value` at a bit offset.
+fn write_field(data: &mut [u8], offset: u64, bits: u32, value: u64) {
+    for i in 0..bits as u64 {
+        set_bit(data, offset + i, value >> (bits as u64 - 1 - i) & 1 == 1);
+    }
+}
+
+/// Run BITFIELD subcommands against a bitmap, returning one reply each.
+fn run_field_commands(data: &mut [u8], commands: &[FieldCommand]) -> Vec<RespValue> {
+    commands
+        .iter()
+        .map(|command| {
+            let FieldCommand {
+                op,
+                ty,
+                offset,
+                overflow,
+            } = *command;
+            let current = ty.decode(read_field(data, offset, ty.bits));
+            let (updated, reply) = match op {
+                FieldOp::Get => return RespValue::Integer(current as i64),
+                // Unsigned fields take the new value's two's complement bits,
+                // so negative values overflow, as in Redis
+                FieldOp::Set(value) if !ty.signed => {
+                    (ty.fit(value as u64 as i128, overflow), current)
+                }
+                FieldOp::Set(value) => (ty.fit(value as i128, overflow), current),
+                FieldOp::IncrBy(increment) => {
+                    let updated = ty.fit(current + increment as i128, overflow);
+                    (updated, updated.unwrap_or_default())
+                }
+            };
+            match updated {
+                Some(updated) => {
+                    write_field(data, offset, ty.bits, updated as u64);
+                    RespValue::Integer(reply as i64)
+                }
+                None => RespValue::BulkString(None),
+            }
+        })
+        .collect()
+}
+
+impl Handler {
+    /// SETBIT key offset value: the bit's previous value.
+    pub(super) async fn handle_setbit(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("setbit");
+        }
+        let offset = match parse_bit_offset(args[1]) {
+            Ok(offset) => offset,
+            Err(e) => return e,
+        };
+        let on = match args[2].as_ref() {
+            b"0" => false,
+            b"1" => true,
+            _ => return RespValue::Error("ERR bit is not an integer or out of range".to_string()),
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let mut data = grown_bytes(slot, (offset / 8) as usize + 1)?;
+                let previous = get_bit(&data, offset);
+                set_bit(&mut data, offset, on);
+                store_string(slot, data.into());
+                Ok(previous)
+            })
+            .await;
+        match result {
+            Ok(previous) => {
+                self.notify(NOTIFY_STRING, "setbit", args[0]);
+                RespValue::Integer(previous as i64)
+            }
+            Err(e) => storage_error("setbit", e),
+        }
+    }
+
+    /// GETBIT key offset
+    pub(super) async fn handle_getbit(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("getbit");
+        }
+        let offset = match parse_bit_offset(args[1]) {
+            Ok(offset) => offset,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => get_bit(value.as_string()?, offset),
+                    None => false,
+                })
+            })
+            .await;
+        match result {
+            Ok(bit) => RespValue::Integer(bit as i64),
+            Err(e) => storage_error("getbit", e),
+        }
+    }
+
+    /// BITCOUNT key [start end [BYTE | BIT]]
+    pub(super) async fn handle_bitcount(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("bitcount");
+        }
+        let range = match args.len() {
+            1 => None,
+            2 => return syntax_error(),
+            _ => match (
+                parse_int(args[1]),
+                parse_int(args[2]),
+                parse_range_unit(&args[3..]),
+            ) {
+                (Ok(start), Ok(end), Ok(unit)) => Some((start, end, unit)),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return e,
+            },
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(value) = value else {
+                    return Ok(0);
+                };
+                let data = value.as_string()?;
+                let bits = data.len() as u64 * 8;
+                let (start, end) = match range {
+                    None => (0, bits),
+                    Some((start, end, _)) if start < 0 && end < 0 && start > end => return Ok(0),
+                    Some((start, end, RangeUnit::Byte)) => {
+                        match clamp_range(start, end, data.len() as u64) {
+                            Some((start, end)) => (start * 8, end * 8 + 8),
+                            None => return Ok(0),
+                        }
+                    }
+                    Some((start, end, RangeUnit::Bit)) => match clamp_range(start, end, bits) {
+                        Some((start, end)) => (start, end + 1),
+                        None => return Ok(0),
+                    },
+                };
+                Ok(if start < end {
+                    count_bits(data, start, end - 1)
+                } else {
+                    0
+                })
+            })
+            .await;
+        match result {
+            Ok(count) => RespValue::Integer(count as i64),
+            Err(e) => storage_error("bitcount", e),
+        }
+    }
+
+    /// BITPOS key bit [start [end [BYTE | BIT]]]
+    pub(super) async fn handle_bitpos(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("bitpos");
+        }
+        let bit = match args[1].as_ref() {
+            b"0" => false,
+            b"1" => true,
+            _ => return RespValue::Error("ERR The bit argument must be 1 or 0.".to_string()),
+        };
+        let start = match args.get(2).map(|arg| parse_int(arg)).transpose() {
+            Ok(start) => start.unwrap_or(0),
+            Err(e) => return e,
+        };
+        let end = match args.get(3).map(|arg| parse_int(arg)).transpose() {
+            Ok(end) => end,
+            Err(e) => return e,
+        };
+        let unit = match parse_range_unit(args.get(4..).unwrap_or_default()) {
+            Ok(unit) => unit,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(value) = value else {
+                    // A missing key is an endless run of zeros
+                    return Ok(if bit { -1 } else { 0 });
+                };
+                let data = value.as_string()?;
+                let len = match unit {
+                    RangeUnit::Byte => data.len() as u64,
+                    RangeUnit::Bit => data.len() as u64 * 8,
+                };
+                let Some((start, last)) = clamp_range(start, end.unwrap_or(-1), len) else {
+                    return Ok(-1);
+                };
+                let (start, last) = match unit {
+                    RangeUnit::Byte => (start * 8, last * 8 + 7),
+                    RangeUnit::Bit => (start, last),
+                };
+                Ok(match find_bit(data, start, last, bit) {
+                    Some(offset) => offset as i64,
+                    // Without an explicit end, the string is followed by
+                    // zeros
+                    None if !bit && end.is_none() => last as i64 + 1,
+                    None => -1,
+                })
+            })
+            .await;
+        match result {
+            Ok(position) => RespValue::Integer(position),
+            Err(e) => storage_error("bitpos", e),
+        }
+    }
+
+    /// BITOP AND | OR | XOR | NOT destkey key [key ...]: length of the
+    /// result, which replaces the destination (deleted if empty).
+    pub(super) async fn handle_bitop(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 3 {
+            return wrong_args("bitop");
+        }
+        let Some(op) = BitOp::parse(args[0]) else {
+            return syntax_error();
+        };
+        if op == BitOp::Not && args.len() != 3 {
+            return RespValue::Error(
+                "ERR BITOP NOT must be called with a single source key.".to_string(),
+            );
+        }
+
+        // The destination may also be a source
+        let (keys, indexes) = distinct_keys(&args[1..]);
+        let result = self
+            .storage
+            .modify_many(&keys, |slots| {
+                let mut sources = Vec::with_capacity(indexes.len() - 1);
+                for &index in &indexes[1..] {
+                    sources.push(string_value(&slots[index])?.cloned().unwrap_or_default());
+                }
+                let sources: Vec<&[u8]> = sources.iter().map(|source| source.as_ref()).collect();
+                let output = op.apply(&sources);
+
+                let destination = &mut slots[indexes[0]];
+                let existed = destination.get().is_some();
+                let len = output.len();
+                if output.is_empty() {
+                    destination.delete();
+                } else {
+                    destination.set(StorageValue::new(Bytes::from(output)));
+                }
+                Ok((len, existed))
+            })
+            .await;
+        match result {
+            Ok((len, existed)) => {
+                self.notify_stored(NOTIFY_STRING, "set", args[1], len, existed);
+                RespValue::Integer(len as i64)
+            }
+            Err(e) => storage_error("bitop", e),
+        }
+    }
+
+    /// BITFIELD key [GET type offset] [SET type offset value]
+    /// [INCRBY type offset increment] [OVERFLOW WRAP | SAT | FAIL] ...
+    /// and BITFIELD_RO key [GET type offset ...]
+    pub(super) async fn handle_bitfield(&self, args: &[&Bytes], read_only: bool) -> RespValue {
+        let command = if read_only { "bitfield_ro" } else { "bitfield" };
+        if args.is_empty() {
+            return wrong_args(command);
+        }
+        let commands = match parse_field_commands(&args[1..], read_only) {
+            Ok(commands) => commands,
+            Err(e) => return e,
+        };
+
+        // Writes grow the string up front, even if they then fail to overflow
+        let write_end = commands
+            .iter()
+            .filter(|command| command.op != FieldOp::Get)
+            .map(|command| (command.offset + command.ty.bits as u64).div_ceil(8) as usize)
+            .max();
+
+        let result = match write_end {
+            None => {
+                self.storage
+                    .inspect(args[0], |value| {
+                        let mut data = match value {
+                            Some(value) => value.as_string()?.to_vec(),
+                            None => Vec::new(),
+                        };
+                        Ok(run_field_commands(&mut data, &commands))
+                    })
+                    .await
+            }
+            Some(write_end) => {
+                self.storage
+                    .modify(args[0], |slot| {
+                        let mut data = grown_bytes(slot, write_end)?;
+                        let replies = run_field_commands(&mut data, &commands);
+                        store_string(slot, Bytes::from(data));
+                        Ok(replies)
+                    })
+                    .await
+            }
+        };
+        match result {
+            Ok(replies) => {
+                if write_end.is_some() {
+                    self.notify(NOTIFY_STRING, "setbit", args[0]);
+                }
+                RespValue::Array(Some(replies))
+            }
+            Err(e) => storage_error(command, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    fn args(parts: &[&str]) -> Vec<Bytes> {
+        parts
+            .iter()
+            .map(|part| Bytes::from(part.to_string()))
+            .collect()
+    }
+
+    fn int(value: RespValue) -> i64 {
+        match value {
+            RespValue::Integer(n) => n,
+            other => panic!("Expected Integer, got {:?}", other),
+        }
+    }
+
+    async fn run(handler: &Handler, parts: &[&str]) -> RespValue {
+        let owned = args(&parts[1..]);
+        let args: Vec<&Bytes> = owned.iter().collect();
+        match parts[0] {
+            "setbit" => handler.handle_setbit(&args).await,
+            "getbit" => handler.handle_getbit(&args).await,
+            "bitcount" => handler.handle_bitcount(&args).await,
+            "bitpos" => handler.handle_bitpos(&args).await,
+            "bitop" => handler.handle_bitop(&args).await,
+            "bitfield" => handler.handle_bitfield(&args, false).await,
+            "bitfield_ro" => handler.handle_bitfield(&args, true).await,
+            other => panic!("unexpected command {}", other),
+        }
+    }
+
+    /// Integer replies of a BITFIELD call, `None` for nil.
+    async fn bitfield(handler: &Handler, parts: &[&str]) -> Vec<Option<i64>> {
+        match run(handler, parts).await {
+            RespValue::Array(Some(replies)) => replies
+                .into_iter()
+                .map(|reply| match reply {
+                    RespValue::Integer(n) => Some(n),
+                    RespValue::BulkString(None) => None,
+                    other => panic!("Expected Integer, got {:?}", other),
+                })
+                .collect(),
+            other => panic!("Expected Array, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_setbit_bitcount_bitpos() {
+        let handler = create_handler();
+        assert_eq!(int(run(&handler, &["setbit", "b", "7", "1"]).await), 0);
+        assert_eq!(int(run(&handler, &["setbit", "b", "7", "0"]).await), 1);
+        assert_eq!(
+            handler.storage.get(b"b").await.unwrap(),
+            Some(Bytes::from_static(b"\0"))
+        );
+
+        handler.storage.set(b"s", b"foobar").await.unwrap();
+        for (range, expected) in [
+            (&[][..], 26),
+            (&["0", "0"], 4),
+            (&["1", "1"], 6),
+            (&["1", "1", "BYTE"], 6),
+            (&["5", "30", "BIT"], 17),
+            (&["-2", "-1"], 7),
+            (&["-1", "-2"], 0),
+        ] {
+            let mut parts = vec!["bitcount", "s"];
+            parts.extend_from_slice(range);
+            assert_eq!(int(run(&handler, &parts).await), expected, "{:?}", range);
+        }
+        assert_eq!(int(run(&handler, &["getbit", "s", "1"]).await), 1);
+        assert_eq!(int(run(&handler, &["getbit", "s", "1000"]).await), 0);
+
+        handler.storage.set(b"p", b"\xff\xf0\x00").await.unwrap();
+        for (parts, expected) in [
+            (&["bitpos", "p", "0"][..], 12),
+            (&["bitpos", "p", "1", "2"], -1),
+            (&["bitpos", "p", "1", "7", "15", "BIT"], 7),
+            (&["bitpos", "missing", "0"], 0),
+        ] {
+            assert_eq!(int(run(&handler, parts).await), expected, "{:?}", parts);
+        }
+        handler.storage.set(b"f", b"\xff\xff").await.unwrap();
+        assert_eq!(int(run(&handler, &["bitpos", "f", "0"]).await), 16);
+        assert_eq!(
+            int(run(&handler, &["bitpos", "f", "0", "0", "-1"]).await),
+            -1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bitop() {
+        let handler = create_handler();
+        handler.storage.set(b"a", b"foobar").await.unwrap();
+        handler.storage.set(b"b", b"abcdef").await.unwrap();
+
+        assert_eq!(
+            int(run(&handler, &["bitop", "AND", "d", "a", "b"]).await),
+            6
+        );
+        assert_eq!(
+            handler.storage.get(b"d").await.unwrap(),
+            Some(Bytes::from_static(b"`bc`ab"))
+        );
+        assert_eq!(
+            int(run(&handler, &["bitop", "or", "d", "d", "missing"]).await),
+            6
+        );
+        assert_eq!(
+            int(run(&handler, &["bitop", "NOT", "n", "missing"]).await),
+            0
+        );
+        assert!(!handler.storage.exists(b"n").await.unwrap());
+        assert!(matches!(
+            run(&handler, &["bitop", "NOT", "n", "a", "b"]).await,
+            RespValue::Error(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bitfield_overflow() {
+        let handler = create_handler();
+        assert_eq!(
+            bitfield(
+                &handler,
+                &["bitfield", "k", "SET", "i8", "0", "100", "GET", "i8", "0"]
+            )
+            .await,
+            vec![Some(0), Some(100)]
+        );
+        assert_eq!(
+            bitfield(
+                &handler,
+                &[
+                    "bitfield", "k", "INCRBY", "i8", "0", "100", "OVERFLOW", "SAT", "INCRBY", "i8",
+                    "0", "100", "OVERFLOW", "FAIL", "INCRBY", "i8", "0", "1",
+                ],
+            )
+            .await,
+            vec![Some(-56), Some(44), Some(45)]
+        );
+        assert_eq!(
+            bitfield(
+                &handler,
+                &["bitfield", "k", "OVERFLOW", "FAIL", "INCRBY", "i8", "0", "100"],
+            )
+            .await,
+            vec![None]
+        );
+        assert_eq!(
+            bitfield(
+                &handler,
+                &["bitfield", "u", "SET", "u8", "#1", "-1", "GET", "u8", "8", "GET", "u4", "0"],
+            )
+            .await,
+            vec![Some(0), Some(255), Some(0)]
+        );
+        assert_eq!(
+            bitfield(
+                &handler,
+                &["bitfield", "u", "OVERFLOW", "SAT", "INCRBY", "u2", "100", "-1"],
+            )
+            .await,
+            vec![Some(0)]
+        );
+        assert_eq!(
+            bitfield(&handler, &["bitfield_ro", "u", "GET", "u8", "8"]).await,
+            vec![Some(255)]
+        );
+        assert!(matches!(
+            run(&handler, &["bitfield_ro", "u", "SET", "u8", "0", "1"]).await,
+            RespValue::Error(_)
+        ));
+        assert!(matches!(
+            run(&handler, &["bitfield", "u", "GET", "u64", "0"]).await,
+            RespValue::Error(_)
+        ));
+    }
+}
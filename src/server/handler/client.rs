@@ -0,0 +1,463 @@
+//! Connection commands: CLIENT ID, LIST, INFO, SETNAME, GETNAME, KILL,
+//! PAUSE, UNPAUSE, NO-EVICT, TRACKING, CACHING, GETREDIR and TRACKINGINFO.
+//!
+//! Each connection reports its state to the server's client registry after
+//! every command, which is what CLIENT LIST shows of the other connections.
+//!
+//! A tracking client's options are kept both here, to decide which reads
+//! it caches, and in the server's tracking registry, which sends the
+//! invalidations. The keys a command read are remembered by `execute`.
+
+use super::transaction::Transaction;
+use super::{parse_int, parse_utf8, syntax_error, wrong_args, Cmd, Handler};
+use crate::protocol::{ProtocolVersion, RespValue};
+use crate::server::tracking::TrackingOptions;
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn error(message: &str) -> RespValue {
+    RespValue::Error(format!("ERR {}", message))
+}
+
+impl Handler {
+    /// Whether the keys read by the current command are remembered for
+    /// client-side caching.
+    pub(super) fn tracks_reads(&self) -> bool {
+        match &self.tracking {
+            None => false,
+            // Broadcasting clients remember nothing
+            Some(options) if options.bcast => false,
+            Some(options) if options.optin => self.caching == Some(true),
+            Some(options) if options.optout => self.caching != Some(false),
+            Some(_) => true,
+        }
+    }
+
+    /// Report what the connection is doing to the client registry.
+    pub(super) fn report_state(&self) {
+        self.client.update(|state| {
+            state.last_interaction = Instant::now();
+            state.subscriptions = (
+                self.subscriber.channels().len(),
+                self.subscriber.patterns().len(),
+                self.subscriber.shard_count(),
+            );
+            state.multi = self.transaction.as_ref().map(Transaction::queued);
+            state.watched_keys = self.watched.len();
+            state.blocked = false;
+            state.tracking = self.tracking.is_some();
+            state.redirect = self.tracking_redirect();
+            state.resp = match self.protocol_version {
+                ProtocolVersion::Resp2 => 2,
+                ProtocolVersion::Resp3 => 3,
+            };
+        });
+    }
+
+    /// Hold `cmd` off while clients are paused. CLIENT commands are never
+    /// held, so the pause can always be lifted.
+    pub(super) async fn wait_unpaused(&self, cmd: Cmd) {
+        if cmd == Cmd::Client || !self.context.clients.is_paused() {
+            return;
+        }
+        let write = match cmd {
+            Cmd::Exec => self.transaction.as_ref().is_some_and(Transaction::writes),
+            cmd => cmd.is_write(),
+        };
+        self.context.clients.wait_unpaused(write).await;
+    }
+
+    /// Name the connection, as CLIENT SETNAME and HELLO SETNAME do. An empty
+    /// name removes it.
+    pub(super) fn set_client_name(&self, name: &Bytes) -> Result<(), RespValue> {
+        // Names are shown in CLIENT LIST, which separates fields by spaces
+        if !name.iter().all(|b| (b'!'..=b'~').contains(b)) {
+            return Err(error(
+                "Client names cannot contain spaces, newlines or special characters.",
+            ));
+        }
+        let name = (!name.is_empty()).then(|| name.clone());
+        self.client.update(|state| state.name = name);
+        Ok(())
+    }
+
+    pub(super) fn handle_client(&mut self, args: &[&Bytes]) -> RespValue {
+        let Some(subcommand) = args.first() else {
+            return wrong_args("client");
+        };
+        let ok = || RespValue::SimpleString("OK".to_string());
+        match subcommand.to_ascii_uppercase().as_slice() {
+            b"ID" if args.len() == 1 => RespValue::Integer(self.subscriber.id() as i64),
+            b"LIST" => self.handle_client_list(&args[1..]),
+            b"INFO" if args.len() == 1 => {
+                RespValue::BulkString(Some(Bytes::from(self.client.describe() + "\n")))
+            }
+            b"SETNAME" if args.len() == 2 => match self.set_client_name(args[1]) {
+                Ok(()) => ok(),
+                Err(e) => e,
+            },
+            b"GETNAME" if args.len() == 1 => RespValue::BulkString(self.client.state().name),
+            b"KILL" if args.len() >= 2 => self.handle_client_kill(&args[1..]),
+            b"PAUSE" if args.len() == 2 || args.len() == 3 => {
+                self.handle_client_pause(&args[1..])
+            }
+            b"UNPAUSE" if args.len() == 1 => {
+                self.context.clients.unpause();
+                ok()
+            }
+            b"NO-EVICT" if args.len() == 2 => {
+                // Keys are never evicted, so the flag is only reported
+                let no_evict = match args[1].to_ascii_uppercase().as_slice() {
+                    b"ON" => true,
+                    b"OFF" => false,
+                    _ => return syntax_error(),
+                };
+                self.client.update(|state| state.no_evict = no_evict);
+                ok()
+            }
+            b"TRACKING" if args.len() >= 2 => self.handle_client_tracking(&args[1..]),
+            b"CACHING" if args.len() == 2 => self.handle_client_caching(args[1]),
+            b"GETREDIR" if args.len() == 1 => RespValue::Integer(self.tracking_redirect()),
+            b"TRACKINGINFO" if args.len() == 1 => self.client_tracking_info(),
+            b"ID" | b"INFO" | b"SETNAME" | b"GETNAME" | b"KILL" | b"PAUSE" | b"UNPAUSE"
+            | b"NO-EVICT" | b"TRACKING" | b"CACHING" | b"GETREDIR" | b"TRACKINGINFO" => {
+                RespValue::Error(format!(
+                    "ERR unknown subcommand or wrong number of arguments for '{}'. Try CLIENT HELP.",
+                    String::from_utf8_lossy(subcommand)
+                ))
+            }
+            _ => RespValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+                String::from_utf8_lossy(subcommand)
+            )),
+        }
+    }
+
+    /// CLIENT LIST [TYPE NORMAL|MASTER|REPLICA|PUBSUB] [ID id ...]
+    fn handle_client_list(&self, args: &[&Bytes]) -> RespValue {
+        let mut pubsub = None;
+        let mut ids = Vec::new();
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].to_ascii_uppercase().as_slice() {
+                b"TYPE" if i + 1 < args.len() => {
+                    pubsub = match args[i + 1].to_ascii_uppercase().as_slice() {
+                        b"NORMAL" => Some(false),
+                        b"PUBSUB" => Some(true),
+                        // There are no replication links
+                        b"MASTER" | b"REPLICA" | b"SLAVE" => {
+                            return RespValue::BulkString(Some(Bytes::new()))
+                        }
+                        _ => {
+                            return error(&format!(
+                                "Unknown client type '{}'",
+                                String::from_utf8_lossy(args[i + 1])
+                            ))
+                        }
+                    };
+                    i += 2;
+                }
+                b"ID" if i + 1 < args.len() => {
+                    for arg in &args[i + 1..] {
+                        match parse_utf8::<u64>(arg).filter(|&id| id > 0) {
+                            Some(id) => ids.push(id),
+                            None => return error("Invalid client ID"),
+                        }
+                    }
+                    i = args.len();
+                }
+                _ => return syntax_error(),
+            }
+        }
+        let mut list = String::new();
+        for client in self.context.clients.clients() {
+            if !ids.is_empty() && !ids.contains(&client.id()) {
+                continue;
+            }
+            let subscribed = client.state().subscriptions != (0, 0, 0);
+            if pubsub.is_some_and(|pubsub| pubsub != subscribed) {
+                continue;
+            }
+            list.push_str(&client.describe());
+            list.push('\n');
+        }
+        RespValue::BulkString(Some(Bytes::from(list)))
+    }
+
+    /// CLIENT KILL addr, or CLIENT KILL [ID id] [ADDR addr] [LADDR addr]
+    /// [USER username] [SKIPME yes|no]
+    fn handle_client_kill(&self, args: &[&Bytes]) -> RespValue {
+        let clients = self.context.clients.clients();
+        let address = |addr: &Bytes| String::from_utf8_lossy(addr).into_owned();
+        if let [addr] = args {
+            let addr = address(addr);
+            let client = clients
+                .iter()
+                .find(|client| client.addr().is_some_and(|a| a.to_string() == addr));
+            return match client {
+                Some(client) => {
+                    client.kill();
+                    RespValue::SimpleString("OK".to_string())
+                }
+                None => error("No such client"),
+            };
+        }
+        if !args.len().is_multiple_of(2) {
+            return syntax_error();
+        }
+
+        let (mut id, mut addr, mut laddr) = (None, None, None);
+        let mut skip_me = true;
+        for pair in args.chunks(2) {
+            let value = pair[1];
+            match pair[0].to_ascii_uppercase().as_slice() {
+                b"ID" => match parse_utf8::<u64>(value).filter(|&id| id > 0) {
+                    Some(value) => id = Some(value),
+                    None => return error("client-id should be greater than 0"),
+                },
+                b"ADDR" => addr = Some(address(value)),
+                b"LADDR" => laddr = Some(address(value)),
+                // Every client is authenticated as the default user
+                b"USER" if value.as_ref() == b"default" => {}
+                b"USER" => {
+                    return error(&format!(
+                        "No such user '{}'",
+                        String::from_utf8_lossy(value)
+                    ))
+                }
+                b"SKIPME" => match value.to_ascii_uppercase().as_slice() {
+                    b"YES" => skip_me = true,
+                    b"NO" => skip_me = false,
+                    _ => return syntax_error(),
+                },
+                _ => return syntax_error(),
+            }
+        }
+        let matches = |expected: &Option<String>, actual: Option<SocketAddr>| {
+            expected
+                .as_ref()
+                .is_none_or(|expected| actual.is_some_and(|a| a.to_string() == *expected))
+        };
+        let mut killed = 0;
+        for client in &clients {
+            if skip_me && Arc::ptr_eq(client, &self.client) {
+                continue;
+            }
+            if id.is_none_or(|id| client.id() == id)
+                && matches(&addr, client.addr())
+                && matches(&laddr, client.laddr())
+            {
+                client.kill();
+                killed += 1;
+            }
+        }
+        RespValue::Integer(killed)
+    }
+
+    /// CLIENT PAUSE timeout [WRITE|ALL]
+    fn handle_client_pause(&self, args: &[&Bytes]) -> RespValue {
+        let timeout = match parse_utf8::<i64>(args[0]) {
+            Some(timeout) if timeout < 0 => return error("timeout is negative"),
+            Some(timeout) => Duration::from_millis(timeout as u64),
+            None => return error("timeout is not an integer or out of range"),
+        };
+        let all = match args.get(1).map(|mode| mode.to_ascii_uppercase()) {
+            None => true,
+            Some(mode) if mode == b"ALL" => true,
+            Some(mode) if mode == b"WRITE" => false,
+            Some(_) => return syntax_error(),
+        };
+        self.context.clients.pause(timeout, all);
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    /// CLIENT TRACKING ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST]
+    /// [OPTIN] [OPTOUT] [NOLOOP]
+    fn handle_client_tracking(&mut self, args: &[&Bytes]) -> RespValue {
+        let mut options = TrackingOptions::default();
+        let mut i = 1;
+        while i < args.len() {
+            let has_value = i + 1 < args.len();
+            match args[i].to_ascii_uppercase().as_slice() {
+                b"REDIRECT" if has_value => {
+                    if options.redirect.is_some() {
+                        return error("A client can only redirect to a single other client");
+                    }
+                    let id = match parse_int(args[i + 1]) {
+                        Ok(id) => id,
+                        Err(e) => return e,
+                    };
+                    if id <= 0 || !self.context.pubsub.is_connected(id as u64) {
+                        return error("The client ID you want redirect to does not exist");
+                    }
+                    options.redirect = Some(id as u64);
+                    i += 1;
+                }
+                b"PREFIX" if has_value => {
+                    options.prefixes.push(args[i + 1].clone());
+                    i += 1;
+                }
+                b"BCAST" => options.bcast = true,
+                b"OPTIN" => options.optin = true,
+                b"OPTOUT" => options.optout = true,
+                b"NOLOOP" => options.noloop = true,
+                _ => return syntax_error(),
+            }
+            i += 1;
+        }
+
+        match args[0].to_ascii_uppercase().as_slice() {
+            b"ON" => {}
+            b"OFF" => {
+                if self.tracking.take().is_some() {
+                    self.context.tracking.disable(self.subscriber.id());
+                }
+                self.caching = None;
+                return RespValue::SimpleString("OK".to_string());
+            }
+            _ => return syntax_error(),
+        }
+        if !options.bcast && !options.prefixes.is_empty() {
+            return error("PREFIX option requires BCAST mode to be enabled");
+        }
+        if let Some(current) = &self.tracking {
+            if current.bcast != options.bcast {
+                return error(
+                    "You can't switch BCAST mode on/off before disabling tracking for this \
+                     client, and then re-enabling it with a different mode.",
+                );
+            }
+        }
+        if options.bcast && (options.optin || options.optout) {
+            return error("OPTIN and OPTOUT are not compatible with BCAST");
+        }
+        if options.optin && options.optout {
+            return error("You can't use both OPTIN and OPTOUT");
+        }
+        if let Some(current) = &self.tracking {
+            if (options.optin && current.optout) || (options.optout && current.optin) {
+                return error(
+                    "You can't switch OPTIN/OPTOUT mode before disabling tracking for this \
+                     client, and then re-enabling it with a different mode.",
+                );
+            }
+        }
+        if options.bcast {
+            // Prefixes add up over calls, and must never overlap
+            let existing = self
+                .tracking
+                .as_ref()
+                .map_or(&[][..], |current| &current.prefixes[..]);
+            for (n, prefix) in options.prefixes.iter().enumerate() {
+                let overlaps =
+                    |other: &Bytes| prefix.starts_with(other) || other.starts_with(prefix);
+                if let Some(other) = existing.iter().find(|other| overlaps(other)) {
+                    return prefix_overlap(prefix, "an existing", other);
+                }
+                if let Some(other) = options.prefixes[n + 1..]
+                    .iter()
+                    .find(|other| overlaps(other))
+                {
+                    return prefix_overlap(prefix, "another provided", other);
+                }
+            }
+            let mut prefixes = existing.to_vec();
+            prefixes.append(&mut options.prefixes);
+            options.prefixes = prefixes;
+        }
+        self.context
+            .tracking
+            .enable(self.subscriber.id(), options.clone());
+        self.tracking = Some(options);
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    /// CLIENT CACHING YES|NO
+    fn handle_client_caching(&mut self, mode: &Bytes) -> RespValue {
+        let Some(options) = &self.tracking else {
+            return error(
+                "CLIENT CACHING can be called only when the client is in tracking mode with \
+                 OPTIN or OPTOUT mode enabled",
+            );
+        };
+        match mode.to_ascii_uppercase().as_slice() {
+            b"YES" if options.optin => self.caching = Some(true),
+            b"YES" => {
+                return error(
+                    "CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.",
+                )
+            }
+            b"NO" if options.optout => self.caching = Some(false),
+            b"NO" => {
+                return error(
+                    "CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.",
+                )
+            }
+            _ => return syntax_error(),
+        }
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    /// The client invalidations are redirected to, 0 for none and -1 when
+    /// not tracking.
+    fn tracking_redirect(&self) -> i64 {
+        match &self.tracking {
+            None => -1,
+            Some(options) => options.redirect.map_or(0, |id| id as i64),
+        }
+    }
+
+    /// CLIENT TRACKINGINFO: the tracking flags, redirection and prefixes.
+    fn client_tracking_info(&self) -> RespValue {
+        let bulk = |s: &'static str| RespValue::BulkString(Some(Bytes::from_static(s.as_bytes())));
+        let mut flags = Vec::new();
+        match &self.tracking {
+            None => flags.push(bulk("off")),
+            Some(options) => {
+                flags.push(bulk("on"));
+                for (set, flag) in [
+                    (options.bcast, "bcast"),
+                    (options.optin, "optin"),
+                    (options.optout, "optout"),
+                    (self.caching == Some(true), "caching-yes"),
+                    (self.caching == Some(false), "caching-no"),
+                    (options.noloop, "noloop"),
+                ] {
+                    if set {
+                        flags.push(bulk(flag));
+                    }
+                }
+                if let Some(id) = options.redirect {
+                    if !self.context.pubsub.is_connected(id) {
+                        flags.push(bulk("broken_redirect"));
+                    }
+                }
+            }
+        }
+        let prefixes = self
+            .tracking
+            .iter()
+            .flat_map(|options| &options.prefixes)
+            .map(|prefix| RespValue::BulkString(Some(prefix.clone())))
+            .collect();
+        self.map_reply(vec![
+            (bulk("flags"), self.set_reply(flags)),
+            (
+                bulk("redirect"),
+                RespValue::Integer(self.tracking_redirect()),
+            ),
+            (bulk("prefixes"), RespValue::Array(Some(prefixes))),
+        ])
+    }
+}
+
+fn prefix_overlap(prefix: &Bytes, which: &str, other: &Bytes) -> RespValue {
+    error(&format!(
+        "Prefix '{}' overlaps with {} prefix '{}'. Prefixes for a single client must not overlap.",
+        String::from_utf8_lossy(prefix),
+        which,
+        String::from_utf8_lossy(other)
+    ))
+}
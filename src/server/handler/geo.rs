@@ -0,0 +1,870 @@
+//! Geospatial commands: GEOADD, GEODIST, GEOHASH, GEOPOS, GEOSEARCH and
+//! GEOSEARCHSTORE.
+//!
+//! Geo indexes are sorted sets scored by 52-bit geohashes (see
+//! [`crate::geo`]), so the sorted set commands work on them too, as in Redis.
+
+use super::{
+    distinct_keys, parse_float, parse_int, storage_error, syntax_error, wrong_args, Handler,
+};
+use crate::geo::{self, Shape};
+use crate::protocol::{ProtocolVersion, RespValue};
+use crate::server::notify::NOTIFY_ZSET;
+use crate::storage::{SortedSet, StorageValue, ValueData};
+use bytes::Bytes;
+
+/// Meters per distance unit.
+fn parse_unit(arg: &[u8]) -> Result<f64, RespValue> {
+    match arg.to_ascii_lowercase().as_slice() {
+        b"m" => Ok(1.0),
+        b"km" => Ok(1000.0),
+        b"ft" => Ok(0.3048),
+        b"mi" => Ok(1609.34),
+        _ => Err(RespValue::Error(
+            "ERR unsupported unit provided. please use M, KM, FT, MI".to_string(),
+        )),
+    }
+}
+
+/// Parse a longitude/latitude pair, rejecting positions that cannot be
+/// indexed.
+fn parse_position(longitude: &[u8], latitude: &[u8]) -> Result<(f64, f64), RespValue> {
+    let position = (parse_float(longitude)?, parse_float(latitude)?);
+    if !geo::valid_coordinates(position.0, position.1) {
+        return Err(RespValue::Error(format!(
+            "ERR invalid longitude,latitude pair {:.6},{:.6}",
+            position.0, position.1
+        )));
+    }
+    Ok(position)
+}
+
+/// Position of a member, decoded from its score.
+fn member_position(zset: &SortedSet, member: &[u8]) -> Option<(f64, f64)> {
+    zset.score(member).map(|score| geo::decode(score as u64))
+}
+
+/// Distances are replied with four decimals, whatever the protocol.
+fn distance_reply(distance: f64) -> RespValue {
+    RespValue::BulkString(Some(format!("{:.4}", distance).into()))
+}
+
+/// Where a search is centered.
+enum Origin {
+    Member(Bytes),
+    Position(f64, f64),
+}
+
+/// Arguments of GEOSEARCH and GEOSEARCHSTORE, after the key(s).
+struct SearchArgs {
+    origin: Origin,
+    shape: Shape,
+    /// Meters per unit of the shape and of the replied distances.
+    unit: f64,
+    /// Sort by distance, ascending if `Some(false)`.
+    descending: Option<bool>,
+    count: Option<usize>,
+    any: bool,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    store_dist: bool,
+}
+
+impl SearchArgs {
+    fn parse(args: &[&Bytes], store: bool) -> Result<Self, RespValue> {
+        let (mut origin, mut shape, mut unit) = (None, None, 1.0);
+        let (mut descending, mut count, mut any) = (None, None, false);
+        let (mut with_coord, mut with_dist, mut with_hash) = (false, false, false);
+        let mut store_dist = false;
+
+        let mut i = 0;
+        while i < args.len() {
+            let option = args[i].to_ascii_uppercase();
+            let left = args.len() - i - 1;
+            match option.as_slice() {
+                b"FROMMEMBER" if left >= 1 => {
+                    if origin.is_some() {
+                        return Err(origin_error());
+                    }
+                    origin = Some(Origin::Member(args[i + 1].clone()));
+                    i += 2;
+                }
+                b"FROMLONLAT" if left >= 2 => {
+                    if origin.is_some() {
+                        return Err(origin_error());
+                    }
+                    let (longitude, latitude) = parse_position(args[i + 1], args[i + 2])?;
+                    origin = Some(Origin::Position(longitude, latitude));
+                    i += 3;
+                }
+                b"BYRADIUS" if left >= 2 => {
+                    if shape.is_some() {
+                        return Err(shape_error());
+                    }
+                    let radius = parse_float(args[i + 1])?;
+                    if radius < 0.0 {
+                        return Err(RespValue::Error(
+                            "ERR radius cannot be negative".to_string(),
+                        ));
+                    }
+                    unit = parse_unit(args[i + 2])?;
+                    shape = Some(Shape::Radius(radius * unit));
+                    i += 3;
+                }
+                b"BYBOX" if left >= 3 => {
+                    if shape.is_some() {
+                        return Err(shape_error());
+                    }
+                    let (width, height) = (parse_float(args[i + 1])?, parse_float(args[i + 2])?);
+                    if width < 0.0 || height < 0.0 {
+                        return Err(RespValue::Error(
+                            "ERR height or width cannot be negative".to_string(),
+                        ));
+                    }
+                    unit = parse_unit(args[i + 3])?;
+                    shape = Some(Shape::Box {
+                        width: width * unit,
+                        height: height * unit,
+                    });
+                    i += 4;
+                }
+                b"ASC" => {
+                    descending = Some(false);
+                    i += 1;
+                }
+                b"DESC" => {
+                    descending = Some(true);
+                    i += 1;
+                }
+                b"COUNT" if left >= 1 => {
+                    let n = parse_int(args[i + 1])?;
+                    if n <= 0 {
+                        return Err(RespValue::Error("ERR COUNT must be > 0".to_string()));
+                    }
+                    count = Some(n as usize);
+                    i += 2;
+                }
+                b"ANY" => {
+                    any = true;
+                    i += 1;
+                }
+                b"WITHCOORD" if !store => {
+                    with_coord = true;
+                    i += 1;
+                }
+                b"WITHDIST" if !store => {
+                    with_dist = true;
+                    i += 1;
+                }
+                b"WITHHASH" if !store => {
+                    with_hash = true;
+                    i += 1;
+                }
+                b"STOREDIST" if store => {
+                    store_dist = true;
+                    i += 1;
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        if any && count.is_none() {
+            return Err(RespValue::Error(
+                "ERR the ANY argument requires COUNT argument".to_string(),
+            ));
+        }
+        Ok(SearchArgs {
+            origin: origin.ok_or_else(origin_error)?,
+            shape: shape.ok_or_else(shape_error)?,
+            unit,
+            descending,
+            count,
+            any,
+            with_coord,
+            with_dist,
+            with_hash,
+            store_dist,
+        })
+    }
+
+    /// Members within the shape, as `(member, score, distance in meters)`,
+    /// sorted and truncated as requested. `None` if the origin member does
+    /// not exist.
+    fn run(&self, zset: &SortedSet) -> Option<Vec<(Bytes, f64, f64)>> {
+        let center = match &self.origin {
+            Origin::Member(member) => member_position(zset, member)?,
+            Origin::Position(longitude, latitude) => (*longitude, *latitude),
+        };
+        // COUNT ANY stops at the first matches found
+        let limit = self.count.filter(|_| self.any).unwrap_or(usize::MAX);
+
+        let mut matches = Vec::new();
+        'ranges: for range in geo::search_ranges(center, &self.shape) {
+            let start = zset.partition_point(|_, score| score < range.start as f64);
+            for (member, score) in zset.range(start, zset.len()) {
+                if score >= range.end as f64 {
+                    break;
+                }
+                let position = geo::decode(score as u64);
+                if let Some(distance) = self.shape.distance_within(center, position) {
+                    matches.push((member.clone(), score, distance));
+                    if matches.len() == limit {
+                        break 'ranges;
+                    }
+                }
+            }
+        }
+
+        // The closest members are returned when COUNT is given without ANY
+        let descending = match self.descending {
+            None if self.count.is_some() && !self.any => Some(false),
+            descending => descending,
+        };
+        if let Some(descending) = descending {
+            matches.sort_by(|a, b| a.2.total_cmp(&b.2));
+            if descending {
+                matches.reverse();
+            }
+        }
+        if let Some(count) = self.count {
+            matches.truncate(count);
+        }
+        Some(matches)
+    }
+}
+
+fn origin_error() -> RespValue {
+    RespValue::Error(
+        "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH".to_string(),
+    )
+}
+
+fn shape_error() -> RespValue {
+    RespValue::Error(
+        "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH".to_string(),
+    )
+}
+
+impl Handler {
+    /// Reply with a coordinate: a double under RESP3, a bulk string with
+    /// Redis's 17 decimals under RESP2.
+    fn coordinate_reply(&self, value: f64) -> RespValue {
+        match self.protocol_version {
+            ProtocolVersion::Resp3 => RespValue::Double(value),
+            ProtocolVersion::Resp2 => {
+                let formatted = format!("{:.17}", value);
+                let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+                RespValue::BulkString(Some(Bytes::copy_from_slice(trimmed.as_bytes())))
+            }
+        }
+    }
+
+    fn position_reply(&self, (longitude, latitude): (f64, f64)) -> RespValue {
+        RespValue::Array(Some(vec![
+            self.coordinate_reply(longitude),
+            self.coordinate_reply(latitude),
+        ]))
+    }
+
+    /// GEOADD key [NX | XX] [CH] longitude latitude member
+    /// [longitude latitude member ...]
+    pub(super) async fn handle_geoadd(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 4 {
+            return wrong_args("geoadd");
+        }
+
+        let (mut nx, mut xx, mut ch) = (false, false, false);
+        let mut i = 1;
+        while i < args.len() {
+            let flag = match args[i].to_ascii_uppercase().as_slice() {
+                b"NX" => &mut nx,
+                b"XX" => &mut xx,
+                b"CH" => &mut ch,
+                _ => break,
+            };
+            *flag = true;
+            i += 1;
+        }
+        if nx && xx {
+            return RespValue::Error(
+                "ERR XX and NX options at the same time are not compatible".to_string(),
+            );
+        }
+        let triples = &args[i..];
+        if triples.is_empty() || !triples.len().is_multiple_of(3) {
+            return RespValue::Error(
+                "ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... "
+                    .to_string(),
+            );
+        }
+
+        let mut elements = Vec::with_capacity(triples.len() / 3);
+        for triple in triples.chunks(3) {
+            let (longitude, latitude) = match parse_position(triple[0], triple[1]) {
+                Ok(position) => position,
+                Err(e) => return e,
+            };
+            let score = geo::encode(longitude, latitude).expect("valid position") as f64;
+            elements.push((score, triple[2]));
+        }
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let (mut added, mut changed) = (0, 0);
+                for &(score, member) in &elements {
+                    let current = slot.zset()?.and_then(|zset| zset.score(member));
+                    match current {
+                        None if xx => continue,
+                        Some(_) if nx => continue,
+                        Some(current) if current == score => continue,
+                        None => added += 1,
+                        Some(_) => changed += 1,
+                    }
+                    slot.zset_mut()?.insert(member.clone(), score);
+                }
+                Ok((added, changed))
+            })
+            .await;
+        match result {
+            Ok((added, changed)) => {
+                // GEOADD is a ZADD, and reported as one
+                if added + changed > 0 {
+                    self.notify(NOTIFY_ZSET, "zadd", args[0]);
+                }
+                RespValue::Integer(if ch { added + changed } else { added })
+            }
+            Err(e) => storage_error("geoadd", e),
+        }
+    }
+
+    /// GEODIST key member1 member2 [M | KM | FT | MI]
+    pub(super) async fn handle_geodist(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 3 {
+            return wrong_args("geodist");
+        }
+        let unit = match args {
+            [_, _, _] => 1.0,
+            [_, _, _, unit] => match parse_unit(unit) {
+                Ok(unit) => unit,
+                Err(e) => return e,
+            },
+            _ => return syntax_error(),
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(zset) = value.map(|value| value.as_zset()).transpose()? else {
+                    return Ok(None);
+                };
+                Ok(member_position(zset, args[1])
+                    .zip(member_position(zset, args[2]))
+                    .map(|(a, b)| geo::distance(a, b)))
+            })
+            .await;
+        match result {
+            Ok(Some(distance)) => distance_reply(distance / unit),
+            Ok(None) => RespValue::BulkString(None),
+            Err(e) => storage_error("geodist", e),
+        }
+    }
+
+    /// GEOPOS key [member ...]
+    pub(super) async fn handle_geopos(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("geopos");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let zset = value.map(|value| value.as_zset()).transpose()?;
+                Ok(args[1..]
+                    .iter()
+                    .map(|member| zset.and_then(|zset| member_position(zset, member)))
+                    .collect::<Vec<_>>())
+            })
+            .await;
+        match result {
+            Ok(positions) => RespValue::Array(Some(
+                positions
+                    .into_iter()
+                    .map(|position| match position {
+                        Some(position) => self.position_reply(position),
+                        None => self.null_array(),
+                    })
+                    .collect(),
+            )),
+            Err(e) => storage_error("geopos", e),
+        }
+    }
+
+    /// GEOHASH key [member ...]
+    pub(super) async fn handle_geohash(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("geohash");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let zset = value.map(|value| value.as_zset()).transpose()?;
+                Ok(args[1..]
+                    .iter()
+                    .map(|member| {
+                        let score = zset.and_then(|zset| zset.score(member))?;
+                        Some(Bytes::from(geo::geohash(score as u64)))
+                    })
+                    .map(RespValue::BulkString)
+                    .collect())
+            })
+            .await;
+        match result {
+            Ok(hashes) => RespValue::Array(Some(hashes)),
+            Err(e) => storage_error("geohash", e),
+        }
+    }
+
+    /// GEOSEARCH key FROMMEMBER member | FROMLONLAT longitude latitude
+    /// BYRADIUS radius unit | BYBOX width height unit [ASC | DESC]
+    /// [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]
+    pub(super) async fn handle_geosearch(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 6 {
+            return wrong_args("geosearch");
+        }
+        let search = match SearchArgs::parse(&args[1..], false) {
+            Ok(search) => search,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| match value {
+                Some(value) => Ok(search.run(value.as_zset()?)),
+                None => Ok(Some(Vec::new())),
+            })
+            .await;
+        let matches = match result {
+            Ok(Some(matches)) => matches,
+            Ok(None) => {
+                return RespValue::Error("ERR could not decode requested zset member".to_string())
+            }
+            Err(e) => return storage_error("geosearch", e),
+        };
+
+        let items = matches
+            .into_iter()
+            .map(|(member, score, distance)| {
+                if !(search.with_dist || search.with_hash || search.with_coord) {
+                    return RespValue::BulkString(Some(member));
+                }
+                let mut item = vec![RespValue::BulkString(Some(member))];
+                if search.with_dist {
+                    item.push(distance_reply(distance / search.unit));
+                }
+                if search.with_hash {
+                    item.push(RespValue::Integer(score as i64));
+                }
+                if search.with_coord {
+                    item.push(self.position_reply(geo::decode(score as u64)));
+                }
+                RespValue::Array(Some(item))
+            })
+            .collect();
+        RespValue::Array(Some(items))
+    }
+
+    /// GEOSEARCHSTORE destination source FROMMEMBER member | FROMLONLAT
+    /// longitude latitude BYRADIUS radius unit | BYBOX width height unit
+    /// [ASC | DESC] [COUNT count [ANY]] [STOREDIST]
+    pub(super) async fn handle_geosearchstore(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 7 {
+            return wrong_args("geosearchstore");
+        }
+        let search = match SearchArgs::parse(&args[2..], true) {
+            Ok(search) => search,
+            Err(e) => return e,
+        };
+
+        // The destination may also be the source
+        let (keys, indexes) = distinct_keys(&args[..2]);
+        let result = self
+            .storage
+            .modify_many(&keys, |slots| {
+                let matches = match slots[indexes[1]].zset()? {
+                    Some(zset) => match search.run(zset) {
+                        Some(matches) => matches,
+                        None => return Ok(Err("ERR could not decode requested zset member")),
+                    },
+                    None => Vec::new(),
+                };
+
+                let zset: SortedSet = matches
+                    .into_iter()
+                    .map(|(member, score, distance)| {
+                        let score = if search.store_dist {
+                            distance / search.unit
+                        } else {
+                            score
+                        };
+                        (score, member)
+                    })
+                    .collect::<Vec<_>>()
+                    .into();
+                let len = zset.len();
+                let destination = &mut slots[indexes[0]];
+                let existed = destination.get().is_some();
+                if zset.is_empty() {
+                    destination.delete();
+                } else {
+                    destination.set(StorageValue::new(ValueData::SortedSet(zset)));
+                }
+                Ok(Ok((len, existed)))
+            })
+            .await;
+        match result {
+            Ok(Ok((len, existed))) => {
+                self.notify_stored(NOTIFY_ZSET, "geosearchstore", args[0], len, existed);
+                RespValue::Integer(len as i64)
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("geosearchstore", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    async fn run(handler: &Handler, parts: &[&str]) -> RespValue {
+        let owned: Vec<Bytes> = parts[1..]
+            .iter()
+            .map(|part| Bytes::from(part.to_string()))
+            .collect();
+        let args: Vec<&Bytes> = owned.iter().collect();
+        match parts[0] {
+            "geoadd" => handler.handle_geoadd(&args).await,
+            "geodist" => handler.handle_geodist(&args).await,
+            "geopos" => handler.handle_geopos(&args).await,
+            "geohash" => handler.handle_geohash(&args).await,
+            "geosearch" => handler.handle_geosearch(&args).await,
+            "geosearchstore" => handler.handle_geosearchstore(&args).await,
+            other => panic!("unexpected command {}", other),
+        }
+    }
+
+    /// Flatten a reply into strings, nested arrays included.
+    fn strings(value: RespValue) -> Vec<String> {
+        match value {
+            RespValue::Array(Some(items)) => items.into_iter().flat_map(strings).collect(),
+            RespValue::BulkString(Some(b)) => vec![String::from_utf8(b.to_vec()).unwrap()],
+            RespValue::Integer(n) => vec![n.to_string()],
+            RespValue::BulkString(None) | RespValue::Array(None) => vec!["nil".to_string()],
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    async fn sicily(handler: &Handler) {
+        let reply = run(
+            handler,
+            &[
+                "geoadd",
+                "Sicily",
+                "13.361389",
+                "38.115556",
+                "Palermo",
+                "15.087269",
+                "37.502669",
+                "Catania",
+            ],
+        )
+        .await;
+        assert!(matches!(reply, RespValue::Integer(2)));
+    }
+
+    #[tokio::test]
+    async fn test_geoadd_pos_dist_hash() {
+        let handler = create_handler();
+        sicily(&handler).await;
+
+        assert_eq!(
+            strings(
+                run(
+                    &handler,
+                    &["geopos", "Sicily", "Palermo", "Catania", "NonExisting"]
+                )
+                .await
+            ),
+            [
+                "13.36138933897018433",
+                "38.11555639549629859",
+                "15.08726745843887329",
+                "37.50266842333162032",
+                "nil"
+            ]
+        );
+        assert_eq!(
+            strings(run(&handler, &["geodist", "Sicily", "Palermo", "Catania"]).await),
+            ["166274.1516"]
+        );
+        assert_eq!(
+            strings(run(&handler, &["geodist", "Sicily", "Palermo", "Catania", "km"]).await),
+            ["166.2742"]
+        );
+        assert_eq!(
+            strings(run(&handler, &["geodist", "Sicily", "Palermo", "Catania", "mi"]).await),
+            ["103.3182"]
+        );
+        assert_eq!(
+            strings(run(&handler, &["geodist", "Sicily", "Palermo", "Foo"]).await),
+            ["nil"]
+        );
+        assert_eq!(
+            strings(run(&handler, &["geohash", "Sicily", "Palermo", "Catania"]).await),
+            ["sqc8b49rny0", "sqdtr74hyu0"]
+        );
+
+        // Options follow ZADD's
+        let reply = run(
+            &handler,
+            &["geoadd", "Sicily", "XX", "CH", "13.5", "38", "Palermo"],
+        )
+        .await;
+        assert!(matches!(reply, RespValue::Integer(1)));
+        let reply = run(&handler, &["geoadd", "Sicily", "NX", "13", "38", "Palermo"]).await;
+        assert!(matches!(reply, RespValue::Integer(0)));
+        assert!(matches!(
+            run(&handler, &["geoadd", "Sicily", "200", "38", "Nowhere"]).await,
+            RespValue::Error(e) if e == "ERR invalid longitude,latitude pair 200.000000,38.000000"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_geosearch() {
+        let handler = create_handler();
+        sicily(&handler).await;
+        run(
+            &handler,
+            &[
+                "geoadd",
+                "Sicily",
+                "12.758489",
+                "38.788135",
+                "edge1",
+                "17.241510",
+                "38.788135",
+                "edge2",
+            ],
+        )
+        .await;
+
+        let search = |extra: &[&'static str]| {
+            let mut parts = vec!["geosearch", "Sicily", "FROMLONLAT", "15", "37"];
+            parts.extend_from_slice(extra);
+            parts
+        };
+        assert_eq!(
+            strings(run(&handler, &search(&["BYRADIUS", "200", "km", "ASC"])).await),
+            ["Catania", "Palermo"]
+        );
+        assert_eq!(
+            strings(
+                run(
+                    &handler,
+                    &search(&["BYBOX", "400", "400", "km", "ASC", "WITHCOORD", "WITHDIST"])
+                )
+                .await
+            ),
+            [
+                "Catania",
+                "56.4413",
+                "15.08726745843887329",
+                "37.50266842333162032",
+                "Palermo",
+                "190.4424",
+                "13.36138933897018433",
+                "38.11555639549629859",
+                "edge2",
+                "279.7403",
+                "17.24151045083999634",
+                "38.78813451624225195",
+                "edge1",
+                "279.7405",
+                "12.7584877610206604",
+                "38.78813451624225195",
+            ]
+        );
+        assert_eq!(
+            strings(
+                run(
+                    &handler,
+                    &search(&["BYRADIUS", "200", "km", "DESC", "COUNT", "1", "WITHHASH"])
+                )
+                .await
+            ),
+            ["Palermo", "3479099956230698"]
+        );
+        // COUNT without ANY returns the closest members
+        assert_eq!(
+            strings(run(&handler, &search(&["BYRADIUS", "300", "km", "COUNT", "1"])).await),
+            ["Catania"]
+        );
+        assert_eq!(
+            strings(
+                run(
+                    &handler,
+                    &search(&["BYRADIUS", "300", "km", "COUNT", "2", "ANY"])
+                )
+                .await
+            )
+            .len(),
+            2
+        );
+        assert_eq!(
+            strings(
+                run(
+                    &handler,
+                    &[
+                        "geosearch",
+                        "Sicily",
+                        "FROMMEMBER",
+                        "Palermo",
+                        "BYRADIUS",
+                        "170",
+                        "km",
+                        "ASC"
+                    ]
+                )
+                .await
+            ),
+            ["Palermo", "edge1", "Catania"]
+        );
+
+        assert!(matches!(
+            run(&handler, &["geosearch", "Sicily", "FROMMEMBER", "Foo", "BYRADIUS", "1", "km"]).await,
+            RespValue::Error(e) if e == "ERR could not decode requested zset member"
+        ));
+        assert!(matches!(
+            run(&handler, &search(&["BYRADIUS", "1", "km", "ANY"])).await,
+            RespValue::Error(e) if e.contains("ANY argument requires COUNT")
+        ));
+        assert!(matches!(
+            run(&handler, &["geosearch", "Sicily", "BYRADIUS", "1", "km", "ASC", "WITHDIST"]).await,
+            RespValue::Error(e) if e.contains("FROMMEMBER or FROMLONLAT")
+        ));
+        assert!(strings(
+            run(
+                &handler,
+                &[
+                    "geosearch",
+                    "none",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "1",
+                    "km"
+                ]
+            )
+            .await
+        )
+        .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_geosearchstore() {
+        let handler = create_handler();
+        sicily(&handler).await;
+
+        let reply = run(
+            &handler,
+            &[
+                "geosearchstore",
+                "near",
+                "Sicily",
+                "FROMLONLAT",
+                "15",
+                "37",
+                "BYRADIUS",
+                "200",
+                "km",
+                "ASC",
+                "STOREDIST",
+            ],
+        )
+        .await;
+        assert!(matches!(reply, RespValue::Integer(2)));
+        let distance = handler
+            .storage
+            .inspect(b"near", |value| {
+                Ok(value.unwrap().as_zset()?.score(b"Catania"))
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(format!("{:.4}", distance), "56.4413");
+
+        // Stored scores are geohashes by default, so the result is a geo index
+        let reply = run(
+            &handler,
+            &[
+                "geosearchstore",
+                "near",
+                "Sicily",
+                "FROMLONLAT",
+                "15",
+                "37",
+                "BYRADIUS",
+                "100",
+                "km",
+            ],
+        )
+        .await;
+        assert!(matches!(reply, RespValue::Integer(1)));
+        assert_eq!(
+            strings(run(&handler, &["geohash", "near", "Catania"]).await),
+            ["sqdtr74hyu0"]
+        );
+
+        // An empty result deletes the destination
+        let reply = run(
+            &handler,
+            &[
+                "geosearchstore",
+                "near",
+                "Sicily",
+                "FROMLONLAT",
+                "0",
+                "0",
+                "BYRADIUS",
+                "1",
+                "km",
+            ],
+        )
+        .await;
+        assert!(matches!(reply, RespValue::Integer(0)));
+        assert!(!handler.storage.exists(b"near").await.unwrap());
+        assert!(matches!(
+            run(
+                &handler,
+                &[
+                    "geosearchstore", "near", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "1",
+                    "km", "WITHDIST",
+                ],
+            )
+            .await,
+            RespValue::Error(e) if e == "ERR syntax error"
+        ));
+    }
+}
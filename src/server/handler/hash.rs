@@ -0,0 +1,640 @@
+//! Hash commands (HSET, HGET, HGETALL, HINCRBY, HSCAN, ...).
+
+use super::scan::{scan_batch, ScanArgs, ScanKind};
+use super::{
+    format_float, parse_float, parse_int, parse_random_count, parse_utf8, storage_error,
+    syntax_error, wrong_args, Handler,
+};
+use crate::protocol::{ProtocolVersion, RespValue};
+use crate::server::notify::NOTIFY_HASH;
+use bytes::Bytes;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+impl Handler {
+    /// HSET key field value [field value ...] (also HMSET, which replies OK).
+    pub(super) async fn handle_hset(&self, args: &[&Bytes], command: &str) -> RespValue {
+        if args.len() < 3 || args.len().is_multiple_of(2) {
+            return wrong_args(command);
+        }
+
+        let pairs = &args[1..];
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let hash = slot.hash_mut()?;
+                let mut added = 0;
+                for pair in pairs.chunks(2) {
+                    if hash.insert(pair[0].clone(), pair[1].clone()).is_none() {
+                        added += 1;
+                    }
+                }
+                Ok(added)
+            })
+            .await;
+        if result.is_ok() {
+            self.notify(NOTIFY_HASH, "hset", args[0]);
+        }
+
+        match result {
+            Ok(_) if command == "hmset" => RespValue::SimpleString("OK".to_string()),
+            Ok(added) => RespValue::Integer(added),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// HSETNX key field value
+    pub(super) async fn handle_hsetnx(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("hsetnx");
+        }
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                if slot.hash()?.is_some_and(|hash| hash.contains_key(args[1])) {
+                    return Ok(false);
+                }
+                slot.hash_mut()?.insert(args[1].clone(), args[2].clone());
+                Ok(true)
+            })
+            .await;
+
+        match result {
+            Ok(set) => {
+                if set {
+                    self.notify(NOTIFY_HASH, "hset", args[0]);
+                }
+                RespValue::Integer(set as i64)
+            }
+            Err(e) => storage_error("hsetnx", e),
+        }
+    }
+
+    /// HGET key field
+    pub(super) async fn handle_hget(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("hget");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_hash()?.get(args[1]).cloned(),
+                    None => None,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(value) => RespValue::BulkString(value),
+            Err(e) => storage_error("hget", e),
+        }
+    }
+
+    /// HMGET key field [field ...]
+    pub(super) async fn handle_hmget(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("hmget");
+        }
+
+        let fields = &args[1..];
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let hash = value.map(|v| v.as_hash()).transpose()?;
+                Ok(fields
+                    .iter()
+                    .map(|field| RespValue::BulkString(hash.and_then(|h| h.get(*field).cloned())))
+                    .collect())
+            })
+            .await;
+
+        match result {
+            Ok(values) => RespValue::Array(Some(values)),
+            Err(e) => storage_error("hmget", e),
+        }
+    }
+
+    /// HGETALL key
+    pub(super) async fn handle_hgetall(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("hgetall");
+        }
+
+        match self.hash_entries(args[0]).await {
+            Ok(entries) => self.map_reply(
+                entries
+                    .into_iter()
+                    .map(|(f, v)| {
+                        (
+                            RespValue::BulkString(Some(f)),
+                            RespValue::BulkString(Some(v)),
+                        )
+                    })
+                    .collect(),
+            ),
+            Err(e) => e,
+        }
+    }
+
+    /// HDEL key field [field ...]
+    pub(super) async fn handle_hdel(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("hdel");
+        }
+
+        let fields = &args[1..];
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                if slot.hash()?.is_none() {
+                    return Ok(0);
+                }
+                let hash = slot.hash_mut()?;
+                Ok(fields
+                    .iter()
+                    .filter(|field| hash.remove(**field).is_some())
+                    .count())
+            })
+            .await;
+
+        match result {
+            Ok(removed) => {
+                if removed > 0 {
+                    self.notify(NOTIFY_HASH, "hdel", args[0]);
+                    self.notify_if_removed(args[0]).await;
+                }
+                RespValue::Integer(removed as i64)
+            }
+            Err(e) => storage_error("hdel", e),
+        }
+    }
+
+    /// HEXISTS key field
+    pub(super) async fn handle_hexists(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("hexists");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_hash()?.contains_key(args[1]),
+                    None => false,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(exists) => RespValue::Integer(exists as i64),
+            Err(e) => storage_error("hexists", e),
+        }
+    }
+
+    /// HLEN key
+    pub(super) async fn handle_hlen(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("hlen");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_hash()?.len(),
+                    None => 0,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(len) => RespValue::Integer(len as i64),
+            Err(e) => storage_error("hlen", e),
+        }
+    }
+
+    /// HKEYS key
+    pub(super) async fn handle_hkeys(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("hkeys");
+        }
+
+        match self.hash_entries(args[0]).await {
+            Ok(entries) => RespValue::Array(Some(
+                entries
+                    .into_iter()
+                    .map(|(f, _)| RespValue::BulkString(Some(f)))
+                    .collect(),
+            )),
+            Err(e) => e,
+        }
+    }
+
+    /// HVALS key
+    pub(super) async fn handle_hvals(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("hvals");
+        }
+
+        match self.hash_entries(args[0]).await {
+            Ok(entries) => RespValue::Array(Some(
+                entries
+                    .into_iter()
+                    .map(|(_, v)| RespValue::BulkString(Some(v)))
+                    .collect(),
+            )),
+            Err(e) => e,
+        }
+    }
+
+    /// HSTRLEN key field
+    pub(super) async fn handle_hstrlen(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("hstrlen");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_hash()?.get(args[1]).map_or(0, |v| v.len()),
+                    None => 0,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(len) => RespValue::Integer(len as i64),
+            Err(e) => storage_error("hstrlen", e),
+        }
+    }
+
+    /// HINCRBY key field increment
+    pub(super) async fn handle_hincrby(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("hincrby");
+        }
+        let increment = match parse_int(args[2]) {
+            Ok(increment) => increment,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let current = match slot.hash()?.and_then(|hash| hash.get(args[1])) {
+                    Some(value) => match parse_utf8::<i64>(value) {
+                        Some(current) => current,
+                        None => return Ok(Err("ERR hash value is not an integer")),
+                    },
+                    None => 0,
+                };
+                let Some(updated) = current.checked_add(increment) else {
+                    return Ok(Err("ERR increment or decrement would overflow"));
+                };
+                slot.hash_mut()?
+                    .insert(args[1].clone(), updated.to_string().into());
+                Ok(Ok(updated))
+            })
+            .await;
+
+        match result {
+            Ok(Ok(value)) => {
+                self.notify(NOTIFY_HASH, "hincrby", args[0]);
+                RespValue::Integer(value)
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("hincrby", e),
+        }
+    }
+
+    /// HINCRBYFLOAT key field increment
+    pub(super) async fn handle_hincrbyfloat(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("hincrbyfloat");
+        }
+        let increment = match parse_float(args[2]) {
+            Ok(increment) => increment,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let current = match slot.hash()?.and_then(|hash| hash.get(args[1])) {
+                    Some(value) => match parse_utf8::<f64>(value).filter(|f| !f.is_nan()) {
+                        Some(current) => current,
+                        None => return Ok(Err("ERR hash value is not a float")),
+                    },
+                    None => 0.0,
+                };
+                let updated = current + increment;
+                if !updated.is_finite() {
+                    return Ok(Err("ERR increment would produce NaN or Infinity"));
+                }
+                let formatted = Bytes::from(format_float(updated));
+                slot.hash_mut()?.insert(args[1].clone(), formatted.clone());
+                Ok(Ok(formatted))
+            })
+            .await;
+
+        match result {
+            Ok(Ok(value)) => {
+                self.notify(NOTIFY_HASH, "hincrbyfloat", args[0]);
+                RespValue::BulkString(Some(value))
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("hincrbyfloat", e),
+        }
+    }
+
+    /// HRANDFIELD key [count [WITHVALUES]]
+    pub(super) async fn handle_hrandfield(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() || args.len() > 3 {
+            return wrong_args("hrandfield");
+        }
+        let count = match args.get(1).map(|c| parse_random_count(c)).transpose() {
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+        let with_values = match args.get(2) {
+            Some(flag) if flag.eq_ignore_ascii_case(b"WITHVALUES") => true,
+            Some(_) => return syntax_error(),
+            None => false,
+        };
+
+        let entries = match self.hash_entries(args[0]).await {
+            Ok(entries) => entries,
+            Err(e) => return e,
+        };
+        let mut rng = rand::thread_rng();
+
+        let Some(count) = count else {
+            return RespValue::BulkString(entries.choose(&mut rng).map(|(f, _)| f.clone()));
+        };
+
+        // Positive count: distinct fields; negative count: may repeat
+        let picked: Vec<&(Bytes, Bytes)> = if count >= 0 {
+            entries.choose_multiple(&mut rng, count as usize).collect()
+        } else if entries.is_empty() {
+            Vec::new()
+        } else {
+            (0..count.unsigned_abs())
+                .map(|_| &entries[rng.gen_range(0..entries.len())])
+                .collect()
+        };
+
+        let items = picked
+            .into_iter()
+            .flat_map(|(f, v)| {
+                let field = RespValue::BulkString(Some(f.clone()));
+                let value = RespValue::BulkString(Some(v.clone()));
+                match (with_values, self.protocol_version) {
+                    (false, _) => vec![field],
+                    (true, ProtocolVersion::Resp3) => {
+                        vec![RespValue::Array(Some(vec![field, value]))]
+                    }
+                    (true, ProtocolVersion::Resp2) => vec![field, value],
+                }
+            })
+            .collect();
+        RespValue::Array(Some(items))
+    }
+
+    /// HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]
+    pub(super) async fn handle_hscan(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("hscan");
+        }
+        let scan = match ScanArgs::parse(&args[1..], ScanKind::Hash) {
+            Ok(scan) => scan,
+            Err(e) => return e,
+        };
+
+        let entries = match self.hash_entries(args[0]).await {
+            Ok(entries) => entries,
+            Err(e) => return e,
+        };
+
+        let (cursor, batch) = scan_batch(entries.into_iter(), scan.cursor, scan.count);
+        let items = batch
+            .into_iter()
+            .filter(|(field, _)| scan.matches(field))
+            .flat_map(|(field, value)| {
+                let mut out = vec![RespValue::BulkString(Some(field))];
+                if !scan.novalues {
+                    out.push(RespValue::BulkString(Some(value)));
+                }
+                out
+            })
+            .collect();
+
+        RespValue::Array(Some(vec![
+            RespValue::BulkString(Some(cursor.to_string().into())),
+            RespValue::Array(Some(items)),
+        ]))
+    }
+
+    /// Snapshot all field/value pairs of a hash (empty if the key is missing).
+    async fn hash_entries(&self, key: &[u8]) -> Result<Vec<(Bytes, Bytes)>, RespValue> {
+        self.storage
+            .inspect(key, |value| {
+                Ok(match value {
+                    Some(value) => value
+                        .as_hash()?
+                        .iter()
+                        .map(|(f, v)| (f.clone(), v.clone()))
+                        .collect(),
+                    None => Vec::new(),
+                })
+            })
+            .await
+            .map_err(|e| storage_error("hash read", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    fn b(s: &str) -> Bytes {
+        Bytes::from(s.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_hset_hget_hdel() {
+        let handler = create_handler();
+        let (key, f1, v1, f2, v2) = (b("h"), b("f1"), b("v1"), b("f2"), b("v2"));
+
+        let result = handler
+            .handle_hset(&[&key, &f1, &v1, &f2, &v2], "hset")
+            .await;
+        assert!(matches!(result, RespValue::Integer(2)));
+        let result = handler.handle_hset(&[&key, &f1, &v2], "hset").await;
+        assert!(matches!(result, RespValue::Integer(0)));
+
+        match handler.handle_hget(&[&key, &f1]).await {
+            RespValue::BulkString(Some(v)) => assert_eq!(v, "v2"),
+            _ => panic!("Expected BulkString"),
+        }
+
+        assert!(matches!(
+            handler.handle_hdel(&[&key, &f1, &f2, &b("missing")]).await,
+            RespValue::Integer(2)
+        ));
+        // Deleting the last field removes the key
+        assert!(!handler.storage.exists(&key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_hgetall_resp2_and_resp3() {
+        let mut handler = create_handler();
+        let key = b("h");
+        handler
+            .handle_hset(&[&key, &b("a"), &b("1"), &b("b"), &b("2")], "hset")
+            .await;
+
+        match handler.handle_hgetall(&[&key]).await {
+            RespValue::Array(Some(items)) => assert_eq!(items.len(), 4),
+            _ => panic!("Expected flat Array under RESP2"),
+        }
+
+        handler.set_protocol_version(ProtocolVersion::Resp3);
+        match handler.handle_hgetall(&[&key]).await {
+            RespValue::Map(pairs) => assert_eq!(pairs.len(), 2),
+            _ => panic!("Expected Map under RESP3"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hincrby_and_errors() {
+        let handler = create_handler();
+        let (key, field) = (b("h"), b("n"));
+
+        assert!(matches!(
+            handler.handle_hincrby(&[&key, &field, &b("5")]).await,
+            RespValue::Integer(5)
+        ));
+        assert!(matches!(
+            handler.handle_hincrby(&[&key, &field, &b("-7")]).await,
+            RespValue::Integer(-2)
+        ));
+
+        handler
+            .handle_hset(&[&key, &b("s"), &b("abc")], "hset")
+            .await;
+        match handler.handle_hincrby(&[&key, &b("s"), &b("1")]).await {
+            RespValue::Error(msg) => assert!(msg.contains("not an integer")),
+            _ => panic!("Expected Error"),
+        }
+
+        handler
+            .handle_hset(&[&key, &b("max"), &b(&i64::MAX.to_string())], "hset")
+            .await;
+        match handler.handle_hincrby(&[&key, &b("max"), &b("1")]).await {
+            RespValue::Error(msg) => assert!(msg.contains("overflow")),
+            _ => panic!("Expected Error"),
+        }
+
+        match handler
+            .handle_hincrbyfloat(&[&key, &b("f"), &b("10.5")])
+            .await
+        {
+            RespValue::BulkString(Some(v)) => assert_eq!(v, "10.5"),
+            _ => panic!("Expected BulkString"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hash_wrongtype() {
+        let handler = create_handler();
+        handler.storage.set(b"str", b"value").await.unwrap();
+        let key = b("str");
+
+        match handler.handle_hset(&[&key, &b("f"), &b("v")], "hset").await {
+            RespValue::Error(msg) => assert!(msg.starts_with("WRONGTYPE")),
+            _ => panic!("Expected WRONGTYPE"),
+        }
+        match handler.handle_hget(&[&key, &b("f")]).await {
+            RespValue::Error(msg) => assert!(msg.starts_with("WRONGTYPE")),
+            _ => panic!("Expected WRONGTYPE"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hscan_and_hrandfield() {
+        let handler = create_handler();
+        let key = b("h");
+        for i in 0..50 {
+            handler
+                .handle_hset(&[&key, &b(&format!("f{}", i)), &b("v")], "hset")
+                .await;
+        }
+
+        let mut cursor = b("0");
+        let mut fields = 0;
+        loop {
+            match handler
+                .handle_hscan(&[&key, &cursor, &b("COUNT"), &b("8")])
+                .await
+            {
+                RespValue::Array(Some(reply)) => {
+                    if let RespValue::Array(Some(items)) = &reply[1] {
+                        fields += items.len() / 2;
+                    }
+                    match &reply[0] {
+                        RespValue::BulkString(Some(next)) => cursor = next.clone(),
+                        _ => panic!("Expected cursor"),
+                    }
+                }
+                _ => panic!("Expected Array"),
+            }
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(fields, 50);
+
+        match handler.handle_hrandfield(&[&key, &b("-80")]).await {
+            RespValue::Array(Some(items)) => assert_eq!(items.len(), 80),
+            _ => panic!("Expected Array"),
+        }
+        match handler
+            .handle_hrandfield(&[&key, &b("80"), &b("WITHVALUES")])
+            .await
+        {
+            RespValue::Array(Some(items)) => assert_eq!(items.len(), 100),
+            _ => panic!("Expected Array"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hrandfield_rejects_huge_negative_counts() {
+        let handler = create_handler();
+        let key = b("h");
+        handler.handle_hset(&[&key, &b("f"), &b("v")], "hset").await;
+
+        for count in ["-9223372036854775808", "-100000000000"] {
+            for args in [
+                vec![&key, &b(count)],
+                vec![&key, &b(count), &b("WITHVALUES")],
+            ] {
+                assert!(matches!(
+                    handler.handle_hrandfield(&args).await,
+                    RespValue::Error(msg) if msg == "ERR value is out of range"
+                ));
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! HyperLogLog commands: PFADD, PFCOUNT and PFMERGE.
+//!
+//! HyperLogLogs are string values in Redis's encoding (see
+//! [`crate::hyperloglog`]), so GET/SET and values dumped from Redis work on
+//! them unchanged.
+
+use super::string::{store_string, string_value};
+use super::{distinct_keys, storage_error, wrong_args, Handler};
+use crate::hyperloglog::HyperLogLog;
+use crate::protocol::RespValue;
+use crate::server::notify::NOTIFY_STRING;
+use crate::storage::{StorageError, ValueSlot};
+use bytes::Bytes;
+
+const INVALID_HLL: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+
+/// Decode the HyperLogLog held in a slot; `Ok(Err(..))` if the string is not
+/// a valid one.
+fn hll_value(slot: &ValueSlot) -> Result<Result<Option<HyperLogLog>, &'static str>, StorageError> {
+    Ok(match string_value(slot)? {
+        Some(data) => HyperLogLog::decode(data).map(Some).ok_or(INVALID_HLL),
+        None => Ok(None),
+    })
+}
+
+impl Handler {
+    /// PFADD key [element ...]
+    pub(super) async fn handle_pfadd(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("pfadd");
+        }
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let (mut hll, created) = match hll_value(slot)? {
+                    Ok(Some(hll)) => (hll, false),
+                    Ok(None) => (HyperLogLog::new(), true),
+                    Err(msg) => return Ok(Err(msg)),
+                };
+                let mut changed = created;
+                for element in &args[1..] {
+                    changed |= hll.add(element);
+                }
+                if changed {
+                    store_string(slot, Bytes::from(hll.encode()));
+                }
+                Ok(Ok(changed))
+            })
+            .await;
+        match result {
+            Ok(Ok(changed)) => {
+                if changed {
+                    self.notify(NOTIFY_STRING, "pfadd", args[0]);
+                }
+                RespValue::Integer(changed as i64)
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("pfadd", e),
+        }
+    }
+
+    /// PFCOUNT key [key ...]
+    pub(super) async fn handle_pfcount(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("pfcount");
+        }
+
+        let result = if args.len() == 1 {
+            // A single key's count is cached in its header
+            self.storage
+                .modify(args[0], |slot| {
+                    let hll = match hll_value(slot)? {
+                        Ok(Some(hll)) => hll,
+                        Ok(None) => return Ok(Ok(0)),
+                        Err(msg) => return Ok(Err(msg)),
+                    };
+                    if let Some(count) = hll.cached_count() {
+                        return Ok(Ok(count));
+                    }
+                    let count = hll.count();
+                    if let Some(data) = string_value(slot)? {
+                        let data = HyperLogLog::with_cached_count(data, count);
+                        store_string(slot, Bytes::from(data));
+                    }
+                    Ok(Ok(count))
+                })
+                .await
+        } else {
+            // The union is counted without being stored
+            let (keys, _) = distinct_keys(args);
+            self.storage
+                .modify_many(&keys, |slots| {
+                    let mut union = HyperLogLog::new();
+                    for slot in slots.iter() {
+                        match hll_value(slot)? {
+                            Ok(Some(hll)) => union.merge(&hll),
+                            Ok(None) => {}
+                            Err(msg) => return Ok(Err(msg)),
+                        }
+                    }
+                    Ok(Ok(union.count()))
+                })
+                .await
+        };
+        match result {
+            Ok(Ok(count)) => RespValue::Integer(count as i64),
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("pfcount", e),
+        }
+    }
+
+    /// PFMERGE destkey [sourcekey ...]
+    pub(super) async fn handle_pfmerge(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("pfmerge");
+        }
+
+        // The destination is part of the union, and may also be a source
+        let (keys, indexes) = distinct_keys(args);
+        let result = self
+            .storage
+            .modify_many(&keys, |slots| {
+                let mut union = HyperLogLog::new();
+                for slot in slots.iter() {
+                    match hll_value(slot)? {
+                        Ok(Some(hll)) => union.merge(&hll),
+                        Ok(None) => {}
+                        Err(msg) => return Ok(Err(msg)),
+                    }
+                }
+                store_string(&mut slots[indexes[0]], Bytes::from(union.encode()));
+                Ok(Ok(()))
+            })
+            .await;
+        match result {
+            Ok(Ok(())) => {
+                // Redis reports PFMERGE as a PFADD to the destination
+                self.notify(NOTIFY_STRING, "pfadd", args[0]);
+                RespValue::SimpleString("OK".to_string())
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("pfmerge", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    fn int(value: RespValue) -> i64 {
+        match value {
+            RespValue::Integer(n) => n,
+            other => panic!("Expected Integer, got {:?}", other),
+        }
+    }
+
+    async fn pfadd(handler: &Handler, key: &str, elements: impl Iterator<Item = String>) -> i64 {
+        let mut owned = vec![Bytes::from(key.to_string())];
+        owned.extend(elements.map(Bytes::from));
+        let args: Vec<&Bytes> = owned.iter().collect();
+        int(handler.handle_pfadd(&args).await)
+    }
+
+    async fn pfcount(handler: &Handler, keys: &[&str]) -> RespValue {
+        let owned: Vec<Bytes> = keys
+            .iter()
+            .map(|key| Bytes::from(key.to_string()))
+            .collect();
+        let args: Vec<&Bytes> = owned.iter().collect();
+        handler.handle_pfcount(&args).await
+    }
+
+    #[tokio::test]
+    async fn test_pfadd_pfcount() {
+        let handler = create_handler();
+        assert_eq!(pfadd(&handler, "hll", std::iter::empty()).await, 1);
+        assert_eq!(pfadd(&handler, "hll", std::iter::empty()).await, 0);
+        assert_eq!(int(pfcount(&handler, &["hll"]).await), 0);
+
+        let elements = |range: std::ops::Range<u32>| range.map(|i| format!("visitor:{}", i));
+        assert_eq!(pfadd(&handler, "hll", elements(0..3)).await, 1);
+        assert_eq!(pfadd(&handler, "hll", elements(0..3)).await, 0);
+        assert_eq!(int(pfcount(&handler, &["hll"]).await), 3);
+
+        // The count is cached in the stored header until the next change
+        let stored = handler.storage.get(b"hll").await.unwrap().unwrap();
+        assert_eq!(
+            HyperLogLog::decode(&stored).unwrap().cached_count(),
+            Some(3)
+        );
+        assert_eq!(int(pfcount(&handler, &["hll", "missing"]).await), 3);
+
+        handler.storage.set(b"plain", b"value").await.unwrap();
+        assert!(matches!(
+            pfcount(&handler, &["plain"]).await,
+            RespValue::Error(e) if e.starts_with("WRONGTYPE Key is not a valid HyperLogLog")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_union_count_and_merge() {
+        let handler = create_handler();
+        let elements = |range: std::ops::Range<u32>| range.map(|i| format!("visitor:{}", i));
+        pfadd(&handler, "monday", elements(0..6_000)).await;
+        pfadd(&handler, "tuesday", elements(4_000..10_000)).await;
+
+        let union = int(pfcount(&handler, &["monday", "tuesday"]).await);
+        assert!(
+            (9_700..=10_300).contains(&union),
+            "union counted as {}",
+            union
+        );
+
+        let owned = [
+            Bytes::from("week"),
+            Bytes::from("monday"),
+            Bytes::from("tuesday"),
+        ];
+        let args: Vec<&Bytes> = owned.iter().collect();
+        assert!(
+            matches!(handler.handle_pfmerge(&args).await, RespValue::SimpleString(s) if s == "OK")
+        );
+        assert_eq!(int(pfcount(&handler, &["week"]).await), union);
+
+        // Merging into an existing key keeps its registers
+        pfadd(&handler, "week", elements(10_000..12_000)).await;
+        let args: Vec<&Bytes> = owned[..2].iter().collect();
+        handler.handle_pfmerge(&args).await;
+        let merged = int(pfcount(&handler, &["week"]).await);
+        assert!(
+            (11_600..=12_400).contains(&merged),
+            "merge counted as {}",
+            merged
+        );
+    }
+
+    #[tokio::test]
+    async fn test_persists_in_lmdb() {
+        use crate::storage::lmdb::LmdbStorage;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.lmdb");
+        let elements = |range: std::ops::Range<u32>| range.map(|i| format!("visitor:{}", i));
+        let open = || {
+            let storage: Arc<dyn StorageBackend> =
+                Arc::new(LmdbStorage::new_with_map_size(&path, 64 * 1024 * 1024).unwrap());
+            Handler::new(storage)
+        };
+
+        let count = {
+            let handler = open();
+            pfadd(&handler, "sparse", elements(0..100)).await;
+            pfadd(&handler, "dense", elements(0..50_000)).await;
+            (
+                int(pfcount(&handler, &["sparse"]).await),
+                int(pfcount(&handler, &["dense"]).await),
+            )
+        };
+
+        let handler = open();
+        assert_eq!(int(pfcount(&handler, &["sparse"]).await), count.0);
+        assert_eq!(int(pfcount(&handler, &["dense"]).await), count.1);
+        assert_eq!(pfadd(&handler, "sparse", elements(0..100)).await, 0);
+    }
+}
@@ -0,0 +1,626 @@
+//! Generic key commands: EXPIRE family, TTL family, PERSIST, SCAN, KEYS and
+//! key management (RENAME, COPY, TYPE, ...).
+
+use super::scan::{ScanArgs, ScanKind};
+use super::{expire_deadline, parse_int, storage_error, syntax_error, wrong_args, Handler};
+use crate::protocol::RespValue;
+use crate::server::notify::NOTIFY_GENERIC;
+use crate::storage::ExpiryCondition;
+use bytes::Bytes;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of keys KEYS examines per storage scan call.
+const KEYS_BATCH: usize = 1000;
+
+/// Which view of a key's expiry TTL-style commands report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TtlReply {
+    /// TTL / PTTL: time left.
+    Remaining,
+    /// EXPIRETIME / PEXPIRETIME: absolute Unix time.
+    Deadline,
+}
+
+/// Parse the NX/XX/GT/LT flags of the EXPIRE family.
+fn parse_expiry_condition(args: &[&Bytes]) -> Result<ExpiryCondition, RespValue> {
+    let mut condition = ExpiryCondition::default();
+    for arg in args {
+        match arg.to_ascii_uppercase().as_slice() {
+            b"NX" => condition.no_expiry = true,
+            b"XX" => condition.has_expiry = true,
+            b"GT" => condition.later = true,
+            b"LT" => condition.earlier = true,
+            _ => {
+                return Err(RespValue::Error(format!(
+                    "ERR Unsupported option {}",
+                    String::from_utf8_lossy(arg)
+                )))
+            }
+        }
+    }
+
+    if condition.no_expiry && (condition.has_expiry || condition.later || condition.earlier) {
+        return Err(RespValue::Error(
+            "ERR NX and XX, GT or LT options at the same time are not compatible".to_string(),
+        ));
+    }
+    if condition.later && condition.earlier {
+        return Err(RespValue::Error(
+            "ERR GT and LT options at the same time are not compatible".to_string(),
+        ));
+    }
+    Ok(condition)
+}
+
+impl Handler {
+    /// EXPIRE / PEXPIRE / EXPIREAT / PEXPIREAT key amount [NX|XX|GT|LT]
+    pub(super) async fn handle_expire(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        millis: bool,
+        absolute: bool,
+    ) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args(command);
+        }
+        let amount = match parse_int(args[1]) {
+            Ok(amount) => amount,
+            Err(e) => return e,
+        };
+        let condition = match parse_expiry_condition(&args[2..]) {
+            Ok(condition) => condition,
+            Err(e) => return e,
+        };
+        let deadline = match expire_deadline(amount, millis, absolute, command) {
+            Ok(deadline) => deadline,
+            Err(e) => return e,
+        };
+
+        match self.storage.set_expiry(args[0], deadline, condition).await {
+            Ok(applied) => {
+                if applied {
+                    // A deadline in the past removes the key
+                    let event = if deadline <= SystemTime::now() {
+                        "del"
+                    } else {
+                        "expire"
+                    };
+                    self.notify(NOTIFY_GENERIC, event, args[0]);
+                }
+                RespValue::Integer(applied as i64)
+            }
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// TTL / PTTL / EXPIRETIME / PEXPIRETIME key: -2 if the key does not
+    /// exist, -1 if it has no expiry.
+    pub(super) async fn handle_ttl(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        millis: bool,
+        reply: TtlReply,
+    ) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args(command);
+        }
+
+        let expiry = match self.storage.get_expiry(args[0]).await {
+            Ok(expiry) => expiry,
+            Err(e) => return storage_error(command, e),
+        };
+        let Some(expiry) = expiry else {
+            return RespValue::Integer(-2);
+        };
+        let Some(expires_at) = expiry else {
+            return RespValue::Integer(-1);
+        };
+
+        let ms = match reply {
+            TtlReply::Remaining => expires_at
+                .duration_since(SystemTime::now())
+                .map_or(0, |left| left.as_millis()),
+            TtlReply::Deadline => expires_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis()),
+        } as i64;
+        RespValue::Integer(match (millis, reply) {
+            (true, _) => ms,
+            // TTL rounds to the nearest second, as Redis does
+            (false, TtlReply::Remaining) => (ms + 500) / 1000,
+            (false, TtlReply::Deadline) => ms / 1000,
+        })
+    }
+
+    /// PERSIST key
+    pub(super) async fn handle_persist(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("persist");
+        }
+
+        match self.storage.clear_expiry(args[0]).await {
+            Ok(cleared) => {
+                if cleared {
+                    self.notify(NOTIFY_GENERIC, "persist", args[0]);
+                }
+                RespValue::Integer(cleared as i64)
+            }
+            Err(e) => storage_error("persist", e),
+        }
+    }
+
+    /// RENAME / RENAMENX key newkey
+    ///
+    /// The value moves with its TTL, in one atomic step on backends whose
+    /// multi-key updates are atomic.
+    pub(super) async fn handle_rename(&self, args: &[&Bytes], nx: bool) -> RespValue {
+        let command = if nx { "renamenx" } else { "rename" };
+        if args.len() != 2 {
+            return wrong_args(command);
+        }
+        let (source, destination) = (args[0], args[1]);
+
+        let result = if source == destination {
+            // Renaming a key to itself only checks that it exists
+            self.storage
+                .exists(source)
+                .await
+                .map(|exists| exists.then_some(!nx))
+        } else {
+            self.storage
+                .modify_many(&[source, destination], |slots| {
+                    let Some(value) = slots[0].get().cloned() else {
+                        return Ok(None);
+                    };
+                    if nx && slots[1].get().is_some() {
+                        return Ok(Some(false));
+                    }
+                    slots[0].delete();
+                    slots[1].set(value);
+                    Ok(Some(true))
+                })
+                .await
+        };
+
+        match result {
+            Ok(None) => RespValue::Error("ERR no such key".to_string()),
+            Ok(Some(renamed)) => {
+                if renamed {
+                    self.notify(NOTIFY_GENERIC, "rename_from", source);
+                    self.notify(NOTIFY_GENERIC, "rename_to", destination);
+                    self.context.blocking.signal_all(destination);
+                }
+                if nx {
+                    RespValue::Integer(renamed as i64)
+                } else {
+                    RespValue::SimpleString("OK".to_string())
+                }
+            }
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// COPY source destination [DB destination-db] [REPLACE]
+    pub(super) async fn handle_copy(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("copy");
+        }
+        let (source, destination) = (args[0], args[1]);
+
+        let mut replace = false;
+        let mut i = 2;
+        while i < args.len() {
+            if args[i].eq_ignore_ascii_case(b"REPLACE") {
+                replace = true;
+                i += 1;
+            } else if args[i].eq_ignore_ascii_case(b"DB") && i + 1 < args.len() {
+                // Coral has a single database
+                match parse_int(args[i + 1]) {
+                    Ok(0) => {}
+                    Ok(_) => return RespValue::Error("ERR DB index is out of range".to_string()),
+                    Err(e) => return e,
+                }
+                i += 2;
+            } else {
+                return syntax_error();
+            }
+        }
+        if source == destination {
+            return RespValue::Error("ERR source and destination objects are the same".to_string());
+        }
+
+        let result = self
+            .storage
+            .modify_many(&[source, destination], |slots| {
+                let Some(value) = slots[0].get().cloned() else {
+                    return Ok(false);
+                };
+                if !replace && slots[1].get().is_some() {
+                    return Ok(false);
+                }
+                slots[1].set(value);
+                Ok(true)
+            })
+            .await;
+
+        match result {
+            Ok(copied) => {
+                if copied {
+                    self.notify(NOTIFY_GENERIC, "copy_to", destination);
+                    self.context.blocking.signal_all(destination);
+                }
+                RespValue::Integer(copied as i64)
+            }
+            Err(e) => storage_error("copy", e),
+        }
+    }
+
+    /// TYPE key
+    pub(super) async fn handle_type(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("type");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(value.map_or("none", |value| value.data.type_name()))
+            })
+            .await;
+        match result {
+            Ok(name) => RespValue::SimpleString(name.to_string()),
+            Err(e) => storage_error("type", e),
+        }
+    }
+
+    /// TOUCH key [key ...]: number of keys that exist.
+    pub(super) async fn handle_touch(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("touch");
+        }
+
+        let mut touched = 0;
+        for key in args {
+            match self.storage.exists(key).await {
+                Ok(true) => touched += 1,
+                Ok(false) => {}
+                Err(e) => return storage_error("touch", e),
+            }
+        }
+        RespValue::Integer(touched)
+    }
+
+    /// RANDOMKEY
+    pub(super) async fn handle_randomkey(&self, args: &[&Bytes]) -> RespValue {
+        if !args.is_empty() {
+            return wrong_args("randomkey");
+        }
+
+        match self.storage.random_key().await {
+            Ok(key) => RespValue::BulkString(key),
+            Err(e) => storage_error("randomkey", e),
+        }
+    }
+
+    /// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+    ///
+    /// Cursor numbers stand for storage cursors kept in the shared
+    /// [`CursorRegistry`](crate::server::cursors::CursorRegistry).
+    pub(super) async fn handle_scan(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("scan");
+        }
+        let scan = match ScanArgs::parse(args, ScanKind::Keys) {
+            Ok(scan) => scan,
+            Err(e) => return e,
+        };
+        let cursor = match scan.cursor {
+            0 => None,
+            id => match self.context.scan_cursors.resolve(id) {
+                Some(cursor) => Some(cursor),
+                None => return RespValue::Error("ERR invalid cursor".to_string()),
+            },
+        };
+
+        let batch = match self
+            .storage
+            .scan(cursor.as_deref(), scan.pattern.as_deref(), scan.count)
+            .await
+        {
+            Ok(batch) => batch,
+            Err(e) => return storage_error("scan", e),
+        };
+
+        let mut keys = Vec::with_capacity(batch.keys.len());
+        for key in batch.keys {
+            if let Some(key_type) = &scan.key_type {
+                let found = self
+                    .storage
+                    .inspect(&key, |value| Ok(value.map(|v| v.data.type_name())))
+                    .await;
+                match found {
+                    Ok(Some(name)) if name == key_type => {}
+                    Ok(_) => continue,
+                    Err(e) => return storage_error("scan", e),
+                }
+            }
+            keys.push(RespValue::BulkString(Some(key)));
+        }
+
+        let next = batch
+            .cursor
+            .map_or(0, |cursor| self.context.scan_cursors.register(cursor));
+        RespValue::Array(Some(vec![
+            RespValue::BulkString(Some(next.to_string().into())),
+            RespValue::Array(Some(keys)),
+        ]))
+    }
+
+    /// KEYS pattern
+    pub(super) async fn handle_keys(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("keys");
+        }
+
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let batch = match self
+                .storage
+                .scan(cursor.as_deref(), Some(args[0]), KEYS_BATCH)
+                .await
+            {
+                Ok(batch) => batch,
+                Err(e) => return storage_error("keys", e),
+            };
+            keys.extend(
+                batch
+                    .keys
+                    .into_iter()
+                    .map(|key| RespValue::BulkString(Some(key))),
+            );
+            cursor = batch.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        RespValue::Array(Some(keys))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::{ListEnd, StorageBackend};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    fn b(s: &str) -> Bytes {
+        Bytes::from(s.to_string())
+    }
+
+    fn int(value: RespValue) -> i64 {
+        match value {
+            RespValue::Integer(n) => n,
+            other => panic!("Expected Integer, got {:?}", other),
+        }
+    }
+
+    async fn expire(handler: &Handler, args: &[&str]) -> RespValue {
+        let args: Vec<Bytes> = args.iter().map(|arg| b(arg)).collect();
+        let args: Vec<&Bytes> = args.iter().collect();
+        handler.handle_expire(&args, "expire", false, false).await
+    }
+
+    async fn ttl(handler: &Handler, key: &str, millis: bool) -> i64 {
+        int(handler
+            .handle_ttl(&[&b(key)], "ttl", millis, TtlReply::Remaining)
+            .await)
+    }
+
+    #[tokio::test]
+    async fn test_expire_ttl_persist() {
+        let handler = create_handler();
+        assert_eq!(ttl(&handler, "k", false).await, -2);
+        assert_eq!(int(expire(&handler, &["k", "100"]).await), 0);
+
+        handler.storage.set(b"k", b"v").await.unwrap();
+        assert_eq!(ttl(&handler, "k", false).await, -1);
+        assert_eq!(int(expire(&handler, &["k", "100"]).await), 1);
+        assert_eq!(ttl(&handler, "k", false).await, 100);
+        assert!(ttl(&handler, "k", true).await > 99_000);
+
+        let deadline = int(handler
+            .handle_ttl(&[&b("k")], "expiretime", false, TtlReply::Deadline)
+            .await);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        assert!((now + 99..=now + 100).contains(&deadline));
+
+        assert_eq!(int(handler.handle_persist(&[&b("k")]).await), 1);
+        assert_eq!(int(handler.handle_persist(&[&b("k")]).await), 0);
+        assert_eq!(ttl(&handler, "k", false).await, -1);
+
+        // A deadline in the past deletes the key
+        let past = b("1");
+        assert_eq!(
+            int(handler
+                .handle_expire(&[&b("k"), &past], "pexpireat", true, true)
+                .await),
+            1
+        );
+        assert_eq!(handler.storage.get(b"k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_expire_conditions() {
+        let handler = create_handler();
+        handler.storage.set(b"k", b"v").await.unwrap();
+
+        assert_eq!(int(expire(&handler, &["k", "100", "XX"]).await), 0);
+        assert_eq!(int(expire(&handler, &["k", "100", "GT"]).await), 0);
+        assert_eq!(int(expire(&handler, &["k", "100", "LT"]).await), 1);
+        assert_eq!(int(expire(&handler, &["k", "50", "NX"]).await), 0);
+        assert_eq!(int(expire(&handler, &["k", "200", "LT"]).await), 0);
+        assert_eq!(int(expire(&handler, &["k", "200", "GT", "XX"]).await), 1);
+        assert_eq!(int(expire(&handler, &["k", "50", "xx", "lt"]).await), 1);
+        assert_eq!(ttl(&handler, "k", false).await, 50);
+
+        for (args, message) in [
+            (
+                &["k", "1", "NX", "XX"][..],
+                "ERR NX and XX, GT or LT options at the same time are not compatible",
+            ),
+            (
+                &["k", "1", "GT", "LT"],
+                "ERR GT and LT options at the same time are not compatible",
+            ),
+            (&["k", "1", "SOON"], "ERR Unsupported option SOON"),
+            (
+                &["k", "9223372036854775807"],
+                "ERR invalid expire time in 'expire' command",
+            ),
+        ] {
+            match expire(&handler, args).await {
+                RespValue::Error(msg) => assert_eq!(msg, message),
+                other => panic!("Expected Error, got {:?}", other),
+            }
+        }
+        assert!(ttl(&handler, "k", true).await > Duration::from_secs(40).as_millis() as i64);
+    }
+
+    fn scan_reply(value: RespValue) -> (String, Vec<Bytes>) {
+        let RespValue::Array(Some(mut reply)) = value else {
+            panic!("Expected Array, got {:?}", value);
+        };
+        let Some(RespValue::Array(Some(keys))) = reply.pop() else {
+            panic!("Expected key array");
+        };
+        let Some(RespValue::BulkString(Some(cursor))) = reply.pop() else {
+            panic!("Expected cursor");
+        };
+        let keys = keys
+            .into_iter()
+            .map(|key| match key {
+                RespValue::BulkString(Some(key)) => key,
+                other => panic!("Expected BulkString, got {:?}", other),
+            })
+            .collect();
+        (String::from_utf8(cursor.to_vec()).unwrap(), keys)
+    }
+
+    #[tokio::test]
+    async fn test_scan_and_keys() {
+        let handler = create_handler();
+        for i in 0..25 {
+            handler
+                .storage
+                .set(format!("user:{}", i).as_bytes(), b"v")
+                .await
+                .unwrap();
+        }
+        handler
+            .storage
+            .modify(b"user:hash", |slot| {
+                slot.hash_mut()?.insert(b("f"), b("v"));
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let mut cursor = "0".to_string();
+        let mut seen = Vec::new();
+        loop {
+            let args = [b(&cursor), b("COUNT"), b("4"), b("type"), b("STRING")];
+            let args: Vec<&Bytes> = args.iter().collect();
+            let (next, keys) = scan_reply(handler.handle_scan(&args).await);
+            seen.extend(keys);
+            cursor = next;
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(seen.len(), 25);
+        assert!(!seen.contains(&b("user:hash")));
+
+        let (cursor, keys) = scan_reply(
+            handler
+                .handle_scan(&[&b("0"), &b("MATCH"), &b("user:h*"), &b("COUNT"), &b("100")])
+                .await,
+        );
+        assert_eq!((cursor.as_str(), keys), ("0", vec![b("user:hash")]));
+
+        match handler.handle_keys(&[&b("user:1?")]).await {
+            RespValue::Array(Some(keys)) => assert_eq!(keys.len(), 10),
+            other => panic!("Expected Array, got {:?}", other),
+        }
+        assert!(matches!(
+            handler.handle_scan(&[&b("12345")]).await,
+            RespValue::Error(msg) if msg == "ERR invalid cursor"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rename_copy_type() {
+        let handler = create_handler();
+        handler.storage.set(b"a", b"1").await.unwrap();
+        assert_eq!(int(expire(&handler, &["a", "100"]).await), 1);
+        let list = [b("x")];
+        handler
+            .storage
+            .list_push(b"l", &list, ListEnd::Right, true)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            handler.handle_rename(&[&b("a"), &b("b")], false).await,
+            RespValue::SimpleString(s) if s == "OK"
+        ));
+        assert_eq!(handler.storage.get(b"a").await.unwrap(), None);
+        assert_eq!(ttl(&handler, "b", false).await, 100);
+        assert_eq!(
+            int(handler.handle_rename(&[&b("b"), &b("l")], true).await),
+            0
+        );
+        assert!(matches!(
+            handler.handle_rename(&[&b("missing"), &b("b")], false).await,
+            RespValue::Error(msg) if msg == "ERR no such key"
+        ));
+
+        assert_eq!(int(handler.handle_copy(&[&b("b"), &b("c")]).await), 1);
+        assert_eq!(ttl(&handler, "c", false).await, 100);
+        assert_eq!(int(handler.handle_copy(&[&b("l"), &b("c")]).await), 0);
+        assert_eq!(
+            int(handler
+                .handle_copy(&[&b("l"), &b("c"), &b("replace")])
+                .await),
+            1
+        );
+        assert_eq!(ttl(&handler, "c", false).await, -1);
+
+        for (key, name) in [("b", "string"), ("c", "list"), ("a", "none")] {
+            assert!(matches!(
+                handler.handle_type(&[&b(key)]).await,
+                RespValue::SimpleString(s) if s == name
+            ));
+        }
+        assert_eq!(
+            int(handler.handle_touch(&[&b("a"), &b("b"), &b("c")]).await),
+            2
+        );
+        assert!(matches!(
+            handler.handle_randomkey(&[]).await,
+            RespValue::BulkString(Some(key)) if [b("b"), b("c"), b("l")].contains(&key)
+        ));
+    }
+}
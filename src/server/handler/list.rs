@@ -0,0 +1,1042 @@
+//! List commands (LPUSH, LPOP, LRANGE, LMOVE, LMPOP, ...).
+
+use super::{
+    normalize_range, parse_float, parse_int, storage_error, syntax_error, wrong_args, Handler,
+};
+use crate::protocol::RespValue;
+use crate::server::notify::NOTIFY_LIST;
+use crate::storage::{ListEnd, StorageError};
+use bytes::Bytes;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Parse a `LEFT`/`RIGHT` direction argument.
+pub(super) fn parse_list_end(arg: &[u8]) -> Option<ListEnd> {
+    if arg.eq_ignore_ascii_case(b"LEFT") {
+        Some(ListEnd::Left)
+    } else if arg.eq_ignore_ascii_case(b"RIGHT") {
+        Some(ListEnd::Right)
+    } else {
+        None
+    }
+}
+
+/// Keyspace event of a push to `end`.
+fn push_event(end: ListEnd) -> &'static str {
+    match end {
+        ListEnd::Left => "lpush",
+        ListEnd::Right => "rpush",
+    }
+}
+
+/// Keyspace event of a pop from `end`.
+fn pop_event(end: ListEnd) -> &'static str {
+    match end {
+        ListEnd::Left => "lpop",
+        ListEnd::Right => "rpop",
+    }
+}
+
+fn bulk_array(elements: Vec<Bytes>) -> RespValue {
+    RespValue::Array(Some(
+        elements
+            .into_iter()
+            .map(|e| RespValue::BulkString(Some(e)))
+            .collect(),
+    ))
+}
+
+impl Handler {
+    /// LPUSH/RPUSH/LPUSHX/RPUSHX key element [element ...]
+    pub(super) async fn handle_push(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        end: ListEnd,
+        create: bool,
+    ) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args(command);
+        }
+
+        let elements: Vec<Bytes> = args[1..].iter().map(|e| (*e).clone()).collect();
+        match self
+            .storage
+            .list_push(args[0], &elements, end, create)
+            .await
+        {
+            Ok(len) => {
+                if len > 0 {
+                    self.notify(NOTIFY_LIST, push_event(end), args[0]);
+                    self.context.blocking.signal(args[0]);
+                }
+                RespValue::Integer(len as i64)
+            }
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// LPOP/RPOP key [count]
+    pub(super) async fn handle_pop(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        end: ListEnd,
+    ) -> RespValue {
+        if args.is_empty() || args.len() > 2 {
+            return wrong_args(command);
+        }
+        let count = match args.get(1).map(|c| parse_int(c)).transpose() {
+            Ok(Some(count)) if count < 0 => {
+                return RespValue::Error("ERR value is out of range, must be positive".to_string())
+            }
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .list_pop(args[0], end, count.unwrap_or(1) as usize)
+            .await;
+        if matches!(&result, Ok(Some(popped)) if !popped.is_empty()) {
+            self.notify_popped(args[0], end).await;
+        }
+
+        match (result, count) {
+            (Ok(popped), None) => RespValue::BulkString(popped.and_then(|mut p| p.pop())),
+            (Ok(Some(popped)), Some(_)) => bulk_array(popped),
+            (Ok(None), Some(_)) => self.null_array(),
+            (Err(e), _) => storage_error(command, e),
+        }
+    }
+
+    /// LLEN key
+    pub(super) async fn handle_llen(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("llen");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_list()?.len(),
+                    None => 0,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(len) => RespValue::Integer(len as i64),
+            Err(e) => storage_error("llen", e),
+        }
+    }
+
+    /// LRANGE key start stop
+    pub(super) async fn handle_lrange(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("lrange");
+        }
+        let (start, stop) = match (parse_int(args[1]), parse_int(args[2])) {
+            (Ok(start), Ok(stop)) => (start, stop),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(list) = value.map(|v| v.as_list()).transpose()? else {
+                    return Ok(Vec::new());
+                };
+                Ok(match normalize_range(start, stop, list.len()) {
+                    Some((start, stop)) => list.range(start..=stop).cloned().collect(),
+                    None => Vec::new(),
+                })
+            })
+            .await;
+
+        match result {
+            Ok(elements) => bulk_array(elements),
+            Err(e) => storage_error("lrange", e),
+        }
+    }
+
+    /// LINDEX key index
+    pub(super) async fn handle_lindex(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("lindex");
+        }
+        let index = match parse_int(args[1]) {
+            Ok(index) => index,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(list) = value.map(|v| v.as_list()).transpose()? else {
+                    return Ok(None);
+                };
+                Ok(list_index(index, list.len()).map(|i| list[i].clone()))
+            })
+            .await;
+
+        match result {
+            Ok(element) => RespValue::BulkString(element),
+            Err(e) => storage_error("lindex", e),
+        }
+    }
+
+    /// LSET key index element
+    pub(super) async fn handle_lset(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("lset");
+        }
+        let index = match parse_int(args[1]) {
+            Ok(index) => index,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some(len) = slot.list()?.map(|list| list.len()) else {
+                    return Ok(Err("ERR no such key"));
+                };
+                let Some(i) = list_index(index, len) else {
+                    return Ok(Err("ERR index out of range"));
+                };
+                slot.list_mut()?[i] = args[2].clone();
+                Ok(Ok(()))
+            })
+            .await;
+
+        match result {
+            Ok(Ok(())) => {
+                self.notify(NOTIFY_LIST, "lset", args[0]);
+                RespValue::SimpleString("OK".to_string())
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("lset", e),
+        }
+    }
+
+    /// LINSERT key BEFORE|AFTER pivot element
+    pub(super) async fn handle_linsert(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 4 {
+            return wrong_args("linsert");
+        }
+        let after = if args[1].eq_ignore_ascii_case(b"AFTER") {
+            true
+        } else if args[1].eq_ignore_ascii_case(b"BEFORE") {
+            false
+        } else {
+            return syntax_error();
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some(list) = slot.list()? else {
+                    return Ok(0);
+                };
+                let Some(pos) = list.iter().position(|e| e == args[2]) else {
+                    return Ok(-1);
+                };
+                let list = slot.list_mut()?;
+                list.insert(pos + after as usize, args[3].clone());
+                Ok(list.len() as i64)
+            })
+            .await;
+
+        match result {
+            Ok(len) => {
+                if len > 0 {
+                    self.notify(NOTIFY_LIST, "linsert", args[0]);
+                }
+                RespValue::Integer(len)
+            }
+            Err(e) => storage_error("linsert", e),
+        }
+    }
+
+    /// LREM key count element
+    pub(super) async fn handle_lrem(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("lrem");
+        }
+        let count = match parse_int(args[1]) {
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some(list) = slot.list()? else {
+                    return Ok(0);
+                };
+                // Positions to remove, nearest to the starting end first
+                let matches = list.iter().enumerate().filter(|(_, e)| *e == args[2]);
+                let mut positions: Vec<usize> = if count < 0 {
+                    matches.rev().take(limit).map(|(i, _)| i).collect()
+                } else {
+                    matches.take(limit).map(|(i, _)| i).collect()
+                };
+                if positions.is_empty() {
+                    return Ok(0);
+                }
+
+                positions.sort_unstable();
+                let list = slot.list_mut()?;
+                for pos in positions.iter().rev() {
+                    list.remove(*pos);
+                }
+                Ok(positions.len())
+            })
+            .await;
+
+        match result {
+            Ok(removed) => {
+                if removed > 0 {
+                    self.notify(NOTIFY_LIST, "lrem", args[0]);
+                    self.notify_if_removed(args[0]).await;
+                }
+                RespValue::Integer(removed as i64)
+            }
+            Err(e) => storage_error("lrem", e),
+        }
+    }
+
+    /// LTRIM key start stop
+    pub(super) async fn handle_ltrim(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("ltrim");
+        }
+        let (start, stop) = match (parse_int(args[1]), parse_int(args[2])) {
+            (Ok(start), Ok(stop)) => (start, stop),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some(len) = slot.list()?.map(|list| list.len()) else {
+                    return Ok(false);
+                };
+                match normalize_range(start, stop, len) {
+                    Some((start, stop)) if start == 0 && stop + 1 == len => {}
+                    Some((start, stop)) => {
+                        let list = slot.list_mut()?;
+                        list.truncate(stop + 1);
+                        list.drain(..start);
+                    }
+                    None => slot.delete(),
+                }
+                Ok(true)
+            })
+            .await;
+
+        match result {
+            Ok(existed) => {
+                // Redis reports LTRIM on any existing list, trimmed or not
+                if existed {
+                    self.notify(NOTIFY_LIST, "ltrim", args[0]);
+                    self.notify_if_removed(args[0]).await;
+                }
+                RespValue::SimpleString("OK".to_string())
+            }
+            Err(e) => storage_error("ltrim", e),
+        }
+    }
+
+    /// LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+    pub(super) async fn handle_lpos(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("lpos");
+        }
+
+        let mut rank = 1i64;
+        let mut count = None;
+        let mut maxlen = 0usize;
+        for option in args[2..].chunks(2) {
+            let [name, value] = option else {
+                return syntax_error();
+            };
+            let value = match parse_int(value) {
+                Ok(value) => value,
+                Err(e) => return e,
+            };
+            if name.eq_ignore_ascii_case(b"RANK") {
+                if value == 0 {
+                    return RespValue::Error(
+                        "ERR RANK can't be zero: use 1 to start from the first match, 2 from \
+                         the second ... or use negative to start from the end of the list"
+                            .to_string(),
+                    );
+                }
+                // Redis only accepts ranks whose negation fits
+                if value == i64::MIN {
+                    return RespValue::Error(format!(
+                        "ERR value is out of range, must be between {} and {}",
+                        -i64::MAX,
+                        i64::MAX
+                    ));
+                }
+                rank = value;
+            } else if name.eq_ignore_ascii_case(b"COUNT") {
+                if value < 0 {
+                    return RespValue::Error("ERR COUNT can't be negative".to_string());
+                }
+                count = Some(value as usize);
+            } else if name.eq_ignore_ascii_case(b"MAXLEN") {
+                if value < 0 {
+                    return RespValue::Error("ERR MAXLEN can't be negative".to_string());
+                }
+                maxlen = value as usize;
+            } else {
+                return syntax_error();
+            }
+        }
+
+        let wanted = match count {
+            Some(0) => usize::MAX,
+            Some(count) => count,
+            None => 1,
+        };
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(list) = value.map(|v| v.as_list()).transpose()? else {
+                    return Ok(Vec::new());
+                };
+                let scanned = if maxlen == 0 { list.len() } else { maxlen };
+                let indexed = list.iter().enumerate();
+                let matches: Box<dyn Iterator<Item = (usize, &Bytes)>> = if rank > 0 {
+                    Box::new(indexed.take(scanned))
+                } else {
+                    Box::new(indexed.rev().take(scanned))
+                };
+                Ok(matches
+                    .filter(|(_, e)| *e == args[1])
+                    .skip(rank.unsigned_abs() as usize - 1)
+                    .take(wanted)
+                    .map(|(i, _)| i as i64)
+                    .collect::<Vec<_>>())
+            })
+            .await;
+
+        match (result, count) {
+            (Ok(positions), Some(_)) => RespValue::Array(Some(
+                positions.into_iter().map(RespValue::Integer).collect(),
+            )),
+            (Ok(positions), None) => match positions.first() {
+                Some(pos) => RespValue::Integer(*pos),
+                None => RespValue::BulkString(None),
+            },
+            (Err(e), _) => storage_error("lpos", e),
+        }
+    }
+
+    /// LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+    pub(super) async fn handle_lmove(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 4 {
+            return wrong_args("lmove");
+        }
+        let (Some(from), Some(to)) = (parse_list_end(args[2]), parse_list_end(args[3])) else {
+            return syntax_error();
+        };
+
+        self.list_move(args[0], args[1], from, to, "lmove").await
+    }
+
+    /// RPOPLPUSH source destination
+    pub(super) async fn handle_rpoplpush(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("rpoplpush");
+        }
+
+        self.list_move(args[0], args[1], ListEnd::Right, ListEnd::Left, "rpoplpush")
+            .await
+    }
+
+    pub(super) async fn list_move(
+        &self,
+        source: &[u8],
+        destination: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+        command: &str,
+    ) -> RespValue {
+        match self.try_list_move(source, destination, from, to).await {
+            Ok(moved) => RespValue::BulkString(moved),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    async fn try_list_move(
+        &self,
+        source: &[u8],
+        destination: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Bytes>, StorageError> {
+        let moved = self
+            .storage
+            .list_move(source, destination, from, to)
+            .await?;
+        if moved.is_some() {
+            self.notify(NOTIFY_LIST, push_event(to), destination);
+            self.notify_popped(source, from).await;
+            self.context.blocking.signal(destination);
+        }
+        Ok(moved)
+    }
+
+    /// Publish the events of a pop from the list at `key`.
+    async fn notify_popped(&self, key: &[u8], end: ListEnd) {
+        self.notify(NOTIFY_LIST, pop_event(end), key);
+        self.notify_if_removed(key).await;
+    }
+
+    /// LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]
+    pub(super) async fn handle_lmpop(&self, args: &[&Bytes]) -> RespValue {
+        let (keys, end, count) = match parse_mpop_args(args, "lmpop") {
+            Ok(parsed) => parsed,
+            Err(e) => return e,
+        };
+
+        match self.pop_first(keys, end, count).await {
+            Ok(Some((key, elements))) => RespValue::Array(Some(vec![
+                RespValue::BulkString(Some(key)),
+                bulk_array(elements),
+            ])),
+            Ok(None) => self.null_array(),
+            Err(e) => storage_error("lmpop", e),
+        }
+    }
+
+    /// BLPOP/BRPOP key [key ...] timeout
+    pub(super) async fn handle_bpop(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        end: ListEnd,
+    ) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args(command);
+        }
+        let (keys, timeout) = args.split_at(args.len() - 1);
+        let timeout = match parse_timeout(timeout[0]) {
+            Ok(timeout) => timeout,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .block_on(keys, timeout, || self.pop_first(keys, end, 1))
+            .await;
+        match result {
+            Ok(Some((key, mut elements))) => RespValue::Array(Some(vec![
+                RespValue::BulkString(Some(key)),
+                RespValue::BulkString(elements.pop()),
+            ])),
+            Ok(None) => self.null_array(),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// BLMPOP timeout numkeys key [key ...] LEFT|RIGHT [COUNT count]
+    pub(super) async fn handle_blmpop(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("blmpop");
+        }
+        let timeout = match parse_timeout(args[0]) {
+            Ok(timeout) => timeout,
+            Err(e) => return e,
+        };
+        let (keys, end, count) = match parse_mpop_args(&args[1..], "blmpop") {
+            Ok(parsed) => parsed,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .block_on(keys, timeout, || self.pop_first(keys, end, count))
+            .await;
+        match result {
+            Ok(Some((key, elements))) => RespValue::Array(Some(vec![
+                RespValue::BulkString(Some(key)),
+                bulk_array(elements),
+            ])),
+            Ok(None) => self.null_array(),
+            Err(e) => storage_error("blmpop", e),
+        }
+    }
+
+    /// BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout
+    pub(super) async fn handle_blmove(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 5 {
+            return wrong_args("blmove");
+        }
+        let (Some(from), Some(to)) = (parse_list_end(args[2]), parse_list_end(args[3])) else {
+            return syntax_error();
+        };
+        let timeout = match parse_timeout(args[4]) {
+            Ok(timeout) => timeout,
+            Err(e) => return e,
+        };
+
+        self.blocking_move(args[0], args[1], from, to, timeout, "blmove")
+            .await
+    }
+
+    /// BRPOPLPUSH source destination timeout
+    pub(super) async fn handle_brpoplpush(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("brpoplpush");
+        }
+        let timeout = match parse_timeout(args[2]) {
+            Ok(timeout) => timeout,
+            Err(e) => return e,
+        };
+
+        self.blocking_move(
+            args[0],
+            args[1],
+            ListEnd::Right,
+            ListEnd::Left,
+            timeout,
+            "brpoplpush",
+        )
+        .await
+    }
+
+    async fn blocking_move(
+        &self,
+        source: &Bytes,
+        destination: &Bytes,
+        from: ListEnd,
+        to: ListEnd,
+        timeout: Option<Duration>,
+        command: &str,
+    ) -> RespValue {
+        let result = self
+            .block_on(&[source], timeout, || {
+                self.try_list_move(source, destination, from, to)
+            })
+            .await;
+        match result {
+            Ok(moved) => RespValue::BulkString(moved),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// Run `attempt` until it produces a value, waiting for writes to `keys`
+    /// in between. Returns `None` once `timeout` (if any) elapses. Inside a
+    /// transaction nothing can be written meanwhile, so it only tries once.
+    pub(super) async fn block_on<T, F, Fut>(
+        &self,
+        keys: &[&Bytes],
+        timeout: Option<Duration>,
+        mut attempt: F,
+    ) -> Result<Option<T>, StorageError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<Option<T>, StorageError>>,
+    {
+        if self.in_exec {
+            return attempt().await;
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut ticket = None;
+
+        loop {
+            if let Some(value) = self.isolated(attempt()).await? {
+                return Ok(Some(value));
+            }
+
+            // Queue up, then retry once so a push racing with the
+            // registration is not missed.
+            let Some(ticket) = &ticket else {
+                ticket = Some(self.context.blocking.register(keys));
+                continue;
+            };
+
+            match deadline {
+                Some(deadline) => {
+                    if tokio::time::timeout_at(deadline, ticket.notified())
+                        .await
+                        .is_err()
+                    {
+                        return Ok(None);
+                    }
+                }
+                None => ticket.notified().await,
+            }
+        }
+    }
+
+    /// Pop from the first non-empty list among `keys`.
+    pub(super) async fn pop_first(
+        &self,
+        keys: &[&Bytes],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<(Bytes, Vec<Bytes>)>, StorageError> {
+        for key in keys {
+            if let Some(elements) = self.storage.list_pop(key, end, count).await? {
+                self.notify_popped(key, end).await;
+                return Ok(Some(((*key).clone(), elements)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Parse `numkeys key [key ...] LEFT|RIGHT [COUNT count]`, shared with BLMPOP.
+pub(super) fn parse_mpop_args<'a>(
+    args: &'a [&'a Bytes],
+    command: &str,
+) -> Result<(&'a [&'a Bytes], ListEnd, usize), RespValue> {
+    if args.len() < 3 {
+        return Err(wrong_args(command));
+    }
+    let numkeys = parse_int(args[0])?;
+    if numkeys <= 0 {
+        return Err(RespValue::Error(
+            "ERR numkeys should be greater than 0".to_string(),
+        ));
+    }
+    let numkeys = numkeys as usize;
+    if numkeys >= args.len() - 1 {
+        return Err(RespValue::Error(
+            "ERR Number of keys can't be greater than number of args".to_string(),
+        ));
+    }
+
+    let keys = &args[1..=numkeys];
+    let end = parse_list_end(args[numkeys + 1]).ok_or_else(syntax_error)?;
+    let count = match &args[numkeys + 2..] {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+            let count = parse_int(count)?;
+            if count <= 0 {
+                return Err(RespValue::Error(
+                    "ERR count should be greater than 0".to_string(),
+                ));
+            }
+            count as usize
+        }
+        _ => return Err(syntax_error()),
+    };
+    Ok((keys, end, count))
+}
+
+/// Parse a blocking timeout in (possibly fractional) seconds; 0 blocks forever.
+fn parse_timeout(arg: &[u8]) -> Result<Option<Duration>, RespValue> {
+    let seconds = parse_float(arg)
+        .ok()
+        .filter(|seconds| seconds.is_finite())
+        .ok_or_else(|| {
+            RespValue::Error("ERR timeout is not a float or out of range".to_string())
+        })?;
+    if seconds < 0.0 {
+        return Err(RespValue::Error("ERR timeout is negative".to_string()));
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(seconds)
+        .map(Some)
+        .map_err(|_| RespValue::Error("ERR timeout is out of range".to_string()))
+}
+
+/// Resolve a possibly negative list index.
+fn list_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    fn b(s: &str) -> Bytes {
+        Bytes::from(s.to_string())
+    }
+
+    fn strings(value: RespValue) -> Vec<String> {
+        match value {
+            RespValue::Array(Some(items)) => items
+                .into_iter()
+                .map(|item| match item {
+                    RespValue::BulkString(Some(s)) => String::from_utf8(s.to_vec()).unwrap(),
+                    other => panic!("Expected BulkString, got {:?}", other),
+                })
+                .collect(),
+            other => panic!("Expected Array, got {:?}", other),
+        }
+    }
+
+    async fn rpush(handler: &Handler, key: &str, elements: &[&str]) {
+        let mut args = vec![b(key)];
+        args.extend(elements.iter().map(|e| b(e)));
+        let args: Vec<&Bytes> = args.iter().collect();
+        handler
+            .handle_push(&args, "rpush", ListEnd::Right, true)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_push_pop_range() {
+        let handler = create_handler();
+        let key = b("list");
+
+        let result = handler
+            .handle_push(&[&key, &b("b"), &b("a")], "lpush", ListEnd::Left, true)
+            .await;
+        assert!(matches!(result, RespValue::Integer(2)));
+        rpush(&handler, "list", &["c", "d"]).await;
+
+        let range = handler.handle_lrange(&[&key, &b("0"), &b("-1")]).await;
+        assert_eq!(strings(range), ["a", "b", "c", "d"]);
+        let range = handler.handle_lrange(&[&key, &b("-2"), &b("100")]).await;
+        assert_eq!(strings(range), ["c", "d"]);
+
+        match handler.handle_pop(&[&key], "lpop", ListEnd::Left).await {
+            RespValue::BulkString(Some(v)) => assert_eq!(v, "a"),
+            _ => panic!("Expected BulkString"),
+        }
+        let popped = handler
+            .handle_pop(&[&key, &b("5")], "rpop", ListEnd::Right)
+            .await;
+        assert_eq!(strings(popped), ["d", "c", "b"]);
+
+        // Popping the last element removes the key
+        assert!(!handler.storage.exists(&key).await.unwrap());
+        assert!(matches!(
+            handler
+                .handle_pop(&[&key, &b("1")], "lpop", ListEnd::Left)
+                .await,
+            RespValue::Array(None)
+        ));
+        assert!(matches!(
+            handler
+                .handle_push(&[&key, &b("x")], "lpushx", ListEnd::Left, false)
+                .await,
+            RespValue::Integer(0)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_lset_linsert_lrem_ltrim() {
+        let handler = create_handler();
+        let key = b("list");
+        rpush(&handler, "list", &["a", "x", "b", "x", "c", "x"]).await;
+
+        let removed = handler.handle_lrem(&[&key, &b("-2"), &b("x")]).await;
+        assert!(matches!(removed, RespValue::Integer(2)));
+        let range = handler.handle_lrange(&[&key, &b("0"), &b("-1")]).await;
+        assert_eq!(strings(range), ["a", "x", "b", "c"]);
+
+        handler.handle_lset(&[&key, &b("-1"), &b("z")]).await;
+        let inserted = handler
+            .handle_linsert(&[&key, &b("BEFORE"), &b("b"), &b("y")])
+            .await;
+        assert!(matches!(inserted, RespValue::Integer(5)));
+        assert!(matches!(
+            handler
+                .handle_linsert(&[&key, &b("AFTER"), &b("missing"), &b("y")])
+                .await,
+            RespValue::Integer(-1)
+        ));
+
+        handler.handle_ltrim(&[&key, &b("1"), &b("-2")]).await;
+        let range = handler.handle_lrange(&[&key, &b("0"), &b("-1")]).await;
+        assert_eq!(strings(range), ["x", "y", "b"]);
+
+        match handler.handle_lset(&[&key, &b("10"), &b("z")]).await {
+            RespValue::Error(msg) => assert_eq!(msg, "ERR index out of range"),
+            _ => panic!("Expected Error"),
+        }
+
+        handler.handle_ltrim(&[&key, &b("5"), &b("10")]).await;
+        assert!(!handler.storage.exists(&key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_lpos() {
+        let handler = create_handler();
+        let key = b("list");
+        rpush(&handler, "list", &["a", "b", "c", "1", "2", "3", "c", "c"]).await;
+
+        assert!(matches!(
+            handler.handle_lpos(&[&key, &b("c")]).await,
+            RespValue::Integer(2)
+        ));
+        assert!(matches!(
+            handler
+                .handle_lpos(&[&key, &b("c"), &b("RANK"), &b("-1")])
+                .await,
+            RespValue::Integer(7)
+        ));
+        assert!(matches!(
+            handler
+                .handle_lpos(&[&key, &b("c"), &b("RANK"), &b("-9223372036854775808")])
+                .await,
+            RespValue::Error(msg) if msg == "ERR value is out of range, must be between \
+                -9223372036854775807 and 9223372036854775807"
+        ));
+        assert!(matches!(
+            handler
+                .handle_lpos(&[&key, &b("c"), &b("RANK"), &b("-9223372036854775807")])
+                .await,
+            RespValue::BulkString(None)
+        ));
+        match handler
+            .handle_lpos(&[&key, &b("c"), &b("COUNT"), &b("0"), &b("RANK"), &b("2")])
+            .await
+        {
+            RespValue::Array(Some(items)) => {
+                assert!(matches!(
+                    items[..],
+                    [RespValue::Integer(6), RespValue::Integer(7)]
+                ))
+            }
+            _ => panic!("Expected Array"),
+        }
+        assert!(matches!(
+            handler
+                .handle_lpos(&[&key, &b("c"), &b("MAXLEN"), &b("2")])
+                .await,
+            RespValue::BulkString(None)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_lmove_and_lmpop() {
+        let handler = create_handler();
+        let (src, dst) = (b("src"), b("dst"));
+        rpush(&handler, "src", &["a", "b", "c"]).await;
+
+        match handler
+            .handle_lmove(&[&src, &dst, &b("LEFT"), &b("RIGHT")])
+            .await
+        {
+            RespValue::BulkString(Some(v)) => assert_eq!(v, "a"),
+            _ => panic!("Expected BulkString"),
+        }
+        // Rotating a list onto itself
+        handler.handle_rpoplpush(&[&src, &src]).await;
+        let range = handler.handle_lrange(&[&src, &b("0"), &b("-1")]).await;
+        assert_eq!(strings(range), ["c", "b"]);
+
+        handler.storage.set(b"str", b"value").await.unwrap();
+        match handler.handle_rpoplpush(&[&src, &b("str")]).await {
+            RespValue::Error(msg) => assert!(msg.starts_with("WRONGTYPE")),
+            _ => panic!("Expected WRONGTYPE"),
+        }
+        // The source is untouched when the destination has the wrong type
+        let range = handler.handle_lrange(&[&src, &b("0"), &b("-1")]).await;
+        assert_eq!(strings(range), ["c", "b"]);
+
+        match handler
+            .handle_lmpop(&[
+                &b("2"),
+                &b("missing"),
+                &src,
+                &b("RIGHT"),
+                &b("COUNT"),
+                &b("5"),
+            ])
+            .await
+        {
+            RespValue::Array(Some(reply)) => {
+                assert!(matches!(&reply[0], RespValue::BulkString(Some(k)) if k == "src"));
+                assert_eq!(strings(reply[1].clone()), ["b", "c"]);
+            }
+            _ => panic!("Expected Array"),
+        }
+        assert!(matches!(
+            handler.handle_lmpop(&[&b("1"), &src, &b("LEFT")]).await,
+            RespValue::Array(None)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_blpop_timeout() {
+        let handler = create_handler();
+        let start = std::time::Instant::now();
+
+        let result = handler
+            .handle_bpop(&[&b("empty"), &b("0.05")], "blpop", ListEnd::Left)
+            .await;
+        assert!(matches!(result, RespValue::Array(None)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(handler.context.blocking.blocked_clients(), 0);
+
+        match handler
+            .handle_bpop(&[&b("empty"), &b("-1")], "blpop", ListEnd::Left)
+            .await
+        {
+            RespValue::Error(msg) => assert_eq!(msg, "ERR timeout is negative"),
+            _ => panic!("Expected Error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocked_clients_served_in_order() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let context = Arc::new(crate::server::ServerContext::new());
+        let config = Arc::new(crate::config::Config::default());
+        let new_handler = || {
+            Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            )
+        };
+
+        let mut waiters = Vec::new();
+        for expected_blocked in 1..=2 {
+            let handler = new_handler();
+            waiters.push(tokio::spawn(async move {
+                handler
+                    .handle_bpop(&[&b("q"), &b("0")], "brpop", ListEnd::Right)
+                    .await
+            }));
+            while context.blocking.blocked_clients() < expected_blocked {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        let pusher = new_handler();
+        for element in ["first", "second"] {
+            pusher
+                .handle_push(&[&b("q"), &b(element)], "lpush", ListEnd::Left, true)
+                .await;
+        }
+
+        for (waiter, expected) in waiters.into_iter().zip(["first", "second"]) {
+            let reply = tokio::time::timeout(Duration::from_secs(1), waiter)
+                .await
+                .expect("waiter was not woken")
+                .unwrap();
+            assert_eq!(strings(reply), ["q", expected]);
+        }
+        assert_eq!(context.blocking.blocked_clients(), 0);
+    }
+}
@@ -0,0 +1,470 @@
+//! Pub/sub: SUBSCRIBE, UNSUBSCRIBE, PSUBSCRIBE, PUNSUBSCRIBE, SSUBSCRIBE,
+//! SUNSUBSCRIBE, PUBLISH, SPUBLISH and PUBSUB.
+//!
+//! (Un)subscribing replies once per channel, so these commands leave all
+//! but their last reply in `extra_replies`. Messages are delivered by
+//! `handle_stream` between commands: under RESP3 as push frames, which
+//! clients tell apart from replies, and under RESP2 as arrays, which is why
+//! RESP2 connections with subscriptions may only run a few commands.
+//! Client tracking invalidations arrive in the same mailbox.
+
+use super::{wrong_args, Cmd, Handler};
+use crate::protocol::{ProtocolVersion, RespValue};
+use crate::server::pubsub::Message;
+use crate::server::tracking::{is_tracking_message, INVALIDATE_CHANNEL};
+use bytes::Bytes;
+
+/// Commands RESP2 connections may run while they have subscriptions.
+pub(super) fn allowed_when_subscribed(cmd: Cmd) -> bool {
+    matches!(
+        cmd,
+        Cmd::Subscribe
+            | Cmd::Unsubscribe
+            | Cmd::PSubscribe
+            | Cmd::PUnsubscribe
+            | Cmd::SSubscribe
+            | Cmd::SUnsubscribe
+            | Cmd::Ping
+    )
+}
+
+/// What (UN)SUBSCRIBE, P(UN)SUBSCRIBE and S(UN)SUBSCRIBE act on.
+#[derive(Clone, Copy)]
+pub(super) enum Subscription {
+    Channel,
+    Pattern,
+    ShardChannel,
+}
+
+impl Subscription {
+    fn subscribe_command(self) -> &'static str {
+        match self {
+            Subscription::Channel => "subscribe",
+            Subscription::Pattern => "psubscribe",
+            Subscription::ShardChannel => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_command(self) -> &'static str {
+        match self {
+            Subscription::Channel => "unsubscribe",
+            Subscription::Pattern => "punsubscribe",
+            Subscription::ShardChannel => "sunsubscribe",
+        }
+    }
+}
+
+impl Handler {
+    /// Whether the connection is restricted to pub/sub commands.
+    pub(super) fn in_subscribed_mode(&self) -> bool {
+        self.protocol_version == ProtocolVersion::Resp2
+            && self.subscriber.count() + self.subscriber.shard_count() > 0
+    }
+
+    /// A pub/sub message or confirmation as sent to this connection.
+    pub(super) fn push_frame(&self, items: Vec<RespValue>) -> RespValue {
+        match self.protocol_version {
+            ProtocolVersion::Resp3 => RespValue::Push(items),
+            ProtocolVersion::Resp2 => RespValue::Array(Some(items)),
+        }
+    }
+
+    /// A message from the mailbox as sent to this connection, if it can
+    /// receive it. Under RESP2, invalidations are only delivered to
+    /// connections subscribed to the invalidation channel, as messages of
+    /// that channel.
+    pub(super) fn message_frame(&self, message: Message) -> Option<RespValue> {
+        if self.protocol_version == ProtocolVersion::Resp3 || !is_tracking_message(&message) {
+            return Some(self.push_frame(message));
+        }
+        let subscribed = self
+            .subscriber
+            .channels()
+            .iter()
+            .any(|channel| channel.as_ref() == INVALIDATE_CHANNEL);
+        let keys = match message.into_iter().nth(1) {
+            Some(keys @ RespValue::Array(_)) => keys,
+            Some(RespValue::Null) => RespValue::Array(None),
+            // `tracking-redir-broken` is only sent as a push
+            _ => return None,
+        };
+        subscribed.then(|| {
+            self.push_frame(vec![
+                RespValue::BulkString(Some(Bytes::from_static(b"message"))),
+                RespValue::BulkString(Some(Bytes::from_static(INVALIDATE_CHANNEL))),
+                keys,
+            ])
+        })
+    }
+
+    /// Confirmation of a (un)subscription, with the number of subscriptions
+    /// left: shard channels are counted apart, as in Redis.
+    fn confirmation(
+        &self,
+        command: &'static str,
+        subscription: Subscription,
+        name: Option<&Bytes>,
+    ) -> RespValue {
+        let name = match name {
+            Some(name) => RespValue::BulkString(Some(name.clone())),
+            None if self.protocol_version == ProtocolVersion::Resp3 => RespValue::Null,
+            None => RespValue::BulkString(None),
+        };
+        let count = match subscription {
+            Subscription::ShardChannel => self.subscriber.shard_count(),
+            Subscription::Channel | Subscription::Pattern => self.subscriber.count(),
+        };
+        self.push_frame(vec![
+            RespValue::BulkString(Some(Bytes::from_static(command.as_bytes()))),
+            name,
+            RespValue::Integer(count as i64),
+        ])
+    }
+
+    /// Reply with `confirmations`, all but the last through `extra_replies`.
+    fn confirmations(&mut self, mut confirmations: Vec<RespValue>) -> RespValue {
+        let last = confirmations.pop().expect("at least one confirmation");
+        self.extra_replies.extend(confirmations);
+        last
+    }
+
+    /// SUBSCRIBE channel [channel ...] / PSUBSCRIBE pattern [pattern ...] /
+    /// SSUBSCRIBE shardchannel [shardchannel ...]
+    pub(super) fn handle_subscribe(
+        &mut self,
+        args: &[&Bytes],
+        subscription: Subscription,
+    ) -> RespValue {
+        let command = subscription.subscribe_command();
+        if args.is_empty() {
+            return wrong_args(command);
+        }
+        let pubsub = &self.context.pubsub;
+        let mut confirmations = Vec::with_capacity(args.len());
+        for &name in args {
+            match subscription {
+                Subscription::Channel => pubsub.subscribe(&mut self.subscriber, name),
+                Subscription::Pattern => pubsub.psubscribe(&mut self.subscriber, name),
+                Subscription::ShardChannel => pubsub.ssubscribe(&mut self.subscriber, name),
+            };
+            confirmations.push(self.confirmation(command, subscription, Some(name)));
+        }
+        self.confirmations(confirmations)
+    }
+
+    /// UNSUBSCRIBE [channel ...] / PUNSUBSCRIBE [pattern ...] /
+    /// SUNSUBSCRIBE [shardchannel ...]
+    ///
+    /// Without arguments, drops every subscription of that kind.
+    pub(super) fn handle_unsubscribe(
+        &mut self,
+        args: &[&Bytes],
+        subscription: Subscription,
+    ) -> RespValue {
+        let command = subscription.unsubscribe_command();
+        let names: Vec<Bytes> = if args.is_empty() {
+            match subscription {
+                Subscription::Channel => self.subscriber.channels().to_vec(),
+                Subscription::Pattern => self.subscriber.patterns().to_vec(),
+                Subscription::ShardChannel => self.subscriber.shard_channels().to_vec(),
+            }
+        } else {
+            args.iter().map(|&name| name.clone()).collect()
+        };
+        if names.is_empty() {
+            return self.confirmation(command, subscription, None);
+        }
+
+        let pubsub = &self.context.pubsub;
+        let mut confirmations = Vec::with_capacity(names.len());
+        for name in &names {
+            match subscription {
+                Subscription::Channel => pubsub.unsubscribe(&mut self.subscriber, name),
+                Subscription::Pattern => pubsub.punsubscribe(&mut self.subscriber, name),
+                Subscription::ShardChannel => pubsub.sunsubscribe(&mut self.subscriber, name),
+            };
+            confirmations.push(self.confirmation(command, subscription, Some(name)));
+        }
+        self.confirmations(confirmations)
+    }
+
+    /// PUBLISH channel message
+    pub(super) fn handle_publish(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("publish");
+        }
+        RespValue::Integer(self.context.pubsub.publish(args[0], args[1]) as i64)
+    }
+
+    /// SPUBLISH shardchannel message
+    pub(super) fn handle_spublish(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("spublish");
+        }
+        RespValue::Integer(self.context.pubsub.spublish(args[0], args[1]) as i64)
+    }
+
+    /// PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT |
+    /// SHARDCHANNELS [pattern] | SHARDNUMSUB [shardchannel ...]
+    pub(super) fn handle_pubsub(&self, args: &[&Bytes]) -> RespValue {
+        let Some(subcommand) = args.first() else {
+            return wrong_args("pubsub");
+        };
+        let pubsub = &self.context.pubsub;
+        let pattern = args.get(1).map(|pattern| pattern.as_ref());
+        let channel_list = |channels: Vec<Bytes>| {
+            RespValue::Array(Some(
+                channels
+                    .into_iter()
+                    .map(|channel| RespValue::BulkString(Some(channel)))
+                    .collect(),
+            ))
+        };
+        let subscriber_counts = |count: &dyn Fn(&[u8]) -> usize| {
+            RespValue::Array(Some(
+                args[1..]
+                    .iter()
+                    .flat_map(|&channel| {
+                        [
+                            RespValue::BulkString(Some(channel.clone())),
+                            RespValue::Integer(count(channel) as i64),
+                        ]
+                    })
+                    .collect(),
+            ))
+        };
+        match subcommand.to_ascii_uppercase().as_slice() {
+            b"CHANNELS" if args.len() <= 2 => channel_list(pubsub.active_channels(pattern)),
+            b"SHARDCHANNELS" if args.len() <= 2 => {
+                channel_list(pubsub.active_shard_channels(pattern))
+            }
+            b"NUMSUB" => subscriber_counts(&|channel| pubsub.subscribers(channel)),
+            b"SHARDNUMSUB" => subscriber_counts(&|channel| pubsub.shard_subscribers(channel)),
+            b"NUMPAT" if args.len() == 1 => RespValue::Integer(pubsub.patterns() as i64),
+            b"CHANNELS" | b"SHARDCHANNELS" | b"NUMPAT" => RespValue::Error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'. Try PUBSUB HELP.",
+                String::from_utf8_lossy(subcommand)
+            )),
+            _ => RespValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try PUBSUB HELP.",
+                String::from_utf8_lossy(subcommand)
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::ServerContext;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    async fn run(handler: &mut Handler, args: &[&str]) -> RespValue {
+        let parts = args
+            .iter()
+            .map(|arg| RespValue::BulkString(Some(Bytes::from(arg.to_string()))))
+            .collect();
+        handler.handle_command(RespValue::Array(Some(parts))).await
+    }
+
+    fn frame(value: &RespValue) -> Vec<String> {
+        let (RespValue::Array(Some(items)) | RespValue::Push(items)) = value else {
+            panic!("Expected frame, got {:?}", value);
+        };
+        items
+            .iter()
+            .map(|item| match item {
+                RespValue::BulkString(Some(b)) => String::from_utf8_lossy(b).into_owned(),
+                RespValue::Integer(n) => n.to_string(),
+                other => format!("{:?}", other),
+            })
+            .collect()
+    }
+
+    fn new_pair() -> (Handler, Handler) {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let context = Arc::new(ServerContext::new());
+        let config = Arc::new(Config::default());
+        let new_handler = || {
+            Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            )
+        };
+        (new_handler(), new_handler())
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_and_publish() {
+        let (mut subscriber, mut publisher) = new_pair();
+
+        let reply = run(&mut subscriber, &["SUBSCRIBE", "a", "b"]).await;
+        assert_eq!(frame(&reply), ["subscribe", "b", "2"]);
+        let extra: Vec<_> = subscriber.extra_replies.drain(..).collect();
+        assert_eq!(frame(&extra[0]), ["subscribe", "a", "1"]);
+        assert!(matches!(reply, RespValue::Array(_)));
+        assert_eq!(
+            frame(&run(&mut subscriber, &["PSUBSCRIBE", "c*"]).await),
+            ["psubscribe", "c*", "3"]
+        );
+
+        assert!(matches!(
+            run(&mut publisher, &["PUBLISH", "a", "hello"]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(matches!(
+            run(&mut publisher, &["PUBLISH", "cat", "meow"]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(matches!(
+            run(&mut publisher, &["PUBLISH", "z", "nobody"]).await,
+            RespValue::Integer(0)
+        ));
+        let message = subscriber.subscriber.try_receive().unwrap();
+        assert_eq!(
+            frame(&subscriber.push_frame(message)),
+            ["message", "a", "hello"]
+        );
+        let message = subscriber.subscriber.try_receive().unwrap();
+        assert_eq!(
+            frame(&subscriber.push_frame(message)),
+            ["pmessage", "c*", "cat", "meow"]
+        );
+
+        // RESP2 subscribers are limited to pub/sub commands
+        assert!(matches!(
+            run(&mut subscriber, &["GET", "k"]).await,
+            RespValue::Error(e) if e.starts_with("ERR Can't execute 'get': only (P|S)SUBSCRIBE")
+        ));
+        assert_eq!(frame(&run(&mut subscriber, &["PING"]).await), ["pong", ""]);
+
+        let RespValue::Array(Some(channels)) = run(&mut publisher, &["PUBSUB", "CHANNELS"]).await
+        else {
+            panic!("Expected Array");
+        };
+        assert_eq!(channels.len(), 2);
+        assert_eq!(
+            frame(&run(&mut publisher, &["PUBSUB", "NUMSUB", "a", "z"]).await),
+            ["a", "1", "z", "0"]
+        );
+        assert!(matches!(
+            run(&mut publisher, &["PUBSUB", "NUMPAT"]).await,
+            RespValue::Integer(1)
+        ));
+
+        assert_eq!(
+            frame(&run(&mut subscriber, &["UNSUBSCRIBE"]).await),
+            ["unsubscribe", "b", "1"]
+        );
+        assert_eq!(subscriber.extra_replies.len(), 1);
+        subscriber.extra_replies.clear();
+        assert_eq!(
+            frame(&run(&mut subscriber, &["PUNSUBSCRIBE"]).await),
+            ["punsubscribe", "c*", "0"]
+        );
+        assert_eq!(
+            frame(&run(&mut subscriber, &["UNSUBSCRIBE"]).await),
+            ["unsubscribe", "BulkString(None)", "0"]
+        );
+        assert!(matches!(
+            run(&mut subscriber, &["PING"]).await,
+            RespValue::SimpleString(_)
+        ));
+
+        // Closed connections stop receiving
+        run(&mut subscriber, &["SUBSCRIBE", "a"]).await;
+        drop(subscriber);
+        assert!(matches!(
+            run(&mut publisher, &["PUBLISH", "a", "hello"]).await,
+            RespValue::Integer(0)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resp3_pushes() {
+        let (mut subscriber, mut publisher) = new_pair();
+        subscriber.set_protocol_version(ProtocolVersion::Resp3);
+
+        assert!(matches!(
+            run(&mut subscriber, &["SUBSCRIBE", "a"]).await,
+            RespValue::Push(_)
+        ));
+        // Any command works while subscribed
+        run(&mut subscriber, &["SET", "k", "v"]).await;
+        assert!(matches!(
+            run(&mut subscriber, &["GET", "k"]).await,
+            RespValue::BulkString(Some(_))
+        ));
+
+        run(&mut publisher, &["PUBLISH", "a", "hello"]).await;
+        let message = subscriber.subscriber.try_receive().unwrap();
+        assert!(
+            matches!(subscriber.push_frame(message), RespValue::Push(items) if items.len() == 3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sharded_pubsub() {
+        let (mut subscriber, mut publisher) = new_pair();
+
+        run(&mut subscriber, &["SUBSCRIBE", "a"]).await;
+        let reply = run(&mut subscriber, &["SSUBSCRIBE", "{user}a", "{user}b"]).await;
+        assert_eq!(frame(&reply), ["ssubscribe", "{user}b", "2"]);
+        assert_eq!(
+            frame(&subscriber.extra_replies.remove(0)),
+            ["ssubscribe", "{user}a", "1"]
+        );
+
+        assert!(matches!(
+            run(&mut publisher, &["SPUBLISH", "{user}a", "hi"]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(matches!(
+            run(&mut publisher, &["SPUBLISH", "a", "hi"]).await,
+            RespValue::Integer(0)
+        ));
+        assert!(matches!(
+            run(&mut publisher, &["PUBLISH", "{user}a", "hi"]).await,
+            RespValue::Integer(0)
+        ));
+        let message = subscriber.subscriber.try_receive().unwrap();
+        assert_eq!(
+            frame(&subscriber.push_frame(message)),
+            ["smessage", "{user}a", "hi"]
+        );
+
+        let reply = run(&mut publisher, &["PUBSUB", "SHARDCHANNELS", "*a"]).await;
+        assert_eq!(frame(&reply), ["{user}a"]);
+        let reply = run(&mut publisher, &["PUBSUB", "SHARDNUMSUB", "{user}b", "a"]).await;
+        assert_eq!(frame(&reply), ["{user}b", "1", "a", "0"]);
+
+        // Shard channels keep the connection subscribed on their own
+        run(&mut subscriber, &["UNSUBSCRIBE"]).await;
+        assert!(matches!(
+            run(&mut subscriber, &["GET", "k"]).await,
+            RespValue::Error(_)
+        ));
+        subscriber.extra_replies.clear();
+        assert_eq!(
+            frame(&run(&mut subscriber, &["SUNSUBSCRIBE"]).await),
+            ["sunsubscribe", "{user}b", "0"]
+        );
+        assert!(matches!(
+            run(&mut subscriber, &["GET", "k"]).await,
+            RespValue::BulkString(None)
+        ));
+
+        // Messages are pushes under RESP3
+        subscriber.set_protocol_version(ProtocolVersion::Resp3);
+        run(&mut subscriber, &["SSUBSCRIBE", "s"]).await;
+        run(&mut publisher, &["SPUBLISH", "s", "hi"]).await;
+        let message = subscriber.subscriber.try_receive().unwrap();
+        assert!(
+            matches!(subscriber.push_frame(message), RespValue::Push(items) if items.len() == 3)
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Cursor helpers shared by the SCAN/HSCAN/SSCAN/ZSCAN family.
+//!
+//! SCAN iterates with storage cursors, see
+//! [`StorageBackend::scan`](crate::storage::StorageBackend::scan); the
+//! collection commands use the hash cursors below.
+//!
+//! Elements of a collection are visited in the order of a stable hash of
+//! their member bytes, and the cursor is the next hash position to resume
+//! from. Members present for the whole iteration are therefore always
+//! returned, even if the collection is modified between calls (they may be
+//! returned more than once), matching Redis's SCAN guarantees.
+
+use super::{parse_int, syntax_error};
+use crate::glob::glob_match;
+use crate::protocol::RespValue;
+use bytes::Bytes;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Which command's options [`ScanArgs::parse`] accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ScanKind {
+    /// SCAN: also accepts `TYPE type`.
+    Keys,
+    /// HSCAN: also accepts `NOVALUES`.
+    Hash,
+    /// SSCAN / ZSCAN.
+    Members,
+}
+
+/// Parsed `cursor [MATCH pattern] [COUNT count] [NOVALUES] [TYPE type]`
+/// arguments.
+pub(super) struct ScanArgs {
+    pub cursor: u64,
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+    pub novalues: bool,
+    /// Lowercased type name to filter keys by.
+    pub key_type: Option<String>,
+}
+
+impl ScanArgs {
+    /// Parse scan arguments, accepting the options specific to `kind`.
+    pub fn parse(args: &[&Bytes], kind: ScanKind) -> Result<Self, RespValue> {
+        let cursor = std::str::from_utf8(args[0])
+            .ok()
+            .and_then(|c| c.parse::<u64>().ok())
+            .ok_or_else(|| RespValue::Error("ERR invalid cursor".to_string()))?;
+
+        let mut scan = ScanArgs {
+            cursor,
+            pattern: None,
+            count: 10,
+            novalues: false,
+            key_type: None,
+        };
+
+        let mut i = 1;
+        while i < args.len() {
+            let option = args[i];
+            if option.eq_ignore_ascii_case(b"MATCH") && i + 1 < args.len() {
+                scan.pattern = Some(args[i + 1].clone());
+                i += 2;
+            } else if option.eq_ignore_ascii_case(b"COUNT") && i + 1 < args.len() {
+                let count = parse_int(args[i + 1])?;
+                if count < 1 {
+                    return Err(syntax_error());
+                }
+                scan.count = count as usize;
+                i += 2;
+            } else if kind == ScanKind::Hash && option.eq_ignore_ascii_case(b"NOVALUES") {
+                scan.novalues = true;
+                i += 1;
+            } else if kind == ScanKind::Keys
+                && option.eq_ignore_ascii_case(b"TYPE")
+                && i + 1 < args.len()
+            {
+                scan.key_type = Some(String::from_utf8_lossy(args[i + 1]).to_ascii_lowercase());
+                i += 2;
+            } else {
+                return Err(syntax_error());
+            }
+        }
+
+        Ok(scan)
+    }
+
+    /// Whether a member passes the MATCH filter.
+    pub fn matches(&self, member: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern, member))
+    }
+}
+
+/// Stable cursor position of a member.
+pub(super) fn member_hash(member: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    member.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Select the next batch of `items` (keyed by member) after `cursor`.
+///
+/// Returns the cursor to resume from (0 when the iteration is complete)
+/// and the selected items. Members sharing a hash are never split across
+/// batches, so no element can be skipped.
+pub(super) fn scan_batch<T>(
+    items: impl Iterator<Item = (Bytes, T)>,
+    cursor: u64,
+    count: usize,
+) -> (u64, Vec<(Bytes, T)>) {
+    let mut candidates: Vec<(u64, Bytes, T)> = items
+        .map(|(member, item)| (member_hash(&member), member, item))
+        .filter(|(hash, _, _)| *hash >= cursor)
+        .collect();
+    candidates.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+
+    let mut batch = Vec::new();
+    let mut last_hash = None;
+    let mut remaining = candidates.into_iter().peekable();
+
+    while let Some((hash, member, item)) = remaining.next() {
+        batch.push((member, item));
+        last_hash = Some(hash);
+        if batch.len() >= count && remaining.peek().is_none_or(|next| next.0 != hash) {
+            break;
+        }
+    }
+
+    let next_cursor = match (remaining.peek(), last_hash) {
+        (Some(_), Some(hash)) => hash.wrapping_add(1),
+        _ => 0,
+    };
+    (next_cursor, batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_batch_visits_everything_once() {
+        let members: Vec<Bytes> = (0..100).map(|i| Bytes::from(format!("m{}", i))).collect();
+        let mut cursor = 0;
+        let mut seen = Vec::new();
+
+        loop {
+            let (next, batch) = scan_batch(members.iter().map(|m| (m.clone(), ())), cursor, 7);
+            assert!(batch.len() >= 7 || next == 0);
+            seen.extend(batch.into_iter().map(|(m, _)| m));
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        seen.sort();
+        let mut expected = members.clone();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+}
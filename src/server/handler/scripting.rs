@@ -0,0 +1,810 @@
+//! Lua scripting: EVAL, EVALSHA and SCRIPT.
+//!
+//! A script holds the exec lock exclusively and runs as one storage batch,
+//! so it is atomic like a transaction: no other client runs in between, and
+//! on LMDB its writes commit together. The interpreter is synchronous, so
+//! commands called through `redis.call` are driven to completion in place;
+//! on a multi-threaded runtime the script leaves its worker thread to the
+//! other connections while it runs, which lets them ask for SCRIPT KILL.
+
+use super::{parse_int, storage_error, wrong_args, Cmd, Handler};
+use crate::lua::{format_number, Host, Interp, LuaError, TableRef, Value};
+use crate::protocol::{ProtocolVersion, RespValue};
+use crate::server::scripts::{sha1_hex, KillOutcome, Script};
+use bytes::Bytes;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tracing::{debug, info, warn};
+
+/// Nesting limit of tables converted to replies and back.
+const MAX_CONVERSION_DEPTH: usize = 100;
+
+/// Commands scripts may not call.
+fn forbidden_in_scripts(cmd: Cmd) -> bool {
+    matches!(
+        cmd,
+        Cmd::Eval
+            | Cmd::EvalSha
+            | Cmd::Script
+            | Cmd::Multi
+            | Cmd::Exec
+            | Cmd::Discard
+            | Cmd::Watch
+            | Cmd::Unwatch
+            | Cmd::Hello
+            | Cmd::Subscribe
+            | Cmd::Unsubscribe
+            | Cmd::PSubscribe
+            | Cmd::PUnsubscribe
+            | Cmd::SSubscribe
+            | Cmd::SUnsubscribe
+            | Cmd::Client
+            | Cmd::Auth
+    )
+}
+
+impl Handler {
+    /// EVAL script numkeys [key ...] [arg ...]
+    /// EVALSHA sha1 numkeys [key ...] [arg ...]
+    pub(super) async fn handle_eval(&mut self, args: &[&Bytes], by_sha: bool) -> RespValue {
+        let command = if by_sha { "evalsha" } else { "eval" };
+        if args.len() < 2 {
+            return wrong_args(command);
+        }
+        let numkeys = match parse_int(args[1]) {
+            Ok(n) if n < 0 => {
+                return RespValue::Error("ERR Number of keys can't be negative".to_string())
+            }
+            Ok(n) if n as usize > args.len() - 2 => {
+                return RespValue::Error(
+                    "ERR Number of keys can't be greater than number of args".to_string(),
+                )
+            }
+            Ok(n) => n as usize,
+            Err(e) => return e,
+        };
+
+        let scripts = &self.context.scripts;
+        let script = if by_sha {
+            match scripts.get(&String::from_utf8_lossy(args[0])) {
+                Some(script) => script,
+                None => {
+                    return RespValue::Error(
+                        "NOSCRIPT No matching script. Please use EVAL.".to_string(),
+                    )
+                }
+            }
+        } else {
+            match scripts.load(args[0]) {
+                Ok(script) => script,
+                Err(e) => {
+                    return RespValue::Error(format!(
+                        "ERR Error compiling script (new function): {}",
+                        e
+                    ))
+                }
+            }
+        };
+        let keys: Vec<Bytes> = args[2..2 + numkeys].iter().map(|&k| k.clone()).collect();
+        let argv: Vec<Bytes> = args[2 + numkeys..].iter().map(|&a| a.clone()).collect();
+
+        // Inside EXEC the lock is already held exclusively
+        let context = Arc::clone(&self.context);
+        let _exclusive = match self.in_exec {
+            true => None,
+            false => Some(context.exec_lock.write().await),
+        };
+
+        let storage = Arc::clone(&self.storage);
+        let in_exec = std::mem::replace(&mut self.in_exec, true);
+        let mut reply = None;
+        let result = {
+            let batch = pin!(async {
+                reply = Some(self.run_script(&script, keys, argv));
+            });
+            storage.atomically(batch).await
+        };
+        self.in_exec = in_exec;
+        match (result, reply) {
+            (Ok(()), Some(reply)) => reply,
+            (Err(e), _) => storage_error(command, e),
+            (Ok(()), None) => unreachable!("batches run to completion"),
+        }
+    }
+
+    /// Run a script to its end and convert its result to a reply.
+    fn run_script(&mut self, script: &Script, keys: Vec<Bytes>, argv: Vec<Bytes>) -> RespValue {
+        let runtime = Handle::try_current()
+            .ok()
+            .filter(|handle| handle.runtime_flavor() == RuntimeFlavor::MultiThread);
+        let context = Arc::clone(&self.context);
+        let running = context.scripts.start(context.dirty.load(Ordering::Relaxed));
+        let client_protocol = self.protocol_version;
+        // Scripts see RESP2 replies unless they call redis.setresp(3)
+        self.protocol_version = ProtocolVersion::Resp2;
+
+        let blocking = runtime.is_some();
+        let mut host = ScriptHost {
+            handler: self,
+            runtime,
+            killed: Arc::clone(running.killed()),
+        };
+        let run = || {
+            let mut interp = Interp::new(&mut host);
+            interp.set_global("redis", redis_library());
+            interp.freeze();
+            // Scripts may modify their arguments
+            interp.set_global("KEYS", string_array(keys));
+            interp.set_global("ARGV", string_array(argv));
+            let result = interp.run(&script.chunk);
+            (result, interp.line())
+        };
+        let (result, line) = match blocking {
+            true => tokio::task::block_in_place(run),
+            false => run(),
+        };
+        self.protocol_version = client_protocol;
+
+        match result {
+            Ok(values) => {
+                let value = values.into_iter().next().unwrap_or_default();
+                lua_to_resp(&value, client_protocol, 0)
+            }
+            Err(LuaError::Interrupted) => {
+                RespValue::Error("ERR Script killed by user with SCRIPT KILL...".to_string())
+            }
+            Err(LuaError::Error(error)) => {
+                let message = match &error {
+                    Value::Table(table) => match table.get_str("err") {
+                        Value::String(err) => String::from_utf8_lossy(&err).into_owned(),
+                        _ => "ERR unknown error".to_string(),
+                    },
+                    other => format!(
+                        "ERR {}",
+                        String::from_utf8_lossy(&other.as_bytes().unwrap_or_default())
+                    ),
+                };
+                RespValue::Error(format!(
+                    "{} script: {}, on @user_script:{}.",
+                    message, script.sha, line
+                ))
+            }
+        }
+    }
+
+    /// SCRIPT LOAD|EXISTS|FLUSH|KILL
+    pub(super) fn handle_script(&mut self, args: &[&Bytes]) -> RespValue {
+        let Some(subcommand) = args.first() else {
+            return wrong_args("script");
+        };
+        let scripts = &self.context.scripts;
+        match subcommand.to_ascii_uppercase().as_slice() {
+            b"LOAD" if args.len() == 2 => match scripts.load(args[1]) {
+                Ok(script) => RespValue::BulkString(Some(Bytes::from(script.sha.clone()))),
+                Err(e) => {
+                    RespValue::Error(format!("ERR Error compiling script (new function): {}", e))
+                }
+            },
+            b"EXISTS" if args.len() >= 2 => RespValue::Array(Some(
+                args[1..]
+                    .iter()
+                    .map(|sha| {
+                        RespValue::Integer(scripts.exists(&String::from_utf8_lossy(sha)) as i64)
+                    })
+                    .collect(),
+            )),
+            b"FLUSH" if args.len() <= 2 => {
+                // Flushing is immediate either way
+                if let Some(mode) = args.get(1) {
+                    if !mode.eq_ignore_ascii_case(b"ASYNC") && !mode.eq_ignore_ascii_case(b"SYNC") {
+                        return RespValue::Error(
+                            "ERR SCRIPT FLUSH only support SYNC|ASYNC option".to_string(),
+                        );
+                    }
+                }
+                scripts.flush();
+                RespValue::SimpleString("OK".to_string())
+            }
+            b"KILL" if args.len() == 1 => {
+                match scripts.kill(self.context.dirty.load(Ordering::Relaxed)) {
+                    KillOutcome::Killed => RespValue::SimpleString("OK".to_string()),
+                    KillOutcome::NotBusy => {
+                        RespValue::Error("NOTBUSY No scripts in execution right now.".to_string())
+                    }
+                    KillOutcome::Unkillable => RespValue::Error(
+                        "UNKILLABLE Sorry the script already executed write commands against \
+                         the dataset. You can either wait the script termination or kill the \
+                         server in a hard way using the SHUTDOWN NOSAVE command."
+                            .to_string(),
+                    ),
+                }
+            }
+            b"LOAD" | b"EXISTS" | b"FLUSH" | b"KILL" => RespValue::Error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'. Try SCRIPT HELP.",
+                String::from_utf8_lossy(subcommand)
+            )),
+            _ => RespValue::Error(format!(
+                "ERR unknown subcommand '{}'. Try SCRIPT HELP.",
+                String::from_utf8_lossy(subcommand)
+            )),
+        }
+    }
+}
+
+/// The `redis` library: functions calling back into [`ScriptHost`].
+fn redis_library() -> Value {
+    let redis = TableRef::new();
+    for name in [
+        "call",
+        "pcall",
+        "error_reply",
+        "status_reply",
+        "sha1hex",
+        "log",
+        "setresp",
+    ] {
+        redis.set_str(name, Interp::host_function(name));
+    }
+    for (i, level) in ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"]
+        .into_iter()
+        .enumerate()
+    {
+        redis.set_str(level, Value::Number(i as f64));
+    }
+    Value::Table(redis)
+}
+
+fn string_array(items: Vec<Bytes>) -> Value {
+    Value::Table(TableRef::from_array(
+        items.into_iter().map(Value::String).collect(),
+    ))
+}
+
+/// A table with a single field, the way scripts see status and error replies.
+fn single_field(field: &str, value: Value) -> Value {
+    let table = TableRef::new();
+    table.set_str(field, value);
+    Value::Table(table)
+}
+
+/// An error raised in a script, as a table like those of error replies.
+fn script_error(message: impl Into<Bytes>) -> LuaError {
+    LuaError::Error(single_field("err", Value::string(message)))
+}
+
+/// The handler running a script, serving its calls to the `redis` library.
+struct ScriptHost<'a> {
+    handler: &'a mut Handler,
+    /// Runtime to wait on for commands that suspend, if the script may block
+    /// its thread.
+    runtime: Option<Handle>,
+    killed: Arc<AtomicBool>,
+}
+
+impl Host for ScriptHost<'_> {
+    fn call(&mut self, name: &'static str, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        let value = match name {
+            "call" | "pcall" => {
+                let reply = self.call_command(args)?;
+                match reply {
+                    RespValue::Error(e) if name == "call" => return Err(script_error(e)),
+                    reply => resp_to_lua(&reply),
+                }
+            }
+            "error_reply" | "status_reply" => {
+                let field = if name == "error_reply" { "err" } else { "ok" };
+                match args.first() {
+                    Some(Value::String(s)) => single_field(field, Value::String(s.clone())),
+                    _ => return Err(script_error("ERR wrong number or type of arguments")),
+                }
+            }
+            "sha1hex" => match args.first().and_then(Value::as_bytes) {
+                Some(data) if args.len() == 1 => Value::string(sha1_hex(&data)),
+                _ => return Err(script_error("ERR wrong number of arguments")),
+            },
+            "log" => {
+                let level = args.first().and_then(Value::as_number);
+                if args.len() < 2 || level.is_none() {
+                    return Err(script_error(
+                        "ERR redis.log() requires two arguments or more.",
+                    ));
+                }
+                let message = args[1..]
+                    .iter()
+                    .map(|arg| {
+                        String::from_utf8_lossy(&arg.as_bytes().unwrap_or_default()).into_owned()
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                match level.unwrap_or_default() as i64 {
+                    0 | 1 => debug!("Script: {}", message),
+                    2 => info!("Script: {}", message),
+                    3 => warn!("Script: {}", message),
+                    _ => return Err(script_error("ERR Invalid debug level.")),
+                }
+                Value::Nil
+            }
+            "setresp" => {
+                self.handler.protocol_version = match args.first().and_then(Value::as_number) {
+                    Some(n) if n == 2.0 && args.len() == 1 => ProtocolVersion::Resp2,
+                    Some(n) if n == 3.0 && args.len() == 1 => ProtocolVersion::Resp3,
+                    _ => return Err(script_error("ERR RESP version must be 2 or 3.")),
+                };
+                Value::Nil
+            }
+            _ => unreachable!("{} is not a redis library function", name),
+        };
+        Ok(vec![value])
+    }
+
+    fn interrupted(&mut self) -> bool {
+        self.killed.load(Ordering::Acquire)
+    }
+}
+
+impl ScriptHost<'_> {
+    /// Run a command for `redis.call` or `redis.pcall`. Failures of the
+    /// command are replied as errors; failures of the call itself are raised.
+    fn call_command(&mut self, args: Vec<Value>) -> Result<RespValue, LuaError> {
+        if args.is_empty() {
+            return Err(script_error(
+                "ERR Please specify at least one argument for this redis lib call",
+            ));
+        }
+        let mut parts = Vec::with_capacity(args.len());
+        for arg in &args {
+            let arg = match arg {
+                Value::String(s) => s.clone(),
+                Value::Number(n) => Bytes::from(format_number(*n)),
+                _ => {
+                    return Err(script_error(
+                        "ERR Lua redis lib command arguments must be strings or integers",
+                    ))
+                }
+            };
+            parts.push(RespValue::BulkString(Some(arg)));
+        }
+
+        let RespValue::BulkString(Some(name)) = &parts[0] else {
+            unreachable!("arguments are bulk strings");
+        };
+        let cmd = Cmd::parse(name);
+        if cmd == Cmd::Unknown {
+            return Err(script_error("ERR Unknown Redis command called from script"));
+        }
+        if forbidden_in_scripts(cmd) {
+            return Err(script_error(
+                "ERR This Redis command is not allowed from script",
+            ));
+        }
+        if !cmd.accepts(parts.len()) {
+            return Err(script_error(
+                "ERR Wrong number of args calling Redis command from script",
+            ));
+        }
+
+        // Commands run within the script's batch, which on LMDB completes
+        // them at once; other backends may have to be waited for
+        let mut command = pin!(self.handler.execute(cmd, &parts));
+        if let Poll::Ready(reply) = command
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            return Ok(reply);
+        }
+        match &self.runtime {
+            Some(runtime) => Ok(runtime.block_on(command)),
+            None => Err(script_error(
+                "ERR Command can not complete within a script on this runtime",
+            )),
+        }
+    }
+}
+
+/// Convert a command reply to a Lua value, the way Redis does.
+fn resp_to_lua(reply: &RespValue) -> Value {
+    match reply {
+        RespValue::Integer(n) => Value::Number(*n as f64),
+        RespValue::BulkString(Some(s)) => Value::String(s.clone()),
+        RespValue::BulkString(None) | RespValue::Array(None) => Value::Boolean(false),
+        RespValue::Null => Value::Nil,
+        RespValue::Boolean(b) => Value::Boolean(*b),
+        RespValue::SimpleString(s) => single_field("ok", Value::string(s.clone())),
+        RespValue::Error(e) => single_field("err", Value::string(e.clone())),
+        RespValue::Double(d) => single_field("double", Value::Number(*d)),
+        RespValue::Array(Some(items)) | RespValue::Push(items) => Value::Table(
+            TableRef::from_array(items.iter().map(resp_to_lua).collect()),
+        ),
+        RespValue::Map(pairs) => {
+            let map = TableRef::new();
+            for (key, value) in pairs {
+                let _ = map.set(resp_to_lua(key), resp_to_lua(value));
+            }
+            single_field("map", Value::Table(map))
+        }
+        RespValue::Set(items) => {
+            let set = TableRef::new();
+            for item in items {
+                let _ = set.set(resp_to_lua(item), Value::Boolean(true));
+            }
+            single_field("set", Value::Table(set))
+        }
+    }
+}
+
+/// Convert a script's result to a reply for a client using `protocol`, the
+/// way Redis does: numbers are truncated to integers, and arrays end at
+/// their first nil.
+fn lua_to_resp(value: &Value, protocol: ProtocolVersion, depth: usize) -> RespValue {
+    let resp3 = protocol == ProtocolVersion::Resp3;
+    match value {
+        Value::Nil if resp3 => RespValue::Null,
+        Value::Nil | Value::Function(_) => RespValue::BulkString(None),
+        Value::Boolean(b) if resp3 => RespValue::Boolean(*b),
+        Value::Boolean(true) => RespValue::Integer(1),
+        Value::Boolean(false) => RespValue::BulkString(None),
+        Value::Number(n) => RespValue::Integer(*n as i64),
+        Value::String(s) => RespValue::BulkString(Some(s.clone())),
+        Value::Table(_) if depth >= MAX_CONVERSION_DEPTH => {
+            RespValue::Error("ERR reached lua stack limit".to_string())
+        }
+        Value::Table(table) => {
+            if let Value::String(err) = table.get_str("err") {
+                return RespValue::Error(String::from_utf8_lossy(&err).into_owned());
+            }
+            if let Value::String(ok) = table.get_str("ok") {
+                return RespValue::SimpleString(String::from_utf8_lossy(&ok).into_owned());
+            }
+            if let Some(d) = table.get_str("double").as_number() {
+                return match resp3 {
+                    true => RespValue::Double(d),
+                    false => RespValue::BulkString(Some(Bytes::from(format_number(d)))),
+                };
+            }
+            if let Value::Table(map) = table.get_str("map") {
+                let pairs = table_entries(&map)
+                    .into_iter()
+                    .map(|(k, v)| {
+                        (
+                            lua_to_resp(&k, protocol, depth + 1),
+                            lua_to_resp(&v, protocol, depth + 1),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                return match resp3 {
+                    true => RespValue::Map(pairs),
+                    false => RespValue::Array(Some(
+                        pairs.into_iter().flat_map(|(k, v)| [k, v]).collect(),
+                    )),
+                };
+            }
+            if let Value::Table(set) = table.get_str("set") {
+                let members = table_entries(&set)
+                    .into_iter()
+                    .map(|(k, _)| lua_to_resp(&k, protocol, depth + 1))
+                    .collect();
+                return match resp3 {
+                    true => RespValue::Set(members),
+                    false => RespValue::Array(Some(members)),
+                };
+            }
+            let mut items = Vec::new();
+            for i in 1.. {
+                match table.get_index(i) {
+                    Value::Nil => break,
+                    item => items.push(lua_to_resp(&item, protocol, depth + 1)),
+                }
+            }
+            RespValue::Array(Some(items))
+        }
+    }
+}
+
+fn table_entries(table: &TableRef) -> Vec<(Value, Value)> {
+    let mut entries = Vec::new();
+    let mut key = Value::Nil;
+    while let Ok(Some((k, v))) = table.next(&key) {
+        entries.push((k.clone(), v));
+        key = k;
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::ServerContext;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::time::Duration;
+
+    async fn run(handler: &mut Handler, args: &[&str]) -> RespValue {
+        let parts = args
+            .iter()
+            .map(|arg| RespValue::BulkString(Some(Bytes::from(arg.to_string()))))
+            .collect();
+        handler.handle_command(RespValue::Array(Some(parts))).await
+    }
+
+    async fn eval(handler: &mut Handler, script: &str, keys_and_args: &[&str]) -> RespValue {
+        let numkeys = keys_and_args
+            .iter()
+            .position(|arg| *arg == "--")
+            .unwrap_or(keys_and_args.len());
+        let mut args = vec!["EVAL", script];
+        let numkeys = numkeys.to_string();
+        args.push(&numkeys);
+        args.extend(keys_and_args.iter().filter(|arg| **arg != "--"));
+        run(handler, &args).await
+    }
+
+    fn bulk(value: &RespValue) -> &[u8] {
+        match value {
+            RespValue::BulkString(Some(b)) => b,
+            other => panic!("Expected bulk string, got {:?}", other),
+        }
+    }
+
+    fn error(value: &RespValue) -> &str {
+        match value {
+            RespValue::Error(e) => e,
+            other => panic!("Expected error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_eval_conversions() {
+        let mut handler = Handler::new(Arc::new(MemoryStorage::new()));
+        let reply = eval(
+            &mut handler,
+            "redis.call('SET', KEYS[1], ARGV[1])\n\
+             redis.call('RPUSH', 'list', 'a', 2)\n\
+             return {redis.call('GET', KEYS[1]), redis.call('INCR', 'n'), 3.99,\n\
+                     redis.call('GET', 'missing'), redis.call('LRANGE', 'list', 0, -1),\n\
+                     redis.status_reply('FINE'), true, false, nil, 'after nil'}",
+            &["k", "--", "v"],
+        )
+        .await;
+        let RespValue::Array(Some(items)) = reply else {
+            panic!("Expected Array");
+        };
+        assert_eq!(items.len(), 8);
+        assert_eq!(bulk(&items[0]), b"v");
+        assert!(matches!(items[1], RespValue::Integer(1)));
+        assert!(matches!(items[2], RespValue::Integer(3)));
+        assert!(matches!(items[3], RespValue::BulkString(None)));
+        assert!(matches!(&items[4], RespValue::Array(Some(list)) if bulk(&list[1]) == b"2"));
+        assert!(matches!(&items[5], RespValue::SimpleString(s) if s == "FINE"));
+        assert!(matches!(items[6], RespValue::Integer(1)));
+        assert!(matches!(items[7], RespValue::BulkString(None)));
+
+        // Status and error replies are tables to scripts
+        let reply = eval(
+            &mut handler,
+            "local ok = redis.call('SET', 'k', 'v')\n\
+             local err = redis.pcall('INCR', 'k')\n\
+             return {ok.ok, err.err, type(redis.call('TYPE', 'k'))}",
+            &[],
+        )
+        .await;
+        let RespValue::Array(Some(items)) = reply else {
+            panic!("Expected Array");
+        };
+        assert_eq!(bulk(&items[0]), b"OK");
+        assert!(bulk(&items[1]).starts_with(b"ERR value is not an integer"));
+        assert_eq!(bulk(&items[2]), b"table");
+
+        // RESP3 clients get RESP3 types, and scripts can ask for them too
+        handler.set_protocol_version(ProtocolVersion::Resp3);
+        let reply = eval(
+            &mut handler,
+            "redis.setresp(3)\n\
+             redis.call('HSET', 'h', 'f', 'v')\n\
+             local map = redis.call('HGETALL', 'h').map\n\
+             return {map.f, {double = 1.5}, {map = {a = 1}}, {set = {x = true}}, true, nil}",
+            &[],
+        )
+        .await;
+        let RespValue::Array(Some(items)) = reply else {
+            panic!("Expected Array");
+        };
+        assert_eq!(bulk(&items[0]), b"v");
+        assert!(matches!(items[1], RespValue::Double(d) if d == 1.5));
+        assert!(matches!(&items[2], RespValue::Map(pairs) if pairs.len() == 1));
+        assert!(matches!(&items[3], RespValue::Set(members) if members.len() == 1));
+        assert!(matches!(items[4], RespValue::Boolean(true)));
+        assert!(matches!(
+            eval(&mut handler, "return nil", &[]).await,
+            RespValue::Null
+        ));
+        assert_eq!(handler.protocol_version(), ProtocolVersion::Resp3);
+    }
+
+    #[tokio::test]
+    async fn test_eval_errors() {
+        let mut handler = Handler::new(Arc::new(MemoryStorage::new()));
+        let sha = sha1_hex(b"return redis.call('INCR', KEYS[1])");
+        run(&mut handler, &["SET", "k", "v"]).await;
+        assert_eq!(
+            error(&eval(&mut handler, "return redis.call('INCR', KEYS[1])", &["k"]).await),
+            format!(
+                "ERR value is not an integer or out of range script: {}, on @user_script:1.",
+                sha
+            )
+        );
+        assert!(error(&eval(&mut handler, "\nerror('boom')", &[]).await)
+            .starts_with("ERR user_script:2: boom script: "));
+        assert!(
+            error(&eval(&mut handler, "return redis.call('NOPE')", &[]).await)
+                .starts_with("ERR Unknown Redis command called from script script: ")
+        );
+        assert!(
+            error(&eval(&mut handler, "return redis.call('GET')", &[]).await)
+                .starts_with("ERR Wrong number of args calling Redis command from script script: ")
+        );
+        assert!(
+            error(&eval(&mut handler, "return redis.call('MULTI')", &[]).await)
+                .starts_with("ERR This Redis command is not allowed from script script: ")
+        );
+        assert!(
+            error(&eval(&mut handler, "return redis.call('GET', {})", &[]).await)
+                .starts_with("ERR Lua redis lib command arguments must be strings or integers")
+        );
+        assert!(error(&eval(&mut handler, "x = 1", &[]).await)
+            .starts_with("ERR user_script:1: Attempt to modify a readonly table script: "));
+        assert_eq!(
+            error(&eval(&mut handler, "return redis.error_reply('MY failure')", &[]).await),
+            "MY failure"
+        );
+        assert!(error(&eval(&mut handler, "return (", &[]).await)
+            .starts_with("ERR Error compiling script (new function): user_script:1: "));
+
+        assert_eq!(
+            error(&run(&mut handler, &["EVAL", "return 1", "x"]).await),
+            "ERR value is not an integer or out of range"
+        );
+        assert_eq!(
+            error(&run(&mut handler, &["EVAL", "return 1", "-1"]).await),
+            "ERR Number of keys can't be negative"
+        );
+        assert_eq!(
+            error(&run(&mut handler, &["EVAL", "return 1", "2", "k"]).await),
+            "ERR Number of keys can't be greater than number of args"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_script_cache() {
+        let mut handler = Handler::new(Arc::new(MemoryStorage::new()));
+        let sha = sha1_hex(b"return ARGV[1]");
+        assert!(run(&mut handler, &["EVALSHA", &sha, "0", "x"])
+            .await
+            .to_bytes()
+            .starts_with(b"-NOSCRIPT"));
+
+        assert_eq!(
+            bulk(&run(&mut handler, &["SCRIPT", "LOAD", "return ARGV[1]"]).await),
+            sha.as_bytes()
+        );
+        assert_eq!(
+            bulk(&run(&mut handler, &["EVALSHA", &sha.to_uppercase(), "0", "x"]).await),
+            b"x"
+        );
+        let RespValue::Array(Some(exists)) =
+            run(&mut handler, &["SCRIPT", "EXISTS", &sha, "nope"]).await
+        else {
+            panic!("Expected Array");
+        };
+        assert!(matches!(
+            exists[..],
+            [RespValue::Integer(1), RespValue::Integer(0)]
+        ));
+
+        // EVAL caches the scripts it runs
+        run(&mut handler, &["SCRIPT", "FLUSH", "ASYNC"]).await;
+        assert!(matches!(
+            run(&mut handler, &["EVALSHA", &sha, "0"]).await,
+            RespValue::Error(e) if e.starts_with("NOSCRIPT")
+        ));
+        eval(&mut handler, "return ARGV[1]", &[]).await;
+        assert!(matches!(
+            run(&mut handler, &["EVALSHA", &sha, "0"]).await,
+            RespValue::BulkString(None)
+        ));
+
+        assert_eq!(
+            error(&run(&mut handler, &["SCRIPT", "KILL"]).await),
+            "NOTBUSY No scripts in execution right now."
+        );
+        assert_eq!(
+            error(&run(&mut handler, &["SCRIPT", "NOPE"]).await),
+            "ERR unknown subcommand 'NOPE'. Try SCRIPT HELP."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_eval_on_lmdb() {
+        use crate::storage::lmdb::LmdbStorage;
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(
+            LmdbStorage::new_with_map_size(dir.path().join("test.lmdb"), 64 * 1024 * 1024).unwrap(),
+        );
+        let mut handler = Handler::new(Arc::clone(&storage));
+
+        // The script's writes commit together and its reads see them
+        let reply = eval(
+            &mut handler,
+            "for i = 1, 100 do redis.call('RPUSH', KEYS[1], i) end\n\
+             return redis.call('LLEN', KEYS[1])",
+            &["list"],
+        )
+        .await;
+        assert!(matches!(reply, RespValue::Integer(100)));
+        assert_eq!(storage.keys_count().await.unwrap(), 1);
+
+        // Scripts run within transactions too
+        run(&mut handler, &["MULTI"]).await;
+        eval(&mut handler, "return redis.call('LPOP', 'list')", &[]).await;
+        assert!(matches!(
+            run(&mut handler, &["EXEC"]).await,
+            RespValue::Array(Some(replies)) if bulk(&replies[0]) == b"1"
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_script_kill() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let context = Arc::new(ServerContext::new());
+        let config = Arc::new(Config::default());
+        let new_handler = || {
+            Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            )
+        };
+
+        let mut looping = new_handler();
+        let script =
+            tokio::spawn(async move { eval(&mut looping, "while true do end", &[]).await });
+        let mut other = new_handler();
+        while context.scripts.kill(u64::MAX) == KillOutcome::NotBusy {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(is_ok(&run(&mut other, &["SCRIPT", "KILL"]).await));
+        assert_eq!(
+            error(&script.await.unwrap()),
+            "ERR Script killed by user with SCRIPT KILL..."
+        );
+        assert!(is_ok(&run(&mut other, &["SET", "k", "v"]).await));
+
+        // Scripts that wrote can not be killed
+        let dirty = context.dirty.load(Ordering::Relaxed);
+        let mut writing = new_handler();
+        let script = tokio::spawn(async move {
+            let script = "redis.call('SET', 'k', 'w')\n\
+                          local start = os.clock()\n\
+                          while os.clock() - start < 1 do end";
+            eval(&mut writing, script, &[]).await
+        });
+        while context.dirty.load(Ordering::Relaxed) == dirty {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(error(&run(&mut other, &["SCRIPT", "KILL"]).await).starts_with("UNKILLABLE"));
+        assert!(matches!(
+            script.await.unwrap(),
+            RespValue::Array(None) | RespValue::BulkString(None)
+        ));
+        assert_eq!(storage.get(b"k").await.unwrap().unwrap(), "w");
+    }
+
+    fn is_ok(value: &RespValue) -> bool {
+        matches!(value, RespValue::SimpleString(s) if s == "OK")
+    }
+}
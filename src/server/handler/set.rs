@@ -0,0 +1,725 @@
+//! Set commands (SADD, SMEMBERS, SINTER, SUNIONSTORE, SSCAN, ...).
+
+use super::scan::{scan_batch, ScanArgs, ScanKind};
+use super::{
+    distinct_keys, parse_int, parse_random_count, storage_error, syntax_error, wrong_args, Handler,
+};
+use crate::protocol::RespValue;
+use crate::server::notify::NOTIFY_SET;
+use crate::storage::{StorageError, StorageValue, ValueData, ValueSlot};
+use bytes::Bytes;
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
+use std::collections::HashSet;
+
+/// Set algebra performed by SINTER/SUNION/SDIFF and their STORE variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+impl SetOp {
+    /// Combine the sets stored at `slots` (in key order).
+    fn apply<'a>(
+        self,
+        slots: impl IntoIterator<Item = &'a ValueSlot>,
+    ) -> Result<HashSet<Bytes>, StorageError> {
+        let mut sets = Vec::new();
+        for slot in slots {
+            sets.push(slot.members()?);
+        }
+
+        Ok(match self {
+            // A missing key is an empty set, which empties the intersection
+            SetOp::Inter => match sets.iter().copied().collect::<Option<Vec<_>>>() {
+                Some(mut sets) => {
+                    sets.sort_by_key(|set| set.len());
+                    let (smallest, rest) = sets.split_first().expect("at least one key");
+                    smallest
+                        .iter()
+                        .filter(|member| rest.iter().all(|set| set.contains(*member)))
+                        .cloned()
+                        .collect()
+                }
+                None => HashSet::new(),
+            },
+            SetOp::Union => sets.into_iter().flatten().flatten().cloned().collect(),
+            SetOp::Diff => {
+                let mut result = sets[0].cloned().unwrap_or_default();
+                for set in sets[1..].iter().flatten() {
+                    result.retain(|member| !set.contains(member));
+                }
+                result
+            }
+        })
+    }
+}
+
+impl Handler {
+    /// Reply with set members: a set under RESP3, an array under RESP2.
+    fn members_reply(&self, members: impl IntoIterator<Item = Bytes>) -> RespValue {
+        self.set_reply(
+            members
+                .into_iter()
+                .map(|m| RespValue::BulkString(Some(m)))
+                .collect(),
+        )
+    }
+
+    /// SADD key member [member ...]
+    pub(super) async fn handle_sadd(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("sadd");
+        }
+
+        let members = &args[1..];
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let set = slot.members_mut()?;
+                Ok(members
+                    .iter()
+                    .filter(|member| set.insert((**member).clone()))
+                    .count())
+            })
+            .await;
+
+        match result {
+            Ok(added) => {
+                if added > 0 {
+                    self.notify(NOTIFY_SET, "sadd", args[0]);
+                }
+                RespValue::Integer(added as i64)
+            }
+            Err(e) => storage_error("sadd", e),
+        }
+    }
+
+    /// SREM key member [member ...]
+    pub(super) async fn handle_srem(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("srem");
+        }
+
+        let members = &args[1..];
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                if slot.members()?.is_none() {
+                    return Ok(0);
+                }
+                let set = slot.members_mut()?;
+                Ok(members
+                    .iter()
+                    .filter(|member| set.remove(member.as_ref()))
+                    .count())
+            })
+            .await;
+
+        match result {
+            Ok(removed) => {
+                if removed > 0 {
+                    self.notify(NOTIFY_SET, "srem", args[0]);
+                    self.notify_if_removed(args[0]).await;
+                }
+                RespValue::Integer(removed as i64)
+            }
+            Err(e) => storage_error("srem", e),
+        }
+    }
+
+    /// SMEMBERS key
+    pub(super) async fn handle_smembers(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("smembers");
+        }
+
+        match self.set_members(args[0]).await {
+            Ok(members) => self.members_reply(members),
+            Err(e) => e,
+        }
+    }
+
+    /// SISMEMBER key member
+    pub(super) async fn handle_sismember(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("sismember");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_set()?.contains(args[1]),
+                    None => false,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(found) => RespValue::Integer(found as i64),
+            Err(e) => storage_error("sismember", e),
+        }
+    }
+
+    /// SMISMEMBER key member [member ...]
+    pub(super) async fn handle_smismember(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("smismember");
+        }
+
+        let members = &args[1..];
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let set = value.map(|v| v.as_set()).transpose()?;
+                Ok(members
+                    .iter()
+                    .map(|member| {
+                        let found = set.is_some_and(|s| s.contains(*member));
+                        RespValue::Integer(found as i64)
+                    })
+                    .collect())
+            })
+            .await;
+
+        match result {
+            Ok(flags) => RespValue::Array(Some(flags)),
+            Err(e) => storage_error("smismember", e),
+        }
+    }
+
+    /// SCARD key
+    pub(super) async fn handle_scard(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("scard");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_set()?.len(),
+                    None => 0,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(len) => RespValue::Integer(len as i64),
+            Err(e) => storage_error("scard", e),
+        }
+    }
+
+    /// SPOP key [count]
+    pub(super) async fn handle_spop(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() || args.len() > 2 {
+            return wrong_args("spop");
+        }
+        let count = match args.get(1).map(|c| parse_int(c)).transpose() {
+            Ok(Some(count)) if count < 0 => {
+                return RespValue::Error("ERR value is out of range, must be positive".to_string())
+            }
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some(set) = slot.members()? else {
+                    return Ok(Vec::new());
+                };
+                let picked: Vec<Bytes> = set
+                    .iter()
+                    .cloned()
+                    .choose_multiple(&mut rand::thread_rng(), count.unwrap_or(1) as usize);
+                if !picked.is_empty() {
+                    let set = slot.members_mut()?;
+                    for member in &picked {
+                        set.remove(member);
+                    }
+                }
+                Ok(picked)
+            })
+            .await;
+        if matches!(&result, Ok(popped) if !popped.is_empty()) {
+            self.notify(NOTIFY_SET, "spop", args[0]);
+            self.notify_if_removed(args[0]).await;
+        }
+
+        match (result, count) {
+            (Ok(mut popped), None) => RespValue::BulkString(popped.pop()),
+            (Ok(popped), Some(_)) => self.members_reply(popped),
+            (Err(e), _) => storage_error("spop", e),
+        }
+    }
+
+    /// SRANDMEMBER key [count]
+    pub(super) async fn handle_srandmember(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() || args.len() > 2 {
+            return wrong_args("srandmember");
+        }
+        let count = match args.get(1).map(|c| parse_random_count(c)).transpose() {
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+
+        let members: Vec<Bytes> = match self.set_members(args[0]).await {
+            Ok(members) => members.into_iter().collect(),
+            Err(e) => return e,
+        };
+        let mut rng = rand::thread_rng();
+
+        let Some(count) = count else {
+            return RespValue::BulkString(members.choose(&mut rng).cloned());
+        };
+
+        // Positive count: distinct members; negative count: may repeat
+        let picked: Vec<Bytes> = if count >= 0 {
+            members
+                .choose_multiple(&mut rng, count as usize)
+                .cloned()
+                .collect()
+        } else if members.is_empty() {
+            Vec::new()
+        } else {
+            (0..count.unsigned_abs())
+                .map(|_| members[rng.gen_range(0..members.len())].clone())
+                .collect()
+        };
+
+        RespValue::Array(Some(
+            picked
+                .into_iter()
+                .map(|m| RespValue::BulkString(Some(m)))
+                .collect(),
+        ))
+    }
+
+    /// SMOVE source destination member
+    pub(super) async fn handle_smove(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("smove");
+        }
+        let (source, destination, member) = (args[0], args[1], args[2]);
+
+        let result = if source == destination {
+            self.storage
+                .inspect(source, |value| {
+                    let moved = match value {
+                        Some(value) => value.as_set()?.contains(member),
+                        None => false,
+                    };
+                    Ok((moved, false))
+                })
+                .await
+        } else {
+            self.storage
+                .modify_many(&[source, destination], |slots| {
+                    // Check the destination type before touching the source
+                    slots[1].members()?;
+                    if !slots[0].members()?.is_some_and(|set| set.contains(member)) {
+                        return Ok((false, false));
+                    }
+                    slots[0].members_mut()?.remove(member);
+                    let added = slots[1].members_mut()?.insert(member.clone());
+                    Ok((true, added))
+                })
+                .await
+        };
+
+        match result {
+            Ok((moved, added)) => {
+                if moved && source != destination {
+                    self.notify(NOTIFY_SET, "srem", source);
+                    self.notify_if_removed(source).await;
+                    if added {
+                        self.notify(NOTIFY_SET, "sadd", destination);
+                    }
+                }
+                RespValue::Integer(moved as i64)
+            }
+            Err(e) => storage_error("smove", e),
+        }
+    }
+
+    /// SINTER/SUNION/SDIFF key [key ...]
+    pub(super) async fn handle_set_op(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        op: SetOp,
+    ) -> RespValue {
+        if args.is_empty() {
+            return wrong_args(command);
+        }
+
+        let (keys, indexes) = distinct_keys(args);
+        let result = self
+            .storage
+            .modify_many(&keys, |slots| op.apply(indexes.iter().map(|&i| &slots[i])))
+            .await;
+
+        match result {
+            Ok(members) => self.members_reply(members),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// SINTERSTORE/SUNIONSTORE/SDIFFSTORE destination key [key ...]
+    pub(super) async fn handle_set_op_store(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        op: SetOp,
+    ) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args(command);
+        }
+
+        // The destination may also be a source, so share its slot
+        let (keys, indexes) = distinct_keys(args);
+        let result = self
+            .storage
+            .modify_many(&keys, |slots| {
+                let members = op.apply(indexes[1..].iter().map(|&i| &slots[i]))?;
+                let len = members.len();
+                let destination = &mut slots[indexes[0]];
+                let existed = destination.get().is_some();
+                if members.is_empty() {
+                    destination.delete();
+                } else {
+                    destination.set(StorageValue::new(ValueData::Set(members)));
+                }
+                Ok((len, existed))
+            })
+            .await;
+
+        match result {
+            Ok((len, existed)) => {
+                self.notify_stored(NOTIFY_SET, command, args[0], len, existed);
+                RespValue::Integer(len as i64)
+            }
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// SINTERCARD numkeys key [key ...] [LIMIT limit]
+    pub(super) async fn handle_sintercard(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("sintercard");
+        }
+        let numkeys = match parse_int(args[0]) {
+            Ok(numkeys) if numkeys <= 0 => {
+                return RespValue::Error("ERR numkeys should be greater than 0".to_string())
+            }
+            Ok(numkeys) => numkeys as usize,
+            Err(e) => return e,
+        };
+        if numkeys > args.len() - 1 {
+            return RespValue::Error(
+                "ERR Number of keys can't be greater than number of args".to_string(),
+            );
+        }
+
+        let limit = match &args[numkeys + 1..] {
+            [] => 0,
+            [option, limit] if option.eq_ignore_ascii_case(b"LIMIT") => match parse_int(limit) {
+                Ok(limit) if limit < 0 => {
+                    return RespValue::Error("ERR LIMIT can't be negative".to_string())
+                }
+                Ok(limit) => limit as usize,
+                Err(e) => return e,
+            },
+            _ => return syntax_error(),
+        };
+
+        let (keys, indexes) = distinct_keys(&args[1..=numkeys]);
+        let result = self
+            .storage
+            .modify_many(&keys, |slots| {
+                SetOp::Inter.apply(indexes.iter().map(|&i| &slots[i]))
+            })
+            .await;
+
+        match result {
+            Ok(members) if limit > 0 => RespValue::Integer(members.len().min(limit) as i64),
+            Ok(members) => RespValue::Integer(members.len() as i64),
+            Err(e) => storage_error("sintercard", e),
+        }
+    }
+
+    /// SSCAN key cursor [MATCH pattern] [COUNT count]
+    pub(super) async fn handle_sscan(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("sscan");
+        }
+        let scan = match ScanArgs::parse(&args[1..], ScanKind::Members) {
+            Ok(scan) => scan,
+            Err(e) => return e,
+        };
+
+        let members = match self.set_members(args[0]).await {
+            Ok(members) => members,
+            Err(e) => return e,
+        };
+
+        let (cursor, batch) = scan_batch(
+            members.into_iter().map(|m| (m, ())),
+            scan.cursor,
+            scan.count,
+        );
+        let items = batch
+            .into_iter()
+            .filter(|(member, _)| scan.matches(member))
+            .map(|(member, _)| RespValue::BulkString(Some(member)))
+            .collect();
+
+        RespValue::Array(Some(vec![
+            RespValue::BulkString(Some(cursor.to_string().into())),
+            RespValue::Array(Some(items)),
+        ]))
+    }
+
+    /// Snapshot the members of a set (empty if the key is missing).
+    async fn set_members(&self, key: &[u8]) -> Result<HashSet<Bytes>, RespValue> {
+        self.storage
+            .inspect(key, |value| {
+                Ok(match value {
+                    Some(value) => value.as_set()?.clone(),
+                    None => HashSet::new(),
+                })
+            })
+            .await
+            .map_err(|e| storage_error("set read", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolVersion;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    fn b(s: &str) -> Bytes {
+        Bytes::from(s.to_string())
+    }
+
+    /// Sorted member strings of an array or set reply.
+    fn members(value: RespValue) -> Vec<String> {
+        let items = match value {
+            RespValue::Array(Some(items)) | RespValue::Set(items) => items,
+            other => panic!("Expected Array or Set, got {:?}", other),
+        };
+        let mut members: Vec<String> = items
+            .into_iter()
+            .map(|item| match item {
+                RespValue::BulkString(Some(m)) => String::from_utf8(m.to_vec()).unwrap(),
+                other => panic!("Expected BulkString, got {:?}", other),
+            })
+            .collect();
+        members.sort();
+        members
+    }
+
+    async fn sadd(handler: &Handler, key: &str, items: &[&str]) {
+        let mut args = vec![b(key)];
+        args.extend(items.iter().map(|m| b(m)));
+        let args: Vec<&Bytes> = args.iter().collect();
+        handler.handle_sadd(&args).await;
+    }
+
+    #[tokio::test]
+    async fn test_sadd_srem_smembers() {
+        let mut handler = create_handler();
+        let key = b("s");
+
+        assert!(matches!(
+            handler
+                .handle_sadd(&[&key, &b("a"), &b("b"), &b("a")])
+                .await,
+            RespValue::Integer(2)
+        ));
+        assert!(matches!(
+            handler.handle_sismember(&[&key, &b("a")]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(matches!(
+            handler.handle_scard(&[&key]).await,
+            RespValue::Integer(2)
+        ));
+
+        match handler.handle_smembers(&[&key]).await {
+            RespValue::Array(Some(items)) => assert_eq!(items.len(), 2),
+            other => panic!("Expected Array, got {:?}", other),
+        }
+        handler.set_protocol_version(ProtocolVersion::Resp3);
+        match handler.handle_smembers(&[&key]).await {
+            value @ RespValue::Set(_) => assert_eq!(members(value), ["a", "b"]),
+            other => panic!("Expected Set, got {:?}", other),
+        }
+
+        assert!(matches!(
+            handler
+                .handle_srem(&[&key, &b("a"), &b("b"), &b("c")])
+                .await,
+            RespValue::Integer(2)
+        ));
+        // Removing the last member removes the key
+        assert!(!handler.storage.exists(&key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_set_algebra_and_store() {
+        let handler = create_handler();
+        sadd(&handler, "a", &["1", "2", "3"]).await;
+        sadd(&handler, "b", &["2", "3", "4"]).await;
+
+        let (a, bk, missing) = (b("a"), b("b"), b("missing"));
+        assert_eq!(
+            members(
+                handler
+                    .handle_set_op(&[&a, &bk], "sinter", SetOp::Inter)
+                    .await
+            ),
+            ["2", "3"]
+        );
+        assert_eq!(
+            members(
+                handler
+                    .handle_set_op(&[&a, &bk], "sunion", SetOp::Union)
+                    .await
+            ),
+            ["1", "2", "3", "4"]
+        );
+        assert_eq!(
+            members(
+                handler
+                    .handle_set_op(&[&a, &bk], "sdiff", SetOp::Diff)
+                    .await
+            ),
+            ["1"]
+        );
+        assert!(members(
+            handler
+                .handle_set_op(&[&a, &missing], "sinter", SetOp::Inter)
+                .await
+        )
+        .is_empty());
+
+        // The destination can also be one of the sources
+        assert!(matches!(
+            handler
+                .handle_set_op_store(&[&a, &a, &bk], "sunionstore", SetOp::Union)
+                .await,
+            RespValue::Integer(4)
+        ));
+        assert_eq!(
+            members(handler.handle_smembers(&[&a]).await),
+            ["1", "2", "3", "4"]
+        );
+
+        // An empty result deletes the destination
+        assert!(matches!(
+            handler
+                .handle_set_op_store(&[&a, &bk, &missing], "sinterstore", SetOp::Inter)
+                .await,
+            RespValue::Integer(0)
+        ));
+        assert!(!handler.storage.exists(&a).await.unwrap());
+
+        assert!(matches!(
+            handler
+                .handle_sintercard(&[&b("1"), &bk, &b("LIMIT"), &b("2")])
+                .await,
+            RespValue::Integer(2)
+        ));
+        assert!(matches!(
+            handler.handle_sintercard(&[&b("3"), &bk]).await,
+            RespValue::Error(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_smove_spop_srandmember() {
+        let handler = create_handler();
+        sadd(&handler, "src", &["x", "y"]).await;
+        let (src, dst) = (b("src"), b("dst"));
+
+        assert!(matches!(
+            handler.handle_smove(&[&src, &dst, &b("x")]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(matches!(
+            handler.handle_smove(&[&src, &dst, &b("x")]).await,
+            RespValue::Integer(0)
+        ));
+        assert_eq!(members(handler.handle_smembers(&[&dst]).await), ["x"]);
+
+        handler.storage.set(b"str", b"value").await.unwrap();
+        assert!(matches!(
+            handler.handle_smove(&[&src, &b("str"), &b("y")]).await,
+            RespValue::Error(msg) if msg.starts_with("WRONGTYPE")
+        ));
+
+        match handler.handle_srandmember(&[&src, &b("-3")]).await {
+            value @ RespValue::Array(_) => assert_eq!(members(value), ["y", "y", "y"]),
+            other => panic!("Expected Array, got {:?}", other),
+        }
+        for count in ["-9223372036854775808", "-100000000000"] {
+            assert!(matches!(
+                handler.handle_srandmember(&[&src, &b(count)]).await,
+                RespValue::Error(msg) if msg == "ERR value is out of range"
+            ));
+        }
+        match handler.handle_spop(&[&src]).await {
+            RespValue::BulkString(Some(m)) => assert_eq!(m, "y"),
+            other => panic!("Expected BulkString, got {:?}", other),
+        }
+        assert!(!handler.storage.exists(&src).await.unwrap());
+        assert!(members(handler.handle_spop(&[&src, &b("2")]).await).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sscan() {
+        let handler = create_handler();
+        let items: Vec<String> = (0..25).map(|i| format!("m{}", i)).collect();
+        let items: Vec<&str> = items.iter().map(String::as_str).collect();
+        sadd(&handler, "s", &items).await;
+
+        let key = b("s");
+        let mut cursor = b("0");
+        let mut seen = Vec::new();
+        loop {
+            match handler
+                .handle_sscan(&[&key, &cursor, &b("COUNT"), &b("10")])
+                .await
+            {
+                RespValue::Array(Some(mut reply)) => {
+                    seen.extend(members(reply.pop().unwrap()));
+                    match reply.pop().unwrap() {
+                        RespValue::BulkString(Some(next)) => cursor = next,
+                        other => panic!("Expected cursor, got {:?}", other),
+                    }
+                }
+                other => panic!("Expected Array, got {:?}", other),
+            }
+            if cursor == "0" {
+                break;
+            }
+        }
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 25);
+    }
+}
@@ -0,0 +1,1788 @@
+//! Stream commands (XADD, XRANGE, XREAD, XREADGROUP, XPENDING, XCLAIM, ...).
+
+use super::{parse_int, parse_utf8, storage_error, syntax_error, wrong_args, Handler};
+use crate::protocol::RespValue;
+use crate::server::notify::NOTIFY_STREAM;
+use crate::storage::stream::{ConsumerGroup, PendingEntry, StreamFields, StreamTrim};
+use crate::storage::{StorageError, Stream, StreamId, ValueSlot};
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const INVALID_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+const KEY_REQUIRED: &str = "ERR The XGROUP subcommand requires the key to exist. \
+     Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.";
+
+type StreamEntries = BTreeMap<StreamId, StreamFields>;
+
+/// An entry in a reply; fields are `None` once the entry was deleted.
+type EntryReply = (StreamId, Option<StreamFields>);
+
+/// Entries read from one stream.
+type StreamRead = (Bytes, Vec<EntryReply>);
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+fn parse_id(arg: &[u8], default_seq: u64) -> Result<StreamId, RespValue> {
+    StreamId::parse(arg, default_seq).ok_or_else(|| RespValue::Error(INVALID_ID.to_string()))
+}
+
+/// Parse the start of an XRANGE interval: `-`, an ID or an exclusive `(ID`.
+fn parse_range_start(arg: &[u8]) -> Result<StreamId, RespValue> {
+    match arg {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        _ => match arg.strip_prefix(b"(") {
+            Some(id) => parse_id(id, 0)?.next().ok_or_else(|| {
+                RespValue::Error("ERR invalid start ID for the interval".to_string())
+            }),
+            None => parse_id(arg, 0),
+        },
+    }
+}
+
+/// Parse the end of an XRANGE interval: `+`, an ID or an exclusive `(ID`.
+fn parse_range_end(arg: &[u8]) -> Result<StreamId, RespValue> {
+    match arg {
+        b"+" => Ok(StreamId::MAX),
+        b"-" => Ok(StreamId::MIN),
+        _ => match arg.strip_prefix(b"(") {
+            Some(id) => parse_id(id, u64::MAX)?
+                .prev()
+                .ok_or_else(|| RespValue::Error("ERR invalid end ID for the interval".to_string())),
+            None => parse_id(arg, u64::MAX),
+        },
+    }
+}
+
+/// Parse `MAXLEN|MINID [=|~] threshold`, returning the strategy, whether it
+/// is approximate and the number of arguments consumed.
+fn parse_trim(args: &[&Bytes]) -> Result<(StreamTrim, bool, usize), RespValue> {
+    let mut used = 1;
+    let approximate = match args.get(1).map(|arg| arg.as_ref()) {
+        Some(b"~") => true,
+        Some(b"=") => false,
+        _ => {
+            used -= 1;
+            false
+        }
+    };
+    used += 1;
+    let threshold = args.get(used).ok_or_else(syntax_error)?;
+
+    let trim = if args[0].eq_ignore_ascii_case(b"MAXLEN") {
+        let max_len = parse_int(threshold)?;
+        if max_len < 0 {
+            return Err(RespValue::Error(
+                "ERR The MAXLEN argument must be >= 0.".to_string(),
+            ));
+        }
+        StreamTrim::MaxLen(max_len as u64)
+    } else {
+        StreamTrim::MinId(parse_id(threshold, 0)?)
+    };
+    Ok((trim, approximate, used + 1))
+}
+
+/// Validate a LIMIT given with trimming. Returns the cap, `None` if unlimited.
+fn trim_limit(limit: Option<i64>, approximate: bool) -> Result<Option<usize>, RespValue> {
+    match limit {
+        None | Some(0) => Ok(None),
+        Some(limit) if limit < 0 => Err(RespValue::Error(
+            "ERR The LIMIT argument must be >= 0.".to_string(),
+        )),
+        Some(_) if !approximate => Err(RespValue::Error(
+            "ERR syntax error, LIMIT cannot be used without the special ~ option".to_string(),
+        )),
+        Some(limit) => Ok(Some(limit as usize)),
+    }
+}
+
+/// ID argument of XADD.
+#[derive(Debug, Clone, Copy)]
+enum XAddId {
+    /// `*`
+    Auto,
+    /// `ms-*`
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl XAddId {
+    fn parse(arg: &[u8]) -> Result<Self, RespValue> {
+        if arg == b"*" {
+            return Ok(XAddId::Auto);
+        }
+        match arg.strip_suffix(b"-*") {
+            Some(ms) => parse_utf8::<u64>(ms)
+                .map(XAddId::AutoSeq)
+                .ok_or_else(|| RespValue::Error(INVALID_ID.to_string())),
+            None => parse_id(arg, 0).map(XAddId::Explicit),
+        }
+    }
+
+    /// Resolve to a concrete ID greater than `last`.
+    fn resolve(self, last: StreamId, now_ms: u64) -> Result<StreamId, &'static str> {
+        const TOO_SMALL: &str =
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item";
+        match self {
+            XAddId::Auto if now_ms > last.ms => Ok(StreamId::new(now_ms, 0)),
+            XAddId::Auto => last.next().ok_or(
+                "ERR The stream has exhausted the last possible ID, unable to add more items",
+            ),
+            XAddId::AutoSeq(ms) if ms > last.ms => Ok(StreamId::new(ms, 0)),
+            XAddId::AutoSeq(ms) if ms == last.ms => last
+                .seq
+                .checked_add(1)
+                .map(|seq| StreamId::new(ms, seq))
+                .ok_or(TOO_SMALL),
+            XAddId::AutoSeq(_) => Err(TOO_SMALL),
+            XAddId::Explicit(StreamId::MIN) => {
+                Err("ERR The ID specified in XADD must be greater than 0-0")
+            }
+            XAddId::Explicit(id) if id <= last => Err(TOO_SMALL),
+            XAddId::Explicit(id) => Ok(id),
+        }
+    }
+}
+
+/// Parsed options of XREAD and XREADGROUP.
+struct ReadArgs<'a> {
+    group: Option<(&'a Bytes, &'a Bytes)>,
+    count: usize,
+    /// `None` without BLOCK; `Some(None)` blocks forever.
+    block: Option<Option<Duration>>,
+    noack: bool,
+    keys: &'a [&'a Bytes],
+    ids: &'a [&'a Bytes],
+}
+
+impl<'a> ReadArgs<'a> {
+    fn parse(args: &'a [&'a Bytes], command: &str, with_group: bool) -> Result<Self, RespValue> {
+        let mut read = ReadArgs {
+            group: None,
+            count: usize::MAX,
+            block: None,
+            noack: false,
+            keys: &[],
+            ids: &[],
+        };
+
+        let mut i = 0;
+        loop {
+            let Some(option) = args.get(i) else {
+                return Err(syntax_error());
+            };
+            let has_value = i + 1 < args.len();
+            if option.eq_ignore_ascii_case(b"STREAMS") {
+                i += 1;
+                break;
+            } else if option.eq_ignore_ascii_case(b"COUNT") && has_value {
+                let count = parse_int(args[i + 1])?;
+                if count > 0 {
+                    read.count = count as usize;
+                }
+                i += 2;
+            } else if option.eq_ignore_ascii_case(b"BLOCK") && has_value {
+                let timeout = parse_int(args[i + 1])?;
+                if timeout < 0 {
+                    return Err(RespValue::Error("ERR timeout is negative".to_string()));
+                }
+                read.block = Some((timeout > 0).then(|| Duration::from_millis(timeout as u64)));
+                i += 2;
+            } else if with_group && option.eq_ignore_ascii_case(b"GROUP") && i + 2 < args.len() {
+                read.group = Some((args[i + 1], args[i + 2]));
+                i += 3;
+            } else if with_group && option.eq_ignore_ascii_case(b"NOACK") {
+                read.noack = true;
+                i += 1;
+            } else {
+                return Err(syntax_error());
+            }
+        }
+
+        let streams = &args[i..];
+        if streams.is_empty() || !streams.len().is_multiple_of(2) {
+            return Err(RespValue::Error(format!(
+                "ERR Unbalanced '{}' list of streams: for each stream key an ID or '{}' must be specified.",
+                command,
+                if with_group { ">" } else { "$" }
+            )));
+        }
+        if with_group && read.group.is_none() {
+            return Err(RespValue::Error(
+                "ERR Missing GROUP option for XREADGROUP".to_string(),
+            ));
+        }
+        (read.keys, read.ids) = streams.split_at(streams.len() / 2);
+        Ok(read)
+    }
+}
+
+/// Where XREADGROUP reads from: new entries (`>`) or the consumer's history.
+#[derive(Debug, Clone, Copy)]
+enum GroupRead {
+    New,
+    History(StreamId),
+}
+
+fn no_group(key: &[u8], group: &[u8]) -> String {
+    format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    )
+}
+
+/// Mutable consumer group at `slot`, `None` if the key or group is missing.
+/// Leaves the slot untouched when returning `None`.
+fn group_mut<'a>(
+    slot: &'a mut ValueSlot,
+    group: &[u8],
+) -> Result<Option<(&'a mut ConsumerGroup, &'a StreamEntries)>, StorageError> {
+    if !slot
+        .stream()?
+        .is_some_and(|stream| stream.groups.contains_key(group))
+    {
+        return Ok(None);
+    }
+    let Stream {
+        entries, groups, ..
+    } = slot.stream_mut()?;
+    Ok(groups.get_mut(group).map(|group| (group, &*entries)))
+}
+
+/// Deliver entries to a consumer for XREADGROUP. Returns `None` if the
+/// group does not exist.
+fn read_group(
+    slot: &mut ValueSlot,
+    group_name: &[u8],
+    consumer: &Bytes,
+    read: GroupRead,
+    count: usize,
+    noack: bool,
+) -> Result<Option<Vec<EntryReply>>, StorageError> {
+    let now = now_ms();
+    let Some(stream) = slot.stream()? else {
+        return Ok(None);
+    };
+    let Some(group) = stream.groups.get(group_name) else {
+        return Ok(None);
+    };
+
+    let GroupRead::History(after) = read else {
+        let delivered: Vec<EntryReply> = stream
+            .entries
+            .range((Bound::Excluded(group.last_delivered_id), Bound::Unbounded))
+            .take(count)
+            .map(|(id, fields)| (*id, Some(fields.clone())))
+            .collect();
+        let last = delivered.last().map(|(id, _)| *id);
+        let entries_read = last.map(|last| {
+            stream
+                .entries_read_at(last)
+                .or_else(|| group.entries_read.map(|read| read + delivered.len() as u64))
+        });
+
+        let Some((group, _)) = group_mut(slot, group_name)? else {
+            return Ok(None);
+        };
+        let member = group.touch_consumer(consumer, now);
+        if let (Some(last), Some(entries_read)) = (last, entries_read) {
+            member.active_ms = Some(now);
+            group.last_delivered_id = last;
+            group.entries_read = entries_read;
+        }
+        if !noack {
+            for (id, _) in &delivered {
+                group.pending.insert(
+                    *id,
+                    PendingEntry {
+                        consumer: consumer.clone(),
+                        delivered_ms: now,
+                        delivery_count: 1,
+                    },
+                );
+            }
+        }
+        return Ok(Some(delivered));
+    };
+
+    let history = group
+        .pending
+        .range((Bound::Excluded(after), Bound::Unbounded))
+        .filter(|(_, pending)| pending.consumer == consumer)
+        .take(count)
+        .map(|(id, _)| (*id, stream.entries.get(id).cloned()))
+        .collect();
+    if let Some((group, _)) = group_mut(slot, group_name)? {
+        group.touch_consumer(consumer, now);
+    }
+    Ok(Some(history))
+}
+
+/// Parsed XCLAIM/XAUTOCLAIM claim settings.
+struct Claim<'a> {
+    consumer: &'a Bytes,
+    min_idle: u64,
+    delivered_ms: u64,
+    retry_count: Option<u64>,
+    justid: bool,
+}
+
+impl Claim<'_> {
+    /// Claim a pending entry if it has been idle long enough. Entries
+    /// deleted from the stream are dropped from the PEL instead.
+    fn apply(
+        &self,
+        group: &mut ConsumerGroup,
+        entries: &StreamEntries,
+        id: StreamId,
+        now: u64,
+    ) -> ClaimOutcome {
+        let Some(pending) = group.pending.get_mut(&id) else {
+            return ClaimOutcome::Skipped;
+        };
+        if self.min_idle > 0 && now.saturating_sub(pending.delivered_ms) < self.min_idle {
+            return ClaimOutcome::Skipped;
+        }
+        let Some(fields) = entries.get(&id) else {
+            group.pending.remove(&id);
+            return ClaimOutcome::Deleted;
+        };
+
+        pending.consumer = self.consumer.clone();
+        pending.delivered_ms = self.delivered_ms;
+        match self.retry_count {
+            Some(count) => pending.delivery_count = count,
+            None if !self.justid => pending.delivery_count += 1,
+            None => {}
+        }
+        ClaimOutcome::Claimed(fields.clone())
+    }
+}
+
+enum ClaimOutcome {
+    Claimed(StreamFields),
+    Deleted,
+    Skipped,
+}
+
+fn parse_min_idle(arg: &[u8], command: &str) -> Result<u64, RespValue> {
+    parse_utf8::<i64>(arg)
+        .map(|idle| idle.max(0) as u64)
+        .ok_or_else(|| {
+            RespValue::Error(format!(
+                "ERR Invalid min-idle-time argument for {}",
+                command.to_ascii_uppercase()
+            ))
+        })
+}
+
+impl Handler {
+    /// Reply with one entry: `[id, [field, value, ...]]`.
+    fn entry_reply(&self, id: StreamId, fields: Option<StreamFields>) -> RespValue {
+        let fields = match fields {
+            Some(fields) => RespValue::Array(Some(
+                fields
+                    .into_iter()
+                    .flat_map(|(f, v)| {
+                        [
+                            RespValue::BulkString(Some(f)),
+                            RespValue::BulkString(Some(v)),
+                        ]
+                    })
+                    .collect(),
+            )),
+            None => self.null_array(),
+        };
+        RespValue::Array(Some(vec![
+            RespValue::BulkString(Some(id.to_string().into())),
+            fields,
+        ]))
+    }
+
+    fn entries_reply(&self, entries: Vec<EntryReply>) -> RespValue {
+        RespValue::Array(Some(
+            entries
+                .into_iter()
+                .map(|(id, fields)| self.entry_reply(id, fields))
+                .collect(),
+        ))
+    }
+
+    /// Reply to XREAD/XREADGROUP: a map of key to entries under RESP3, an
+    /// array of `[key, entries]` pairs under RESP2.
+    fn streams_reply(&self, streams: Vec<StreamRead>) -> RespValue {
+        let pairs: Vec<(RespValue, RespValue)> = streams
+            .into_iter()
+            .map(|(key, entries)| {
+                (
+                    RespValue::BulkString(Some(key)),
+                    self.entries_reply(entries),
+                )
+            })
+            .collect();
+        match self.protocol_version {
+            crate::protocol::ProtocolVersion::Resp3 => RespValue::Map(pairs),
+            crate::protocol::ProtocolVersion::Resp2 => RespValue::Array(Some(
+                pairs
+                    .into_iter()
+                    .map(|(key, entries)| RespValue::Array(Some(vec![key, entries])))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] *|id field value [field value ...]
+    pub(super) async fn handle_xadd(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 4 {
+            return wrong_args("xadd");
+        }
+
+        let mut nomkstream = false;
+        let mut trim = None;
+        let mut limit = None;
+        let mut i = 1;
+        while i < args.len() {
+            let option = args[i];
+            if option.eq_ignore_ascii_case(b"NOMKSTREAM") {
+                nomkstream = true;
+                i += 1;
+            } else if option.eq_ignore_ascii_case(b"MAXLEN")
+                || option.eq_ignore_ascii_case(b"MINID")
+            {
+                match parse_trim(&args[i..]) {
+                    Ok((strategy, approximate, used)) => {
+                        trim = Some((strategy, approximate));
+                        i += used;
+                    }
+                    Err(e) => return e,
+                }
+            } else if option.eq_ignore_ascii_case(b"LIMIT") && i + 1 < args.len() {
+                match parse_int(args[i + 1]) {
+                    Ok(value) => limit = Some(value),
+                    Err(e) => return e,
+                }
+                i += 2;
+            } else {
+                break;
+            }
+        }
+
+        let trim = match trim {
+            Some((strategy, approximate)) => match trim_limit(limit, approximate) {
+                Ok(limit) => Some((strategy, limit)),
+                Err(e) => return e,
+            },
+            None if limit.is_some() => return syntax_error(),
+            None => None,
+        };
+        let Some(id_arg) = args.get(i) else {
+            return wrong_args("xadd");
+        };
+        let id = match XAddId::parse(id_arg) {
+            Ok(id) => id,
+            Err(e) => return e,
+        };
+        let pairs = &args[i + 1..];
+        if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+            return wrong_args("xadd");
+        }
+        let fields: StreamFields = pairs
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let last = match slot.stream()? {
+                    Some(stream) => stream.last_id,
+                    None if nomkstream => return Ok(Ok(None)),
+                    None => StreamId::MIN,
+                };
+                let id = match id.resolve(last, now_ms()) {
+                    Ok(id) => id,
+                    Err(msg) => return Ok(Err(msg)),
+                };
+                let stream = slot.stream_mut()?;
+                stream.append(id, fields.clone());
+                let trimmed = match trim {
+                    Some((strategy, limit)) => stream.trim(strategy, limit),
+                    None => 0,
+                };
+                Ok(Ok(Some((id, trimmed))))
+            })
+            .await;
+
+        match result {
+            Ok(Ok(Some((id, trimmed)))) => {
+                self.notify(NOTIFY_STREAM, "xadd", args[0]);
+                if trimmed > 0 {
+                    self.notify(NOTIFY_STREAM, "xtrim", args[0]);
+                }
+                self.context.blocking.signal_all(args[0]);
+                RespValue::BulkString(Some(id.to_string().into()))
+            }
+            Ok(Ok(None)) => RespValue::BulkString(None),
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("xadd", e),
+        }
+    }
+
+    /// XRANGE key start end [COUNT count] / XREVRANGE key end start [COUNT count]
+    pub(super) async fn handle_xrange(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        rev: bool,
+    ) -> RespValue {
+        if args.len() != 3 && args.len() != 5 {
+            return wrong_args(command);
+        }
+        let (start, end) = if rev {
+            (args[2], args[1])
+        } else {
+            (args[1], args[2])
+        };
+        let (start, end) = match (parse_range_start(start), parse_range_end(end)) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
+        let count = match args.get(3..5) {
+            Some([option, count]) if option.eq_ignore_ascii_case(b"COUNT") => {
+                match parse_int(count) {
+                    Ok(count) => count.max(0) as usize,
+                    Err(e) => return e,
+                }
+            }
+            Some(_) => return syntax_error(),
+            None => usize::MAX,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(stream) = value.map(|v| v.as_stream()).transpose()? else {
+                    return Ok(Vec::new());
+                };
+                if start > end {
+                    return Ok(Vec::new());
+                }
+                let range = stream.entries.range(start..=end);
+                let entries =
+                    |(id, fields): (&StreamId, &StreamFields)| (*id, Some(fields.clone()));
+                Ok(if rev {
+                    range.rev().take(count).map(entries).collect()
+                } else {
+                    range.take(count).map(entries).collect()
+                })
+            })
+            .await;
+
+        match result {
+            Ok(entries) => self.entries_reply(entries),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// XLEN key
+    pub(super) async fn handle_xlen(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("xlen");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_stream()?.len(),
+                    None => 0,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(len) => RespValue::Integer(len as i64),
+            Err(e) => storage_error("xlen", e),
+        }
+    }
+
+    /// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+    pub(super) async fn handle_xread(&self, args: &[&Bytes]) -> RespValue {
+        let read = match ReadArgs::parse(args, "xread", false) {
+            Ok(read) => read,
+            Err(e) => return e,
+        };
+
+        // `$` means "entries added from now on", so resolve it before blocking
+        let mut after = Vec::with_capacity(read.keys.len());
+        for (key, id) in read.keys.iter().zip(read.ids) {
+            let id = match id.as_ref() {
+                b"$" => {
+                    let last = self
+                        .storage
+                        .inspect(key, |value| {
+                            Ok(match value {
+                                Some(value) => value.as_stream()?.last_id,
+                                None => StreamId::MIN,
+                            })
+                        })
+                        .await;
+                    match last {
+                        Ok(last) => last,
+                        Err(e) => return storage_error("xread", e),
+                    }
+                }
+                b">" => {
+                    return RespValue::Error(
+                        "ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option."
+                            .to_string(),
+                    )
+                }
+                id => match parse_id(id, 0) {
+                    Ok(id) => id,
+                    Err(e) => return e,
+                },
+            };
+            after.push(id);
+        }
+
+        let attempt = || self.read_streams(read.keys, &after, read.count);
+        let result = match read.block {
+            Some(timeout) => self.block_on(read.keys, timeout, attempt).await,
+            None => self.isolated(attempt()).await,
+        };
+        match result {
+            Ok(Some(streams)) => self.streams_reply(streams),
+            Ok(None) => self.null_array(),
+            Err(e) => storage_error("xread", e),
+        }
+    }
+
+    /// Entries after the given IDs, `None` if no stream has any.
+    async fn read_streams(
+        &self,
+        keys: &[&Bytes],
+        after: &[StreamId],
+        count: usize,
+    ) -> Result<Option<Vec<StreamRead>>, StorageError> {
+        let mut streams = Vec::new();
+        for (key, after) in keys.iter().zip(after) {
+            let entries: Vec<EntryReply> = self
+                .storage
+                .inspect(key, |value| {
+                    let Some(stream) = value.map(|v| v.as_stream()).transpose()? else {
+                        return Ok(Vec::new());
+                    };
+                    Ok(stream
+                        .entries
+                        .range((Bound::Excluded(*after), Bound::Unbounded))
+                        .take(count)
+                        .map(|(id, fields)| (*id, Some(fields.clone())))
+                        .collect())
+                })
+                .await?;
+            if !entries.is_empty() {
+                streams.push(((*key).clone(), entries));
+            }
+        }
+        Ok((!streams.is_empty()).then_some(streams))
+    }
+
+    /// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK]
+    /// STREAMS key [key ...] id [id ...]
+    pub(super) async fn handle_xreadgroup(&self, args: &[&Bytes]) -> RespValue {
+        let read = match ReadArgs::parse(args, "xreadgroup", true) {
+            Ok(read) => read,
+            Err(e) => return e,
+        };
+        let Some((group, consumer)) = read.group else {
+            return syntax_error();
+        };
+
+        let mut requests = Vec::with_capacity(read.ids.len());
+        for id in read.ids {
+            requests.push(match id.as_ref() {
+                b">" => GroupRead::New,
+                b"$" => {
+                    return RespValue::Error(
+                        "ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set."
+                            .to_string(),
+                    )
+                }
+                id => match parse_id(id, 0) {
+                    Ok(id) => GroupRead::History(id),
+                    Err(e) => return e,
+                },
+            });
+        }
+
+        // Reading history never blocks
+        let attempt = || async {
+            let mut streams = Vec::new();
+            let mut history = false;
+            for (key, request) in read.keys.iter().zip(&requests) {
+                let entries = self
+                    .storage
+                    .modify(key, |slot| {
+                        read_group(slot, group, consumer, *request, read.count, read.noack)
+                    })
+                    .await?;
+                let Some(entries) = entries else {
+                    return Ok(Some(Err(format!(
+                        "{} in XREADGROUP with GROUP option",
+                        no_group(key, group)
+                    ))));
+                };
+                history |= matches!(request, GroupRead::History(_));
+                if !entries.is_empty() || matches!(request, GroupRead::History(_)) {
+                    streams.push(((*key).clone(), entries));
+                }
+            }
+            Ok((history || !streams.is_empty()).then_some(Ok(streams)))
+        };
+
+        let result = match read.block {
+            Some(timeout) => self.block_on(read.keys, timeout, attempt).await,
+            None => self.isolated(attempt()).await,
+        };
+        match result {
+            Ok(Some(Ok(streams))) => self.streams_reply(streams),
+            Ok(Some(Err(msg))) => RespValue::Error(msg),
+            Ok(None) => self.null_array(),
+            Err(e) => storage_error("xreadgroup", e),
+        }
+    }
+
+    /// XACK key group id [id ...]
+    pub(super) async fn handle_xack(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 3 {
+            return wrong_args("xack");
+        }
+        let ids = match args[2..]
+            .iter()
+            .map(|id| parse_id(id, 0))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(ids) => ids,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some((group, _)) = group_mut(slot, args[1])? else {
+                    return Ok(0);
+                };
+                Ok(ids
+                    .iter()
+                    .filter(|id| group.pending.remove(id).is_some())
+                    .count())
+            })
+            .await;
+
+        match result {
+            Ok(acked) => RespValue::Integer(acked as i64),
+            Err(e) => storage_error("xack", e),
+        }
+    }
+
+    /// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+    pub(super) async fn handle_xpending(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("xpending");
+        }
+
+        // Extended form: optional IDLE, then start end count [consumer]
+        let mut extended = None;
+        if args.len() > 2 {
+            let mut rest = &args[2..];
+            let mut min_idle = 0;
+            if rest[0].eq_ignore_ascii_case(b"IDLE") && rest.len() > 1 {
+                match parse_int(rest[1]) {
+                    Ok(idle) => min_idle = idle.max(0) as u64,
+                    Err(e) => return e,
+                }
+                rest = &rest[2..];
+            }
+            if rest.len() != 3 && rest.len() != 4 {
+                return syntax_error();
+            }
+            let (start, end) = match (parse_range_start(rest[0]), parse_range_end(rest[1])) {
+                (Ok(start), Ok(end)) => (start, end),
+                (Err(e), _) | (_, Err(e)) => return e,
+            };
+            let count = match parse_int(rest[2]) {
+                Ok(count) => count.max(0) as usize,
+                Err(e) => return e,
+            };
+            extended = Some((min_idle, start, end, count, rest.get(3).copied()));
+        }
+
+        let now = now_ms();
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(stream) = value.map(|v| v.as_stream()).transpose()? else {
+                    return Ok(None);
+                };
+                let Some(group) = stream.groups.get(args[1].as_ref()) else {
+                    return Ok(None);
+                };
+
+                let Some((min_idle, start, end, count, consumer)) = extended else {
+                    return Ok(Some(self.pending_summary(group)));
+                };
+                if start > end {
+                    return Ok(Some(RespValue::Array(Some(Vec::new()))));
+                }
+                let entries = group
+                    .pending
+                    .range(start..=end)
+                    .filter(|(_, pending)| consumer.is_none_or(|c| pending.consumer == c))
+                    .filter(|(_, pending)| now.saturating_sub(pending.delivered_ms) >= min_idle)
+                    .take(count)
+                    .map(|(id, pending)| {
+                        RespValue::Array(Some(vec![
+                            RespValue::BulkString(Some(id.to_string().into())),
+                            RespValue::BulkString(Some(pending.consumer.clone())),
+                            RespValue::Integer(now.saturating_sub(pending.delivered_ms) as i64),
+                            RespValue::Integer(pending.delivery_count as i64),
+                        ]))
+                    })
+                    .collect();
+                Ok(Some(RespValue::Array(Some(entries))))
+            })
+            .await;
+
+        match result {
+            Ok(Some(reply)) => reply,
+            Ok(None) => RespValue::Error(no_group(args[0], args[1])),
+            Err(e) => storage_error("xpending", e),
+        }
+    }
+
+    /// Summary form of XPENDING: count, smallest and largest ID, and the
+    /// number of pending entries per consumer.
+    fn pending_summary(&self, group: &ConsumerGroup) -> RespValue {
+        let (Some((first, _)), Some((last, _))) = (
+            group.pending.first_key_value(),
+            group.pending.last_key_value(),
+        ) else {
+            return RespValue::Array(Some(vec![
+                RespValue::Integer(0),
+                RespValue::BulkString(None),
+                RespValue::BulkString(None),
+                self.null_array(),
+            ]));
+        };
+
+        let mut per_consumer: BTreeMap<&Bytes, usize> = Default::default();
+        for pending in group.pending.values() {
+            *per_consumer.entry(&pending.consumer).or_default() += 1;
+        }
+        RespValue::Array(Some(vec![
+            RespValue::Integer(group.pending.len() as i64),
+            RespValue::BulkString(Some(first.to_string().into())),
+            RespValue::BulkString(Some(last.to_string().into())),
+            RespValue::Array(Some(
+                per_consumer
+                    .into_iter()
+                    .map(|(consumer, count)| {
+                        RespValue::Array(Some(vec![
+                            RespValue::BulkString(Some(consumer.clone())),
+                            RespValue::BulkString(Some(count.to_string().into())),
+                        ]))
+                    })
+                    .collect(),
+            )),
+        ]))
+    }
+
+    /// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms]
+    /// [TIME unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]
+    pub(super) async fn handle_xclaim(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 5 {
+            return wrong_args("xclaim");
+        }
+        let min_idle = match parse_min_idle(args[3], "xclaim") {
+            Ok(min_idle) => min_idle,
+            Err(e) => return e,
+        };
+
+        // IDs run until the first argument that is not an ID
+        let ids: Vec<StreamId> = args[4..]
+            .iter()
+            .map_while(|arg| StreamId::parse(arg, 0))
+            .collect();
+        if ids.is_empty() {
+            return RespValue::Error(INVALID_ID.to_string());
+        }
+
+        let now = now_ms();
+        let mut claim = Claim {
+            consumer: args[2],
+            min_idle,
+            delivered_ms: now,
+            retry_count: None,
+            justid: false,
+        };
+        let mut force = false;
+        let mut last_id = None;
+        let mut i = 4 + ids.len();
+        while i < args.len() {
+            let option = args[i];
+            let value = args.get(i + 1);
+            let int_value = || value.map_or(Err(syntax_error()), |v| parse_int(v));
+            if option.eq_ignore_ascii_case(b"FORCE") {
+                force = true;
+            } else if option.eq_ignore_ascii_case(b"JUSTID") {
+                claim.justid = true;
+            } else if option.eq_ignore_ascii_case(b"IDLE") {
+                match int_value() {
+                    Ok(idle) => claim.delivered_ms = now.saturating_sub(idle.max(0) as u64),
+                    Err(e) => return e,
+                }
+                i += 1;
+            } else if option.eq_ignore_ascii_case(b"TIME") {
+                match int_value() {
+                    Ok(time) => claim.delivered_ms = (time.max(0) as u64).min(now),
+                    Err(e) => return e,
+                }
+                i += 1;
+            } else if option.eq_ignore_ascii_case(b"RETRYCOUNT") {
+                match int_value() {
+                    Ok(count) => claim.retry_count = Some(count.max(0) as u64),
+                    Err(e) => return e,
+                }
+                i += 1;
+            } else if option.eq_ignore_ascii_case(b"LASTID") {
+                match value.map_or(Err(syntax_error()), |v| parse_id(v, 0)) {
+                    Ok(id) => last_id = Some(id),
+                    Err(e) => return e,
+                }
+                i += 1;
+            } else {
+                return RespValue::Error(format!(
+                    "ERR Unrecognized XCLAIM option '{}'",
+                    String::from_utf8_lossy(option)
+                ));
+            }
+            i += 1;
+        }
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some((group, entries)) = group_mut(slot, args[1])? else {
+                    return Ok(None);
+                };
+                let mut claimed = Vec::new();
+                for &id in &ids {
+                    if force && !group.pending.contains_key(&id) && entries.contains_key(&id) {
+                        group.pending.insert(
+                            id,
+                            PendingEntry {
+                                consumer: args[2].clone(),
+                                delivered_ms: now,
+                                delivery_count: 0,
+                            },
+                        );
+                    }
+                    if let ClaimOutcome::Claimed(fields) = claim.apply(group, entries, id, now) {
+                        claimed.push((id, fields));
+                    }
+                }
+                if let Some(last_id) = last_id {
+                    group.last_delivered_id = group.last_delivered_id.max(last_id);
+                }
+                let consumer = group.touch_consumer(args[2], now);
+                if !claimed.is_empty() {
+                    consumer.active_ms = Some(now);
+                }
+                Ok(Some(claimed))
+            })
+            .await;
+
+        match result {
+            Ok(Some(claimed)) => self.claimed_reply(claimed, claim.justid),
+            Ok(None) => RespValue::Error(no_group(args[0], args[1])),
+            Err(e) => storage_error("xclaim", e),
+        }
+    }
+
+    fn claimed_reply(&self, claimed: Vec<(StreamId, StreamFields)>, justid: bool) -> RespValue {
+        RespValue::Array(Some(
+            claimed
+                .into_iter()
+                .map(|(id, fields)| match justid {
+                    true => RespValue::BulkString(Some(id.to_string().into())),
+                    false => self.entry_reply(id, Some(fields)),
+                })
+                .collect(),
+        ))
+    }
+
+    /// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+    pub(super) async fn handle_xautoclaim(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 5 {
+            return wrong_args("xautoclaim");
+        }
+        let min_idle = match parse_min_idle(args[3], "xautoclaim") {
+            Ok(min_idle) => min_idle,
+            Err(e) => return e,
+        };
+        let start = match parse_range_start(args[4]) {
+            Ok(start) => start,
+            Err(e) => return e,
+        };
+
+        let mut count = 100;
+        let mut justid = false;
+        let mut i = 5;
+        while i < args.len() {
+            if args[i].eq_ignore_ascii_case(b"JUSTID") {
+                justid = true;
+            } else if args[i].eq_ignore_ascii_case(b"COUNT") && i + 1 < args.len() {
+                match parse_int(args[i + 1]) {
+                    Ok(value) if value > 0 => count = value as usize,
+                    Ok(_) => return RespValue::Error("ERR COUNT must be > 0".to_string()),
+                    Err(e) => return e,
+                }
+                i += 1;
+            } else {
+                return syntax_error();
+            }
+            i += 1;
+        }
+
+        let now = now_ms();
+        let claim = Claim {
+            consumer: args[2],
+            min_idle,
+            delivered_ms: now,
+            retry_count: None,
+            justid,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some((group, entries)) = group_mut(slot, args[1])? else {
+                    return Ok(None);
+                };
+
+                // Scan a bounded number of PEL entries per call
+                let mut attempts = count.saturating_mul(10);
+                let mut next = StreamId::MIN;
+                let (mut claimed, mut deleted) = (Vec::new(), Vec::new());
+                let candidates: Vec<StreamId> =
+                    group.pending.range(start..).map(|(id, _)| *id).collect();
+                for id in candidates {
+                    if attempts == 0 || claimed.len() == count {
+                        next = id;
+                        break;
+                    }
+                    attempts -= 1;
+                    match claim.apply(group, entries, id, now) {
+                        ClaimOutcome::Claimed(fields) => claimed.push((id, fields)),
+                        ClaimOutcome::Deleted => deleted.push(id),
+                        ClaimOutcome::Skipped => {}
+                    }
+                }
+
+                let consumer = group.touch_consumer(args[2], now);
+                if !claimed.is_empty() {
+                    consumer.active_ms = Some(now);
+                }
+                Ok(Some((next, claimed, deleted)))
+            })
+            .await;
+
+        match result {
+            Ok(Some((next, claimed, deleted))) => RespValue::Array(Some(vec![
+                RespValue::BulkString(Some(next.to_string().into())),
+                self.claimed_reply(claimed, justid),
+                RespValue::Array(Some(
+                    deleted
+                        .into_iter()
+                        .map(|id| RespValue::BulkString(Some(id.to_string().into())))
+                        .collect(),
+                )),
+            ])),
+            Ok(None) => RespValue::Error(no_group(args[0], args[1])),
+            Err(e) => storage_error("xautoclaim", e),
+        }
+    }
+
+    /// XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]
+    pub(super) async fn handle_xtrim(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 3 {
+            return wrong_args("xtrim");
+        }
+        if !args[1].eq_ignore_ascii_case(b"MAXLEN") && !args[1].eq_ignore_ascii_case(b"MINID") {
+            return syntax_error();
+        }
+        let (strategy, approximate, used) = match parse_trim(&args[1..]) {
+            Ok(parsed) => parsed,
+            Err(e) => return e,
+        };
+        let limit = match &args[1 + used..] {
+            [] => None,
+            [option, limit] if option.eq_ignore_ascii_case(b"LIMIT") => match parse_int(limit) {
+                Ok(limit) => Some(limit),
+                Err(e) => return e,
+            },
+            _ => return syntax_error(),
+        };
+        let limit = match trim_limit(limit, approximate) {
+            Ok(limit) => limit,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                if slot.stream()?.is_none() {
+                    return Ok(0);
+                }
+                Ok(slot.stream_mut()?.trim(strategy, limit))
+            })
+            .await;
+
+        match result {
+            Ok(removed) => {
+                if removed > 0 {
+                    self.notify(NOTIFY_STREAM, "xtrim", args[0]);
+                }
+                RespValue::Integer(removed as i64)
+            }
+            Err(e) => storage_error("xtrim", e),
+        }
+    }
+
+    /// XDEL key id [id ...]
+    pub(super) async fn handle_xdel(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("xdel");
+        }
+        let ids = match args[1..]
+            .iter()
+            .map(|id| parse_id(id, 0))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(ids) => ids,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                if slot.stream()?.is_none() {
+                    return Ok(0);
+                }
+                let stream = slot.stream_mut()?;
+                Ok(ids.iter().filter(|id| stream.delete(**id)).count())
+            })
+            .await;
+
+        match result {
+            Ok(deleted) => {
+                if deleted > 0 {
+                    self.notify(NOTIFY_STREAM, "xdel", args[0]);
+                }
+                RespValue::Integer(deleted as i64)
+            }
+            Err(e) => storage_error("xdel", e),
+        }
+    }
+
+    /// XGROUP CREATE|SETID|DESTROY|CREATECONSUMER|DELCONSUMER key group ...
+    pub(super) async fn handle_xgroup(&self, args: &[&Bytes]) -> RespValue {
+        let Some(subcommand) = args.first() else {
+            return wrong_args("xgroup");
+        };
+        let subcommand = String::from_utf8_lossy(subcommand).to_lowercase();
+        let args = &args[1..];
+
+        let arity_ok = match subcommand.as_str() {
+            "create" => (3..=6).contains(&args.len()),
+            "setid" => args.len() == 3 || args.len() == 5,
+            "destroy" => args.len() == 2,
+            "createconsumer" | "delconsumer" => args.len() == 3,
+            _ => {
+                return RespValue::Error(format!(
+                    "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                    subcommand
+                ))
+            }
+        };
+        if !arity_ok {
+            return wrong_args(&format!("xgroup|{}", subcommand));
+        }
+        let key = args[0];
+
+        // Options of CREATE and SETID
+        let mut mkstream = false;
+        let mut entries_read = None;
+        if subcommand == "create" || subcommand == "setid" {
+            let mut i = 3;
+            while i < args.len() {
+                if subcommand == "create" && args[i].eq_ignore_ascii_case(b"MKSTREAM") {
+                    mkstream = true;
+                } else if args[i].eq_ignore_ascii_case(b"ENTRIESREAD") && i + 1 < args.len() {
+                    match parse_int(args[i + 1]) {
+                        Ok(-1) => {}
+                        Ok(read) if read >= 0 => entries_read = Some(read as u64),
+                        Ok(_) => {
+                            return RespValue::Error(
+                                "ERR value for ENTRIESREAD must be positive or -1".to_string(),
+                            )
+                        }
+                        Err(e) => return e,
+                    }
+                    i += 1;
+                } else {
+                    return syntax_error();
+                }
+                i += 1;
+            }
+        }
+        let id = match subcommand.as_str() {
+            "create" | "setid" if args[2].as_ref() == b"$" => None,
+            "create" | "setid" => match parse_id(args[2], 0) {
+                Ok(id) => Some(id),
+                Err(e) => return e,
+            },
+            _ => None,
+        };
+
+        let result = self
+            .storage
+            .modify(key, |slot| {
+                if slot.stream()?.is_none() {
+                    if !mkstream {
+                        return Ok(Err(KEY_REQUIRED.to_string()));
+                    }
+                    slot.stream_mut()?;
+                }
+                self.xgroup_apply(slot, &subcommand, args, id, entries_read)
+            })
+            .await;
+
+        match result {
+            Ok(Ok(reply)) => {
+                // DESTROY and CREATECONSUMER reply 0 when they change nothing
+                let changed = match subcommand.as_str() {
+                    "destroy" | "createconsumer" => matches!(reply, RespValue::Integer(1)),
+                    _ => true,
+                };
+                if changed {
+                    self.notify(NOTIFY_STREAM, &format!("xgroup-{}", subcommand), key);
+                }
+                if subcommand == "destroy" {
+                    // Blocked XREADGROUP callers must see the group is gone
+                    self.context.blocking.signal_all(key);
+                }
+                reply
+            }
+            Ok(Err(msg)) => RespValue::Error(msg),
+            Err(e) => storage_error("xgroup", e),
+        }
+    }
+
+    /// Apply an XGROUP subcommand (`args` starting at the key) to an
+    /// existing stream.
+    fn xgroup_apply(
+        &self,
+        slot: &mut ValueSlot,
+        subcommand: &str,
+        args: &[&Bytes],
+        id: Option<StreamId>,
+        entries_read: Option<u64>,
+    ) -> Result<Result<RespValue, String>, StorageError> {
+        let (key, group) = (args[0], args[1]);
+        let no_such_group = || {
+            Ok(Err(format!(
+                "NOGROUP No such consumer group '{}' for key name '{}'",
+                String::from_utf8_lossy(group),
+                String::from_utf8_lossy(key)
+            )))
+        };
+        let Some(stream) = slot.stream()? else {
+            return Ok(Err(KEY_REQUIRED.to_string()));
+        };
+        let exists = stream.groups.contains_key(group);
+        let id = id.unwrap_or(stream.last_id);
+        let entries_read =
+            entries_read.or_else(|| (id >= stream.last_id).then_some(stream.entries_added));
+
+        match subcommand {
+            "create" => {
+                if exists {
+                    return Ok(Err(
+                        "BUSYGROUP Consumer Group name already exists".to_string()
+                    ));
+                }
+                slot.stream_mut()?
+                    .groups
+                    .insert(group.clone(), ConsumerGroup::new(id, entries_read));
+                Ok(Ok(RespValue::SimpleString("OK".to_string())))
+            }
+            "setid" => {
+                let Some((group, _)) = group_mut(slot, group)? else {
+                    return no_such_group();
+                };
+                group.last_delivered_id = id;
+                group.entries_read = entries_read;
+                Ok(Ok(RespValue::SimpleString("OK".to_string())))
+            }
+            "destroy" => {
+                if !exists {
+                    return Ok(Ok(RespValue::Integer(0)));
+                }
+                slot.stream_mut()?.groups.remove(group.as_ref());
+                Ok(Ok(RespValue::Integer(1)))
+            }
+            "createconsumer" => {
+                let Some((group, _)) = group_mut(slot, group)? else {
+                    return no_such_group();
+                };
+                let created = !group.consumers.contains_key(args[2]);
+                group.touch_consumer(args[2], now_ms());
+                Ok(Ok(RespValue::Integer(created as i64)))
+            }
+            _ => {
+                let Some((group, _)) = group_mut(slot, group)? else {
+                    return no_such_group();
+                };
+                let pending = group.pending_count(args[2]);
+                group
+                    .pending
+                    .retain(|_, pending| pending.consumer != args[2]);
+                group.consumers.remove(args[2].as_ref());
+                Ok(Ok(RespValue::Integer(pending as i64)))
+            }
+        }
+    }
+
+    /// XINFO STREAM key / XINFO GROUPS key / XINFO CONSUMERS key group
+    pub(super) async fn handle_xinfo(&self, args: &[&Bytes]) -> RespValue {
+        let Some(subcommand) = args.first() else {
+            return wrong_args("xinfo");
+        };
+        let subcommand = String::from_utf8_lossy(subcommand).to_lowercase();
+        let arity = match subcommand.as_str() {
+            "stream" | "groups" => 2,
+            "consumers" => 3,
+            _ => {
+                return RespValue::Error(format!(
+                    "ERR unknown subcommand '{}'. Try XINFO HELP.",
+                    subcommand
+                ))
+            }
+        };
+        if args.len() != arity {
+            return wrong_args(&format!("xinfo|{}", subcommand));
+        }
+
+        let now = now_ms();
+        let result = self
+            .storage
+            .inspect(args[1], |value| {
+                let Some(stream) = value.map(|v| v.as_stream()).transpose()? else {
+                    return Ok(Err("ERR no such key".to_string()));
+                };
+                Ok(match subcommand.as_str() {
+                    "stream" => Ok(self.stream_info(stream)),
+                    "groups" => Ok(RespValue::Array(Some(
+                        stream
+                            .groups
+                            .iter()
+                            .map(|(name, group)| self.group_info(stream, name, group))
+                            .collect(),
+                    ))),
+                    _ => match stream.groups.get(args[2].as_ref()) {
+                        Some(group) => Ok(RespValue::Array(Some(
+                            group
+                                .consumers
+                                .iter()
+                                .map(|(name, consumer)| {
+                                    let field = |name: &str| {
+                                        RespValue::BulkString(Some(Bytes::copy_from_slice(
+                                            name.as_bytes(),
+                                        )))
+                                    };
+                                    let inactive = consumer
+                                        .active_ms
+                                        .map_or(-1, |active| now.saturating_sub(active) as i64);
+                                    self.map_reply(vec![
+                                        (field("name"), RespValue::BulkString(Some(name.clone()))),
+                                        (
+                                            field("pending"),
+                                            RespValue::Integer(group.pending_count(name) as i64),
+                                        ),
+                                        (
+                                            field("idle"),
+                                            RespValue::Integer(
+                                                now.saturating_sub(consumer.seen_ms) as i64,
+                                            ),
+                                        ),
+                                        (field("inactive"), RespValue::Integer(inactive)),
+                                    ])
+                                })
+                                .collect(),
+                        ))),
+                        None => Err(format!(
+                            "NOGROUP No such consumer group '{}' for key name '{}'",
+                            String::from_utf8_lossy(args[2]),
+                            String::from_utf8_lossy(args[1])
+                        )),
+                    },
+                })
+            })
+            .await;
+
+        match result {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(msg)) => RespValue::Error(msg),
+            Err(e) => storage_error("xinfo", e),
+        }
+    }
+
+    fn stream_info(&self, stream: &Stream) -> RespValue {
+        let field =
+            |name: &str| RespValue::BulkString(Some(Bytes::copy_from_slice(name.as_bytes())));
+        let id = |id: StreamId| RespValue::BulkString(Some(id.to_string().into()));
+        let entry = |entry: Option<(&StreamId, &StreamFields)>| match entry {
+            Some((entry_id, fields)) => self.entry_reply(*entry_id, Some(fields.clone())),
+            None => RespValue::BulkString(None),
+        };
+        self.map_reply(vec![
+            (field("length"), RespValue::Integer(stream.len() as i64)),
+            (field("last-generated-id"), id(stream.last_id)),
+            (field("max-deleted-entry-id"), id(stream.max_deleted_id)),
+            (
+                field("entries-added"),
+                RespValue::Integer(stream.entries_added as i64),
+            ),
+            (field("recorded-first-entry-id"), id(stream.first_id())),
+            (
+                field("groups"),
+                RespValue::Integer(stream.groups.len() as i64),
+            ),
+            (
+                field("first-entry"),
+                entry(stream.entries.first_key_value()),
+            ),
+            (field("last-entry"), entry(stream.entries.last_key_value())),
+        ])
+    }
+
+    fn group_info(&self, stream: &Stream, name: &Bytes, group: &ConsumerGroup) -> RespValue {
+        let field =
+            |name: &str| RespValue::BulkString(Some(Bytes::copy_from_slice(name.as_bytes())));
+        let optional = |value: Option<u64>| match value {
+            Some(value) => RespValue::Integer(value as i64),
+            None => RespValue::BulkString(None),
+        };
+        self.map_reply(vec![
+            (field("name"), RespValue::BulkString(Some(name.clone()))),
+            (
+                field("consumers"),
+                RespValue::Integer(group.consumers.len() as i64),
+            ),
+            (
+                field("pending"),
+                RespValue::Integer(group.pending.len() as i64),
+            ),
+            (
+                field("last-delivered-id"),
+                RespValue::BulkString(Some(group.last_delivered_id.to_string().into())),
+            ),
+            (field("entries-read"), optional(group.entries_read)),
+            (field("lag"), optional(stream.lag(group))),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    fn b(s: &str) -> Bytes {
+        Bytes::from(s.to_string())
+    }
+
+    fn args(parts: &[&str]) -> Vec<Bytes> {
+        parts.iter().map(|part| b(part)).collect()
+    }
+
+    async fn run(handler: &Handler, parts: &[&str]) -> RespValue {
+        let owned = args(&parts[1..]);
+        let args: Vec<&Bytes> = owned.iter().collect();
+        match parts[0] {
+            "xadd" => handler.handle_xadd(&args).await,
+            "xrange" => handler.handle_xrange(&args, "xrange", false).await,
+            "xrevrange" => handler.handle_xrange(&args, "xrevrange", true).await,
+            "xlen" => handler.handle_xlen(&args).await,
+            "xread" => handler.handle_xread(&args).await,
+            "xreadgroup" => handler.handle_xreadgroup(&args).await,
+            "xgroup" => handler.handle_xgroup(&args).await,
+            "xack" => handler.handle_xack(&args).await,
+            "xpending" => handler.handle_xpending(&args).await,
+            "xclaim" => handler.handle_xclaim(&args).await,
+            "xautoclaim" => handler.handle_xautoclaim(&args).await,
+            "xtrim" => handler.handle_xtrim(&args).await,
+            "xdel" => handler.handle_xdel(&args).await,
+            other => panic!("unexpected command {}", other),
+        }
+    }
+
+    /// IDs of the entries in an XRANGE-style reply.
+    fn ids(value: RespValue) -> Vec<String> {
+        match value {
+            RespValue::Array(Some(entries)) => entries
+                .into_iter()
+                .map(|entry| match entry {
+                    RespValue::Array(Some(mut parts)) => match parts.swap_remove(0) {
+                        RespValue::BulkString(Some(id)) => String::from_utf8(id.to_vec()).unwrap(),
+                        other => panic!("Expected BulkString, got {:?}", other),
+                    },
+                    RespValue::BulkString(Some(id)) => String::from_utf8(id.to_vec()).unwrap(),
+                    other => panic!("Expected entry, got {:?}", other),
+                })
+                .collect(),
+            other => panic!("Expected Array, got {:?}", other),
+        }
+    }
+
+    fn error(value: RespValue) -> String {
+        match value {
+            RespValue::Error(msg) => msg,
+            other => panic!("Expected Error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_xadd_ids_and_ranges() {
+        let handler = create_handler();
+        for id in ["1-1", "1-2", "2-0", "3-*"] {
+            assert!(matches!(
+                run(&handler, &["xadd", "s", id, "f", "v"]).await,
+                RespValue::BulkString(Some(_))
+            ));
+        }
+
+        assert!(error(run(&handler, &["xadd", "s", "3-0", "f", "v"]).await)
+            .contains("equal or smaller"));
+        assert!(
+            error(run(&handler, &["xadd", "new", "0-0", "f", "v"]).await)
+                .contains("greater than 0-0")
+        );
+        assert!(matches!(
+            run(&handler, &["xadd", "new", "NOMKSTREAM", "*", "f", "v"]).await,
+            RespValue::BulkString(None)
+        ));
+        assert!(matches!(
+            run(&handler, &["xlen", "new"]).await,
+            RespValue::Integer(0)
+        ));
+
+        assert_eq!(
+            ids(run(&handler, &["xrange", "s", "-", "+"]).await),
+            ["1-1", "1-2", "2-0", "3-0"]
+        );
+        assert_eq!(
+            ids(run(&handler, &["xrange", "s", "(1-1", "2"]).await),
+            ["1-2", "2-0"]
+        );
+        assert_eq!(
+            ids(run(&handler, &["xrevrange", "s", "+", "-", "COUNT", "2"]).await),
+            ["3-0", "2-0"]
+        );
+
+        // MAXLEN trims from the oldest entry; XDEL leaves the counters intact
+        assert!(matches!(
+            run(&handler, &["xadd", "s", "MAXLEN", "3", "4-0", "f", "v"]).await,
+            RespValue::BulkString(Some(_))
+        ));
+        assert_eq!(
+            ids(run(&handler, &["xrange", "s", "-", "+"]).await),
+            ["2-0", "3-0", "4-0"]
+        );
+        assert!(matches!(
+            run(&handler, &["xdel", "s", "3-0", "9-0"]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(matches!(
+            run(&handler, &["xtrim", "s", "MINID", "5"]).await,
+            RespValue::Integer(2)
+        ));
+        assert!(
+            error(run(&handler, &["xtrim", "s", "MAXLEN", "1", "LIMIT", "10"]).await).contains("~")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_consumer_group_lifecycle() {
+        let handler = create_handler();
+        assert!(
+            error(run(&handler, &["xgroup", "CREATE", "s", "g", "$"]).await)
+                .contains("requires the key to exist")
+        );
+        assert!(matches!(
+            run(&handler, &["xgroup", "CREATE", "s", "g", "$", "MKSTREAM"]).await,
+            RespValue::SimpleString(_)
+        ));
+        assert!(
+            error(run(&handler, &["xgroup", "CREATE", "s", "g", "0"]).await)
+                .starts_with("BUSYGROUP")
+        );
+        for id in ["1-0", "2-0", "3-0"] {
+            run(&handler, &["xadd", "s", id, "f", "v"]).await;
+        }
+
+        let read = |consumer: &'static str, id: &'static str| {
+            let handler = &handler;
+            async move {
+                match run(
+                    handler,
+                    &[
+                        "xreadgroup",
+                        "GROUP",
+                        "g",
+                        consumer,
+                        "COUNT",
+                        "2",
+                        "STREAMS",
+                        "s",
+                        id,
+                    ],
+                )
+                .await
+                {
+                    RespValue::Array(Some(mut streams)) => match streams.remove(0) {
+                        RespValue::Array(Some(mut pair)) => ids(pair.remove(1)),
+                        other => panic!("Expected Array, got {:?}", other),
+                    },
+                    RespValue::Array(None) => Vec::new(),
+                    other => panic!("Expected Array, got {:?}", other),
+                }
+            }
+        };
+        assert_eq!(read("alice", ">").await, ["1-0", "2-0"]);
+        assert_eq!(read("bob", ">").await, ["3-0"]);
+        assert!(read("bob", ">").await.is_empty());
+        // History returns the consumer's own pending entries
+        assert_eq!(read("alice", "0").await, ["1-0", "2-0"]);
+
+        assert!(matches!(
+            run(&handler, &["xack", "s", "g", "1-0", "1-0"]).await,
+            RespValue::Integer(1)
+        ));
+        match run(&handler, &["xpending", "s", "g"]).await {
+            RespValue::Array(Some(summary)) => {
+                assert!(matches!(summary[0], RespValue::Integer(2)));
+                assert!(matches!(&summary[1], RespValue::BulkString(Some(id)) if id == "2-0"));
+                assert!(matches!(&summary[2], RespValue::BulkString(Some(id)) if id == "3-0"));
+            }
+            other => panic!("Expected Array, got {:?}", other),
+        }
+
+        // Claiming moves ownership; deleted entries leave the PEL
+        assert_eq!(
+            ids(run(&handler, &["xclaim", "s", "g", "bob", "0", "2-0", "JUSTID"]).await),
+            ["2-0"]
+        );
+        assert_eq!(
+            ids(run(&handler, &["xpending", "s", "g", "-", "+", "10", "bob"]).await),
+            ["2-0", "3-0"]
+        );
+        run(&handler, &["xdel", "s", "3-0"]).await;
+        match run(&handler, &["xautoclaim", "s", "g", "alice", "0", "0"]).await {
+            RespValue::Array(Some(mut reply)) => {
+                assert_eq!(ids(reply.remove(2)), ["3-0"]);
+                assert_eq!(ids(reply.remove(1)), ["2-0"]);
+                assert!(matches!(&reply[0], RespValue::BulkString(Some(id)) if id == "0-0"));
+            }
+            other => panic!("Expected Array, got {:?}", other),
+        }
+
+        assert!(matches!(
+            run(&handler, &["xgroup", "DELCONSUMER", "s", "g", "alice"]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(error(
+            run(
+                &handler,
+                &["xreadgroup", "GROUP", "nope", "c", "STREAMS", "s", ">"]
+            )
+            .await
+        )
+        .starts_with("NOGROUP"));
+    }
+
+    #[tokio::test]
+    async fn test_xread_block_woken_by_xadd() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let context = Arc::new(crate::server::ServerContext::new());
+        let config = Arc::new(crate::config::Config::default());
+        let new_handler = || {
+            Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            )
+        };
+
+        // Every blocked reader sees the entry, since XREAD does not consume
+        let mut readers = Vec::new();
+        for expected_blocked in 1..=2 {
+            let handler = new_handler();
+            readers.push(tokio::spawn(async move {
+                run(&handler, &["xread", "BLOCK", "0", "STREAMS", "s", "$"]).await
+            }));
+            while context.blocking.blocked_clients() < expected_blocked {
+                tokio::task::yield_now().await;
+            }
+        }
+        run(&new_handler(), &["xadd", "s", "5-0", "f", "v"]).await;
+
+        for reader in readers {
+            let reply = tokio::time::timeout(Duration::from_secs(1), reader)
+                .await
+                .expect("reader was not woken")
+                .unwrap();
+            match reply {
+                RespValue::Array(Some(mut streams)) => match streams.remove(0) {
+                    RespValue::Array(Some(mut pair)) => assert_eq!(ids(pair.remove(1)), ["5-0"]),
+                    other => panic!("Expected Array, got {:?}", other),
+                },
+                other => panic!("Expected Array, got {:?}", other),
+            }
+        }
+
+        let reply = run(
+            &new_handler(),
+            &["xread", "BLOCK", "20", "STREAMS", "s", "$"],
+        )
+        .await;
+        assert!(matches!(reply, RespValue::Array(None)));
+    }
+}
@@ -0,0 +1,1315 @@
+//! String commands: SET options, MGET/MSET/MSETNX, INCR, DECR, INCRBY, DECRBY,
+//! INCRBYFLOAT, range commands (APPEND, GETRANGE, SETRANGE, ...), the GET
+//! variants and LCS.
+//!
+//! Everything that reads and writes a key runs as one storage update, so it
+//! is atomic on every backend.
+
+use super::{
+    expire_deadline, format_float, invalid_expire_time, parse_float, parse_int, parse_utf8,
+    storage_error, syntax_error, wrong_args, Handler,
+};
+use crate::metrics::{Metrics, Timer};
+use crate::protocol::RespValue;
+use crate::server::notify::{NOTIFY_GENERIC, NOTIFY_STRING};
+use crate::storage::{StorageError, StorageValue, ValueData, ValueSlot};
+use bytes::Bytes;
+use std::time::SystemTime;
+
+/// Write condition of SET.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SetCondition {
+    /// NX: only set if the key does not exist.
+    Missing,
+    /// XX: only set if the key exists.
+    Exists,
+}
+
+/// What SET does with the key's expiry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SetExpiry {
+    /// Drop any existing TTL (the default).
+    Clear,
+    At(SystemTime),
+    /// KEEPTTL
+    Keep,
+}
+
+/// Options following `SET key value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct SetOptions {
+    pub(super) condition: Option<SetCondition>,
+    pub(super) expiry: SetExpiry,
+    /// GET: reply with the previous value.
+    pub(super) get: bool,
+}
+
+impl SetOptions {
+    pub(super) fn parse(args: &[&Bytes]) -> Result<Self, RespValue> {
+        let mut options = SetOptions {
+            condition: None,
+            expiry: SetExpiry::Clear,
+            get: false,
+        };
+        let mut expiry_given = false;
+
+        let mut i = 0;
+        while i < args.len() {
+            let option = args[i].to_ascii_uppercase();
+            match option.as_slice() {
+                b"NX" | b"XX" => {
+                    if options.condition.is_some() {
+                        return Err(syntax_error());
+                    }
+                    options.condition = Some(match option.as_slice() {
+                        b"NX" => SetCondition::Missing,
+                        _ => SetCondition::Exists,
+                    });
+                }
+                b"GET" => options.get = true,
+                b"KEEPTTL" => {
+                    if expiry_given {
+                        return Err(syntax_error());
+                    }
+                    expiry_given = true;
+                    options.expiry = SetExpiry::Keep;
+                }
+                b"EX" | b"PX" | b"EXAT" | b"PXAT" => {
+                    let Some(amount) = args.get(i + 1) else {
+                        return Err(syntax_error());
+                    };
+                    if expiry_given {
+                        return Err(syntax_error());
+                    }
+                    expiry_given = true;
+                    options.expiry = SetExpiry::At(parse_expiry_option(&option, amount, "set")?);
+                    i += 1;
+                }
+                _ => return Err(syntax_error()),
+            }
+            i += 1;
+        }
+        Ok(options)
+    }
+}
+
+/// Largest string SETRANGE and APPEND may produce, as Redis's default
+/// `proto-max-bulk-len`.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// Deadline of an `EX`/`PX`/`EXAT`/`PXAT` option, given in uppercase.
+fn parse_expiry_option(
+    option: &[u8],
+    amount: &Bytes,
+    command: &str,
+) -> Result<SystemTime, RespValue> {
+    let amount = parse_int(amount)?;
+    if amount <= 0 {
+        return Err(invalid_expire_time(command));
+    }
+    expire_deadline(
+        amount,
+        option.starts_with(b"P"),
+        option.ends_with(b"AT"),
+        command,
+    )
+}
+
+/// Parse GETEX options; no option keeps the current TTL.
+fn parse_getex_expiry(args: &[&Bytes]) -> Result<SetExpiry, RespValue> {
+    match args {
+        [] => Ok(SetExpiry::Keep),
+        [option] if option.eq_ignore_ascii_case(b"PERSIST") => Ok(SetExpiry::Clear),
+        [option, amount] => {
+            let option = option.to_ascii_uppercase();
+            match option.as_slice() {
+                b"EX" | b"PX" | b"EXAT" | b"PXAT" => Ok(SetExpiry::At(parse_expiry_option(
+                    &option, amount, "getex",
+                )?)),
+                _ => Err(syntax_error()),
+            }
+        }
+        _ => Err(syntax_error()),
+    }
+}
+
+/// Byte range of GETRANGE, with Redis's clamping rules; `None` when empty.
+fn string_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    if len == 0 || (start < 0 && end < 0 && start > end) {
+        return None;
+    }
+    let start = if start < 0 { start + len } else { start }.max(0);
+    let end = if end < 0 { end + len } else { end }.clamp(0, len - 1);
+    (start <= end).then_some((start as usize, end as usize))
+}
+
+/// Contiguous run of an LCS: inclusive byte ranges in both strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LcsMatch {
+    a: (usize, usize),
+    b: (usize, usize),
+}
+
+impl LcsMatch {
+    fn len(&self) -> usize {
+        self.a.1 - self.a.0 + 1
+    }
+}
+
+/// Longest common subsequence of two strings, with the runs it is made of
+/// listed from the end of the strings backwards, as Redis reports them.
+fn longest_common_subsequence(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<LcsMatch>) {
+    // table[i * width + j]: LCS length of a[..i] and b[..j]
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut sequence = Vec::with_capacity(table[a.len() * width + b.len()] as usize);
+    let mut matches = Vec::new();
+    let mut current: Option<LcsMatch> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        if a[i - 1] == b[j - 1] {
+            sequence.push(a[i - 1]);
+            // Consecutive matches are contiguous in both strings
+            match &mut current {
+                Some(run) => {
+                    run.a.0 = i - 1;
+                    run.b.0 = j - 1;
+                }
+                None => {
+                    current = Some(LcsMatch {
+                        a: (i - 1, i - 1),
+                        b: (j - 1, j - 1),
+                    })
+                }
+            }
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            matches.extend(current.take());
+        }
+    }
+    matches.extend(current);
+    sequence.reverse();
+    (sequence, matches)
+}
+
+/// Read the string stored in a slot, `None` if the key is missing.
+pub(super) fn string_value(slot: &ValueSlot) -> Result<Option<&Bytes>, StorageError> {
+    slot.get().map(|value| value.as_string()).transpose()
+}
+
+/// Store a string in a slot, keeping the expiry of an existing key.
+pub(super) fn store_string(slot: &mut ValueSlot, data: Bytes) {
+    match slot.get_mut() {
+        Some(value) => value.data = ValueData::String(data),
+        None => slot.set(StorageValue::new(data)),
+    }
+}
+
+/// Parse a stored integer the way Redis does: only the canonical decimal
+/// form counts, so `+1`, `01` and ` 1` are not integers.
+fn parse_stored_int(value: &[u8]) -> Option<i64> {
+    parse_utf8::<i64>(value).filter(|n| n.to_string().as_bytes() == value)
+}
+
+/// Key/value pairs of MSET-style arguments with duplicate keys merged; the
+/// last value given for a key wins.
+fn distinct_pairs<'a>(args: &[&'a Bytes]) -> Vec<(&'a [u8], &'a [u8])> {
+    let mut pairs: Vec<(&[u8], &[u8])> = Vec::with_capacity(args.len() / 2);
+    for pair in args.chunks_exact(2) {
+        match pairs.iter_mut().find(|(key, _)| *key == pair[0].as_ref()) {
+            Some(existing) => existing.1 = pair[1],
+            None => pairs.push((pair[0], pair[1])),
+        }
+    }
+    pairs
+}
+
+impl Handler {
+    /// SET with a condition, GET or KEEPTTL: applied as a single atomic
+    /// update so no other writer can slip in between the check and the write.
+    pub(super) async fn handle_set_conditional(
+        &self,
+        key: &Bytes,
+        value: &Bytes,
+        options: &SetOptions,
+    ) -> RespValue {
+        let result = self
+            .storage
+            .modify(key, |slot| {
+                let previous = match slot.get() {
+                    Some(current) if options.get => Some(current.as_string()?.clone()),
+                    _ => None,
+                };
+                let allowed = match options.condition {
+                    None => true,
+                    Some(SetCondition::Missing) => slot.get().is_none(),
+                    Some(SetCondition::Exists) => slot.get().is_some(),
+                };
+                if !allowed {
+                    return Ok((false, previous));
+                }
+
+                let expires_at = match options.expiry {
+                    SetExpiry::Clear => None,
+                    SetExpiry::At(at) => Some(at),
+                    SetExpiry::Keep => slot.get().and_then(|current| current.expires_at),
+                };
+                if expires_at.is_some_and(|at| at <= SystemTime::now()) {
+                    slot.delete();
+                } else {
+                    slot.set(StorageValue {
+                        data: ValueData::String(value.clone()),
+                        expires_at,
+                    });
+                }
+                Ok((true, previous))
+            })
+            .await;
+
+        match result {
+            Ok((written, previous)) => {
+                if written {
+                    Metrics::get().record_key_operation("set", 1);
+                    self.notify_set(key, options.expiry);
+                }
+                match (options.get, written) {
+                    (true, _) => RespValue::BulkString(previous),
+                    (false, true) => RespValue::SimpleString("OK".to_string()),
+                    (false, false) => RespValue::BulkString(None),
+                }
+            }
+            Err(e) => storage_error("set", e),
+        }
+    }
+
+    /// Publish the events of a SET: `set`, then `expire` for a TTL, or
+    /// `del` when the deadline has already passed.
+    pub(super) fn notify_set(&self, key: &[u8], expiry: SetExpiry) {
+        self.notify(NOTIFY_STRING, "set", key);
+        if let SetExpiry::At(at) = expiry {
+            let event = if at <= SystemTime::now() {
+                "del"
+            } else {
+                "expire"
+            };
+            self.notify(NOTIFY_GENERIC, event, key);
+        }
+    }
+
+    /// MGET key [key ...]: nil for missing keys and non-string values.
+    pub(super) async fn handle_mget(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("mget");
+        }
+
+        let keys: Vec<&[u8]> = args.iter().map(|key| key.as_ref()).collect();
+        let timer = Timer::new();
+        let result = self.storage.get_many(&keys).await;
+        Metrics::get().record_storage_operation("get_many", "storage", timer.elapsed_seconds());
+
+        match result {
+            Ok(values) => RespValue::Array(Some(
+                values.into_iter().map(RespValue::BulkString).collect(),
+            )),
+            Err(e) => storage_error("mget", e),
+        }
+    }
+
+    /// MSET key value [key value ...]
+    pub(super) async fn handle_mset(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() || !args.len().is_multiple_of(2) {
+            return wrong_args("mset");
+        }
+
+        let pairs = distinct_pairs(args);
+        let timer = Timer::new();
+        let result = self.storage.set_many(&pairs).await;
+        let metrics = Metrics::get();
+        metrics.record_storage_operation("set_many", "storage", timer.elapsed_seconds());
+
+        match result {
+            Ok(()) => {
+                metrics.record_key_operation("set", pairs.len() as u64);
+                for (key, _) in &pairs {
+                    self.notify(NOTIFY_STRING, "set", key);
+                }
+                RespValue::SimpleString("OK".to_string())
+            }
+            Err(e) => storage_error("mset", e),
+        }
+    }
+
+    /// MSETNX key value [key value ...]: sets all keys only if none exists,
+    /// as a single multi-key update. That update is atomic on memory and
+    /// LMDB; on S3 it only guarantees that racing calls do not both apply.
+    pub(super) async fn handle_msetnx(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() || !args.len().is_multiple_of(2) {
+            return wrong_args("msetnx");
+        }
+
+        let pairs = distinct_pairs(args);
+        let keys: Vec<&[u8]> = pairs.iter().map(|(key, _)| *key).collect();
+        let result = self
+            .storage
+            .modify_many(&keys, |slots| {
+                if slots.iter().any(|slot| slot.get().is_some()) {
+                    return Ok(false);
+                }
+                for (slot, (_, value)) in slots.iter_mut().zip(&pairs) {
+                    slot.set(StorageValue::new(Bytes::copy_from_slice(value)));
+                }
+                Ok(true)
+            })
+            .await;
+
+        match result {
+            Ok(written) => {
+                if written {
+                    Metrics::get().record_key_operation("set", pairs.len() as u64);
+                    for key in &keys {
+                        self.notify(NOTIFY_STRING, "set", key);
+                    }
+                }
+                RespValue::Integer(written as i64)
+            }
+            Err(e) => storage_error("msetnx", e),
+        }
+    }
+
+    /// APPEND key value: length of the string after the append.
+    pub(super) async fn handle_append(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("append");
+        }
+        let suffix = args[1];
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let mut data = string_value(slot)?.map_or_else(Vec::new, |data| data.to_vec());
+                if data.len() + suffix.len() > MAX_STRING_LEN {
+                    return Ok(Err(
+                        "ERR string exceeds maximum allowed size (proto-max-bulk-len)",
+                    ));
+                }
+                data.extend_from_slice(suffix);
+                let len = data.len();
+                store_string(slot, data.into());
+                Ok(Ok(len))
+            })
+            .await;
+
+        match result {
+            Ok(Ok(len)) => {
+                self.notify(NOTIFY_STRING, "append", args[0]);
+                RespValue::Integer(len as i64)
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("append", e),
+        }
+    }
+
+    /// STRLEN key
+    pub(super) async fn handle_strlen(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("strlen");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_string()?.len(),
+                    None => 0,
+                })
+            })
+            .await;
+        match result {
+            Ok(len) => RespValue::Integer(len as i64),
+            Err(e) => storage_error("strlen", e),
+        }
+    }
+
+    /// GETRANGE / SUBSTR key start end
+    pub(super) async fn handle_getrange(&self, args: &[&Bytes], command: &str) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args(command);
+        }
+        let (start, end) = match (parse_int(args[1]), parse_int(args[2])) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(value) = value else {
+                    return Ok(Bytes::new());
+                };
+                let data = value.as_string()?;
+                Ok(match string_range(start, end, data.len()) {
+                    Some((start, end)) => data.slice(start..=end),
+                    None => Bytes::new(),
+                })
+            })
+            .await;
+        match result {
+            Ok(data) => RespValue::BulkString(Some(data)),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// SETRANGE key offset value: overwrite part of the string, zero-padding
+    /// it if needed, and reply with its new length.
+    pub(super) async fn handle_setrange(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("setrange");
+        }
+        let offset = match parse_int(args[1]) {
+            Ok(offset) if offset >= 0 => offset as usize,
+            Ok(_) => return RespValue::Error("ERR offset is out of range".to_string()),
+            Err(e) => return e,
+        };
+        let patch = args[2];
+        let end = offset.saturating_add(patch.len());
+        if !patch.is_empty() && end > MAX_STRING_LEN {
+            return RespValue::Error(
+                "ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string(),
+            );
+        }
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let current = string_value(slot)?;
+                // An empty patch never creates or changes the key
+                if patch.is_empty() {
+                    return Ok(current.map_or(0, |data| data.len()));
+                }
+                let mut data = current.map_or_else(Vec::new, |data| data.to_vec());
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[offset..end].copy_from_slice(patch);
+                let len = data.len();
+                store_string(slot, data.into());
+                Ok(len)
+            })
+            .await;
+
+        match result {
+            Ok(len) => {
+                if !patch.is_empty() {
+                    self.notify(NOTIFY_STRING, "setrange", args[0]);
+                }
+                RespValue::Integer(len as i64)
+            }
+            Err(e) => storage_error("setrange", e),
+        }
+    }
+
+    /// GETDEL key
+    pub(super) async fn handle_getdel(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("getdel");
+        }
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let value = string_value(slot)?.cloned();
+                if value.is_some() {
+                    slot.delete();
+                }
+                Ok(value)
+            })
+            .await;
+        match result {
+            Ok(value) => {
+                if value.is_some() {
+                    self.notify(NOTIFY_GENERIC, "del", args[0]);
+                }
+                RespValue::BulkString(value)
+            }
+            Err(e) => storage_error("getdel", e),
+        }
+    }
+
+    /// GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+    /// PXAT unix-time-milliseconds | PERSIST]
+    pub(super) async fn handle_getex(&self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("getex");
+        }
+        let expiry = match parse_getex_expiry(&args[1..]) {
+            Ok(expiry) => expiry,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some(data) = string_value(slot)?.cloned() else {
+                    return Ok(None);
+                };
+                let event = match expiry {
+                    SetExpiry::Keep => None,
+                    SetExpiry::At(at) if at <= SystemTime::now() => {
+                        slot.delete();
+                        Some("del")
+                    }
+                    SetExpiry::At(at) => {
+                        if let Some(value) = slot.get_mut() {
+                            value.expires_at = Some(at);
+                        }
+                        Some("expire")
+                    }
+                    SetExpiry::Clear => {
+                        // Only dirty the slot if there is a TTL to remove
+                        if slot.get().is_some_and(|value| value.expires_at.is_some()) {
+                            if let Some(value) = slot.get_mut() {
+                                value.expires_at = None;
+                            }
+                            Some("persist")
+                        } else {
+                            None
+                        }
+                    }
+                };
+                Ok(Some((data, event)))
+            })
+            .await;
+        match result {
+            Ok(Some((data, event))) => {
+                if let Some(event) = event {
+                    self.notify(NOTIFY_GENERIC, event, args[0]);
+                }
+                RespValue::BulkString(Some(data))
+            }
+            Ok(None) => RespValue::BulkString(None),
+            Err(e) => storage_error("getex", e),
+        }
+    }
+
+    /// GETSET key value: like SET, replying with the previous string.
+    pub(super) async fn handle_getset(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("getset");
+        }
+        let value = args[1];
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let previous = string_value(slot)?.cloned();
+                slot.set(StorageValue::new(value.clone()));
+                Ok(previous)
+            })
+            .await;
+        match result {
+            Ok(previous) => {
+                Metrics::get().record_key_operation("set", 1);
+                self.notify(NOTIFY_STRING, "set", args[0]);
+                RespValue::BulkString(previous)
+            }
+            Err(e) => storage_error("getset", e),
+        }
+    }
+
+    /// LCS key1 key2 [LEN] [IDX] [MINMATCHLEN min-match-len] [WITHMATCHLEN]
+    pub(super) async fn handle_lcs(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("lcs");
+        }
+        let (mut len_only, mut idx, mut with_match_len) = (false, false, false);
+        let mut min_match_len = 0;
+        let mut i = 2;
+        while i < args.len() {
+            let option = args[i].to_ascii_uppercase();
+            match option.as_slice() {
+                b"LEN" => len_only = true,
+                b"IDX" => idx = true,
+                b"WITHMATCHLEN" => with_match_len = true,
+                b"MINMATCHLEN" if i + 1 < args.len() => {
+                    min_match_len = match parse_int(args[i + 1]) {
+                        Ok(min) => min.max(0) as usize,
+                        Err(e) => return e,
+                    };
+                    i += 1;
+                }
+                _ => return syntax_error(),
+            }
+            i += 1;
+        }
+        if len_only && idx {
+            return RespValue::Error(
+                "ERR If you want both the length and indexes, please just use IDX.".to_string(),
+            );
+        }
+
+        // Read both strings from one consistent snapshot
+        let keys: Vec<&[u8]> = if args[0] == args[1] {
+            vec![args[0]]
+        } else {
+            vec![args[0], args[1]]
+        };
+        let result = self
+            .storage
+            .modify_many(&keys, |slots| {
+                let mut strings = Vec::with_capacity(2);
+                for slot in slots.iter() {
+                    match string_value(slot) {
+                        Ok(data) => strings.push(data.cloned().unwrap_or_default()),
+                        Err(StorageError::WrongType) => {
+                            return Ok(Err("ERR The specified keys must contain string values"))
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(Ok(strings))
+            })
+            .await;
+        let strings = match result {
+            Ok(Ok(strings)) => strings,
+            Ok(Err(msg)) => return RespValue::Error(msg.to_string()),
+            Err(e) => return storage_error("lcs", e),
+        };
+        let (a, b) = (&strings[0], strings.last().unwrap_or(&strings[0]));
+
+        let cells = (a.len() + 1).saturating_mul(b.len() + 1);
+        if cells.saturating_mul(size_of::<u32>()) > MAX_STRING_LEN {
+            return RespValue::Error(
+                "ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"
+                    .to_string(),
+            );
+        }
+        let (sequence, matches) = longest_common_subsequence(a, b);
+
+        if len_only {
+            return RespValue::Integer(sequence.len() as i64);
+        }
+        if !idx {
+            return RespValue::BulkString(Some(sequence.into()));
+        }
+
+        let range = |(start, end): (usize, usize)| {
+            RespValue::Array(Some(vec![
+                RespValue::Integer(start as i64),
+                RespValue::Integer(end as i64),
+            ]))
+        };
+        let matches = matches
+            .into_iter()
+            .filter(|run| run.len() >= min_match_len)
+            .map(|run| {
+                let mut entry = vec![range(run.a), range(run.b)];
+                if with_match_len {
+                    entry.push(RespValue::Integer(run.len() as i64));
+                }
+                RespValue::Array(Some(entry))
+            })
+            .collect();
+        self.map_reply(vec![
+            (
+                RespValue::BulkString(Some(Bytes::from_static(b"matches"))),
+                RespValue::Array(Some(matches)),
+            ),
+            (
+                RespValue::BulkString(Some(Bytes::from_static(b"len"))),
+                RespValue::Integer(sequence.len() as i64),
+            ),
+        ])
+    }
+
+    /// INCR key / DECR key
+    pub(super) async fn handle_incr(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        delta: i64,
+    ) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args(command);
+        }
+        self.increment(args[0], command, delta).await
+    }
+
+    /// INCRBY key increment / DECRBY key decrement
+    pub(super) async fn handle_incrby(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        decrement: bool,
+    ) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args(command);
+        }
+        let delta = match parse_int(args[1]) {
+            Ok(delta) if !decrement => delta,
+            Ok(delta) => match delta.checked_neg() {
+                Some(delta) => delta,
+                None => return RespValue::Error("ERR decrement would overflow".to_string()),
+            },
+            Err(e) => return e,
+        };
+        self.increment(args[0], command, delta).await
+    }
+
+    async fn increment(&self, key: &Bytes, command: &str, delta: i64) -> RespValue {
+        let result = self
+            .storage
+            .modify(key, |slot| {
+                let current = match string_value(slot)? {
+                    Some(value) => match parse_stored_int(value) {
+                        Some(current) => current,
+                        None => return Ok(Err("ERR value is not an integer or out of range")),
+                    },
+                    None => 0,
+                };
+                let Some(updated) = current.checked_add(delta) else {
+                    return Ok(Err("ERR increment or decrement would overflow"));
+                };
+                store_string(slot, updated.to_string().into());
+                Ok(Ok(updated))
+            })
+            .await;
+
+        match result {
+            Ok(Ok(value)) => {
+                // INCR, DECR and DECRBY all report as INCRBY, as in Redis
+                self.notify(NOTIFY_STRING, "incrby", key);
+                RespValue::Integer(value)
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// INCRBYFLOAT key increment
+    pub(super) async fn handle_incrbyfloat(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("incrbyfloat");
+        }
+        let increment = match parse_float(args[1]) {
+            Ok(increment) => increment,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let current = match string_value(slot)? {
+                    Some(value) => match parse_utf8::<f64>(value).filter(|f| f.is_finite()) {
+                        Some(current) => current,
+                        None => return Ok(Err("ERR value is not a valid float")),
+                    },
+                    None => 0.0,
+                };
+                let updated = current + increment;
+                if !updated.is_finite() {
+                    return Ok(Err("ERR increment would produce NaN or Infinity"));
+                }
+                let formatted = Bytes::from(format_float(updated));
+                store_string(slot, formatted.clone());
+                Ok(Ok(formatted))
+            })
+            .await;
+
+        match result {
+            Ok(Ok(value)) => {
+                self.notify(NOTIFY_STRING, "incrbyfloat", args[0]);
+                RespValue::BulkString(Some(value))
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("incrbyfloat", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Cmd;
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    fn b(s: &str) -> Bytes {
+        Bytes::from(s.to_string())
+    }
+
+    fn error(value: RespValue) -> String {
+        match value {
+            RespValue::Error(msg) => msg,
+            other => panic!("Expected Error, got {:?}", other),
+        }
+    }
+
+    async fn set(handler: &Handler, parts: &[&str]) -> RespValue {
+        let args: Vec<RespValue> = parts
+            .iter()
+            .map(|part| RespValue::BulkString(Some(b(part))))
+            .collect();
+        handler.handle_set(&args).await
+    }
+
+    #[tokio::test]
+    async fn test_set_conditions_and_get() {
+        let handler = create_handler();
+
+        assert!(matches!(
+            set(&handler, &["lock", "a", "NX", "PX", "30000"]).await,
+            RespValue::SimpleString(_)
+        ));
+        assert!(matches!(
+            set(&handler, &["lock", "b", "NX", "PX", "30000"]).await,
+            RespValue::BulkString(None)
+        ));
+        assert!(matches!(
+            set(&handler, &["missing", "x", "XX"]).await,
+            RespValue::BulkString(None)
+        ));
+        assert_eq!(handler.storage.get(b"missing").await.unwrap(), None);
+
+        // GET replies with the old value whether or not the write happened
+        match set(&handler, &["lock", "c", "NX", "GET"]).await {
+            RespValue::BulkString(Some(old)) => assert_eq!(old, b("a")),
+            other => panic!("Expected BulkString, got {:?}", other),
+        }
+        match set(&handler, &["lock", "d", "XX", "GET"]).await {
+            RespValue::BulkString(Some(old)) => assert_eq!(old, b("a")),
+            other => panic!("Expected BulkString, got {:?}", other),
+        }
+        assert_eq!(handler.storage.get(b"lock").await.unwrap(), Some(b("d")));
+
+        handler
+            .storage
+            .update(b"h", &mut |slot| {
+                slot.hash_mut()?.insert(b("f"), b("v"));
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert!(error(set(&handler, &["h", "v", "GET"]).await).starts_with("WRONGTYPE"));
+        assert!(matches!(
+            set(&handler, &["h", "v"]).await,
+            RespValue::SimpleString(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_set_expiry_options() {
+        let handler = create_handler();
+        let ttl = |handler: &Handler, key: &'static [u8]| {
+            let storage = Arc::clone(&handler.storage);
+            async move {
+                storage
+                    .get_value(key)
+                    .await
+                    .unwrap()
+                    .and_then(|value| value.expires_at)
+                    .map(|at| at.duration_since(SystemTime::now()).unwrap_or_default())
+            }
+        };
+
+        set(&handler, &["k", "v", "EX", "100"]).await;
+        assert!(ttl(&handler, b"k").await.unwrap() > Duration::from_secs(90));
+        set(&handler, &["k", "v2", "KEEPTTL"]).await;
+        assert!(ttl(&handler, b"k").await.is_some());
+        set(&handler, &["k", "v3"]).await;
+        assert!(ttl(&handler, b"k").await.is_none());
+
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
+            + 50_000;
+        set(&handler, &["k", "v", "PXAT", &at.to_string()]).await;
+        assert!(ttl(&handler, b"k").await.unwrap() > Duration::from_secs(40));
+
+        // A deadline in the past removes the key
+        set(&handler, &["k", "v", "EXAT", "1"]).await;
+        assert_eq!(handler.storage.get(b"k").await.unwrap(), None);
+
+        for bad in [
+            &["k", "v", "NX", "XX"][..],
+            &["k", "v", "EX", "10", "PX", "10"],
+            &["k", "v", "KEEPTTL", "EX", "10"],
+            &["k", "v", "EX"],
+            &["k", "v", "BOGUS"],
+        ] {
+            assert_eq!(error(set(&handler, bad).await), "ERR syntax error");
+        }
+        assert_eq!(
+            error(set(&handler, &["k", "v", "EX", "0"]).await),
+            "ERR invalid expire time in 'set' command"
+        );
+        assert_eq!(
+            error(set(&handler, &["k", "v", "EX", "9223372036854775807"]).await),
+            "ERR invalid expire time in 'set' command"
+        );
+        assert_eq!(
+            error(set(&handler, &["k", "v", "PX", "soon"]).await),
+            "ERR value is not an integer or out of range"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_incr_family() {
+        let handler = create_handler();
+        let key = b("n");
+
+        assert!(matches!(
+            handler.handle_incr(&[&key], "incr", 1).await,
+            RespValue::Integer(1)
+        ));
+        assert!(matches!(
+            handler
+                .handle_incrby(&[&key, &b("10")], "incrby", false)
+                .await,
+            RespValue::Integer(11)
+        ));
+        assert!(matches!(
+            handler
+                .handle_incrby(&[&key, &b("12")], "decrby", true)
+                .await,
+            RespValue::Integer(-1)
+        ));
+        assert!(matches!(
+            handler.handle_incr(&[&key], "decr", -1).await,
+            RespValue::Integer(-2)
+        ));
+        assert_eq!(handler.storage.get(b"n").await.unwrap(), Some(b("-2")));
+
+        // Overflow leaves the value untouched
+        handler
+            .storage
+            .set(b"n", b"9223372036854775807")
+            .await
+            .unwrap();
+        assert_eq!(
+            error(handler.handle_incr(&[&key], "incr", 1).await),
+            "ERR increment or decrement would overflow"
+        );
+        assert_eq!(
+            error(
+                handler
+                    .handle_incrby(&[&key, &b("-9223372036854775808")], "decrby", true)
+                    .await
+            ),
+            "ERR decrement would overflow"
+        );
+
+        for stored in ["abc", "+1", "01", " 1", "1.5"] {
+            handler.storage.set(b"n", stored.as_bytes()).await.unwrap();
+            assert_eq!(
+                error(handler.handle_incr(&[&key], "incr", 1).await),
+                "ERR value is not an integer or out of range"
+            );
+        }
+        handler
+            .storage
+            .update(b"h", &mut |slot| {
+                slot.hash_mut()?.insert(b("f"), b("v"));
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert!(error(handler.handle_incr(&[&b("h")], "incr", 1).await).starts_with("WRONGTYPE"));
+    }
+
+    #[tokio::test]
+    async fn test_incr_keeps_ttl() {
+        let handler = create_handler();
+        handler
+            .storage
+            .set_with_expiry(b"n", b"5", Duration::from_secs(100))
+            .await
+            .unwrap();
+        handler.handle_incr(&[&b("n")], "incr", 1).await;
+
+        let value = handler.storage.get_value(b"n").await.unwrap().unwrap();
+        assert_eq!(value.data, ValueData::String(b("6")));
+        assert!(value.expires_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_incrbyfloat() {
+        let handler = create_handler();
+        let key = b("f");
+
+        handler.storage.set(b"f", b"10.50").await.unwrap();
+        match handler.handle_incrbyfloat(&[&key, &b("0.1")]).await {
+            RespValue::BulkString(Some(value)) => assert_eq!(value, b("10.6")),
+            other => panic!("Expected BulkString, got {:?}", other),
+        }
+        handler.storage.set(b"f", b"5.0e3").await.unwrap();
+        match handler.handle_incrbyfloat(&[&key, &b("2.0e2")]).await {
+            RespValue::BulkString(Some(value)) => assert_eq!(value, b("5200")),
+            other => panic!("Expected BulkString, got {:?}", other),
+        }
+
+        assert_eq!(
+            error(handler.handle_incrbyfloat(&[&key, &b("inf")]).await),
+            "ERR increment would produce NaN or Infinity"
+        );
+        handler.storage.set(b"f", b"nope").await.unwrap();
+        assert_eq!(
+            error(handler.handle_incrbyfloat(&[&key, &b("1")]).await),
+            "ERR value is not a valid float"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_incr_is_atomic() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let mut tasks = Vec::new();
+        for _ in 0..8 {
+            let handler = Handler::new(Arc::clone(&storage));
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..100 {
+                    handler.handle_incr(&[&b("n")], "incr", 1).await;
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(storage.get(b"n").await.unwrap(), Some(b("800")));
+    }
+
+    #[tokio::test]
+    async fn test_mget_mset_msetnx() {
+        let handler = create_handler();
+        let args = [b("a"), b("1"), b("b"), b("2"), b("a"), b("3")];
+        let args: Vec<&Bytes> = args.iter().collect();
+        assert!(matches!(
+            handler.handle_mset(&args).await,
+            RespValue::SimpleString(s) if s == "OK"
+        ));
+        handler
+            .storage
+            .modify(b"h", |slot| {
+                slot.hash_mut()?.insert(b("f"), b("v"));
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let values = match handler
+            .handle_mget(&[&b("a"), &b("missing"), &b("b"), &b("h")])
+            .await
+        {
+            RespValue::Array(Some(values)) => values,
+            other => panic!("Expected Array, got {:?}", other),
+        };
+        let values: Vec<Option<Bytes>> = values
+            .into_iter()
+            .map(|value| match value {
+                RespValue::BulkString(value) => value,
+                other => panic!("Expected BulkString, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(values, vec![Some(b("3")), None, Some(b("2")), None]);
+
+        // MSETNX writes nothing if any key exists
+        assert!(matches!(
+            handler
+                .handle_msetnx(&[&b("c"), &b("x"), &b("a"), &b("x")])
+                .await,
+            RespValue::Integer(0)
+        ));
+        assert_eq!(handler.storage.get(b"c").await.unwrap(), None);
+        assert!(matches!(
+            handler
+                .handle_msetnx(&[&b("c"), &b("x"), &b("d"), &b("y")])
+                .await,
+            RespValue::Integer(1)
+        ));
+        assert_eq!(handler.storage.get(b"d").await.unwrap(), Some(b("y")));
+
+        assert_eq!(
+            error(handler.handle_mset(&[&b("a")]).await),
+            "ERR wrong number of arguments for 'mset' command"
+        );
+    }
+
+    async fn call(handler: &mut Handler, cmd: Cmd, parts: &[&str]) -> RespValue {
+        let args: Vec<Bytes> = parts.iter().map(|part| b(part)).collect();
+        let args: Vec<&Bytes> = args.iter().collect();
+        handler.dispatch(cmd, &args).await
+    }
+
+    fn bulk(value: RespValue) -> Option<Bytes> {
+        match value {
+            RespValue::BulkString(value) => value,
+            other => panic!("Expected BulkString, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_range_commands() {
+        let mut handler = create_handler();
+        assert!(matches!(
+            call(&mut handler, Cmd::Append, &["log", "Hello"]).await,
+            RespValue::Integer(5)
+        ));
+        assert!(matches!(
+            call(&mut handler, Cmd::Append, &["log", " World"]).await,
+            RespValue::Integer(11)
+        ));
+        assert!(matches!(
+            call(&mut handler, Cmd::StrLen, &["log"]).await,
+            RespValue::Integer(11)
+        ));
+        for (start, end, expected) in [
+            ("0", "4", "Hello"),
+            ("-5", "-1", "World"),
+            ("0", "-100", "H"),
+            ("5", "3", ""),
+            ("-1", "-5", ""),
+            ("6", "1000", "World"),
+        ] {
+            assert_eq!(
+                bulk(call(&mut handler, Cmd::GetRange, &["log", start, end]).await),
+                Some(b(expected))
+            );
+        }
+
+        // SETRANGE pads with zero bytes and keeps the TTL
+        set(&handler, &["pad", "ab", "EX", "100"]).await;
+        assert!(matches!(
+            call(&mut handler, Cmd::SetRange, &["pad", "4", "cd"]).await,
+            RespValue::Integer(6)
+        ));
+        assert_eq!(
+            handler.storage.get(b"pad").await.unwrap(),
+            Some(Bytes::from_static(b"ab\0\0cd"))
+        );
+        assert!(handler
+            .storage
+            .get_expiry(b"pad")
+            .await
+            .unwrap()
+            .unwrap()
+            .is_some());
+        assert!(matches!(
+            call(&mut handler, Cmd::SetRange, &["missing", "3", ""]).await,
+            RespValue::Integer(0)
+        ));
+        assert!(!handler.storage.exists(b"missing").await.unwrap());
+        assert_eq!(
+            error(call(&mut handler, Cmd::SetRange, &["pad", "-1", "x"]).await),
+            "ERR offset is out of range"
+        );
+
+        // GETEX changes the TTL, GETSET clears it, GETDEL removes the key
+        assert_eq!(
+            bulk(call(&mut handler, Cmd::GetEx, &["pad", "PERSIST"]).await),
+            Some(Bytes::from_static(b"ab\0\0cd"))
+        );
+        assert_eq!(
+            handler.storage.get_expiry(b"pad").await.unwrap(),
+            Some(None)
+        );
+        call(&mut handler, Cmd::GetEx, &["log", "PX", "50000"]).await;
+        assert!(handler
+            .storage
+            .get_expiry(b"log")
+            .await
+            .unwrap()
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            bulk(call(&mut handler, Cmd::GetSet, &["log", "new"]).await),
+            Some(b("Hello World"))
+        );
+        assert_eq!(
+            handler.storage.get_expiry(b"log").await.unwrap(),
+            Some(None)
+        );
+        assert_eq!(
+            bulk(call(&mut handler, Cmd::GetDel, &["log"]).await),
+            Some(b("new"))
+        );
+        assert_eq!(bulk(call(&mut handler, Cmd::GetDel, &["log"]).await), None);
+        assert_eq!(
+            error(call(&mut handler, Cmd::GetEx, &["pad", "EX", "0"]).await),
+            "ERR invalid expire time in 'getex' command"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lcs() {
+        let mut handler = create_handler();
+        set(&handler, &["key1", "ohmytext"]).await;
+        set(&handler, &["key2", "mynewtext"]).await;
+
+        assert_eq!(
+            bulk(call(&mut handler, Cmd::Lcs, &["key1", "key2"]).await),
+            Some(b("mytext"))
+        );
+        assert!(matches!(
+            call(&mut handler, Cmd::Lcs, &["key1", "key2", "LEN"]).await,
+            RespValue::Integer(6)
+        ));
+
+        let (sequence, matches) = longest_common_subsequence(b"ohmytext", b"mynewtext");
+        assert_eq!(sequence, b"mytext");
+        assert_eq!(
+            matches,
+            vec![
+                LcsMatch {
+                    a: (4, 7),
+                    b: (5, 8)
+                },
+                LcsMatch {
+                    a: (2, 3),
+                    b: (0, 1)
+                },
+            ]
+        );
+
+        let reply = call(
+            &mut handler,
+            Cmd::Lcs,
+            &["key1", "key2", "IDX", "MINMATCHLEN", "4", "WITHMATCHLEN"],
+        )
+        .await;
+        let RespValue::Array(Some(reply)) = reply else {
+            panic!("Expected Array, got {:?}", reply);
+        };
+        let RespValue::Array(Some(matches)) = &reply[1] else {
+            panic!("Expected matches array");
+        };
+        assert_eq!(matches.len(), 1);
+        assert!(matches!(
+            &matches[0],
+            RespValue::Array(Some(entry)) if matches!(entry[2], RespValue::Integer(4))
+        ));
+        assert!(matches!(reply[3], RespValue::Integer(6)));
+    }
+}
@@ -0,0 +1,392 @@
+//! Transactions: MULTI, EXEC, DISCARD, WATCH and UNWATCH.
+//!
+//! Commands sent after MULTI are checked and queued. EXEC runs them back to
+//! back while other clients are held off by the server's exec lock, and
+//! hands them to the storage backend as one batch, so on LMDB they commit
+//! as a single write transaction. WATCH makes EXEC abort if another write
+//! modified a watched key first.
+
+use super::{bulk_args, storage_error, unknown_command, wrong_args, Cmd, Handler};
+use crate::protocol::RespValue;
+use bytes::Bytes;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+
+/// Commands queued by an open transaction.
+#[derive(Default)]
+pub(super) struct Transaction {
+    commands: Vec<(Cmd, Vec<RespValue>)>,
+    /// A command was rejected while queueing, so EXEC must fail.
+    failed: bool,
+}
+
+impl Transaction {
+    /// Number of commands queued.
+    pub(super) fn queued(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Whether any queued command writes.
+    pub(super) fn writes(&self) -> bool {
+        self.commands.iter().any(|(cmd, _)| cmd.is_write())
+    }
+}
+
+impl Handler {
+    /// Queue a command sent after MULTI. Errors that can be detected before
+    /// it runs are replied right away and make the transaction fail.
+    pub(super) fn queue_command(&mut self, cmd: Cmd, parts: Vec<RespValue>) -> RespValue {
+        let error = if cmd == Cmd::Unknown {
+            Some(unknown_command(&parts[0]))
+        } else if !cmd.accepts(parts.len()) {
+            Some(wrong_args(cmd.name()))
+        } else {
+            bulk_args(&parts[1..]).err()
+        };
+
+        let Some(transaction) = &mut self.transaction else {
+            unreachable!("commands are only queued inside MULTI");
+        };
+        match error {
+            Some(error) => {
+                transaction.failed = true;
+                error
+            }
+            None => {
+                transaction.commands.push((cmd, parts));
+                RespValue::SimpleString("QUEUED".to_string())
+            }
+        }
+    }
+
+    /// MULTI
+    pub(super) fn handle_multi(&mut self, args: &[&Bytes]) -> RespValue {
+        if !args.is_empty() {
+            return wrong_args("multi");
+        }
+        if self.transaction.is_some() {
+            return RespValue::Error("ERR MULTI calls can not be nested".to_string());
+        }
+        self.transaction = Some(Transaction::default());
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    /// EXEC
+    pub(super) async fn handle_exec(&mut self, args: &[RespValue]) -> RespValue {
+        if !args.is_empty() {
+            return wrong_args("exec");
+        }
+        let Some(transaction) = self.transaction.take() else {
+            return RespValue::Error("ERR EXEC without MULTI".to_string());
+        };
+
+        let context = Arc::clone(&self.context);
+        let _exclusive = context.exec_lock.write().await;
+        let modified = self.watched.is_dirty();
+        context.watches.unwatch_all(&mut self.watched);
+        if transaction.failed {
+            return RespValue::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string(),
+            );
+        }
+        if modified {
+            return self.null_array();
+        }
+
+        // Errors of individual commands are replied in place; only a storage
+        // failure of the batch as a whole fails EXEC
+        let storage = Arc::clone(&self.storage);
+        let mut replies = Vec::with_capacity(transaction.commands.len());
+        self.in_exec = true;
+        let result = {
+            let batch = pin!(async {
+                for (cmd, parts) in &transaction.commands {
+                    replies.push(self.execute(*cmd, parts).await);
+                }
+            });
+            storage.atomically(batch).await
+        };
+        self.in_exec = false;
+        match result {
+            Ok(()) => RespValue::Array(Some(replies)),
+            Err(e) => storage_error("exec", e),
+        }
+    }
+
+    /// DISCARD
+    pub(super) fn handle_discard(&mut self, args: &[&Bytes]) -> RespValue {
+        if !args.is_empty() {
+            return wrong_args("discard");
+        }
+        if self.transaction.take().is_none() {
+            return RespValue::Error("ERR DISCARD without MULTI".to_string());
+        }
+        self.context.watches.unwatch_all(&mut self.watched);
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    /// WATCH key [key ...]
+    pub(super) fn handle_watch(&mut self, args: &[&Bytes]) -> RespValue {
+        if args.is_empty() {
+            return wrong_args("watch");
+        }
+        if self.transaction.is_some() {
+            return RespValue::Error("ERR WATCH inside MULTI is not allowed".to_string());
+        }
+        for key in args {
+            self.context.watches.watch(&mut self.watched, key);
+        }
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    /// UNWATCH
+    pub(super) fn handle_unwatch(&mut self, args: &[&Bytes]) -> RespValue {
+        if !args.is_empty() {
+            return wrong_args("unwatch");
+        }
+        self.context.watches.unwatch_all(&mut self.watched);
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    /// Run `f` with other clients' transactions held off, for commands that
+    /// do not hold the exec lock for their whole run. Inside EXEC the lock is
+    /// already held exclusively.
+    pub(super) async fn isolated<F: Future>(&self, f: F) -> F::Output {
+        if self.in_exec {
+            return f.await;
+        }
+        let _shared = self.context.exec_lock.read().await;
+        f.await
+    }
+}
+
+impl Drop for Handler {
+    fn drop(&mut self) {
+        self.context.watches.unwatch_all(&mut self.watched);
+        self.context.tracking.disable(self.subscriber.id());
+        self.context.pubsub.close(&mut self.subscriber);
+        self.context.clients.unregister(self.client.id());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::ServerContext;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::time::Duration;
+
+    async fn run(handler: &mut Handler, args: &[&str]) -> RespValue {
+        let parts = args
+            .iter()
+            .map(|arg| RespValue::BulkString(Some(Bytes::from(arg.to_string()))))
+            .collect();
+        handler.handle_command(RespValue::Array(Some(parts))).await
+    }
+
+    fn is_ok(value: &RespValue) -> bool {
+        matches!(value, RespValue::SimpleString(s) if s == "OK")
+    }
+
+    #[tokio::test]
+    async fn test_exec_runs_queued_commands() {
+        let mut handler = Handler::new(Arc::new(MemoryStorage::new()));
+        assert!(is_ok(&run(&mut handler, &["MULTI"]).await));
+        assert!(matches!(
+            run(&mut handler, &["SET", "k", "v"]).await,
+            RespValue::SimpleString(s) if s == "QUEUED"
+        ));
+        run(&mut handler, &["INCR", "k"]).await;
+        run(&mut handler, &["GET", "k"]).await;
+        // Nothing runs before EXEC
+        assert!(handler.storage.get(b"k").await.unwrap().is_none());
+
+        // A command failing at run time does not stop the others
+        let RespValue::Array(Some(replies)) = run(&mut handler, &["EXEC"]).await else {
+            panic!("Expected Array");
+        };
+        assert_eq!(replies.len(), 3);
+        assert!(is_ok(&replies[0]));
+        assert!(matches!(&replies[1], RespValue::Error(e) if e.starts_with("ERR value is not")));
+        assert!(matches!(&replies[2], RespValue::BulkString(Some(v)) if v == "v"));
+
+        assert!(matches!(
+            run(&mut handler, &["EXEC"]).await,
+            RespValue::Error(e) if e == "ERR EXEC without MULTI"
+        ));
+        assert!(matches!(
+            run(&mut handler, &["DISCARD"]).await,
+            RespValue::Error(e) if e == "ERR DISCARD without MULTI"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_queue_errors_abort_exec() {
+        let mut handler = Handler::new(Arc::new(MemoryStorage::new()));
+        run(&mut handler, &["MULTI"]).await;
+        run(&mut handler, &["SET", "k", "v"]).await;
+        assert!(matches!(
+            run(&mut handler, &["NOSUCHCOMMAND"]).await,
+            RespValue::Error(_)
+        ));
+        assert!(matches!(
+            run(&mut handler, &["GET"]).await,
+            RespValue::Error(e) if e == "ERR wrong number of arguments for 'get' command"
+        ));
+        assert!(matches!(
+            run(&mut handler, &["MULTI"]).await,
+            RespValue::Error(e) if e == "ERR MULTI calls can not be nested"
+        ));
+        assert!(matches!(
+            run(&mut handler, &["EXEC"]).await,
+            RespValue::Error(e) if e.starts_with("EXECABORT")
+        ));
+        assert!(handler.storage.get(b"k").await.unwrap().is_none());
+
+        run(&mut handler, &["MULTI"]).await;
+        run(&mut handler, &["SET", "k", "v"]).await;
+        assert!(is_ok(&run(&mut handler, &["DISCARD"]).await));
+        assert!(handler.storage.get(b"k").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_watch_aborts_exec_on_modification() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let context = Arc::new(ServerContext::new());
+        let config = Arc::new(Config::default());
+        let new_handler = || {
+            Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            )
+        };
+        let mut client = new_handler();
+        let mut other = new_handler();
+
+        assert!(is_ok(&run(&mut client, &["WATCH", "k"]).await));
+        run(&mut other, &["SET", "k", "theirs"]).await;
+        run(&mut client, &["MULTI"]).await;
+        assert!(matches!(
+            run(&mut client, &["WATCH", "k"]).await,
+            RespValue::Error(e) if e == "ERR WATCH inside MULTI is not allowed"
+        ));
+        run(&mut client, &["SET", "k", "mine"]).await;
+        assert!(matches!(
+            run(&mut client, &["EXEC"]).await,
+            RespValue::Array(None)
+        ));
+        assert_eq!(storage.get(b"k").await.unwrap().unwrap(), "theirs");
+
+        // EXEC unwatched everything, and writes to other keys do not count
+        run(&mut client, &["WATCH", "k"]).await;
+        run(&mut other, &["SET", "other", "x"]).await;
+        run(&mut client, &["MULTI"]).await;
+        run(&mut client, &["SET", "k", "mine"]).await;
+        assert!(matches!(
+            run(&mut client, &["EXEC"]).await,
+            RespValue::Array(Some(replies)) if replies.len() == 1
+        ));
+        assert_eq!(storage.get(b"k").await.unwrap().unwrap(), "mine");
+
+        // UNWATCH forgets the watched keys, as does a closed connection
+        run(&mut client, &["WATCH", "k"]).await;
+        run(&mut client, &["UNWATCH"]).await;
+        run(&mut other, &["DEL", "k"]).await;
+        run(&mut client, &["MULTI"]).await;
+        assert!(matches!(
+            run(&mut client, &["EXEC"]).await,
+            RespValue::Array(Some(_))
+        ));
+        run(&mut client, &["WATCH", "k"]).await;
+        drop(client);
+        assert_eq!(context.watches.watched_keys(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_exec_on_lmdb() {
+        use crate::storage::lmdb::LmdbStorage;
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(
+            LmdbStorage::new_with_map_size(dir.path().join("test.lmdb"), 64 * 1024 * 1024).unwrap(),
+        );
+        let mut handler = Handler::new(Arc::clone(&storage));
+
+        // Commands see the writes of earlier ones, and blocking commands
+        // return at once
+        run(&mut handler, &["MULTI"]).await;
+        run(&mut handler, &["RPUSH", "list", "a", "b"]).await;
+        run(&mut handler, &["LMOVE", "list", "other", "LEFT", "RIGHT"]).await;
+        run(&mut handler, &["BLPOP", "empty", "0"]).await;
+        run(&mut handler, &["LRANGE", "other", "0", "-1"]).await;
+        let RespValue::Array(Some(replies)) = run(&mut handler, &["EXEC"]).await else {
+            panic!("Expected Array");
+        };
+        assert!(matches!(replies[0], RespValue::Integer(2)));
+        assert!(matches!(&replies[1], RespValue::BulkString(Some(v)) if v == "a"));
+        assert!(matches!(replies[2], RespValue::Array(None)));
+        assert!(matches!(&replies[3], RespValue::Array(Some(items)) if items.len() == 1));
+        assert_eq!(storage.keys_count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_exec_on_lmdb_never_waits() {
+        use crate::storage::lmdb::LmdbStorage;
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(
+            LmdbStorage::new_with_map_size(dir.path().join("test.lmdb"), 64 * 1024 * 1024).unwrap(),
+        );
+        let mut handler = Handler::new(Arc::clone(&storage));
+
+        // Every command that would wait for data outside a transaction
+        // replies nil at once, so the batch commits in one go
+        run(&mut handler, &["MULTI"]).await;
+        run(&mut handler, &["SET", "k", "v"]).await;
+        run(&mut handler, &["BRPOP", "empty", "0"]).await;
+        run(
+            &mut handler,
+            &["BLMOVE", "empty", "dst", "LEFT", "RIGHT", "0"],
+        )
+        .await;
+        run(&mut handler, &["BRPOPLPUSH", "empty", "dst", "0"]).await;
+        run(&mut handler, &["BLMPOP", "0", "1", "empty", "LEFT"]).await;
+        run(
+            &mut handler,
+            &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"],
+        )
+        .await;
+        run(&mut handler, &["XREAD", "BLOCK", "0", "STREAMS", "s", "$"]).await;
+        let group_read = [
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "c",
+            "BLOCK",
+            "0",
+            "STREAMS",
+            "s",
+            ">",
+        ];
+        run(&mut handler, &group_read).await;
+        let reply = tokio::time::timeout(Duration::from_secs(1), run(&mut handler, &["EXEC"]))
+            .await
+            .expect("EXEC waited");
+        let RespValue::Array(Some(replies)) = reply else {
+            panic!("Expected Array, got {reply:?}");
+        };
+        assert!(is_ok(&replies[0]));
+        assert!(matches!(replies[1], RespValue::Array(None)));
+        assert!(matches!(replies[2], RespValue::BulkString(None)));
+        assert!(matches!(replies[3], RespValue::BulkString(None)));
+        assert!(matches!(replies[4], RespValue::Array(None)));
+        assert!(is_ok(&replies[5]));
+        assert!(matches!(replies[6], RespValue::Array(None)));
+        assert!(matches!(replies[7], RespValue::Array(None)));
+        assert!(storage.get(b"k").await.unwrap().is_some());
+    }
+}
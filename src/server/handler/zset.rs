@@ -0,0 +1,1215 @@
+//! Sorted set commands (ZADD, ZRANGE, ZRANK, ZPOPMIN, ZUNIONSTORE, ZSCAN, ...).
+
+use super::scan::{scan_batch, ScanArgs, ScanKind};
+use super::{
+    distinct_keys, format_float, normalize_range, parse_float, parse_int, parse_utf8,
+    storage_error, syntax_error, wrong_args, Handler,
+};
+use crate::protocol::{ProtocolVersion, RespValue};
+use crate::server::notify::NOTIFY_ZSET;
+use crate::storage::{SortedSet, StorageError, StorageValue, ValueData, ValueSlot};
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// Boundary of a BYSCORE range: `1.5`, `(1.5` (exclusive), `-inf`, `+inf`.
+#[derive(Debug, Clone, Copy)]
+struct ScoreBound {
+    value: f64,
+    exclusive: bool,
+}
+
+impl ScoreBound {
+    fn parse(arg: &[u8]) -> Result<Self, RespValue> {
+        let (exclusive, value) = match arg.strip_prefix(b"(") {
+            Some(rest) => (true, rest),
+            None => (false, arg),
+        };
+        parse_utf8::<f64>(value)
+            .filter(|value| !value.is_nan())
+            .map(|value| ScoreBound { value, exclusive })
+            .ok_or_else(|| RespValue::Error("ERR min or max is not a float".to_string()))
+    }
+
+    /// Whether `score` lies below this bound used as a minimum.
+    fn below_min(&self, score: f64) -> bool {
+        score < self.value || (self.exclusive && score == self.value)
+    }
+
+    /// Whether `score` lies within or below this bound used as a maximum.
+    fn within_max(&self, score: f64) -> bool {
+        score < self.value || (!self.exclusive && score == self.value)
+    }
+}
+
+/// Boundary of a BYLEX range: `[a` (inclusive), `(a` (exclusive), `-`, `+`.
+#[derive(Debug, Clone)]
+enum LexBound {
+    Min,
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+impl LexBound {
+    fn parse(arg: &Bytes) -> Result<Self, RespValue> {
+        match arg.first() {
+            Some(b'-') if arg.len() == 1 => Ok(LexBound::Min),
+            Some(b'+') if arg.len() == 1 => Ok(LexBound::Max),
+            Some(b'[') => Ok(LexBound::Inclusive(arg.slice(1..))),
+            Some(b'(') => Ok(LexBound::Exclusive(arg.slice(1..))),
+            _ => Err(RespValue::Error(
+                "ERR min or max not valid string range item".to_string(),
+            )),
+        }
+    }
+
+    fn below_min(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(bound) => member < bound.as_ref(),
+            LexBound::Exclusive(bound) => member <= bound.as_ref(),
+        }
+    }
+
+    fn within_max(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(bound) => member <= bound.as_ref(),
+            LexBound::Exclusive(bound) => member < bound.as_ref(),
+        }
+    }
+}
+
+/// Selection of a sorted set by rank, score or member.
+#[derive(Debug, Clone)]
+enum ZRange {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+impl ZRange {
+    /// Ascending ranks `start..end` selected from `zset`. With `rev`, rank
+    /// ranges count from the highest score.
+    fn ranks(&self, zset: &SortedSet, rev: bool) -> (usize, usize) {
+        let len = zset.len();
+        match self {
+            ZRange::Rank(start, stop) => match normalize_range(*start, *stop, len) {
+                Some((start, stop)) if rev => (len - 1 - stop, len - start),
+                Some((start, stop)) => (start, stop + 1),
+                None => (0, 0),
+            },
+            ZRange::Score(min, max) => {
+                let start = zset.partition_point(|_, score| min.below_min(score));
+                let end = zset.partition_point(|_, score| max.within_max(score));
+                (start, end.max(start))
+            }
+            ZRange::Lex(min, max) => {
+                let start = zset.partition_point(|member, _| min.below_min(member));
+                let end = zset.partition_point(|member, _| max.within_max(member));
+                (start, end.max(start))
+            }
+        }
+    }
+}
+
+/// How ZRANGE-style commands select members.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RangeBy {
+    Rank,
+    Score,
+    Lex,
+}
+
+/// Parsed ZRANGE arguments.
+struct ZRangeArgs {
+    range: ZRange,
+    rev: bool,
+    offset: i64,
+    count: i64,
+    with_scores: bool,
+}
+
+impl ZRangeArgs {
+    /// Parse `start stop [options]`. `fixed` carries the selection mode of
+    /// the legacy ZRANGEBYSCORE-style commands, which reject BYSCORE/BYLEX/REV.
+    fn parse(args: &[&Bytes], fixed: Option<(RangeBy, bool)>) -> Result<Self, RespValue> {
+        let (mut by, mut rev) = fixed.unwrap_or((RangeBy::Rank, false));
+        let mut limit = None;
+        let mut with_scores = false;
+
+        let mut i = 2;
+        while i < args.len() {
+            let option = args[i];
+            if option.eq_ignore_ascii_case(b"WITHSCORES") {
+                with_scores = true;
+            } else if option.eq_ignore_ascii_case(b"LIMIT") && i + 2 < args.len() {
+                limit = Some((parse_int(args[i + 1])?, parse_int(args[i + 2])?));
+                i += 2;
+            } else if fixed.is_none() && option.eq_ignore_ascii_case(b"BYSCORE") {
+                by = RangeBy::Score;
+            } else if fixed.is_none() && option.eq_ignore_ascii_case(b"BYLEX") {
+                by = RangeBy::Lex;
+            } else if fixed.is_none() && option.eq_ignore_ascii_case(b"REV") {
+                rev = true;
+            } else {
+                return Err(syntax_error());
+            }
+            i += 1;
+        }
+
+        if limit.is_some() && by == RangeBy::Rank {
+            return Err(RespValue::Error(
+                "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                    .to_string(),
+            ));
+        }
+        if with_scores && by == RangeBy::Lex {
+            return Err(RespValue::Error(
+                "ERR syntax error, WITHSCORES not supported in combination with BYLEX".to_string(),
+            ));
+        }
+
+        // Reversed score and lex ranges are given as `max min`
+        let (low, high) = if rev && by != RangeBy::Rank {
+            (args[1], args[0])
+        } else {
+            (args[0], args[1])
+        };
+        let range = parse_range(by, low, high)?;
+        let (offset, count) = limit.unwrap_or((0, -1));
+
+        Ok(ZRangeArgs {
+            range,
+            rev,
+            offset,
+            count,
+            with_scores,
+        })
+    }
+}
+
+/// Parse a `min max` (or `start stop`) pair for the given selection mode.
+fn parse_range(by: RangeBy, min: &Bytes, max: &Bytes) -> Result<ZRange, RespValue> {
+    Ok(match by {
+        RangeBy::Rank => ZRange::Rank(parse_int(min)?, parse_int(max)?),
+        RangeBy::Score => ZRange::Score(ScoreBound::parse(min)?, ScoreBound::parse(max)?),
+        RangeBy::Lex => ZRange::Lex(LexBound::parse(min)?, LexBound::parse(max)?),
+    })
+}
+
+/// Score aggregation for ZUNIONSTORE/ZINTERSTORE.
+#[derive(Debug, Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn combine(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf is NaN, which Redis stores as 0
+            Aggregate::Sum => Some(a + b).filter(|s| !s.is_nan()).unwrap_or(0.0),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// Member scores of a ZUNIONSTORE/ZINTERSTORE source. Plain sets count as
+/// sorted sets with every score 1; missing keys are empty.
+fn source_scores(slot: &ValueSlot) -> Result<HashMap<Bytes, f64>, StorageError> {
+    Ok(match slot.get().map(|value| &value.data) {
+        None => HashMap::new(),
+        Some(ValueData::SortedSet(zset)) => zset.iter().map(|(m, s)| (m.clone(), s)).collect(),
+        Some(ValueData::Set(set)) => set.iter().map(|m| (m.clone(), 1.0)).collect(),
+        Some(_) => return Err(StorageError::WrongType),
+    })
+}
+
+/// Parsed ZADD flags.
+#[derive(Debug, Default)]
+struct ZAddFlags {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+    ch: bool,
+    incr: bool,
+}
+
+impl Handler {
+    /// Reply with a score: a double under RESP3, a bulk string under RESP2.
+    fn double_reply(&self, value: f64) -> RespValue {
+        match self.protocol_version {
+            ProtocolVersion::Resp3 => RespValue::Double(value),
+            ProtocolVersion::Resp2 => RespValue::BulkString(Some(format_float(value).into())),
+        }
+    }
+
+    /// Reply with members, optionally with their scores: flat pairs under
+    /// RESP2, `[member, score]` arrays under RESP3.
+    fn scored_reply(&self, entries: Vec<(Bytes, f64)>, with_scores: bool) -> RespValue {
+        let items = entries
+            .into_iter()
+            .flat_map(|(member, score)| {
+                let member = RespValue::BulkString(Some(member));
+                match (with_scores, self.protocol_version) {
+                    (false, _) => vec![member],
+                    (true, ProtocolVersion::Resp3) => {
+                        vec![RespValue::Array(Some(vec![
+                            member,
+                            self.double_reply(score),
+                        ]))]
+                    }
+                    (true, ProtocolVersion::Resp2) => vec![member, self.double_reply(score)],
+                }
+            })
+            .collect();
+        RespValue::Array(Some(items))
+    }
+
+    /// ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]
+    pub(super) async fn handle_zadd(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 3 {
+            return wrong_args("zadd");
+        }
+
+        let mut flags = ZAddFlags::default();
+        let mut i = 1;
+        while i < args.len() {
+            let flag = match args[i].to_ascii_uppercase().as_slice() {
+                b"NX" => &mut flags.nx,
+                b"XX" => &mut flags.xx,
+                b"GT" => &mut flags.gt,
+                b"LT" => &mut flags.lt,
+                b"CH" => &mut flags.ch,
+                b"INCR" => &mut flags.incr,
+                _ => break,
+            };
+            *flag = true;
+            i += 1;
+        }
+
+        let pairs = &args[i..];
+        if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+            return syntax_error();
+        }
+        if flags.nx && flags.xx {
+            return RespValue::Error(
+                "ERR XX and NX options at the same time are not compatible".to_string(),
+            );
+        }
+        if [flags.gt, flags.lt, flags.nx]
+            .iter()
+            .filter(|&&set| set)
+            .count()
+            > 1
+        {
+            return RespValue::Error(
+                "ERR GT, LT, and/or NX options at the same time are not compatible".to_string(),
+            );
+        }
+        if flags.incr && pairs.len() > 2 {
+            return RespValue::Error(
+                "ERR INCR option supports a single increment-element pair".to_string(),
+            );
+        }
+
+        let mut elements = Vec::with_capacity(pairs.len() / 2);
+        for pair in pairs.chunks(2) {
+            match parse_float(pair[0]) {
+                Ok(score) => elements.push((score, pair[1])),
+                Err(e) => return e,
+            }
+        }
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let (mut added, mut changed, mut incr_result) = (0, 0, None);
+                for &(score, member) in &elements {
+                    let current = slot.zset()?.and_then(|zset| zset.score(member));
+                    let updated = match current {
+                        None if flags.xx => continue,
+                        None => score,
+                        Some(_) if flags.nx => continue,
+                        Some(current) if flags.incr => current + score,
+                        Some(_) => score,
+                    };
+                    if updated.is_nan() {
+                        return Ok(Err("ERR resulting score is not a number (NaN)"));
+                    }
+                    if let Some(current) = current {
+                        if (flags.gt && updated <= current) || (flags.lt && updated >= current) {
+                            continue;
+                        }
+                    }
+
+                    incr_result = Some(updated);
+                    if current != Some(updated) {
+                        slot.zset_mut()?.insert(member.clone(), updated);
+                        match current {
+                            None => added += 1,
+                            Some(_) => changed += 1,
+                        }
+                    }
+                }
+                Ok(Ok((added, changed, incr_result)))
+            })
+            .await;
+        if let Ok(Ok((added, changed, _))) = result {
+            if added + changed > 0 {
+                let event = if flags.incr { "zincr" } else { "zadd" };
+                self.notify(NOTIFY_ZSET, event, args[0]);
+            }
+        }
+
+        match result {
+            Ok(Ok((_, _, score))) if flags.incr => match score {
+                Some(score) => self.double_reply(score),
+                None => RespValue::BulkString(None),
+            },
+            Ok(Ok((added, changed, _))) => {
+                RespValue::Integer(if flags.ch { added + changed } else { added })
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("zadd", e),
+        }
+    }
+
+    /// ZINCRBY key increment member
+    pub(super) async fn handle_zincrby(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args("zincrby");
+        }
+        let increment = match parse_float(args[1]) {
+            Ok(increment) => increment,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let current = slot.zset()?.and_then(|zset| zset.score(args[2]));
+                let updated = current.unwrap_or(0.0) + increment;
+                if updated.is_nan() {
+                    return Ok(Err("ERR resulting score is not a number (NaN)"));
+                }
+                slot.zset_mut()?.insert(args[2].clone(), updated);
+                Ok(Ok(updated))
+            })
+            .await;
+
+        match result {
+            Ok(Ok(score)) => {
+                self.notify(NOTIFY_ZSET, "zincr", args[0]);
+                self.double_reply(score)
+            }
+            Ok(Err(msg)) => RespValue::Error(msg.to_string()),
+            Err(e) => storage_error("zincrby", e),
+        }
+    }
+
+    /// ZREM key member [member ...]
+    pub(super) async fn handle_zrem(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("zrem");
+        }
+
+        let members = &args[1..];
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                if slot.zset()?.is_none() {
+                    return Ok(0);
+                }
+                let zset = slot.zset_mut()?;
+                Ok(members
+                    .iter()
+                    .filter(|member| zset.remove(member).is_some())
+                    .count())
+            })
+            .await;
+
+        match result {
+            Ok(removed) => {
+                if removed > 0 {
+                    self.notify(NOTIFY_ZSET, "zrem", args[0]);
+                    self.notify_if_removed(args[0]).await;
+                }
+                RespValue::Integer(removed as i64)
+            }
+            Err(e) => storage_error("zrem", e),
+        }
+    }
+
+    /// ZCARD key
+    pub(super) async fn handle_zcard(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 1 {
+            return wrong_args("zcard");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_zset()?.len(),
+                    None => 0,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(len) => RespValue::Integer(len as i64),
+            Err(e) => storage_error("zcard", e),
+        }
+    }
+
+    /// ZSCORE key member
+    pub(super) async fn handle_zscore(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() != 2 {
+            return wrong_args("zscore");
+        }
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value.as_zset()?.score(args[1]),
+                    None => None,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(Some(score)) => self.double_reply(score),
+            Ok(None) => RespValue::BulkString(None),
+            Err(e) => storage_error("zscore", e),
+        }
+    }
+
+    /// ZMSCORE key member [member ...]
+    pub(super) async fn handle_zmscore(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("zmscore");
+        }
+
+        let members = &args[1..];
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let zset = value.map(|v| v.as_zset()).transpose()?;
+                Ok(members
+                    .iter()
+                    .map(|member| zset.and_then(|z| z.score(member)))
+                    .collect::<Vec<_>>())
+            })
+            .await;
+
+        match result {
+            Ok(scores) => RespValue::Array(Some(
+                scores
+                    .into_iter()
+                    .map(|score| match score {
+                        Some(score) => self.double_reply(score),
+                        None => RespValue::BulkString(None),
+                    })
+                    .collect(),
+            )),
+            Err(e) => storage_error("zmscore", e),
+        }
+    }
+
+    /// ZRANK/ZREVRANK key member [WITHSCORE]
+    pub(super) async fn handle_zrank(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        rev: bool,
+    ) -> RespValue {
+        if args.len() < 2 || args.len() > 3 {
+            return wrong_args(command);
+        }
+        let with_score = match args.get(2) {
+            Some(flag) if flag.eq_ignore_ascii_case(b"WITHSCORE") => true,
+            Some(_) => return syntax_error(),
+            None => false,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(zset) = value.map(|v| v.as_zset()).transpose()? else {
+                    return Ok(None);
+                };
+                Ok(zset.rank(args[1]).map(|rank| {
+                    let rank = if rev { zset.len() - 1 - rank } else { rank };
+                    (rank, zset.score(args[1]).unwrap_or_default())
+                }))
+            })
+            .await;
+
+        match result {
+            Ok(Some((rank, score))) if with_score => RespValue::Array(Some(vec![
+                RespValue::Integer(rank as i64),
+                self.double_reply(score),
+            ])),
+            Ok(Some((rank, _))) => RespValue::Integer(rank as i64),
+            Ok(None) if with_score => self.null_array(),
+            Ok(None) => RespValue::BulkString(None),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// ZCOUNT key min max / ZLEXCOUNT key min max
+    pub(super) async fn handle_zcount(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        by: RangeBy,
+    ) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args(command);
+        }
+        let range = match parse_range(by, args[1], args[2]) {
+            Ok(range) => range,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => {
+                        let (start, end) = range.ranks(value.as_zset()?, false);
+                        end - start
+                    }
+                    None => 0,
+                })
+            })
+            .await;
+
+        match result {
+            Ok(count) => RespValue::Integer(count as i64),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// ZRANGE key start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES],
+    /// and the legacy ZREVRANGE/ZRANGEBYSCORE/ZRANGEBYLEX forms via `fixed`.
+    pub(super) async fn handle_zrange(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        fixed: Option<(RangeBy, bool)>,
+    ) -> RespValue {
+        if args.len() < 3 {
+            return wrong_args(command);
+        }
+        let range = match ZRangeArgs::parse(&args[1..], fixed) {
+            Ok(range) => range,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .inspect(args[0], |value| {
+                let Some(zset) = value.map(|v| v.as_zset()).transpose()? else {
+                    return Ok(Vec::new());
+                };
+                if range.offset < 0 {
+                    return Ok(Vec::new());
+                }
+
+                let (start, end) = range.range.ranks(zset, range.rev);
+                let entries = zset.range(start, end).map(|(m, s)| (m.clone(), s));
+                let entries: Box<dyn Iterator<Item = (Bytes, f64)>> = if range.rev {
+                    Box::new(entries.rev())
+                } else {
+                    Box::new(entries)
+                };
+                let count = usize::try_from(range.count).unwrap_or(usize::MAX);
+                Ok(entries.skip(range.offset as usize).take(count).collect())
+            })
+            .await;
+
+        match result {
+            Ok(entries) => self.scored_reply(entries, range.with_scores),
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// ZREMRANGEBYRANK key start stop / ZREMRANGEBYSCORE key min max /
+    /// ZREMRANGEBYLEX key min max
+    pub(super) async fn handle_zremrange(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        by: RangeBy,
+    ) -> RespValue {
+        if args.len() != 3 {
+            return wrong_args(command);
+        }
+        let range = match parse_range(by, args[1], args[2]) {
+            Ok(range) => range,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some(zset) = slot.zset()? else {
+                    return Ok(0);
+                };
+                let (start, end) = range.ranks(zset, false);
+                if start == end {
+                    return Ok(0);
+                }
+                Ok(slot.zset_mut()?.drain(start, end).len())
+            })
+            .await;
+
+        match result {
+            Ok(removed) => {
+                if removed > 0 {
+                    self.notify(NOTIFY_ZSET, command, args[0]);
+                    self.notify_if_removed(args[0]).await;
+                }
+                RespValue::Integer(removed as i64)
+            }
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// ZPOPMIN/ZPOPMAX key [count]
+    pub(super) async fn handle_zpop(&self, args: &[&Bytes], command: &str, max: bool) -> RespValue {
+        if args.is_empty() || args.len() > 2 {
+            return wrong_args(command);
+        }
+        let count = match args.get(1).map(|c| parse_int(c)).transpose() {
+            Ok(Some(count)) if count < 0 => {
+                return RespValue::Error("ERR value is out of range, must be positive".to_string())
+            }
+            Ok(count) => count,
+            Err(e) => return e,
+        };
+
+        let result = self
+            .storage
+            .modify(args[0], |slot| {
+                let Some(zset) = slot.zset()? else {
+                    return Ok(Vec::new());
+                };
+                let take = (count.unwrap_or(1) as usize).min(zset.len());
+                if take == 0 {
+                    return Ok(Vec::new());
+                }
+                let zset = slot.zset_mut()?;
+                Ok(if max {
+                    let len = zset.len();
+                    let mut popped = zset.drain(len - take, len);
+                    popped.reverse();
+                    popped
+                } else {
+                    zset.drain(0, take)
+                })
+            })
+            .await;
+        if matches!(&result, Ok(popped) if !popped.is_empty()) {
+            self.notify(NOTIFY_ZSET, command, args[0]);
+            self.notify_if_removed(args[0]).await;
+        }
+
+        match (result, count) {
+            // Without a count the reply is a flat [member, score] in both protocols
+            (Ok(popped), None) => RespValue::Array(Some(
+                popped
+                    .into_iter()
+                    .flat_map(|(member, score)| {
+                        [
+                            RespValue::BulkString(Some(member)),
+                            self.double_reply(score),
+                        ]
+                    })
+                    .collect(),
+            )),
+            (Ok(popped), Some(_)) => self.scored_reply(popped, true),
+            (Err(e), _) => storage_error(command, e),
+        }
+    }
+
+    /// ZUNIONSTORE/ZINTERSTORE destination numkeys key [key ...]
+    /// [WEIGHTS weight [weight ...]] [AGGREGATE SUM|MIN|MAX]
+    pub(super) async fn handle_zstore(
+        &self,
+        args: &[&Bytes],
+        command: &str,
+        inter: bool,
+    ) -> RespValue {
+        if args.len() < 3 {
+            return wrong_args(command);
+        }
+        let numkeys = match parse_int(args[1]) {
+            Ok(numkeys) if numkeys <= 0 => {
+                return RespValue::Error(format!(
+                    "ERR at least 1 input key is needed for '{}' command",
+                    command
+                ))
+            }
+            Ok(numkeys) => numkeys as usize,
+            Err(e) => return e,
+        };
+        if numkeys > args.len() - 2 {
+            return syntax_error();
+        }
+
+        let sources = &args[2..2 + numkeys];
+        let mut weights = vec![1.0; numkeys];
+        let mut aggregate = Aggregate::Sum;
+        let mut i = 2 + numkeys;
+        while i < args.len() {
+            let option = args[i];
+            if option.eq_ignore_ascii_case(b"WEIGHTS") && i + numkeys < args.len() {
+                for (weight, arg) in weights.iter_mut().zip(&args[i + 1..=i + numkeys]) {
+                    match parse_utf8::<f64>(arg).filter(|w| !w.is_nan()) {
+                        Some(w) => *weight = w,
+                        None => {
+                            return RespValue::Error("ERR weight value is not a float".to_string())
+                        }
+                    }
+                }
+                i += numkeys + 1;
+            } else if option.eq_ignore_ascii_case(b"AGGREGATE") && i + 1 < args.len() {
+                let kind = args[i + 1];
+                aggregate = if kind.eq_ignore_ascii_case(b"SUM") {
+                    Aggregate::Sum
+                } else if kind.eq_ignore_ascii_case(b"MIN") {
+                    Aggregate::Min
+                } else if kind.eq_ignore_ascii_case(b"MAX") {
+                    Aggregate::Max
+                } else {
+                    return syntax_error();
+                };
+                i += 2;
+            } else {
+                return syntax_error();
+            }
+        }
+
+        // The destination may also be a source, so share its slot
+        let mut requested = vec![args[0]];
+        requested.extend_from_slice(sources);
+        let (keys, indexes) = distinct_keys(&requested);
+
+        let result = self
+            .storage
+            .modify_many(&keys, |slots| {
+                let mut combined: Option<HashMap<Bytes, f64>> = None;
+                for (&index, &weight) in indexes[1..].iter().zip(&weights) {
+                    let scores = source_scores(&slots[index])?.into_iter().map(|(m, s)| {
+                        // 0 * inf is NaN, which Redis treats as 0
+                        let weighted = s * weight;
+                        (m, if weighted.is_nan() { 0.0 } else { weighted })
+                    });
+                    combined = Some(match combined {
+                        None => scores.collect(),
+                        Some(mut acc) if inter => {
+                            let scores: HashMap<Bytes, f64> = scores.collect();
+                            acc.retain(|member, score| match scores.get(member) {
+                                Some(&other) => {
+                                    *score = aggregate.combine(*score, other);
+                                    true
+                                }
+                                None => false,
+                            });
+                            acc
+                        }
+                        Some(mut acc) => {
+                            for (member, score) in scores {
+                                acc.entry(member)
+                                    .and_modify(|acc| *acc = aggregate.combine(*acc, score))
+                                    .or_insert(score);
+                            }
+                            acc
+                        }
+                    });
+                }
+
+                let zset: SortedSet = combined
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(member, score)| (score, member))
+                    .collect::<Vec<_>>()
+                    .into();
+                let len = zset.len();
+                let destination = &mut slots[indexes[0]];
+                let existed = destination.get().is_some();
+                if zset.is_empty() {
+                    destination.delete();
+                } else {
+                    destination.set(StorageValue::new(ValueData::SortedSet(zset)));
+                }
+                Ok((len, existed))
+            })
+            .await;
+
+        match result {
+            Ok((len, existed)) => {
+                self.notify_stored(NOTIFY_ZSET, command, args[0], len, existed);
+                RespValue::Integer(len as i64)
+            }
+            Err(e) => storage_error(command, e),
+        }
+    }
+
+    /// ZSCAN key cursor [MATCH pattern] [COUNT count]
+    pub(super) async fn handle_zscan(&self, args: &[&Bytes]) -> RespValue {
+        if args.len() < 2 {
+            return wrong_args("zscan");
+        }
+        let scan = match ScanArgs::parse(&args[1..], ScanKind::Members) {
+            Ok(scan) => scan,
+            Err(e) => return e,
+        };
+
+        let entries = self
+            .storage
+            .inspect(args[0], |value| {
+                Ok(match value {
+                    Some(value) => value
+                        .as_zset()?
+                        .iter()
+                        .map(|(m, s)| (m.clone(), s))
+                        .collect(),
+                    None => Vec::new(),
+                })
+            })
+            .await;
+        let entries: Vec<(Bytes, f64)> = match entries {
+            Ok(entries) => entries,
+            Err(e) => return storage_error("zscan", e),
+        };
+
+        let (cursor, batch) = scan_batch(entries.into_iter(), scan.cursor, scan.count);
+        let items = batch
+            .into_iter()
+            .filter(|(member, _)| scan.matches(member))
+            .flat_map(|(member, score)| {
+                [
+                    RespValue::BulkString(Some(member)),
+                    RespValue::BulkString(Some(format_float(score).into())),
+                ]
+            })
+            .collect();
+
+        RespValue::Array(Some(vec![
+            RespValue::BulkString(Some(cursor.to_string().into())),
+            RespValue::Array(Some(items)),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::StorageBackend;
+    use std::sync::Arc;
+
+    fn create_handler() -> Handler {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        Handler::new(storage)
+    }
+
+    fn b(s: &str) -> Bytes {
+        Bytes::from(s.to_string())
+    }
+
+    fn strings(value: RespValue) -> Vec<String> {
+        match value {
+            RespValue::Array(Some(items)) => items
+                .into_iter()
+                .map(|item| match item {
+                    RespValue::BulkString(Some(s)) => String::from_utf8(s.to_vec()).unwrap(),
+                    other => panic!("Expected BulkString, got {:?}", other),
+                })
+                .collect(),
+            other => panic!("Expected Array, got {:?}", other),
+        }
+    }
+
+    async fn zadd(handler: &Handler, key: &str, pairs: &[(&str, &str)]) {
+        let mut args = vec![b(key)];
+        for (score, member) in pairs {
+            args.push(b(score));
+            args.push(b(member));
+        }
+        let args: Vec<&Bytes> = args.iter().collect();
+        assert!(matches!(
+            handler.handle_zadd(&args).await,
+            RespValue::Integer(_)
+        ));
+    }
+
+    async fn zrange(handler: &Handler, args: &[&str]) -> Vec<String> {
+        let args: Vec<Bytes> = args.iter().map(|a| b(a)).collect();
+        let args: Vec<&Bytes> = args.iter().collect();
+        strings(handler.handle_zrange(&args, "zrange", None).await)
+    }
+
+    #[tokio::test]
+    async fn test_zadd_flags() {
+        let handler = create_handler();
+        let key = b("z");
+        zadd(&handler, "z", &[("1", "a"), ("2", "b")]).await;
+
+        // NX only adds, XX only updates, CH counts changed members
+        let result = handler
+            .handle_zadd(&[&key, &b("NX"), &b("5"), &b("a"), &b("3"), &b("c")])
+            .await;
+        assert!(matches!(result, RespValue::Integer(1)));
+        let result = handler
+            .handle_zadd(&[&key, &b("XX"), &b("CH"), &b("5"), &b("a"), &b("4"), &b("d")])
+            .await;
+        assert!(matches!(result, RespValue::Integer(1)));
+        assert_eq!(zrange(&handler, &["z", "0", "-1"]).await, ["b", "c", "a"]);
+
+        // GT refuses to lower a score; INCR returns the new score or nil
+        let result = handler
+            .handle_zadd(&[&key, &b("GT"), &b("INCR"), &b("-1"), &b("a")])
+            .await;
+        assert!(matches!(result, RespValue::BulkString(None)));
+        match handler
+            .handle_zadd(&[&key, &b("INCR"), &b("2.5"), &b("a")])
+            .await
+        {
+            RespValue::BulkString(Some(score)) => assert_eq!(score, "7.5"),
+            other => panic!("Expected BulkString, got {:?}", other),
+        }
+
+        assert!(matches!(
+            handler
+                .handle_zadd(&[&key, &b("NX"), &b("XX"), &b("1"), &b("a")])
+                .await,
+            RespValue::Error(_)
+        ));
+        assert!(matches!(
+            handler
+                .handle_zadd(&[&key, &b("GT"), &b("LT"), &b("1"), &b("a")])
+                .await,
+            RespValue::Error(_)
+        ));
+        assert!(matches!(
+            handler.handle_zadd(&[&key, &b("nan"), &b("a")]).await,
+            RespValue::Error(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_zrange_variants() {
+        let handler = create_handler();
+        zadd(
+            &handler,
+            "z",
+            &[("1", "a"), ("2", "b"), ("3", "c"), ("4", "d"), ("5", "e")],
+        )
+        .await;
+
+        assert_eq!(zrange(&handler, &["z", "1", "2"]).await, ["b", "c"]);
+        assert_eq!(zrange(&handler, &["z", "0", "1", "REV"]).await, ["e", "d"]);
+        assert_eq!(
+            zrange(&handler, &["z", "(1", "4", "BYSCORE", "LIMIT", "1", "2"]).await,
+            ["c", "d"]
+        );
+        assert_eq!(
+            zrange(&handler, &["z", "+inf", "(3", "BYSCORE", "REV"]).await,
+            ["e", "d"]
+        );
+        assert_eq!(
+            zrange(&handler, &["z", "0", "0", "WITHSCORES"]).await,
+            ["a", "1"]
+        );
+        assert_eq!(
+            zrange(&handler, &["z", "[b", "(d", "BYLEX"]).await,
+            ["b", "c"]
+        );
+        assert_eq!(
+            zrange(&handler, &["z", "+", "[d", "BYLEX", "REV"]).await,
+            ["e", "d"]
+        );
+
+        let (key, low, high) = (b("z"), b("2"), b("4"));
+        let result = handler
+            .handle_zrange(
+                &[&key, &high, &low],
+                "zrevrangebyscore",
+                Some((RangeBy::Score, true)),
+            )
+            .await;
+        assert_eq!(strings(result), ["d", "c", "b"]);
+
+        assert!(matches!(
+            handler
+                .handle_zrange(
+                    &[&key, &low, &high, &b("LIMIT"), &b("0"), &b("1")],
+                    "zrange",
+                    None
+                )
+                .await,
+            RespValue::Error(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rank_score_count_and_resp3_doubles() {
+        let mut handler = create_handler();
+        zadd(&handler, "z", &[("1.5", "a"), ("2", "b"), ("2", "c")]).await;
+        let key = b("z");
+
+        assert!(matches!(
+            handler.handle_zrank(&[&key, &b("c")], "zrank", false).await,
+            RespValue::Integer(2)
+        ));
+        assert!(matches!(
+            handler
+                .handle_zrank(&[&key, &b("c")], "zrevrank", true)
+                .await,
+            RespValue::Integer(0)
+        ));
+        assert!(matches!(
+            handler.handle_zrank(&[&key, &b("x")], "zrank", false).await,
+            RespValue::BulkString(None)
+        ));
+        assert!(matches!(
+            handler
+                .handle_zcount(&[&key, &b("(1.5"), &b("+inf")], "zcount", RangeBy::Score)
+                .await,
+            RespValue::Integer(2)
+        ));
+        assert!(matches!(
+            handler
+                .handle_zcount(&[&key, &b("-"), &b("+")], "zlexcount", RangeBy::Lex)
+                .await,
+            RespValue::Integer(3)
+        ));
+
+        match handler.handle_zscore(&[&key, &b("a")]).await {
+            RespValue::BulkString(Some(score)) => assert_eq!(score, "1.5"),
+            other => panic!("Expected BulkString, got {:?}", other),
+        }
+        handler.set_protocol_version(ProtocolVersion::Resp3);
+        assert!(matches!(
+            handler.handle_zscore(&[&key, &b("a")]).await,
+            RespValue::Double(score) if score == 1.5
+        ));
+        match handler.handle_zmscore(&[&key, &b("b"), &b("x")]).await {
+            RespValue::Array(Some(items)) => {
+                assert!(matches!(items[0], RespValue::Double(score) if score == 2.0));
+                assert!(matches!(items[1], RespValue::BulkString(None)));
+            }
+            other => panic!("Expected Array, got {:?}", other),
+        }
+        match handler.handle_zpop(&[&key, &b("2")], "zpopmax", true).await {
+            RespValue::Array(Some(items)) => {
+                assert_eq!(items.len(), 2);
+                match &items[0] {
+                    RespValue::Array(Some(pair)) => {
+                        assert!(matches!(&pair[0], RespValue::BulkString(Some(m)) if m == "c"));
+                        assert!(matches!(pair[1], RespValue::Double(score) if score == 2.0));
+                    }
+                    other => panic!("Expected pair, got {:?}", other),
+                }
+            }
+            other => panic!("Expected Array, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zremrange_and_zpop() {
+        let handler = create_handler();
+        zadd(
+            &handler,
+            "z",
+            &[("1", "a"), ("2", "b"), ("3", "c"), ("4", "d"), ("5", "e")],
+        )
+        .await;
+        let key = b("z");
+
+        let result = handler
+            .handle_zremrange(&[&key, &b("0"), &b("0")], "zremrangebyrank", RangeBy::Rank)
+            .await;
+        assert!(matches!(result, RespValue::Integer(1)));
+        let result = handler
+            .handle_zremrange(
+                &[&key, &b("(4"), &b("inf")],
+                "zremrangebyscore",
+                RangeBy::Score,
+            )
+            .await;
+        assert!(matches!(result, RespValue::Integer(1)));
+        assert_eq!(zrange(&handler, &["z", "0", "-1"]).await, ["b", "c", "d"]);
+
+        assert_eq!(
+            strings(handler.handle_zpop(&[&key], "zpopmin", false).await),
+            ["b", "2"]
+        );
+        assert_eq!(
+            strings(handler.handle_zpop(&[&key, &b("5")], "zpopmax", true).await),
+            ["d", "4", "c", "3"]
+        );
+        assert!(!handler.storage.exists(&key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_zunionstore_zinterstore() {
+        let handler = create_handler();
+        zadd(&handler, "z1", &[("1", "a"), ("2", "b")]).await;
+        zadd(&handler, "z2", &[("10", "b"), ("20", "c")]).await;
+        handler.storage.set(b"str", b"value").await.unwrap();
+
+        let (dest, two, z1, z2) = (b("dest"), b("2"), b("z1"), b("z2"));
+        let result = handler
+            .handle_zstore(
+                &[&dest, &two, &z1, &z2, &b("WEIGHTS"), &b("2"), &b("1")],
+                "zunionstore",
+                false,
+            )
+            .await;
+        assert!(matches!(result, RespValue::Integer(3)));
+        assert_eq!(
+            zrange(&handler, &["dest", "0", "-1", "WITHSCORES"]).await,
+            ["a", "2", "b", "14", "c", "20"]
+        );
+
+        // The destination can be one of the sources
+        let result = handler
+            .handle_zstore(
+                &[&z1, &two, &z1, &z2, &b("AGGREGATE"), &b("MAX")],
+                "zinterstore",
+                true,
+            )
+            .await;
+        assert!(matches!(result, RespValue::Integer(1)));
+        assert_eq!(
+            zrange(&handler, &["z1", "0", "-1", "WITHSCORES"]).await,
+            ["b", "10"]
+        );
+
+        assert!(matches!(
+            handler
+                .handle_zstore(&[&dest, &two, &z1, &b("str")], "zunionstore", false)
+                .await,
+            RespValue::Error(msg) if msg.starts_with("WRONGTYPE")
+        ));
+        assert!(matches!(
+            handler
+                .handle_zstore(&[&dest, &b("0"), &z1], "zunionstore", false)
+                .await,
+            RespValue::Error(_)
+        ));
+    }
+}
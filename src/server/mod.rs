@@ -1,5 +1,15 @@
 //! TCP server and command handling.
 
+pub mod blocking;
+pub mod clients;
+pub mod context;
+pub mod cursors;
 pub mod handler;
+pub mod notify;
+pub mod pubsub;
+pub mod scripts;
+pub mod tracking;
+pub mod watch;
 
+pub use context::ServerContext;
 pub use handler::*;
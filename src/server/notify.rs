@@ -0,0 +1,162 @@
+//! Keyspace notifications (`notify-keyspace-events`).
+//!
+//! Commands report the keys they modify, with an event name and the class
+//! of the event, and the server publishes them on the
+//! `__keyspace@0__:<key>` and `__keyevent@0__:<event>` channels when the
+//! configured flags enable that class. Flags are given as in Redis: a
+//! string of class characters, plus `K` and `E` to pick the channels.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Publish on `__keyspace@0__:<key>` (`K`).
+pub const NOTIFY_KEYSPACE: u32 = 1 << 0;
+/// Publish on `__keyevent@0__:<event>` (`E`).
+pub const NOTIFY_KEYEVENT: u32 = 1 << 1;
+/// Type-independent commands like DEL, EXPIRE and RENAME (`g`).
+pub const NOTIFY_GENERIC: u32 = 1 << 2;
+/// String commands (`$`).
+pub const NOTIFY_STRING: u32 = 1 << 3;
+/// List commands (`l`).
+pub const NOTIFY_LIST: u32 = 1 << 4;
+/// Set commands (`s`).
+pub const NOTIFY_SET: u32 = 1 << 5;
+/// Hash commands (`h`).
+pub const NOTIFY_HASH: u32 = 1 << 6;
+/// Sorted set commands (`z`).
+pub const NOTIFY_ZSET: u32 = 1 << 7;
+/// Keys removed because they expired (`x`).
+pub const NOTIFY_EXPIRED: u32 = 1 << 8;
+/// Keys evicted for memory (`e`). Coral never evicts.
+pub const NOTIFY_EVICTED: u32 = 1 << 9;
+/// Stream commands (`t`).
+pub const NOTIFY_STREAM: u32 = 1 << 10;
+/// Reads of missing keys (`m`).
+pub const NOTIFY_KEY_MISS: u32 = 1 << 11;
+/// Module events (`d`). Coral has no modules.
+pub const NOTIFY_MODULE: u32 = 1 << 13;
+/// Keys created (`n`).
+pub const NOTIFY_NEW: u32 = 1 << 14;
+/// Every class `A` stands for: all but key misses and new keys.
+pub const NOTIFY_ALL: u32 = NOTIFY_GENERIC
+    | NOTIFY_STRING
+    | NOTIFY_LIST
+    | NOTIFY_SET
+    | NOTIFY_HASH
+    | NOTIFY_ZSET
+    | NOTIFY_EXPIRED
+    | NOTIFY_EVICTED
+    | NOTIFY_STREAM
+    | NOTIFY_MODULE;
+
+/// Class characters, in the order Redis prints them.
+const CLASSES: [(char, u32); 10] = [
+    ('g', NOTIFY_GENERIC),
+    ('$', NOTIFY_STRING),
+    ('l', NOTIFY_LIST),
+    ('s', NOTIFY_SET),
+    ('h', NOTIFY_HASH),
+    ('z', NOTIFY_ZSET),
+    ('x', NOTIFY_EXPIRED),
+    ('e', NOTIFY_EVICTED),
+    ('t', NOTIFY_STREAM),
+    ('d', NOTIFY_MODULE),
+];
+
+/// Flags of a `notify-keyspace-events` string, `None` if it has a
+/// character that is not a class.
+pub fn parse_flags(classes: &str) -> Option<u32> {
+    classes.chars().try_fold(0, |flags, c| {
+        let flag = match c {
+            'A' => NOTIFY_ALL,
+            'K' => NOTIFY_KEYSPACE,
+            'E' => NOTIFY_KEYEVENT,
+            'm' => NOTIFY_KEY_MISS,
+            'n' => NOTIFY_NEW,
+            _ => CLASSES.iter().find(|(class, _)| *class == c)?.1,
+        };
+        Some(flags | flag)
+    })
+}
+
+/// `notify-keyspace-events` string of `flags`, as CONFIG GET prints it.
+pub fn format_flags(flags: u32) -> String {
+    let mut classes = String::new();
+    if flags & NOTIFY_ALL == NOTIFY_ALL {
+        classes.push('A');
+    } else {
+        for (class, flag) in CLASSES {
+            if flags & flag != 0 {
+                classes.push(class);
+            }
+        }
+    }
+    for (class, flag) in [
+        ('K', NOTIFY_KEYSPACE),
+        ('E', NOTIFY_KEYEVENT),
+        ('m', NOTIFY_KEY_MISS),
+        ('n', NOTIFY_NEW),
+    ] {
+        if flags & flag != 0 {
+            classes.push(class);
+        }
+    }
+    classes
+}
+
+/// The `notify-keyspace-events` setting of a running server.
+#[derive(Default)]
+pub struct KeyspaceEvents {
+    flags: AtomicU32,
+}
+
+impl KeyspaceEvents {
+    pub fn new(flags: u32) -> Self {
+        Self {
+            flags: AtomicU32::new(flags),
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags.load(Ordering::Relaxed)
+    }
+
+    pub fn set_flags(&self, flags: u32) {
+        self.flags.store(flags, Ordering::Relaxed);
+    }
+
+    /// Whether events of `class` are published on any channel.
+    pub fn enabled(&self, class: u32) -> bool {
+        let flags = self.flags();
+        flags & class != 0 && flags & (NOTIFY_KEYSPACE | NOTIFY_KEYEVENT) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_round_trip() {
+        assert_eq!(parse_flags(""), Some(0));
+        assert_eq!(parse_flags("Ex"), Some(NOTIFY_KEYEVENT | NOTIFY_EXPIRED));
+        assert_eq!(parse_flags("KEA").map(format_flags).as_deref(), Some("AKE"));
+        assert_eq!(parse_flags("Kg$lshzxetd"), parse_flags("AK"));
+        assert_eq!(
+            parse_flags("Elgmn").map(format_flags).as_deref(),
+            Some("glEmn")
+        );
+        // A leaves out key misses and new keys
+        assert_eq!(
+            parse_flags("A").unwrap() & (NOTIFY_KEY_MISS | NOTIFY_NEW),
+            0
+        );
+        assert_eq!(parse_flags("Kq"), None);
+        assert_eq!(parse_flags("k"), None);
+
+        let events = KeyspaceEvents::new(parse_flags("g$").unwrap());
+        assert!(!events.enabled(NOTIFY_GENERIC));
+        events.set_flags(parse_flags("g$K").unwrap());
+        assert!(events.enabled(NOTIFY_STRING));
+        assert!(!events.enabled(NOTIFY_LIST));
+    }
+}
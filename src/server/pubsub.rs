@@ -0,0 +1,429 @@
+//! Channels of pub/sub messaging (SUBSCRIBE, PSUBSCRIBE, SSUBSCRIBE,
+//! PUBLISH, SPUBLISH).
+//!
+//! Every connection owns a [`Subscriber`]: a mailbox other connections post
+//! messages to, and the channels and patterns it listens on. The registry
+//! maps channels and patterns to the mailboxes of their subscribers, so a
+//! publisher reaches them without waiting on their connections, which
+//! deliver the messages whenever they are not busy with a command.
+//!
+//! Shard channels are kept apart, in one registry per hash slot like Redis
+//! Cluster shards them, and are not matched by patterns.
+//!
+//! Mailboxes are also reachable by subscriber id, which is the client id,
+//! for messages addressed to one client such as tracking invalidations.
+
+use crate::glob::glob_match;
+use crate::protocol::RespValue;
+use crate::slot::{key_slot, SLOTS};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Items of a message frame, like `["message", channel, payload]`. The
+/// receiving connection sends them as a push or an array, depending on its
+/// protocol.
+pub type Message = Vec<RespValue>;
+
+/// Mailboxes subscribed to each channel or pattern, by subscriber id.
+type Subscribers = HashMap<Bytes, HashMap<u64, UnboundedSender<Message>>>;
+
+/// A connection's mailbox and subscriptions.
+pub struct Subscriber {
+    id: u64,
+    sender: UnboundedSender<Message>,
+    receiver: UnboundedReceiver<Message>,
+    /// Subscribed channels, patterns and shard channels, in subscription
+    /// order.
+    channels: Vec<Bytes>,
+    patterns: Vec<Bytes>,
+    shard_channels: Vec<Bytes>,
+}
+
+impl Subscriber {
+    /// The client id, unique for the lifetime of the server.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Number of channels and patterns subscribed to.
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Number of shard channels subscribed to.
+    pub fn shard_count(&self) -> usize {
+        self.shard_channels.len()
+    }
+
+    pub fn channels(&self) -> &[Bytes] {
+        &self.channels
+    }
+
+    pub fn patterns(&self) -> &[Bytes] {
+        &self.patterns
+    }
+
+    pub fn shard_channels(&self) -> &[Bytes] {
+        &self.shard_channels
+    }
+
+    /// Wait for the next message. Cancel safe.
+    pub async fn receive(&mut self) -> Message {
+        match self.receiver.recv().await {
+            Some(message) => message,
+            // The subscriber holds a sender itself
+            None => unreachable!("mailbox closed"),
+        }
+    }
+
+    /// Next message if one is waiting.
+    pub fn try_receive(&mut self) -> Option<Message> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+#[derive(Default)]
+struct Channels {
+    channels: Subscribers,
+    patterns: Subscribers,
+}
+
+/// Channels and patterns subscribed to by all connections.
+pub struct PubSubRegistry {
+    channels: Mutex<Channels>,
+    /// Shard channels, by hash slot.
+    shards: Box<[Mutex<Subscribers>]>,
+    /// Mailbox of every connection, by subscriber id.
+    mailboxes: Mutex<HashMap<u64, UnboundedSender<Message>>>,
+    next_id: AtomicU64,
+}
+
+impl Default for PubSubRegistry {
+    fn default() -> Self {
+        Self {
+            channels: Mutex::default(),
+            shards: (0..SLOTS).map(|_| Mutex::default()).collect(),
+            mailboxes: Mutex::default(),
+            // Client ids start at 1 like in Redis, where 0 means none
+            next_id: AtomicU64::new(1),
+        }
+    }
+}
+
+fn bulk(data: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(Bytes::copy_from_slice(data)))
+}
+
+impl PubSubRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A mailbox for a new connection, subscribed to nothing.
+    pub fn subscriber(&self) -> Subscriber {
+        let (sender, receiver) = unbounded_channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.mailboxes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, sender.clone());
+        Subscriber {
+            id,
+            sender,
+            receiver,
+            channels: Vec::new(),
+            patterns: Vec::new(),
+            shard_channels: Vec::new(),
+        }
+    }
+
+    /// Subscribe to `channel`. Returns false if already subscribed.
+    pub fn subscribe(&self, subscriber: &mut Subscriber, channel: &Bytes) -> bool {
+        if subscriber.channels.contains(channel) {
+            return false;
+        }
+        add(&mut self.lock().channels, subscriber, channel);
+        subscriber.channels.push(channel.clone());
+        true
+    }
+
+    /// Unsubscribe from `channel`. Returns false if not subscribed.
+    pub fn unsubscribe(&self, subscriber: &mut Subscriber, channel: &[u8]) -> bool {
+        let Some(i) = subscriber.channels.iter().position(|c| c == channel) else {
+            return false;
+        };
+        subscriber.channels.remove(i);
+        remove(&mut self.lock().channels, subscriber.id, channel);
+        true
+    }
+
+    /// Subscribe to channels matching `pattern`. Returns false if already
+    /// subscribed.
+    pub fn psubscribe(&self, subscriber: &mut Subscriber, pattern: &Bytes) -> bool {
+        if subscriber.patterns.contains(pattern) {
+            return false;
+        }
+        add(&mut self.lock().patterns, subscriber, pattern);
+        subscriber.patterns.push(pattern.clone());
+        true
+    }
+
+    /// Unsubscribe from `pattern`. Returns false if not subscribed.
+    pub fn punsubscribe(&self, subscriber: &mut Subscriber, pattern: &[u8]) -> bool {
+        let Some(i) = subscriber.patterns.iter().position(|p| p == pattern) else {
+            return false;
+        };
+        subscriber.patterns.remove(i);
+        remove(&mut self.lock().patterns, subscriber.id, pattern);
+        true
+    }
+
+    /// Subscribe to the shard channel `channel`. Returns false if already
+    /// subscribed.
+    pub fn ssubscribe(&self, subscriber: &mut Subscriber, channel: &Bytes) -> bool {
+        if subscriber.shard_channels.contains(channel) {
+            return false;
+        }
+        add(&mut self.lock_shard(channel), subscriber, channel);
+        subscriber.shard_channels.push(channel.clone());
+        true
+    }
+
+    /// Unsubscribe from the shard channel `channel`. Returns false if not
+    /// subscribed.
+    pub fn sunsubscribe(&self, subscriber: &mut Subscriber, channel: &[u8]) -> bool {
+        let Some(i) = subscriber.shard_channels.iter().position(|c| c == channel) else {
+            return false;
+        };
+        subscriber.shard_channels.remove(i);
+        remove(&mut self.lock_shard(channel), subscriber.id, channel);
+        true
+    }
+
+    /// Drop every subscription of a connection.
+    pub fn unsubscribe_all(&self, subscriber: &mut Subscriber) {
+        if subscriber.count() > 0 {
+            let mut channels = self.lock();
+            for channel in subscriber.channels.drain(..) {
+                remove(&mut channels.channels, subscriber.id, &channel);
+            }
+            for pattern in subscriber.patterns.drain(..) {
+                remove(&mut channels.patterns, subscriber.id, &pattern);
+            }
+        }
+        for channel in subscriber.shard_channels.drain(..) {
+            remove(&mut self.lock_shard(&channel), subscriber.id, &channel);
+        }
+    }
+
+    /// Drop every subscription of a connection going away, and its mailbox.
+    pub fn close(&self, subscriber: &mut Subscriber) {
+        self.unsubscribe_all(subscriber);
+        self.mailboxes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&subscriber.id);
+    }
+
+    /// Post `message` to the mailbox of client `id`. Returns false if no such
+    /// client is connected.
+    pub fn post(&self, id: u64, message: Message) -> bool {
+        let mailboxes = self.mailboxes.lock().unwrap_or_else(|e| e.into_inner());
+        mailboxes
+            .get(&id)
+            .is_some_and(|sender| sender.send(message).is_ok())
+    }
+
+    /// Whether client `id` is connected.
+    pub fn is_connected(&self, id: u64) -> bool {
+        self.mailboxes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(&id)
+    }
+
+    /// Post `payload` to the subscribers of `channel` and of the patterns
+    /// matching it, returning how many messages were posted.
+    pub fn publish(&self, channel: &[u8], payload: &Bytes) -> usize {
+        let channels = self.lock();
+        let mut posted = 0;
+        for sender in channels
+            .channels
+            .get(channel)
+            .into_iter()
+            .flat_map(|s| s.values())
+        {
+            let message = vec![
+                bulk(b"message"),
+                bulk(channel),
+                RespValue::BulkString(Some(payload.clone())),
+            ];
+            posted += sender.send(message).is_ok() as usize;
+        }
+        for (pattern, senders) in &channels.patterns {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            for sender in senders.values() {
+                let message = vec![
+                    bulk(b"pmessage"),
+                    RespValue::BulkString(Some(pattern.clone())),
+                    bulk(channel),
+                    RespValue::BulkString(Some(payload.clone())),
+                ];
+                posted += sender.send(message).is_ok() as usize;
+            }
+        }
+        posted
+    }
+
+    /// Post `payload` to the subscribers of the shard channel `channel`,
+    /// returning how many messages were posted.
+    pub fn spublish(&self, channel: &[u8], payload: &Bytes) -> usize {
+        let shard = self.lock_shard(channel);
+        let mut posted = 0;
+        for sender in shard.get(channel).into_iter().flat_map(|s| s.values()) {
+            let message = vec![
+                bulk(b"smessage"),
+                bulk(channel),
+                RespValue::BulkString(Some(payload.clone())),
+            ];
+            posted += sender.send(message).is_ok() as usize;
+        }
+        posted
+    }
+
+    /// Channels with subscribers, matching `pattern` if given.
+    pub fn active_channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        self.lock()
+            .channels
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    /// Number of subscribers of `channel`, patterns not included.
+    pub fn subscribers(&self, channel: &[u8]) -> usize {
+        self.lock().channels.get(channel).map_or(0, HashMap::len)
+    }
+
+    /// Number of distinct patterns subscribed to.
+    pub fn patterns(&self) -> usize {
+        self.lock().patterns.len()
+    }
+
+    /// Shard channels with subscribers, matching `pattern` if given.
+    pub fn active_shard_channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        let mut active = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            active.extend(
+                shard
+                    .keys()
+                    .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+                    .cloned(),
+            );
+        }
+        active
+    }
+
+    /// Number of subscribers of the shard channel `channel`.
+    pub fn shard_subscribers(&self, channel: &[u8]) -> usize {
+        self.lock_shard(channel)
+            .get(channel)
+            .map_or(0, HashMap::len)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Channels> {
+        self.channels.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_shard(&self, channel: &[u8]) -> MutexGuard<'_, Subscribers> {
+        self.shards[key_slot(channel)]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn add(subscribers: &mut Subscribers, subscriber: &Subscriber, name: &Bytes) {
+    subscribers
+        .entry(name.clone())
+        .or_default()
+        .insert(subscriber.id, subscriber.sender.clone());
+}
+
+fn remove(subscribers: &mut Subscribers, id: u64, name: &[u8]) {
+    if let Some(senders) = subscribers.get_mut(name) {
+        senders.remove(&id);
+        if senders.is_empty() {
+            subscribers.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_reaches_channels_and_patterns() {
+        let registry = PubSubRegistry::new();
+        let mut first = registry.subscriber();
+        let mut second = registry.subscriber();
+        let news = Bytes::from_static(b"news");
+
+        assert!(registry.subscribe(&mut first, &news));
+        assert!(!registry.subscribe(&mut first, &news));
+        assert!(registry.psubscribe(&mut second, &Bytes::from_static(b"n*")));
+        assert!(registry.subscribe(&mut second, &news));
+        assert_eq!(second.count(), 2);
+        assert_eq!(registry.subscribers(b"news"), 2);
+        assert_eq!(registry.patterns(), 1);
+
+        assert_eq!(registry.publish(b"news", &Bytes::from_static(b"hi")), 3);
+        assert_eq!(registry.publish(b"other", &Bytes::from_static(b"hi")), 0);
+        assert!(
+            matches!(&first.try_receive().unwrap()[..], [_, _, RespValue::BulkString(Some(p))] if p == "hi")
+        );
+        assert!(first.try_receive().is_none());
+        assert_eq!(second.try_receive().unwrap().len(), 3);
+        assert_eq!(second.try_receive().unwrap().len(), 4);
+
+        assert!(registry.unsubscribe(&mut first, b"news"));
+        assert!(!registry.unsubscribe(&mut first, b"news"));
+        registry.unsubscribe_all(&mut second);
+        assert_eq!(second.count(), 0);
+        assert!(registry.active_channels(None).is_empty());
+        assert_eq!(registry.patterns(), 0);
+    }
+
+    #[test]
+    fn test_shard_channels_are_separate() {
+        let registry = PubSubRegistry::new();
+        let mut subscriber = registry.subscriber();
+        let news = Bytes::from_static(b"news");
+
+        assert!(registry.ssubscribe(&mut subscriber, &news));
+        assert!(registry.psubscribe(&mut subscriber, &Bytes::from_static(b"*")));
+        assert_eq!((subscriber.count(), subscriber.shard_count()), (1, 1));
+        assert_eq!(registry.shard_subscribers(b"news"), 1);
+        assert_eq!(registry.subscribers(b"news"), 0);
+
+        // PUBLISH reaches patterns only, SPUBLISH shard channels only
+        assert_eq!(registry.publish(b"news", &Bytes::from_static(b"a")), 1);
+        assert_eq!(registry.spublish(b"news", &Bytes::from_static(b"b")), 1);
+        assert_eq!(subscriber.try_receive().unwrap().len(), 4);
+        assert!(
+            matches!(&subscriber.try_receive().unwrap()[0], RespValue::BulkString(Some(kind)) if kind == "smessage")
+        );
+        assert_eq!(registry.active_shard_channels(Some(b"n*")), [news]);
+
+        registry.unsubscribe_all(&mut subscriber);
+        assert_eq!(subscriber.shard_count(), 0);
+        assert!(registry.active_shard_channels(None).is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+//! Cache of Lua scripts (EVAL, SCRIPT LOAD) and the script running now.
+//!
+//! Scripts are cached by the SHA1 digest of their source, so EVALSHA can run
+//! them without sending the source again. Only one script runs at a time, as
+//! scripts hold the server's exec lock exclusively; once it has run longer
+//! than [`BUSY_THRESHOLD`], other clients are told the server is busy and
+//! may stop it with SCRIPT KILL, as long as it did not write yet.
+
+use crate::lua::{self, Chunk};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Run time after which a script makes other clients' commands fail with
+/// BUSY, like Redis' `busy-reply-threshold`.
+pub const BUSY_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// A compiled script.
+pub struct Script {
+    /// Lowercase hex SHA1 digest of the source.
+    pub sha: String,
+    pub chunk: Chunk,
+}
+
+/// Outcome of SCRIPT KILL.
+#[derive(Debug, PartialEq, Eq)]
+pub enum KillOutcome {
+    NotBusy,
+    /// The script already wrote, so stopping it would break atomicity.
+    Unkillable,
+    Killed,
+}
+
+struct Running {
+    started: Instant,
+    killed: Arc<AtomicBool>,
+    /// Server write count when the script started.
+    dirty: u64,
+}
+
+/// Scripts shared by all connections.
+#[derive(Default)]
+pub struct ScriptRegistry {
+    scripts: Mutex<HashMap<String, Arc<Script>>>,
+    running: Mutex<Option<Running>>,
+}
+
+/// Marks a script as running until dropped.
+pub struct RunningScript<'a> {
+    registry: &'a ScriptRegistry,
+    killed: Arc<AtomicBool>,
+}
+
+impl RunningScript<'_> {
+    /// Whether SCRIPT KILL asked the script to stop.
+    pub fn killed(&self) -> &Arc<AtomicBool> {
+        &self.killed
+    }
+}
+
+impl Drop for RunningScript<'_> {
+    fn drop(&mut self) {
+        *self.registry.lock_running() = None;
+    }
+}
+
+/// Lowercase hex SHA1 digest of `data`.
+pub fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl ScriptRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compile a script and cache it, unless it is already.
+    pub fn load(&self, source: &[u8]) -> Result<Arc<Script>, String> {
+        let sha = sha1_hex(source);
+        if let Some(script) = self.get(&sha) {
+            return Ok(script);
+        }
+        let script = Arc::new(Script {
+            chunk: lua::compile(source)?,
+            sha: sha.clone(),
+        });
+        self.lock().insert(sha, Arc::clone(&script));
+        Ok(script)
+    }
+
+    /// Cached script with digest `sha`, in any letter case.
+    pub fn get(&self, sha: &str) -> Option<Arc<Script>> {
+        self.lock().get(&sha.to_ascii_lowercase()).cloned()
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.get(sha).is_some()
+    }
+
+    /// Forget all cached scripts.
+    pub fn flush(&self) {
+        self.lock().clear();
+    }
+
+    /// Mark a script as running, with `dirty` the server's write count.
+    pub fn start(&self, dirty: u64) -> RunningScript<'_> {
+        let killed = Arc::new(AtomicBool::new(false));
+        *self.lock_running() = Some(Running {
+            started: Instant::now(),
+            killed: Arc::clone(&killed),
+            dirty,
+        });
+        RunningScript {
+            registry: self,
+            killed,
+        }
+    }
+
+    /// Whether a script has been running longer than [`BUSY_THRESHOLD`].
+    pub fn busy(&self) -> bool {
+        self.lock_running()
+            .as_ref()
+            .is_some_and(|running| running.started.elapsed() >= BUSY_THRESHOLD)
+    }
+
+    /// Ask the running script to stop, with `dirty` the server's write
+    /// count now.
+    pub fn kill(&self, dirty: u64) -> KillOutcome {
+        match &*self.lock_running() {
+            None => KillOutcome::NotBusy,
+            Some(running) if running.dirty != dirty => KillOutcome::Unkillable,
+            Some(running) => {
+                running.killed.store(true, Ordering::Release);
+                KillOutcome::Killed
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Arc<Script>>> {
+        self.scripts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_running(&self) -> MutexGuard<'_, Option<Running>> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_kill() {
+        let registry = ScriptRegistry::new();
+        let script = registry.load(b"return 1").unwrap();
+        assert_eq!(script.sha, "e0e1f9fabfc9d4800c877a703b823ac0578ff8db");
+        assert!(registry.exists("E0E1F9FABFC9D4800C877A703B823AC0578FF8DB"));
+        assert!(registry.load(b"return +").is_err());
+        registry.flush();
+        assert!(!registry.exists(&script.sha));
+
+        assert_eq!(registry.kill(0), KillOutcome::NotBusy);
+        let running = registry.start(7);
+        assert!(!registry.busy());
+        assert_eq!(registry.kill(8), KillOutcome::Unkillable);
+        assert!(!running.killed().load(Ordering::Acquire));
+        assert_eq!(registry.kill(7), KillOutcome::Killed);
+        assert!(running.killed().load(Ordering::Acquire));
+        drop(running);
+        assert_eq!(registry.kill(7), KillOutcome::NotBusy);
+    }
+}
@@ -0,0 +1,304 @@
+//! Keys cached by clients with client-side caching enabled (CLIENT
+//! TRACKING).
+//!
+//! In the default mode the server remembers the keys each tracking client
+//! read, and the first time one of them is modified afterwards it sends the
+//! client an invalidation message and forgets the key until it is read
+//! again. In broadcasting mode (BCAST) nothing is remembered: every
+//! modified key matching one of the client's prefixes is invalidated.
+//!
+//! Invalidation messages go to the mailbox of the client itself, or of the
+//! client it redirects them to. RESP2 connections cannot receive pushes, so
+//! a redirection target receives them as messages of the
+//! [`INVALIDATE_CHANNEL`] it subscribed to.
+
+use super::pubsub::{Message, PubSubRegistry};
+use crate::protocol::RespValue;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Channel RESP2 clients subscribe to for the invalidation messages
+/// redirected to them.
+pub const INVALIDATE_CHANNEL: &[u8] = b"__redis__:invalidate";
+
+/// How a client tracks keys, as set by CLIENT TRACKING ON.
+#[derive(Debug, Clone, Default)]
+pub struct TrackingOptions {
+    /// Client receiving the invalidation messages instead of this one.
+    pub redirect: Option<u64>,
+    /// Whether every key matching `prefixes` is invalidated, read or not.
+    pub bcast: bool,
+    /// Prefixes of the keys invalidated in BCAST mode; none matches all.
+    pub prefixes: Vec<Bytes>,
+    /// Whether only keys read right after CLIENT CACHING YES are tracked.
+    pub optin: bool,
+    /// Whether keys read right after CLIENT CACHING NO are not tracked.
+    pub optout: bool,
+    /// Whether keys modified by the client itself are not invalidated.
+    pub noloop: bool,
+}
+
+impl TrackingOptions {
+    fn wants(&self, key: &[u8]) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p))
+    }
+}
+
+#[derive(Default)]
+struct Table {
+    /// Options of every tracking client, by client id.
+    clients: HashMap<u64, TrackingOptions>,
+    /// Clients that read each key, in the default mode. Entries of clients
+    /// that stopped tracking are dropped on the next invalidation.
+    keys: HashMap<Bytes, HashSet<u64>>,
+}
+
+/// Tracking clients and the keys they cache, shared by all connections.
+#[derive(Default)]
+pub struct TrackingRegistry {
+    table: Mutex<Table>,
+    /// Number of tracking clients, so writes skip the lock while nobody
+    /// tracks.
+    tracking: AtomicUsize,
+}
+
+impl TrackingRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking for `client`, or change the options it tracks with.
+    pub fn enable(&self, client: u64, options: TrackingOptions) {
+        let mut table = self.lock();
+        table.clients.insert(client, options);
+        self.tracking.store(table.clients.len(), Ordering::Release);
+    }
+
+    /// Stop tracking for `client`.
+    pub fn disable(&self, client: u64) {
+        if self.tracking_clients() == 0 {
+            return;
+        }
+        let mut table = self.lock();
+        if table.clients.remove(&client).is_some() {
+            self.tracking.store(table.clients.len(), Ordering::Release);
+        }
+    }
+
+    /// Number of clients with tracking enabled.
+    pub fn tracking_clients(&self) -> usize {
+        self.tracking.load(Ordering::Acquire)
+    }
+
+    /// Number of keys remembered for clients in the default mode.
+    pub fn tracked_keys(&self) -> usize {
+        self.lock().keys.len()
+    }
+
+    /// Remember that `client` read `keys`.
+    pub fn remember(&self, client: u64, keys: Vec<Bytes>) {
+        let mut table = self.lock();
+        if !table.clients.contains_key(&client) {
+            return;
+        }
+        for key in keys {
+            table.keys.entry(key).or_default().insert(client);
+        }
+    }
+
+    /// Send an invalidation of `key`, modified by client `writer` if any, to
+    /// the clients caching it.
+    pub fn invalidate(&self, key: &[u8], writer: Option<u64>, pubsub: &PubSubRegistry) {
+        if self.tracking_clients() == 0 {
+            return;
+        }
+        let targets: Vec<(u64, Option<u64>)> = {
+            let mut table = self.lock();
+            let readers = table.keys.remove(key).unwrap_or_default();
+            table
+                .clients
+                .iter()
+                .filter(|(id, options)| match options.bcast {
+                    true => options.wants(key),
+                    false => readers.contains(id),
+                })
+                .filter(|(id, options)| !(options.noloop && writer == Some(**id)))
+                .map(|(id, options)| (*id, options.redirect))
+                .collect()
+        };
+        let keys = RespValue::Array(Some(vec![RespValue::BulkString(Some(
+            Bytes::copy_from_slice(key),
+        ))]));
+        for (client, redirect) in targets {
+            send(pubsub, client, redirect, invalidation(keys.clone()));
+        }
+    }
+
+    /// Invalidate every key of every tracking client, when the database is
+    /// flushed.
+    pub fn invalidate_all(&self, pubsub: &PubSubRegistry) {
+        if self.tracking_clients() == 0 {
+            return;
+        }
+        let targets: Vec<(u64, Option<u64>)> = {
+            let mut table = self.lock();
+            table.keys.clear();
+            table
+                .clients
+                .iter()
+                .map(|(id, options)| (*id, options.redirect))
+                .collect()
+        };
+        for (client, redirect) in targets {
+            send(pubsub, client, redirect, invalidation(RespValue::Null));
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Table> {
+        self.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// An `invalidate` message for `keys`, an array or null for all keys.
+fn invalidation(keys: RespValue) -> Message {
+    vec![
+        RespValue::BulkString(Some(Bytes::from_static(b"invalidate"))),
+        keys,
+    ]
+}
+
+/// Post `message` to `client`, or to the client it redirects to. When that
+/// one is gone, the client is told its redirection is broken instead.
+fn send(pubsub: &PubSubRegistry, client: u64, redirect: Option<u64>, message: Message) {
+    match redirect {
+        None => {
+            pubsub.post(client, message);
+        }
+        Some(target) => {
+            if !pubsub.post(target, message) {
+                pubsub.post(
+                    client,
+                    vec![
+                        RespValue::BulkString(Some(Bytes::from_static(b"tracking-redir-broken"))),
+                        RespValue::Integer(target as i64),
+                    ],
+                );
+            }
+        }
+    }
+}
+
+/// Whether `message` was sent by client tracking rather than pub/sub.
+pub fn is_tracking_message(message: &Message) -> bool {
+    matches!(
+        message.first(),
+        Some(RespValue::BulkString(Some(kind)))
+            if kind.as_ref() == b"invalidate" || kind.as_ref() == b"tracking-redir-broken"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalidated(message: &Message) -> Option<Vec<Bytes>> {
+        match message.as_slice() {
+            [RespValue::BulkString(Some(kind)), RespValue::Array(Some(keys))]
+                if kind.as_ref() == b"invalidate" =>
+            {
+                Some(
+                    keys.iter()
+                        .filter_map(|key| match key {
+                            RespValue::BulkString(Some(key)) => Some(key.clone()),
+                            _ => None,
+                        })
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_invalidate_reaches_readers_once() {
+        let pubsub = PubSubRegistry::new();
+        let registry = TrackingRegistry::new();
+        let mut reader = pubsub.subscriber();
+        let mut other = pubsub.subscriber();
+        registry.enable(reader.id(), TrackingOptions::default());
+        registry.enable(other.id(), TrackingOptions::default());
+        registry.remember(reader.id(), vec![Bytes::from_static(b"k")]);
+
+        registry.invalidate(b"k", None, &pubsub);
+        let message = reader.try_receive().unwrap();
+        assert_eq!(
+            invalidated(&message).unwrap(),
+            vec![Bytes::from_static(b"k")]
+        );
+        assert!(other.try_receive().is_none());
+
+        // Forgotten until read again
+        registry.invalidate(b"k", None, &pubsub);
+        assert!(reader.try_receive().is_none());
+        assert_eq!(registry.tracked_keys(), 0);
+    }
+
+    #[test]
+    fn test_bcast_prefixes_and_noloop() {
+        let pubsub = PubSubRegistry::new();
+        let registry = TrackingRegistry::new();
+        let mut client = pubsub.subscriber();
+        registry.enable(
+            client.id(),
+            TrackingOptions {
+                bcast: true,
+                prefixes: vec![Bytes::from_static(b"user:")],
+                noloop: true,
+                ..Default::default()
+            },
+        );
+
+        registry.invalidate(b"user:1", None, &pubsub);
+        assert!(client.try_receive().is_some());
+        registry.invalidate(b"item:1", None, &pubsub);
+        registry.invalidate(b"user:2", Some(client.id()), &pubsub);
+        assert!(client.try_receive().is_none());
+
+        registry.disable(client.id());
+        registry.invalidate(b"user:1", None, &pubsub);
+        assert!(client.try_receive().is_none());
+        assert_eq!(registry.tracking_clients(), 0);
+    }
+
+    #[test]
+    fn test_broken_redirect_is_reported() {
+        let pubsub = PubSubRegistry::new();
+        let registry = TrackingRegistry::new();
+        let mut client = pubsub.subscriber();
+        let mut target = pubsub.subscriber();
+        let target_id = target.id();
+        registry.enable(
+            client.id(),
+            TrackingOptions {
+                redirect: Some(target_id),
+                bcast: true,
+                ..Default::default()
+            },
+        );
+
+        registry.invalidate(b"k", None, &pubsub);
+        assert!(target.try_receive().is_some());
+        assert!(client.try_receive().is_none());
+
+        pubsub.close(&mut target);
+        registry.invalidate_all(&pubsub);
+        let message = client.try_receive().unwrap();
+        assert!(is_tracking_message(&message));
+        assert!(matches!(
+            message.as_slice(),
+            [_, RespValue::Integer(id)] if *id == target_id as i64
+        ));
+    }
+}
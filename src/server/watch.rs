@@ -0,0 +1,521 @@
+//! Keys watched by clients for optimistic transactions (WATCH).
+//!
+//! Every client owns a flag that is raised when any key it watches is
+//! modified, by any client including itself; EXEC then aborts. Handlers see
+//! storage through [`WatchedStorage`], which reports the keys each write
+//! modifies, so no command has to signal its writes by hand. It also counts
+//! writes, which tells whether a script may still be killed, and publishes
+//! the keyspace events that do not depend on the command: `keymiss` for
+//! reads of missing keys and `new` for keys created.
+//!
+//! The same reports invalidate modified keys in the caches of tracking
+//! clients, and on request the keys a command reads are logged, for a
+//! tracking client to remember them.
+
+use super::notify::{NOTIFY_KEY_MISS, NOTIFY_NEW};
+use super::ServerContext;
+use crate::storage::{
+    Batch, ExpiryCondition, ExpiryListener, ListEnd, MultiUpdateFn, ScanBatch, StorageBackend,
+    StorageError, StorageValue, UpdateFn, ViewFn,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// Keys watched by one client, and whether any of them was modified since.
+#[derive(Default)]
+pub struct WatchedKeys {
+    keys: Vec<Bytes>,
+    dirty: Arc<AtomicBool>,
+}
+
+impl WatchedKeys {
+    /// Whether a watched key was modified since it was watched.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+
+    /// Number of keys watched.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Watchers of each key, shared by all connections.
+#[derive(Default)]
+pub struct WatchRegistry {
+    by_key: Mutex<HashMap<Bytes, Vec<Arc<AtomicBool>>>>,
+    /// Number of watched keys, so writes skip the lock while nothing is
+    /// watched.
+    watched: AtomicUsize,
+}
+
+impl WatchRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `key` to the keys watched by a client.
+    pub fn watch(&self, watched: &mut WatchedKeys, key: &Bytes) {
+        if watched.keys.contains(key) {
+            return;
+        }
+        let mut by_key = self.lock();
+        by_key
+            .entry(key.clone())
+            .or_default()
+            .push(Arc::clone(&watched.dirty));
+        self.watched.store(by_key.len(), Ordering::Release);
+        watched.keys.push(key.clone());
+    }
+
+    /// Stop watching all keys of a client and reset its flag.
+    pub fn unwatch_all(&self, watched: &mut WatchedKeys) {
+        if !watched.keys.is_empty() {
+            let mut by_key = self.lock();
+            for key in watched.keys.drain(..) {
+                let Some(watchers) = by_key.get_mut(&key) else {
+                    continue;
+                };
+                watchers.retain(|dirty| !Arc::ptr_eq(dirty, &watched.dirty));
+                if watchers.is_empty() {
+                    by_key.remove(&key);
+                }
+            }
+            self.watched.store(by_key.len(), Ordering::Release);
+        }
+        watched.dirty.store(false, Ordering::Release);
+    }
+
+    /// Number of keys watched by any client.
+    pub fn watched_keys(&self) -> usize {
+        self.watched.load(Ordering::Acquire)
+    }
+
+    /// Flag the clients watching `key`.
+    pub fn touch(&self, key: &[u8]) {
+        if self.watched_keys() == 0 {
+            return;
+        }
+        for dirty in self.lock().get(key).into_iter().flatten() {
+            dirty.store(true, Ordering::Release);
+        }
+    }
+
+    /// Flag every watching client, e.g. when the database is flushed.
+    pub fn touch_all(&self) {
+        if self.watched_keys() == 0 {
+            return;
+        }
+        for dirty in self.lock().values().flatten() {
+            dirty.store(true, Ordering::Release);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Bytes, Vec<Arc<AtomicBool>>>> {
+        self.by_key.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Keys read since logging started, and whether anything was written.
+#[derive(Default)]
+struct ReadLog {
+    keys: Vec<Bytes>,
+    wrote: bool,
+}
+
+/// Storage decorator of one client that reports the keys modified through
+/// it to the server's [`WatchRegistry`] and [`TrackingRegistry`].
+///
+/// [`TrackingRegistry`]: super::tracking::TrackingRegistry
+pub struct WatchedStorage {
+    inner: Arc<dyn StorageBackend>,
+    context: Arc<ServerContext>,
+    /// Id of the client writing through this storage.
+    client: u64,
+    /// Whether reads are logged, so reads skip the lock while they are not.
+    logging: AtomicBool,
+    reads: Mutex<ReadLog>,
+}
+
+impl WatchedStorage {
+    pub fn new(inner: Arc<dyn StorageBackend>, context: Arc<ServerContext>, client: u64) -> Self {
+        Self {
+            inner,
+            context,
+            client,
+            logging: AtomicBool::new(false),
+            reads: Mutex::default(),
+        }
+    }
+
+    /// Start logging the keys read. Returns false if already logging, for
+    /// a command run by another one.
+    pub fn log_reads(&self) -> bool {
+        !self.logging.swap(true, Ordering::AcqRel)
+    }
+
+    /// Stop logging, returning the keys read unless something was written
+    /// since logging started: only read-only commands cache keys.
+    pub fn take_reads(&self) -> Option<Vec<Bytes>> {
+        self.logging.store(false, Ordering::Release);
+        let log = std::mem::take(&mut *self.lock_reads());
+        (!log.wrote && !log.keys.is_empty()).then_some(log.keys)
+    }
+
+    /// Whether `key` exists, without counting as a read of it.
+    pub async fn contains(&self, key: &[u8]) -> Result<bool, StorageError> {
+        self.inner.exists(key).await
+    }
+
+    fn touch(&self, key: &[u8]) {
+        self.context.dirty.fetch_add(1, Ordering::Relaxed);
+        self.context.watches.touch(key);
+        self.context.invalidate(key, Some(self.client));
+        if self.logging.load(Ordering::Acquire) {
+            self.lock_reads().wrote = true;
+        }
+    }
+
+    fn read(&self, key: &[u8]) {
+        if self.logging.load(Ordering::Acquire) {
+            self.lock_reads().keys.push(Bytes::copy_from_slice(key));
+        }
+    }
+
+    fn lock_reads(&self) -> MutexGuard<'_, ReadLog> {
+        self.reads.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether `key` is missing and `new` events are wanted, before a write
+    /// that does not tell whether it created the key.
+    async fn is_new(&self, key: &[u8]) -> Result<bool, StorageError> {
+        Ok(self.context.keyspace_events.enabled(NOTIFY_NEW) && !self.inner.exists(key).await?)
+    }
+
+    fn created(&self, key: &[u8]) {
+        self.context.notify(NOTIFY_NEW, "new", key);
+    }
+
+    fn missed(&self, key: &[u8]) {
+        self.context.notify(NOTIFY_KEY_MISS, "keymiss", key);
+    }
+}
+
+#[async_trait]
+impl StorageBackend for WatchedStorage {
+    async fn set(&self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        let new = self.is_new(key).await?;
+        self.inner.set(key, value).await?;
+        self.touch(key);
+        if new {
+            self.created(key);
+        }
+        Ok(())
+    }
+
+    async fn set_with_expiry(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) -> Result<(), StorageError> {
+        let new = self.is_new(key).await?;
+        self.inner.set_with_expiry(key, value, ttl).await?;
+        self.touch(key);
+        if new {
+            self.created(key);
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &[u8]) -> Result<Option<Bytes>, StorageError> {
+        let value = self.inner.get(key).await?;
+        self.read(key);
+        if value.is_none() {
+            self.missed(key);
+        }
+        Ok(value)
+    }
+
+    async fn get_value(&self, key: &[u8]) -> Result<Option<StorageValue>, StorageError> {
+        let value = self.inner.get_value(key).await?;
+        self.read(key);
+        if value.is_none() {
+            self.missed(key);
+        }
+        Ok(value)
+    }
+
+    async fn view(&self, key: &[u8], f: ViewFn<'_>) -> Result<(), StorageError> {
+        let mut missing = false;
+        self.inner
+            .view(key, &mut |value| {
+                missing = value.is_none();
+                f(value)
+            })
+            .await?;
+        self.read(key);
+        if missing {
+            self.missed(key);
+        }
+        Ok(())
+    }
+
+    async fn update(&self, key: &[u8], f: UpdateFn<'_>) -> Result<(), StorageError> {
+        // The closure may run more than once; the last run is the one applied
+        let mut dirty = false;
+        let mut created = false;
+        self.inner
+            .update(key, &mut |slot| {
+                let existed = slot.get().is_some();
+                let result = f(slot);
+                dirty = slot.is_dirty();
+                created = !existed && slot.get().is_some_and(|v| !v.data.is_empty_aggregate());
+                result
+            })
+            .await?;
+        match dirty {
+            true => self.touch(key),
+            false => self.read(key),
+        }
+        if created {
+            self.created(key);
+        }
+        Ok(())
+    }
+
+    async fn update_many(&self, keys: &[&[u8]], f: MultiUpdateFn<'_>) -> Result<(), StorageError> {
+        let mut dirty = vec![false; keys.len()];
+        let mut created = vec![false; keys.len()];
+        self.inner
+            .update_many(keys, &mut |slots| {
+                let existed: Vec<bool> = slots.iter().map(|slot| slot.get().is_some()).collect();
+                let result = f(slots);
+                for (i, slot) in slots.iter().enumerate() {
+                    dirty[i] = slot.is_dirty();
+                    created[i] =
+                        !existed[i] && slot.get().is_some_and(|v| !v.data.is_empty_aggregate());
+                }
+                result
+            })
+            .await?;
+        for (i, key) in keys.iter().enumerate() {
+            match dirty[i] {
+                true => self.touch(key),
+                false => self.read(key),
+            }
+            if created[i] {
+                self.created(key);
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_push(
+        &self,
+        key: &[u8],
+        elements: &[Bytes],
+        end: ListEnd,
+        create: bool,
+    ) -> Result<usize, StorageError> {
+        let len = self.inner.list_push(key, elements, end, create).await?;
+        if len > 0 {
+            self.touch(key);
+        }
+        // Lists are never empty, so one this long holds just these elements
+        if len > 0 && len == elements.len() {
+            self.created(key);
+        }
+        Ok(len)
+    }
+
+    async fn list_pop(
+        &self,
+        key: &[u8],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Bytes>>, StorageError> {
+        let popped = self.inner.list_pop(key, end, count).await?;
+        if popped.as_ref().is_some_and(|popped| !popped.is_empty()) {
+            self.touch(key);
+        }
+        Ok(popped)
+    }
+
+    async fn list_move(
+        &self,
+        source: &[u8],
+        destination: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Bytes>, StorageError> {
+        let new = source != destination && self.is_new(destination).await?;
+        let moved = self.inner.list_move(source, destination, from, to).await?;
+        if moved.is_some() {
+            self.touch(source);
+            self.touch(destination);
+            if new {
+                self.created(destination);
+            }
+        }
+        Ok(moved)
+    }
+
+    async fn get_expiry(&self, key: &[u8]) -> Result<Option<Option<SystemTime>>, StorageError> {
+        let expiry = self.inner.get_expiry(key).await?;
+        self.read(key);
+        if expiry.is_none() {
+            self.missed(key);
+        }
+        Ok(expiry)
+    }
+
+    async fn set_expiry(
+        &self,
+        key: &[u8],
+        expires_at: SystemTime,
+        condition: ExpiryCondition,
+    ) -> Result<bool, StorageError> {
+        let applied = self.inner.set_expiry(key, expires_at, condition).await?;
+        if applied {
+            self.touch(key);
+        }
+        Ok(applied)
+    }
+
+    async fn clear_expiry(&self, key: &[u8]) -> Result<bool, StorageError> {
+        let cleared = self.inner.clear_expiry(key).await?;
+        if cleared {
+            self.touch(key);
+        }
+        Ok(cleared)
+    }
+
+    async fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>, StorageError> {
+        let values = self.inner.get_many(keys).await?;
+        for (key, value) in keys.iter().zip(&values) {
+            self.read(key);
+            if value.is_none() {
+                self.missed(key);
+            }
+        }
+        Ok(values)
+    }
+
+    async fn set_many(&self, pairs: &[(&[u8], &[u8])]) -> Result<(), StorageError> {
+        let mut new = Vec::with_capacity(pairs.len());
+        for (key, _) in pairs {
+            new.push(self.is_new(key).await?);
+        }
+        self.inner.set_many(pairs).await?;
+        for ((key, _), new) in pairs.iter().zip(new) {
+            self.touch(key);
+            if new {
+                self.created(key);
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<bool, StorageError> {
+        let deleted = self.inner.delete(key).await?;
+        if deleted {
+            self.touch(key);
+        }
+        Ok(deleted)
+    }
+
+    async fn delete_many(&self, keys: &[&[u8]]) -> Result<usize, StorageError> {
+        // The count does not say which keys existed, so all of them are
+        // reported once anything was deleted
+        let count = self.inner.delete_many(keys).await?;
+        if count > 0 {
+            for key in keys {
+                self.touch(key);
+            }
+        }
+        Ok(count)
+    }
+
+    async fn exists(&self, key: &[u8]) -> Result<bool, StorageError> {
+        let exists = self.inner.exists(key).await?;
+        self.read(key);
+        if !exists {
+            self.missed(key);
+        }
+        Ok(exists)
+    }
+
+    async fn scan(
+        &self,
+        cursor: Option<&[u8]>,
+        pattern: Option<&[u8]>,
+        count: usize,
+    ) -> Result<ScanBatch, StorageError> {
+        self.inner.scan(cursor, pattern, count).await
+    }
+
+    async fn random_key(&self) -> Result<Option<Bytes>, StorageError> {
+        self.inner.random_key().await
+    }
+
+    async fn keys_count(&self) -> Result<usize, StorageError> {
+        self.inner.keys_count().await
+    }
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush().await?;
+        self.context.dirty.fetch_add(1, Ordering::Relaxed);
+        self.context.watches.touch_all();
+        self.context.invalidate_all();
+        if self.logging.load(Ordering::Acquire) {
+            self.lock_reads().wrote = true;
+        }
+        Ok(())
+    }
+
+    async fn atomically(&self, batch: Batch<'_>) -> Result<(), StorageError> {
+        self.inner.atomically(batch).await
+    }
+
+    fn set_expiry_listener(&self, listener: ExpiryListener) {
+        self.inner.set_expiry_listener(listener);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_touch_flags_watchers_until_unwatched() {
+        let registry = WatchRegistry::new();
+        let key = Bytes::from_static(b"k");
+        let mut first = WatchedKeys::default();
+        let mut second = WatchedKeys::default();
+        registry.watch(&mut first, &key);
+        registry.watch(&mut second, &Bytes::from_static(b"other"));
+
+        registry.touch(b"k");
+        assert!(first.is_dirty());
+        assert!(!second.is_dirty());
+
+        registry.unwatch_all(&mut first);
+        assert!(!first.is_dirty());
+        registry.touch(b"k");
+        assert!(!first.is_dirty());
+
+        registry.touch_all();
+        assert!(second.is_dirty());
+        registry.unwatch_all(&mut second);
+        assert_eq!(registry.watched_keys(), 0);
+    }
+}
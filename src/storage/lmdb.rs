@@ -1,39 +1,183 @@
-use super::{StorageBackend, StorageError, StorageValue};
+use super::{
+    Batch, ExpiryCondition, ExpiryListener, ListEnd, MultiUpdateFn, ScanBatch, StorageBackend,
+    StorageError, StorageValue, UpdateFn, ValueData, ValueSlot,
+};
+use crate::glob::glob_match;
 use async_trait::async_trait;
-use lmdb::{Transaction, WriteFlags};
+use bytes::Bytes;
+use lmdb::{Cursor, RoTransaction, RwTransaction, Transaction, WriteFlags};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::path::Path;
-use std::sync::Arc;
-use std::time::{Duration, UNIX_EPOCH};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Named database holding one record per key.
+const KEYS_DB: &str = "keys";
+/// Named database holding list elements, see [`StoredData::List`].
+const LIST_ITEMS_DB: &str = "list_items";
+/// Leading byte of every key record, identifying its encoding.
+const RECORD_FORMAT: u8 = 1;
+
+/// Payload of a key record.
+#[derive(Serialize, Deserialize)]
+enum StoredData {
+    /// Value stored inline in the record.
+    Inline(ValueData),
+    /// List whose elements are stored as separate records in the list items
+    /// database, at sequence numbers `head..head + len`. Pushing or popping
+    /// touches only the affected elements and this header.
+    List { head: i64, len: u64 },
+}
 
 /// Serializable representation of storage values for LMDB persistence.
+///
+/// Encoded with bincode so that binary payloads are stored verbatim, after a
+/// [`RECORD_FORMAT`] byte.
 #[derive(Serialize, Deserialize)]
 struct SerializableStorageValue {
-    data: String,
+    data: StoredData,
     /// Unix timestamp in milliseconds for expiry (persistable across restarts).
     expires_at_ms: Option<u64>,
 }
 
-impl From<&StorageValue> for SerializableStorageValue {
-    fn from(value: &StorageValue) -> Self {
+/// Record of a string key as written by versions before binary-safe
+/// storage: JSON, in the unnamed database. Migrated on open.
+#[derive(Deserialize)]
+struct LegacyRecord {
+    data: String,
+    expires_at_ms: Option<u64>,
+}
+
+impl From<LegacyRecord> for SerializableStorageValue {
+    fn from(record: LegacyRecord) -> Self {
         Self {
-            data: value.data.clone(),
-            expires_at_ms: value.expires_at.and_then(|t| {
-                t.duration_since(UNIX_EPOCH)
-                    .ok()
-                    .map(|d| d.as_millis() as u64)
-            }),
+            data: StoredData::Inline(ValueData::String(Bytes::from(record.data))),
+            expires_at_ms: record.expires_at_ms,
         }
     }
 }
 
-impl From<SerializableStorageValue> for StorageValue {
-    fn from(value: SerializableStorageValue) -> Self {
-        Self {
-            data: value.data,
-            expires_at: value
-                .expires_at_ms
-                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+impl SerializableStorageValue {
+    fn encode(&self) -> Result<Vec<u8>, StorageError> {
+        let mut bytes = vec![RECORD_FORMAT];
+        bincode::serialize_into(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, StorageError> {
+        match bytes.split_first() {
+            Some((&RECORD_FORMAT, record)) => Ok(bincode::deserialize(record)?),
+            _ => Err(StorageError::OperationFailed(
+                "unsupported LMDB record format; the database was written by an \
+                 incompatible version"
+                    .to_string(),
+            )),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at().is_some_and(|t| SystemTime::now() > t)
+    }
+
+    /// Sequence range `(head, len)` of out-of-line list elements, if any.
+    fn list_range(&self) -> Option<(i64, u64)> {
+        match self.data {
+            StoredData::List { head, len } => Some((head, len)),
+            StoredData::Inline(_) => None,
+        }
+    }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at_ms
+            .map(|ms| UNIX_EPOCH + Duration::from_millis(ms))
+    }
+}
+
+fn expiry_to_ms(expires_at: Option<SystemTime>) -> Option<u64> {
+    expires_at.and_then(|t| {
+        t.duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_millis() as u64)
+    })
+}
+
+/// Key of a list element: length-prefixed key followed by the big-endian
+/// sequence number (sign bit flipped), so a list's elements are contiguous
+/// and ordered.
+fn item_key(key: &[u8], seq: i64) -> Vec<u8> {
+    let mut item = Vec::with_capacity(4 + key.len() + 8);
+    item.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    item.extend_from_slice(key);
+    item.extend_from_slice(&((seq as u64) ^ (1 << 63)).to_be_bytes());
+    item
+}
+
+thread_local! {
+    /// Write transaction of the atomic batch running on this thread, tagged
+    /// with its environment, see [`LmdbStorage::atomically`].
+    static BATCH_TXN: RefCell<Option<(usize, RwTransaction<'static>)>> = const { RefCell::new(None) };
+
+    /// Keys found expired by the write transactions running on this thread,
+    /// for the expiry listener once they commit.
+    static EXPIRED: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Marks where a write transaction's expired keys start in [`EXPIRED`], and
+/// forgets them when dropped unless they were taken on commit.
+struct ExpiredMark(usize);
+
+impl ExpiredMark {
+    fn new() -> Self {
+        ExpiredMark(EXPIRED.with_borrow(Vec::len))
+    }
+
+    fn take(self) -> Vec<Vec<u8>> {
+        EXPIRED.with_borrow_mut(|expired| expired.split_off(self.0.min(expired.len())))
+    }
+}
+
+impl Drop for ExpiredMark {
+    fn drop(&mut self) {
+        EXPIRED.with_borrow_mut(|expired| expired.truncate(self.0));
+    }
+}
+
+/// Clears [`BATCH_TXN`] when dropped, so a batch that unwinds never leaves
+/// its transaction behind.
+struct BatchGuard;
+
+impl BatchGuard {
+    fn install(env: usize, txn: RwTransaction<'static>) -> Self {
+        BATCH_TXN.with_borrow_mut(|batch| *batch = Some((env, txn)));
+        BatchGuard
+    }
+
+    fn take(self) -> Option<RwTransaction<'static>> {
+        BATCH_TXN.with_borrow_mut(|batch| batch.take().map(|(_, txn)| txn))
+    }
+}
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        BATCH_TXN.with_borrow_mut(|batch| batch.take());
+    }
+}
+
+/// Transaction a read runs in: one of its own, or the running batch's.
+enum ReadTxn<'env> {
+    Own(RoTransaction<'env>),
+    Batch(&'env RwTransaction<'env>),
+}
+
+impl Transaction for ReadTxn<'_> {
+    fn txn(&self) -> *mut lmdb_sys::MDB_txn {
+        match self {
+            ReadTxn::Own(txn) => txn.txn(),
+            ReadTxn::Batch(txn) => txn.txn(),
         }
     }
 }
@@ -41,6 +185,8 @@ impl From<SerializableStorageValue> for StorageValue {
 pub struct LmdbStorage {
     env: Arc<lmdb::Environment>,
     db: lmdb::Database,
+    list_items: lmdb::Database,
+    expiry_listener: Mutex<Option<ExpiryListener>>,
 }
 
 impl LmdbStorage {
@@ -64,156 +210,1288 @@ impl LmdbStorage {
     ) -> Result<Self, StorageError> {
         let env = lmdb::Environment::new()
             .set_flags(lmdb::EnvironmentFlags::NO_SUB_DIR | lmdb::EnvironmentFlags::NO_SYNC)
-            .set_max_dbs(1)
+            .set_max_dbs(2)
             .set_map_size(map_size)
             .open(path.as_ref())?;
 
-        let db = env.create_db(None, lmdb::DatabaseFlags::empty())?;
+        let (db, list_items) = Self::open_databases(&env)?;
 
         Ok(Self {
             env: Arc::new(env),
             db,
+            list_items,
+            expiry_listener: Mutex::new(None),
         })
     }
-}
 
-#[async_trait]
-impl StorageBackend for LmdbStorage {
-    async fn set(&self, key: &str, value: &str) -> Result<(), StorageError> {
-        let storage_value = StorageValue::new(value.to_owned());
-        let serializable = SerializableStorageValue::from(&storage_value);
-        let serialized = serde_json::to_vec(&serializable)?;
+    /// Open the named databases, first moving any records of the legacy
+    /// layout out of the unnamed database, in one write transaction.
+    fn open_databases(
+        env: &lmdb::Environment,
+    ) -> Result<(lmdb::Database, lmdb::Database), StorageError> {
+        let main = env.open_db(None)?;
+        let mut txn = env.begin_rw_txn()?;
+        // Entries of the named databases are not JSON, so they are skipped
+        let mut legacy: Vec<(Vec<u8>, LegacyRecord)> = Vec::new();
+        {
+            let cursor = txn.open_ro_cursor(main)?;
+            let mut position = cursor.get(None, None, lmdb_sys::MDB_FIRST);
+            loop {
+                let (key, bytes) = match position {
+                    Ok((Some(key), bytes)) => (key, bytes),
+                    Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                    Err(e) => return Err(e.into()),
+                };
+                if let Ok(record) = serde_json::from_slice(bytes) {
+                    legacy.push((key.to_vec(), record));
+                }
+                position = cursor.get(None, None, lmdb_sys::MDB_NEXT);
+            }
+        }
+        for (key, _) in &legacy {
+            txn.del(main, key, None)?;
+        }
 
-        let mut txn = self.env.begin_rw_txn()?;
-        txn.put(self.db, &key, &serialized, WriteFlags::empty())?;
-        Transaction::commit(txn)?;
+        // SAFETY: no other handle to these databases is open yet.
+        let db = unsafe { txn.create_db(Some(KEYS_DB), lmdb::DatabaseFlags::empty())? };
+        let list_items =
+            unsafe { txn.create_db(Some(LIST_ITEMS_DB), lmdb::DatabaseFlags::empty())? };
+        for (key, record) in legacy {
+            let record = SerializableStorageValue::from(record).encode()?;
+            txn.put(db, &key, &record, WriteFlags::empty())?;
+        }
+        txn.commit()?;
+        Ok((db, list_items))
+    }
 
-        Ok(())
+    /// Number of key records, expired or not.
+    fn entries<T: Transaction>(&self, txn: &T) -> Result<usize, StorageError> {
+        let mut stat = std::mem::MaybeUninit::<lmdb_sys::MDB_stat>::uninit();
+        // SAFETY: `txn` and `db` are valid for the duration of the call
+        // and `mdb_stat` fully initializes `stat` when it returns 0.
+        let rc = unsafe { lmdb_sys::mdb_stat(txn.txn(), self.db.dbi(), stat.as_mut_ptr()) };
+        if rc != 0 {
+            return Err(lmdb::Error::from_err_code(rc).into());
+        }
+        Ok(unsafe { stat.assume_init() }.ms_entries)
     }
 
-    async fn set_with_expiry(
+    /// Identity of the environment, to match it against [`BATCH_TXN`].
+    fn env_id(&self) -> usize {
+        Arc::as_ptr(&self.env) as usize
+    }
+
+    /// Run `f` in this thread's batch transaction, or else in a read
+    /// transaction of its own.
+    fn read<T>(
         &self,
-        key: &str,
-        value: &str,
-        ttl: Duration,
+        f: impl FnOnce(&ReadTxn) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        BATCH_TXN.with_borrow(|batch| match batch {
+            Some((env, txn)) if *env == self.env_id() => f(&ReadTxn::Batch(txn)),
+            _ => f(&ReadTxn::Own(self.env.begin_ro_txn()?)),
+        })
+    }
+
+    /// Run `f` in this thread's batch transaction, or else in a write
+    /// transaction of its own, committed if `f` succeeds.
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&mut RwTransaction) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let mark = ExpiredMark::new();
+        let (result, in_batch) = BATCH_TXN.with_borrow_mut(|batch| match batch {
+            Some((env, txn)) if *env == self.env_id() => (f(txn), true),
+            _ => {
+                let commit = || {
+                    let mut txn = self.env.begin_rw_txn()?;
+                    let result = f(&mut txn)?;
+                    txn.commit()?;
+                    Ok(result)
+                };
+                (commit(), false)
+            }
+        });
+        if in_batch {
+            // The batch keeps what `f` did even if it failed, and tells of
+            // the keys that expired meanwhile when it commits
+            std::mem::forget(mark);
+        } else if result.is_ok() {
+            self.expired(mark.take());
+        }
+        result
+    }
+
+    /// Tell the expiry listener of `keys`, removed by a committed transaction.
+    fn expired(&self, keys: Vec<Vec<u8>>) {
+        if keys.is_empty() {
+            return;
+        }
+        let listener = self
+            .expiry_listener
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(listener) = listener.as_ref() {
+            for key in keys {
+                listener(&key);
+            }
+        }
+    }
+
+    fn get_record<T: Transaction>(
+        &self,
+        txn: &T,
+        key: &[u8],
+    ) -> Result<Option<SerializableStorageValue>, StorageError> {
+        match txn.get(self.db, &key) {
+            Ok(bytes) => Ok(Some(SerializableStorageValue::decode(bytes)?)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn put_record(
+        &self,
+        txn: &mut RwTransaction,
+        key: &[u8],
+        record: &SerializableStorageValue,
     ) -> Result<(), StorageError> {
-        let storage_value = StorageValue::new_with_expiry(value.to_owned(), ttl);
-        let serializable = SerializableStorageValue::from(&storage_value);
-        let serialized = serde_json::to_vec(&serializable)?;
+        txn.put(self.db, &key, &record.encode()?, WriteFlags::empty())?;
+        Ok(())
+    }
+
+    /// Get a record inside a write transaction, deleting it if it has expired.
+    /// The expiry listener hears of it once the transaction commits.
+    fn live_record(
+        &self,
+        txn: &mut RwTransaction,
+        key: &[u8],
+    ) -> Result<Option<SerializableStorageValue>, StorageError> {
+        match self.get_record(txn, key)? {
+            Some(record) if record.is_expired() => {
+                self.remove_record(txn, key, &record)?;
+                EXPIRED.with_borrow_mut(|expired| expired.push(key.to_vec()));
+                Ok(None)
+            }
+            record => Ok(record),
+        }
+    }
 
-        let mut txn = self.env.begin_rw_txn()?;
-        txn.put(self.db, &key, &serialized, WriteFlags::empty())?;
-        Transaction::commit(txn)?;
+    /// Materialize a record into a value, reading out-of-line list elements.
+    fn to_value<T: Transaction>(
+        &self,
+        txn: &T,
+        key: &[u8],
+        record: SerializableStorageValue,
+    ) -> Result<StorageValue, StorageError> {
+        let expires_at = record.expires_at();
+        let data = match record.data {
+            StoredData::Inline(data) => data,
+            StoredData::List { head, len } => {
+                // Elements are contiguous: seek to the head, then step forward
+                let cursor = txn.open_ro_cursor(self.list_items)?;
+                let mut list = VecDeque::with_capacity(len as usize);
+                let start = item_key(key, head);
+                let mut position = cursor.get(Some(&start), None, lmdb_sys::MDB_SET_RANGE);
+                for _ in 0..len {
+                    let (_, element) = position?;
+                    list.push_back(Bytes::copy_from_slice(element));
+                    position = cursor.get(None, None, lmdb_sys::MDB_NEXT);
+                }
+                ValueData::List(list)
+            }
+        };
+        Ok(StorageValue { data, expires_at })
+    }
 
+    /// Delete out-of-line list elements in the given sequence range.
+    fn clear_items(
+        &self,
+        txn: &mut RwTransaction,
+        key: &[u8],
+        range: Option<(i64, u64)>,
+    ) -> Result<(), StorageError> {
+        if let Some((head, len)) = range {
+            for seq in head..head + len as i64 {
+                txn.del(self.list_items, &item_key(key, seq), None)?;
+            }
+        }
         Ok(())
     }
 
-    async fn get(&self, key: &str) -> Result<Option<String>, StorageError> {
-        let (data, is_expired) = {
-            let txn = self.env.begin_ro_txn()?;
+    fn remove_record(
+        &self,
+        txn: &mut RwTransaction,
+        key: &[u8],
+        record: &SerializableStorageValue,
+    ) -> Result<(), StorageError> {
+        self.clear_items(txn, key, record.list_range())?;
+        txn.del(self.db, &key, None)?;
+        Ok(())
+    }
 
-            match Transaction::get(&txn, self.db, &key) {
-                Ok(bytes) => {
-                    let serializable: SerializableStorageValue = serde_json::from_slice(bytes)?;
-                    let storage_value = StorageValue::from(serializable);
+    /// Write a value, replacing the previous record and its list elements
+    /// (`previous_range`).
+    fn write_value(
+        &self,
+        txn: &mut RwTransaction,
+        key: &[u8],
+        previous_range: Option<(i64, u64)>,
+        value: StorageValue,
+    ) -> Result<(), StorageError> {
+        self.clear_items(txn, key, previous_range)?;
 
-                    if storage_value.is_expired() {
-                        (None, true)
-                    } else {
-                        (Some(storage_value.data), false)
-                    }
+        let data = match value.data {
+            ValueData::List(list) => {
+                for (seq, element) in list.iter().enumerate() {
+                    txn.put(
+                        self.list_items,
+                        &item_key(key, seq as i64),
+                        element,
+                        WriteFlags::empty(),
+                    )?;
+                }
+                StoredData::List {
+                    head: 0,
+                    len: list.len() as u64,
                 }
-                Err(lmdb::Error::NotFound) => (None, false),
-                Err(e) => return Err(StorageError::OperationFailed(format!("Get error: {}", e))),
             }
+            data => StoredData::Inline(data),
+        };
+
+        let record = SerializableStorageValue {
+            data,
+            expires_at_ms: expiry_to_ms(value.expires_at),
         };
+        self.put_record(txn, key, &record)
+    }
+
+    fn put_value(&self, key: &[u8], value: StorageValue) -> Result<(), StorageError> {
+        self.write(|txn| {
+            let previous = self.get_record(txn, key)?;
+            let previous_range = previous.and_then(|record| record.list_range());
+            self.write_value(txn, key, previous_range, value)
+        })
+    }
+
+    /// Read a live value, lazily deleting it if it has expired.
+    fn read_value(&self, key: &[u8]) -> Result<Option<StorageValue>, StorageError> {
+        let (value, is_expired) = self.read(|txn| {
+            Ok(match self.get_record(txn, key)? {
+                Some(record) if record.is_expired() => (None, true),
+                Some(record) => (Some(self.to_value(txn, key, record)?), false),
+                None => (None, false),
+            })
+        })?;
 
         if is_expired {
             // Clean up expired key after transaction is dropped
-            self.delete(key).await?;
+            self.delete_if_expired(key)?;
         }
 
-        Ok(data)
+        Ok(value)
     }
 
-    async fn delete(&self, key: &str) -> Result<bool, StorageError> {
-        let mut txn = self.env.begin_rw_txn()?;
+    /// Delete a key only if it is still expired, so a concurrent rewrite
+    /// between the read and this write transaction is never lost.
+    fn delete_if_expired(&self, key: &[u8]) -> Result<(), StorageError> {
+        self.write(|txn| self.live_record(txn, key).map(drop))
+    }
 
-        match txn.del(self.db, &key, None) {
-            Ok(()) => {
-                txn.commit()?;
-                Ok(true)
-            }
-            Err(lmdb::Error::NotFound) => Ok(false),
-            Err(e) => Err(e.into()),
+    /// Header of the list at `key` as `(head, len, expires_at_ms)`.
+    fn list_header(
+        &self,
+        txn: &mut RwTransaction,
+        key: &[u8],
+    ) -> Result<Option<(i64, u64, Option<u64>)>, StorageError> {
+        match self.live_record(txn, key)? {
+            Some(SerializableStorageValue {
+                data: StoredData::List { head, len },
+                expires_at_ms,
+            }) => Ok(Some((head, len, expires_at_ms))),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(None),
         }
     }
 
-    async fn delete_many(&self, keys: &[&str]) -> Result<usize, StorageError> {
-        if keys.is_empty() {
-            return Ok(0);
+    fn push_items(
+        &self,
+        txn: &mut RwTransaction,
+        key: &[u8],
+        elements: &[Bytes],
+        end: ListEnd,
+        create: bool,
+    ) -> Result<usize, StorageError> {
+        let (mut head, mut len, expires_at_ms) = match self.list_header(txn, key)? {
+            Some(header) => header,
+            None if create && !elements.is_empty() => (0, 0, None),
+            None => return Ok(0),
+        };
+
+        for element in elements {
+            let seq = match end {
+                ListEnd::Left => {
+                    head -= 1;
+                    head
+                }
+                ListEnd::Right => head + len as i64,
+            };
+            txn.put(
+                self.list_items,
+                &item_key(key, seq),
+                element,
+                WriteFlags::empty(),
+            )?;
+            len += 1;
         }
 
-        let mut txn = self.env.begin_rw_txn()?;
-        let mut count = 0;
+        let record = SerializableStorageValue {
+            data: StoredData::List { head, len },
+            expires_at_ms,
+        };
+        self.put_record(txn, key, &record)?;
+        Ok(len as usize)
+    }
+
+    fn pop_items(
+        &self,
+        txn: &mut RwTransaction,
+        key: &[u8],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Bytes>>, StorageError> {
+        let Some((mut head, mut len, expires_at_ms)) = self.list_header(txn, key)? else {
+            return Ok(None);
+        };
 
-        for key in keys {
-            match txn.del(self.db, key, None) {
-                Ok(()) => count += 1,
-                Err(lmdb::Error::NotFound) => {}
-                Err(e) => {
-                    txn.abort();
-                    return Err(e.into());
-                }
+        let count = count.min(len as usize);
+        let mut popped = Vec::with_capacity(count);
+        for _ in 0..count {
+            let seq = match end {
+                ListEnd::Left => head,
+                ListEnd::Right => head + len as i64 - 1,
+            };
+            let item = item_key(key, seq);
+            popped.push(Bytes::copy_from_slice(txn.get(self.list_items, &item)?));
+            txn.del(self.list_items, &item, None)?;
+            if end == ListEnd::Left {
+                head += 1;
             }
+            len -= 1;
         }
 
-        txn.commit()?;
-        Ok(count)
+        if len == 0 {
+            txn.del(self.db, &key, None)?;
+        } else if count > 0 {
+            let record = SerializableStorageValue {
+                data: StoredData::List { head, len },
+                expires_at_ms,
+            };
+            self.put_record(txn, key, &record)?;
+        }
+        Ok(Some(popped))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LmdbStorage {
+    async fn set(&self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        self.put_value(key, StorageValue::new(Bytes::copy_from_slice(value)))
+    }
+
+    async fn set_with_expiry(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) -> Result<(), StorageError> {
+        self.put_value(
+            key,
+            StorageValue::new_with_expiry(Bytes::copy_from_slice(value), ttl),
+        )
+    }
+
+    async fn get(&self, key: &[u8]) -> Result<Option<Bytes>, StorageError> {
+        self.read_value(key)?
+            .map(|value| match value.data {
+                ValueData::String(data) => Ok(data),
+                _ => Err(StorageError::WrongType),
+            })
+            .transpose()
+    }
+
+    async fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>, StorageError> {
+        let mut expired = Vec::new();
+        let values = self.read(|txn| {
+            keys.iter()
+                .map(|key| match self.get_record(txn, key)? {
+                    Some(record) if record.is_expired() => {
+                        expired.push(*key);
+                        Ok(None)
+                    }
+                    Some(SerializableStorageValue {
+                        data: StoredData::Inline(ValueData::String(data)),
+                        ..
+                    }) => Ok(Some(data)),
+                    _ => Ok(None),
+                })
+                .collect::<Result<Vec<_>, StorageError>>()
+        })?;
+
+        for key in expired {
+            self.delete_if_expired(key)?;
+        }
+        Ok(values)
+    }
+
+    async fn set_many(&self, pairs: &[(&[u8], &[u8])]) -> Result<(), StorageError> {
+        self.write(|txn| {
+            for (key, value) in pairs {
+                let previous = self.get_record(txn, key)?;
+                let previous_range = previous.and_then(|record| record.list_range());
+                let value = StorageValue::new(Bytes::copy_from_slice(value));
+                self.write_value(txn, key, previous_range, value)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn get_value(&self, key: &[u8]) -> Result<Option<StorageValue>, StorageError> {
+        self.read_value(key)
     }
 
-    async fn exists(&self, key: &str) -> Result<bool, StorageError> {
-        let (exists, is_expired) = {
-            let txn = self.env.begin_ro_txn()?;
+    async fn update(&self, key: &[u8], f: UpdateFn<'_>) -> Result<(), StorageError> {
+        // The whole read-modify-write runs inside one write transaction;
+        // LMDB allows a single writer at a time, so it is atomic.
+        self.update_many(&[key], &mut |slots| f(&mut slots[0]))
+            .await
+    }
+
+    async fn update_many(&self, keys: &[&[u8]], f: MultiUpdateFn<'_>) -> Result<(), StorageError> {
+        self.write(|txn| {
+            // For each key: whether a record existed, and its list element range
+            let mut previous = Vec::with_capacity(keys.len());
+            let mut slots = Vec::with_capacity(keys.len());
+            for key in keys {
+                let record = self.live_record(txn, key)?;
+                previous.push(record.as_ref().map(|record| record.list_range()));
+                let current = record
+                    .map(|record| self.to_value(txn, key, record))
+                    .transpose()?;
+                slots.push(ValueSlot::new(current));
+            }
 
-            match Transaction::get(&txn, self.db, &key) {
-                Ok(bytes) => {
-                    let serializable: SerializableStorageValue = serde_json::from_slice(bytes)?;
-                    let storage_value = StorageValue::from(serializable);
+            f(&mut slots)?;
 
-                    if storage_value.is_expired() {
-                        (false, true)
-                    } else {
-                        (true, false)
+            for ((key, previous), slot) in keys.iter().zip(previous).zip(slots) {
+                if !slot.is_dirty() {
+                    continue;
+                }
+                match (slot.into_value(), previous) {
+                    (Some(value), previous) => {
+                        self.write_value(txn, key, previous.flatten(), value)?
+                    }
+                    (None, Some(range)) => {
+                        self.clear_items(txn, key, range)?;
+                        txn.del(self.db, key, None)?;
                     }
+                    (None, None) => {}
                 }
-                Err(lmdb::Error::NotFound) => (false, false),
-                Err(e) => {
-                    return Err(StorageError::OperationFailed(format!(
-                        "Exists error: {}",
-                        e
-                    )))
+            }
+            Ok(())
+        })
+    }
+
+    async fn list_push(
+        &self,
+        key: &[u8],
+        elements: &[Bytes],
+        end: ListEnd,
+        create: bool,
+    ) -> Result<usize, StorageError> {
+        self.write(|txn| self.push_items(txn, key, elements, end, create))
+    }
+
+    async fn list_pop(
+        &self,
+        key: &[u8],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Bytes>>, StorageError> {
+        self.write(|txn| self.pop_items(txn, key, end, count))
+    }
+
+    async fn list_move(
+        &self,
+        source: &[u8],
+        destination: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Bytes>, StorageError> {
+        self.write(|txn| {
+            // Check both types before modifying anything
+            if self.list_header(txn, source)?.is_none() {
+                return Ok(None);
+            }
+            self.list_header(txn, destination)?;
+
+            let moved = self
+                .pop_items(txn, source, from, 1)?
+                .and_then(|mut popped| popped.pop());
+            if let Some(element) = &moved {
+                self.push_items(txn, destination, std::slice::from_ref(element), to, true)?;
+            }
+            Ok(moved)
+        })
+    }
+
+    async fn get_expiry(&self, key: &[u8]) -> Result<Option<Option<SystemTime>>, StorageError> {
+        self.read(|txn| {
+            Ok(self
+                .get_record(txn, key)?
+                .filter(|record| !record.is_expired())
+                .map(|record| record.expires_at()))
+        })
+    }
+
+    async fn set_expiry(
+        &self,
+        key: &[u8],
+        expires_at: SystemTime,
+        condition: ExpiryCondition,
+    ) -> Result<bool, StorageError> {
+        // Only the key record changes; list elements stay where they are
+        self.write(|txn| match self.live_record(txn, key)? {
+            Some(record) if condition.allows(record.expires_at(), expires_at) => {
+                if expires_at <= SystemTime::now() {
+                    self.remove_record(txn, key, &record)?;
+                } else {
+                    let record = SerializableStorageValue {
+                        expires_at_ms: expiry_to_ms(Some(expires_at)),
+                        ..record
+                    };
+                    self.put_record(txn, key, &record)?;
                 }
+                Ok(true)
+            }
+            _ => Ok(false),
+        })
+    }
+
+    async fn clear_expiry(&self, key: &[u8]) -> Result<bool, StorageError> {
+        self.write(|txn| match self.live_record(txn, key)? {
+            Some(record) if record.expires_at_ms.is_some() => {
+                let record = SerializableStorageValue {
+                    expires_at_ms: None,
+                    ..record
+                };
+                self.put_record(txn, key, &record)?;
+                Ok(true)
             }
+            _ => Ok(false),
+        })
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<bool, StorageError> {
+        self.write(|txn| {
+            let Some(record) = self.get_record(txn, key)? else {
+                return Ok(false);
+            };
+            self.remove_record(txn, key, &record)?;
+            Ok(!record.is_expired())
+        })
+    }
+
+    async fn delete_many(&self, keys: &[&[u8]]) -> Result<usize, StorageError> {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        self.write(|txn| {
+            let mut count = 0;
+            for key in keys {
+                // Duplicate keys are only counted once: the record is gone
+                // after the first removal.
+                if let Some(record) = self.get_record(txn, key)? {
+                    self.remove_record(txn, key, &record)?;
+                    if !record.is_expired() {
+                        count += 1;
+                    }
+                }
+            }
+            Ok(count)
+        })
+    }
+
+    async fn exists(&self, key: &[u8]) -> Result<bool, StorageError> {
+        let expired = match self.read(|txn| self.get_record(txn, key))? {
+            Some(record) => record.is_expired(),
+            None => return Ok(false),
         };
 
-        if is_expired {
-            // Clean up expired key after transaction is dropped
-            self.delete(key).await?;
+        if expired {
+            self.delete_if_expired(key)?;
         }
+        Ok(!expired)
+    }
+
+    async fn scan(
+        &self,
+        cursor: Option<&[u8]>,
+        pattern: Option<&[u8]>,
+        count: usize,
+    ) -> Result<ScanBatch, StorageError> {
+        // Keys are ordered, so the cursor is simply the last key examined
+        self.read(|txn| {
+            let records = txn.open_ro_cursor(self.db)?;
+            let mut position = match cursor {
+                Some(after) => match records.get(Some(after), None, lmdb_sys::MDB_SET_RANGE) {
+                    Ok((Some(key), _)) if key == after => {
+                        records.get(None, None, lmdb_sys::MDB_NEXT)
+                    }
+                    position => position,
+                },
+                None => records.get(None, None, lmdb_sys::MDB_FIRST),
+            };
+
+            let mut keys = Vec::new();
+            let mut last = None;
+            for _ in 0..count.max(1) {
+                let (key, bytes) = match position {
+                    Ok((Some(key), bytes)) => (key, bytes),
+                    Ok((None, _)) | Err(lmdb::Error::NotFound) => {
+                        return Ok(ScanBatch { keys, cursor: None })
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                let record = SerializableStorageValue::decode(bytes)?;
+                if !record.is_expired() && pattern.is_none_or(|pattern| glob_match(pattern, key)) {
+                    keys.push(Bytes::copy_from_slice(key));
+                }
+                last = Some(key);
+                position = records.get(None, None, lmdb_sys::MDB_NEXT);
+            }
+
+            // Only hand out a cursor if there is something left to examine
+            let cursor = match position {
+                Ok((Some(_), _)) => last.map(Bytes::copy_from_slice),
+                Ok((None, _)) | Err(lmdb::Error::NotFound) => None,
+                Err(e) => return Err(e.into()),
+            };
+            Ok(ScanBatch { keys, cursor })
+        })
+    }
 
-        Ok(exists)
+    async fn random_key(&self) -> Result<Option<Bytes>, StorageError> {
+        // Step to a uniformly chosen record and take the first live key from
+        // there, wrapping around once. Stepping only walks the B-tree leaves,
+        // so this is much cheaper than decoding every record.
+        self.read(|txn| {
+            let entries = self.entries(txn)?;
+            if entries == 0 {
+                return Ok(None);
+            }
+            let start = rand::thread_rng().gen_range(0..entries);
+
+            let records = txn.open_ro_cursor(self.db)?;
+            let mut position = records.get(None, None, lmdb_sys::MDB_FIRST);
+            for _ in 0..start {
+                position = records.get(None, None, lmdb_sys::MDB_NEXT);
+            }
+            for index in (start..entries).chain(0..start) {
+                if index == 0 && start != 0 {
+                    position = records.get(None, None, lmdb_sys::MDB_FIRST);
+                }
+                let (key, bytes) = match position {
+                    Ok((Some(key), bytes)) => (key, bytes),
+                    Ok((None, _)) | Err(lmdb::Error::NotFound) => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                let record = SerializableStorageValue::decode(bytes)?;
+                if !record.is_expired() {
+                    return Ok(Some(Bytes::copy_from_slice(key)));
+                }
+                position = records.get(None, None, lmdb_sys::MDB_NEXT);
+            }
+            Ok(None)
+        })
     }
 
     async fn keys_count(&self) -> Result<usize, StorageError> {
-        let stat = self.env.stat()?;
-        Ok(stat.entries())
+        self.read(|txn| self.entries(txn))
     }
 
     async fn flush(&self) -> Result<(), StorageError> {
-        let mut txn = self.env.begin_rw_txn()?;
-        txn.clear_db(self.db)?;
-        Transaction::commit(txn)?;
-        Ok(())
+        self.write(|txn| {
+            txn.clear_db(self.db)?;
+            txn.clear_db(self.list_items)?;
+            Ok(())
+        })
+    }
+
+    async fn atomically(&self, mut batch: Batch<'_>) -> Result<(), StorageError> {
+        if BATCH_TXN
+            .with_borrow(|batch| batch.as_ref().is_some_and(|(env, _)| *env == self.env_id()))
+        {
+            // Nested batches join the running one
+            batch.await;
+            return Ok(());
+        }
+
+        let txn = self.env.begin_rw_txn()?;
+        // SAFETY: the transaction only outlives its borrow of `self.env`
+        // inside `BATCH_TXN`, which the guard empties before this returns.
+        let txn = unsafe { std::mem::transmute::<RwTransaction<'_>, RwTransaction<'static>>(txn) };
+        let mark = ExpiredMark::new();
+        let guard = BatchGuard::install(self.env_id(), txn);
+
+        // Every operation of the batch completes synchronously in the
+        // installed transaction, so a single poll runs it to the end.
+        let poll = batch.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+        debug_assert!(poll.is_ready(), "atomic batch suspended");
+        let txn = guard.take();
+        match (poll, txn) {
+            (Poll::Ready(()), Some(txn)) => {
+                txn.commit()?;
+                self.expired(mark.take());
+                Ok(())
+            }
+            _ => Err(StorageError::OperationFailed(
+                "atomic batch did not complete in one step".to_string(),
+            )),
+        }
+    }
+
+    fn set_expiry_listener(&self, listener: ExpiryListener) {
+        *self
+            .expiry_listener
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(listener);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, RngCore};
+
+    fn create_storage() -> (tempfile::TempDir, LmdbStorage) {
+        let dir = tempfile::tempdir().unwrap();
+        let storage =
+            LmdbStorage::new_with_map_size(dir.path().join("test.lmdb"), 64 * 1024 * 1024).unwrap();
+        (dir, storage)
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_set_get_delete() {
+        let (_dir, storage) = create_storage();
+        storage.set(b"key1", b"value1").await.unwrap();
+
+        assert_eq!(
+            storage.get(b"key1").await.unwrap(),
+            Some(Bytes::from_static(b"value1"))
+        );
+        assert!(storage.delete(b"key1").await.unwrap());
+        assert_eq!(storage.get(b"key1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_binary_roundtrip() {
+        let (_dir, storage) = create_storage();
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..32 {
+            let mut key = vec![0u8; rng.gen_range(1..64)];
+            let mut value = vec![0u8; rng.gen_range(0..4096)];
+            rng.fill_bytes(&mut key);
+            rng.fill_bytes(&mut value);
+
+            storage.set(&key, &value).await.unwrap();
+            assert_eq!(
+                storage.get(&key).await.unwrap().as_deref(),
+                Some(&value[..])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_hash_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.lmdb");
+
+        {
+            let storage = LmdbStorage::new_with_map_size(&path, 64 * 1024 * 1024).unwrap();
+            let storage: &dyn StorageBackend = &storage;
+            storage
+                .modify(b"h", |slot| {
+                    let hash = slot.hash_mut()?;
+                    hash.insert(Bytes::from_static(b"f"), Bytes::from_static(b"\x00v"));
+                    Ok(())
+                })
+                .await
+                .unwrap();
+            assert!(matches!(
+                storage.get(b"h").await,
+                Err(StorageError::WrongType)
+            ));
+        }
+
+        let storage = LmdbStorage::new_with_map_size(&path, 64 * 1024 * 1024).unwrap();
+        let storage: &dyn StorageBackend = &storage;
+        let value = storage
+            .inspect(b"h", |value| {
+                Ok(value.unwrap().as_hash()?.get(&b"f"[..]).cloned())
+            })
+            .await
+            .unwrap();
+        assert_eq!(value, Some(Bytes::from_static(b"\x00v")));
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_stream_groups_persist() {
+        use crate::storage::stream::{ConsumerGroup, PendingEntry, StreamId};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.lmdb");
+        let id = StreamId::new(1, 0);
+
+        {
+            let storage = LmdbStorage::new_with_map_size(&path, 64 * 1024 * 1024).unwrap();
+            let storage: &dyn StorageBackend = &storage;
+            storage
+                .modify(b"s", |slot| {
+                    let stream = slot.stream_mut()?;
+                    stream.append(
+                        id,
+                        vec![(Bytes::from_static(b"f"), Bytes::from_static(b"v"))],
+                    );
+                    let mut group = ConsumerGroup::new(id, Some(1));
+                    group.pending.insert(
+                        id,
+                        PendingEntry {
+                            consumer: Bytes::from_static(b"c"),
+                            delivered_ms: 42,
+                            delivery_count: 1,
+                        },
+                    );
+                    stream.groups.insert(Bytes::from_static(b"g"), group);
+                    Ok(())
+                })
+                .await
+                .unwrap();
+        }
+
+        let storage = LmdbStorage::new_with_map_size(&path, 64 * 1024 * 1024).unwrap();
+        let storage: &dyn StorageBackend = &storage;
+        let pending = storage
+            .inspect(b"s", |value| {
+                let stream = value.unwrap().as_stream()?;
+                Ok(stream.groups[&b"g"[..]].pending.get(&id).cloned())
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pending.consumer, Bytes::from_static(b"c"));
+        assert_eq!(pending.delivered_ms, 42);
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_migrates_legacy_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("legacy.lmdb");
+        {
+            // Layout of versions before binary-safe storage
+            let env = lmdb::Environment::new()
+                .set_flags(lmdb::EnvironmentFlags::NO_SUB_DIR)
+                .set_max_dbs(1)
+                .open(&path)
+                .unwrap();
+            let db = env.open_db(None).unwrap();
+            let mut txn = env.begin_rw_txn().unwrap();
+            for (key, record) in [
+                ("greeting", r#"{"data":"hello","expires_at_ms":null}"#),
+                (
+                    "keys",
+                    r#"{"data":"named like a database","expires_at_ms":null}"#,
+                ),
+                ("gone", r#"{"data":"x","expires_at_ms":1}"#),
+            ] {
+                txn.put(db, &key, &record, WriteFlags::empty()).unwrap();
+            }
+            txn.commit().unwrap();
+        }
+
+        let storage = LmdbStorage::new_with_map_size(&path, 64 * 1024 * 1024).unwrap();
+        assert_eq!(storage.get(b"greeting").await.unwrap().unwrap(), "hello");
+        assert_eq!(
+            storage.get(b"keys").await.unwrap().unwrap(),
+            "named like a database"
+        );
+        assert_eq!(storage.get(b"gone").await.unwrap(), None);
+        storage.set(b"new", b"value").await.unwrap();
+        drop(storage);
+
+        // Reopening finds nothing left to migrate
+        let storage = LmdbStorage::new_with_map_size(&path, 64 * 1024 * 1024).unwrap();
+        assert_eq!(storage.keys_count().await.unwrap(), 3);
+        assert_eq!(storage.get(b"greeting").await.unwrap().unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_lmdb_rejects_unknown_record_format() {
+        let record = SerializableStorageValue {
+            data: StoredData::Inline(ValueData::String(Bytes::from_static(b"v"))),
+            expires_at_ms: None,
+        };
+        let encoded = record.encode().unwrap();
+        assert_eq!(encoded[0], RECORD_FORMAT);
+        assert!(SerializableStorageValue::decode(&encoded).is_ok());
+        assert!(SerializableStorageValue::decode(&encoded[1..]).is_err());
+        assert!(SerializableStorageValue::decode(&[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_list_expiry_keeps_items() {
+        let (_dir, storage) = create_storage();
+        let elements = [Bytes::from_static(b"a"), Bytes::from_static(b"b")];
+        storage
+            .list_push(b"l", &elements, ListEnd::Right, true)
+            .await
+            .unwrap();
+
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let nx = ExpiryCondition {
+            no_expiry: true,
+            ..Default::default()
+        };
+        assert!(storage.set_expiry(b"l", later, nx).await.unwrap());
+        assert!(!storage.set_expiry(b"l", later, nx).await.unwrap());
+        assert_eq!(
+            storage.get_expiry(b"l").await.unwrap(),
+            Some(Some(
+                UNIX_EPOCH + Duration::from_millis(expiry_to_ms(Some(later)).unwrap())
+            ))
+        );
+        assert!(storage.clear_expiry(b"l").await.unwrap());
+        assert_eq!(storage.get_expiry(b"l").await.unwrap(), Some(None));
+        assert_eq!(
+            storage.list_pop(b"l", ListEnd::Left, 2).await.unwrap(),
+            Some(elements.to_vec())
+        );
+
+        // A deadline in the past removes the list and its elements
+        storage
+            .list_push(b"l", &elements, ListEnd::Right, true)
+            .await
+            .unwrap();
+        assert!(storage
+            .set_expiry(b"l", UNIX_EPOCH, ExpiryCondition::default())
+            .await
+            .unwrap());
+        assert_eq!(storage.get_expiry(b"l").await.unwrap(), None);
+        let txn = storage.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(storage.list_items).unwrap();
+        assert_eq!(cursor.iter().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_scan_in_key_order() {
+        let (_dir, storage) = create_storage();
+        for i in 0..20 {
+            storage
+                .set(format!("k{:02}", i).as_bytes(), b"v")
+                .await
+                .unwrap();
+        }
+        storage
+            .set_with_expiry(b"k05", b"v", Duration::from_millis(1))
+            .await
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        let mut cursor: Option<Bytes> = None;
+        let mut seen = Vec::new();
+        loop {
+            let batch = storage.scan(cursor.as_deref(), None, 6).await.unwrap();
+            seen.extend(batch.keys);
+            cursor = batch.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        let expected: Vec<Bytes> = (0..20)
+            .filter(|i| *i != 5)
+            .map(|i| Bytes::from(format!("k{:02}", i)))
+            .collect();
+        assert_eq!(seen, expected);
+
+        // A cursor key deleted mid-iteration still resumes after it
+        let batch = storage.scan(None, Some(b"k1*"), 3).await.unwrap();
+        assert_eq!(batch.keys, Vec::<Bytes>::new());
+        storage.delete(b"k02").await.unwrap();
+        let batch = storage
+            .scan(batch.cursor.as_deref(), Some(b"k1*"), 100)
+            .await
+            .unwrap();
+        assert_eq!(batch.keys.len(), 10);
+        assert_eq!(batch.cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_random_key_skips_expired() {
+        let (_dir, storage) = create_storage();
+        assert_eq!(storage.random_key().await.unwrap(), None);
+
+        storage
+            .set_with_expiry(b"gone", b"v", Duration::from_millis(1))
+            .await
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(storage.random_key().await.unwrap(), None);
+
+        storage.set(b"a", b"v").await.unwrap();
+        storage.set(b"\xff\xff", b"v").await.unwrap();
+        for _ in 0..20 {
+            let key = storage.random_key().await.unwrap().unwrap();
+            assert!(key == b"a"[..] || key == b"\xff\xff"[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_random_key_is_not_biased_by_gaps() {
+        let (_dir, storage) = create_storage();
+        for i in 0..10 {
+            storage
+                .set(format!("user:{}", i).as_bytes(), b"v")
+                .await
+                .unwrap();
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..300 {
+            seen.insert(storage.random_key().await.unwrap().unwrap());
+        }
+        // Each key comes back with probability 1/10 per call
+        assert_eq!(seen.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_get_many_set_many() {
+        let (_dir, storage) = create_storage();
+        storage
+            .list_push(b"l", &[Bytes::from_static(b"x")], ListEnd::Left, true)
+            .await
+            .unwrap();
+        storage
+            .set_with_expiry(b"gone", b"v", Duration::from_millis(1))
+            .await
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(
+            storage.get_many(&[b"l", b"gone", b"none"]).await.unwrap(),
+            vec![None, None, None]
+        );
+        assert!(!storage.exists(b"gone").await.unwrap());
+
+        // Overwriting a list drops its out-of-line elements
+        storage
+            .set_many(&[(b"l", b"1"), (b"s", b"2")])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get_many(&[b"l", b"s"]).await.unwrap(),
+            vec![
+                Some(Bytes::from_static(b"1")),
+                Some(Bytes::from_static(b"2"))
+            ]
+        );
+        let txn = storage.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(storage.list_items).unwrap();
+        assert_eq!(cursor.iter().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_list_push_pop() {
+        let (_dir, storage) = create_storage();
+        let elements = |items: &[&'static str]| -> Vec<Bytes> {
+            items
+                .iter()
+                .map(|e| Bytes::from_static(e.as_bytes()))
+                .collect()
+        };
+
+        assert_eq!(
+            storage
+                .list_push(b"q", &elements(&["b", "a"]), ListEnd::Left, true)
+                .await
+                .unwrap(),
+            2
+        );
+        storage
+            .list_push(b"q", &elements(&["c", "d"]), ListEnd::Right, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get_value(b"q").await.unwrap().unwrap().data,
+            ValueData::List(elements(&["a", "b", "c", "d"]).into())
+        );
+
+        // Generic read-modify-write on a list written element by element
+        let dyn_storage: &dyn StorageBackend = &storage;
+        dyn_storage
+            .modify(b"q", |slot| {
+                slot.list_mut()?.insert(2, Bytes::from_static(b"x"));
+                Ok(())
+            })
+            .await
+            .unwrap();
+        storage
+            .list_push(b"q", &elements(&["z"]), ListEnd::Left, true)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage.list_pop(b"q", ListEnd::Right, 2).await.unwrap(),
+            Some(elements(&["d", "c"]))
+        );
+        assert_eq!(
+            storage.list_pop(b"q", ListEnd::Left, 10).await.unwrap(),
+            Some(elements(&["z", "a", "b", "x"]))
+        );
+        assert_eq!(
+            storage.list_pop(b"q", ListEnd::Left, 1).await.unwrap(),
+            None
+        );
+        assert_eq!(storage.keys_count().await.unwrap(), 0);
+
+        // No element records are left behind
+        let txn = storage.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(storage.list_items).unwrap();
+        assert_eq!(cursor.iter().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_list_move_and_overwrite() {
+        let (_dir, storage) = create_storage();
+        let element = |e: &'static str| vec![Bytes::from_static(e.as_bytes())];
+
+        storage
+            .list_push(b"src", &element("a"), ListEnd::Right, true)
+            .await
+            .unwrap();
+        storage.set(b"str", b"value").await.unwrap();
+
+        assert!(matches!(
+            storage
+                .list_move(b"src", b"str", ListEnd::Left, ListEnd::Left)
+                .await,
+            Err(StorageError::WrongType)
+        ));
+        assert_eq!(
+            storage
+                .list_move(b"src", b"dst", ListEnd::Left, ListEnd::Left)
+                .await
+                .unwrap(),
+            Some(Bytes::from_static(b"a"))
+        );
+        assert!(!storage.exists(b"src").await.unwrap());
+
+        // Overwriting a list with a string drops its elements
+        storage.set(b"dst", b"plain").await.unwrap();
+        assert_eq!(
+            storage.get(b"dst").await.unwrap(),
+            Some(Bytes::from_static(b"plain"))
+        );
+        let txn = storage.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(storage.list_items).unwrap();
+        assert_eq!(cursor.iter().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_atomically_commits_or_rolls_back() {
+        let (_dir, storage) = create_storage();
+
+        storage
+            .atomically(std::pin::pin!(async {
+                storage.set(b"a", b"1").await.unwrap();
+                storage
+                    .list_push(b"list", &[Bytes::from_static(b"x")], ListEnd::Left, true)
+                    .await
+                    .unwrap();
+                // Reads inside the batch see its own writes
+                assert_eq!(storage.get(b"a").await.unwrap().unwrap(), "1");
+                assert_eq!(storage.keys_count().await.unwrap(), 2);
+            }))
+            .await
+            .unwrap();
+        assert_eq!(storage.keys_count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_lmdb_expiry_listener_runs_after_commit() {
+        let (_dir, storage) = create_storage();
+        let heard = Arc::new(Mutex::new(Vec::new()));
+        let listener_heard = Arc::clone(&heard);
+        storage.set_expiry_listener(Arc::new(move |key: &[u8]| {
+            listener_heard.lock().unwrap().push(key.to_vec());
+        }));
+        let heard = || std::mem::take(&mut *heard.lock().unwrap());
+
+        storage
+            .set_with_expiry(b"gone", b"v", Duration::from_millis(1))
+            .await
+            .unwrap();
+        storage.set(b"k", b"v").await.unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        // A write that fails aborts the removal, so nobody hears of it yet
+        let result = storage
+            .update_many(&[b"gone", b"k"], &mut |_| Err(StorageError::WrongType))
+            .await;
+        assert!(matches!(result, Err(StorageError::WrongType)));
+        assert!(heard().is_empty());
+        assert_eq!(storage.get(b"gone").await.unwrap(), None);
+        assert_eq!(heard(), vec![b"gone".to_vec()]);
+
+        // A batch tells of its expired keys once it commits, including
+        // those of operations that failed within it
+        storage
+            .set_with_expiry(b"gone", b"v", Duration::from_millis(1))
+            .await
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        storage
+            .atomically(std::pin::pin!(async {
+                let result = storage
+                    .update_many(&[b"gone", b"k"], &mut |_| Err(StorageError::WrongType))
+                    .await;
+                assert!(result.is_err());
+                assert!(heard().is_empty());
+            }))
+            .await
+            .unwrap();
+        assert_eq!(heard(), vec![b"gone".to_vec()]);
+        assert!(!storage.exists(b"gone").await.unwrap());
+    }
+
+    /// Batches must not suspend, which debug builds assert. Otherwise one
+    /// that does fails and leaves nothing behind.
+    #[tokio::test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "atomic batch suspended"))]
+    async fn test_lmdb_atomically_rejects_suspended_batch() {
+        let (_dir, storage) = create_storage();
+        storage.set(b"a", b"1").await.unwrap();
+
+        let result = storage
+            .atomically(std::pin::pin!(async {
+                storage.delete(b"a").await.unwrap();
+                storage.set(b"b", b"2").await.unwrap();
+                tokio::task::yield_now().await;
+            }))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(storage.get(b"a").await.unwrap().unwrap(), "1");
+        assert!(!storage.exists(b"b").await.unwrap());
     }
 }
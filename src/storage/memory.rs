@@ -1,18 +1,35 @@
-use super::{StorageBackend, StorageError, StorageValue};
+use super::{
+    ExpiryListener, MultiUpdateFn, ScanBatch, StorageBackend, StorageError, StorageValue, UpdateFn,
+    ValueSlot, ViewFn,
+};
+use crate::glob::glob_match;
 use async_trait::async_trait;
+use bytes::Bytes;
 use papaya::HashMap;
+use rand::seq::IteratorRandom;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 
+/// A single key's value. `None` marks an entry that was removed while a
+/// reader may still hold a reference to it.
+type Entry = Mutex<Option<StorageValue>>;
+
+/// Listener shared with the cleanup task.
+type ListenerSlot = Mutex<Option<ExpiryListener>>;
+
 /// In-memory storage backend using concurrent hashmap.
 ///
 /// Fastest backend option. Data is volatile and lost on shutdown.
 /// Uses lazy expiry cleanup (expired keys removed on access).
-/// Built on papaya for high-performance concurrent access.
+/// Built on papaya for high-performance concurrent access: reads are
+/// lock-free at the map level, while writers are serialized by a single
+/// write lock so read-modify-write operations are atomic.
 pub struct MemoryStorage {
-    data: HashMap<String, StorageValue>,
+    data: Arc<HashMap<Bytes, Entry>>,
+    write_lock: Arc<Mutex<()>>,
     approximate_count: Arc<AtomicUsize>,
+    expiry_listener: Arc<ListenerSlot>,
 }
 
 impl Default for MemoryStorage {
@@ -27,130 +44,318 @@ impl MemoryStorage {
     }
 
     pub fn new_with_cleanup_interval(cleanup_interval: Duration) -> Self {
-        let data = HashMap::new();
+        let data = Arc::new(HashMap::new());
+        let write_lock = Arc::new(Mutex::new(()));
         let approximate_count = Arc::new(AtomicUsize::new(0));
+        let expiry_listener = Arc::new(Mutex::new(None));
 
         // Spawn background cleanup task
-        let data_clone = data.clone();
+        let data_clone = Arc::clone(&data);
+        let lock_clone = Arc::clone(&write_lock);
         let count_clone = Arc::clone(&approximate_count);
+        let listener_clone = Arc::clone(&expiry_listener);
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(cleanup_interval);
             loop {
                 interval.tick().await;
-                Self::cleanup_expired(&data_clone, &count_clone);
+                Self::cleanup_expired(&data_clone, &lock_clone, &count_clone, &listener_clone);
             }
         });
 
         Self {
             data,
+            write_lock,
             approximate_count,
+            expiry_listener,
         }
     }
 
-    fn cleanup_expired(data: &HashMap<String, StorageValue>, count: &AtomicUsize) {
+    fn cleanup_expired(
+        data: &HashMap<Bytes, Entry>,
+        write_lock: &Mutex<()>,
+        count: &AtomicUsize,
+        listener: &ListenerSlot,
+    ) {
         let mut to_remove = Vec::new();
 
         {
             let guard = data.pin();
-            for (key, value) in guard.iter() {
-                if value.is_expired() {
+            for (key, entry) in guard.iter() {
+                if lock_entry(entry).as_ref().is_some_and(|v| v.is_expired()) {
                     to_remove.push(key.clone());
                 }
             }
         }
 
         if !to_remove.is_empty() {
+            let _write = lock(write_lock);
             let guard = data.pin();
+            let listener = lock(listener).clone();
             for key in &to_remove {
-                if guard.remove(key).is_some() {
-                    count.fetch_sub(1, Ordering::Relaxed);
+                // Re-check under the write lock: the key may have been rewritten
+                let expired = guard.get(key).is_some_and(|entry| {
+                    lock_entry(entry).as_ref().is_some_and(|v| v.is_expired())
+                });
+                if expired {
+                    if let Some(entry) = guard.remove(key) {
+                        *lock_entry(entry) = None;
+                        count.fetch_sub(1, Ordering::Relaxed);
+                        if let Some(listener) = &listener {
+                            listener(key);
+                        }
+                    }
                 }
             }
         }
     }
+
+    /// Read a live value through `f`, lazily removing it if it has expired.
+    fn read<T>(&self, key: &[u8], f: impl FnOnce(&StorageValue) -> T) -> Option<T> {
+        let guard = self.data.pin();
+        let entry = guard.get(key)?;
+        let locked = lock_entry(entry);
+        let value = locked.as_ref()?;
+
+        if value.is_expired() {
+            drop(locked);
+            drop(guard);
+            self.remove_expired(key);
+            None
+        } else {
+            Some(f(value))
+        }
+    }
+
+    fn remove_expired(&self, key: &[u8]) {
+        let _write = lock(&self.write_lock);
+        let guard = self.data.pin();
+        let expired = guard
+            .get(key)
+            .is_some_and(|entry| lock_entry(entry).as_ref().is_none_or(|v| v.is_expired()));
+        if expired {
+            self.remove_locked(&guard, key);
+            if let Some(listener) = lock(&self.expiry_listener).as_ref() {
+                listener(key);
+            }
+        }
+    }
+
+    /// Insert or replace a value. Caller must hold the write lock.
+    fn insert_locked<G: papaya::Guard>(
+        &self,
+        guard: &papaya::HashMapRef<'_, Bytes, Entry, std::hash::RandomState, G>,
+        key: &[u8],
+        value: StorageValue,
+    ) {
+        if let Some(entry) = guard.get(key) {
+            *lock_entry(entry) = Some(value);
+        } else {
+            guard.insert(Bytes::copy_from_slice(key), Mutex::new(Some(value)));
+            self.approximate_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Remove a key. Caller must hold the write lock.
+    fn remove_locked<G: papaya::Guard>(
+        &self,
+        guard: &papaya::HashMapRef<'_, Bytes, Entry, std::hash::RandomState, G>,
+        key: &[u8],
+    ) -> bool {
+        match guard.remove(key) {
+            Some(entry) => {
+                let existed = lock_entry(entry)
+                    .take()
+                    .is_some_and(|value| !value.is_expired());
+                self.approximate_count.fetch_sub(1, Ordering::Relaxed);
+                existed
+            }
+            None => false,
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A panic while holding the lock cannot leave a value half-written
+    // (values are replaced wholesale), so poisoning is safe to ignore.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn lock_entry(entry: &Entry) -> MutexGuard<'_, Option<StorageValue>> {
+    lock(entry)
 }
 
 #[async_trait]
 impl StorageBackend for MemoryStorage {
-    async fn set(&self, key: &str, value: &str) -> Result<(), StorageError> {
+    async fn set(&self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        let _write = lock(&self.write_lock);
         let guard = self.data.pin();
-        let is_new = guard.get(key).is_none();
-        guard.insert(key.to_owned(), StorageValue::new(value.to_owned()));
+        self.insert_locked(
+            &guard,
+            key,
+            StorageValue::new(Bytes::copy_from_slice(value)),
+        );
+        Ok(())
+    }
 
-        if is_new {
-            self.approximate_count.fetch_add(1, Ordering::Relaxed);
+    async fn set_many(&self, pairs: &[(&[u8], &[u8])]) -> Result<(), StorageError> {
+        // One write lock for the batch, so readers never see half of it
+        let _write = lock(&self.write_lock);
+        let guard = self.data.pin();
+        for (key, value) in pairs {
+            self.insert_locked(
+                &guard,
+                key,
+                StorageValue::new(Bytes::copy_from_slice(value)),
+            );
         }
         Ok(())
     }
 
     async fn set_with_expiry(
         &self,
-        key: &str,
-        value: &str,
+        key: &[u8],
+        value: &[u8],
         ttl: Duration,
     ) -> Result<(), StorageError> {
+        let _write = lock(&self.write_lock);
         let guard = self.data.pin();
-        let is_new = guard.get(key).is_none();
-        guard.insert(
-            key.to_owned(),
-            StorageValue::new_with_expiry(value.to_owned(), ttl),
+        self.insert_locked(
+            &guard,
+            key,
+            StorageValue::new_with_expiry(Bytes::copy_from_slice(value), ttl),
         );
+        Ok(())
+    }
 
-        if is_new {
-            self.approximate_count.fetch_add(1, Ordering::Relaxed);
+    async fn get(&self, key: &[u8]) -> Result<Option<Bytes>, StorageError> {
+        self.read(key, |value| value.as_string().cloned())
+            .transpose()
+    }
+
+    async fn get_value(&self, key: &[u8]) -> Result<Option<StorageValue>, StorageError> {
+        Ok(self.read(key, StorageValue::clone))
+    }
+
+    async fn view(&self, key: &[u8], f: ViewFn<'_>) -> Result<(), StorageError> {
+        match self.read(key, |value| f(Some(value))) {
+            Some(result) => result,
+            None => f(None),
         }
-        Ok(())
     }
 
-    async fn get(&self, key: &str) -> Result<Option<String>, StorageError> {
+    async fn update(&self, key: &[u8], f: UpdateFn<'_>) -> Result<(), StorageError> {
+        let _write = lock(&self.write_lock);
         let guard = self.data.pin();
 
-        if let Some(entry) = guard.get(key) {
-            if entry.is_expired() {
-                // Drop guard before removing to avoid holding reference
-                drop(guard);
-                let remove_guard = self.data.pin();
-                if remove_guard.remove(key).is_some() {
-                    self.approximate_count.fetch_sub(1, Ordering::Relaxed);
-                }
-                Ok(None)
-            } else {
-                Ok(Some(entry.data.clone()))
+        let Some(entry) = guard.get(key) else {
+            let mut slot = ValueSlot::new(None);
+            f(&mut slot)?;
+            if let Some(value) = slot.into_value() {
+                self.insert_locked(&guard, key, value);
+            }
+            return Ok(());
+        };
+
+        // Move the value out of its entry for the duration of the closure so
+        // aggregates are modified in place; readers of this key wait on the
+        // entry lock meanwhile.
+        let mut locked = lock_entry(entry);
+        let current = locked.take().filter(|value| !value.is_expired());
+        let mut slot = ValueSlot::new(current);
+        let result = f(&mut slot);
+
+        match slot.into_value() {
+            Some(value) => *locked = Some(value),
+            None => {
+                drop(locked);
+                self.remove_locked(&guard, key);
             }
-        } else {
-            Ok(None)
         }
+        result
     }
 
-    async fn delete(&self, key: &str) -> Result<bool, StorageError> {
+    async fn update_many(&self, keys: &[&[u8]], f: MultiUpdateFn<'_>) -> Result<(), StorageError> {
+        let _write = lock(&self.write_lock);
         let guard = self.data.pin();
-        let existed = guard.remove(key).is_some();
 
-        if existed {
-            self.approximate_count.fetch_sub(1, Ordering::Relaxed);
+        // As in `update`, values are moved out of their (locked) entries while
+        // the closure runs. Keys are distinct, so each entry is locked once.
+        let mut locked: Vec<_> = keys
+            .iter()
+            .map(|key| guard.get(*key).map(lock_entry))
+            .collect();
+        let mut slots: Vec<ValueSlot> = locked
+            .iter_mut()
+            .map(|entry| {
+                let current = entry.as_mut().and_then(|value| value.take());
+                ValueSlot::new(current.filter(|value| !value.is_expired()))
+            })
+            .collect();
+        let result = f(&mut slots);
+
+        for ((key, entry), slot) in keys.iter().zip(locked).zip(slots) {
+            match (slot.into_value(), entry) {
+                (Some(value), Some(mut entry)) => *entry = Some(value),
+                (Some(value), None) => self.insert_locked(&guard, key, value),
+                (None, Some(entry)) => {
+                    drop(entry);
+                    self.remove_locked(&guard, key);
+                }
+                (None, None) => {}
+            }
         }
-        Ok(existed)
+        result
     }
 
-    async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+    async fn delete(&self, key: &[u8]) -> Result<bool, StorageError> {
+        let _write = lock(&self.write_lock);
         let guard = self.data.pin();
+        Ok(self.remove_locked(&guard, key))
+    }
 
-        if let Some(entry) = guard.get(key) {
-            if entry.is_expired() {
-                // Drop guard before removing
-                drop(guard);
-                let remove_guard = self.data.pin();
-                if remove_guard.remove(key).is_some() {
-                    self.approximate_count.fetch_sub(1, Ordering::Relaxed);
-                }
-                Ok(false)
-            } else {
-                Ok(true)
-            }
-        } else {
-            Ok(false)
+    async fn exists(&self, key: &[u8]) -> Result<bool, StorageError> {
+        Ok(self.read(key, |_| ()).is_some())
+    }
+
+    async fn scan(
+        &self,
+        cursor: Option<&[u8]>,
+        pattern: Option<&[u8]>,
+        count: usize,
+    ) -> Result<ScanBatch, StorageError> {
+        // The map is unordered: iterate in key order by picking the `count`
+        // smallest keys after the cursor (the last key of the previous batch)
+        let mut keys: Vec<Bytes> = {
+            let guard = self.data.pin();
+            guard
+                .iter()
+                .filter(|(key, _)| cursor.is_none_or(|cursor| key.as_ref() > cursor))
+                .filter(|(_, entry)| lock_entry(entry).as_ref().is_some_and(|v| !v.is_expired()))
+                .map(|(key, _)| key.clone())
+                .collect()
+        };
+
+        let count = count.max(1);
+        let more = keys.len() > count;
+        if more {
+            keys.select_nth_unstable(count);
+            keys.truncate(count);
         }
+        keys.sort_unstable();
+
+        let cursor = if more { keys.last().cloned() } else { None };
+        keys.retain(|key| pattern.is_none_or(|pattern| glob_match(pattern, key)));
+        Ok(ScanBatch { keys, cursor })
+    }
+
+    async fn random_key(&self) -> Result<Option<Bytes>, StorageError> {
+        let guard = self.data.pin();
+        Ok(guard
+            .iter()
+            .filter(|(_, entry)| lock_entry(entry).as_ref().is_some_and(|v| !v.is_expired()))
+            .map(|(key, _)| key.clone())
+            .choose(&mut rand::thread_rng()))
     }
 
     async fn keys_count(&self) -> Result<usize, StorageError> {
@@ -160,11 +365,19 @@ impl StorageBackend for MemoryStorage {
     }
 
     async fn flush(&self) -> Result<(), StorageError> {
+        let _write = lock(&self.write_lock);
         let guard = self.data.pin();
+        for (_, entry) in guard.iter() {
+            *lock_entry(entry) = None;
+        }
         guard.clear();
         self.approximate_count.store(0, Ordering::Relaxed);
         Ok(())
     }
+
+    fn set_expiry_listener(&self, listener: ExpiryListener) {
+        *lock(&self.expiry_listener) = Some(listener);
+    }
 }
 
 #[cfg(test)]
@@ -176,54 +389,108 @@ mod tests {
     #[tokio::test]
     async fn test_memory_basic_set_get() {
         let storage = MemoryStorage::new();
-        storage.set("key1", "value1").await.unwrap();
+        storage.set(b"key1", b"value1").await.unwrap();
 
         assert_eq!(
-            storage.get("key1").await.unwrap(),
-            Some("value1".to_string())
+            storage.get(b"key1").await.unwrap(),
+            Some(Bytes::from_static(b"value1"))
         );
-        assert_eq!(storage.get("nonexistent").await.unwrap(), None);
+        assert_eq!(storage.get(b"nonexistent").await.unwrap(), None);
     }
 
     #[tokio::test]
     async fn test_memory_delete() {
         let storage = MemoryStorage::new();
-        storage.set("key1", "value1").await.unwrap();
+        storage.set(b"key1", b"value1").await.unwrap();
 
-        assert!(storage.delete("key1").await.unwrap());
-        assert!(!storage.delete("nonexistent").await.unwrap());
-        assert_eq!(storage.get("key1").await.unwrap(), None);
+        assert!(storage.delete(b"key1").await.unwrap());
+        assert!(!storage.delete(b"nonexistent").await.unwrap());
+        assert_eq!(storage.get(b"key1").await.unwrap(), None);
     }
 
     #[tokio::test]
     async fn test_memory_exists() {
         let storage = MemoryStorage::new();
-        storage.set("key1", "value1").await.unwrap();
+        storage.set(b"key1", b"value1").await.unwrap();
 
-        assert!(storage.exists("key1").await.unwrap());
-        assert!(!storage.exists("nonexistent").await.unwrap());
+        assert!(storage.exists(b"key1").await.unwrap());
+        assert!(!storage.exists(b"nonexistent").await.unwrap());
 
-        storage.delete("key1").await.unwrap();
-        assert!(!storage.exists("key1").await.unwrap());
+        storage.delete(b"key1").await.unwrap();
+        assert!(!storage.exists(b"key1").await.unwrap());
     }
 
     #[tokio::test]
     async fn test_memory_expiry() {
         let storage = MemoryStorage::new();
         storage
-            .set_with_expiry("expiring_key", "value", Duration::from_millis(50))
+            .set_with_expiry(b"expiring_key", b"value", Duration::from_millis(50))
             .await
             .unwrap();
 
         assert_eq!(
-            storage.get("expiring_key").await.unwrap(),
-            Some("value".to_string())
+            storage.get(b"expiring_key").await.unwrap(),
+            Some(Bytes::from_static(b"value"))
         );
-        assert!(storage.exists("expiring_key").await.unwrap());
+        assert!(storage.exists(b"expiring_key").await.unwrap());
 
         thread::sleep(Duration::from_millis(100));
 
-        assert_eq!(storage.get("expiring_key").await.unwrap(), None);
-        assert!(!storage.exists("expiring_key").await.unwrap());
+        assert_eq!(storage.get(b"expiring_key").await.unwrap(), None);
+        assert!(!storage.exists(b"expiring_key").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_memory_binary_roundtrip() {
+        use rand::{Rng, RngCore};
+
+        let storage = MemoryStorage::new();
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..32 {
+            let mut key = vec![0u8; rng.gen_range(1..64)];
+            let mut value = vec![0u8; rng.gen_range(0..4096)];
+            rng.fill_bytes(&mut key);
+            rng.fill_bytes(&mut value);
+
+            storage.set(&key, &value).await.unwrap();
+            assert_eq!(
+                storage.get(&key).await.unwrap().as_deref(),
+                Some(&value[..])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_scan_survives_concurrent_writes() {
+        let storage = MemoryStorage::new();
+        for i in 0..50 {
+            storage
+                .set(format!("key{:02}", i).as_bytes(), b"v")
+                .await
+                .unwrap();
+        }
+
+        let mut cursor: Option<Bytes> = None;
+        let mut seen = Vec::new();
+        loop {
+            let batch = storage.scan(cursor.as_deref(), None, 7).await.unwrap();
+            assert!(batch.keys.len() <= 7);
+            seen.extend(batch.keys);
+            // Keys removed or added mid-iteration do not disturb the others
+            storage.delete(b"key49").await.unwrap();
+            storage.set(b"key00a", b"v").await.unwrap();
+            cursor = batch.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        for i in 0..49 {
+            assert!(seen.contains(&Bytes::from(format!("key{:02}", i))));
+        }
+
+        let batch = storage.scan(None, Some(b"key1?"), 100).await.unwrap();
+        assert_eq!(batch.keys.len(), 10);
+        assert_eq!(batch.cursor, None);
     }
 }
@@ -5,8 +5,12 @@
 pub mod lmdb;
 pub mod memory;
 pub mod s3;
+pub mod sorted_set;
+pub mod stream;
 pub mod traits;
 
+pub use sorted_set::SortedSet;
+pub use stream::{Stream, StreamId};
 pub use traits::*;
 
 // Storage factory for creating different backends
@@ -1,22 +1,124 @@
 #[cfg(feature = "s3-backend")]
-use super::{StorageBackend, StorageError};
+use super::{
+    MultiUpdateFn, ScanBatch, StorageBackend, StorageError, StorageValue, UpdateFn, ValueData,
+    ValueSlot,
+};
+#[cfg(feature = "s3-backend")]
+use crate::glob::glob_match;
 #[cfg(feature = "s3-backend")]
 use async_trait::async_trait;
 #[cfg(feature = "s3-backend")]
-use aws_sdk_s3::Client;
+use aws_sdk_s3::{config::http::HttpResponse, error::SdkError, Client};
+#[cfg(feature = "s3-backend")]
+use bytes::Bytes;
+#[cfg(feature = "s3-backend")]
+use rand::Rng;
 #[cfg(feature = "s3-backend")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "s3-backend")]
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Maximum number of requests a batched call keeps in flight.
+#[cfg(feature = "s3-backend")]
+const MAX_CONCURRENT_REQUESTS: usize = 32;
+
+/// Leading byte of every object body, identifying its encoding.
+#[cfg(feature = "s3-backend")]
+const OBJECT_FORMAT: u8 = 1;
 
+/// Object body stored for each key, bincode-encoded so values stay
+/// binary-safe, after an [`OBJECT_FORMAT`] byte.
 #[cfg(feature = "s3-backend")]
 #[derive(Serialize, Deserialize)]
 struct S3StorageValue {
+    data: ValueData,
+    /// Unix timestamp in milliseconds.
+    expires_at: Option<u64>,
+}
+
+/// Object body of a string key as written by versions before binary-safe
+/// storage: JSON. Read as is and replaced by the next write.
+#[cfg(feature = "s3-backend")]
+#[derive(Deserialize)]
+struct LegacyS3Value {
     data: String,
     expires_at: Option<u64>,
 }
 
 #[cfg(feature = "s3-backend")]
+impl S3StorageValue {
+    fn encode(&self) -> Result<Vec<u8>, StorageError> {
+        let mut body = vec![OBJECT_FORMAT];
+        bincode::serialize_into(&mut body, self)?;
+        Ok(body)
+    }
+
+    fn decode(body: &[u8]) -> Result<Self, StorageError> {
+        match body.split_first() {
+            Some((&OBJECT_FORMAT, value)) => Ok(bincode::deserialize(value)?),
+            Some((b'{', _)) => {
+                let legacy: LegacyS3Value = serde_json::from_slice(body)?;
+                Ok(Self {
+                    data: ValueData::String(Bytes::from(legacy.data)),
+                    expires_at: legacy.expires_at,
+                })
+            }
+            _ => Err(StorageError::OperationFailed(
+                "unsupported S3 object format; the object was written by an \
+                 incompatible version"
+                    .to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "s3-backend")]
+impl From<StorageValue> for S3StorageValue {
+    fn from(value: StorageValue) -> Self {
+        Self {
+            data: value.data,
+            expires_at: value.expires_at.and_then(|t| {
+                t.duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|d| d.as_millis() as u64)
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "s3-backend")]
+impl From<S3StorageValue> for StorageValue {
+    fn from(value: S3StorageValue) -> Self {
+        Self {
+            data: value.data,
+            expires_at: value
+                .expires_at
+                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+        }
+    }
+}
+
+/// Marker of object keys holding a hex-encoded key, see
+/// [`S3Storage::key_path`].
+#[cfg(feature = "s3-backend")]
+const HEX_MARKER: &str = "~hex/";
+
+/// How many times `update` retries after losing a race with another writer.
+#[cfg(feature = "s3-backend")]
+const MAX_UPDATE_ATTEMPTS: u32 = 16;
+
+/// S3-backed storage. Each key is one object.
+///
+/// Single-key updates are compare-and-swap loops: the object is written back
+/// with `If-Match` on the ETag it was read with (or `If-None-Match: *` when
+/// it did not exist), and the closure is re-run if another writer got there
+/// first. Multi-key updates (`update_many`) write each key the same way and
+/// undo the keys already written when a later one conflicts, so of two
+/// updates racing on a key only one applies. They are not isolated, though:
+/// readers can see part of an update before it is undone, and a crash
+/// halfway leaves it partly applied.
+#[cfg(feature = "s3-backend")]
+#[derive(Clone)]
 pub struct S3Storage {
     client: Client,
     bucket: String,
@@ -36,52 +138,39 @@ impl S3Storage {
         })
     }
 
-    fn key_path(&self, key: &str) -> String {
-        format!("{}{}", self.prefix, key)
+    /// Map a binary key onto an S3 object key.
+    ///
+    /// UTF-8 keys are used verbatim, as they always were; anything else, and
+    /// keys that would be mistaken for the marker, is hex-encoded under a
+    /// `~hex/` marker so arbitrary bytes stay addressable.
+    fn key_path(&self, key: &[u8]) -> String {
+        match std::str::from_utf8(key) {
+            Ok(key) if !key.starts_with(HEX_MARKER) => format!("{}{}", self.prefix, key),
+            _ => {
+                let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("{}{}{}", self.prefix, HEX_MARKER, hex)
+            }
+        }
     }
-}
 
-#[cfg(feature = "s3-backend")]
-#[async_trait]
-impl StorageBackend for S3Storage {
-    async fn set(&self, key: &str, value: &str) -> Result<(), StorageError> {
-        let storage_value = S3StorageValue {
-            data: value.to_owned(),
-            expires_at: None,
+    /// Inverse of [`Self::key_path`]; `None` for objects that are not keys.
+    fn path_key(&self, path: &str) -> Option<Bytes> {
+        let name = path.strip_prefix(self.prefix.as_str())?;
+        let Some(hex) = name.strip_prefix(HEX_MARKER) else {
+            return Some(Bytes::copy_from_slice(name.as_bytes()));
         };
-
-        let body = serde_json::to_vec(&storage_value)?;
-
-        self.client
-            .put_object()
-            .bucket(&self.bucket)
-            .key(self.key_path(key))
-            .body(body.into())
-            .send()
-            .await
-            .map_err(|e| StorageError::OperationFailed(format!("S3 put error: {}", e)))?;
-
-        Ok(())
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .map(Bytes::from)
     }
 
-    async fn set_with_expiry(
-        &self,
-        key: &str,
-        value: &str,
-        ttl: Duration,
-    ) -> Result<(), StorageError> {
-        let expires_at = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64
-            + ttl.as_millis() as u64;
-
-        let storage_value = S3StorageValue {
-            data: value.to_owned(),
-            expires_at: Some(expires_at),
-        };
-
-        let body = serde_json::to_vec(&storage_value)?;
+    async fn put_value(&self, key: &[u8], value: StorageValue) -> Result<(), StorageError> {
+        let body = S3StorageValue::from(value).encode()?;
 
         self.client
             .put_object()
@@ -95,7 +184,12 @@ impl StorageBackend for S3Storage {
         Ok(())
     }
 
-    async fn get(&self, key: &str) -> Result<Option<String>, StorageError> {
+    /// Fetch a value together with the ETag of the object holding it.
+    /// Expired values are returned as-is.
+    async fn read_versioned(
+        &self,
+        key: &[u8],
+    ) -> Result<Option<(StorageValue, Option<String>)>, StorageError> {
         match self
             .client
             .get_object()
@@ -105,6 +199,7 @@ impl StorageBackend for S3Storage {
             .await
         {
             Ok(output) => {
+                let etag = output.e_tag;
                 let bytes = output
                     .body
                     .collect()
@@ -114,23 +209,8 @@ impl StorageBackend for S3Storage {
                     })?
                     .into_bytes();
 
-                let storage_value: S3StorageValue = serde_json::from_slice(&bytes)?;
-
-                // Check expiration
-                if let Some(expires_at) = storage_value.expires_at {
-                    let now = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64;
-
-                    if now > expires_at {
-                        // Delete expired key
-                        self.delete(key).await?;
-                        return Ok(None);
-                    }
-                }
-
-                Ok(Some(storage_value.data))
+                let storage_value: StorageValue = S3StorageValue::decode(&bytes)?.into();
+                Ok(Some((storage_value, etag)))
             }
             Err(e) => {
                 if e.to_string().contains("NoSuchKey") {
@@ -145,7 +225,279 @@ impl StorageBackend for S3Storage {
         }
     }
 
-    async fn delete(&self, key: &str) -> Result<bool, StorageError> {
+    /// Fetch a live value, deleting it if it has expired.
+    async fn read_value(&self, key: &[u8]) -> Result<Option<StorageValue>, StorageError> {
+        let Some((storage_value, _)) = self.read_versioned(key).await? else {
+            return Ok(None);
+        };
+
+        // Check expiration
+        if storage_value.is_expired() {
+            // Delete expired key
+            self.delete(key).await?;
+            return Ok(None);
+        }
+
+        Ok(Some(storage_value))
+    }
+
+    /// Write `value` (or delete the key when `None`) only if the object is
+    /// still the version read with `etag`, or still missing when `etag` is
+    /// `None`.
+    async fn write_if_unchanged(
+        &self,
+        key: &[u8],
+        value: Option<StorageValue>,
+        etag: Option<&str>,
+    ) -> Result<Write, StorageError> {
+        let Some(value) = value else {
+            let Some(etag) = etag else {
+                return Ok(Write::Done(None));
+            };
+            return match self
+                .client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(self.key_path(key))
+                .if_match(etag)
+                .send()
+                .await
+            {
+                Ok(_) => Ok(Write::Done(None)),
+                Err(e) if is_conflict(&e) || e.to_string().contains("NoSuchKey") => {
+                    Ok(Write::Conflict)
+                }
+                Err(e) => Err(StorageError::OperationFailed(format!(
+                    "S3 delete error: {}",
+                    e
+                ))),
+            };
+        };
+
+        let body = S3StorageValue::from(value).encode()?;
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.key_path(key))
+            .body(body.into());
+        let request = match etag {
+            Some(etag) => request.if_match(etag),
+            None => request.if_none_match("*"),
+        };
+
+        match request.send().await {
+            Ok(output) => Ok(Write::Done(output.e_tag)),
+            Err(e) if is_conflict(&e) => Ok(Write::Conflict),
+            Err(e) => Err(StorageError::OperationFailed(format!(
+                "S3 put error: {}",
+                e
+            ))),
+        }
+    }
+
+    /// Write the dirty `slots` of `keys`, each only if unchanged since it
+    /// was read at `versions` (value and ETag). When one write conflicts or
+    /// fails, the keys already written are put back as they were read and
+    /// `false` (or the error) is returned.
+    async fn write_all(
+        &self,
+        keys: &[&[u8]],
+        slots: Vec<ValueSlot>,
+        versions: Vec<(Option<StorageValue>, Option<String>)>,
+    ) -> Result<bool, StorageError> {
+        let mut written = Vec::new();
+        for ((key, slot), (previous, etag)) in keys.iter().zip(slots).zip(versions) {
+            if !slot.is_dirty() {
+                continue;
+            }
+            let outcome = self
+                .write_if_unchanged(key, slot.into_value(), etag.as_deref())
+                .await;
+            let failure = match outcome {
+                Ok(Write::Done(etag)) => {
+                    written.push((key, previous, etag));
+                    continue;
+                }
+                Ok(Write::Conflict) => Ok(false),
+                Err(e) => Err(e),
+            };
+
+            // Undo in reverse order, leaving alone keys changed since
+            for (key, previous, etag) in written.into_iter().rev() {
+                self.write_if_unchanged(key, previous, etag.as_deref())
+                    .await?;
+            }
+            return failure;
+        }
+        Ok(true)
+    }
+}
+
+/// Sleep briefly after losing a race with another writer, longer with each
+/// `attempt`, before re-reading.
+#[cfg(feature = "s3-backend")]
+async fn backoff(attempt: u32) {
+    let backoff = rand::thread_rng().gen_range(0..5u64 << attempt.min(6));
+    tokio::time::sleep(Duration::from_millis(backoff)).await;
+}
+
+/// Outcome of [`S3Storage::write_if_unchanged`].
+#[cfg(feature = "s3-backend")]
+enum Write {
+    /// Written, with the ETag of the new object (`None` once deleted).
+    Done(Option<String>),
+    /// Another writer changed the object in between; nothing was written.
+    Conflict,
+}
+
+/// Whether a conditional request failed because the object changed
+/// (`412 Precondition Failed`) or a concurrent conditional write is in
+/// flight (`409 Conflict`).
+#[cfg(feature = "s3-backend")]
+fn is_conflict<E>(error: &SdkError<E, HttpResponse>) -> bool {
+    error
+        .raw_response()
+        .is_some_and(|response| matches!(response.status().as_u16(), 409 | 412))
+}
+
+#[cfg(feature = "s3-backend")]
+#[async_trait]
+impl StorageBackend for S3Storage {
+    async fn set(&self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        self.put_value(key, StorageValue::new(Bytes::copy_from_slice(value)))
+            .await
+    }
+
+    async fn set_with_expiry(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) -> Result<(), StorageError> {
+        let expires_at = SystemTime::now() + ttl;
+        let storage_value = StorageValue {
+            data: ValueData::String(Bytes::copy_from_slice(value)),
+            expires_at: Some(expires_at),
+        };
+        self.put_value(key, storage_value).await
+    }
+
+    async fn get(&self, key: &[u8]) -> Result<Option<Bytes>, StorageError> {
+        self.read_value(key)
+            .await?
+            .map(|value| match value.data {
+                ValueData::String(data) => Ok(data),
+                _ => Err(StorageError::WrongType),
+            })
+            .transpose()
+    }
+
+    async fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>, StorageError> {
+        let mut values = Vec::with_capacity(keys.len());
+        for chunk in keys.chunks(MAX_CONCURRENT_REQUESTS) {
+            let reads: Vec<_> = chunk
+                .iter()
+                .map(|key| {
+                    let storage = self.clone();
+                    let key = key.to_vec();
+                    tokio::spawn(async move { storage.read_value(&key).await })
+                })
+                .collect();
+            for read in reads {
+                let value = read.await.map_err(|e| {
+                    StorageError::OperationFailed(format!("S3 get task failed: {}", e))
+                })??;
+                values.push(value.and_then(|value| match value.data {
+                    ValueData::String(data) => Some(data),
+                    _ => None,
+                }));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Writes run concurrently and are not atomic: a failure can leave some
+    /// keys written.
+    async fn set_many(&self, pairs: &[(&[u8], &[u8])]) -> Result<(), StorageError> {
+        for chunk in pairs.chunks(MAX_CONCURRENT_REQUESTS) {
+            let writes: Vec<_> = chunk
+                .iter()
+                .map(|(key, value)| {
+                    let storage = self.clone();
+                    let key = key.to_vec();
+                    let value = StorageValue::new(Bytes::copy_from_slice(value));
+                    tokio::spawn(async move { storage.put_value(&key, value).await })
+                })
+                .collect();
+            for write in writes {
+                write.await.map_err(|e| {
+                    StorageError::OperationFailed(format!("S3 set task failed: {}", e))
+                })??;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_value(&self, key: &[u8]) -> Result<Option<StorageValue>, StorageError> {
+        self.read_value(key).await
+    }
+
+    async fn update(&self, key: &[u8], f: UpdateFn<'_>) -> Result<(), StorageError> {
+        for attempt in 0..MAX_UPDATE_ATTEMPTS {
+            let (current, etag) = match self.read_versioned(key).await? {
+                Some((value, etag)) => (Some(value), etag),
+                None => (None, None),
+            };
+            let mut slot = ValueSlot::new(current.filter(|value| !value.is_expired()));
+            f(&mut slot)?;
+
+            if !slot.is_dirty() {
+                return Ok(());
+            }
+            if let Write::Done(_) = self
+                .write_if_unchanged(key, slot.into_value(), etag.as_deref())
+                .await?
+            {
+                return Ok(());
+            }
+            backoff(attempt).await;
+        }
+
+        Err(StorageError::OperationFailed(format!(
+            "S3 update of '{}' kept conflicting with concurrent writers",
+            self.key_path(key)
+        )))
+    }
+
+    async fn update_many(&self, keys: &[&[u8]], f: MultiUpdateFn<'_>) -> Result<(), StorageError> {
+        for attempt in 0..MAX_UPDATE_ATTEMPTS {
+            let mut versions = Vec::with_capacity(keys.len());
+            let mut slots = Vec::with_capacity(keys.len());
+            for key in keys {
+                let (current, etag) = match self.read_versioned(key).await? {
+                    Some((value, etag)) => (Some(value), etag),
+                    None => (None, None),
+                };
+                slots.push(ValueSlot::new(
+                    current.clone().filter(|value| !value.is_expired()),
+                ));
+                versions.push((current, etag));
+            }
+            f(&mut slots)?;
+
+            if self.write_all(keys, slots, versions).await? {
+                return Ok(());
+            }
+            backoff(attempt).await;
+        }
+
+        Err(StorageError::OperationFailed(
+            "S3 multi-key update kept conflicting with concurrent writers".to_string(),
+        ))
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<bool, StorageError> {
         match self
             .client
             .delete_object()
@@ -168,7 +520,7 @@ impl StorageBackend for S3Storage {
         }
     }
 
-    async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+    async fn exists(&self, key: &[u8]) -> Result<bool, StorageError> {
         match self
             .client
             .head_object()
@@ -191,6 +543,45 @@ impl StorageBackend for S3Storage {
         }
     }
 
+    /// Cursors are `list_objects_v2` continuation tokens. Expiry is only
+    /// checked when an object is read, so expired keys may be listed.
+    async fn scan(
+        &self,
+        cursor: Option<&[u8]>,
+        pattern: Option<&[u8]>,
+        count: usize,
+    ) -> Result<ScanBatch, StorageError> {
+        let mut request = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&self.prefix)
+            .max_keys(count.clamp(1, 1000) as i32);
+
+        if let Some(token) = cursor {
+            request = request.continuation_token(String::from_utf8_lossy(token));
+        }
+
+        let output = request
+            .send()
+            .await
+            .map_err(|e| StorageError::OperationFailed(format!("S3 list error: {}", e)))?;
+
+        let keys = output
+            .contents
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|object| self.path_key(object.key()?))
+            .filter(|key| pattern.is_none_or(|pattern| glob_match(pattern, key)))
+            .collect();
+        let cursor = if output.is_truncated.unwrap_or(false) {
+            output.next_continuation_token.map(Bytes::from)
+        } else {
+            None
+        };
+        Ok(ScanBatch { keys, cursor })
+    }
+
     async fn keys_count(&self) -> Result<usize, StorageError> {
         let mut count = 0;
         let mut continuation_token = None;
@@ -294,3 +685,88 @@ impl StorageBackend for S3Storage {
         Ok(())
     }
 }
+
+#[cfg(all(test, feature = "s3-backend"))]
+mod tests {
+    use super::*;
+    use rand::{Rng, RngCore};
+
+    /// Requires AWS credentials and a scratch bucket named by `CORAL_S3_TEST_BUCKET`.
+    #[tokio::test]
+    #[ignore]
+    async fn test_s3_binary_roundtrip() {
+        let bucket = std::env::var("CORAL_S3_TEST_BUCKET").expect("CORAL_S3_TEST_BUCKET not set");
+        let storage = S3Storage::new(bucket, Some("coral-test/".to_string()))
+            .await
+            .unwrap();
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..8 {
+            let mut key = vec![0u8; rng.gen_range(1..64)];
+            let mut value = vec![0u8; rng.gen_range(0..4096)];
+            rng.fill_bytes(&mut key);
+            rng.fill_bytes(&mut value);
+
+            storage.set(&key, &value).await.unwrap();
+            assert_eq!(
+                storage.get(&key).await.unwrap().as_deref(),
+                Some(&value[..])
+            );
+            storage.delete(&key).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_s3_value_encoding_is_binary_safe() {
+        let mut data = vec![0u8; 1024];
+        rand::thread_rng().fill_bytes(&mut data);
+
+        let value = S3StorageValue {
+            data: ValueData::String(Bytes::from(data.clone())),
+            expires_at: Some(42),
+        };
+        let decoded = S3StorageValue::decode(&value.encode().unwrap()).unwrap();
+        assert_eq!(decoded.data, ValueData::String(Bytes::from(data)));
+        assert_eq!(decoded.expires_at, Some(42));
+    }
+
+    #[test]
+    fn test_s3_reads_legacy_json_objects() {
+        let decoded =
+            S3StorageValue::decode(br#"{"data":"hello","expires_at":1700000000000}"#).unwrap();
+        assert_eq!(
+            decoded.data,
+            ValueData::String(Bytes::from_static(b"hello"))
+        );
+        assert_eq!(decoded.expires_at, Some(1700000000000));
+        assert!(S3StorageValue::decode(&[0, 1, 2]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_s3_key_paths_keep_legacy_names() {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .build();
+        let storage = S3Storage {
+            client: Client::from_conf(config),
+            bucket: "bucket".to_string(),
+            prefix: "redis/".to_string(),
+        };
+        for key in [
+            &b"user:1"[..],
+            b"with space",
+            b"back\\slash",
+            b"~tilde",
+            "caf\u{e9}".as_bytes(),
+        ] {
+            let path = storage.key_path(key);
+            assert_eq!(path.as_bytes(), [&b"redis/"[..], key].concat());
+            assert_eq!(storage.path_key(&path).as_deref(), Some(key));
+        }
+        for key in [&b"\xff\x00"[..], b"~hex/41"] {
+            let path = storage.key_path(key);
+            assert!(path.starts_with("redis/~hex/"));
+            assert_eq!(storage.path_key(&path).as_deref(), Some(key));
+        }
+    }
+}
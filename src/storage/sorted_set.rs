@@ -0,0 +1,178 @@
+//! Sorted set payload: members ordered by (score, member) with rank lookups.
+//!
+//! Members are kept in a vector sorted by score and then by member bytes,
+//! alongside a member -> score index. Score and membership lookups are
+//! O(1), rank lookups and range boundaries are binary searches, and
+//! inserts and removals shift the vector (a memmove, fast in practice for
+//! the set sizes Redis workloads keep in a single key).
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Ordering of `(score, member)` entries. Scores are never NaN.
+fn entry_cmp(a: (f64, &[u8]), b: (f64, &[u8])) -> Ordering {
+    a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<(f64, Bytes)>", into = "Vec<(f64, Bytes)>")]
+pub struct SortedSet {
+    entries: Vec<(f64, Bytes)>,
+    scores: HashMap<Bytes, f64>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Score of a member, if present.
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Insert a member or update its score. Returns the previous score.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        debug_assert!(!score.is_nan());
+        // Fold -0.0 into 0.0 so equal scores order by member
+        let score = score + 0.0;
+        let previous = self.remove(&member);
+        let pos = self.search(score, &member).unwrap_or_else(|pos| pos);
+        self.entries.insert(pos, (score, member.clone()));
+        self.scores.insert(member, score);
+        previous
+    }
+
+    /// Remove a member. Returns its score if it was present.
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        if let Ok(pos) = self.search(score, member) {
+            self.entries.remove(pos);
+        }
+        Some(score)
+    }
+
+    /// Zero-based rank of a member in ascending order.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        self.search(score, member).ok()
+    }
+
+    /// Entry at an ascending rank.
+    pub fn get(&self, rank: usize) -> Option<(&Bytes, f64)> {
+        self.entries
+            .get(rank)
+            .map(|(score, member)| (member, *score))
+    }
+
+    /// Entries in ascending order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> + ExactSizeIterator {
+        self.entries.iter().map(|(score, member)| (member, *score))
+    }
+
+    /// Entries with ranks in `start..end`, ascending.
+    pub fn range(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> + ExactSizeIterator {
+        let end = end.min(self.len());
+        self.entries[start.min(end)..end]
+            .iter()
+            .map(|(score, member)| (member, *score))
+    }
+
+    /// Number of entries that sort before every entry for which `pred` is
+    /// false. `pred` must hold for a prefix of the entries.
+    pub fn partition_point(&self, mut pred: impl FnMut(&Bytes, f64) -> bool) -> usize {
+        self.entries
+            .partition_point(|(score, member)| pred(member, *score))
+    }
+
+    /// Remove and return the entries with ranks in `start..end`.
+    pub fn drain(&mut self, start: usize, end: usize) -> Vec<(Bytes, f64)> {
+        let end = end.min(self.len());
+        let removed: Vec<(Bytes, f64)> = self
+            .entries
+            .drain(start.min(end)..end)
+            .map(|(score, member)| (member, score))
+            .collect();
+        for (member, _) in &removed {
+            self.scores.remove(member);
+        }
+        removed
+    }
+
+    fn search(&self, score: f64, member: &[u8]) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|(s, m)| entry_cmp((*s, m), (score, member)))
+    }
+}
+
+impl From<Vec<(f64, Bytes)>> for SortedSet {
+    fn from(entries: Vec<(f64, Bytes)>) -> Self {
+        let mut set = SortedSet::new();
+        for (score, member) in entries {
+            set.insert(member, score);
+        }
+        set
+    }
+}
+
+impl From<SortedSet> for Vec<(f64, Bytes)> {
+    fn from(set: SortedSet) -> Self {
+        set.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn b(s: &str) -> Bytes {
+        Bytes::from(s.to_string())
+    }
+
+    #[test]
+    fn test_ordering_and_rank() {
+        let mut set = SortedSet::new();
+        set.insert(b("c"), 1.0);
+        set.insert(b("a"), 2.0);
+        set.insert(b("b"), 1.0);
+
+        let members: Vec<&Bytes> = set.iter().map(|(m, _)| m).collect();
+        assert_eq!(members, [&b("b"), &b("c"), &b("a")]);
+        assert_eq!(set.rank(b"a"), Some(2));
+
+        // Updating a score moves the member
+        assert_eq!(set.insert(b("a"), 0.5), Some(2.0));
+        assert_eq!(set.rank(b"a"), Some(0));
+        assert_eq!(set.len(), 3);
+
+        assert_eq!(set.remove(b"b"), Some(1.0));
+        assert_eq!(set.rank(b"c"), Some(1));
+        assert_eq!(set.remove(b"b"), None);
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let mut set = SortedSet::new();
+        set.insert(b("x"), f64::INFINITY);
+        set.insert(b("y"), -3.5);
+
+        let encoded = bincode::serialize(&set).unwrap();
+        let decoded: SortedSet = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(decoded, set);
+        assert_eq!(decoded.score(b"x"), Some(f64::INFINITY));
+    }
+}
@@ -0,0 +1,277 @@
+//! Stream payload: entries keyed by monotonic IDs plus consumer groups.
+//!
+//! Consumer groups, including their pending entries lists, are part of the
+//! stream value, so every backend persists them together with the entries.
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Stream entry ID: milliseconds time part and sequence number.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Parse `ms-seq`, or a bare `ms` with `default_seq` as the sequence.
+    pub fn parse(arg: &[u8], default_seq: u64) -> Option<Self> {
+        let arg = std::str::from_utf8(arg).ok()?;
+        let (ms, seq) = match arg.split_once('-') {
+            Some((ms, seq)) => (ms.parse().ok()?, seq.parse().ok()?),
+            None => (arg.parse().ok()?, default_seq),
+        };
+        Some(Self { ms, seq })
+    }
+
+    /// The smallest ID greater than this one.
+    pub fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self { ms: self.ms, seq }),
+            None => self.ms.checked_add(1).map(|ms| Self { ms, seq: 0 }),
+        }
+    }
+
+    /// The largest ID smaller than this one.
+    pub fn prev(self) -> Option<Self> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(Self { ms: self.ms, seq }),
+            None => self.ms.checked_sub(1).map(|ms| Self { ms, seq: u64::MAX }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Field/value pairs of a stream entry, in insertion order.
+pub type StreamFields = Vec<(Bytes, Bytes)>;
+
+/// How XADD and XTRIM trim a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamTrim {
+    /// Keep at most this many entries.
+    MaxLen(u64),
+    /// Drop entries with IDs below this one.
+    MinId(StreamId),
+}
+
+/// A delivered but not yet acknowledged entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingEntry {
+    pub consumer: Bytes,
+    /// Unix time of the last delivery, in milliseconds.
+    pub delivered_ms: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Consumer {
+    /// Unix time of the last attempted interaction, in milliseconds.
+    pub seen_ms: u64,
+    /// Unix time of the last successful interaction, if any.
+    pub active_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsumerGroup {
+    pub last_delivered_id: StreamId,
+    /// Logical number of entries read by the group, when known.
+    pub entries_read: Option<u64>,
+    /// Pending entries list, shared by all consumers of the group.
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Bytes, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered_id: StreamId, entries_read: Option<u64>) -> Self {
+        Self {
+            last_delivered_id,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    /// Look up a consumer, creating it if needed, and mark it as seen.
+    pub fn touch_consumer(&mut self, name: &Bytes, now_ms: u64) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.clone()).or_insert(Consumer {
+            seen_ms: now_ms,
+            active_ms: None,
+        });
+        consumer.seen_ms = now_ms;
+        consumer
+    }
+
+    /// Number of pending entries owned by a consumer.
+    pub fn pending_count(&self, consumer: &[u8]) -> usize {
+        self.pending
+            .values()
+            .filter(|entry| entry.consumer == consumer)
+            .count()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stream {
+    pub entries: BTreeMap<StreamId, StreamFields>,
+    /// Highest ID ever added, even if that entry was deleted since.
+    pub last_id: StreamId,
+    /// Highest ID removed by XDEL.
+    pub max_deleted_id: StreamId,
+    /// Number of entries ever added.
+    pub entries_added: u64,
+    pub groups: BTreeMap<Bytes, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// ID of the first entry, or 0-0 for an empty stream.
+    pub fn first_id(&self) -> StreamId {
+        self.entries.keys().next().copied().unwrap_or_default()
+    }
+
+    /// Append an entry. `id` must be greater than `last_id`.
+    pub fn append(&mut self, id: StreamId, fields: StreamFields) {
+        debug_assert!(id > self.last_id);
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Delete an entry, returning whether it existed.
+    pub fn delete(&mut self, id: StreamId) -> bool {
+        if self.entries.remove(&id).is_none() {
+            return false;
+        }
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        true
+    }
+
+    /// Trim from the oldest entry, removing at most `limit` entries when
+    /// given. Returns the number of entries removed.
+    pub fn trim(&mut self, trim: StreamTrim, limit: Option<usize>) -> usize {
+        let mut removed = 0;
+        while limit.is_none_or(|limit| removed < limit) {
+            let len = self.entries.len() as u64;
+            let Some(entry) = self.entries.first_entry() else {
+                break;
+            };
+            let drop = match trim {
+                StreamTrim::MaxLen(max_len) => len > max_len,
+                StreamTrim::MinId(min_id) => *entry.key() < min_id,
+            };
+            if !drop {
+                break;
+            }
+            entry.remove();
+            removed += 1;
+        }
+        removed
+    }
+
+    /// Logical number of entries up to and including `id`, when it can be
+    /// known. Trimming only removes the oldest entries, so the count stays
+    /// exact as long as no entry was ever deleted out of order.
+    pub fn entries_read_at(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 || id >= self.last_id {
+            return Some(self.entries_added);
+        }
+        let trimmed = self.entries_added - self.len() as u64;
+        if self.max_deleted_id == StreamId::MIN {
+            let live = self.entries.range(..=id).count() as u64;
+            return Some(trimmed + live);
+        }
+        (id < self.first_id() && self.max_deleted_id < self.first_id()).then_some(trimmed)
+    }
+
+    /// Number of entries a group has yet to read, when it can be known.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if group.last_delivered_id >= self.last_id {
+            return Some(0);
+        }
+        let read = group
+            .entries_read
+            .or_else(|| self.entries_read_at(group.last_delivered_id))?;
+        Some(self.entries_added.saturating_sub(read))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> StreamFields {
+        vec![(Bytes::from_static(b"f"), Bytes::from_static(b"v"))]
+    }
+
+    #[test]
+    fn test_id_parse_and_neighbours() {
+        assert_eq!(StreamId::parse(b"5-3", 0), Some(StreamId::new(5, 3)));
+        assert_eq!(
+            StreamId::parse(b"5", u64::MAX),
+            Some(StreamId::new(5, u64::MAX))
+        );
+        assert_eq!(StreamId::parse(b"5-", 0), None);
+        assert_eq!(StreamId::parse(b"-1", 0), None);
+
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(StreamId::new(2, 0).prev(), Some(StreamId::new(1, u64::MAX)));
+        assert_eq!(StreamId::MAX.next(), None);
+        assert_eq!(StreamId::MIN.prev(), None);
+        assert_eq!(StreamId::new(7, 1).to_string(), "7-1");
+    }
+
+    #[test]
+    fn test_trim_and_read_counters() {
+        let mut stream = Stream::new();
+        for ms in 1..=10 {
+            stream.append(StreamId::new(ms, 0), fields());
+        }
+
+        assert_eq!(stream.trim(StreamTrim::MaxLen(8), None), 2);
+        assert_eq!(
+            stream.trim(StreamTrim::MinId(StreamId::new(6, 0)), Some(1)),
+            1
+        );
+        assert_eq!(stream.first_id(), StreamId::new(4, 0));
+        assert_eq!(stream.entries_read_at(StreamId::new(5, 0)), Some(5));
+
+        // Out-of-order deletions make intermediate counters unknowable
+        assert!(stream.delete(StreamId::new(7, 0)));
+        assert!(!stream.delete(StreamId::new(7, 0)));
+        assert_eq!(stream.entries_read_at(StreamId::new(5, 0)), None);
+        assert_eq!(stream.entries_read_at(StreamId::new(10, 0)), Some(10));
+
+        let group = ConsumerGroup::new(StreamId::new(2, 0), Some(2));
+        assert_eq!(stream.lag(&group), Some(8));
+    }
+}
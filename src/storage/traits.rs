@@ -1,29 +1,84 @@
+use super::{SortedSet, Stream};
 use async_trait::async_trait;
+use bytes::Bytes;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+/// Typed payload of a stored key.
+///
+/// Each variant corresponds to a Redis data type. Aggregate types are
+/// never stored empty: backends delete the key once the last element is gone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ValueData {
+    String(Bytes),
+    Hash(HashMap<Bytes, Bytes>),
+    List(VecDeque<Bytes>),
+    Set(HashSet<Bytes>),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl ValueData {
+    /// Redis type name as reported by the TYPE command.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ValueData::String(_) => "string",
+            ValueData::Hash(_) => "hash",
+            ValueData::List(_) => "list",
+            ValueData::Set(_) => "set",
+            ValueData::SortedSet(_) => "zset",
+            ValueData::Stream(_) => "stream",
+        }
+    }
+
+    /// Whether this is an aggregate type with no elements left.
+    /// Streams are kept when empty, as Redis does.
+    pub fn is_empty_aggregate(&self) -> bool {
+        match self {
+            ValueData::String(_) | ValueData::Stream(_) => false,
+            ValueData::Hash(hash) => hash.is_empty(),
+            ValueData::List(list) => list.is_empty(),
+            ValueData::Set(set) => set.is_empty(),
+            ValueData::SortedSet(zset) => zset.is_empty(),
+        }
+    }
+}
+
+impl From<Bytes> for ValueData {
+    fn from(data: Bytes) -> Self {
+        ValueData::String(data)
+    }
+}
+
 /// Value stored in backend with optional expiry time.
 ///
-/// Uses `SystemTime` for expiry to support persistence across restarts.
+/// Data is binary-safe. Uses `SystemTime` for expiry to support
+/// persistence across restarts.
 #[derive(Debug, Clone)]
 pub struct StorageValue {
-    pub data: String,
+    pub data: ValueData,
     /// Absolute expiry time (Unix epoch based, persistable).
     pub expires_at: Option<SystemTime>,
 }
 
 impl StorageValue {
     /// Create a value with no expiry.
-    pub fn new(data: String) -> Self {
+    pub fn new(data: impl Into<ValueData>) -> Self {
         Self {
-            data,
+            data: data.into(),
             expires_at: None,
         }
     }
 
     /// Create a value that expires after the given TTL.
-    pub fn new_with_expiry(data: String, ttl: Duration) -> Self {
+    pub fn new_with_expiry(data: impl Into<ValueData>, ttl: Duration) -> Self {
         Self {
-            data,
+            data: data.into(),
             expires_at: Some(SystemTime::now() + ttl),
         }
     }
@@ -34,35 +89,521 @@ impl StorageValue {
             .map(|expires_at| SystemTime::now() > expires_at)
             .unwrap_or(false)
     }
+
+    /// Borrow the string payload, failing with `WrongType` for other types.
+    pub fn as_string(&self) -> Result<&Bytes, StorageError> {
+        match &self.data {
+            ValueData::String(data) => Ok(data),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Borrow the hash payload, failing with `WrongType` for other types.
+    pub fn as_hash(&self) -> Result<&HashMap<Bytes, Bytes>, StorageError> {
+        match &self.data {
+            ValueData::Hash(hash) => Ok(hash),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Borrow the list payload, failing with `WrongType` for other types.
+    pub fn as_list(&self) -> Result<&VecDeque<Bytes>, StorageError> {
+        match &self.data {
+            ValueData::List(list) => Ok(list),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Borrow the set payload, failing with `WrongType` for other types.
+    pub fn as_set(&self) -> Result<&HashSet<Bytes>, StorageError> {
+        match &self.data {
+            ValueData::Set(set) => Ok(set),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Borrow the sorted set payload, failing with `WrongType` for other types.
+    pub fn as_zset(&self) -> Result<&SortedSet, StorageError> {
+        match &self.data {
+            ValueData::SortedSet(zset) => Ok(zset),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Borrow the stream payload, failing with `WrongType` for other types.
+    pub fn as_stream(&self) -> Result<&Stream, StorageError> {
+        match &self.data {
+            ValueData::Stream(stream) => Ok(stream),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+}
+
+/// End of a list that a push or pop applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    /// Push an element onto this end of `list`.
+    pub fn push(self, list: &mut VecDeque<Bytes>, element: Bytes) {
+        match self {
+            ListEnd::Left => list.push_front(element),
+            ListEnd::Right => list.push_back(element),
+        }
+    }
+
+    /// Pop an element from this end of `list`.
+    pub fn pop(self, list: &mut VecDeque<Bytes>) -> Option<Bytes> {
+        match self {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        }
+    }
 }
 
+/// Conditions under which [`StorageBackend::set_expiry`] replaces a key's
+/// expiry, mirroring the NX/XX/GT/LT flags of EXPIRE. A key without an
+/// expiry counts as having an infinite TTL. The default allows any change.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExpiryCondition {
+    /// Only if the key has no expiry (NX).
+    pub no_expiry: bool,
+    /// Only if the key already has an expiry (XX).
+    pub has_expiry: bool,
+    /// Only if the new expiry is later than the current one (GT).
+    pub later: bool,
+    /// Only if the new expiry is earlier than the current one (LT).
+    pub earlier: bool,
+}
+
+impl ExpiryCondition {
+    /// Whether an expiry of `new` may replace `current`.
+    pub fn allows(self, current: Option<SystemTime>, new: SystemTime) -> bool {
+        (!self.no_expiry || current.is_none())
+            && (!self.has_expiry || current.is_some())
+            && (!self.later || current.is_some_and(|current| new > current))
+            && (!self.earlier || current.is_none_or(|current| new < current))
+    }
+}
+
+/// One batch of a keyspace iteration, see [`StorageBackend::scan`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanBatch {
+    /// Keys in this batch that matched the pattern.
+    pub keys: Vec<Bytes>,
+    /// Opaque position to resume from, `None` once the iteration is complete.
+    pub cursor: Option<Bytes>,
+}
+
+/// Mutable view of a single key handed to [`StorageBackend::update`] closures.
+///
+/// Holds the current (non-expired) value and remembers whether it was
+/// accessed mutably, so backends only write back when something changed.
+#[derive(Debug)]
+pub struct ValueSlot {
+    value: Option<StorageValue>,
+    dirty: bool,
+}
+
+impl ValueSlot {
+    pub fn new(value: Option<StorageValue>) -> Self {
+        Self {
+            value,
+            dirty: false,
+        }
+    }
+
+    /// Current value, if the key exists.
+    pub fn get(&self) -> Option<&StorageValue> {
+        self.value.as_ref()
+    }
+
+    /// Mutable access to the current value. Marks the slot as modified.
+    pub fn get_mut(&mut self) -> Option<&mut StorageValue> {
+        self.dirty = true;
+        self.value.as_mut()
+    }
+
+    /// Replace the value (including its expiry).
+    pub fn set(&mut self, value: StorageValue) {
+        self.dirty = true;
+        self.value = Some(value);
+    }
+
+    /// Remove the key.
+    pub fn delete(&mut self) {
+        self.dirty = self.dirty || self.value.is_some();
+        self.value = None;
+    }
+
+    /// Whether the closure modified the slot.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Read the hash stored here, `None` if the key is missing.
+    pub fn hash(&self) -> Result<Option<&HashMap<Bytes, Bytes>>, StorageError> {
+        self.value.as_ref().map(StorageValue::as_hash).transpose()
+    }
+
+    /// Mutable hash stored here, creating an empty one if the key is missing.
+    pub fn hash_mut(&mut self) -> Result<&mut HashMap<Bytes, Bytes>, StorageError> {
+        if let Some(value) = &self.value {
+            value.as_hash()?;
+        }
+        self.dirty = true;
+        let value = self
+            .value
+            .get_or_insert_with(|| StorageValue::new(ValueData::Hash(HashMap::new())));
+        match &mut value.data {
+            ValueData::Hash(hash) => Ok(hash),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Read the list stored here, `None` if the key is missing.
+    pub fn list(&self) -> Result<Option<&VecDeque<Bytes>>, StorageError> {
+        self.value.as_ref().map(StorageValue::as_list).transpose()
+    }
+
+    /// Mutable list stored here, creating an empty one if the key is missing.
+    pub fn list_mut(&mut self) -> Result<&mut VecDeque<Bytes>, StorageError> {
+        if let Some(value) = &self.value {
+            value.as_list()?;
+        }
+        self.dirty = true;
+        let value = self
+            .value
+            .get_or_insert_with(|| StorageValue::new(ValueData::List(VecDeque::new())));
+        match &mut value.data {
+            ValueData::List(list) => Ok(list),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Read the set stored here, `None` if the key is missing.
+    pub fn members(&self) -> Result<Option<&HashSet<Bytes>>, StorageError> {
+        self.value.as_ref().map(StorageValue::as_set).transpose()
+    }
+
+    /// Mutable set stored here, creating an empty one if the key is missing.
+    pub fn members_mut(&mut self) -> Result<&mut HashSet<Bytes>, StorageError> {
+        if let Some(value) = &self.value {
+            value.as_set()?;
+        }
+        self.dirty = true;
+        let value = self
+            .value
+            .get_or_insert_with(|| StorageValue::new(ValueData::Set(HashSet::new())));
+        match &mut value.data {
+            ValueData::Set(set) => Ok(set),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Read the sorted set stored here, `None` if the key is missing.
+    pub fn zset(&self) -> Result<Option<&SortedSet>, StorageError> {
+        self.value.as_ref().map(StorageValue::as_zset).transpose()
+    }
+
+    /// Mutable sorted set stored here, creating an empty one if the key is missing.
+    pub fn zset_mut(&mut self) -> Result<&mut SortedSet, StorageError> {
+        if let Some(value) = &self.value {
+            value.as_zset()?;
+        }
+        self.dirty = true;
+        let value = self
+            .value
+            .get_or_insert_with(|| StorageValue::new(ValueData::SortedSet(SortedSet::new())));
+        match &mut value.data {
+            ValueData::SortedSet(zset) => Ok(zset),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Read the stream stored here, `None` if the key is missing.
+    pub fn stream(&self) -> Result<Option<&Stream>, StorageError> {
+        self.value.as_ref().map(StorageValue::as_stream).transpose()
+    }
+
+    /// Mutable stream stored here, creating an empty one if the key is missing.
+    pub fn stream_mut(&mut self) -> Result<&mut Stream, StorageError> {
+        if let Some(value) = &self.value {
+            value.as_stream()?;
+        }
+        self.dirty = true;
+        let value = self
+            .value
+            .get_or_insert_with(|| StorageValue::new(ValueData::Stream(Stream::new())));
+        match &mut value.data {
+            ValueData::Stream(stream) => Ok(stream),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Consume the slot, dropping aggregates that were emptied.
+    pub fn into_value(self) -> Option<StorageValue> {
+        self.value.filter(|v| !v.data.is_empty_aggregate())
+    }
+}
+
+/// Read-only closure passed to [`StorageBackend::view`].
+pub type ViewFn<'a> = &'a mut (dyn FnMut(Option<&StorageValue>) -> Result<(), StorageError> + Send);
+
+/// Read-modify-write closure passed to [`StorageBackend::update`].
+///
+/// Backends that detect write conflicts may invoke it more than once, so it
+/// must not have side effects beyond the slot and its own captured output.
+pub type UpdateFn<'a> = &'a mut (dyn FnMut(&mut ValueSlot) -> Result<(), StorageError> + Send);
+
+/// Multi-key variant of [`UpdateFn`] passed to [`StorageBackend::update_many`].
+/// Slots are in the same order as the requested keys.
+pub type MultiUpdateFn<'a> =
+    &'a mut (dyn FnMut(&mut [ValueSlot]) -> Result<(), StorageError> + Send);
+
+/// Callback told of each key a backend removed because it expired, see
+/// [`StorageBackend::set_expiry_listener`].
+pub type ExpiryListener = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+/// Commands run as one unit by [`StorageBackend::atomically`].
+pub type Batch<'a> = Pin<&'a mut (dyn Future<Output = ()> + Send + 'a)>;
+
 /// Trait for pluggable storage backends.
 ///
 /// All operations are async and thread-safe. Implementations handle
-/// their own concurrency control and expiry cleanup.
+/// their own concurrency control and expiry cleanup. Keys and values are
+/// arbitrary byte strings.
 #[async_trait]
 pub trait StorageBackend: Send + Sync {
     /// Store a key-value pair without expiry.
-    async fn set(&self, key: &str, value: &str) -> Result<(), StorageError>;
+    async fn set(&self, key: &[u8], value: &[u8]) -> Result<(), StorageError>;
 
     /// Store a key-value pair with TTL expiry.
     async fn set_with_expiry(
         &self,
-        key: &str,
-        value: &str,
+        key: &[u8],
+        value: &[u8],
         ttl: Duration,
     ) -> Result<(), StorageError>;
 
-    /// Retrieve a value by key. Returns None if key doesn't exist or expired.
-    async fn get(&self, key: &str) -> Result<Option<String>, StorageError>;
+    /// Retrieve a string value by key. Returns None if key doesn't exist or expired.
+    /// Fails with `WrongType` if the key holds a non-string value.
+    async fn get(&self, key: &[u8]) -> Result<Option<Bytes>, StorageError>;
+
+    /// Retrieve the typed value stored at a key, including its expiry.
+    async fn get_value(&self, key: &[u8]) -> Result<Option<StorageValue>, StorageError>;
+
+    /// Inspect the value stored at a key without copying it out.
+    ///
+    /// Default implementation goes through `get_value`; backends that keep
+    /// values in memory can override it to avoid the copy.
+    async fn view(&self, key: &[u8], f: ViewFn<'_>) -> Result<(), StorageError> {
+        let value = self.get_value(key).await?;
+        f(value.as_ref())
+    }
+
+    /// Atomically read, modify and write back a single key.
+    ///
+    /// The closure sees the current non-expired value. If it modifies the
+    /// slot, the result is written back (or the key deleted when the slot is
+    /// emptied) without any other writer interleaving.
+    async fn update(&self, key: &[u8], f: UpdateFn<'_>) -> Result<(), StorageError>;
+
+    /// Atomically read, modify and write back several distinct keys.
+    ///
+    /// Same contract as [`update`](Self::update), applied to all keys at once.
+    async fn update_many(&self, keys: &[&[u8]], f: MultiUpdateFn<'_>) -> Result<(), StorageError>;
+
+    /// Push elements onto one end of a list, creating it unless `create` is
+    /// false. Returns the new length (0 if the key was missing and not created).
+    ///
+    /// Default implementation goes through `update`; backends that store
+    /// lists out of line override it to avoid rewriting the whole list.
+    async fn list_push(
+        &self,
+        key: &[u8],
+        elements: &[Bytes],
+        end: ListEnd,
+        create: bool,
+    ) -> Result<usize, StorageError> {
+        let mut len = 0;
+        self.update(key, &mut |slot| {
+            len = 0;
+            if !create && slot.list()?.is_none() {
+                return Ok(());
+            }
+            let list = slot.list_mut()?;
+            for element in elements {
+                end.push(list, element.clone());
+            }
+            len = list.len();
+            Ok(())
+        })
+        .await?;
+        Ok(len)
+    }
+
+    /// Pop up to `count` elements from one end of a list.
+    /// Returns `None` if the key does not exist.
+    async fn list_pop(
+        &self,
+        key: &[u8],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Bytes>>, StorageError> {
+        let mut popped = None;
+        self.update(key, &mut |slot| {
+            popped = None;
+            if slot.list()?.is_none() {
+                return Ok(());
+            }
+            let list = slot.list_mut()?;
+            popped = Some(std::iter::from_fn(|| end.pop(list)).take(count).collect());
+            Ok(())
+        })
+        .await?;
+        Ok(popped)
+    }
+
+    /// Atomically pop an element from `source` and push it onto
+    /// `destination`. Returns `None` if `source` does not exist.
+    async fn list_move(
+        &self,
+        source: &[u8],
+        destination: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Bytes>, StorageError> {
+        let mut moved = None;
+        if source == destination {
+            self.update(source, &mut |slot| {
+                moved = None;
+                if slot.list()?.is_none() {
+                    return Ok(());
+                }
+                let list = slot.list_mut()?;
+                if let Some(element) = from.pop(list) {
+                    to.push(list, element.clone());
+                    moved = Some(element);
+                }
+                Ok(())
+            })
+            .await?;
+        } else {
+            self.update_many(&[source, destination], &mut |slots| {
+                moved = None;
+                // Check the destination type before touching the source
+                if slots[0].list()?.is_none() {
+                    return Ok(());
+                }
+                slots[1].list()?;
+                let Some(element) = from.pop(slots[0].list_mut()?) else {
+                    return Ok(());
+                };
+                to.push(slots[1].list_mut()?, element.clone());
+                moved = Some(element);
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(moved)
+    }
+
+    /// Expiry of a key: `None` if the key does not exist, `Some(None)` if
+    /// it exists without an expiry.
+    async fn get_expiry(&self, key: &[u8]) -> Result<Option<Option<SystemTime>>, StorageError> {
+        let mut expiry = None;
+        self.view(key, &mut |value| {
+            expiry = value.map(|value| value.expires_at);
+            Ok(())
+        })
+        .await?;
+        Ok(expiry)
+    }
+
+    /// Set the expiry of an existing key if `condition` allows it; a
+    /// deadline in the past deletes the key. Returns whether the expiry was
+    /// applied (false if the key is missing or the condition failed).
+    ///
+    /// Default implementation goes through `update`; backends that store
+    /// values out of line override it to touch only the key's metadata.
+    async fn set_expiry(
+        &self,
+        key: &[u8],
+        expires_at: SystemTime,
+        condition: ExpiryCondition,
+    ) -> Result<bool, StorageError> {
+        let mut applied = false;
+        self.update(key, &mut |slot| {
+            applied = false;
+            let Some(value) = slot.get() else {
+                return Ok(());
+            };
+            if !condition.allows(value.expires_at, expires_at) {
+                return Ok(());
+            }
+            if expires_at <= SystemTime::now() {
+                slot.delete();
+            } else if let Some(value) = slot.get_mut() {
+                value.expires_at = Some(expires_at);
+            }
+            applied = true;
+            Ok(())
+        })
+        .await?;
+        Ok(applied)
+    }
+
+    /// Remove the expiry of a key. Returns whether the key had one.
+    async fn clear_expiry(&self, key: &[u8]) -> Result<bool, StorageError> {
+        let mut cleared = false;
+        self.update(key, &mut |slot| {
+            cleared = false;
+            if slot.get().is_some_and(|value| value.expires_at.is_some()) {
+                if let Some(value) = slot.get_mut() {
+                    value.expires_at = None;
+                    cleared = true;
+                }
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(cleared)
+    }
+
+    /// Get several string values, in key order. Missing keys and keys
+    /// holding other types read as `None`, as MGET expects.
+    /// Default implementation calls get() for each key individually.
+    async fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>, StorageError> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(match self.get(key).await {
+                Err(StorageError::WrongType) => None,
+                value => value?,
+            });
+        }
+        Ok(values)
+    }
+
+    /// Set several string values, clearing any TTLs. Keys must be distinct.
+    /// Default implementation calls set() for each pair individually.
+    async fn set_many(&self, pairs: &[(&[u8], &[u8])]) -> Result<(), StorageError> {
+        for (key, value) in pairs {
+            self.set(key, value).await?;
+        }
+        Ok(())
+    }
 
     /// Delete a key. Returns true if key existed.
-    async fn delete(&self, key: &str) -> Result<bool, StorageError>;
+    async fn delete(&self, key: &[u8]) -> Result<bool, StorageError>;
 
     /// Delete multiple keys. Returns count of keys that existed and were deleted.
     /// Default implementation calls delete() for each key individually.
     /// Backends can override for more efficient batch operations.
-    async fn delete_many(&self, keys: &[&str]) -> Result<usize, StorageError> {
+    async fn delete_many(&self, keys: &[&[u8]]) -> Result<usize, StorageError> {
         let mut count = 0;
         for key in keys {
             if self.delete(key).await? {
@@ -73,13 +614,77 @@ pub trait StorageBackend: Send + Sync {
     }
 
     /// Check if a key exists and is not expired.
-    async fn exists(&self, key: &str) -> Result<bool, StorageError>;
+    async fn exists(&self, key: &[u8]) -> Result<bool, StorageError>;
+
+    /// Iterate over the keyspace in batches.
+    ///
+    /// Pass `None` to start an iteration and the returned cursor to continue
+    /// it. Each call examines roughly `count` keys and returns those matching
+    /// the glob `pattern`, so a batch may be empty before the iteration ends.
+    /// Keys present for the whole iteration are returned, even if other keys
+    /// are added or removed meanwhile.
+    async fn scan(
+        &self,
+        cursor: Option<&[u8]>,
+        pattern: Option<&[u8]>,
+        count: usize,
+    ) -> Result<ScanBatch, StorageError>;
+
+    /// Pick a random non-expired key, `None` if there is none.
+    ///
+    /// The default samples the whole keyspace through [`scan`](Self::scan);
+    /// backends with cheaper random access override it.
+    async fn random_key(&self) -> Result<Option<Bytes>, StorageError> {
+        let mut chosen = None;
+        let mut seen = 0u64;
+        let mut cursor: Option<Bytes> = None;
+        loop {
+            let batch = self.scan(cursor.as_deref(), None, 1000).await?;
+            for key in batch.keys {
+                // Reservoir sampling: keep the n-th key with probability 1/n
+                seen += 1;
+                if rand::thread_rng().gen_range(0..seen) == 0 {
+                    chosen = Some(key);
+                }
+            }
+            cursor = batch.cursor;
+            if cursor.is_none() {
+                return Ok(chosen);
+            }
+        }
+    }
 
     /// Get total count of non-expired keys.
     async fn keys_count(&self) -> Result<usize, StorageError>;
 
     /// Remove all keys from the database.
     async fn flush(&self) -> Result<(), StorageError>;
+
+    /// Run `batch`, a sequence of operations on this backend, as a single
+    /// transaction.
+    ///
+    /// The caller keeps other clients out while a batch runs, so the default
+    /// just runs it. Transactional backends override it to also make the
+    /// batch all-or-nothing on disk; they may require every operation of the
+    /// batch to complete without suspending, so the batch must never wait on
+    /// anything but the backend itself. Blocking commands run inside one
+    /// only try once for that reason.
+    ///
+    /// Only the stored data is rolled back if the batch fails: anything the
+    /// batch told clients meanwhile, such as keyspace notifications, is not
+    /// taken back.
+    async fn atomically(&self, batch: Batch<'_>) -> Result<(), StorageError> {
+        batch.await;
+        Ok(())
+    }
+
+    /// Tell `listener` of every key the backend removes because it expired,
+    /// whether on access or in the background, replacing any previous
+    /// listener. The listener runs while the backend may hold its locks, so
+    /// it must not call back into the backend.
+    ///
+    /// The default ignores it, for backends that never remove expired keys.
+    fn set_expiry_listener(&self, _listener: ExpiryListener) {}
 }
 
 /// Errors that can occur during storage operations.
@@ -91,12 +696,66 @@ pub enum StorageError {
     #[error("serialization error: {0}")]
     Serde(#[from] serde_json::Error),
 
+    #[error("encoding error: {0}")]
+    Encoding(#[from] bincode::Error),
+
     #[error("operation failed: {0}")]
     OperationFailed(String),
 
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+
     #[error("key not found: {0}")]
     KeyNotFound(String),
 
     #[error("connection error: {0}")]
     ConnectionError(String),
 }
+
+impl dyn StorageBackend {
+    /// Typed wrapper around [`StorageBackend::view`].
+    pub async fn inspect<T, F>(&self, key: &[u8], mut f: F) -> Result<T, StorageError>
+    where
+        T: Send,
+        F: FnMut(Option<&StorageValue>) -> Result<T, StorageError> + Send,
+    {
+        let mut output = None;
+        self.view(key, &mut |value| {
+            output = Some(f(value)?);
+            Ok(())
+        })
+        .await?;
+        output.ok_or_else(|| StorageError::OperationFailed("view closure not invoked".into()))
+    }
+
+    /// Typed wrapper around [`StorageBackend::update`] that returns the
+    /// closure's result.
+    pub async fn modify<T, F>(&self, key: &[u8], mut f: F) -> Result<T, StorageError>
+    where
+        T: Send,
+        F: FnMut(&mut ValueSlot) -> Result<T, StorageError> + Send,
+    {
+        let mut output = None;
+        self.update(key, &mut |slot| {
+            output = Some(f(slot)?);
+            Ok(())
+        })
+        .await?;
+        output.ok_or_else(|| StorageError::OperationFailed("update closure not invoked".into()))
+    }
+
+    /// Typed wrapper around [`StorageBackend::update_many`].
+    pub async fn modify_many<T, F>(&self, keys: &[&[u8]], mut f: F) -> Result<T, StorageError>
+    where
+        T: Send,
+        F: FnMut(&mut [ValueSlot]) -> Result<T, StorageError> + Send,
+    {
+        let mut output = None;
+        self.update_many(keys, &mut |slots| {
+            output = Some(f(slots)?);
+            Ok(())
+        })
+        .await?;
+        output.ok_or_else(|| StorageError::OperationFailed("update closure not invoked".into()))
+    }
+}
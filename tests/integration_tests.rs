@@ -15,7 +15,7 @@ async fn test_server_integration_basic_commands() {
     // Test PING command
     let ping_response = handler
         .handle_command(RespValue::Array(Some(vec![RespValue::BulkString(Some(
-            "PING".to_string(),
+            "PING".into(),
         ))])))
         .await;
 
@@ -27,9 +27,9 @@ async fn test_server_integration_basic_commands() {
     // Test SET command
     let set_response = handler
         .handle_command(RespValue::Array(Some(vec![
-            RespValue::BulkString(Some("SET".to_string())),
-            RespValue::BulkString(Some("testkey".to_string())),
-            RespValue::BulkString(Some("testvalue".to_string())),
+            RespValue::BulkString(Some("SET".into())),
+            RespValue::BulkString(Some("testkey".into())),
+            RespValue::BulkString(Some("testvalue".into())),
         ])))
         .await;
 
@@ -41,8 +41,8 @@ async fn test_server_integration_basic_commands() {
     // Test GET command
     let get_response = handler
         .handle_command(RespValue::Array(Some(vec![
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("testkey".to_string())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("testkey".into())),
         ])))
         .await;
 
@@ -63,17 +63,17 @@ async fn test_multiple_clients() {
     // Client 1 sets a key
     handler1
         .handle_command(RespValue::Array(Some(vec![
-            RespValue::BulkString(Some("SET".to_string())),
-            RespValue::BulkString(Some("shared_key".to_string())),
-            RespValue::BulkString(Some("shared_value".to_string())),
+            RespValue::BulkString(Some("SET".into())),
+            RespValue::BulkString(Some("shared_key".into())),
+            RespValue::BulkString(Some("shared_value".into())),
         ])))
         .await;
 
     // Client 2 should be able to get the same key
     let response = handler2
         .handle_command(RespValue::Array(Some(vec![
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("shared_key".to_string())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("shared_key".into())),
         ])))
         .await;
 
@@ -91,7 +91,7 @@ async fn test_error_handling() {
     // Test invalid command
     let response = handler
         .handle_command(RespValue::Array(Some(vec![RespValue::BulkString(Some(
-            "INVALID_COMMAND".to_string(),
+            "INVALID_COMMAND".into(),
         ))])))
         .await;
 
@@ -152,9 +152,9 @@ async fn test_config_get_integration() {
     // Test CONFIG GET port
     let response = handler
         .handle_command(RespValue::Array(Some(vec![
-            RespValue::BulkString(Some("CONFIG".to_string())),
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("port".to_string())),
+            RespValue::BulkString(Some("CONFIG".into())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("port".into())),
         ])))
         .await;
 
@@ -175,10 +175,10 @@ async fn test_config_get_integration() {
     // Test CONFIG GET with multiple parameters
     let response = handler
         .handle_command(RespValue::Array(Some(vec![
-            RespValue::BulkString(Some("CONFIG".to_string())),
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("port".to_string())),
-            RespValue::BulkString(Some("bind".to_string())),
+            RespValue::BulkString(Some("CONFIG".into())),
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("port".into())),
+            RespValue::BulkString(Some("bind".into())),
         ])))
         .await;
 
@@ -189,3 +189,151 @@ async fn test_config_get_integration() {
         _ => panic!("Expected Array response"),
     }
 }
+
+#[tokio::test]
+async fn test_blocking_pop_over_tcp() {
+    use coral_redis::server::ServerContext;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+    let config = Arc::new(Config::default());
+    let context = Arc::new(ServerContext::new());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    {
+        let context = Arc::clone(&context);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut handler = Handler::new_with_context(
+                    Arc::clone(&storage),
+                    Arc::clone(&config),
+                    Arc::clone(&context),
+                );
+                tokio::spawn(async move {
+                    let _ = handler.handle_stream(&mut stream).await;
+                });
+            }
+        });
+    }
+
+    let wait_for_blocked = |expected: usize| {
+        let context = Arc::clone(&context);
+        async move {
+            tokio::time::timeout(Duration::from_secs(2), async {
+                while context.blocking.blocked_clients() != expected {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            })
+            .await
+            .expect("blocked client count did not settle");
+        }
+    };
+
+    // A client that disconnects while blocked leaves the wait queue
+    let mut abandoned = TcpStream::connect(addr).await.unwrap();
+    abandoned
+        .write_all(b"*3\r\n$5\r\nBLPOP\r\n$1\r\nq\r\n$1\r\n0\r\n")
+        .await
+        .unwrap();
+    wait_for_blocked(1).await;
+    drop(abandoned);
+    wait_for_blocked(0).await;
+
+    // A blocked client is served by a push from another connection
+    let mut waiter = TcpStream::connect(addr).await.unwrap();
+    waiter
+        .write_all(b"*3\r\n$5\r\nBLPOP\r\n$1\r\nq\r\n$1\r\n0\r\n")
+        .await
+        .unwrap();
+    wait_for_blocked(1).await;
+
+    let mut pusher = TcpStream::connect(addr).await.unwrap();
+    pusher
+        .write_all(b"*3\r\n$5\r\nRPUSH\r\n$1\r\nq\r\n$1\r\na\r\n")
+        .await
+        .unwrap();
+    let mut buf = [0u8; 64];
+    let n = pusher.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b":1\r\n");
+
+    let expected = b"*2\r\n$1\r\nq\r\n$1\r\na\r\n";
+    let mut reply = vec![0u8; expected.len()];
+    tokio::time::timeout(Duration::from_secs(2), waiter.read_exact(&mut reply))
+        .await
+        .expect("blocked client was not served")
+        .unwrap();
+    assert_eq!(&reply, expected);
+    wait_for_blocked(0).await;
+}
+
+#[tokio::test]
+async fn test_client_kill_over_tcp() {
+    use coral_redis::server::ServerContext;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let storage: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+    let config = Arc::new(Config::default());
+    let context = Arc::new(ServerContext::new());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut handler = Handler::new_with_context(
+                Arc::clone(&storage),
+                Arc::clone(&config),
+                Arc::clone(&context),
+            );
+            tokio::spawn(async move {
+                let _ = handler.handle_stream(&mut stream).await;
+            });
+        }
+    });
+
+    // A client blocked on an empty list, killed by its address
+    let mut victim = TcpStream::connect(addr).await.unwrap();
+    victim
+        .write_all(b"*3\r\n$5\r\nBLPOP\r\n$1\r\nq\r\n$1\r\n0\r\n")
+        .await
+        .unwrap();
+    let victim_addr = victim.local_addr().unwrap().to_string();
+
+    let mut admin = TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0u8; 1024];
+    let list = loop {
+        admin
+            .write_all(b"*2\r\n$6\r\nCLIENT\r\n$4\r\nLIST\r\n")
+            .await
+            .unwrap();
+        let n = admin.read(&mut buf).await.unwrap();
+        let list = String::from_utf8_lossy(&buf[..n]).into_owned();
+        if list.contains("flags=b") {
+            break list;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    };
+    assert!(list.contains(&format!("addr={} laddr={} ", victim_addr, addr)));
+    assert!(list.contains("cmd=blpop"));
+
+    let kill = format!(
+        "*4\r\n$6\r\nCLIENT\r\n$4\r\nKILL\r\n$4\r\nADDR\r\n${}\r\n{}\r\n",
+        victim_addr.len(),
+        victim_addr
+    );
+    admin.write_all(kill.as_bytes()).await.unwrap();
+    let n = admin.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b":1\r\n");
+
+    let n = tokio::time::timeout(Duration::from_secs(2), victim.read(&mut buf))
+        .await
+        .expect("killed client was not disconnected")
+        .unwrap();
+    assert_eq!(n, 0);
+}